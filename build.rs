@@ -0,0 +1,17 @@
+// Generates cp3_dsp.h from the extern "C" functions and #[repr(C)] types,
+// so the header never falls behind the library
+
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml")).unwrap();
+    match cbindgen::generate_with_config(&crate_dir, config) {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{crate_dir}/cp3_dsp.h"));
+        }
+        // a source that doesn't parse fails the build with a better error
+        Err(error) => println!("cargo:warning=cp3_dsp.h not generated: {error}"),
+    }
+}
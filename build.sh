@@ -1 +1,2 @@
-cargo build && cargo build --release && cbindgen --config cbindgen.toml --output cp3_dsp.h
+# building also regenerates cp3_dsp.h, see build.rs
+cargo build && cargo build --release
@@ -3,63 +3,1013 @@
 #ifndef CP3_DSP_H
 #define CP3_DSP_H
 
+/* Generated by cbindgen when building, don't edit by hand */
+
 #include <stdarg.h>
 #include <stdbool.h>
+#include <stddef.h>
 #include <stdint.h>
 #include <stdlib.h>
 
+#define MIN_SPECTRUM_SIZE 64
+
+#define MAX_SPECTRUM_SIZE 8192
+
+/**
+ * level of silent bins
+ */
+#define SPECTRUM_FLOOR_DB -120.0
+
+/**
+ * maximum number of send buses, including the default ones
+ */
+#define MAX_BUSES 8
+
+/**
+ * default buses, fed by the voices' own reverb and delay sends
+ */
+#define REVERB_BUS 0
+
+#define DELAY_BUS 1
+
+/**
+ * notes in a chord, and voices of a track in chord mode
+ */
+#define MAX_CHORD_NOTES 6
+
+#define MAX_CHORUS_TAPS 3
+
 #define A4_FREQ 440.0
 
 #define A4_MIDI 69
 
-#define MAX_BUFFER_SIZE 8192
+#define TRACK_COUNT 16
+
+/**
+ * samples per partition, and the latency of the convolution
+ */
+#define PARTITION_SIZE 256
+
+/**
+ * longest impulse response loaded, in seconds; the rest is cut off
+ */
+#define MAX_IR_SECONDS 10.0
+
+/**
+ * default length of the crossfade, in milliseconds
+ */
+#define DEFAULT_DECLICK_MS 2.0
+
+/**
+ * longest delay time in seconds
+ */
+#define MAX_DELAY_TIME 4.0
+
+/**
+ * Number of taps a MultiTapDelay reads
+ */
+#define MAX_DELAY_TAPS 8
+
+/**
+ * number of parameter slots reserved for each instrument of the kit
+ */
+#define DRUM_PARAMETER_STRIDE 8
+
+#define DrumInstrument_COUNT 7
+
+/**
+ * Stereo outputs tracks can be routed to, the main output included
+ */
+#define MAX_OUTPUTS 8
+
+/**
+ * most breakpoints a multi-stage envelope holds
+ */
+#define MAX_STAGES 8
+
+/**
+ * parameters from this index onward go to the track's EQ instead of its voice
+ */
+#define EQ_PARAMETER_OFFSET 100
+
+/**
+ * 0: low gain (dB), 1: low frequency, 2: mid gain (dB), 3: mid frequency,
+ * 4: mid Q, 5: high gain (dB), 6: high frequency
+ */
+#define EQ_PARAMETER_COUNT 7
+
+/**
+ * pitch a key-tracked cutoff is set for, middle C
+ */
+#define KEY_TRACKING_CENTER 60.0
+
+#define GATE_STEPS 16
+
+/**
+ * global LFOs in an engine
+ */
+#define GLOBAL_LFO_COUNT 4
+
+/**
+ * macros per track
+ */
+#define MACRO_COUNT 2
+
+/**
+ * parameters one macro can set
+ */
+#define MAX_MACRO_DESTINATIONS 8
+
+/**
+ * pitch bend range in semitones (up and down)
+ */
+#define PITCH_BEND_RANGE 2.0
+
+/**
+ * controller numbers from this offset onward are mapped to voice parameters
+ */
+#define CC_PARAMETER_OFFSET 20
+
+#define MOD_SLOT_COUNT 8
+
+#define DRAWBAR_COUNT 9
+
+/**
+ * sizes of the name and unit buffers of a `ParameterDescription`,
+ * including the terminating NUL
+ */
+#define PARAMETER_NAME_LENGTH 32
+
+#define PARAMETER_UNIT_LENGTH 8
+
+#define MIN_PHASER_STAGES 4
+
+#define MAX_PHASER_STAGES 8
+
+/**
+ * furthest shift up or down, in semitones
+ */
+#define MAX_SHIFT 12.0
+
+/**
+ * number of parameters addressable through `set_parameter`
+ */
+#define PARAMETER_COUNT (KEY_TRACKING_PARAMETER + 1)
+
+#define OPERATOR_COUNT 4
+
+/**
+ * parameter selecting the operator routing, see `ALGORITHMS`
+ */
+#define ALGORITHM_PARAMETER 19
+
+/**
+ * index of the first per-operator parameter. Each operator has a block of
+ * `OPERATOR_PARAMETERS`: frequency, level, attack, decay, feedback,
+ * frequency mode (see `FrequencyMode`), ratio and offset (Hz)
+ */
+#define FIRST_OPERATOR_PARAMETER 20
+
+#define OPERATOR_PARAMETERS 8
+
+/**
+ * parameter for how far the filter cutoff follows the note, after the
+ * operator blocks
+ */
+#define KEY_TRACKING_PARAMETER (FIRST_OPERATOR_PARAMETER + ((int8_t)OPERATOR_COUNT * OPERATOR_PARAMETERS))
+
+#define PRESET_BANK_SIZE 128
+
+/**
+ * most slices a sample is chopped into, one per pitch
+ */
+#define MAX_SLICES 128
+
+/**
+ * maximum oversampling factor
+ */
+#define MAX_OVERSAMPLING 8
+
+/**
+ * user defined scales, selected after the built-in ones
+ */
+#define USER_SCALE_COUNT 4
+
+/**
+ * scale id of the first user scale
+ */
+#define FIRST_USER_SCALE 14
+
+/**
+ * every pitch class, quantizing to it leaves notes alone
+ */
+#define CHROMATIC_MASK 4095
+
+/**
+ * longest stretch of audio a snapshot can show
+ */
+#define MAX_SCOPE_MS 1000.0
+
+/**
+ * resolution of the tick part of a bar.beat.tick position
+ */
+#define TICKS_PER_BEAT 960
+
+/**
+ * parameter locks an event holds
+ */
+#define MAX_PARAMETER_LOCKS 8
+
+#define LANES 8
+
+/**
+ * default smoothing time of parameter changes, in milliseconds
+ */
+#define DEFAULT_SMOOTHING_MS 10.0
+
+/**
+ * Snapshots kept per track, A and B
+ */
+#define SNAPSHOT_SLOTS 2
+
+/**
+ * maximum number of oscillators stacked in unison mode
+ */
+#define MAX_UNISON 8
 
 #define VOICE_COUNT 1
 
+/**
+ * entries in a custom velocity table, one per MIDI velocity
+ */
+#define VELOCITY_TABLE_SIZE 128
+
+typedef struct CurveType CurveType;
+
 typedef struct Engine Engine;
 
-typedef void (*PlaybackProgressCallback)(float);
+/**
+ * Sends changes to an engine. Nothing blocks, so a handle can be used from
+ * any thread, including a realtime one, except for `capture_preset`, which
+ * waits for the engine, and `load_preset`, which builds the preset's voices
+ * and effects.
+ */
+typedef struct EngineHandle EngineHandle;
+
+/**
+ * Called once per buffer with the host's context, the zero-based bar, beat
+ * and tick of the playback position, and the position in beats, at the
+ * start of the buffer
+ */
+typedef void (*PlaybackProgressCallback)(void*, uint32_t, uint32_t, uint32_t, float);
+
+/**
+ * Called with the host's context, whether a note starts or stops, its pitch,
+ * its track, the frame within the current buffer it plays at, and its
+ * position in beats
+ */
+typedef void (*NotePlayedCallback)(void*, bool, uint8_t, uint8_t, uint32_t, float);
+
+/**
+ * Called with the host's context when the transport starts or stops,
+ * whether it's playing, and the position in beats
+ */
+typedef void (*TransportCallback)(void*, bool, float);
+
+/**
+ * Peak and RMS level, linear
+ */
+typedef struct Level {
+  float peak;
+  float rms;
+} Level;
+
+/**
+ * Called after each rendered buffer with the host's context and the levels
+ * of every track, followed by the master level
+ */
+typedef void (*MeteringCallback)(void*, const struct Level*, size_t);
+
+/**
+ * A sequencer event as passed to `add_event` and `update_event`
+ */
+typedef struct EventC {
+  float beat_time;
+  float duration;
+  uint8_t pitch;
+  uint8_t velocity;
+  uint8_t track;
+  float param1;
+  float param2;
+  /**
+   * chance of the event playing, 0.0 to 1.0
+   */
+  float probability;
+  /**
+   * 0: always, 1: on loop `condition_a` of every `condition_b`, 2: first
+   * loop only, 3: all but the first loop
+   */
+  uint8_t condition;
+  uint8_t condition_a;
+  uint8_t condition_b;
+  float nudge_ms;
+  /**
+   * number of hits (ratchets), 1 plays the event once
+   */
+  uint8_t retrigger_count;
+  /**
+   * beats between ratchets, 0.0 spreads them over the duration
+   */
+  float retrigger_rate;
+} EventC;
+
+/**
+ * A parameter change as passed to `set_parameter`
+ */
+typedef struct ParamChangeC {
+  uint8_t track;
+  /**
+   * voice parameter, drum parameter or from `EQ_PARAMETER_OFFSET` on, an
+   * EQ parameter
+   */
+  int8_t parameter;
+  float value;
+} ParamChangeC;
+
+/**
+ * `ParameterInfo` as passed to hosts over FFI. Names and units longer
+ * than their buffers are cut off.
+ */
+typedef struct ParameterDescription {
+  int8_t id;
+  char name[PARAMETER_NAME_LENGTH];
+  char unit[PARAMETER_UNIT_LENGTH];
+  float min;
+  float max;
+  float default_value;
+  /**
+   * see `ParameterCurve`
+   */
+  uint8_t curve;
+} ParameterDescription;
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+void set_playback_progress_callback(const struct EngineHandle *handle,
+                                    PlaybackProgressCallback callback,
+                                    void *context);
+
+void set_note_played_callback(const struct EngineHandle *handle,
+                              NotePlayedCallback callback,
+                              void *context);
+
+void set_transport_callback(const struct EngineHandle *handle,
+                            TransportCallback callback,
+                            void *context);
+
+void set_metering_callback(const struct EngineHandle *handle,
+                           MeteringCallback callback,
+                           void *context);
+
+struct Level get_track_level(const struct EngineHandle *handle, uint8_t track);
+
+struct Level get_master_level(const struct EngineHandle *handle);
 
-typedef void (*NotePlayedCallback)(bool, int8_t, int8_t);
+bool set_spectrum_analyzer(const struct EngineHandle *handle, bool enabled, uint32_t size);
 
-void set_playback_progress_callback(PlaybackProgressCallback callback);
+size_t get_spectrum(const struct EngineHandle *handle, float *bins, size_t len);
 
-void set_note_played_callback(NotePlayedCallback callback);
+/**
+ * Create an engine, along with the handle that sends it messages and
+ * sets its callbacks; every engine has its own, so several can run at once
+ */
+struct Engine *engine_init(float sample_rate, struct EngineHandle **handle);
 
-struct Engine *engine_init(float sample_rate);
+/**
+ * Like `engine_init`, processing at `processing_rate` whatever the host's
+ * `sample_rate`, converting between them at the boundary
+ */
+struct Engine *engine_init_with_processing_rate(float sample_rate,
+                                                float processing_rate,
+                                                struct EngineHandle **handle);
+
+/**
+ * Follow a change of the host's sample rate, the engine keeps processing
+ * at the rate it was created with. Not while rendering.
+ */
+void set_host_sample_rate(struct Engine *engine, float sample_rate);
 
 void set_play_pause(struct Engine *engine, bool is_playing);
 
-void add_event(float beat_time,
-               int8_t pitch,
-               int8_t velocity,
-               float duration,
-               int8_t track,
-               float param1,
-               float param2);
+/**
+ * Stop every sounding note
+ */
+bool all_notes_off(const struct EngineHandle *handle);
+
+bool render_offline(struct Engine *engine,
+                    uint32_t bars,
+                    float tempo,
+                    const char *path,
+                    uint8_t format);
+
+bool render_stems(struct Engine *engine,
+                  uint32_t bars,
+                  float tempo,
+                  const char *directory,
+                  uint8_t format,
+                  bool include_buses);
+
+bool set_metronome(const struct EngineHandle *handle, bool enabled, float volume);
+
+bool set_count_in(const struct EngineHandle *handle, uint32_t bars);
+
+bool set_position_beats(const struct EngineHandle *handle, float beats);
+
+bool set_loop_markers(const struct EngineHandle *handle, float start, float end);
+
+bool set_one_shot(const struct EngineHandle *handle, bool one_shot);
+
+/**
+ * Length in milliseconds, up to 5.0, of the crossfade hiding the click of a
+ * sounding voice being restarted or stolen; 0.0 turns it off
+ */
+bool set_declick_time(const struct EngineHandle *handle, float ms);
+
+/**
+ * What stopping the transport does to the sounding notes, 0: flush them
+ * with a short fade, 1: release them, 2: let them play to their note offs;
+ * with `rewind` the transport starts from the beginning again, otherwise
+ * it resumes where it stopped
+ */
+bool set_stop_behavior(const struct EngineHandle *handle, uint8_t mode, bool rewind);
+
+/**
+ * Zero-based bar of the transport position at the end of the last
+ * rendered buffer
+ */
+uint32_t get_current_bar(const struct EngineHandle *handle);
+
+/**
+ * Zero-based beat within the current bar, see `get_current_bar`
+ */
+uint32_t get_current_beat(const struct EngineHandle *handle);
+
+/**
+ * Delay of the output in samples, to report to the host. The sequence is
+ * rendered ahead by this much unless latency compensation is turned off.
+ */
+uint32_t get_latency_samples(struct Engine *engine);
+
+/**
+ * Turn off when the host delays everything else by the reported latency
+ */
+bool set_latency_compensation(const struct EngineHandle *handle, bool enabled);
+
+bool set_scope_source(const struct EngineHandle *handle, int8_t track);
+
+size_t get_scope_snapshot(const struct EngineHandle *handle, float ms, float *points, size_t len);
+
+bool set_recording(const struct EngineHandle *handle, bool enabled);
+
+bool set_record_quantize(const struct EngineHandle *handle, float grid);
+
+/**
+ * The new event's id, 0 when the engine's queue is full
+ */
+uint32_t add_event(const struct EngineHandle *handle, struct EventC event);
+
+/**
+ * Add `count` events in one go, writing their ids to `ids` unless it's null
+ */
+bool add_events(const struct EngineHandle *handle,
+                const struct EventC *events,
+                size_t count,
+                uint32_t *ids);
+
+/**
+ * Stage `count` events to replace the edited pattern's events once
+ * committed, writing their ids to `ids` unless it's null
+ */
+bool stage_events(const struct EngineHandle *handle,
+                  const struct EventC *events,
+                  size_t count,
+                  uint32_t *ids);
+
+/**
+ * Swap the staged events into the edited pattern at the next loop
+ * boundary, or right away
+ */
+bool commit_pattern(const struct EngineHandle *handle, bool immediately);
+
+bool update_event(const struct EngineHandle *handle, uint32_t id, struct EventC event);
 
-void note_on(struct Engine *engine,
-             int8_t pitch,
-             int8_t velocity,
-             int8_t track,
-             float param1,
-             float param2);
+bool remove_event(const struct EngineHandle *handle, uint32_t id);
 
-void note_off(struct Engine *engine, int8_t pitch, int8_t track);
+bool set_event_nudge(const struct EngineHandle *handle, uint32_t id, float nudge_ms);
 
-void set_sound(struct Engine *engine, int8_t sound, int8_t track);
+bool add_event_lock(const struct EngineHandle *handle,
+                    uint32_t event_id,
+                    int8_t parameter,
+                    float value);
 
-void set_parameter(int8_t parameter, float value, int8_t track);
+bool remove_event_lock(const struct EngineHandle *handle, uint32_t event_id, int8_t parameter);
 
-void clear_events(void);
+uint32_t note_on(const struct EngineHandle *handle,
+                 uint8_t pitch,
+                 uint8_t velocity,
+                 uint8_t track,
+                 float,
+                 float,
+                 float pitch_bend,
+                 float pressure,
+                 float timbre);
 
+/**
+ * The note's id, 0 when the engine's queue is full
+ */
+uint32_t note_on_at(const struct EngineHandle *handle,
+                    uint8_t pitch,
+                    uint8_t velocity,
+                    uint8_t track,
+                    float,
+                    float,
+                    float pitch_bend,
+                    float pressure,
+                    float timbre,
+                    uint32_t frame_offset);
+
+bool update_note_expression(const struct EngineHandle *handle,
+                            uint32_t note_id,
+                            uint8_t dimension,
+                            float value);
+
+bool note_off(const struct EngineHandle *handle, uint8_t pitch, uint8_t track);
+
+bool note_off_at(const struct EngineHandle *handle,
+                 uint8_t pitch,
+                 uint8_t track,
+                 uint32_t frame_offset);
+
+bool set_pitch_bend(const struct EngineHandle *handle, uint8_t track, float bend);
+
+bool set_pitch_bend_range(const struct EngineHandle *handle, uint8_t track, float semitones);
+
+bool handle_midi_message(const struct EngineHandle *handle, const uint8_t *bytes, size_t len);
+
+bool handle_midi_message_at(const struct EngineHandle *handle,
+                            const uint8_t *bytes,
+                            size_t len,
+                            uint32_t frame_offset);
+
+bool set_sound(const struct EngineHandle *handle, uint8_t sound, uint8_t track);
+
+bool load_sample(const struct EngineHandle *handle, uint8_t track, const char *path);
+
+/**
+ * Chop the track's sample into `count` equal slices, played from the root
+ * pitch up once the sampler's slice parameter is on
+ */
+bool slice_sample_equally(const struct EngineHandle *handle, uint8_t track, uint8_t count);
+
+/**
+ * Chop the track's sample at `count` points, normalized to its length;
+ * they're sorted and clamped to the sample
+ */
+bool set_sample_slices(const struct EngineHandle *handle,
+                       uint8_t track,
+                       const float *points,
+                       size_t count);
+
+/**
+ * Copy the track's slice points into `points`, returning how many there
+ * are
+ */
+size_t get_sample_slices(const struct EngineHandle *handle,
+                         uint8_t track,
+                         float *points,
+                         size_t len);
+
+/**
+ * Find slice points at the transients of the track's sample (see
+ * `Sample::detect_transients`), copying them into `points` and returning
+ * how many were found. They aren't applied, pass them on, adjusted or not,
+ * to `set_sample_slices`.
+ */
+size_t detect_sample_transients(const struct EngineHandle *handle,
+                                uint8_t track,
+                                float sensitivity,
+                                float *points,
+                                size_t len);
+
+bool set_parameter(const struct EngineHandle *handle, struct ParamChangeC change);
+
+bool set_parameter_smoothing(const struct EngineHandle *handle,
+                             uint8_t smoothing_type,
+                             float time_ms);
+
+/**
+ * Glide parameter changes linearly across the next rendered block instead
+ * of over the smoothing time, for host automation at low block rates
+ */
+bool set_block_ramps(const struct EngineHandle *handle, bool enabled);
+
+/**
+ * Capture the track's parameters into snapshot `slot`, 0 for A and 1 for B
+ */
+bool capture_snapshot(const struct EngineHandle *handle, uint8_t track, uint8_t slot);
+
+/**
+ * Morph the track's parameters between its snapshots, from A at 0.0 to B
+ * at 1.0
+ */
+bool set_morph(const struct EngineHandle *handle, uint8_t track, float amount);
+
+uint8_t get_voice_parameter_count(uint8_t voice_type);
+
+bool get_voice_parameter_info(uint8_t voice_type, uint8_t index, struct ParameterDescription *info);
+
+uint8_t get_effect_parameter_count(uint8_t effect_type, float sample_rate);
+
+bool get_effect_parameter_info(uint8_t effect_type,
+                               uint8_t index,
+                               float sample_rate,
+                               struct ParameterDescription *info);
+
+/**
+ * Render a cycle of a global LFO's shape into the `len` points of
+ * `buffer`, 0.0..1.0, for drawing; false for an unknown shape
+ */
+bool render_lfo_cycle(uint8_t shape, float *buffer, size_t len);
+
+/**
+ * Render the envelope of an FM operator with these attack and decay times
+ * into the `len` points of `buffer`, for drawing. Returns the time the
+ * buffer spans in milliseconds.
+ */
+float render_operator_envelope(float attack_ms,
+                               float decay_ms,
+                               float sample_rate,
+                               float *buffer,
+                               size_t len);
+
+uint8_t get_eq_parameter_count(void);
+
+bool get_eq_parameter_info(uint8_t index, struct ParameterDescription *info);
+
+bool set_swing(const struct EngineHandle *handle, uint8_t track, float amount);
+
+bool set_humanize(const struct EngineHandle *handle,
+                  uint8_t track,
+                  float timing_ms,
+                  float velocity,
+                  bool repeat);
+
+bool set_mutation(const struct EngineHandle *handle,
+                  float amount,
+                  uint64_t seed,
+                  bool lock_seed,
+                  uint32_t every_bars);
+
+bool mutate_pattern(const struct EngineHandle *handle);
+
+bool set_scale(const struct EngineHandle *handle, uint8_t track, uint8_t root, uint8_t scale_id);
+
+/**
+ * `curve` 0: linear, 1: exponential, 2: fixed at `value`
+ */
+bool set_velocity_curve(const struct EngineHandle *handle,
+                        uint8_t track,
+                        uint8_t curve,
+                        uint8_t value);
+
+/**
+ * `mode` 0: restart from silence, 1: continue from the level reached,
+ * 2: legato, only triggering finished envelopes
+ */
+bool set_retrigger_mode(const struct EngineHandle *handle, uint8_t track, uint8_t mode);
+
+/**
+ * `table` holds VELOCITY_TABLE_SIZE velocities, the one played for each
+ * incoming velocity
+ */
+bool set_velocity_table(const struct EngineHandle *handle, uint8_t track, const uint8_t *table);
+
+/**
+ * Sweep a track parameter between `min` and `max` with global LFO
+ * `index`, once every `beats`. `shape` 0: sine, 1: triangle, 2: ramp up,
+ * 3: ramp down, 4: square
+ */
+bool set_track_lfo(const struct EngineHandle *handle,
+                   uint8_t index,
+                   uint8_t track,
+                   int8_t parameter,
+                   uint8_t shape,
+                   float beats,
+                   float min,
+                   float max);
+
+/**
+ * Sweep a parameter of an effect on a send bus with global LFO `index`,
+ * see `set_track_lfo`
+ */
+bool set_bus_lfo(const struct EngineHandle *handle,
+                 uint8_t index,
+                 uint8_t bus,
+                 uint8_t effect,
+                 int8_t parameter,
+                 uint8_t shape,
+                 float beats,
+                 float min,
+                 float max);
+
+bool clear_global_lfo(const struct EngineHandle *handle, uint8_t index);
+
+/**
+ * Assign a track parameter to `slot` of one of the track's macros, taking
+ * `min` to `max` as the macro turns. `curve` 0: linear, 1: exponential,
+ * 2: logarithmic
+ */
+bool set_macro_destination(const struct EngineHandle *handle,
+                           uint8_t track,
+                           uint8_t index,
+                           uint8_t slot,
+                           int8_t parameter,
+                           float min,
+                           float max,
+                           uint8_t curve);
+
+bool clear_macro_destination(const struct EngineHandle *handle,
+                             uint8_t track,
+                             uint8_t index,
+                             uint8_t slot);
+
+/**
+ * `value` 0.0..1.0
+ */
+bool set_macro(const struct EngineHandle *handle, uint8_t track, uint8_t index, float value);
+
+bool set_chord(const struct EngineHandle *handle,
+               uint8_t track,
+               uint8_t chord_type,
+               uint8_t inversion,
+               uint8_t spread);
+
+bool set_chord_intervals(const struct EngineHandle *handle,
+                         uint8_t track,
+                         const uint8_t *intervals,
+                         size_t len,
+                         uint8_t inversion,
+                         uint8_t spread);
+
+bool clear_chord(const struct EngineHandle *handle, uint8_t track);
+
+bool set_user_scale(const struct EngineHandle *handle, uint8_t index, uint16_t mask);
+
+bool set_mod_slot(const struct EngineHandle *handle,
+                  uint8_t track,
+                  uint8_t slot,
+                  uint8_t source,
+                  uint8_t destination,
+                  float depth);
+
+/**
+ * Shape the source of a mod slot. `shaper` 0: none, 1: sample and hold at
+ * `a` Hz, or on every note at zero, 2: slew rising over `a` ms and falling
+ * over `b` ms, 3: quantize to steps of `a`
+ */
+bool set_mod_shaper(const struct EngineHandle *handle,
+                    uint8_t track,
+                    uint8_t slot,
+                    uint8_t shaper,
+                    float a,
+                    float b);
+
+/**
+ * The new bus's index, -1 once all buses are taken or when the engine's
+ * queue is full
+ */
+int8_t create_send_bus(const struct EngineHandle *handle, const char *name);
+
+bool add_bus_effect(const struct EngineHandle *handle, uint8_t bus, uint8_t effect_type);
+
+bool set_bus_effect_parameter(const struct EngineHandle *handle,
+                              uint8_t bus,
+                              uint8_t effect,
+                              int8_t parameter,
+                              float value);
+
+/**
+ * Load a mono or stereo WAV impulse response into the convolution
+ * effect at index `effect` on a bus, transformed for an engine running at
+ * `sample_rate`. Returns false if the file can't be read.
+ */
+bool load_bus_impulse_response(const struct EngineHandle *handle,
+                               uint8_t bus,
+                               uint8_t effect,
+                               const char *path,
+                               float sample_rate);
+
+bool add_track_insert(const struct EngineHandle *handle, uint8_t track, uint8_t effect_type);
+
+bool set_track_insert_parameter(const struct EngineHandle *handle,
+                                uint8_t track,
+                                uint8_t effect,
+                                int8_t parameter,
+                                float value);
+
+/**
+ * Load an impulse response into a convolution insert on a track, see
+ * `load_bus_impulse_response`
+ */
+bool load_track_insert_impulse_response(const struct EngineHandle *handle,
+                                        uint8_t track,
+                                        uint8_t effect,
+                                        const char *path,
+                                        float sample_rate);
+
+bool add_input_insert(const struct EngineHandle *handle, uint8_t effect_type);
+
+bool set_input_insert_parameter(const struct EngineHandle *handle,
+                                uint8_t effect,
+                                int8_t parameter,
+                                float value);
+
+bool set_input_send(const struct EngineHandle *handle, uint8_t bus, float level, bool pre_fader);
+
+bool set_input_monitor(const struct EngineHandle *handle, float level);
+
+/**
+ * `threshold` 0 to start right away, `track` negative to only keep the
+ * recording under its name
+ */
+bool record_sample(const struct EngineHandle *handle,
+                   const char *name,
+                   uint8_t source,
+                   float threshold,
+                   bool synced,
+                   int8_t track,
+                   uint32_t max_frames);
+
+bool stop_recording(const struct EngineHandle *handle);
+
+bool load_recorded_sample(const struct EngineHandle *handle, uint8_t track, const char *name);
+
+bool set_bus_return(const struct EngineHandle *handle, uint8_t bus, float level);
+
+bool set_bus_freeze(const struct EngineHandle *handle, uint8_t bus, bool freeze);
+
+bool set_send(const struct EngineHandle *handle,
+              uint8_t track,
+              uint8_t bus,
+              float level,
+              bool pre_fader);
+
+bool set_track_volume(const struct EngineHandle *handle, uint8_t track, float volume);
+
+bool set_track_pan(const struct EngineHandle *handle, uint8_t track, float pan);
+
+bool set_track_mute(const struct EngineHandle *handle, uint8_t track, bool mute);
+
+bool set_track_solo(const struct EngineHandle *handle, uint8_t track, bool solo);
+
+/**
+ * Play a track on one of the stereo outputs of `render_outputs`, 0 for the
+ * main one
+ */
+bool set_track_output(const struct EngineHandle *handle, uint8_t track, uint8_t output);
+
+/**
+ * Master tune in cents, -100.0..100.0
+ */
+bool set_master_tune(const struct EngineHandle *handle, float cents);
+
+/**
+ * Master tune putting A4 at `freq`, e.g. 432.0 Hz
+ */
+bool set_reference_pitch(const struct EngineHandle *handle, float freq);
+
+/**
+ * Transpose a track's notes by `transpose` semitones and fine tune it by
+ * `cents`, -100.0..100.0. Drum tracks aren't transposed.
+ */
+bool set_track_tuning(const struct EngineHandle *handle,
+                      uint8_t track,
+                      int8_t transpose,
+                      float cents);
+
+/**
+ * Retune the notes to the Scala (.scl) scale at `path`, `root` keeping its
+ * equal-tempered pitch
+ */
+bool load_scala_scale(const struct EngineHandle *handle, const char *path, uint8_t root);
+
+/**
+ * Back to equal temperament
+ */
+bool clear_scala_scale(const struct EngineHandle *handle);
+
+bool set_master_volume(const struct EngineHandle *handle, float volume);
+
+bool set_dc_blocking(const struct EngineHandle *handle, bool enabled);
+
+bool set_track_sidechain(const struct EngineHandle *handle,
+                         uint8_t track,
+                         uint8_t source,
+                         float amount,
+                         float attack,
+                         float release);
+
+bool set_bus_sidechain(const struct EngineHandle *handle,
+                       uint8_t bus,
+                       uint8_t source,
+                       float amount,
+                       float attack,
+                       float release);
+
+/**
+ * The new pattern's index, 255 when the engine's queue is full
+ */
+uint8_t create_pattern(const struct EngineHandle *handle, const char *name, float length);
+
+bool set_time_signature(const struct EngineHandle *handle,
+                        uint8_t pattern,
+                        uint8_t numerator,
+                        uint8_t denominator);
+
+bool set_edit_pattern(const struct EngineHandle *handle, uint8_t pattern);
+
+bool queue_pattern(const struct EngineHandle *handle, uint8_t pattern);
+
+bool append_chain(const struct EngineHandle *handle, uint8_t pattern, uint32_t repeats);
+
+bool clear_chain(const struct EngineHandle *handle);
+
+/**
+ * Index of the pattern playing at the end of the last rendered buffer
+ */
+uint8_t get_current_pattern(const struct EngineHandle *handle);
+
+bool clear_events(const struct EngineHandle *handle);
+
+/**
+ * Save the engine's sound and sequence to `path` as JSON, waiting for the
+ * audio thread to capture it. False when the engine doesn't answer, e.g.
+ * because it isn't rendering, or the file can't be written.
+ */
+bool save_preset(const struct EngineHandle *handle, const char *path);
+
+/**
+ * Save the engine's sound and sequence to `path` in the binary format, see
+ * `save_preset`. `load_preset` reads either.
+ */
+bool save_preset_binary(const struct EngineHandle *handle, const char *path);
+
+bool load_preset(const struct EngineHandle *handle, const char *path);
+
+/**
+ * Keep the engine's sound and sequence in the preset bank, see
+ * `save_preset`
+ */
+bool store_preset(const struct EngineHandle *handle, uint8_t slot);
+
+bool recall_preset(const struct EngineHandle *handle, uint8_t slot);
+
+/**
+ * `in_l` and `in_r` are the host's audio input, or null without one
+ */
 void render(struct Engine *engine,
+            const float *in_l,
+            const float *in_r,
             float *buf_l,
             float *buf_r,
             int64_t sample_time,
             float tempo,
             int32_t num_frames);
 
+/**
+ * Render into `output_count` stereo outputs, `outputs` holding the left and
+ * right channel of each in turn. The first is the main output; tracks
+ * routed to an output that isn't passed play on the main one.
+ */
+void render_outputs(struct Engine *engine,
+                    const float *in_l,
+                    const float *in_r,
+                    float *const *outputs,
+                    size_t output_count,
+                    int64_t sample_time,
+                    float tempo,
+                    int32_t num_frames);
+
 void engine_free(struct Engine *ptr);
 
+void engine_handle_free(struct EngineHandle *handle);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
 #endif /* CP3_DSP_H */
@@ -0,0 +1,134 @@
+//! Spectrum analyzer
+//!
+//! Taps the master output, and every half frame takes a Hann windowed FFT
+//! of the last `size` samples. The magnitudes are kept in dB relative to a
+//! full scale sine, floored at `SPECTRUM_FLOOR_DB`.
+
+use rustfft::algorithm::Radix4;
+use rustfft::num_complex::Complex;
+use rustfft::Fft;
+use rustfft::FftDirection::Forward;
+use std::f32::consts::TAU;
+
+pub const MIN_SPECTRUM_SIZE: usize = 64;
+pub const MAX_SPECTRUM_SIZE: usize = 8192;
+/// level of silent bins
+pub const SPECTRUM_FLOOR_DB: f32 = -120.0;
+
+pub struct SpectrumAnalyzer {
+    fft: Radix4<f32>,
+    window: Vec<f32>,
+    // gain bringing a full scale sine to 0 dB
+    scale: f32,
+    // the last `size` input samples
+    input: Vec<f32>,
+    pos: usize,
+    // samples since the last frame
+    count: usize,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    /// `size` is rounded up to a power of two within
+    /// MIN_SPECTRUM_SIZE..=MAX_SPECTRUM_SIZE
+    pub fn new(size: usize) -> Self {
+        let size = size
+            .clamp(MIN_SPECTRUM_SIZE, MAX_SPECTRUM_SIZE)
+            .next_power_of_two();
+        let fft = Radix4::new(size, Forward);
+        let window: Vec<f32> = (0..size)
+            .map(|i| 0.5 - 0.5 * (TAU * i as f32 / size as f32).cos())
+            .collect();
+        let scale = 2.0 / window.iter().sum::<f32>();
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        Self {
+            fft,
+            window,
+            scale,
+            input: vec![0.0; size],
+            pos: 0,
+            count: 0,
+            buffer: vec![Complex::default(); size],
+            scratch,
+            magnitudes: vec![SPECTRUM_FLOOR_DB; size / 2],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Feed one sample, returning true when a new frame is ready
+    #[inline]
+    pub fn process(&mut self, x: f32) -> bool {
+        let size = self.size();
+        self.input[self.pos] = x;
+        self.pos = (self.pos + 1) % size;
+        self.count += 1;
+        if self.count < size / 2 {
+            return false;
+        }
+        self.count = 0;
+        self.analyze();
+        true
+    }
+
+    /// Magnitudes of the latest frame in dB, `size / 2` bins from DC up
+    pub fn magnitudes(&self) -> &[f32] {
+        &self.magnitudes
+    }
+
+    fn analyze(&mut self) {
+        // oldest sample first
+        let (newer, older) = self.input.split_at(self.pos);
+        let samples = older.iter().chain(newer.iter());
+        for ((bin, &x), &w) in self.buffer.iter_mut().zip(samples).zip(self.window.iter()) {
+            *bin = Complex::new(x * w, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        for (magnitude, bin) in self.magnitudes.iter_mut().zip(self.buffer.iter()) {
+            let amplitude = bin.norm() * self.scale;
+            *magnitude = (20.0 * amplitude.log10()).max(SPECTRUM_FLOOR_DB);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_peaks_in_its_bin() {
+        let sample_rate = 48000.0;
+        let mut analyzer = SpectrumAnalyzer::new(1000);
+        assert_eq!(analyzer.size(), 1024);
+        // centred on bin 32
+        let frequency = 32.0 * sample_rate / 1024.0;
+
+        let mut frames = 0;
+        for i in 0..4096 {
+            let x = 0.5 * (TAU * frequency * i as f32 / sample_rate).sin();
+            if analyzer.process(x) {
+                frames += 1;
+            }
+        }
+        assert_eq!(frames, 8);
+
+        let magnitudes = analyzer.magnitudes();
+        assert_eq!(magnitudes.len(), 512);
+        // half of full scale is about -6 dB
+        assert!((magnitudes[32] + 6.02).abs() < 0.1);
+        assert!(magnitudes[100] < -60.0);
+
+        let mut silent = SpectrumAnalyzer::new(0);
+        assert_eq!(silent.size(), MIN_SPECTRUM_SIZE);
+        for _ in 0..MIN_SPECTRUM_SIZE {
+            silent.process(0.0);
+        }
+        assert!(silent.magnitudes().iter().all(|&m| m == SPECTRUM_FLOOR_DB));
+    }
+}
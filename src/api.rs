@@ -0,0 +1,592 @@
+//! Rust API
+//!
+//! The C API works on raw pointers and untyped indices. Rust hosts can use
+//! this instead: an `EngineBuilder` creates an engine together with an
+//! `EngineHandle` holding the sending end of the engine's own message queue
+//! and its shared state. The engine is moved to the audio thread and
+//! rendered there, while handles are cloned to wherever notes, events and
+//! parameter changes come from.
+
+use crate::consts::TRACK_COUNT;
+use crate::drums::DRUM_PARAMETER_STRIDE;
+use crate::engine::Engine;
+use crate::eq::EQ_PARAMETER_OFFSET;
+use crate::lfo::GlobalLfo;
+use crate::macros::MacroDestination;
+use crate::presets::{LoadedPreset, Preset, PresetCapture, CAPTURE_EVENTS, CAPTURE_NAME_LEN};
+use crate::sampler::{sorted_slices, Sample};
+use crate::sequencer::{Event, Message, NoteExpression, StopMode};
+use crate::shared::Shared;
+use crate::synth::VoiceType;
+use crate::tuning::ScalaScale;
+use crate::{next_event_id, MESSAGE_CAPACITY, NEXT_NOTE_ID};
+use crossbeam::channel::{self, Sender, TrySendError};
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Index of a track, below TRACK_COUNT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Track(u8);
+
+impl Track {
+    pub fn new(index: usize) -> Option<Self> {
+        (index < TRACK_COUNT).then_some(Self(index as u8))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventId(pub u32);
+
+/// A live note, for following it with expression changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EqParameter {
+    /// dB
+    LowGain,
+    /// Hz
+    LowFrequency,
+    MidGain,
+    MidFrequency,
+    MidQ,
+    HighGain,
+    HighFrequency,
+}
+
+/// A parameter of a track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parameter {
+    /// parameter of the track's voice, see `VoiceType::parameters`
+    Voice(i8),
+    /// parameter of one instrument of a drum kit
+    Drum {
+        instrument: u8,
+        parameter: i8,
+    },
+    Eq(EqParameter),
+}
+
+impl Parameter {
+    /// Index used by `Message::ParameterChange` and the C API
+    pub fn index(&self) -> i8 {
+        match *self {
+            Parameter::Voice(parameter) => parameter,
+            Parameter::Drum {
+                instrument,
+                parameter,
+            } => instrument as i8 * DRUM_PARAMETER_STRIDE + parameter,
+            Parameter::Eq(parameter) => EQ_PARAMETER_OFFSET + parameter as i8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandleError {
+    /// the engine hasn't caught up with earlier messages
+    QueueFull,
+    /// the engine was dropped
+    Disconnected,
+    /// the engine didn't answer in time, e.g. because it isn't rendering
+    NoReply,
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::QueueFull => write!(f, "engine message queue is full"),
+            HandleError::Disconnected => write!(f, "engine was dropped"),
+            HandleError::NoReply => write!(f, "engine didn't reply"),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+pub struct EngineBuilder {
+    sample_rate: f32,
+    processing_rate: Option<f32>,
+    message_capacity: usize,
+}
+
+impl EngineBuilder {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            processing_rate: None,
+            message_capacity: MESSAGE_CAPACITY,
+        }
+    }
+
+    /// Process at `rate`, converting to and from the host's sample rate at
+    /// the boundary, so the sound doesn't depend on the device's rate
+    pub fn processing_rate(mut self, rate: f32) -> Self {
+        self.processing_rate = (rate > 0.0).then_some(rate);
+        self
+    }
+
+    /// Messages that can wait for the engine before sending fails
+    pub fn message_capacity(mut self, capacity: usize) -> Self {
+        self.message_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn build(self) -> (Engine, EngineHandle) {
+        let (sender, receiver) = channel::bounded(self.message_capacity);
+        let sample_rate = self.processing_rate.unwrap_or(self.sample_rate);
+        let mut engine = Engine::new(receiver, sample_rate);
+        engine.set_host_rate(self.sample_rate);
+        let shared = engine.shared().clone();
+        let handle = EngineHandle {
+            sender,
+            shared,
+            sample_rate,
+        };
+        (engine, handle)
+    }
+}
+
+/// Sends changes to an engine. Nothing blocks, so a handle can be used from
+/// any thread, including a realtime one, except for `capture_preset`, which
+/// waits for the engine, and `load_preset`, which builds the preset's voices
+/// and effects.
+#[derive(Clone)]
+pub struct EngineHandle {
+    sender: Sender<Message>,
+    shared: Arc<Shared>,
+    sample_rate: f32,
+}
+
+impl EngineHandle {
+    /// The rate the engine processes at
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// The engine's callbacks, levels and spectrum
+    pub fn shared(&self) -> &Shared {
+        &self.shared
+    }
+
+    /// Send any message, for what the typed methods don't cover
+    pub fn send(&self, msg: Message) -> Result<(), HandleError> {
+        self.shared.drop_retired();
+        self.sender.try_send(msg).map_err(|error| match error {
+            TrySendError::Full(_) => HandleError::QueueFull,
+            TrySendError::Disconnected(_) => HandleError::Disconnected,
+        })
+    }
+
+    /// Add an event to the edited pattern, under a new id
+    pub fn schedule(&self, event: Event) -> Result<EventId, HandleError> {
+        let id = next_event_id();
+        self.send(Message::Schedule(Event { id, ..event }))?;
+        Ok(EventId(id))
+    }
+
+    /// Add events to the edited pattern in a single message, each under a
+    /// new id
+    pub fn schedule_batch(
+        &self,
+        events: impl IntoIterator<Item = Event>,
+    ) -> Result<Vec<EventId>, HandleError> {
+        let (events, ids) = with_new_ids(events);
+        self.send(Message::ScheduleBatch(events))?;
+        Ok(ids)
+    }
+
+    /// Add events to the staged pattern, each under a new id, to replace the
+    /// edited pattern's events with once committed
+    pub fn stage_events(
+        &self,
+        events: impl IntoIterator<Item = Event>,
+    ) -> Result<Vec<EventId>, HandleError> {
+        let (events, ids) = with_new_ids(events);
+        self.send(Message::StageEvents(events))?;
+        Ok(ids)
+    }
+
+    /// Swap the staged events into the edited pattern, at the next loop
+    /// boundary or right away
+    pub fn commit_pattern(&self, immediately: bool) -> Result<(), HandleError> {
+        self.send(Message::CommitPattern { immediately })
+    }
+
+    /// Replace the event with `id`, keeping its parameter locks
+    pub fn update_event(&self, id: EventId, event: Event) -> Result<(), HandleError> {
+        self.send(Message::UpdateEvent(Event { id: id.0, ..event }))
+    }
+
+    pub fn remove_event(&self, id: EventId) -> Result<(), HandleError> {
+        self.send(Message::RemoveEvent(id.0))
+    }
+
+    pub fn note_on(&self, track: Track, pitch: u8, velocity: u8) -> Result<NoteId, HandleError> {
+        let id = NEXT_NOTE_ID.fetch_add(1, Ordering::Relaxed);
+        self.send(Message::NoteOn {
+            id,
+            track: track.0,
+            pitch,
+            velocity,
+            expression: NoteExpression::default(),
+            frame: 0,
+        })?;
+        Ok(NoteId(id))
+    }
+
+    pub fn note_off(&self, track: Track, pitch: u8) -> Result<(), HandleError> {
+        self.send(Message::NoteOff {
+            track: track.0,
+            pitch,
+            frame: 0,
+        })
+    }
+
+    pub fn set_sound(&self, track: Track, voice_type: VoiceType) -> Result<(), HandleError> {
+        self.send(Message::SetSound {
+            track: track.0,
+            voice_type,
+        })
+    }
+
+    pub fn load_sample(&self, track: Track, sample: Arc<Sample>) -> Result<(), HandleError> {
+        self.send(Message::LoadSample {
+            track: track.0,
+            sample,
+        })
+    }
+
+    /// Chop the track's sample at `points`, normalized to its length
+    pub fn set_sample_slices(&self, track: Track, points: &[f32]) -> Result<(), HandleError> {
+        self.send(Message::SampleSlices {
+            track: track.0,
+            slices: Arc::from(sorted_slices(points)),
+        })
+    }
+
+    pub fn set_parameter(
+        &self,
+        track: Track,
+        parameter: Parameter,
+        value: f32,
+    ) -> Result<(), HandleError> {
+        self.send(Message::ParameterChange(parameter.index(), value, track.0))
+    }
+
+    /// Capture the track's parameters into snapshot A (0) or B (1)
+    pub fn capture_snapshot(&self, track: Track, slot: u8) -> Result<(), HandleError> {
+        self.send(Message::CaptureSnapshot {
+            track: track.0,
+            slot,
+        })
+    }
+
+    /// Morph the track's continuous parameters between its snapshots, from
+    /// A at 0.0 to B at 1.0
+    pub fn set_morph(&self, track: Track, amount: f32) -> Result<(), HandleError> {
+        self.send(Message::Morph {
+            track: track.0,
+            amount,
+        })
+    }
+
+    /// Glide parameter changes across the next rendered block instead of
+    /// over the smoothing time, so automation sent once a block is smooth
+    pub fn set_block_ramps(&self, enabled: bool) -> Result<(), HandleError> {
+        self.send(Message::BlockRamps(enabled))
+    }
+
+    pub fn set_volume(&self, track: Track, volume: f32) -> Result<(), HandleError> {
+        self.send(Message::TrackVolume {
+            track: track.0,
+            volume,
+        })
+    }
+
+    /// -1.0 hard left to 1.0 hard right
+    pub fn set_pan(&self, track: Track, pan: f32) -> Result<(), HandleError> {
+        self.send(Message::TrackPan {
+            track: track.0,
+            pan,
+        })
+    }
+
+    pub fn set_mute(&self, track: Track, mute: bool) -> Result<(), HandleError> {
+        self.send(Message::TrackMute {
+            track: track.0,
+            mute,
+        })
+    }
+
+    /// Play the track on one of the stereo outputs, 0 for the main one
+    pub fn set_output(&self, track: Track, output: u8) -> Result<(), HandleError> {
+        self.send(Message::TrackOutput {
+            track: track.0,
+            output,
+        })
+    }
+
+    pub fn set_solo(&self, track: Track, solo: bool) -> Result<(), HandleError> {
+        self.send(Message::TrackSolo {
+            track: track.0,
+            solo,
+        })
+    }
+
+    /// Assign a parameter to `slot` of one of the track's macros, None
+    /// clears the slot
+    pub fn assign_macro(
+        &self,
+        track: Track,
+        index: u8,
+        slot: u8,
+        destination: Option<MacroDestination>,
+    ) -> Result<(), HandleError> {
+        self.send(Message::MacroAssign {
+            track: track.0,
+            index,
+            slot,
+            destination,
+        })
+    }
+
+    /// Turn one of the track's macros, 0.0..1.0
+    pub fn set_macro(&self, track: Track, index: u8, value: f32) -> Result<(), HandleError> {
+        self.send(Message::Macro {
+            track: track.0,
+            index,
+            value,
+        })
+    }
+
+    /// Set or clear one of the engine's global LFOs
+    pub fn set_global_lfo(&self, index: u8, lfo: Option<GlobalLfo>) -> Result<(), HandleError> {
+        self.send(Message::GlobalLfo { index, lfo })
+    }
+
+    /// Master tune in cents, -100.0..100.0
+    pub fn set_master_tune(&self, cents: f32) -> Result<(), HandleError> {
+        self.send(Message::MasterTune(cents))
+    }
+
+    /// Transpose a track by semitones and fine tune it in cents
+    pub fn set_track_tuning(
+        &self,
+        track: Track,
+        transpose: i8,
+        cents: f32,
+    ) -> Result<(), HandleError> {
+        self.send(Message::TrackTuning {
+            track: track.0,
+            transpose,
+            cents,
+        })
+    }
+
+    /// Retune the notes to `scale` around `root`, or back to equal
+    /// temperament
+    pub fn set_tuning_scale(
+        &self,
+        scale: Option<Arc<ScalaScale>>,
+        root: u8,
+    ) -> Result<(), HandleError> {
+        self.send(Message::TuningScale { scale, root })
+    }
+
+    pub fn set_master_volume(&self, volume: f32) -> Result<(), HandleError> {
+        self.send(Message::MasterVolume(volume))
+    }
+
+    /// Crossfade in milliseconds hiding the click of a sounding voice being
+    /// restarted or stolen, 0.0 to turn it off
+    pub fn set_declick_time(&self, ms: f32) -> Result<(), HandleError> {
+        self.send(Message::DeclickTime(ms))
+    }
+
+    /// What stopping the transport does to the sounding notes, and whether
+    /// it starts from the beginning again
+    pub fn set_stop_behavior(&self, mode: StopMode, rewind: bool) -> Result<(), HandleError> {
+        self.send(Message::StopBehavior { mode, rewind })
+    }
+
+    pub fn set_metronome(&self, enabled: bool, volume: f32) -> Result<(), HandleError> {
+        self.send(Message::Metronome { enabled, volume })
+    }
+
+    /// Stop every sounding note, whatever note offs are still to come
+    pub fn all_notes_off(&self) -> Result<(), HandleError> {
+        self.send(Message::AllNotesOff)
+    }
+
+    /// The engine's current sound, effects and sequence, captured on the
+    /// audio thread at its next block. Waits up to `timeout` for it.
+    pub fn capture_preset(&self, timeout: Duration) -> Result<Preset, HandleError> {
+        let deadline = Instant::now() + timeout;
+        let mut capture = Box::new(PresetCapture::with_room(CAPTURE_EVENTS, CAPTURE_NAME_LEN));
+        loop {
+            let (reply, captured) = channel::bounded(1);
+            self.send(Message::CapturePreset(capture, reply))?;
+            capture = captured
+                .recv_deadline(deadline)
+                .map_err(|_| HandleError::NoReply)?;
+            // captured again with room for what didn't fit
+            match capture.missing_room() {
+                Some((events, name_len)) => {
+                    capture = Box::new(PresetCapture::with_room(events, name_len));
+                }
+                None => return Ok(capture.into_preset(self.sample_rate)),
+            }
+        }
+    }
+
+    /// Load a preset, with its voices and effects built on the calling
+    /// thread
+    pub fn load_preset(&self, preset: Preset) -> Result<(), HandleError> {
+        let chord_tracks = self.shared.chord_tracks();
+        let loaded = LoadedPreset::new(preset, self.sample_rate, chord_tracks);
+        self.send(Message::LoadPreset(Box::new(loaded)))
+    }
+}
+
+/// Give every event a new id, returning the events and their ids
+fn with_new_ids(events: impl IntoIterator<Item = Event>) -> (Vec<Event>, Vec<EventId>) {
+    let events: Vec<Event> = events
+        .into_iter()
+        .map(|event| Event {
+            id: next_event_id(),
+            ..event
+        })
+        .collect();
+    let ids = events.iter().map(|event| EventId(event.id)).collect();
+    (events, ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_void;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn handle_drives_its_engine() {
+        let (mut engine, handle) = EngineBuilder::new(48000.0).message_capacity(2).build();
+        let track = Track::new(1).unwrap();
+        let id = handle
+            .schedule(Event {
+                track: 1,
+                duration: 0.5,
+                ..Default::default()
+            })
+            .unwrap();
+        handle.set_solo(track, true).unwrap();
+        assert_eq!(handle.set_pan(track, 0.0), Err(HandleError::QueueFull));
+
+        engine.set_playing(true);
+        let (mut left, mut right) = (vec![0.0; 4800], vec![0.0; 4800]);
+        engine.process(&mut left, &mut right, 0, 120.0, 4800);
+        assert!(left.iter().any(|&y| y != 0.0));
+
+        handle.remove_event(id).unwrap();
+        drop(engine);
+        assert_eq!(
+            handle.set_master_volume(0.5),
+            Err(HandleError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn batches_take_one_message() {
+        let (mut engine, handle) = EngineBuilder::new(48000.0).message_capacity(1).build();
+        // a dense 64 step pattern on 8 tracks
+        let events = (0..64 * 8).map(|i| Event {
+            beat_time: (i / 8) as f32 * 0.25,
+            track: (i % 8) as u8,
+            duration: 0.25,
+            ..Default::default()
+        });
+        let ids = handle.schedule_batch(events).unwrap();
+        assert_eq!(ids.len(), 512);
+        assert!(ids.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(
+            handle.schedule(Event::default()),
+            Err(HandleError::QueueFull)
+        );
+
+        let (mut left, mut right) = (vec![0.0; 64], vec![0.0; 64]);
+        engine.process(&mut left, &mut right, 0, 120.0, 64);
+        assert_eq!(engine.capture_preset().events.len(), 512);
+    }
+
+    #[test]
+    fn presets_are_captured_on_the_audio_thread() {
+        let (mut engine, handle) = EngineBuilder::new(48000.0).build();
+        assert_eq!(
+            handle.capture_preset(Duration::from_millis(10)),
+            Err(HandleError::NoReply)
+        );
+
+        handle.schedule(Event::default()).unwrap();
+        let host = {
+            let handle = handle.clone();
+            std::thread::spawn(move || handle.capture_preset(Duration::from_secs(10)))
+        };
+        let (mut left, mut right) = (vec![0.0; 64], vec![0.0; 64]);
+        while !host.is_finished() {
+            engine.process(&mut left, &mut right, 0, 120.0, 64);
+        }
+        assert_eq!(host.join().unwrap().unwrap().events.len(), 1);
+    }
+
+    extern "C" fn count_notes(context: *mut c_void, _: bool, _: u8, _: u8, _: u32, _: f32) {
+        let count = unsafe { &*(context as *const AtomicU32) };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn engines_are_isolated() {
+        let (mut engine, handle) = EngineBuilder::new(48000.0).build();
+        let (mut other, other_handle) = EngineBuilder::new(48000.0).build();
+        let (count, other_count) = (AtomicU32::new(0), AtomicU32::new(0));
+        let context = |count: &AtomicU32| count as *const _ as *mut c_void;
+        handle
+            .shared()
+            .set_note_callback(count_notes, context(&count));
+        other_handle
+            .shared()
+            .set_note_callback(count_notes, context(&other_count));
+
+        let track = Track::new(0).unwrap();
+        handle.note_on(track, 60, 100).unwrap();
+        handle.note_on(track, 64, 100).unwrap();
+        other_handle.note_on(track, 60, 100).unwrap();
+        let (mut left, mut right) = (vec![0.0; 64], vec![0.0; 64]);
+        engine.process(&mut left, &mut right, 0, 120.0, 64);
+        other.process(&mut left, &mut right, 0, 120.0, 64);
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+        assert_eq!(other_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn typed_parameters_map_to_indices() {
+        assert_eq!(Track::new(TRACK_COUNT), None);
+        assert_eq!(Parameter::Voice(3).index(), 3);
+        assert_eq!(
+            Parameter::Drum {
+                instrument: 2,
+                parameter: 1
+            }
+            .index(),
+            2 * DRUM_PARAMETER_STRIDE + 1
+        );
+        assert_eq!(
+            Parameter::Eq(EqParameter::MidQ).index(),
+            EQ_PARAMETER_OFFSET + 4
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! Send/return buses
+//!
+//! Tracks feed any number of send buses, each running a chain of effects
+//! whose output is mixed back into the master. Buses are never removed, so
+//! their index stays valid once created. The same effect chains serve as
+//! per-track inserts.
+
+use crate::chorus::Chorus;
+#[cfg(feature = "convolution")]
+use crate::convolution::{Convolver, ImpulseResponse};
+use crate::delay::{Delay, MultiTapDelay, PingPongDelay};
+use crate::gate::TranceGate;
+use crate::parameters::ParameterInfo;
+use crate::phaser::Phaser;
+use crate::pitch_shifter::PitchShifter;
+use crate::presets::EffectPreset;
+use crate::reverb::Reverb;
+use crate::saturation::Saturator;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "convolution")]
+use std::sync::Arc;
+
+/// maximum number of send buses, including the default ones
+pub const MAX_BUSES: usize = 8;
+
+/// default buses, fed by the voices' own reverb and delay sends
+pub const REVERB_BUS: usize = 0;
+pub const DELAY_BUS: usize = 1;
+
+// maximum number of effects in a chain
+const MAX_EFFECTS: usize = 8;
+
+// every effect type's parameter ids are below this, so their values are
+// captured before looking up which ones the type has
+const MAX_EFFECT_PARAMETERS: usize = 32;
+
+/// An audio effect hosted on a bus or track insert
+pub trait Effect: Send {
+    fn process(&mut self, x: f32) -> f32;
+
+    /// Stereo processing; mono effects process the mid signal
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let y = self.process((left + right) * 0.5);
+        (y, y)
+    }
+
+    fn set_parameter(&mut self, _parameter: i8, _value: f32) {}
+
+    fn get_parameter(&self, _parameter: i8) -> f32 {
+        0.0
+    }
+
+    /// Called with the current tempo, for tempo-synced effects
+    fn set_tempo(&mut self, _samples_per_beat: f32) {}
+
+    /// Called with the sequencer's position in beats while the transport
+    /// plays, for effects following the pattern
+    fn set_position(&mut self, _beats: f32) {}
+
+    /// Hold the effect's tail indefinitely, for effects with a tail
+    fn set_freeze(&mut self, _freeze: bool) {}
+
+    /// Convolve with `ir`, for convolution effects
+    #[cfg(feature = "convolution")]
+    fn set_impulse_response(&mut self, _ir: Arc<ImpulseResponse>) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EffectType {
+    Reverb,
+    Delay,
+    Saturator,
+    Chorus,
+    Phaser,
+    PingPongDelay,
+    MultiTapDelay,
+    PitchShifter,
+    TranceGate,
+    #[cfg(feature = "convolution")]
+    Convolution,
+}
+
+impl EffectType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(EffectType::Reverb),
+            1 => Some(EffectType::Delay),
+            2 => Some(EffectType::Saturator),
+            3 => Some(EffectType::Chorus),
+            4 => Some(EffectType::Phaser),
+            5 => Some(EffectType::PingPongDelay),
+            6 => Some(EffectType::MultiTapDelay),
+            7 => Some(EffectType::PitchShifter),
+            8 => Some(EffectType::TranceGate),
+            #[cfg(feature = "convolution")]
+            9 => Some(EffectType::Convolution),
+            _ => None,
+        }
+    }
+
+    /// Metadata of the effect's parameters, as created by `create_effect`
+    pub fn parameters(&self, sample_rate: f32) -> Vec<ParameterInfo> {
+        match self {
+            EffectType::Reverb => Reverb::parameters(),
+            EffectType::Delay => Delay::parameters(sample_rate),
+            EffectType::Saturator => Saturator::parameters(),
+            EffectType::Chorus => Chorus::parameters(),
+            EffectType::Phaser => Phaser::parameters(),
+            EffectType::PingPongDelay => PingPongDelay::parameters(sample_rate),
+            EffectType::MultiTapDelay => MultiTapDelay::parameters(sample_rate),
+            EffectType::PitchShifter => PitchShifter::parameters(),
+            EffectType::TranceGate => TranceGate::parameters(),
+            #[cfg(feature = "convolution")]
+            EffectType::Convolution => Convolver::parameters(),
+        }
+    }
+}
+
+pub fn create_effect(effect_type: EffectType, sample_rate: f32) -> Box<dyn Effect> {
+    match effect_type {
+        EffectType::Reverb => Box::new(Reverb::new(sample_rate)),
+        EffectType::Delay => Box::new(Delay::new(sample_rate * 0.5, 0.5, sample_rate)),
+        EffectType::Saturator => Box::new(Saturator::new(sample_rate)),
+        EffectType::Chorus => Box::new(Chorus::new(sample_rate)),
+        EffectType::Phaser => Box::new(Phaser::new(sample_rate)),
+        EffectType::PingPongDelay => {
+            Box::new(PingPongDelay::new(sample_rate * 0.25, 0.5, sample_rate))
+        }
+        EffectType::MultiTapDelay => Box::new(MultiTapDelay::new(sample_rate)),
+        EffectType::PitchShifter => Box::new(PitchShifter::new(sample_rate)),
+        EffectType::TranceGate => Box::new(TranceGate::new(sample_rate)),
+        #[cfg(feature = "convolution")]
+        EffectType::Convolution => Box::new(Convolver::new()),
+    }
+}
+
+/// Send level of a track into a bus, tapped before or after the track fader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackSend {
+    pub level: f32,
+    pub pre_fader: bool,
+}
+
+/// The typed effects of a chain and the values of their parameters, see
+/// `EffectChain::capture`
+#[derive(Clone, Copy)]
+pub struct ChainCapture {
+    effects: [Option<(EffectType, [f32; MAX_EFFECT_PARAMETERS])>; MAX_EFFECTS],
+}
+
+impl Default for ChainCapture {
+    fn default() -> Self {
+        Self {
+            effects: [None; MAX_EFFECTS],
+        }
+    }
+}
+
+impl ChainCapture {
+    /// The captured effects with the parameters their types have
+    pub fn presets(&self, sample_rate: f32) -> Vec<EffectPreset> {
+        self.effects
+            .iter()
+            .flatten()
+            .map(|(effect_type, values)| EffectPreset {
+                effect_type: *effect_type,
+                parameters: effect_type
+                    .parameters(sample_rate)
+                    .iter()
+                    .filter_map(|info| Some((info.id, *values.get(info.id as usize)?)))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// Effects processed in series
+#[derive(Default)]
+pub struct EffectChain {
+    effects: Vec<Box<dyn Effect>>,
+    // type of each effect, None for effects added without one
+    types: Vec<Option<EffectType>>,
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self {
+            effects: Vec::with_capacity(MAX_EFFECTS),
+            types: Vec::with_capacity(MAX_EFFECTS),
+        }
+    }
+
+    /// A chain of the effects in `presets`, set up as they were saved
+    pub fn from_presets(presets: &[EffectPreset], sample_rate: f32) -> Self {
+        let mut chain = Self::new();
+        for preset in presets.iter() {
+            let index = chain.effect_count();
+            chain.add(preset.effect_type, sample_rate);
+            for &(parameter, value) in preset.parameters.iter() {
+                chain.set_effect_parameter(index, parameter, value);
+            }
+        }
+        chain
+    }
+
+    /// Append an effect to the end of the chain
+    pub fn add_effect(&mut self, effect: Box<dyn Effect>) {
+        self.push(effect, None);
+    }
+
+    /// Append a new effect of `effect_type`, which presets can recreate
+    pub fn add(&mut self, effect_type: EffectType, sample_rate: f32) {
+        self.push(create_effect(effect_type, sample_rate), Some(effect_type));
+    }
+
+    /// Append `effect`, created by `create_effect` for `effect_type`, so it
+    /// can be built off the audio thread. It's handed back when the chain
+    /// is full.
+    pub fn add_created(
+        &mut self,
+        effect_type: EffectType,
+        effect: Box<dyn Effect>,
+    ) -> Option<Box<dyn Effect>> {
+        self.push(effect, Some(effect_type))
+    }
+
+    fn push(
+        &mut self,
+        effect: Box<dyn Effect>,
+        effect_type: Option<EffectType>,
+    ) -> Option<Box<dyn Effect>> {
+        if self.effects.len() == MAX_EFFECTS {
+            return Some(effect);
+        }
+        self.effects.push(effect);
+        self.types.push(effect_type);
+        None
+    }
+
+    /// The chain's effects and their settings, leaving out effects added
+    /// without a type. Impulse responses aren't kept.
+    pub fn presets(&self, sample_rate: f32) -> Vec<EffectPreset> {
+        let mut capture = ChainCapture::default();
+        self.capture(&mut capture);
+        capture.presets(sample_rate)
+    }
+
+    /// Copy the chain's typed effects and their parameter values into
+    /// `capture`, without allocating, so it's done on the audio thread
+    pub fn capture(&self, capture: &mut ChainCapture) {
+        capture.effects = [None; MAX_EFFECTS];
+        let effects = self.effects.iter().zip(self.types.iter());
+        for (captured, (effect, effect_type)) in capture.effects.iter_mut().zip(effects) {
+            *captured = effect_type.map(|effect_type| {
+                let values = std::array::from_fn(|id| effect.get_parameter(id as i8));
+                (effect_type, values)
+            });
+        }
+    }
+
+    pub fn effect_count(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn set_effect_parameter(&mut self, effect: usize, parameter: i8, value: f32) {
+        if let Some(effect) = self.effects.get_mut(effect) {
+            effect.set_parameter(parameter, value);
+        }
+    }
+
+    pub fn effect_parameter(&self, effect: usize, parameter: i8) -> f32 {
+        self.effects
+            .get(effect)
+            .map_or(0.0, |effect| effect.get_parameter(parameter))
+    }
+
+    pub fn set_tempo(&mut self, samples_per_beat: f32) {
+        for effect in self.effects.iter_mut() {
+            effect.set_tempo(samples_per_beat);
+        }
+    }
+
+    pub fn set_position(&mut self, beats: f32) {
+        for effect in self.effects.iter_mut() {
+            effect.set_position(beats);
+        }
+    }
+
+    pub fn set_freeze(&mut self, freeze: bool) {
+        for effect in self.effects.iter_mut() {
+            effect.set_freeze(freeze);
+        }
+    }
+
+    #[cfg(feature = "convolution")]
+    pub fn set_impulse_response(&mut self, effect: usize, ir: Arc<ImpulseResponse>) {
+        if let Some(effect) = self.effects.get_mut(effect) {
+            effect.set_impulse_response(ir);
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.effects
+            .iter_mut()
+            .fold(x, |acc, effect| effect.process(acc))
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        self.effects
+            .iter_mut()
+            .fold((left, right), |(l, r), effect| effect.process_stereo(l, r))
+    }
+}
+
+pub struct SendBus {
+    pub name: String,
+    pub return_level: f32,
+    pub chain: EffectChain,
+}
+
+impl SendBus {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            return_level: 1.0,
+            chain: EffectChain::new(),
+        }
+    }
+
+    /// Append an effect to the end of the chain
+    pub fn add_effect(&mut self, effect: Box<dyn Effect>) {
+        self.chain.add_effect(effect);
+    }
+
+    /// Append a new effect of `effect_type`, which presets can recreate
+    pub fn add(&mut self, effect_type: EffectType, sample_rate: f32) {
+        self.chain.add(effect_type, sample_rate);
+    }
+
+    pub fn effect_count(&self) -> usize {
+        self.chain.effect_count()
+    }
+
+    pub fn set_effect_parameter(&mut self, effect: usize, parameter: i8, value: f32) {
+        self.chain.set_effect_parameter(effect, parameter, value);
+    }
+
+    /// Freeze the tails of all effects on the bus
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.chain.set_freeze(freeze);
+    }
+
+    pub fn effect_parameter(&self, effect: usize, parameter: i8) -> f32 {
+        self.chain.effect_parameter(effect, parameter)
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.chain.process(x) * self.return_level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Gain(f32);
+
+    impl Effect for Gain {
+        fn process(&mut self, x: f32) -> f32 {
+            x * self.0
+        }
+
+        fn set_parameter(&mut self, _: i8, value: f32) {
+            self.0 = value;
+        }
+
+        fn get_parameter(&self, _: i8) -> f32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn empty_bus_passes_input() {
+        let mut bus = SendBus::new("empty");
+        assert_eq!(bus.process(0.5), 0.5);
+        bus.return_level = 0.5;
+        assert_eq!(bus.process(0.5), 0.25);
+    }
+
+    #[test]
+    fn effects_run_in_series() {
+        let mut bus = SendBus::new("gain");
+        bus.add_effect(Box::new(Gain(2.0)));
+        bus.add_effect(Box::new(Gain(3.0)));
+        assert_eq!(bus.process(1.0), 6.0);
+
+        bus.set_effect_parameter(1, 0, 0.5);
+        assert_eq!(bus.effect_parameter(1, 0), 0.5);
+        assert_eq!(bus.process(1.0), 1.0);
+        // missing effects are ignored
+        bus.set_effect_parameter(5, 0, 1.0);
+        assert_eq!(bus.effect_parameter(5, 0), 0.0);
+    }
+
+    #[test]
+    fn chain_length_is_limited() {
+        let mut bus = SendBus::new("full");
+        for _ in 0..MAX_EFFECTS + 2 {
+            bus.add_effect(Box::new(Gain(1.0)));
+        }
+        assert_eq!(bus.effect_count(), MAX_EFFECTS);
+
+        // effects created elsewhere are handed back
+        let effect = create_effect(EffectType::Saturator, 48000.0);
+        assert!(bus
+            .chain
+            .add_created(EffectType::Saturator, effect)
+            .is_some());
+    }
+
+    #[test]
+    fn chains_round_trip_through_presets() {
+        let sample_rate = 48000.0;
+        let mut chain = EffectChain::new();
+        chain.add(EffectType::Chorus, sample_rate);
+        chain.add_effect(Box::new(Gain(2.0)));
+        chain.add(EffectType::Delay, sample_rate);
+        chain.set_effect_parameter(2, 1, 0.25);
+
+        // effects without a type are left out
+        let presets = chain.presets(sample_rate);
+        assert_eq!(presets.len(), 2);
+        assert_eq!(presets[1].effect_type, EffectType::Delay);
+
+        let restored = EffectChain::from_presets(&presets, sample_rate);
+        assert_eq!(restored.effect_count(), 2);
+        assert_eq!(restored.effect_parameter(1, 1), 0.25);
+        assert_eq!(restored.presets(sample_rate), presets);
+    }
+
+    #[test]
+    fn parameter_ids_fit_the_capture() {
+        for effect_type in (0..).map_while(EffectType::from_u8) {
+            for info in effect_type.parameters(48000.0) {
+                assert!(
+                    (info.id as usize) < MAX_EFFECT_PARAMETERS,
+                    "{:?} {}",
+                    effect_type,
+                    info.id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parameter_defaults_match_new_effects() {
+        let sample_rate = 48000.0;
+        for effect_type in (0..).map_while(EffectType::from_u8) {
+            let effect = create_effect(effect_type, sample_rate);
+            for info in effect_type.parameters(sample_rate) {
+                let value = effect.get_parameter(info.id);
+                assert!(
+                    (value - info.default).abs() <= 1e-4 * info.default.abs().max(1.0),
+                    "{:?} {}: {} != {}",
+                    effect_type,
+                    info.name,
+                    value,
+                    info.default
+                );
+                assert!(info.min <= info.default && info.default <= info.max);
+            }
+        }
+    }
+
+    #[test]
+    fn effect_types_from_u8() {
+        assert_eq!(EffectType::from_u8(2), Some(EffectType::Saturator));
+        assert_eq!(EffectType::from_u8(3), Some(EffectType::Chorus));
+        assert_eq!(EffectType::from_u8(4), Some(EffectType::Phaser));
+        assert_eq!(EffectType::from_u8(5), Some(EffectType::PingPongDelay));
+        assert_eq!(EffectType::from_u8(6), Some(EffectType::MultiTapDelay));
+        assert_eq!(EffectType::from_u8(7), Some(EffectType::PitchShifter));
+        assert_eq!(EffectType::from_u8(8), Some(EffectType::TranceGate));
+        #[cfg(feature = "convolution")]
+        assert_eq!(EffectType::from_u8(9), Some(EffectType::Convolution));
+        assert_eq!(EffectType::from_u8(10), None);
+    }
+}
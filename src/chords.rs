@@ -0,0 +1,167 @@
+//! Chord mode
+//!
+//! A track in chord mode plays every note as a chord: intervals above the
+//! played pitch, taken from a named chord type or a user list. Inversions
+//! move the lowest notes up an octave each, and the spread opens the
+//! voicing by moving every second note up by whole octaves.
+
+use crate::utils::transpose;
+
+/// notes in a chord, and voices of a track in chord mode
+pub const MAX_CHORD_NOTES: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChordType {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Major7,
+    Minor7,
+    Dominant7,
+    Major9,
+    Minor9,
+    /// root and fifth
+    Power,
+}
+
+impl ChordType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ChordType::Major),
+            1 => Some(ChordType::Minor),
+            2 => Some(ChordType::Diminished),
+            3 => Some(ChordType::Augmented),
+            4 => Some(ChordType::Sus2),
+            5 => Some(ChordType::Sus4),
+            6 => Some(ChordType::Major7),
+            7 => Some(ChordType::Minor7),
+            8 => Some(ChordType::Dominant7),
+            9 => Some(ChordType::Major9),
+            10 => Some(ChordType::Minor9),
+            11 => Some(ChordType::Power),
+            _ => None,
+        }
+    }
+
+    /// Semitones above the root
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ChordType::Major => &[0, 4, 7],
+            ChordType::Minor => &[0, 3, 7],
+            ChordType::Diminished => &[0, 3, 6],
+            ChordType::Augmented => &[0, 4, 8],
+            ChordType::Sus2 => &[0, 2, 7],
+            ChordType::Sus4 => &[0, 5, 7],
+            ChordType::Major7 => &[0, 4, 7, 11],
+            ChordType::Minor7 => &[0, 3, 7, 10],
+            ChordType::Dominant7 => &[0, 4, 7, 10],
+            ChordType::Major9 => &[0, 4, 7, 11, 14],
+            ChordType::Minor9 => &[0, 3, 7, 10, 14],
+            ChordType::Power => &[0, 7],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chord {
+    // ascending semitones above the played note
+    intervals: [u8; MAX_CHORD_NOTES],
+    len: usize,
+    pub inversion: u8,
+    /// octaves every second note is moved up by
+    pub spread: u8,
+}
+
+impl Chord {
+    pub fn new(chord_type: ChordType, inversion: u8, spread: u8) -> Self {
+        Self::from_intervals(chord_type.intervals(), inversion, spread)
+            .expect("chord types have intervals")
+    }
+
+    /// A chord of semitone intervals above the played note. Intervals past
+    /// MAX_CHORD_NOTES are dropped; None when there are none.
+    pub fn from_intervals(intervals: &[u8], inversion: u8, spread: u8) -> Option<Self> {
+        if intervals.is_empty() {
+            return None;
+        }
+        let len = intervals.len().min(MAX_CHORD_NOTES);
+        let mut sorted = [0; MAX_CHORD_NOTES];
+        sorted[..len].copy_from_slice(&intervals[..len]);
+        sorted[..len].sort_unstable();
+        Some(Self {
+            intervals: sorted,
+            len,
+            inversion,
+            spread,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Pitches of the chord on `pitch`, in interval order. Notes above the
+    /// MIDI range are left out.
+    pub fn notes(&self, pitch: u8) -> impl DoubleEndedIterator<Item = u8> + '_ {
+        let len = self.len;
+        let inversion = self.inversion as usize;
+        self.intervals[..len]
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, &interval)| {
+                // each inversion moves the lowest remaining note up an octave
+                let mut octaves = (inversion + len - 1 - i) / len;
+                if i % 2 == 1 {
+                    octaves += self.spread as usize;
+                }
+                transpose(pitch, interval as i32 + 12 * octaves as i32)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_chords_with_inversions() {
+        let c_major = Chord::new(ChordType::Major, 0, 0);
+        assert_eq!(c_major.notes(60).collect::<Vec<_>>(), [60, 64, 67]);
+
+        let first = Chord::new(ChordType::Major, 1, 0);
+        assert_eq!(first.notes(60).collect::<Vec<_>>(), [72, 64, 67]);
+        let second = Chord::new(ChordType::Major, 2, 0);
+        assert_eq!(second.notes(60).collect::<Vec<_>>(), [72, 76, 67]);
+        // a full turn is the chord an octave up
+        let third = Chord::new(ChordType::Major, 3, 0);
+        assert_eq!(third.notes(60).collect::<Vec<_>>(), [72, 76, 79]);
+
+        let open = Chord::new(ChordType::Minor7, 0, 1);
+        assert_eq!(open.notes(57).collect::<Vec<_>>(), [57, 72, 64, 79]);
+    }
+
+    #[test]
+    fn user_intervals() {
+        assert!(Chord::from_intervals(&[], 0, 0).is_none());
+
+        let chord = Chord::from_intervals(&[7, 0, 12, 1, 2, 3, 4, 5], 0, 0).unwrap();
+        assert_eq!(chord.len(), MAX_CHORD_NOTES);
+        assert_eq!(
+            chord.notes(60).collect::<Vec<_>>(),
+            [60, 61, 62, 63, 67, 72]
+        );
+
+        // notes past the top of the range are dropped
+        let chord = Chord::new(ChordType::Major, 0, 0);
+        assert_eq!(chord.notes(122).collect::<Vec<_>>(), [122, 126]);
+        assert_eq!(ChordType::from_u8(11), Some(ChordType::Power));
+        assert_eq!(ChordType::from_u8(12), None);
+    }
+}
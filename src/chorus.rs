@@ -0,0 +1,204 @@
+//! Chorus/ensemble
+//!
+//! A few taps read a shared delay line at times swept by one LFO, with the
+//! taps' LFO phases spread evenly so they never line up. Taps are panned
+//! across the stereo field by the spread amount.
+
+use crate::bus::Effect;
+use crate::delay::{DelayLine, InterpolationType};
+use crate::parameters::ParameterInfo;
+use std::f32::consts::TAU;
+
+pub const MAX_CHORUS_TAPS: usize = 3;
+
+// shortest delay of a tap, and the range the LFO sweeps at full depth
+const BASE_DELAY_MS: f32 = 7.0;
+const MAX_DEPTH_MS: f32 = 10.0;
+
+pub struct Chorus {
+    delay_line: DelayLine,
+    taps: usize,
+    rate: f32,
+    depth: f32,
+    spread: f32,
+    mix: f32,
+    phase: f32,
+    sample_rate: f32,
+}
+
+impl Chorus {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Rate", 0.01, 10.0, 0.5, "Hz"),
+            ParameterInfo::linear(1, "Depth", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::linear(2, "Spread", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::linear(3, "Mix", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::stepped(4, "Taps", 2.0, MAX_CHORUS_TAPS as f32, 3.0),
+        ]
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        let length = ((BASE_DELAY_MS + MAX_DEPTH_MS) * 0.001 * sample_rate) as usize + 4;
+        Self {
+            delay_line: DelayLine::new(InterpolationType::Linear, length),
+            taps: MAX_CHORUS_TAPS,
+            rate: 0.5,
+            depth: 0.5,
+            spread: 1.0,
+            mix: 0.5,
+            phase: 0.0,
+            sample_rate,
+        }
+    }
+
+    /// Number of delay taps, 2..=MAX_CHORUS_TAPS
+    pub fn set_taps(&mut self, taps: usize) {
+        self.taps = taps.clamp(2, MAX_CHORUS_TAPS);
+    }
+
+    /// LFO rate in Hz
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.max(0.0);
+    }
+
+    /// Sweep depth, 0.0..1.0
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    /// Stereo spread of the taps, 0.0..1.0
+    pub fn set_spread(&mut self, spread: f32) {
+        self.spread = spread.clamp(0.0, 1.0);
+    }
+
+    /// Dry/wet balance, 0.0..1.0
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, x: f32) -> (f32, f32) {
+        self.delay_line.write_and_increment(x);
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for i in 0..self.taps {
+            let offset = i as f32 / self.taps as f32;
+            let lfo = 0.5 + 0.5 * (TAU * (self.phase + offset)).sin();
+            let delay_ms = BASE_DELAY_MS + self.depth * MAX_DEPTH_MS * lfo;
+            let y = self
+                .delay_line
+                .read_delayed(delay_ms * 0.001 * self.sample_rate);
+
+            // taps positioned evenly from left to right
+            let position = i as f32 / (self.taps - 1) as f32 * 2.0 - 1.0;
+            let pan = self.spread * position;
+            left += y * (1.0 - pan) * 0.5;
+            right += y * (1.0 + pan) * 0.5;
+        }
+
+        self.phase += self.rate / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+
+        let wet = 2.0 / self.taps as f32;
+        let dry = x * (1.0 - self.mix);
+        (dry + left * wet * self.mix, dry + right * wet * self.mix)
+    }
+}
+
+/// 0: rate (Hz), 1: depth, 2: spread, 3: mix, 4: taps
+impl Effect for Chorus {
+    fn process(&mut self, x: f32) -> f32 {
+        let (left, right) = Chorus::process_stereo(self, x);
+        (left + right) * 0.5
+    }
+
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        Chorus::process_stereo(self, (left + right) * 0.5)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_rate(value),
+            1 => self.set_depth(value),
+            2 => self.set_spread(value),
+            3 => self.set_mix(value),
+            4 => self.set_taps(value as usize),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.rate,
+            1 => self.depth,
+            2 => self.spread,
+            3 => self.mix,
+            4 => self.taps as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_mix_passes_input() {
+        let mut chorus = Chorus::new(48000.0);
+        chorus.set_mix(0.0);
+        for i in 0..1000 {
+            let x = (i as f32 * 0.01).sin();
+            assert_eq!(chorus.process_stereo(x), (x, x));
+        }
+    }
+
+    #[test]
+    fn wet_signal_is_delayed() {
+        let sample_rate = 48000.0;
+        let mut chorus = Chorus::new(sample_rate);
+        chorus.set_mix(1.0);
+        let base_delay = (BASE_DELAY_MS * 0.001 * sample_rate) as usize;
+
+        let ys: Vec<(f32, f32)> = (0..2000)
+            .map(|i| chorus.process_stereo(if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        // nothing comes out before the shortest tap delay
+        assert!(ys[..base_delay - 1]
+            .iter()
+            .all(|&(l, r)| l == 0.0 && r == 0.0));
+        assert!(ys.iter().any(|&(l, r)| l.abs() > 0.0 || r.abs() > 0.0));
+    }
+
+    #[test]
+    fn spread_separates_channels() {
+        let mut chorus = Chorus::new(48000.0);
+        chorus.set_mix(1.0);
+        chorus.set_spread(1.0);
+        let mut difference = 0.0;
+        for i in 0..9600 {
+            let (l, r) = chorus.process_stereo((i as f32 * 0.05).sin());
+            difference += (l - r).abs();
+        }
+        assert!(difference > 1.0);
+
+        chorus.set_spread(0.0);
+        for i in 0..100 {
+            let (l, r) = chorus.process_stereo((i as f32 * 0.05).sin());
+            assert!((l - r).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut chorus = Chorus::new(48000.0);
+        for (parameter, value) in [(0, 1.5), (1, 0.25), (2, 0.75), (3, 0.3), (4, 2.0)] {
+            chorus.set_parameter(parameter, value);
+            assert_eq!(chorus.get_parameter(parameter), value);
+        }
+        chorus.set_parameter(4, 10.0);
+        assert_eq!(chorus.get_parameter(4), MAX_CHORUS_TAPS as f32);
+    }
+}
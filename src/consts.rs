@@ -4,6 +4,8 @@
 pub const A4_FREQ: f32 = 440.0;
 pub const A4_MIDI: u8 = 69;
 
+pub const TRACK_COUNT: usize = 16;
+
 /// test signals
 pub const DC_SIGNAL: [f32; 7] = [0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
 pub const NYQUIST_SIGNAL: [f32; 7] = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0];
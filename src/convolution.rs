@@ -0,0 +1,410 @@
+//! Convolution
+//!
+//! Convolves its input with a recorded impulse response: the sound of a
+//! room, a plate or a speaker cabinet. The response is cut into partitions
+//! of `PARTITION_SIZE` samples, transformed once when it's loaded. Every
+//! block of input is transformed in turn, each partition is multiplied with
+//! the block as far back as the partition lies in the response, and the
+//! sum is transformed back (uniformly partitioned overlap-save). The output
+//! lags the input by one partition.
+
+use crate::bus::Effect;
+use crate::parameters::ParameterInfo;
+use crate::resampler::{resample, ResamplerQuality};
+use rustfft::algorithm::Radix4;
+use rustfft::num_complex::Complex;
+use rustfft::Fft;
+use rustfft::FftDirection::{Forward, Inverse};
+use std::path::Path;
+use std::sync::Arc;
+
+/// samples per partition, and the latency of the convolution
+pub const PARTITION_SIZE: usize = 256;
+
+/// longest impulse response loaded, in seconds; the rest is cut off
+pub const MAX_IR_SECONDS: f32 = 10.0;
+
+// transforms cover a partition and the one before it
+const FFT_SIZE: usize = PARTITION_SIZE * 2;
+
+/// An impulse response of one or two channels, transformed for convolution
+/// at one sample rate
+pub struct ImpulseResponse {
+    // the spectra of each channel's partitions
+    channels: Vec<Vec<Vec<Complex<f32>>>>,
+    length: usize,
+}
+
+impl ImpulseResponse {
+    /// Transform `channels` recorded at `source_rate` for convolution at
+    /// `sample_rate`; past the first two channels are ignored
+    pub fn new(channels: &[Vec<f32>], source_rate: f32, sample_rate: f32) -> Self {
+        let fft = Radix4::new(FFT_SIZE, Forward);
+        let max_length = (MAX_IR_SECONDS * sample_rate) as usize;
+        let mut length = 0;
+        let channels = channels
+            .iter()
+            .take(2)
+            .map(|samples| {
+                // only what survives the cut is converted
+                let source_length = (max_length as f32 * source_rate / sample_rate).ceil() as usize;
+                let samples = &samples[..samples.len().min(source_length)];
+                let mut samples =
+                    resample(samples, source_rate, sample_rate, ResamplerQuality::High);
+                samples.truncate(max_length);
+                length = length.max(samples.len());
+                samples
+                    .chunks(PARTITION_SIZE)
+                    .map(|partition| {
+                        // zero padded, so the product with an input
+                        // block's spectrum holds their linear convolution
+                        let mut spectrum = vec![Complex::default(); FFT_SIZE];
+                        for (bin, &x) in spectrum.iter_mut().zip(partition) {
+                            *bin = Complex::new(x, 0.0);
+                        }
+                        fft.process(&mut spectrum);
+                        spectrum
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { channels, length }
+    }
+
+    /// Load a mono or stereo WAV file, scaled to unit energy so responses
+    /// of any length play at about the same level
+    pub fn load<P: AsRef<Path>>(path: P, sample_rate: f32) -> Result<Self, hound::Error> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        let channel_count = spec.channels.max(1) as usize;
+        let mut channels: Vec<Vec<f32>> = (0..channel_count.min(2))
+            .map(|channel| {
+                samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(channel_count)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        let energy = channels
+            .iter()
+            .map(|samples| samples.iter().map(|x| x * x).sum::<f32>())
+            .fold(0.0, f32::max);
+        if energy > 0.0 {
+            let scale = 1.0 / energy.sqrt();
+            for x in channels.iter_mut().flatten() {
+                *x *= scale;
+            }
+        }
+        Ok(Self::new(&channels, spec.sample_rate as f32, sample_rate))
+    }
+
+    /// Length in samples at the convolution's sample rate
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn partition_count(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+}
+
+pub struct Convolver {
+    ir: Option<Arc<ImpulseResponse>>,
+    fft: Radix4<f32>,
+    ifft: Radix4<f32>,
+    scratch: Vec<Complex<f32>>,
+    // the previous block of input followed by the one being filled
+    input: Vec<f32>,
+    pos: usize,
+    // spectra of past input blocks, one per partition, the newest at
+    // `history_pos`
+    history: Vec<Vec<Complex<f32>>>,
+    history_pos: usize,
+    spectrum: Vec<Complex<f32>>,
+    // the convolved block being played, per output channel
+    output: [Vec<f32>; 2],
+    mix: f32,
+    gain_db: f32,
+    gain: f32,
+}
+
+impl Convolver {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Mix", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::linear(1, "Gain", -24.0, 24.0, 0.0, "dB"),
+        ]
+    }
+
+    pub fn new() -> Self {
+        let fft = Radix4::new(FFT_SIZE, Forward);
+        let ifft = Radix4::new(FFT_SIZE, Inverse);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+        Self {
+            ir: None,
+            fft,
+            ifft,
+            scratch: vec![Complex::default(); scratch_len],
+            input: vec![0.0; FFT_SIZE],
+            pos: 0,
+            history: Vec::new(),
+            history_pos: 0,
+            spectrum: vec![Complex::default(); FFT_SIZE],
+            output: [vec![0.0; PARTITION_SIZE], vec![0.0; PARTITION_SIZE]],
+            mix: 1.0,
+            gain_db: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// Convolve with `ir` from here on, starting from silence
+    pub fn set_impulse_response(&mut self, ir: Arc<ImpulseResponse>) {
+        self.history = vec![vec![Complex::default(); FFT_SIZE]; ir.partition_count()];
+        self.history_pos = 0;
+        self.input.fill(0.0);
+        self.pos = 0;
+        for output in self.output.iter_mut() {
+            output.fill(0.0);
+        }
+        self.ir = Some(ir);
+    }
+
+    /// Dry/wet balance, 0.0..1.0
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    /// Level of the convolved signal in dB
+    pub fn set_gain(&mut self, gain_db: f32) {
+        self.gain_db = gain_db;
+        self.gain = 10f32.powf(gain_db / 20.0);
+    }
+
+    /// Convolve the full block of input into the output
+    fn process_block(&mut self, ir: &ImpulseResponse) {
+        let history_len = self.history.len();
+        let newest = &mut self.history[self.history_pos];
+        for (bin, &x) in newest.iter_mut().zip(self.input.iter()) {
+            *bin = Complex::new(x, 0.0);
+        }
+        self.fft.process_with_scratch(newest, &mut self.scratch);
+
+        let scale = 1.0 / FFT_SIZE as f32;
+        for (channel, partitions) in ir.channels.iter().enumerate() {
+            self.spectrum.fill(Complex::default());
+            for (age, partition) in partitions.iter().enumerate() {
+                let block = &self.history[(self.history_pos + history_len - age) % history_len];
+                for ((y, x), h) in self.spectrum.iter_mut().zip(block).zip(partition) {
+                    *y += x * h;
+                }
+            }
+            self.ifft
+                .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+            // the first half wraps around, the second is the convolution
+            // of the newest block
+            for (y, bin) in self.output[channel]
+                .iter_mut()
+                .zip(&self.spectrum[PARTITION_SIZE..])
+            {
+                *y = bin.re * scale;
+            }
+        }
+        if ir.channel_count() == 1 {
+            let (left, right) = self.output.split_at_mut(1);
+            right[0].copy_from_slice(&left[0]);
+        }
+
+        self.input.copy_within(PARTITION_SIZE.., 0);
+        self.history_pos = (self.history_pos + 1) % history_len;
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        // nothing to convolve with yet
+        let Some(ir) = self.ir.take() else {
+            return (left, right);
+        };
+        if ir.is_empty() {
+            self.ir = Some(ir);
+            return (left, right);
+        }
+        self.input[PARTITION_SIZE + self.pos] = (left + right) * 0.5;
+        let wet = (self.output[0][self.pos], self.output[1][self.pos]);
+        self.pos += 1;
+        if self.pos == PARTITION_SIZE {
+            self.pos = 0;
+            self.process_block(&ir);
+        }
+        self.ir = Some(ir);
+
+        let dry = 1.0 - self.mix;
+        let wet_gain = self.mix * self.gain;
+        (
+            left * dry + wet.0 * wet_gain,
+            right * dry + wet.1 * wet_gain,
+        )
+    }
+}
+
+impl Default for Convolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 0: mix, 1: gain (dB)
+impl Effect for Convolver {
+    fn process(&mut self, x: f32) -> f32 {
+        let (left, right) = Convolver::process_stereo(self, x, x);
+        (left + right) * 0.5
+    }
+
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        Convolver::process_stereo(self, left, right)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_mix(value),
+            1 => self.set_gain(value),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.mix,
+            1 => self.gain_db,
+            _ => 0.0,
+        }
+    }
+
+    fn set_impulse_response(&mut self, ir: Arc<ImpulseResponse>) {
+        Convolver::set_impulse_response(self, ir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(length: usize, seed: u32) -> Vec<f32> {
+        (0..length as u32)
+            .map(|i| {
+                let x = (i + seed).wrapping_mul(2654435761) >> 16;
+                x as f32 / 65536.0 - 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_direct_convolution() {
+        // spans a few partitions, ending partway through the last
+        let h = noise(PARTITION_SIZE * 3 + 17, 1);
+        let xs = noise(PARTITION_SIZE * 8, 2);
+        let mut convolver = Convolver::new();
+        convolver.set_impulse_response(Arc::new(ImpulseResponse::new(
+            std::slice::from_ref(&h),
+            48000.0,
+            48000.0,
+        )));
+        let ys: Vec<f32> = xs.iter().map(|&x| convolver.process(x)).collect();
+
+        // delayed by one partition
+        for (t, y) in ys.iter().skip(PARTITION_SIZE).enumerate() {
+            let expected: f32 = (0..=t.min(h.len() - 1)).map(|k| h[k] * xs[t - k]).sum();
+            assert!((y - expected).abs() < 1e-3, "{t}: {y} {expected}");
+        }
+    }
+
+    #[test]
+    fn stereo_responses_convolve_each_side() {
+        let mut left = vec![0.0; 40];
+        let mut right = vec![0.0; 40];
+        left[0] = 1.0;
+        right[30] = 0.5;
+        let mut convolver = Convolver::new();
+        convolver.set_impulse_response(Arc::new(ImpulseResponse::new(
+            &[left, right],
+            48000.0,
+            48000.0,
+        )));
+        let ys: Vec<(f32, f32)> = (0..PARTITION_SIZE * 2)
+            .map(|i| {
+                let x = if i == 0 { 1.0 } else { 0.0 };
+                convolver.process_stereo(x, x)
+            })
+            .collect();
+        assert!((ys[PARTITION_SIZE].0 - 1.0).abs() < 1e-5);
+        assert!(ys[PARTITION_SIZE].1.abs() < 1e-5);
+        assert!((ys[PARTITION_SIZE + 30].1 - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn responses_are_resampled_and_limited() {
+        let ir = ImpulseResponse::new(&[vec![1.0; 1000]], 24000.0, 48000.0);
+        assert_eq!(ir.len(), 2000);
+        assert_eq!(ir.channel_count(), 1);
+        let seconds = (MAX_IR_SECONDS * 2.0) as usize;
+        let long = ImpulseResponse::new(&[vec![0.0; 100 * seconds]], 100.0, 100.0);
+        assert_eq!(long.len(), (MAX_IR_SECONDS * 100.0) as usize);
+    }
+
+    #[test]
+    fn passes_input_without_a_response() {
+        let mut convolver = Convolver::new();
+        assert_eq!(convolver.process_stereo(0.5, -0.5), (0.5, -0.5));
+    }
+
+    #[test]
+    fn loads_wav_files() {
+        let path = std::env::temp_dir().join("cp3_dsp_impulse_response.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for x in [0.5, 0.0, 0.0, 0.25, 0.0, 0.0] {
+            writer.write_sample(x as f32).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let ir = ImpulseResponse::load(&path, 48000.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ir.len(), 3);
+        assert_eq!(ir.channel_count(), 2);
+        // the louder channel is scaled to unit energy
+        let mut convolver = Convolver::new();
+        convolver.set_impulse_response(Arc::new(ir));
+        let ys: Vec<(f32, f32)> = (0..PARTITION_SIZE + 2)
+            .map(|i| convolver.process_stereo(if i == 0 { 1.0 } else { 0.0 }, 0.0))
+            .collect();
+        assert!((ys[PARTITION_SIZE].0 - 0.5).abs() < 1e-5);
+        assert!((ys[PARTITION_SIZE + 1].1 - 0.25).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,97 @@
+//! Declicking
+//!
+//! Restarting a sounding voice, stealing it for another note or cutting it
+//! short makes its output jump, which is heard as a click. A `Declicker`
+//! hides the jump: the difference between the last sample before it and
+//! the first after it is added back and faded out over a few milliseconds,
+//! so the output crossfades from the old note into the new one without
+//! delaying the new note.
+
+/// default length of the crossfade, in milliseconds
+pub const DEFAULT_DECLICK_MS: f32 = 2.0;
+
+// longest crossfade, longer ones smear the attacks of the new notes
+const MAX_DECLICK_MS: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Declicker {
+    // jump still being faded out, and the samples left to fade it over
+    offset: f32,
+    remaining: usize,
+    length: usize,
+    last: f32,
+    armed: bool,
+    sample_rate: f32,
+}
+
+impl Declicker {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut declicker = Self {
+            offset: 0.0,
+            remaining: 0,
+            length: 0,
+            last: 0.0,
+            armed: false,
+            sample_rate,
+        };
+        declicker.set_time(DEFAULT_DECLICK_MS);
+        declicker
+    }
+
+    /// Length of the crossfade in milliseconds, up to 5.0; 0.0 turns it off
+    pub fn set_time(&mut self, ms: f32) {
+        let ms = ms.clamp(0.0, MAX_DECLICK_MS);
+        self.length = (ms * 0.001 * self.sample_rate) as usize;
+    }
+
+    /// Crossfade over the jump at the next sample, called when the voice
+    /// is restarted or cut
+    pub fn arm(&mut self) {
+        self.armed = self.length > 0;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        if self.armed {
+            self.armed = false;
+            self.offset = self.last - x;
+            self.remaining = self.length;
+        }
+        let y = if self.remaining > 0 {
+            self.remaining -= 1;
+            x + self.offset * (self.remaining as f32 / self.length as f32)
+        } else {
+            x
+        };
+        self.last = y;
+        y
+    }
+
+    /// Forget the last output, e.g. once the voice has gone quiet
+    pub fn reset(&mut self) {
+        self.last = 0.0;
+        self.remaining = 0;
+        self.armed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumps_are_crossfaded() {
+        let mut declicker = Declicker::new(48000.0);
+        assert_eq!(declicker.process(1.0), 1.0);
+        declicker.arm();
+        // 2 ms at 48 kHz
+        let ys: Vec<f32> = (0..96).map(|_| declicker.process(-1.0)).collect();
+        assert!(ys.windows(2).all(|w| (w[1] - w[0]).abs() < 0.05));
+        assert_eq!(ys[95], -1.0);
+        assert_eq!(declicker.process(-0.5), -0.5);
+
+        declicker.set_time(0.0);
+        declicker.arm();
+        assert_eq!(declicker.process(1.0), 1.0);
+    }
+}
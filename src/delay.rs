@@ -1,47 +1,188 @@
+use crate::bus::Effect;
+use crate::filters::{SVFMode, SVF};
+use crate::osc::{Osc, Waveform};
+use crate::parameters::ParameterInfo;
+use crate::sequencer::NoteDivision;
+use crate::utils::undenormalize;
 use core::time;
 use std::vec;
 
 // const BUFFER_LENGTH: usize = 48000; // 5 seconds at 48 Khz
 
+/// longest delay time in seconds
+pub const MAX_DELAY_TIME: f32 = 4.0;
+
+// per sample slew of the delay time towards its target, so time changes
+// glide instead of jumping
+const TIME_SLEW: f32 = 0.0005;
+
+// damping at or above this frequency bypasses the feedback filter
+const DAMPING_OFF: f32 = 20000.0;
+
 pub struct Delay {
     delay_line: DelayLine,
     time_samples: f32,
+    // time currently read from the delay line, slewing towards the target
+    current_time: f32,
     // target_time: f32,
     // increment: f32,
     feedback: f32,
-    // saturation: f32,
-    // modulation_depth: f32,
+    // delay time as a note value, follows the tempo when set
+    sync: Option<NoteDivision>,
+    samples_per_beat: f32,
+    // lowpass cutoff in the feedback loop, darkening each repeat
+    damping: f32,
+    // soft clipping of the repeats, 0.0..1.0
+    saturation: f32,
+    // delay time modulation in ms
+    modulation_depth: f32,
+    // repeats held at unity feedback, new input kept out of the loop
+    frozen: bool,
     // mix: f32,
-    // svf: SVF,
-    // lfo: Oscillator,
+    svf: SVF,
+    lfo: Osc,
+    sample_rate: f32,
 }
 
 impl Delay {
-    pub fn new(time_samples: f32, feedback: f32) -> Self {
+    /// Times are in samples, so their range depends on the sample rate
+    pub fn parameters(sample_rate: f32) -> Vec<ParameterInfo> {
+        let max_time = MAX_DELAY_TIME * sample_rate;
+        vec![
+            ParameterInfo::linear(0, "Time", 1.0, max_time, sample_rate * 0.5, "samples"),
+            ParameterInfo::linear(1, "Feedback", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::stepped(2, "Sync", 0.0, NoteDivision::COUNT as f32, 0.0),
+            ParameterInfo::logarithmic(3, "Damping", 20.0, DAMPING_OFF, DAMPING_OFF, "Hz"),
+            ParameterInfo::linear(4, "Saturation", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(5, "Modulation depth", 0.0, 10.0, 0.0, "ms"),
+            ParameterInfo::logarithmic(6, "Modulation rate", 0.01, 10.0, 0.5, "Hz"),
+            ParameterInfo::stepped(7, "Freeze", 0.0, 1.0, 0.0),
+        ]
+    }
+
+    pub fn new(time_samples: f32, feedback: f32, sample_rate: f32) -> Self {
+        let length = ((MAX_DELAY_TIME * sample_rate) as usize).max(time_samples as usize);
+        let mut svf = SVF::new(DAMPING_OFF, 0.707, sample_rate);
+        svf.mode = SVFMode::Lowpass;
+        let mut lfo = Osc::new(Waveform::Sine, sample_rate);
+        lfo.set_freq(0.5);
         Self {
-            delay_line: DelayLine::new(InterpolationType::Cubic, time_samples as usize),
+            delay_line: DelayLine::new(InterpolationType::Cubic, length),
             time_samples,
+            current_time: time_samples,
             feedback,
+            sync: None,
+            samples_per_beat: 0.0,
+            damping: DAMPING_OFF,
+            saturation: 0.0,
+            modulation_depth: 0.0,
+            frozen: false,
+            svf,
+            lfo,
+            sample_rate,
         }
     }
 
     #[inline]
     pub fn process(&mut self, input: f32) -> f32 {
-        let delayed = self.delay_line.read(None);
+        self.current_time += (self.time_samples - self.current_time) * TIME_SLEW;
+        let mut time = self.current_time;
+        if self.modulation_depth > 0.0 {
+            time += self.lfo.process() * self.modulation_depth * 0.001 * self.sample_rate;
+        }
+
+        let mut delayed = self.delay_line.read_delayed(time);
+        if self.frozen {
+            self.delay_line.write_and_increment(delayed);
+            return input + delayed;
+        }
+        if self.damping < DAMPING_OFF {
+            delayed = self.svf.process(delayed, 0.0);
+        }
+        if self.saturation > 0.0 {
+            // unity gain for small signals, compressing loud repeats
+            let drive = 1.0 + self.saturation * 4.0;
+            delayed = (delayed * drive).tanh() / drive;
+        }
+
         let output = input + (delayed * self.feedback);
         self.delay_line.write_and_increment(output);
 
         output
     }
 
+    /// Cutoff of the lowpass in the feedback loop in Hz, 20 kHz and up
+    /// turns it off
+    pub fn set_damping(&mut self, freq: f32) {
+        self.damping = freq.max(20.0);
+        self.svf.update_freq(self.damping.min(DAMPING_OFF));
+    }
+
+    pub fn set_saturation(&mut self, saturation: f32) {
+        self.saturation = saturation.clamp(0.0, 1.0);
+    }
+
+    /// Depth of the delay time modulation in ms
+    pub fn set_modulation_depth(&mut self, depth: f32) {
+        self.modulation_depth = depth.clamp(0.0, 10.0);
+    }
+
+    pub fn set_modulation_rate(&mut self, rate: f32) {
+        self.lfo.set_freq(rate.max(0.0));
+    }
+
+    /// Loop the current repeats indefinitely and stop taking in new input
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.frozen = freeze;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Free running delay time; ignored while synced to the tempo
     pub fn set_delay_time(&mut self, time: f32) {
-        self.time_samples = time;
+        if self.sync.is_none() {
+            self.time_samples = time.clamp(1.0, self.delay_line.len() as f32);
+        }
     }
 
     pub fn set_feedback(&mut self, feedback: f32) {
         self.feedback = feedback;
     }
 
+    /// Sync the delay time to a note value, or run free with `None`
+    pub fn set_sync(&mut self, sync: Option<NoteDivision>) {
+        self.sync = sync;
+        self.update_synced_time();
+    }
+
+    pub fn set_tempo(&mut self, samples_per_beat: f32) {
+        self.samples_per_beat = samples_per_beat;
+        self.update_synced_time();
+    }
+
+    fn update_synced_time(&mut self) {
+        if let Some(division) = self.sync {
+            if self.samples_per_beat > 0.0 {
+                self.time_samples = (division.beats() * self.samples_per_beat)
+                    .clamp(1.0, self.delay_line.len() as f32);
+            }
+        }
+    }
+
+    pub fn get_delay_time(&self) -> f32 {
+        self.time_samples
+    }
+
+    pub fn get_feedback(&self) -> f32 {
+        self.feedback
+    }
+
+    pub fn get_sync(&self) -> Option<NoteDivision> {
+        self.sync
+    }
+
     fn cubic_interpolate(y0: f32, y1: f32, y2: f32, y3: f32, mu: f32) -> f32 {
         let mu2 = mu * mu;
         let a0 = y3 - y2 - y0 + y1;
@@ -52,12 +193,347 @@ impl Delay {
     }
 }
 
+/// 0: delay time (samples), 1: feedback, 2: tempo sync (0 runs free,
+/// n syncs to note division n - 1), 3: damping (Hz), 4: saturation,
+/// 5: modulation depth (ms), 6: modulation rate (Hz), 7: freeze
+impl Effect for Delay {
+    fn process(&mut self, x: f32) -> f32 {
+        Delay::process(self, x)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_delay_time(value),
+            1 => self.set_feedback(value),
+            2 => {
+                let sync = (value as u8)
+                    .checked_sub(1)
+                    .and_then(NoteDivision::from_index);
+                self.set_sync(sync);
+            }
+            3 => self.set_damping(value),
+            4 => self.set_saturation(value),
+            5 => self.set_modulation_depth(value),
+            6 => self.set_modulation_rate(value),
+            7 => self.set_freeze(value > 0.5),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.get_delay_time(),
+            1 => self.get_feedback(),
+            2 => self
+                .sync
+                .map_or(0.0, |division| division.index() as f32 + 1.0),
+            3 => self.damping,
+            4 => self.saturation,
+            5 => self.modulation_depth,
+            6 => self.lfo.get_freq(),
+            7 => self.frozen as u8 as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn set_tempo(&mut self, samples_per_beat: f32) {
+        Delay::set_tempo(self, samples_per_beat);
+    }
+
+    fn set_freeze(&mut self, freeze: bool) {
+        Delay::set_freeze(self, freeze);
+    }
+}
+
+/// Stereo delay whose repeats bounce between the channels. With full
+/// cross-feedback each repeat crosses over, with none the channels echo
+/// independently.
+pub struct PingPongDelay {
+    delay_lines: [DelayLine; 2],
+    times: [f32; 2],
+    current_times: [f32; 2],
+    feedback: f32,
+    cross: f32,
+    width: f32,
+}
+
+impl PingPongDelay {
+    pub fn parameters(sample_rate: f32) -> Vec<ParameterInfo> {
+        let (max_time, time) = (MAX_DELAY_TIME * sample_rate, sample_rate * 0.25);
+        vec![
+            ParameterInfo::linear(0, "Left time", 1.0, max_time, time, "samples"),
+            ParameterInfo::linear(1, "Right time", 1.0, max_time, time, "samples"),
+            ParameterInfo::linear(2, "Feedback", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::linear(3, "Cross-feedback", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::linear(4, "Width", 0.0, 1.0, 1.0, ""),
+        ]
+    }
+
+    pub fn new(time_samples: f32, feedback: f32, sample_rate: f32) -> Self {
+        let length = ((MAX_DELAY_TIME * sample_rate) as usize).max(time_samples as usize);
+        Self {
+            delay_lines: [
+                DelayLine::new(InterpolationType::Cubic, length),
+                DelayLine::new(InterpolationType::Cubic, length),
+            ],
+            times: [time_samples; 2],
+            current_times: [time_samples; 2],
+            feedback,
+            cross: 1.0,
+            width: 1.0,
+        }
+    }
+
+    /// Delay time of one channel in samples, 0 is left and 1 is right
+    pub fn set_delay_time(&mut self, channel: usize, time: f32) {
+        if let Some(t) = self.times.get_mut(channel) {
+            *t = time.clamp(1.0, self.delay_lines[0].len() as f32);
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    /// Share of the feedback that crosses to the other channel, 0.0..1.0
+    pub fn set_cross_feedback(&mut self, cross: f32) {
+        self.cross = cross.clamp(0.0, 1.0);
+    }
+
+    /// Stereo width of the repeats, 0.0 (mono) to 1.0
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0.0, 1.0);
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        for (current, target) in self.current_times.iter_mut().zip(self.times) {
+            *current += (target - *current) * TIME_SLEW;
+        }
+        let delayed_l = self.delay_lines[0].read_delayed(self.current_times[0]);
+        let delayed_r = self.delay_lines[1].read_delayed(self.current_times[1]);
+
+        // with cross-feedback the input enters on the left only, so the
+        // first repeat is heard left and the next one right
+        let input_l = left + right * self.cross;
+        let input_r = right * (1.0 - self.cross);
+        let feedback_l = delayed_l * (1.0 - self.cross) + delayed_r * self.cross;
+        let feedback_r = delayed_r * (1.0 - self.cross) + delayed_l * self.cross;
+        self.delay_lines[0].write_and_increment(input_l + feedback_l * self.feedback);
+        self.delay_lines[1].write_and_increment(input_r + feedback_r * self.feedback);
+
+        let mid = (delayed_l + delayed_r) * 0.5;
+        let side = (delayed_l - delayed_r) * 0.5 * self.width;
+        (left + mid + side, right + mid - side)
+    }
+}
+
+/// 0: left time (samples), 1: right time (samples), 2: feedback,
+/// 3: cross-feedback, 4: width
+impl Effect for PingPongDelay {
+    fn process(&mut self, x: f32) -> f32 {
+        let (left, right) = PingPongDelay::process_stereo(self, x, x);
+        (left + right) * 0.5
+    }
+
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        PingPongDelay::process_stereo(self, left, right)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_delay_time(0, value),
+            1 => self.set_delay_time(1, value),
+            2 => self.set_feedback(value),
+            3 => self.set_cross_feedback(value),
+            4 => self.set_width(value),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.times[0],
+            1 => self.times[1],
+            2 => self.feedback,
+            3 => self.cross,
+            4 => self.width,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Number of taps a MultiTapDelay reads
+pub const MAX_DELAY_TAPS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct DelayTap {
+    time: f32,
+    gain: f32,
+    pan: f32,
+}
+
+/// Several taps read from one delay line, each with its own time, gain and
+/// pan, for rhythmic echo patterns and early reflections. The summed taps
+/// are fed back into the line.
+pub struct MultiTapDelay {
+    delay_line: DelayLine,
+    taps: [DelayTap; MAX_DELAY_TAPS],
+    tap_count: usize,
+    feedback: f32,
+    mix: f32,
+}
+
+impl MultiTapDelay {
+    pub fn parameters(sample_rate: f32) -> Vec<ParameterInfo> {
+        let max_time = MAX_DELAY_TIME * sample_rate;
+        let mut parameters = vec![
+            ParameterInfo::linear(0, "Feedback", 0.0, 0.95, 0.0, ""),
+            ParameterInfo::linear(1, "Mix", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::stepped(2, "Taps", 1.0, MAX_DELAY_TAPS as f32, 4.0),
+        ];
+        for i in 0..MAX_DELAY_TAPS {
+            let tap = Self::default_tap(i, sample_rate);
+            let id = 3 + i as i8 * 3;
+            let name = |parameter: &str| format!("Tap {} {}", i + 1, parameter);
+            parameters.extend([
+                ParameterInfo::linear(id, name("time"), 1.0, max_time, tap.time, "samples"),
+                ParameterInfo::linear(id + 1, name("gain"), 0.0, 1.0, tap.gain, ""),
+                ParameterInfo::linear(id + 2, name("pan"), -1.0, 1.0, tap.pan, ""),
+            ]);
+        }
+        parameters
+    }
+
+    /// Starts with four taps a sixteenth of a second apart at 120 bpm,
+    /// fading out and alternating between the channels
+    pub fn new(sample_rate: f32) -> Self {
+        let length = (MAX_DELAY_TIME * sample_rate) as usize;
+        Self {
+            delay_line: DelayLine::new(InterpolationType::Linear, length),
+            taps: std::array::from_fn(|i| Self::default_tap(i, sample_rate)),
+            tap_count: 4,
+            feedback: 0.0,
+            mix: 0.5,
+        }
+    }
+
+    fn default_tap(index: usize, sample_rate: f32) -> DelayTap {
+        DelayTap {
+            time: sample_rate * 0.125 * (index + 1) as f32,
+            gain: 0.8_f32.powi(index as i32),
+            pan: if index.is_multiple_of(2) { -0.5 } else { 0.5 },
+        }
+    }
+
+    /// Number of active taps, 1..=MAX_DELAY_TAPS
+    pub fn set_tap_count(&mut self, count: usize) {
+        self.tap_count = count.clamp(1, MAX_DELAY_TAPS);
+    }
+
+    /// Delay time of a tap in samples
+    pub fn set_tap_time(&mut self, tap: usize, time: f32) {
+        let max = self.delay_line.len() as f32;
+        if let Some(t) = self.taps.get_mut(tap) {
+            t.time = time.clamp(1.0, max);
+        }
+    }
+
+    pub fn set_tap_gain(&mut self, tap: usize, gain: f32) {
+        if let Some(t) = self.taps.get_mut(tap) {
+            t.gain = gain.clamp(0.0, 1.0);
+        }
+    }
+
+    /// -1.0 (left) to 1.0 (right)
+    pub fn set_tap_pan(&mut self, tap: usize, pan: f32) {
+        if let Some(t) = self.taps.get_mut(tap) {
+            t.pan = pan.clamp(-1.0, 1.0);
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0.0, 0.95);
+    }
+
+    /// Dry/wet balance, 0.0..1.0
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, x: f32) -> (f32, f32) {
+        let (mut left, mut right, mut sum) = (0.0, 0.0, 0.0);
+        for tap in &self.taps[..self.tap_count] {
+            let y = self.delay_line.read_delayed(tap.time) * tap.gain;
+            left += y * (1.0 - tap.pan) * 0.5;
+            right += y * (1.0 + tap.pan) * 0.5;
+            sum += y;
+        }
+        // normalise the feedback so more taps don't make it run away
+        let feedback = sum * self.feedback / self.tap_count as f32;
+        self.delay_line.write_and_increment(x + feedback);
+
+        let dry = x * (1.0 - self.mix);
+        (dry + left * self.mix, dry + right * self.mix)
+    }
+}
+
+/// 0: feedback, 1: mix, 2: tap count, then three per tap starting at 3:
+/// time (samples), gain, pan
+impl Effect for MultiTapDelay {
+    fn process(&mut self, x: f32) -> f32 {
+        let (left, right) = MultiTapDelay::process_stereo(self, x);
+        (left + right) * 0.5
+    }
+
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        MultiTapDelay::process_stereo(self, (left + right) * 0.5)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_feedback(value),
+            1 => self.set_mix(value),
+            2 => self.set_tap_count(value as usize),
+            p if p >= 3 => {
+                let (tap, field) = ((p - 3) as usize / 3, (p - 3) % 3);
+                match field {
+                    0 => self.set_tap_time(tap, value),
+                    1 => self.set_tap_gain(tap, value),
+                    _ => self.set_tap_pan(tap, value),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.feedback,
+            1 => self.mix,
+            2 => self.tap_count as f32,
+            p if p >= 3 => {
+                let (tap, field) = ((p - 3) as usize / 3, (p - 3) % 3);
+                match (self.taps.get(tap), field) {
+                    (Some(t), 0) => t.time,
+                    (Some(t), 1) => t.gain,
+                    (Some(t), _) => t.pan,
+                    (None, _) => 0.0,
+                }
+            }
+            _ => 0.0,
+        }
+    }
+}
+
 pub enum InterpolationType {
     None,
     Linear,
     Cubic,
 }
 
+/// Circular buffer on the heap; its length is the longest delay it holds
 pub struct DelayLine {
     pub buffer: Vec<f32>,
     pub index: usize,
@@ -88,8 +564,28 @@ impl DelayLine {
         }
     }
 
+    /// Read `delay` (fractional) samples behind the most recently written
+    /// sample, so a delay of 1.0 returns the last write
+    pub fn read_delayed(&self, delay: f32) -> f32 {
+        let delay = delay.clamp(1.0, self.length as f32);
+        let read_pos = (self.index as f32 - delay).rem_euclid(self.length as f32);
+        match self.interpolation {
+            InterpolationType::None => self.get_sample(read_pos as usize % self.length),
+            InterpolationType::Linear => self.linear_interpolate(read_pos),
+            InterpolationType::Cubic => self.cubic_interpolate(read_pos),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
     pub fn write_and_increment(&mut self, value: f32) {
-        self.buffer[self.index] = value;
+        self.buffer[self.index] = undenormalize(value);
         self.index = (self.index + 1) % self.length;
     }
 
@@ -98,8 +594,9 @@ impl DelayLine {
     }
 
     fn linear_interpolate(&self, index: f32) -> f32 {
-        let floor = index.floor() as usize;
-        let frac = index - floor as f32;
+        // rem_euclid can round a tiny negative position up to the length
+        let floor = index.floor() as usize % self.length;
+        let frac = index.fract();
         let s0 = self.get_sample(floor);
         let s1 = self.get_sample((floor + 1) % self.length);
 
@@ -107,14 +604,10 @@ impl DelayLine {
     }
 
     fn cubic_interpolate(&self, index: f32) -> f32 {
-        let mut floor = index.floor() as usize;
-        let frac = index - floor as f32;
-
-        if floor == 0 {
-            floor += 1;
-        }
+        let floor = index.floor() as usize % self.length;
+        let frac = index.fract();
 
-        let s0 = self.get_sample(floor - 1);
+        let s0 = self.get_sample((floor + self.length - 1) % self.length);
         let s1 = self.get_sample(floor);
         let s2 = self.get_sample((floor + 1) % self.length);
         let s3 = self.get_sample((floor + 2) % self.length);
@@ -134,11 +627,168 @@ mod tests {
 
     #[test]
     fn new_creates_delay() {
-        let delay = Delay::new(0.5, 0.5);
+        let delay = Delay::new(0.5, 0.5, 48000.0);
         assert_eq!(delay.time_samples, 0.5);
         assert_eq!(delay.feedback, 0.5);
     }
 
+    #[test]
+    fn delay_time_sets_echo_position() {
+        let mut delay = Delay::new(100.0, 0.5, 48000.0);
+        let ys: Vec<f32> = (0..150)
+            .map(|i| delay.process(if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        assert_eq!(ys[0], 1.0);
+        let echo = ys[1..].iter().position(|y| y.abs() > 0.25).unwrap() + 1;
+        assert_eq!(echo, 100);
+    }
+
+    #[test]
+    fn synced_time_follows_tempo() {
+        let mut delay = Delay::new(1000.0, 0.5, 48000.0);
+        // dotted eighth at 120 bpm and 48 kHz
+        delay.set_parameter(2, 11.0);
+        assert_eq!(delay.get_sync(), NoteDivision::from_index(10));
+        delay.set_tempo(24000.0);
+        assert_eq!(delay.get_delay_time(), 18000.0);
+
+        // the free running time is ignored while synced
+        delay.set_delay_time(500.0);
+        assert_eq!(delay.get_delay_time(), 18000.0);
+        delay.set_tempo(12000.0);
+        assert_eq!(delay.get_delay_time(), 9000.0);
+
+        delay.set_parameter(2, 0.0);
+        assert_eq!(delay.get_parameter(2), 0.0);
+        delay.set_delay_time(500.0);
+        assert_eq!(delay.get_delay_time(), 500.0);
+    }
+
+    #[test]
+    fn time_changes_glide() {
+        let mut delay = Delay::new(1000.0, 0.0, 48000.0);
+        delay.set_delay_time(2000.0);
+        delay.process(0.0);
+        assert!(delay.current_time > 1000.0 && delay.current_time < 1010.0);
+        for _ in 0..48000 {
+            delay.process(0.0);
+        }
+        assert!((delay.current_time - 2000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn delay_length_follows_sample_rate() {
+        let delay = Delay::new(100.0, 0.5, 96000.0);
+        assert_eq!(delay.delay_line.len(), (MAX_DELAY_TIME * 96000.0) as usize);
+        // longer initial times grow the buffer
+        let delay = Delay::new(500000.0, 0.5, 48000.0);
+        assert_eq!(delay.delay_line.len(), 500000);
+    }
+
+    #[test]
+    fn freeze_loops_repeats_without_input() {
+        let mut delay = Delay::new(100.0, 0.5, 48000.0);
+        for i in 0..150 {
+            delay.process(if i == 0 { 1.0 } else { 0.0 });
+        }
+        delay.set_parameter(7, 1.0);
+        assert_eq!(delay.get_parameter(7), 1.0);
+        // new input passes dry but is not repeated
+        for _ in 0..100 {
+            delay.process(1.0);
+        }
+        // the first repeat loops every 100 samples at its level
+        for _ in 0..100 {
+            let ys: Vec<f32> = (0..100).map(|_| delay.process(0.0)).collect();
+            assert!((ys.iter().sum::<f32>() - 0.5).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn damping_darkens_repeats() {
+        // energy of the repeats of a noise burst, with and without damping
+        let repeat_energy = |damping: f32| {
+            let mut delay = Delay::new(1000.0, 0.9, 48000.0);
+            delay.set_parameter(3, damping);
+            let mut noise = crate::osc::Noise::new(crate::osc::NoiseColor::White, 48000.0);
+            (0..10000)
+                .map(|i| delay.process(if i < 500 { noise.process() } else { 0.0 }))
+                .skip(5000)
+                .map(|y| y * y)
+                .sum::<f32>()
+        };
+        assert!(repeat_energy(1000.0) < repeat_energy(DAMPING_OFF) * 0.5);
+    }
+
+    #[test]
+    fn saturation_limits_runaway_feedback() {
+        let mut delay = Delay::new(100.0, 1.5, 48000.0);
+        delay.set_saturation(1.0);
+        let mut y = 0.0;
+        for i in 0..48000 {
+            y = delay.process(if i == 0 { 1.0 } else { 0.0 });
+        }
+        assert!(y.is_finite() && y.abs() < 2.0);
+    }
+
+    #[test]
+    fn modulation_wobbles_the_echo() {
+        let mut plain = Delay::new(1000.0, 0.5, 48000.0);
+        let mut modulated = Delay::new(1000.0, 0.5, 48000.0);
+        modulated.set_modulation_depth(2.0);
+        modulated.set_modulation_rate(5.0);
+        assert_eq!(modulated.get_parameter(5), 2.0);
+        assert_eq!(modulated.get_parameter(6), 5.0);
+
+        // identical until the first repeat, then pitch shifted against it
+        let mut difference = 0.0;
+        for i in 0..9600 {
+            let x = (i as f32 * 0.1).sin();
+            let d = (modulated.process(x) - plain.process(x)).abs();
+            if i < 900 {
+                assert_eq!(d, 0.0);
+            }
+            difference += d;
+        }
+        assert!(difference > 100.0);
+    }
+
+    #[test]
+    fn ping_pong_alternates_channels() {
+        let mut delay = PingPongDelay::new(100.0, 0.5, 48000.0);
+        delay.set_delay_time(1, 50.0);
+        // skip the glide to the new time
+        delay.current_times = delay.times;
+        let ys: Vec<(f32, f32)> = (0..300)
+            .map(|i| delay.process_stereo(if i == 0 { 1.0 } else { 0.0 }, 0.0))
+            .collect();
+
+        // left after 100 samples, right 50 later, then left again
+        assert_eq!(ys[100], (1.0, 0.0));
+        assert_eq!(ys[150], (0.0, 0.5));
+        assert_eq!(ys[250], (0.25, 0.0));
+        let echoes = ys[1..].iter().filter(|(l, r)| l.abs() + r.abs() > 0.0);
+        assert_eq!(echoes.count(), 3);
+    }
+
+    #[test]
+    fn ping_pong_without_cross_feedback_echoes_in_place() {
+        let mut delay = PingPongDelay::new(100.0, 0.5, 48000.0);
+        delay.set_cross_feedback(0.0);
+        let ys: Vec<(f32, f32)> = (0..250)
+            .map(|i| delay.process_stereo(0.0, if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        assert_eq!(ys[100], (0.0, 1.0));
+        assert_eq!(ys[200], (0.0, 0.5));
+
+        // zero width folds the repeats to the center
+        delay.set_width(0.0);
+        let ys: Vec<(f32, f32)> = (0..101)
+            .map(|i| delay.process_stereo(0.0, if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        assert_eq!(ys[100].0, ys[100].1);
+    }
+
     #[test]
     fn new_creates_delay_line() {
         // let delay_line = DelayLine::new(InterpolationType::None, BUFFER_LENGTH);
@@ -195,4 +845,52 @@ mod tests {
         // assert_eq!(delay_line.read(4.5), 0.515625);
         // assert_eq!(delay_line.read(5.0), 0.0);
     }
+
+    #[test]
+    fn multi_tap_echoes_at_each_tap() {
+        let mut delay = MultiTapDelay::new(48000.0);
+        delay.set_mix(1.0);
+        delay.set_tap_count(3);
+        for (tap, (time, pan)) in [(100.0, -1.0), (250.0, 1.0), (400.0, 0.0)]
+            .into_iter()
+            .enumerate()
+        {
+            delay.set_tap_time(tap, time);
+            delay.set_tap_gain(tap, 1.0);
+            delay.set_tap_pan(tap, pan);
+        }
+        let ys: Vec<(f32, f32)> = (0..1000)
+            .map(|i| delay.process_stereo(if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        assert_eq!(ys[100], (1.0, 0.0));
+        assert_eq!(ys[250], (0.0, 1.0));
+        assert_eq!(ys[400], (0.5, 0.5));
+        let silent = ys
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| ![100, 250, 400].contains(i))
+            .all(|(_, &(l, r))| l == 0.0 && r == 0.0);
+        assert!(silent);
+    }
+
+    #[test]
+    fn multi_tap_parameters_round_trip() {
+        let mut delay = MultiTapDelay::new(48000.0);
+        for (parameter, value) in [
+            (0, 0.4),
+            (1, 0.6),
+            (2, 6.0),
+            (3, 1200.0),
+            (7, 0.3),
+            (26, -0.25),
+        ] {
+            delay.set_parameter(parameter, value);
+            assert_eq!(delay.get_parameter(parameter), value);
+        }
+        delay.set_parameter(2, 20.0);
+        assert_eq!(delay.get_parameter(2), MAX_DELAY_TAPS as f32);
+        // taps past the last one are ignored
+        delay.set_parameter(30, 1.0);
+        assert_eq!(delay.get_parameter(30), 0.0);
+    }
 }
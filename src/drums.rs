@@ -1,5 +1,9 @@
-use crate::envelopes::{CurveType, AR};
-use crate::osc::{Osc, Waveform};
+use crate::envelopes::{CurveType, EnvelopeState, AR};
+use crate::filters::{SVFMode, SVF};
+use crate::osc::{Noise, NoiseColor, Osc, Waveform};
+use crate::parameters::ParameterInfo;
+use crate::synth::SynthVoice;
+use crate::utils::{fractional_pitch_to_freq, pitch_to_freq};
 
 pub struct Kick {
     pitch_hz: f32,
@@ -9,7 +13,7 @@ pub struct Kick {
     pitch_env: AR,
     click_amt: f32,
     click_env: AR,
-    noise: Osc,
+    noise: Noise,
 }
 
 impl Kick {
@@ -38,7 +42,7 @@ impl Kick {
             ),
             click_amt,
             click_env: AR::new(0.0, 10.0, CurveType::Exponential { pow: 3 }, sample_rate),
-            noise: Osc::new(Waveform::Noise, sample_rate),
+            noise: Noise::new(NoiseColor::White, sample_rate),
         }
     }
 
@@ -55,18 +59,55 @@ impl Kick {
         let click = self.noise.process() * self.click_env.process() * self.click_amt;
         self.amp_env.process() * self.osc.process() + click
     }
+
+    pub fn is_active(&self) -> bool {
+        self.amp_env.is_active()
+    }
+
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Pitch", 20.0, 200.0, 50.0, "Hz"),
+            ParameterInfo::linear(1, "Pitch envelope", 0.0, 1.0, 0.2, ""),
+            ParameterInfo::linear(2, "Click", 0.0, 1.0, 0.3, ""),
+            ParameterInfo::logarithmic(3, "Release", 10.0, 2000.0, 400.0, "ms"),
+        ]
+    }
+
+    /// 0: pitch (Hz), 1: pitch envelope amount, 2: click amount, 3: release (ms)
+    pub fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.pitch_hz = value,
+            1 => self.pitch_env_amt = value,
+            2 => self.click_amt = value,
+            3 => {
+                self.amp_env.decay_ms = value;
+                self.pitch_env.decay_ms = value;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.pitch_hz,
+            1 => self.pitch_env_amt,
+            2 => self.click_amt,
+            3 => self.amp_env.decay_ms,
+            _ => 0.0,
+        }
+    }
 }
 
 pub struct Burst {
     env: AR,
-    noise: Osc,
+    noise: Noise,
 }
 
 impl Burst {
     pub fn new(release: f32, sample_rate: f32) -> Self {
         Self {
             env: AR::new(0.0, release, CurveType::Exponential { pow: 2 }, sample_rate),
-            noise: Osc::new(Waveform::Noise, sample_rate),
+            noise: Noise::new(NoiseColor::White, sample_rate),
         }
     }
 
@@ -78,4 +119,934 @@ impl Burst {
     pub fn process(&mut self) -> f32 {
         self.env.process() * self.noise.process()
     }
+
+    pub fn is_active(&self) -> bool {
+        self.env.is_active()
+    }
+}
+
+/*
+    Snare: a pitched body mixed with high-passed noise ("snappy"),
+    each with its own decay
+*/
+pub struct Snare {
+    tone_hz: f32,
+    osc: Osc,
+    tone_env: AR,
+    noise: Noise,
+    noise_env: AR,
+    filter: SVF,
+    snappy: f32,
+    pitch: u8,
+}
+
+impl Snare {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Tone", 80.0, 500.0, 180.0, "Hz"),
+            ParameterInfo::logarithmic(1, "Tone decay", 10.0, 1000.0, 100.0, "ms"),
+            ParameterInfo::logarithmic(2, "Noise decay", 10.0, 1000.0, 200.0, "ms"),
+            ParameterInfo::linear(3, "Snappy", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::logarithmic(4, "Noise filter", 200.0, 10000.0, 2000.0, "Hz"),
+            ParameterInfo::stepped(5, "Noise color", 0.0, 3.0, 0.0),
+        ]
+    }
+}
+
+impl SynthVoice for Snare {
+    fn new(sample_rate: f32) -> Self {
+        let mut filter = SVF::new(2000.0, 0.707, sample_rate);
+        filter.mode = SVFMode::Highpass;
+        Self {
+            tone_hz: 180.0,
+            osc: Osc::new(Waveform::Sine, sample_rate),
+            tone_env: AR::new(0.0, 100.0, CurveType::Exponential { pow: 3 }, sample_rate),
+            noise: Noise::new(NoiseColor::White, sample_rate),
+            noise_env: AR::new(0.0, 200.0, CurveType::Exponential { pow: 2 }, sample_rate),
+            filter,
+            snappy: 0.5,
+            pitch: 0,
+        }
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        // the body drops slightly in pitch as it decays
+        let tone_env = self.tone_env.process();
+        self.osc.set_freq(self.tone_hz * (1.0 + 0.5 * tone_env));
+        let tone = self.osc.process() * tone_env;
+        let noise = self.filter.process(self.noise.process(), 0.0) * self.noise_env.process();
+        tone * (1.0 - self.snappy) + noise * self.snappy
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, _: f32, _: f32) {
+        self.pitch = pitch;
+        self.osc.reset();
+        self.tone_env.trigger(velocity);
+        self.noise_env.trigger(velocity);
+    }
+
+    fn set_pitch(&mut self, _: f32) {}
+
+    fn reset(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    /// 0: tone (Hz), 1: tone decay (ms), 2: noise decay (ms), 3: snappy
+    /// (tone/noise mix), 4: noise filter cutoff (Hz), 5: noise color (see
+    /// `NoiseColor`)
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.tone_hz = value,
+            1 => self.tone_env.decay_ms = value,
+            2 => self.noise_env.decay_ms = value,
+            3 => self.snappy = value.clamp(0.0, 1.0),
+            4 => self.filter.update_freq(value),
+            5 => {
+                if let Some(color) = NoiseColor::from_u8(value as u8) {
+                    self.noise.color = color;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.tone_hz,
+            1 => self.tone_env.decay_ms,
+            2 => self.noise_env.decay_ms,
+            3 => self.snappy,
+            4 => self.filter.get_freq(),
+            5 => self.noise.color as u8 as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        6
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        parameter == 5
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.tone_env.is_active() || self.noise_env.is_active()
+    }
+}
+
+// square oscillator frequencies of the TR-808 cymbal/hi-hat circuit
+const HIHAT_FREQS: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540.0, 800.0];
+
+// per-sample gain factor of a choked hi-hat, fading it out in a few ms
+const CHOKE_FADE: f32 = 0.995;
+
+// default decay of the closed and open hi-hats, in ms
+const CLOSED_HAT_DECAY_MS: f32 = 50.0;
+const OPEN_HAT_DECAY_MS: f32 = 400.0;
+
+/*
+    Hi-hat: a bank of detuned square oscillators with some noise mixed in,
+    high-passed for a metallic timbre. Closed and open hats only differ in
+    their decay
+*/
+pub struct HiHat {
+    tune: f32,
+    oscs: [Osc; 6],
+    noise: Noise,
+    noise_amt: f32,
+    env: AR,
+    filter: SVF,
+    choke: Option<f32>,
+    pitch: u8,
+}
+
+impl HiHat {
+    pub fn closed(sample_rate: f32) -> Self {
+        Self::with_decay(CLOSED_HAT_DECAY_MS, sample_rate)
+    }
+
+    pub fn open(sample_rate: f32) -> Self {
+        Self::with_decay(OPEN_HAT_DECAY_MS, sample_rate)
+    }
+
+    /// Closed and open hats only differ in their default decay
+    pub fn parameters(decay_ms: f32) -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Tune", 0.5, 2.0, 1.0, ""),
+            ParameterInfo::logarithmic(1, "Decay", 10.0, 2000.0, decay_ms, "ms"),
+            ParameterInfo::logarithmic(2, "Filter cutoff", 1000.0, 16000.0, 7000.0, "Hz"),
+            ParameterInfo::linear(3, "Noise", 0.0, 1.0, 0.3, ""),
+        ]
+    }
+
+    fn with_decay(decay_ms: f32, sample_rate: f32) -> Self {
+        let mut filter = SVF::new(7000.0, 0.707, sample_rate);
+        filter.mode = SVFMode::Highpass;
+        let mut hat = Self {
+            tune: 1.0,
+            oscs: [Osc::new(Waveform::Square, sample_rate); 6],
+            noise: Noise::new(NoiseColor::White, sample_rate),
+            noise_amt: 0.3,
+            env: AR::new(
+                0.0,
+                decay_ms,
+                CurveType::Exponential { pow: 3 },
+                sample_rate,
+            ),
+            filter,
+            choke: None,
+            pitch: 0,
+        };
+        hat.update_freqs();
+        hat
+    }
+
+    /// Quickly fade out a ringing hat, e.g. when a closed hat cuts off an
+    /// open one
+    pub fn choke(&mut self) {
+        if self.env.is_active() {
+            self.choke = Some(1.0);
+        }
+    }
+
+    fn update_freqs(&mut self) {
+        for (osc, freq) in self.oscs.iter_mut().zip(HIHAT_FREQS) {
+            osc.set_freq(freq * self.tune);
+        }
+    }
+}
+
+impl SynthVoice for HiHat {
+    fn new(sample_rate: f32) -> Self {
+        Self::closed(sample_rate)
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        if !self.env.is_active() {
+            return 0.0;
+        }
+        let metal =
+            self.oscs.iter_mut().map(|osc| osc.process()).sum::<f32>() / HIHAT_FREQS.len() as f32;
+        let x = metal + self.noise.process() * self.noise_amt;
+        let mut y = self.filter.process(x, 0.0) * self.env.process();
+
+        if let Some(gain) = self.choke {
+            y *= gain;
+            let gain = gain * CHOKE_FADE;
+            if gain < 0.001 {
+                self.choke = None;
+                self.env.state = EnvelopeState::Off;
+            } else {
+                self.choke = Some(gain);
+            }
+        }
+        y
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, _: f32, _: f32) {
+        self.pitch = pitch;
+        self.choke = None;
+        self.env.trigger(velocity);
+    }
+
+    fn set_pitch(&mut self, _: f32) {}
+
+    fn reset(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    /// 0: tune (frequency ratio), 1: decay (ms), 2: filter cutoff (Hz),
+    /// 3: noise amount
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => {
+                self.tune = value.max(0.01);
+                self.update_freqs();
+            }
+            1 => self.env.decay_ms = value,
+            2 => self.filter.update_freq(value),
+            3 => self.noise_amt = value,
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.tune,
+            1 => self.env.decay_ms,
+            2 => self.filter.get_freq(),
+            3 => self.noise_amt,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        4
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.env.is_active()
+    }
+}
+
+// number of noise bursts before the clap's tail
+const CLAP_BURSTS: u32 = 3;
+
+const CLAP_Q: f32 = 2.0;
+
+/*
+    Clap: band-passed noise, shaped by a few short bursts in quick
+    succession followed by a longer tail
+*/
+pub struct Clap {
+    noise: Noise,
+    burst_env: AR,
+    tail_env: AR,
+    spread_ms: f32,
+    time: u32,
+    bursts: u32,
+    velocity: u8,
+    pitch: u8,
+    sample_rate: f32,
+}
+
+impl Clap {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Filter frequency", 200.0, 5000.0, 1200.0, "Hz"),
+            ParameterInfo::logarithmic(1, "Tail decay", 10.0, 1000.0, 200.0, "ms"),
+            ParameterInfo::linear(2, "Spread", 1.0, 30.0, 10.0, "ms"),
+        ]
+    }
+}
+
+impl SynthVoice for Clap {
+    fn new(sample_rate: f32) -> Self {
+        let mut noise = Noise::new(NoiseColor::Filtered, sample_rate);
+        noise.set_filter(1200.0, CLAP_Q);
+        Self {
+            noise,
+            burst_env: AR::new(0.0, 8.0, CurveType::Exponential { pow: 2 }, sample_rate),
+            tail_env: AR::new(0.0, 200.0, CurveType::Exponential { pow: 3 }, sample_rate),
+            spread_ms: 10.0,
+            time: 0,
+            bursts: CLAP_BURSTS,
+            velocity: 0,
+            pitch: 0,
+            sample_rate,
+        }
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        if self.bursts < CLAP_BURSTS {
+            let interval = (self.spread_ms * self.sample_rate / 1000.0).max(1.0) as u32;
+            if self.time.is_multiple_of(interval) {
+                self.burst_env.trigger(self.velocity);
+                self.bursts += 1;
+                // the tail starts along with the last burst
+                if self.bursts == CLAP_BURSTS {
+                    self.tail_env.trigger(self.velocity);
+                }
+            }
+            self.time += 1;
+        }
+        let env = self.burst_env.process().max(self.tail_env.process());
+        self.noise.process() * env
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, _: f32, _: f32) {
+        self.pitch = pitch;
+        self.velocity = velocity;
+        self.time = 0;
+        self.bursts = 0;
+    }
+
+    fn set_pitch(&mut self, _: f32) {}
+
+    fn reset(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    /// 0: filter frequency (Hz), 1: tail decay (ms), 2: burst spread (ms)
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.noise.set_filter(value, CLAP_Q),
+            1 => self.tail_env.decay_ms = value,
+            2 => self.spread_ms = value.max(0.0),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.noise.filter_freq(),
+            1 => self.tail_env.decay_ms,
+            2 => self.spread_ms,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        3
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.bursts < CLAP_BURSTS || self.burst_env.is_active() || self.tail_env.is_active()
+    }
+}
+
+/*
+    Tom: a sine that sweeps down to the played note, with a touch of noise
+*/
+pub struct Tom {
+    tune: f32,
+    pitch_env_amt: f32,
+    noise_amt: f32,
+    osc: Osc,
+    noise: Noise,
+    env: AR,
+    pitch_env: AR,
+    freq: f32,
+    pitch: u8,
+}
+
+impl Tom {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Tune", -24.0, 24.0, 0.0, "st"),
+            ParameterInfo::logarithmic(1, "Decay", 10.0, 2000.0, 300.0, "ms"),
+            ParameterInfo::linear(2, "Pitch envelope", 0.0, 2.0, 0.5, ""),
+            ParameterInfo::linear(3, "Noise", 0.0, 1.0, 0.05, ""),
+        ]
+    }
+}
+
+impl SynthVoice for Tom {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            tune: 0.0,
+            pitch_env_amt: 0.5,
+            noise_amt: 0.05,
+            osc: Osc::new(Waveform::Sine, sample_rate),
+            noise: Noise::new(NoiseColor::White, sample_rate),
+            env: AR::new(0.0, 300.0, CurveType::Exponential { pow: 3 }, sample_rate),
+            pitch_env: AR::new(0.0, 60.0, CurveType::Exponential { pow: 2 }, sample_rate),
+            freq: pitch_to_freq(45),
+            pitch: 45,
+        }
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        let sweep = 1.0 + self.pitch_env.process() * self.pitch_env_amt;
+        self.osc.set_freq(self.freq * sweep);
+        let env = self.env.process();
+        (self.osc.process() + self.noise.process() * self.noise_amt) * env
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, _: f32, _: f32) {
+        self.set_pitch(pitch as f32);
+        self.pitch = pitch;
+        self.osc.reset();
+        self.env.trigger(velocity);
+        self.pitch_env.trigger(velocity);
+    }
+
+    fn set_pitch(&mut self, pitch: f32) {
+        self.freq = fractional_pitch_to_freq(pitch + self.tune);
+    }
+
+    fn reset(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    /// 0: tune (semitones), 1: decay (ms), 2: pitch envelope amount
+    /// (frequency ratio), 3: noise amount
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => {
+                self.tune = value;
+                self.set_pitch(self.pitch as f32);
+            }
+            1 => self.env.decay_ms = value,
+            2 => self.pitch_env_amt = value,
+            3 => self.noise_amt = value,
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.tune,
+            1 => self.env.decay_ms,
+            2 => self.pitch_env_amt,
+            3 => self.noise_amt,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        4
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.env.is_active()
+    }
+}
+
+/*
+    Rimshot: two short, inharmonic sines and a noise click, high-passed
+*/
+pub struct Rimshot {
+    tone_hz: f32,
+    high: Osc,
+    low: Osc,
+    noise: Noise,
+    env: AR,
+    filter: SVF,
+    pitch: u8,
+}
+
+impl Rimshot {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Tone", 500.0, 4000.0, 1667.0, "Hz"),
+            ParameterInfo::logarithmic(1, "Decay", 5.0, 200.0, 25.0, "ms"),
+        ]
+    }
+
+    // ratio of the low to the high oscillator, as in the TR-808
+    const LOW_RATIO: f32 = 455.0 / 1667.0;
+
+    fn update_freqs(&mut self) {
+        self.high.set_freq(self.tone_hz);
+        self.low.set_freq(self.tone_hz * Self::LOW_RATIO);
+    }
+}
+
+impl SynthVoice for Rimshot {
+    fn new(sample_rate: f32) -> Self {
+        let mut filter = SVF::new(400.0, 0.707, sample_rate);
+        filter.mode = SVFMode::Highpass;
+        let mut rimshot = Self {
+            tone_hz: 1667.0,
+            high: Osc::new(Waveform::Sine, sample_rate),
+            low: Osc::new(Waveform::Sine, sample_rate),
+            noise: Noise::new(NoiseColor::White, sample_rate),
+            env: AR::new(0.0, 25.0, CurveType::Exponential { pow: 3 }, sample_rate),
+            filter,
+            pitch: 0,
+        };
+        rimshot.update_freqs();
+        rimshot
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        if !self.env.is_active() {
+            return 0.0;
+        }
+        let x = self.high.process() + self.low.process() + self.noise.process() * 0.3;
+        self.filter.process(x, 0.0) * self.env.process() * 0.5
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, _: f32, _: f32) {
+        self.pitch = pitch;
+        self.high.reset();
+        self.low.reset();
+        self.env.trigger(velocity);
+    }
+
+    fn set_pitch(&mut self, _: f32) {}
+
+    fn reset(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    /// 0: tone (Hz), 1: decay (ms)
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => {
+                self.tone_hz = value;
+                self.update_freqs();
+            }
+            1 => self.env.decay_ms = value,
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.tone_hz,
+            1 => self.env.decay_ms,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        2
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.env.is_active()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrumInstrument {
+    Kick,
+    Snare,
+    ClosedHat,
+    OpenHat,
+    Clap,
+    Tom,
+    Rimshot,
+}
+
+impl DrumInstrument {
+    pub const COUNT: usize = 7;
+
+    /// Instrument for a General MIDI percussion pitch
+    pub fn from_pitch(pitch: u8) -> Option<Self> {
+        match pitch {
+            35 | 36 => Some(DrumInstrument::Kick),
+            37 => Some(DrumInstrument::Rimshot),
+            38 | 40 => Some(DrumInstrument::Snare),
+            39 => Some(DrumInstrument::Clap),
+            42 | 44 => Some(DrumInstrument::ClosedHat),
+            46 => Some(DrumInstrument::OpenHat),
+            41 | 43 | 45 | 47 | 48 | 50 => Some(DrumInstrument::Tom),
+            _ => None,
+        }
+    }
+
+    fn from_index(index: i8) -> Option<Self> {
+        match index {
+            0 => Some(DrumInstrument::Kick),
+            1 => Some(DrumInstrument::Snare),
+            2 => Some(DrumInstrument::ClosedHat),
+            3 => Some(DrumInstrument::OpenHat),
+            4 => Some(DrumInstrument::Clap),
+            5 => Some(DrumInstrument::Tom),
+            6 => Some(DrumInstrument::Rimshot),
+            _ => None,
+        }
+    }
+}
+
+/// number of parameter slots reserved for each instrument of the kit
+pub const DRUM_PARAMETER_STRIDE: i8 = 8;
+
+/// Plays a drum instrument chosen by the note's General MIDI pitch. Kit
+/// parameters are laid out per instrument, `DRUM_PARAMETER_STRIDE` apart
+/// in `DrumInstrument` order, so e.g. parameter 9 is the snare's tone decay
+pub struct DrumKit {
+    kick: Kick,
+    snare: Snare,
+    closed_hat: HiHat,
+    open_hat: HiHat,
+    clap: Clap,
+    tom: Tom,
+    rimshot: Rimshot,
+    noise: Burst,
+    pitch: u8,
+}
+
+impl DrumKit {
+    /// The instruments' parameters, at their offset in the kit
+    pub fn parameters() -> Vec<ParameterInfo> {
+        let instruments = [
+            ("Kick", Kick::parameters()),
+            ("Snare", Snare::parameters()),
+            ("Closed hat", HiHat::parameters(CLOSED_HAT_DECAY_MS)),
+            ("Open hat", HiHat::parameters(OPEN_HAT_DECAY_MS)),
+            ("Clap", Clap::parameters()),
+            ("Tom", Tom::parameters()),
+            ("Rimshot", Rimshot::parameters()),
+        ];
+        instruments
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, (instrument, parameters))| {
+                parameters.into_iter().map(move |info| ParameterInfo {
+                    id: index as i8 * DRUM_PARAMETER_STRIDE + info.id,
+                    name: format!("{} {}", instrument, info.name.to_lowercase()),
+                    ..info
+                })
+            })
+            .collect()
+    }
+
+    fn split_parameter(parameter: i8) -> Option<(DrumInstrument, i8)> {
+        if parameter < 0 {
+            return None;
+        }
+        DrumInstrument::from_index(parameter / DRUM_PARAMETER_STRIDE)
+            .map(|instrument| (instrument, parameter % DRUM_PARAMETER_STRIDE))
+    }
+}
+
+impl SynthVoice for DrumKit {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            kick: Kick::new(50.0, 0.2, 0.3, 400.0, sample_rate),
+            snare: Snare::new(sample_rate),
+            closed_hat: HiHat::closed(sample_rate),
+            open_hat: HiHat::open(sample_rate),
+            clap: Clap::new(sample_rate),
+            tom: Tom::new(sample_rate),
+            rimshot: Rimshot::new(sample_rate),
+            noise: Burst::new(150.0, sample_rate),
+            pitch: 0,
+        }
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        let mut y = 0.0;
+        if self.kick.is_active() {
+            y += self.kick.process();
+        }
+        y += self.snare.process();
+        y += self.closed_hat.process();
+        y += self.open_hat.process();
+        y += self.clap.process();
+        y += self.tom.process();
+        y += self.rimshot.process();
+        if self.noise.is_active() {
+            y += self.noise.process();
+        }
+        y * 0.5
+    }
+
+    /// Pitches outside the General MIDI drum map play a noise burst. A
+    /// closed hat chokes a ringing open hat
+    fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32) {
+        self.pitch = pitch;
+        match DrumInstrument::from_pitch(pitch) {
+            Some(DrumInstrument::Kick) => self.kick.trigger(velocity),
+            Some(DrumInstrument::Snare) => self.snare.play(pitch, velocity, param1, param2),
+            Some(DrumInstrument::ClosedHat) => {
+                self.open_hat.choke();
+                self.closed_hat.play(pitch, velocity, param1, param2);
+            }
+            Some(DrumInstrument::OpenHat) => self.open_hat.play(pitch, velocity, param1, param2),
+            Some(DrumInstrument::Clap) => self.clap.play(pitch, velocity, param1, param2),
+            Some(DrumInstrument::Tom) => self.tom.play(pitch, velocity, param1, param2),
+            Some(DrumInstrument::Rimshot) => self.rimshot.play(pitch, velocity, param1, param2),
+            None => self.noise.trigger(velocity),
+        }
+    }
+
+    fn set_pitch(&mut self, _: f32) {}
+
+    fn reset(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        let Some((instrument, parameter)) = Self::split_parameter(parameter) else {
+            return;
+        };
+        match instrument {
+            DrumInstrument::Kick => self.kick.set_parameter(parameter, value),
+            DrumInstrument::Snare => self.snare.set_parameter(parameter, value),
+            DrumInstrument::ClosedHat => self.closed_hat.set_parameter(parameter, value),
+            DrumInstrument::OpenHat => self.open_hat.set_parameter(parameter, value),
+            DrumInstrument::Clap => self.clap.set_parameter(parameter, value),
+            DrumInstrument::Tom => self.tom.set_parameter(parameter, value),
+            DrumInstrument::Rimshot => self.rimshot.set_parameter(parameter, value),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        let Some((instrument, parameter)) = Self::split_parameter(parameter) else {
+            return 0.0;
+        };
+        match instrument {
+            DrumInstrument::Kick => self.kick.get_parameter(parameter),
+            DrumInstrument::Snare => self.snare.get_parameter(parameter),
+            DrumInstrument::ClosedHat => self.closed_hat.get_parameter(parameter),
+            DrumInstrument::OpenHat => self.open_hat.get_parameter(parameter),
+            DrumInstrument::Clap => self.clap.get_parameter(parameter),
+            DrumInstrument::Tom => self.tom.get_parameter(parameter),
+            DrumInstrument::Rimshot => self.rimshot.get_parameter(parameter),
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        DrumInstrument::COUNT as i8 * DRUM_PARAMETER_STRIDE
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        match Self::split_parameter(parameter) {
+            Some((DrumInstrument::Snare, parameter)) => self.snare.is_stepped(parameter),
+            _ => false,
+        }
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.kick.is_active()
+            || self.snare.is_active()
+            || self.closed_hat.is_active()
+            || self.open_hat.is_active()
+            || self.clap.is_active()
+            || self.tom.is_active()
+            || self.rimshot.is_active()
+            || self.noise.is_active()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn render<V: SynthVoice>(voice: &mut V, length: usize) -> Vec<f32> {
+        (0..length).map(|_| voice.process()).collect()
+    }
+
+    fn peak(ys: &[f32]) -> f32 {
+        ys.iter().fold(0.0, |peak, y| peak.max(y.abs()))
+    }
+
+    #[test]
+    fn instruments_sound_and_decay() {
+        let mut voices: Vec<Box<dyn SynthVoice>> = vec![
+            Box::new(Snare::new(SAMPLE_RATE)),
+            Box::new(HiHat::closed(SAMPLE_RATE)),
+            Box::new(HiHat::open(SAMPLE_RATE)),
+            Box::new(Clap::new(SAMPLE_RATE)),
+            Box::new(Tom::new(SAMPLE_RATE)),
+            Box::new(Rimshot::new(SAMPLE_RATE)),
+        ];
+        for voice in voices.iter_mut() {
+            assert!(!voice.is_active());
+            voice.play(60, 127, 0.0, 0.0);
+            assert!(voice.is_active());
+            let ys: Vec<f32> = (0..SAMPLE_RATE as usize).map(|_| voice.process()).collect();
+            assert!(peak(&ys) > 0.01);
+            assert!(ys.iter().all(|y| y.is_finite()));
+            assert!(!voice.is_active());
+        }
+    }
+
+    #[test]
+    fn open_hat_rings_longer_than_closed() {
+        let mut closed = HiHat::closed(SAMPLE_RATE);
+        let mut open = HiHat::open(SAMPLE_RATE);
+        closed.play(42, 127, 0.0, 0.0);
+        open.play(46, 127, 0.0, 0.0);
+        let tail = 0.1 * SAMPLE_RATE;
+        let closed_ys = render(&mut closed, tail as usize);
+        let open_ys = render(&mut open, tail as usize);
+        assert!(!closed.is_active());
+        assert!(open.is_active());
+        assert!(peak(&open_ys[4000..]) > peak(&closed_ys[4000..]));
+    }
+
+    #[test]
+    fn clap_has_multiple_bursts() {
+        let mut clap = Clap::new(SAMPLE_RATE);
+        clap.set_parameter(2, 10.0);
+        clap.play(39, 127, 0.0, 0.0);
+        // the first burst has died down before the second one starts
+        let ys = render(&mut clap, 480 * 3);
+        assert!(peak(&ys[400..480]) < peak(&ys[480..560]));
+    }
+
+    #[test]
+    fn tom_follows_played_pitch() {
+        let mut tom = Tom::new(SAMPLE_RATE);
+        tom.play(45, 100, 0.0, 0.0);
+        assert_eq!(tom.freq, pitch_to_freq(45));
+        tom.set_parameter(0, 12.0);
+        assert_eq!(tom.freq, pitch_to_freq(57));
+    }
+
+    #[test]
+    fn kit_maps_pitches_to_instruments() {
+        assert_eq!(DrumInstrument::from_pitch(36), Some(DrumInstrument::Kick));
+        assert_eq!(DrumInstrument::from_pitch(38), Some(DrumInstrument::Snare));
+        assert_eq!(
+            DrumInstrument::from_pitch(42),
+            Some(DrumInstrument::ClosedHat)
+        );
+        assert_eq!(
+            DrumInstrument::from_pitch(46),
+            Some(DrumInstrument::OpenHat)
+        );
+        assert_eq!(DrumInstrument::from_pitch(60), None);
+
+        let mut kit = DrumKit::new(SAMPLE_RATE);
+        kit.play(38, 127, 0.0, 0.0);
+        assert!(kit.snare.is_active());
+        assert!(!kit.kick.is_active());
+    }
+
+    #[test]
+    fn closed_hat_chokes_open_hat() {
+        let mut kit = DrumKit::new(SAMPLE_RATE);
+        kit.play(46, 127, 0.0, 0.0);
+        render(&mut kit, 100);
+        kit.play(42, 127, 0.0, 0.0);
+        render(&mut kit, 2000);
+        assert!(!kit.open_hat.is_active());
+        assert!(kit.closed_hat.is_active());
+    }
+
+    #[test]
+    fn kit_parameters_address_instruments() {
+        let mut kit = DrumKit::new(SAMPLE_RATE);
+        kit.set_parameter(DRUM_PARAMETER_STRIDE + 1, 250.0);
+        assert_eq!(kit.snare.get_parameter(1), 250.0);
+        assert_eq!(kit.get_parameter(DRUM_PARAMETER_STRIDE + 1), 250.0);
+        kit.set_parameter(0, 60.0);
+        assert_eq!(kit.get_parameter(0), 60.0);
+        // out of range parameters are ignored
+        kit.set_parameter(-1, 1.0);
+        kit.set_parameter(DRUM_PARAMETER_STRIDE * DrumInstrument::COUNT as i8, 1.0);
+        assert_eq!(kit.get_parameter(-1), 0.0);
+    }
 }
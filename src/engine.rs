@@ -1,39 +1,473 @@
-use crate::delay::Delay;
+#[cfg(feature = "analyzer")]
+use crate::analyzer::SpectrumAnalyzer;
+use crate::bus::{
+    Effect, EffectChain, EffectType, SendBus, TrackSend, DELAY_BUS, MAX_BUSES, REVERB_BUS,
+};
+use crate::chords::{Chord, MAX_CHORD_NOTES};
+use crate::consts::TRACK_COUNT;
+use crate::declick::Declicker;
+use crate::envelopes::RetriggerMode;
+use crate::eq::{Eq3, EQ_PARAMETER_COUNT, EQ_PARAMETER_OFFSET};
+use crate::export::WavFormat;
+use crate::filters::DcBlocker;
+use crate::input::AudioInput;
+use crate::lfo::{GlobalLfo, LfoTarget, GLOBAL_LFO_COUNT};
 use crate::limiter::Limiter;
-use crate::plaits_voice::FmVoice;
-use crate::reverb::Reverb;
-use crate::sequencer::{ScheduledEvent, Sequencer};
-use crate::{Message, NOTE_CALLBACK};
+use crate::macros::{Macro, MACRO_COUNT};
+use crate::meter::{Level, LevelMeter};
+use crate::metronome::Metronome;
+use crate::midi_parse::{MidiMessage, CC_PARAMETER_OFFSET, PITCH_BEND_RANGE};
+use crate::mixer::Mixer;
+use crate::modulators::ModProcessor;
+use crate::presets::{LoadedPreset, Preset, PresetCapture};
+use crate::recorder::{RecordSettings, RecordSource, Recorder};
+use crate::resampler::{ResamplerQuality, StreamResampler};
+use crate::sampler::Sample;
+use crate::scales::{ScaleQuantizer, USER_SCALE_COUNT};
+use crate::sequencer::{EventBuffer, ParameterLocks, ScheduledEvent, Sequencer, StopMode};
+use crate::shared::Shared;
+use crate::sidechain::{Ducker, SidechainTarget};
+use crate::simd;
+use crate::smoothing::{SmoothedParam, SmoothingType, DEFAULT_SMOOTHING_MS};
+use crate::snapshot::{morph, Snapshot, SNAPSHOT_SLOTS};
+use crate::synth::{create_voice, PolyVoice, SynthVoice, VoiceType};
+use crate::tuning::Tuning;
+use crate::utils::{samples_per_beat, samples_to_beats, DenormalGuard};
+use crate::velocity::VelocityCurve;
+use crate::{next_event_id, Message};
 use crossbeam::channel::Receiver;
-use std::collections::HashMap;
+use hound::WavWriter;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+
+// timestamped messages held, later ones are handled right away
+const PENDING_CAPACITY: usize = 256;
+// live notes whose expression can still change, the oldest are forgotten
+const LIVE_NOTE_CAPACITY: usize = 128;
+// note offs held after a stop, one per pitch on every track
+const FINISHING_CAPACITY: usize = TRACK_COUNT * 128;
+// parameter glides per track held before reallocating
+const RAMP_CAPACITY: usize = 16;
+// frames rendered at a time when bouncing to a file
+const OFFLINE_BLOCK_SIZE: usize = 512;
+// fade out of the voices when the transport stops with a flush
+const FLUSH_MS: f32 = 10.0;
+// converting to the host's sample rate runs on every block, so it's cheap
+const RATE_CONVERSION_QUALITY: ResamplerQuality = ResamplerQuality::Low;
+// frames rendered at the engine's rate per block without allocating
+const CONVERSION_CAPACITY: usize = 8192;
+
+/// Stereo outputs tracks can be routed to, the main output included
+pub const MAX_OUTPUTS: usize = 8;
 
 pub struct Engine {
     pub is_playing: bool,
+    start_pending: bool,
     sequencer: Sequencer,
-    voices: [FmVoice; 16],
-    reverb: Reverb,
-    delay: Delay,
+    metronome: Metronome,
+    count_in_bars: u32,
+    // sample time at which the sequence starts, after a count-in
+    count_in_end: Option<i64>,
+    // render the sequence ahead by the latency
+    latency_compensation: bool,
+    transport_start: i64,
+    // transport state of the current buffer, used to timestamp live notes
+    sample_time: i64,
+    tempo: f32,
+    // live notes by id, with their track and pitch, oldest first
+    live_notes: VecDeque<(u32, u8, u8)>,
+    // pitches held on each track, a bit per pitch
+    held_notes: [u128; TRACK_COUNT],
+    stop_mode: StopMode,
+    rewind_on_stop: bool,
+    // sequenced notes playing on after the transport stopped: the sample
+    // time their note off is due, their track and pitch, in order
+    finishing: VecDeque<(i64, u8, u8)>,
+    // gain of the voices fading out after a flush, and the tracks kept
+    // silent after it until they play again
+    flush_gain: Option<f32>,
+    silenced: [bool; TRACK_COUNT],
+    declickers: [Declicker; TRACK_COUNT],
+    // scheduled events of the current block, reused between blocks
+    events: EventBuffer,
+    // timestamped messages waiting for their frame, in frame order
+    pending: VecDeque<(u32, Message)>,
+    pitch_bends: [f32; TRACK_COUNT],
+    pitch_bend_ranges: [f32; TRACK_COUNT],
+    tuning: Tuning,
+    // semitones and cents each track is moved by
+    transposes: [i8; TRACK_COUNT],
+    fine_tunes: [f32; TRACK_COUNT],
+    quantizers: [ScaleQuantizer; TRACK_COUNT],
+    chords: [Option<Chord>; TRACK_COUNT],
+    velocity_curves: [VelocityCurve; TRACK_COUNT],
+    retrigger_modes: [RetriggerMode; TRACK_COUNT],
+    macros: [[Macro; MACRO_COUNT]; TRACK_COUNT],
+    lfos: [Option<GlobalLfo>; GLOBAL_LFO_COUNT],
+    // pitch class masks of the user scales
+    user_scales: [u16; USER_SCALE_COUNT],
+    voices: [Box<dyn SynthVoice>; TRACK_COUNT],
+    voice_types: [VoiceType; TRACK_COUNT],
+    // tracks whose sample or slices changed since they were last published
+    unpublished_samples: [bool; TRACK_COUNT],
+    inserts: [EffectChain; TRACK_COUNT],
+    eqs: [Eq3; TRACK_COUNT],
+    mixer: Mixer,
+    input: AudioInput,
+    recorder: Recorder,
+    // recordings by name
+    samples: HashMap<String, Arc<Sample>>,
+    // parameter changes still gliding to their target, per track
+    parameter_ramps: [Vec<(i8, SmoothedParam)>; TRACK_COUNT],
+    smoothing_type: SmoothingType,
+    smoothing_ms: f32,
+    // parameter changes glide across the block being rendered
+    block_ramps: bool,
+    block_frames: usize,
+    snapshots: [[Option<Snapshot>; SNAPSHOT_SLOTS]; TRACK_COUNT],
+    // values replaced by the parameter locks of each track's last step,
+    // restored at its next trig
+    locked_parameters: [ParameterLocks; TRACK_COUNT],
+    buses: Vec<SendBus>,
+    sends: [[TrackSend; MAX_BUSES]; TRACK_COUNT],
+    track_duckers: [Option<Ducker>; TRACK_COUNT],
+    bus_duckers: [Option<Ducker>; MAX_BUSES],
+    dc_blockers: [DcBlocker; TRACK_COUNT],
+    // left and right, ahead of the limiter
+    master_dc_blockers: [DcBlocker; 2],
+    dc_blocking: bool,
     limiter: Limiter,
+    // output each track plays on, 0 for the main one
+    track_outputs: [u8; TRACK_COUNT],
+    // limiters of the outputs after the main one, which keep them in time
+    // with it
+    output_limiters: Vec<Limiter>,
+    track_meters: [LevelMeter; TRACK_COUNT],
+    master_meter: LevelMeter,
+    scope_source: Option<usize>,
+    // per frame output of each track, then each bus, while bouncing stems
+    stems: Option<Vec<Vec<[f32; 2]>>>,
+    #[cfg(feature = "analyzer")]
+    analyzer: Option<Box<SpectrumAnalyzer>>,
+    shared: Arc<Shared>,
     rx: Receiver<Message>,
+    sample_rate: f32,
+    // set when the host renders at another rate than the engine's
+    conversion: Option<Box<RateConversion>>,
+    // engine frames per host frame, for the offsets of timestamped messages
+    frame_scale: f64,
 }
 
 impl Engine {
     pub fn new(rx: Receiver<Message>, sample_rate: f32) -> Self {
         Engine {
             is_playing: false,
+            start_pending: false,
             sequencer: Sequencer::new(4., sample_rate),
-            voices: [FmVoice::new(sample_rate); 16],
-            reverb: Reverb::new(sample_rate),
-            delay: Delay::new(sample_rate * 0.5, 0.5),
-            limiter: Limiter::new(0.1, 0.5, 0.5, sample_rate),
+            metronome: Metronome::new(sample_rate),
+            count_in_bars: 0,
+            count_in_end: None,
+            latency_compensation: true,
+            transport_start: 0,
+            sample_time: 0,
+            tempo: 120.0,
+            live_notes: VecDeque::with_capacity(LIVE_NOTE_CAPACITY),
+            held_notes: [0; TRACK_COUNT],
+            stop_mode: StopMode::default(),
+            rewind_on_stop: false,
+            finishing: VecDeque::with_capacity(FINISHING_CAPACITY),
+            flush_gain: None,
+            silenced: [false; TRACK_COUNT],
+            declickers: [Declicker::new(sample_rate); TRACK_COUNT],
+            events: EventBuffer::new(),
+            pending: VecDeque::with_capacity(PENDING_CAPACITY),
+            pitch_bends: [0.0; TRACK_COUNT],
+            pitch_bend_ranges: [PITCH_BEND_RANGE; TRACK_COUNT],
+            tuning: Tuning::default(),
+            transposes: [0; TRACK_COUNT],
+            fine_tunes: [0.0; TRACK_COUNT],
+            quantizers: [ScaleQuantizer::default(); TRACK_COUNT],
+            chords: [None; TRACK_COUNT],
+            velocity_curves: [VelocityCurve::Linear; TRACK_COUNT],
+            retrigger_modes: [RetriggerMode::Reset; TRACK_COUNT],
+            macros: [[Macro::default(); MACRO_COUNT]; TRACK_COUNT],
+            lfos: [None; GLOBAL_LFO_COUNT],
+            user_scales: [0; USER_SCALE_COUNT],
+            voices: std::array::from_fn(|_| create_voice(VoiceType::Fm, sample_rate)),
+            voice_types: [VoiceType::Fm; TRACK_COUNT],
+            unpublished_samples: [false; TRACK_COUNT],
+            inserts: std::array::from_fn(|_| EffectChain::new()),
+            eqs: std::array::from_fn(|_| Eq3::new(sample_rate)),
+            mixer: Mixer::new(),
+            input: AudioInput::new(),
+            recorder: Recorder::new(sample_rate),
+            samples: HashMap::new(),
+            parameter_ramps: std::array::from_fn(|_| Vec::with_capacity(RAMP_CAPACITY)),
+            locked_parameters: [ParameterLocks::default(); TRACK_COUNT],
+            smoothing_type: SmoothingType::Linear,
+            smoothing_ms: DEFAULT_SMOOTHING_MS,
+            block_ramps: false,
+            block_frames: 0,
+            snapshots: std::array::from_fn(|_| [None, None]),
+            buses: Self::default_buses(sample_rate),
+            sends: [[TrackSend::default(); MAX_BUSES]; TRACK_COUNT],
+            track_duckers: std::array::from_fn(|_| None),
+            bus_duckers: std::array::from_fn(|_| None),
+            dc_blockers: [DcBlocker::new(sample_rate); TRACK_COUNT],
+            master_dc_blockers: [DcBlocker::new(sample_rate); 2],
+            dc_blocking: true,
+            limiter: Limiter::new(1.5, 100.0, 0.98, sample_rate),
+            track_outputs: [0; TRACK_COUNT],
+            output_limiters: (1..MAX_OUTPUTS)
+                .map(|_| Limiter::new(1.5, 100.0, 0.98, sample_rate))
+                .collect(),
+            track_meters: std::array::from_fn(|_| LevelMeter::new(sample_rate)),
+            master_meter: LevelMeter::new(sample_rate),
+            scope_source: None,
+            stems: None,
+            #[cfg(feature = "analyzer")]
+            analyzer: None,
+            shared: Arc::new(Shared::new(sample_rate)),
             rx,
+            sample_rate,
+            conversion: None,
+            frame_scale: 1.0,
+        }
+    }
+
+    /// Render for a host running at `host_rate` while processing at the
+    /// rate the engine was created with, converting at the boundary, so
+    /// voices and effects sound the same whatever the device's rate
+    pub fn set_host_rate(&mut self, host_rate: f32) {
+        if host_rate > 0.0 && host_rate != self.sample_rate {
+            let conversion = RateConversion::new(host_rate, self.sample_rate);
+            self.frame_scale = conversion.scale;
+            self.conversion = Some(Box::new(conversion));
+        } else {
+            self.conversion = None;
+            self.frame_scale = 1.0;
         }
     }
 
+    fn default_buses(sample_rate: f32) -> Vec<SendBus> {
+        let mut buses = Vec::with_capacity(MAX_BUSES);
+        let mut reverb = SendBus::new("reverb");
+        reverb.add(EffectType::Reverb, sample_rate);
+        buses.push(reverb);
+        let mut delay = SendBus::new("delay");
+        delay.add(EffectType::Delay, sample_rate);
+        buses.push(delay);
+        buses
+    }
+
     pub fn init(&mut self) {
         println!("Engine init");
     }
 
+    pub fn set_playing(&mut self, is_playing: bool) {
+        if is_playing && !self.is_playing {
+            self.start_pending = true;
+        }
+        if !is_playing {
+            self.restore_all_parameter_locks();
+        }
+        if !is_playing && self.is_playing {
+            self.stop_transport();
+        }
+        if is_playing != self.is_playing {
+            self.shared
+                .transport_changed(is_playing, self.sequencer.position_beats());
+        }
+        self.is_playing = is_playing;
+    }
+
+    fn stop_transport(&mut self) {
+        let now = self.sample_time;
+        let note_offs = self
+            .sequencer
+            .flush(now + self.lead(), self.tempo)
+            .map(|(offset, track, pitch)| (now + offset, track, pitch));
+        match self.stop_mode {
+            StopMode::Flush => {
+                drop(note_offs);
+                self.flush_gain = Some(1.0);
+            }
+            StopMode::Release => {
+                drop(note_offs);
+                // the sequence's note offs won't come
+                self.all_notes_off();
+            }
+            StopMode::Finish => {
+                let room = FINISHING_CAPACITY - self.finishing.len();
+                self.finishing.extend(note_offs.take(room));
+                self.finishing
+                    .make_contiguous()
+                    .sort_unstable_by_key(|(due, ..)| *due);
+            }
+        }
+        if self.rewind_on_stop {
+            self.sequencer.rewind();
+        }
+    }
+
+    /// Position in beats `frame` into the current block, where live notes
+    /// land; the block's start while the transport is stopped
+    fn beats_at(&self, frame: u32) -> f32 {
+        let beats = self.sequencer.position_beats();
+        if self.is_playing && !self.start_pending && self.count_in_end.is_none() {
+            beats + samples_to_beats(frame as f32, self.tempo, self.sample_rate)
+        } else {
+            beats
+        }
+    }
+
+    /// Render `bars` bars of the sequence from the start into a stereo WAV
+    /// file, as fast as the engine runs. The audio callback mustn't run
+    /// meanwhile. The metronome and count-in are left out, and the
+    /// transport is stopped afterwards.
+    pub fn render_offline<P: AsRef<Path>>(
+        &mut self,
+        bars: u32,
+        tempo: f32,
+        path: P,
+        format: WavFormat,
+    ) -> Result<(), hound::Error> {
+        let mut writer = WavWriter::create(path, format.spec(self.sample_rate))?;
+        self.bounce(bars, tempo, |_, left, right| {
+            for (&l, &r) in left.iter().zip(right.iter()) {
+                format.write_sample(&mut writer, l)?;
+                format.write_sample(&mut writer, r)?;
+            }
+            Ok(())
+        })?;
+        writer.finalize()
+    }
+
+    /// Render `bars` bars into a stereo WAV file per track, named
+    /// `track_01.wav` and up, in `directory`. Stems are taken after the
+    /// track's fader and pan, and with `include_buses` each send bus's
+    /// return is written to `bus_01.wav` and up, so together they add up
+    /// to the mix before the limiter. Otherwise as `render_offline`.
+    pub fn render_stems<P: AsRef<Path>>(
+        &mut self,
+        bars: u32,
+        tempo: f32,
+        directory: P,
+        format: WavFormat,
+        include_buses: bool,
+    ) -> Result<(), hound::Error> {
+        let directory = directory.as_ref();
+        let spec = format.spec(self.sample_rate);
+        let mut paths: Vec<_> = (1..=TRACK_COUNT)
+            .map(|track| directory.join(format!("track_{track:02}.wav")))
+            .collect();
+        if include_buses {
+            paths.extend(
+                (1..=self.buses.len()).map(|bus| directory.join(format!("bus_{bus:02}.wav"))),
+            );
+        }
+        let mut writers = paths
+            .iter()
+            .map(|path| WavWriter::create(path, spec))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.stems = Some(vec![
+            vec![[0.0; 2]; OFFLINE_BLOCK_SIZE];
+            TRACK_COUNT + self.buses.len()
+        ]);
+        let result = self.bounce(bars, tempo, |engine, left, _| {
+            let Some(stems) = engine.stems.as_ref() else {
+                return Ok(());
+            };
+            for (writer, stem) in writers.iter_mut().zip(stems.iter()) {
+                for &[l, r] in stem[..left.len()].iter() {
+                    format.write_sample(writer, l)?;
+                    format.write_sample(writer, r)?;
+                }
+            }
+            Ok(())
+        });
+        self.stems = None;
+        result?;
+        writers.into_iter().try_for_each(WavWriter::finalize)
+    }
+
+    /// Play `bars` bars from the start as fast as possible, handing each
+    /// block of output to `write`
+    fn bounce(
+        &mut self,
+        bars: u32,
+        tempo: f32,
+        mut write: impl FnMut(&Engine, &[f32], &[f32]) -> Result<(), hound::Error>,
+    ) -> Result<(), hound::Error> {
+        let metronome = std::mem::replace(&mut self.metronome.enabled, false);
+        let count_in_bars = std::mem::take(&mut self.count_in_bars);
+        self.set_playing(false);
+        self.set_playing(true);
+
+        let mut render = || {
+            let bar_length = self.sequencer.time_signature().bar_length();
+            let beats = bars as f32 * bar_length;
+            let length = self.sequencer.beat_to_sample(beats, tempo).max(0) as usize;
+            let mut buf_l = vec![0.0; OFFLINE_BLOCK_SIZE];
+            let mut buf_r = vec![0.0; OFFLINE_BLOCK_SIZE];
+            for start in (0..length).step_by(OFFLINE_BLOCK_SIZE) {
+                let frames = OFFLINE_BLOCK_SIZE.min(length - start);
+                let (left, right) = (&mut buf_l[..frames], &mut buf_r[..frames]);
+                // files are written at the engine's own rate
+                self.render_block(
+                    None,
+                    &mut [[left, right]],
+                    start as i64,
+                    tempo,
+                    frames as i32,
+                );
+                write(self, left, right)?;
+            }
+            Ok(())
+        };
+        let result = render();
+
+        self.set_playing(false);
+        self.metronome.enabled = metronome;
+        self.count_in_bars = count_in_bars;
+        result
+    }
+
+    fn start_transport(&mut self, sample_time: i64, tempo: f32) {
+        self.start_pending = false;
+        self.transport_start = sample_time;
+        self.metronome.reset();
+        for lfo in self.lfos.iter_mut().flatten() {
+            lfo.restart();
+        }
+        let bar_length = self.sequencer.time_signature().bar_length();
+        let beats = self.count_in_bars as f32 * bar_length;
+        self.count_in_end = Some(sample_time + self.sequencer.beat_to_sample(beats, tempo) as i64);
+    }
+
+    /// Delay of the output relative to the input and the live notes, in
+    /// the host's samples, for the host to compensate
+    pub fn latency(&self) -> usize {
+        match &self.conversion {
+            Some(conversion) => {
+                (self.limiter.latency() as f64 / conversion.scale).round() as usize
+                    + conversion.input_delay
+            }
+            None => self.limiter.latency(),
+        }
+    }
+
+    // samples the sequence is rendered ahead of the host's timeline
+    fn lead(&self) -> i64 {
+        if self.latency_compensation {
+            self.limiter.latency() as i64
+        } else {
+            0
+        }
+    }
+
     pub fn process(
         &mut self,
         buf_l: &mut [f32],
@@ -42,97 +476,3503 @@ impl Engine {
         tempo: f32,
         num_frames: i32,
     ) {
-        let mut events = HashMap::new();
+        self.process_with_input(None, buf_l, buf_r, sample_time, tempo, num_frames);
+    }
+
+    /// Render a buffer, mixing in `input`, the host's left and right input
+    /// channels, through the input's inserts and sends
+    pub fn process_with_input(
+        &mut self,
+        input: Option<(&[f32], &[f32])>,
+        buf_l: &mut [f32],
+        buf_r: &mut [f32],
+        sample_time: i64,
+        tempo: f32,
+        num_frames: i32,
+    ) {
+        self.process_outputs(input, &mut [[buf_l, buf_r]], sample_time, tempo, num_frames);
+    }
+
+    /// Render a buffer into several stereo outputs, the left and right
+    /// channel of each. The first is the main output, which the buses, the
+    /// input and the metronome play on too; tracks routed to an output that
+    /// isn't passed play on the main one.
+    pub fn process_outputs(
+        &mut self,
+        input: Option<(&[f32], &[f32])>,
+        buffers: &mut [[&mut [f32]; 2]],
+        sample_time: i64,
+        tempo: f32,
+        num_frames: i32,
+    ) {
+        match self.conversion.take() {
+            Some(mut conversion) => {
+                conversion.process(self, input, buffers, sample_time, tempo, num_frames);
+                self.conversion = Some(conversion);
+            }
+            None => self.render_block(input, buffers, sample_time, tempo, num_frames),
+        }
+    }
+
+    /// Render a block at the engine's own rate
+    fn render_block(
+        &mut self,
+        input: Option<(&[f32], &[f32])>,
+        buffers: &mut [[&mut [f32]; 2]],
+        sample_time: i64,
+        tempo: f32,
+        num_frames: i32,
+    ) {
+        let _denormals = DenormalGuard::new();
+        let output_count = buffers.len().min(MAX_OUTPUTS);
+        let mut events = std::mem::take(&mut self.events);
+        events.clear();
+        self.sample_time = sample_time;
+        self.tempo = tempo;
+        self.block_frames = num_frames.max(0) as usize;
         self.get_msgs();
 
+        // the output each track plays on in this block, and which tracks
+        // the main output mixes
+        let routes = self.track_outputs.map(|output| {
+            if (output as usize) < output_count {
+                output as usize
+            } else {
+                0
+            }
+        });
+        let is_routed = routes.iter().any(|&output| output != 0);
+        let main_mask = routes.map(|output| if output == 0 { 1.0 } else { 0.0 });
+
+        if tempo > 0.0 {
+            let samples_per_beat = samples_per_beat(tempo, self.sample_rate);
+            for voice in self.voices.iter_mut() {
+                voice.set_tempo(samples_per_beat);
+            }
+            for chain in self.inserts.iter_mut() {
+                chain.set_tempo(samples_per_beat);
+            }
+            self.input.inserts.set_tempo(samples_per_beat);
+            for bus in self.buses.iter_mut() {
+                bus.chain.set_tempo(samples_per_beat);
+            }
+        }
+
+        if self.is_playing && self.start_pending {
+            self.start_transport(sample_time, tempo);
+        }
+
+        // the sequence is rendered ahead, so it's heard in time once it has
+        // passed through the limiter
+        let lead = self.lead();
         if self.is_playing {
-            self.sequencer
-                .process(&mut events, sample_time, tempo, num_frames);
+            let time = sample_time + lead;
+            let position = match self.count_in_end {
+                Some(end) if end >= time + num_frames as i64 => None,
+                Some(end) => {
+                    // the sequence starts in this buffer, start it from there;
+                    // what's due before this buffer, at most the lead, plays right away
+                    let offset = (end - time).max(0);
+                    let late = (time - end).max(0);
+                    self.sequencer.start(end, tempo);
+                    let position = self.sequencer.process(
+                        &mut events,
+                        end,
+                        tempo,
+                        num_frames - offset as i32 + late as i32,
+                    );
+                    events.delay(offset as usize);
+                    events.advance(late as usize);
+                    self.count_in_end = None;
+                    position
+                }
+                None => self.sequencer.process(&mut events, time, tempo, num_frames),
+            };
+            if let Some(position) = position {
+                self.shared
+                    .playback_progress(position, self.sequencer.position_beats());
+            }
+            if self.count_in_end.is_none() {
+                let position = self.sequencer.position_beats();
+                self.process_lfos(position);
+                for chain in self.inserts.iter_mut() {
+                    chain.set_position(position);
+                }
+                self.input.inserts.set_position(position);
+                for bus in self.buses.iter_mut() {
+                    bus.chain.set_position(position);
+                }
+            }
+            if self.sequencer.take_mutation_due() {
+                self.mutate_pattern(self.sequencer.current_pattern());
+            }
+            if self.sequencer.is_finished() {
+                self.set_playing(false);
+            }
         }
 
         for frame in 0..num_frames {
+            // timestamped live input due at this frame
+            while self
+                .pending
+                .front()
+                .is_some_and(|(f, _)| *f == frame as u32)
+            {
+                let (_, msg) = self.pending.pop_front().unwrap();
+                self.handle_msg(msg, frame as u32);
+            }
+
+            self.advance_parameter_ramps();
+
+            // note offs of the notes finishing after a stop
+            while self
+                .finishing
+                .front()
+                .is_some_and(|(due, ..)| *due <= self.sample_time + frame as i64)
+            {
+                let (_, track, pitch) = self.finishing.pop_front().unwrap();
+                let pitch = self.quantizers[track as usize].quantize(pitch);
+                self.release_note(track as usize, pitch);
+            }
+
             // play scheduled events
             if let Some(ev) = events.get(&(frame as usize)) {
                 for event in ev.iter() {
                     match event {
                         ScheduledEvent::NoteOn {
-                            time: _,
+                            time,
                             pitch,
                             velocity,
+                            param1,
+                            param2,
                             track,
+                            expression,
+                            locks,
                         } => {
-                            Self::note_played(true, *pitch, *track);
-                            self.voices[*track as usize].trigger(*velocity as u8);
+                            let pitch = self.quantizers[*track as usize].quantize(*pitch);
+                            let beats = self.sequencer.sample_to_beat(*time as i64, tempo);
+                            self.shared
+                                .note_played(true, pitch, *track, frame as u32, beats);
+                            self.apply_parameter_locks(*track as usize, locks);
+                            self.play_note(*track as usize, pitch, *velocity, *param1, *param2);
+                            self.voices[*track as usize].set_expression(*expression);
                         }
-                        ScheduledEvent::NoteOff {
-                            time: _,
-                            pitch,
-                            track,
-                        } => {
-                            // self.synth.stop();
-                            Self::note_played(false, *pitch, *track);
+                        ScheduledEvent::NoteOff { time, pitch, track } => {
+                            let pitch = self.quantizers[*track as usize].quantize(*pitch);
+                            let beats = self.sequencer.sample_to_beat(*time as i64, tempo);
+                            self.shared
+                                .note_played(false, pitch, *track, frame as u32, beats);
+                            self.release_note(*track as usize, pitch);
                         }
                     }
                 }
             }
 
-            let mut mix = 0.0;
-            let mut reverb_bus = 0.0;
-            let mut delay_bus = 0.0;
+            let mut bus_inputs = [0.0; MAX_BUSES];
             let mut active_voice_count = 1.0;
 
-            for voice in self.voices.iter_mut() {
-                if voice.is_active() {
-                    let y = voice.process();
-                    mix += y;
+            let mut outputs = [0.0; TRACK_COUNT];
+            for (track, voice) in self.voices.iter_mut().enumerate() {
+                let y = if voice.is_active() && !self.silenced[track] {
+                    let mut y = self.declickers[track].process(voice.process());
+                    if let Some(gain) = self.flush_gain {
+                        y *= gain;
+                    }
+                    if self.dc_blocking {
+                        y = self.dc_blockers[track].process(y);
+                    }
+                    active_voice_count += 1.0;
+                    y
+                } else {
+                    0.0
+                };
+                // inserts run on an idle track too, so their tails ring out
+                let y = self.inserts[track].process(y);
+                outputs[track] = self.eqs[track].process(y);
+            }
+            if let Some(gain) = self.flush_gain {
+                let gain = gain - 1.0 / (FLUSH_MS * 0.001 * self.sample_rate);
+                self.flush_gain = (gain > 0.0).then_some(gain);
+                if gain <= 0.0 {
+                    self.all_notes_off();
+                    self.silenced = [true; TRACK_COUNT];
+                }
+            }
+
+            // duckers are keyed by the source track's fader output
+            let mut track_gains = [1.0; TRACK_COUNT];
+            for (gain, ducker) in track_gains.iter_mut().zip(self.track_duckers.iter_mut()) {
+                if let Some(ducker) = ducker {
+                    let volume = self.mixer.volumes()[ducker.source];
+                    *gain = ducker.process(outputs[ducker.source] * volume);
+                }
+            }
+            let mut bus_gains = [1.0; MAX_BUSES];
+            for (gain, ducker) in bus_gains.iter_mut().zip(self.bus_duckers.iter_mut()) {
+                if let Some(ducker) = ducker {
+                    let volume = self.mixer.volumes()[ducker.source];
+                    *gain = ducker.process(outputs[ducker.source] * volume);
+                }
+            }
 
-                    reverb_bus += y * voice.reverb_amt;
-                    delay_bus += y * voice.delay_amt;
+            // muted tracks, and tracks left out of a solo, don't feed the buses either
+            let mut ducked = [0.0; TRACK_COUNT];
+            let mut pre_faders = [0.0; TRACK_COUNT];
+            let mut post_faders = [0.0; TRACK_COUNT];
+            simd::multiply(&mut ducked, &outputs, &track_gains);
+            simd::multiply(&mut pre_faders, &ducked, self.mixer.audible());
+            simd::multiply(&mut post_faders, &pre_faders, self.mixer.volumes());
 
-                    active_voice_count += 1.0;
+            for (meter, &y) in self.track_meters.iter_mut().zip(post_faders.iter()) {
+                meter.process(y);
+            }
+
+            let (left_gains, right_gains) = self.mixer.pan_gains();
+            let mut routed = [[0.0; 2]; MAX_OUTPUTS];
+            let mut main = post_faders;
+            if is_routed {
+                for (track, &output) in routes.iter().enumerate() {
+                    let y = post_faders[track];
+                    routed[output][0] += y * left_gains[track];
+                    routed[output][1] += y * right_gains[track];
                 }
+                simd::multiply(&mut main, &post_faders, &main_mask);
             }
+            let mut panned = [0.0; TRACK_COUNT];
+            simd::multiply(&mut panned, &main, left_gains);
+            let mut left = simd::sum(&panned);
+            simd::multiply(&mut panned, &main, right_gains);
+            let mut right = simd::sum(&panned);
 
-            mix /= active_voice_count;
-            reverb_bus /= active_voice_count;
-            delay_bus /= active_voice_count;
+            for (track, voice) in self.voices.iter().enumerate() {
+                if outputs[track] == 0.0 {
+                    continue;
+                }
+                let pre_fader = pre_faders[track];
+                let y = post_faders[track];
 
-            mix += self.reverb.process(reverb_bus);
-            mix += self.delay.process(delay_bus);
+                // the voice's own sends feed the default buses
+                let (reverb_send, delay_send) = voice.sends();
+                bus_inputs[REVERB_BUS] += y * reverb_send;
+                bus_inputs[DELAY_BUS] += y * delay_send;
 
-            // mix = self.limiter.process(mix);
+                for (input, send) in bus_inputs.iter_mut().zip(self.sends[track].iter()) {
+                    if send.level != 0.0 {
+                        *input += send.level * if send.pre_fader { pre_fader } else { y };
+                    }
+                }
+            }
 
-            buf_l[frame as usize] = mix;
-            buf_r[frame as usize] = mix;
-        }
-    }
+            left /= active_voice_count;
+            right /= active_voice_count;
+            for input in bus_inputs.iter_mut() {
+                *input /= active_voice_count;
+            }
 
-    pub fn get_msgs(&mut self) {
-        while let Ok(msg) = self.rx.try_recv() {
-            match msg {
-                Message::Schedule(event) => {
-                    self.sequencer.add_event(event);
+            // the input isn't scaled by the number of playing voices
+            let mut input_sample = 0.0;
+            if let Some((input_l, input_r)) = input {
+                let frame = frame as usize;
+                input_sample = (input_l[frame] + input_r[frame]) * 0.5;
+                let (l, r) = self
+                    .input
+                    .process(input_l[frame], input_r[frame], &mut bus_inputs);
+                left += l;
+                right += r;
+            }
+
+            let mut bus_returns = [0.0; MAX_BUSES];
+            for (((bus, input), gain), y) in self
+                .buses
+                .iter_mut()
+                .zip(bus_inputs)
+                .zip(bus_gains)
+                .zip(bus_returns.iter_mut())
+            {
+                *y = bus.process(input) * gain;
+                left += *y;
+                right += *y;
+            }
+
+            let mut bar_position = None;
+            if self.is_playing {
+                let time_signature = self.sequencer.time_signature();
+                self.metronome.beats_per_bar = time_signature.numerator as u32;
+                let time = sample_time + frame as i64 + lead;
+                let (beat, is_counting_in) = match self.count_in_end {
+                    Some(end) if time < end => (
+                        self.sequencer
+                            .sample_to_beat(time - self.transport_start, tempo),
+                        true,
+                    ),
+                    _ => (self.sequencer.pattern_position(time, tempo), false),
+                };
+                if !is_counting_in {
+                    // recordings follow the position being heard
+                    let heard = self.sequencer.pattern_position(time - lead, tempo);
+                    bar_position = Some(heard / time_signature.bar_length());
+                }
+                // click on the beats of the time signature
+                let beat = beat / time_signature.beat_length();
+                let click = self.metronome.process(beat, is_counting_in);
+                left += click;
+                right += click;
+            }
+
+            if self.dc_blocking {
+                left = self.master_dc_blockers[0].process(left);
+                right = self.master_dc_blockers[1].process(right);
+            }
+            let master_volume = self.mixer.master_volume();
+            let (left, right) = self
+                .limiter
+                .process_stereo(left * master_volume, right * master_volume);
+            self.master_meter.process_stereo(left, right);
+            if let Some(source) = self.recorder.source() {
+                let x = match source {
+                    RecordSource::Input => input_sample,
+                    RecordSource::Master => (left + right) * 0.5,
+                };
+                if let Some(recording) = self.recorder.process(x, bar_position) {
+                    self.store_recording(recording);
                 }
-                Message::NoteOn { track, velocity } => {
-                    Self::note_played(true, 0, track);
-                    self.voices[track as usize].trigger(velocity as u8);
+            }
+            if let Some(stems) = self.stems.as_mut() {
+                let (left_gains, right_gains) = self.mixer.pan_gains();
+                let gain = master_volume / active_voice_count;
+                let (tracks, buses) = stems.split_at_mut(TRACK_COUNT);
+                for (track, stem) in tracks.iter_mut().enumerate() {
+                    let y = post_faders[track] * gain;
+                    stem[frame as usize] = [y * left_gains[track], y * right_gains[track]];
                 }
-                Message::Clear => {
-                    self.sequencer.clear();
+                for (stem, y) in buses.iter_mut().zip(bus_returns) {
+                    stem[frame as usize] = [y * master_volume; 2];
                 }
-                Message::ParameterChange(parameter, value, track) => {
-                    self.voices[track as usize].set_parameter(parameter, value);
+            }
+            self.shared.scope().write(match self.scope_source {
+                Some(track) => post_faders[track],
+                None => (left + right) * 0.5,
+            });
+            #[cfg(feature = "analyzer")]
+            if let Some(analyzer) = self.analyzer.as_mut() {
+                if analyzer.process((left + right) * 0.5) {
+                    self.shared.publish_spectrum(analyzer.magnitudes());
                 }
             }
+
+            let frame = frame as usize;
+            buffers[0][0][frame] = left;
+            buffers[0][1][frame] = right;
+            let gain = master_volume / active_voice_count;
+            for (output, ([buf_l, buf_r], limiter)) in buffers[1..output_count]
+                .iter_mut()
+                .zip(self.output_limiters.iter_mut())
+                .enumerate()
+            {
+                let [left, right] = routed[output + 1];
+                let (left, right) = limiter.process_stereo(left * gain, right * gain);
+                buf_l[frame] = left;
+                buf_r[frame] = right;
+            }
+        }
+        self.publish_levels();
+        self.shared.publish_position(self.sequencer.bar_beat_tick());
+        self.shared
+            .publish_pattern(self.sequencer.current_pattern());
+        // offsets past this block carry over to the next one
+        for (frame, _) in self.pending.iter_mut() {
+            *frame -= num_frames as u32;
         }
+        self.events = events;
     }
 
-    fn note_played(note_on: bool, pitch: u8, track: u8) {
-        if let Some(callback) = *NOTE_CALLBACK.lock().unwrap() {
-            callback(note_on, pitch, track);
+    pub fn get_msgs(&mut self) {
+        while let Ok(msg) = self.rx.try_recv() {
+            // offsets are in the host's frames
+            match (msg.frame() as f64 * self.frame_scale) as u32 {
+                0 => self.handle_msg(msg, 0),
+                // early rather than reallocating once the queue is full
+                _ if self.pending.len() == PENDING_CAPACITY => self.handle_msg(msg, 0),
+                frame => {
+                    // kept in frame order, dispatched from the render loop
+                    let index = self.pending.partition_point(|(f, _)| *f <= frame);
+                    self.pending.insert(index, (frame, msg));
+                }
+            }
         }
+        self.publish_samples();
     }
-}
 
-#[cfg(test)]
-mod tests {}
+    /// Handle a message at `frame` into the current block
+    fn handle_msg(&mut self, msg: Message, frame: u32) {
+        // messages for tracks that don't exist are dropped
+        if msg
+            .track()
+            .is_some_and(|track| track as usize >= TRACK_COUNT)
+        {
+            return;
+        }
+        match msg {
+            Message::Schedule(event) => {
+                self.sequencer.add_event(event);
+            }
+            Message::ScheduleBatch(events) => {
+                self.sequencer.add_events(events);
+            }
+            Message::NoteOn {
+                id,
+                track,
+                pitch,
+                velocity,
+                expression,
+                ..
+            } => {
+                let pitch = self.quantizers[track as usize].quantize(pitch);
+                self.shared
+                    .note_played(true, pitch, track, frame, self.beats_at(frame));
+                self.restore_parameter_locks(track as usize);
+                self.play_note(track as usize, pitch, velocity, 0.0, 0.0);
+                self.voices[track as usize].set_expression(expression);
+                if self.live_notes.len() == LIVE_NOTE_CAPACITY {
+                    self.live_notes.pop_front();
+                }
+                self.live_notes.push_back((id, track, pitch));
+                if self.is_recording() {
+                    self.sequencer.record_note_on(
+                        next_event_id(),
+                        track,
+                        pitch,
+                        velocity,
+                        self.sample_time + frame as i64,
+                        self.tempo,
+                    );
+                }
+            }
+            Message::NoteOff { track, pitch, .. } => {
+                let pitch = self.quantizers[track as usize].quantize(pitch);
+                self.shared
+                    .note_played(false, pitch, track, frame, self.beats_at(frame));
+                self.release_note(track as usize, pitch);
+                self.live_notes
+                    .retain(|&(_, t, p)| (t, p) != (track, pitch));
+                if self.is_recording() {
+                    self.sequencer.record_note_off(
+                        track,
+                        pitch,
+                        self.sample_time + frame as i64,
+                        self.tempo,
+                    );
+                }
+            }
+            Message::NoteExpression {
+                id,
+                dimension,
+                value,
+            } => {
+                // a voice only follows the expression of the last note it played
+                if let Some(&(_, track, pitch)) =
+                    self.live_notes.iter().find(|(note, ..)| *note == id)
+                {
+                    let voice = &mut self.voices[track as usize];
+                    if voice.get_pitch() == pitch {
+                        let mut expression = voice.expression();
+                        expression.set(dimension, value);
+                        voice.set_expression(expression);
+                    }
+                }
+            }
+            Message::PitchBend { track, bend } => {
+                self.set_pitch_bend(track as usize, bend);
+            }
+            Message::PitchBendRange { track, semitones } => {
+                self.set_pitch_bend_range(track as usize, semitones);
+            }
+            Message::MasterTune(cents) => self.tuning.set_master_cents(cents),
+            Message::TrackTuning {
+                track,
+                transpose,
+                cents,
+            } => {
+                if let Some(t) = self.transposes.get_mut(track as usize) {
+                    *t = transpose;
+                    self.fine_tunes[track as usize] = cents.clamp(-100.0, 100.0);
+                }
+            }
+            Message::TuningScale { scale, root } => self.tuning.set_scale(scale, root),
+            Message::Midi { message, .. } => {
+                self.handle_midi(message, frame);
+            }
+            Message::Swing { track, amount } => {
+                self.sequencer.set_swing(track, amount);
+            }
+            Message::Humanize { track, humanize } => {
+                self.sequencer.set_humanize(track, humanize);
+            }
+            Message::Mutation(mutation) => {
+                self.sequencer.set_mutation(mutation);
+            }
+            Message::Mutate => {
+                self.mutate_pattern(self.sequencer.edit_pattern());
+            }
+            Message::Scale { track, root, scale } => {
+                if let Some(quantizer) = self.quantizers.get_mut(track as usize) {
+                    *quantizer = ScaleQuantizer::new(root, scale, &self.user_scales);
+                }
+            }
+            Message::VelocityCurve { track, curve } => {
+                if let Some(velocity_curve) = self.velocity_curves.get_mut(track as usize) {
+                    *velocity_curve = curve;
+                }
+            }
+            Message::RetriggerMode { track, mode } => {
+                if let Some(retrigger_mode) = self.retrigger_modes.get_mut(track as usize) {
+                    *retrigger_mode = mode;
+                    self.voices[track as usize].set_retrigger_mode(mode);
+                }
+            }
+            Message::Chord { track, chord } => {
+                self.set_chord(track as usize, chord);
+            }
+            Message::MacroAssign {
+                track,
+                index,
+                slot,
+                destination,
+            } => {
+                if let Some(m) = self.macro_mut(track as usize, index as usize) {
+                    m.assign(slot as usize, destination);
+                }
+            }
+            Message::Macro {
+                track,
+                index,
+                value,
+            } => self.set_macro(track as usize, index as usize, value),
+            Message::GlobalLfo { index, lfo } => {
+                if let Some(global_lfo) = self.lfos.get_mut(index as usize) {
+                    *global_lfo = lfo;
+                }
+            }
+            Message::UserScale { index, mask } => {
+                if index < USER_SCALE_COUNT {
+                    self.user_scales[index] = mask;
+                    for quantizer in self.quantizers.iter_mut() {
+                        quantizer.update_mask(&self.user_scales);
+                    }
+                }
+            }
+            Message::UpdateEvent(event) => {
+                self.sequencer.update_event(event);
+            }
+            Message::RemoveEvent(id) => {
+                self.sequencer.remove_event(id);
+            }
+            Message::Nudge { id, nudge_ms } => {
+                self.sequencer.set_event_nudge(id, nudge_ms);
+            }
+            Message::EventLock {
+                id,
+                parameter,
+                value,
+            } => {
+                self.sequencer.set_event_lock(id, parameter, value);
+            }
+            Message::CreatePattern { name, length } => {
+                self.sequencer.create_pattern(&name, length);
+            }
+            Message::EditPattern(pattern) => {
+                self.sequencer.set_edit_pattern(pattern);
+            }
+            Message::QueuePattern(pattern) => {
+                self.sequencer.queue_pattern(pattern);
+            }
+            Message::AppendChain(entry) => {
+                self.sequencer.append_chain(entry);
+            }
+            Message::ClearChain => {
+                self.sequencer.clear_chain();
+            }
+            Message::Metronome { enabled, volume } => {
+                self.metronome.enabled = enabled;
+                self.metronome.volume = volume;
+            }
+            Message::CountIn(bars) => {
+                self.count_in_bars = bars;
+            }
+            Message::LatencyCompensation(enabled) => {
+                self.latency_compensation = enabled;
+            }
+            Message::Recording(enabled) => {
+                self.sequencer.set_recording(enabled);
+            }
+            Message::RecordQuantize(grid) => {
+                self.sequencer.set_record_quantize(grid);
+            }
+            Message::SetPosition(beats) => {
+                self.sequencer.set_position_beats(beats);
+            }
+            Message::LoopMarkers { start, end } => {
+                self.sequencer.set_loop_markers(start, end);
+            }
+            Message::OneShot(one_shot) => {
+                self.sequencer.set_one_shot(one_shot);
+            }
+            Message::DeclickTime(ms) => {
+                for declicker in self.declickers.iter_mut() {
+                    declicker.set_time(ms);
+                }
+            }
+            Message::StopBehavior { mode, rewind } => {
+                self.stop_mode = mode;
+                self.rewind_on_stop = rewind;
+            }
+            Message::TimeSignature {
+                pattern,
+                time_signature,
+            } => {
+                self.sequencer.set_time_signature(pattern, time_signature);
+            }
+            Message::Clear => {
+                self.sequencer.clear();
+            }
+            Message::AllNotesOff => self.all_notes_off(),
+            Message::StageEvents(events) => {
+                self.sequencer.stage_events(events);
+            }
+            Message::CommitPattern { immediately } => {
+                // without a transport running, there's no boundary to wait for
+                self.sequencer
+                    .commit_staged(immediately || !self.is_playing);
+            }
+            Message::ParameterChange(parameter, value, track) => {
+                self.smooth_track_parameter(track as usize, parameter, value);
+            }
+            Message::ParameterSmoothing {
+                smoothing_type,
+                time_ms,
+            } => {
+                self.smoothing_type = smoothing_type;
+                self.smoothing_ms = time_ms.max(0.0);
+            }
+            Message::BlockRamps(enabled) => {
+                self.block_ramps = enabled;
+            }
+            Message::CaptureSnapshot { track, slot } => {
+                self.capture_snapshot(track as usize, slot as usize);
+            }
+            Message::Morph { track, amount } => {
+                self.morph(track as usize, amount);
+            }
+            Message::ModSlot { track, index, slot } => {
+                self.voices[track as usize].set_mod_slot(index, slot);
+            }
+            Message::ModShaper {
+                track,
+                index,
+                shaper,
+            } => {
+                let processor = ModProcessor::new(shaper, self.sample_rate);
+                self.voices[track as usize].set_mod_processor(index, processor);
+            }
+            Message::SetSound { track, voice_type } => {
+                self.set_sound(track as usize, voice_type);
+            }
+            Message::LoadSample { track, sample } => self.load_sample(track as usize, sample),
+            Message::SampleSlices { track, slices } => {
+                if let Some(voice) = self.voices.get_mut(track as usize) {
+                    voice.set_slices(slices);
+                    self.unpublished_samples[track as usize] = true;
+                }
+            }
+            Message::Record { settings, buffer } => self.recorder.arm(settings, buffer),
+            Message::StopRecording => {
+                if let Some(recording) = self.recorder.stop() {
+                    self.store_recording(recording);
+                }
+            }
+            Message::LoadRecordedSample { track, name } => {
+                if let Some(sample) = self.samples.get(&name).cloned() {
+                    self.load_sample(track as usize, sample);
+                }
+            }
+            Message::LoadPreset(mut loaded) => {
+                self.apply_loaded_preset(&mut loaded);
+                self.shared.retire(loaded);
+            }
+            Message::CapturePreset(mut capture, reply) => {
+                self.capture_preset_into(&mut capture);
+                // nobody's waiting anymore when the host gave up
+                if let Err(error) = reply.try_send(capture) {
+                    self.shared.retire(error.into_inner());
+                }
+            }
+            Message::CreateBus(bus) => {
+                // the host only creates MAX_BUSES, see `Shared::next_bus`
+                if self.buses.len() < MAX_BUSES {
+                    self.buses.push(bus);
+                }
+            }
+            Message::AddBusEffect {
+                bus,
+                effect_type,
+                effect,
+            } => {
+                let chain = self.buses.get_mut(bus as usize).map(|bus| &mut bus.chain);
+                Self::add_effect(&self.shared, chain, effect_type, effect);
+            }
+            Message::BusEffectParameter {
+                bus,
+                effect,
+                parameter,
+                value,
+            } => {
+                if let Some(bus) = self.buses.get_mut(bus as usize) {
+                    bus.set_effect_parameter(effect as usize, parameter, value);
+                }
+            }
+            #[cfg(feature = "convolution")]
+            Message::BusImpulseResponse { bus, effect, ir } => {
+                if let Some(bus) = self.buses.get_mut(bus as usize) {
+                    bus.chain.set_impulse_response(effect as usize, ir);
+                }
+            }
+            Message::AddTrackInsert {
+                track,
+                effect_type,
+                effect,
+            } => {
+                let chain = self.inserts.get_mut(track as usize);
+                Self::add_effect(&self.shared, chain, effect_type, effect);
+            }
+            Message::TrackInsertParameter {
+                track,
+                effect,
+                parameter,
+                value,
+            } => {
+                if let Some(inserts) = self.inserts.get_mut(track as usize) {
+                    inserts.set_effect_parameter(effect as usize, parameter, value);
+                }
+            }
+            #[cfg(feature = "convolution")]
+            Message::TrackInsertImpulseResponse { track, effect, ir } => {
+                if let Some(inserts) = self.inserts.get_mut(track as usize) {
+                    inserts.set_impulse_response(effect as usize, ir);
+                }
+            }
+            Message::AddInputInsert(effect_type, effect) => {
+                let chain = Some(&mut self.input.inserts);
+                Self::add_effect(&self.shared, chain, effect_type, effect);
+            }
+            Message::InputInsertParameter {
+                effect,
+                parameter,
+                value,
+            } => {
+                self.input
+                    .inserts
+                    .set_effect_parameter(effect as usize, parameter, value);
+            }
+            Message::InputSend { bus, send } => {
+                if let Some(input_send) = self.input.sends.get_mut(bus as usize) {
+                    *input_send = send;
+                }
+            }
+            Message::InputMonitor(level) => self.input.monitor = level.max(0.0),
+            Message::BusReturn { bus, level } => {
+                if let Some(bus) = self.buses.get_mut(bus as usize) {
+                    bus.return_level = level;
+                }
+            }
+            Message::BusFreeze { bus, freeze } => {
+                if let Some(bus) = self.buses.get_mut(bus as usize) {
+                    bus.set_freeze(freeze);
+                }
+            }
+            Message::TrackSend { track, bus, send } => {
+                if (track as usize) < TRACK_COUNT && (bus as usize) < MAX_BUSES {
+                    self.sends[track as usize][bus as usize] = send;
+                }
+            }
+            Message::TrackVolume { track, volume } => {
+                if (track as usize) < TRACK_COUNT {
+                    self.mixer.set_volume(track as usize, volume);
+                }
+            }
+            Message::TrackPan { track, pan } => {
+                if (track as usize) < TRACK_COUNT {
+                    self.mixer.set_pan(track as usize, pan);
+                }
+            }
+            Message::TrackMute { track, mute } => {
+                if (track as usize) < TRACK_COUNT {
+                    self.mixer.set_mute(track as usize, mute);
+                }
+            }
+            Message::TrackSolo { track, solo } => {
+                if (track as usize) < TRACK_COUNT {
+                    self.mixer.set_solo(track as usize, solo);
+                }
+            }
+            Message::TrackOutput { track, output } => {
+                if (track as usize) < TRACK_COUNT && (output as usize) < MAX_OUTPUTS {
+                    self.track_outputs[track as usize] = output;
+                }
+            }
+            Message::MasterVolume(volume) => self.mixer.set_master_volume(volume),
+            Message::DcBlocking(enabled) => {
+                if enabled && !self.dc_blocking {
+                    // don't pick up from state left over from before the bypass
+                    for blocker in self.dc_blockers.iter_mut() {
+                        blocker.reset();
+                    }
+                    for blocker in self.master_dc_blockers.iter_mut() {
+                        blocker.reset();
+                    }
+                }
+                self.dc_blocking = enabled;
+            }
+            Message::ScopeSource(track) => match track {
+                Some(track) if track as usize >= TRACK_COUNT => {}
+                _ => self.scope_source = track.map(usize::from),
+            },
+            #[cfg(feature = "analyzer")]
+            Message::SpectrumAnalyzer(analyzer) => {
+                if let Some(previous) = std::mem::replace(&mut self.analyzer, analyzer) {
+                    self.shared.retire(previous);
+                }
+                if self.analyzer.is_none() {
+                    self.shared.publish_spectrum(&[]);
+                }
+            }
+            Message::Sidechain {
+                target,
+                source,
+                amount,
+                attack,
+                release,
+            } => {
+                if source as usize >= TRACK_COUNT {
+                    return;
+                }
+                let slot = match target {
+                    SidechainTarget::Track(track) => self.track_duckers.get_mut(track as usize),
+                    SidechainTarget::Bus(bus) => self.bus_duckers.get_mut(bus as usize),
+                };
+                if let Some(slot) = slot {
+                    // a zero amount removes the ducker
+                    *slot = (amount > 0.0).then(|| {
+                        Ducker::new(source as usize, amount, attack, release, self.sample_rate)
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn current_pattern(&self) -> usize {
+        self.sequencer.current_pattern()
+    }
+
+    /// Zero-based bar of the transport position within the playing pattern
+    pub fn current_bar(&self) -> u32 {
+        self.sequencer.bar_beat_tick().bar
+    }
+
+    /// Zero-based beat within the current bar
+    pub fn current_beat(&self) -> u32 {
+        self.sequencer.bar_beat_tick().beat
+    }
+
+    /// Callbacks and published state, shared with the engine's handles
+    pub fn shared(&self) -> &Arc<Shared> {
+        &self.shared
+    }
+
+    /// Live notes are recorded while the transport runs, but not during a count-in
+    fn is_recording(&self) -> bool {
+        self.sequencer.is_recording()
+            && self.is_playing
+            && !self.start_pending
+            && self.count_in_end.is_none()
+    }
+
+    fn handle_midi(&mut self, msg: MidiMessage, frame: u32) {
+        let track = msg.channel() as usize % self.voices.len();
+        let beats = self.beats_at(frame);
+        let quantizer = self.quantizers[track];
+        let voice = &mut self.voices[track];
+        match msg {
+            MidiMessage::NoteOn {
+                pitch, velocity, ..
+            } => {
+                let pitch = quantizer.quantize(pitch);
+                self.shared
+                    .note_played(true, pitch, track as u8, frame, beats);
+                self.play_note(track, pitch, velocity, 0.0, 0.0);
+            }
+            MidiMessage::NoteOff { pitch, .. } => {
+                let pitch = quantizer.quantize(pitch);
+                self.shared
+                    .note_played(false, pitch, track as u8, frame, beats);
+                self.release_note(track, pitch);
+            }
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => {
+                if controller >= CC_PARAMETER_OFFSET {
+                    let parameter = (controller - CC_PARAMETER_OFFSET) as i8;
+                    voice.set_parameter_normalized(parameter, value as f32 / 127.0);
+                }
+            }
+            MidiMessage::PitchBend { bend, .. } => {
+                self.set_pitch_bend(track, bend);
+            }
+            MidiMessage::ChannelPressure { pressure, .. } => {
+                voice.set_pressure(pressure);
+            }
+        }
+    }
+
+    /// Bend a track by `bend` (-1.0..1.0) times its pitch bend range
+    fn set_pitch_bend(&mut self, track: usize, bend: f32) {
+        if track >= TRACK_COUNT {
+            return;
+        }
+        self.pitch_bends[track] = bend;
+        let ratio = self.pitch_bend_ratio(track);
+        self.voices[track].set_pitch_bend(ratio);
+    }
+
+    /// Pitch bend of a track as a frequency ratio offset
+    fn pitch_bend_ratio(&self, track: usize) -> f32 {
+        let semitones = self.pitch_bends[track] * self.pitch_bend_ranges[track];
+        (2f32).powf(semitones / 12.0) - 1.0
+    }
+
+    /// Switch the synthesis engine of a track
+    pub fn set_sound(&mut self, track: usize, voice_type: VoiceType) {
+        if track >= TRACK_COUNT || self.voice_types[track] == voice_type {
+            return;
+        }
+        let mut voice = create_voice(voice_type, self.sample_rate);
+        self.switch_voice(track, voice_type, &mut voice, false);
+    }
+
+    /// Swap in `voice`, of `voice_type` and built for chords or not, leaving
+    /// the track's previous voice in its place
+    fn switch_voice(
+        &mut self,
+        track: usize,
+        voice_type: VoiceType,
+        voice: &mut Box<dyn SynthVoice>,
+        is_chord: bool,
+    ) {
+        std::mem::swap(&mut self.voices[track], voice);
+        self.voice_types[track] = voice_type;
+        self.unpublished_samples[track] = true;
+        if self.chords[track].is_some() != is_chord {
+            self.voices[track] = self.copy_track_voice(track);
+        }
+        self.parameter_ramps[track].clear();
+        self.locked_parameters[track].clear();
+        let ratio = self.pitch_bend_ratio(track);
+        self.voices[track].set_pitch_bend(ratio);
+        self.voices[track].set_retrigger_mode(self.retrigger_modes[track]);
+    }
+
+    /// Mutate a pattern. Pitches move along the tracks' scales, except on
+    /// drum tracks, where they pick the instrument.
+    fn mutate_pattern(&mut self, pattern: usize) {
+        let scales = std::array::from_fn(|track| {
+            (self.voice_types[track] != VoiceType::DrumKit).then_some(self.quantizers[track])
+        });
+        self.sequencer.mutate(pattern, &scales);
+    }
+
+    /// Play a note on a track, as a chord when the track is in chord mode.
+    /// Notes are transposed and tuned, except on drum tracks, where the
+    /// pitch picks the instrument.
+    fn play_note(&mut self, track: usize, pitch: u8, velocity: u8, param1: f32, param2: f32) {
+        // pitches past the MIDI range play the top note
+        let pitch = pitch.min(127);
+        // a sounding voice restarts, or gives up one of its notes, with a jump
+        if self.voices[track].is_active() && !self.silenced[track] {
+            self.declickers[track].arm();
+        } else {
+            self.declickers[track].reset();
+        }
+        self.silenced[track] = false;
+        // a note played over itself ends the sounding one first
+        if self.held_notes[track] & 1 << pitch != 0 {
+            self.release_note(track, pitch);
+        }
+        self.held_notes[track] |= 1 << pitch;
+        let velocity = self.velocity_curves[track].apply(velocity);
+        let is_tuned = self.voice_types[track] != VoiceType::DrumKit;
+        let transpose = if is_tuned { self.transposes[track] } else { 0 };
+        let cents = self.fine_tunes[track];
+        let retune = is_tuned && (!self.tuning.is_equal_tempered() || cents != 0.0);
+        let tuning = &self.tuning;
+        let voice = &mut self.voices[track];
+        let mut play = |note: u8| {
+            let note = note.saturating_add_signed(transpose).min(127);
+            voice.play(note, velocity, param1, param2);
+            if retune {
+                voice.set_pitch(tuning.pitch(note) + cents / 100.0);
+            }
+        };
+        match self.chords[track] {
+            // the played note goes last, so the track reports its pitch
+            Some(chord) => {
+                for note in chord.notes(pitch).rev() {
+                    play(note);
+                }
+            }
+            None => play(pitch),
+        }
+    }
+
+    /// Stop a note played with `play_note`, with the rest of its chord
+    fn release_note(&mut self, track: usize, pitch: u8) {
+        let pitch = pitch.min(127);
+        if self.held_notes[track] & 1 << pitch == 0 {
+            return;
+        }
+        self.held_notes[track] &= !(1 << pitch);
+        let transpose = if self.voice_types[track] != VoiceType::DrumKit {
+            self.transposes[track]
+        } else {
+            0
+        };
+        let voice = &mut self.voices[track];
+        let mut stop = |note: u8| voice.stop_note(note.saturating_add_signed(transpose).min(127));
+        match self.chords[track] {
+            Some(chord) => chord.notes(pitch).for_each(stop),
+            None => stop(pitch),
+        }
+    }
+
+    /// Stop every note on every track, whatever note offs are still to come
+    pub fn all_notes_off(&mut self) {
+        for (voice, held) in self.voices.iter_mut().zip(self.held_notes.iter_mut()) {
+            voice.stop();
+            *held = 0;
+        }
+        self.live_notes.clear();
+    }
+
+    /// Switch a track in or out of chord mode. Chords play on a voice per
+    /// note, set up like the track's single voice.
+    fn set_chord(&mut self, track: usize, chord: Option<Chord>) {
+        if track >= TRACK_COUNT {
+            return;
+        }
+        let was_chord = self.chords[track].is_some();
+        self.chords[track] = chord;
+        self.shared.publish_chord_track(track, chord.is_some());
+        if chord.is_some() != was_chord {
+            self.voices[track] = self.copy_track_voice(track);
+            self.unpublished_samples[track] = true;
+        }
+    }
+
+    /// A new voice for a track with the settings of its current one, a
+    /// `PolyVoice` in chord mode
+    fn copy_track_voice(&self, track: usize) -> Box<dyn SynthVoice> {
+        let current = &self.voices[track];
+        let copy = || {
+            let mut voice = create_voice(self.voice_types[track], self.sample_rate);
+            // sample positions depend on the sample, so it goes first
+            if let Some(sample) = current.sample() {
+                voice.set_sample(sample);
+            }
+            if let Some(matrix) = current.mod_matrix() {
+                for (index, slot) in matrix.slots.iter().enumerate() {
+                    voice.set_mod_slot(index, *slot);
+                }
+                for (index, processor) in matrix.processors.iter().enumerate() {
+                    voice.set_mod_processor(index, *processor);
+                }
+            }
+            for parameter in 0..current.parameter_count() {
+                voice.set_parameter(parameter, current.get_parameter(parameter));
+            }
+            voice.set_pitch_bend(self.pitch_bend_ratio(track));
+            voice.set_retrigger_mode(self.retrigger_modes[track]);
+            voice
+        };
+        if self.chords[track].is_some() {
+            Box::new(PolyVoice::with_voices(
+                (0..MAX_CHORD_NOTES).map(|_| copy()).collect(),
+            ))
+        } else {
+            copy()
+        }
+    }
+
+    fn set_pitch_bend_range(&mut self, track: usize, semitones: f32) {
+        if track < TRACK_COUNT {
+            self.pitch_bend_ranges[track] = semitones.max(0.0);
+            self.set_pitch_bend(track, self.pitch_bends[track]);
+        }
+    }
+
+    /// Set a track parameter at once, cancelling any glide towards it
+    fn set_track_parameter(&mut self, track: usize, parameter: i8, value: f32) {
+        if track >= TRACK_COUNT {
+            return;
+        }
+        self.parameter_ramps[track].retain(|(p, _)| *p != parameter);
+        Self::route_parameter(
+            self.voices[track].as_mut(),
+            &mut self.eqs[track],
+            parameter,
+            value,
+        );
+    }
+
+    /// Set a voice parameter, or an EQ parameter from `EQ_PARAMETER_OFFSET` on
+    fn route_parameter(voice: &mut dyn SynthVoice, eq: &mut Eq3, parameter: i8, value: f32) {
+        if parameter >= EQ_PARAMETER_OFFSET {
+            eq.set_parameter(parameter - EQ_PARAMETER_OFFSET, value);
+        } else {
+            voice.set_parameter(parameter, value);
+        }
+    }
+
+    fn track_parameter(&self, track: usize, parameter: i8) -> f32 {
+        if parameter >= EQ_PARAMETER_OFFSET {
+            self.eqs[track].get_parameter(parameter - EQ_PARAMETER_OFFSET)
+        } else {
+            self.voices[track].get_parameter(parameter)
+        }
+    }
+
+    /// Move the global LFOs' targets to the LFOs' values at `position`,
+    /// in beats within the playing pattern
+    fn process_lfos(&mut self, position: f32) {
+        for index in 0..GLOBAL_LFO_COUNT {
+            let Some(lfo) = self.lfos[index].as_mut() else {
+                continue;
+            };
+            let value = lfo.advance(position);
+            match lfo.target {
+                // smoothed, so the steps between buffers don't zipper
+                LfoTarget::Track { track, parameter } => {
+                    self.smooth_track_parameter(track as usize, parameter, value);
+                }
+                LfoTarget::Bus {
+                    bus,
+                    effect,
+                    parameter,
+                } => {
+                    if let Some(bus) = self.buses.get_mut(bus as usize) {
+                        bus.set_effect_parameter(effect as usize, parameter, value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn macro_mut(&mut self, track: usize, index: usize) -> Option<&mut Macro> {
+        self.macros.get_mut(track)?.get_mut(index)
+    }
+
+    /// Turn a macro, moving every parameter assigned to it as a parameter
+    /// change would
+    fn set_macro(&mut self, track: usize, index: usize, value: f32) {
+        let Some(m) = self.macro_mut(track, index) else {
+            return;
+        };
+        m.set_value(value);
+        let m = *m;
+        for (parameter, value) in m.parameters() {
+            self.smooth_track_parameter(track, parameter, value);
+        }
+    }
+
+    /// Glide a track parameter to `value` over the smoothing time, or
+    /// across the current block with block ramps, starting from where it is
+    /// now. Stepped parameters change at once.
+    fn smooth_track_parameter(&mut self, track: usize, parameter: i8, value: f32) {
+        if track >= TRACK_COUNT {
+            return;
+        }
+        // a change while the parameter is locked becomes its new unlocked value
+        self.locked_parameters[track].remove(parameter);
+        let (smoothing_type, smoothing_ms) = if self.block_ramps {
+            let block_ms = self.block_frames as f32 * 1000.0 / self.sample_rate;
+            (SmoothingType::Linear, block_ms)
+        } else {
+            (self.smoothing_type, self.smoothing_ms)
+        };
+        let is_stepped =
+            parameter < EQ_PARAMETER_OFFSET && self.voices[track].is_stepped(parameter);
+        if is_stepped || smoothing_ms == 0.0 {
+            self.set_track_parameter(track, parameter, value);
+            return;
+        }
+
+        let current = self.track_parameter(track, parameter);
+        let ramps = &mut self.parameter_ramps[track];
+        match ramps.iter_mut().find(|(p, _)| *p == parameter) {
+            Some((_, ramp)) => {
+                ramp.set_type(smoothing_type);
+                ramp.set_time(smoothing_ms);
+                ramp.set_target(value);
+            }
+            None => {
+                let mut ramp =
+                    SmoothedParam::new(current, smoothing_type, smoothing_ms, self.sample_rate);
+                ramp.set_target(value);
+                ramps.push((parameter, ramp));
+            }
+        }
+    }
+
+    fn capture_snapshot(&mut self, track: usize, slot: usize) {
+        if track >= TRACK_COUNT || slot >= SNAPSHOT_SLOTS {
+            return;
+        }
+        self.snapshots[track][slot] = Some(Snapshot {
+            voice_type: self.voice_types[track],
+            parameters: self.track_parameters(track).collect(),
+        });
+    }
+
+    /// Glide the track's parameters `amount` of the way from snapshot A to
+    /// B, once both are captured from its current sound
+    fn morph(&mut self, track: usize, amount: f32) {
+        if track >= TRACK_COUNT {
+            return;
+        }
+        let snapshots = std::mem::take(&mut self.snapshots[track]);
+        if let [Some(a), Some(b)] = &snapshots {
+            let voice_type = self.voice_types[track];
+            if a.voice_type == voice_type && b.voice_type == voice_type {
+                for (parameter, from, to) in a.pairs(b) {
+                    let is_stepped =
+                        parameter < EQ_PARAMETER_OFFSET && self.voices[track].is_stepped(parameter);
+                    let value = morph(from, to, amount, is_stepped);
+                    // stepped parameters are only set when they switch over
+                    if is_stepped && value == self.track_parameter(track, parameter) {
+                        continue;
+                    }
+                    self.smooth_track_parameter(track, parameter, value);
+                }
+            }
+        }
+        self.snapshots[track] = snapshots;
+    }
+
+    /// Apply the parameter locks of a step, after restoring the values
+    /// locked by the track's previous one
+    fn apply_parameter_locks(&mut self, track: usize, locks: &ParameterLocks) {
+        self.restore_parameter_locks(track);
+        for (parameter, value) in locks.iter() {
+            // a gliding parameter is restored to where it was heading
+            let unlocked = self.parameter_ramps[track]
+                .iter()
+                .find(|(p, _)| *p == parameter)
+                .map(|(_, ramp)| ramp.target())
+                .unwrap_or_else(|| self.track_parameter(track, parameter));
+            self.locked_parameters[track].set(parameter, unlocked);
+            self.set_track_parameter(track, parameter, value);
+        }
+    }
+
+    fn restore_parameter_locks(&mut self, track: usize) {
+        let locked = std::mem::take(&mut self.locked_parameters[track]);
+        for (parameter, value) in locked.iter() {
+            self.set_track_parameter(track, parameter, value);
+        }
+    }
+
+    fn restore_all_parameter_locks(&mut self) {
+        for track in 0..TRACK_COUNT {
+            self.restore_parameter_locks(track);
+        }
+    }
+
+    /// Move gliding parameters one sample towards their targets
+    fn advance_parameter_ramps(&mut self) {
+        for (track, ramps) in self.parameter_ramps.iter_mut().enumerate() {
+            if ramps.is_empty() {
+                continue;
+            }
+            let voice = self.voices[track].as_mut();
+            let eq = &mut self.eqs[track];
+            ramps.retain_mut(|(parameter, ramp)| {
+                Self::route_parameter(voice, eq, *parameter, ramp.next_value());
+                ramp.is_smoothing()
+            });
+        }
+    }
+
+    /// Every voice and EQ parameter of a track, locked parameters with
+    /// their unlocked values
+    fn track_parameters(&self, track: usize) -> impl Iterator<Item = (i8, f32)> + '_ {
+        let (voice, eq) = (&self.voices[track], &self.eqs[track]);
+        let locked = &self.locked_parameters[track];
+        (0..voice.parameter_count())
+            .map(|p| (p, voice.get_parameter(p)))
+            .chain((0..EQ_PARAMETER_COUNT).map(|p| (EQ_PARAMETER_OFFSET + p, eq.get_parameter(p))))
+            .map(|(p, value)| (p, locked.get(p).unwrap_or(value)))
+    }
+
+    /// The engine's sound and sequence, see `capture_preset_into`
+    pub fn capture_preset(&self) -> Preset {
+        let name_len = self.buses.iter().map(|bus| bus.name.len()).max();
+        let mut capture =
+            PresetCapture::with_room(self.sequencer.events().len(), name_len.unwrap_or(0));
+        self.capture_preset_into(&mut capture);
+        capture.into_preset(self.sample_rate)
+    }
+
+    /// Copy the engine's sound and sequence into `capture` without
+    /// allocating. When the events or a bus name don't fit, they're left out
+    /// and the room they need is noted in the capture.
+    pub fn capture_preset_into(&self, capture: &mut PresetCapture) {
+        for (track, captured) in capture.tracks.iter_mut().enumerate() {
+            captured.voice_type = self.voice_types[track];
+            captured.parameters.clear();
+            captured.parameters.extend(self.track_parameters(track));
+            captured.mod_slots = self.voices[track].mod_matrix().map(|matrix| matrix.slots);
+            captured.strip = *self.mixer.strip(track);
+            captured.sends = self.sends[track];
+            self.inserts[track].capture(&mut captured.inserts);
+            captured.output = self.track_outputs[track];
+        }
+        let mut fits = true;
+        capture.bus_count = self.buses.len();
+        for (bus, captured) in self.buses.iter().zip(capture.buses.iter_mut()) {
+            captured.name.clear();
+            if bus.name.len() <= captured.name.capacity() {
+                captured.name.push_str(&bus.name);
+            } else {
+                fits = false;
+            }
+            captured.return_level = bus.return_level;
+            bus.chain.capture(&mut captured.effects);
+        }
+        self.input.inserts.capture(&mut capture.input_inserts);
+        capture.master_volume = self.mixer.master_volume();
+
+        let events = self.sequencer.events();
+        capture.events.clear();
+        if events.len() <= capture.events.capacity() {
+            capture.events.extend_from_slice(events);
+        } else {
+            fits = false;
+        }
+        capture.missing = (!fits).then(|| {
+            let name_len = self.buses.iter().map(|bus| bus.name.len()).max();
+            (events.len(), name_len.unwrap_or(0))
+        });
+    }
+
+    pub fn apply_preset(&mut self, preset: &Preset) {
+        let chord_tracks = std::array::from_fn(|track| self.chords[track].is_some());
+        let mut loaded = LoadedPreset::new(preset.clone(), self.sample_rate, chord_tracks);
+        self.apply_loaded_preset(&mut loaded);
+    }
+
+    /// Load a preset built off the audio thread, swapping the voices,
+    /// effects and events it replaces into `loaded`
+    pub fn apply_loaded_preset(&mut self, loaded: &mut LoadedPreset) {
+        let tracks = loaded.preset.tracks.iter().zip(loaded.voices.iter_mut());
+        for (index, ((track, voice), inserts)) in tracks.zip(loaded.inserts.iter_mut()).enumerate()
+        {
+            if self.voice_types[index] != track.voice_type {
+                let is_chord = loaded.chord_tracks[index];
+                self.switch_voice(index, track.voice_type, voice, is_chord);
+            }
+            self.locked_parameters[index].clear();
+            for (slot_index, slot) in track.mod_slots.iter().enumerate() {
+                self.voices[index].set_mod_slot(slot_index, *slot);
+            }
+            for &(parameter, value) in track.parameters.iter() {
+                self.set_track_parameter(index, parameter, value);
+            }
+            let strip = track.strip;
+            self.mixer.set_volume(index, strip.volume);
+            self.mixer.set_pan(index, strip.pan);
+            self.mixer.set_mute(index, strip.mute);
+            self.mixer.set_solo(index, strip.solo);
+            self.sends[index] = [TrackSend::default(); MAX_BUSES];
+            for (send, saved) in self.sends[index].iter_mut().zip(track.sends.iter()) {
+                *send = *saved;
+            }
+            std::mem::swap(&mut self.inserts[index], inserts);
+            if (track.output as usize) < MAX_OUTPUTS {
+                self.track_outputs[index] = track.output;
+            }
+        }
+
+        // buses are never removed, so ones missing from the preset stay
+        let existing = self.buses.len();
+        for (bus, saved) in self.buses.iter_mut().zip(loaded.buses.iter_mut()) {
+            std::mem::swap(bus, saved);
+        }
+        if loaded.buses.len() > existing {
+            // there's room for MAX_BUSES
+            self.buses.extend(loaded.buses.drain(existing..));
+        }
+        self.shared.reserve_buses(self.buses.len() as u32);
+        std::mem::swap(&mut self.input.inserts, &mut loaded.input_inserts);
+        self.mixer.set_master_volume(loaded.preset.master_volume);
+
+        self.sequencer.swap_events(&mut loaded.preset.events);
+    }
+
+    /// Add an effect built off the audio thread to `chain`, handing it back
+    /// to be dropped there when there's no such chain or it's full
+    fn add_effect(
+        shared: &Shared,
+        chain: Option<&mut EffectChain>,
+        effect_type: EffectType,
+        effect: Box<dyn Effect>,
+    ) {
+        let rejected = match chain {
+            Some(chain) => chain.add_created(effect_type, effect),
+            None => Some(effect),
+        };
+        if let Some(effect) = rejected {
+            shared.retire(effect);
+        }
+    }
+
+    fn load_sample(&mut self, track: usize, sample: Arc<Sample>) {
+        if track < TRACK_COUNT {
+            self.set_sound(track, VoiceType::Sampler);
+            self.voices[track].set_sample(sample);
+            self.unpublished_samples[track] = true;
+        }
+    }
+
+    /// Keep a finished recording, loading it onto its track if it has one
+    fn store_recording(&mut self, (settings, sample): (RecordSettings, Sample)) {
+        let sample = Arc::new(sample);
+        if let Some(track) = settings.track {
+            self.load_sample(track as usize, sample.clone());
+        }
+        self.samples.insert(settings.name, sample);
+    }
+
+    /// Make changed samples and slices available to the host, those it's
+    /// reading are tried again after the next buffer's messages
+    fn publish_samples(&mut self) {
+        for track in 0..TRACK_COUNT {
+            if self.unpublished_samples[track] {
+                let voice = &self.voices[track];
+                let published = self
+                    .shared
+                    .publish_sample(track, voice.sample(), voice.slices());
+                self.unpublished_samples[track] = !published;
+            }
+        }
+    }
+
+    fn publish_levels(&self) {
+        let mut levels = [Level::default(); TRACK_COUNT + 1];
+        for (level, meter) in levels.iter_mut().zip(self.track_meters.iter()) {
+            *level = meter.level();
+        }
+        levels[TRACK_COUNT] = self.master_meter.level();
+        self.shared.publish_levels(&levels);
+    }
+}
+
+/// Rendering for a host at another sample rate: the host's input is
+/// converted to the engine's rate, and each block renders as many frames at
+/// the engine's rate as the host's outputs need before converting them back
+struct RateConversion {
+    input: StreamResampler,
+    output: StreamResampler,
+    // the input's channels, then every output's, at the engine's rate
+    scratch: Vec<Vec<f32>>,
+    // engine samples per host sample
+    scale: f64,
+    // host samples the input is delayed, so each block finds enough of it
+    input_delay: usize,
+    // the engine's sample time, followed on from block to block while the
+    // host's does
+    time: i64,
+    next_host_time: Option<i64>,
+}
+
+impl RateConversion {
+    fn new(host_rate: f32, sample_rate: f32) -> Self {
+        let mut input = StreamResampler::new(2, host_rate, sample_rate, RATE_CONVERSION_QUALITY);
+        let input_delay = input.lookahead() + 2;
+        input.push(&[], input_delay);
+        let output = StreamResampler::new(
+            MAX_OUTPUTS * 2,
+            sample_rate,
+            host_rate,
+            RATE_CONVERSION_QUALITY,
+        );
+        Self {
+            input,
+            output,
+            scratch: (0..(MAX_OUTPUTS + 1) * 2)
+                .map(|_| Vec::with_capacity(CONVERSION_CAPACITY))
+                .collect(),
+            scale: sample_rate as f64 / host_rate as f64,
+            input_delay,
+            time: 0,
+            next_host_time: None,
+        }
+    }
+
+    fn process(
+        &mut self,
+        engine: &mut Engine,
+        input: Option<(&[f32], &[f32])>,
+        buffers: &mut [[&mut [f32]; 2]],
+        sample_time: i64,
+        tempo: f32,
+        num_frames: i32,
+    ) {
+        let frames = num_frames.max(0) as usize;
+        let count = self.output.needed(frames);
+        // the host jumped, or this is the first block
+        if self.next_host_time != Some(sample_time) {
+            self.time = (sample_time as f64 * self.scale).round() as i64;
+        }
+        self.next_host_time = Some(sample_time + frames as i64);
+
+        for channel in self.scratch.iter_mut() {
+            channel.clear();
+            channel.resize(count, 0.0);
+        }
+        let (inputs, outputs) = self.scratch.split_at_mut(2);
+        let [in_l, in_r] = inputs else { unreachable!() };
+        match input {
+            Some((left, right)) => self.input.push(&[left, right], frames),
+            None => self.input.push(&[], frames),
+        }
+        self.input.read(&mut [&mut in_l[..], &mut in_r[..]], count);
+
+        let output_count = buffers.len().min(MAX_OUTPUTS);
+        {
+            let mut pairs = outputs.chunks_exact_mut(2).map(|pair| match pair {
+                [left, right] => [&mut left[..], &mut right[..]],
+                _ => unreachable!(),
+            });
+            let mut rendered: [[&mut [f32]; 2]; MAX_OUTPUTS] =
+                std::array::from_fn(|_| pairs.next().unwrap());
+            engine.render_block(
+                input.map(|_| (&in_l[..], &in_r[..])),
+                &mut rendered[..output_count],
+                self.time,
+                tempo,
+                count as i32,
+            );
+        }
+        self.time += count as i64;
+
+        let rendered: [&[f32]; MAX_OUTPUTS * 2] =
+            std::array::from_fn(|channel| &outputs[channel][..]);
+        self.output.push(&rendered[..output_count * 2], count);
+        let mut channels = buffers.iter_mut().flat_map(|pair| pair.iter_mut());
+        let mut host: [&mut [f32]; MAX_OUTPUTS * 2] = std::array::from_fn(|_| {
+            channels
+                .next()
+                .map_or(&mut [][..], |channel| &mut channel[..])
+        });
+        self.output.read(&mut host[..output_count * 2], frames);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::create_effect;
+    use crate::chords::ChordType;
+    use crate::lfo::LfoShape;
+    use crate::macros::{MacroCurve, MacroDestination};
+    use crate::modulation::ModSlot;
+    use crate::modulators::ModShaper;
+    use crate::mutation::Mutation;
+    use crate::plaits_voice::ALGORITHM_PARAMETER;
+    use crate::sampler::{equal_slices, Sample};
+    use crate::scales::Scale;
+    use crate::sequencer::{Event, ExpressionDimension, NoteExpression};
+    use crossbeam::channel;
+    use std::sync::Arc;
+
+    #[test]
+    fn preset_round_trip() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.voices[1].set_parameter(4, 0.75);
+        engine.sequencer.add_event(Event {
+            beat_time: 2.0,
+            pitch: 64,
+            velocity: 90,
+            param1: 0.0,
+            param2: 0.0,
+            track: 1,
+            duration: 0.5,
+            ..Default::default()
+        });
+        engine.mixer.set_pan(1, 0.5);
+        engine.mixer.set_master_volume(0.8);
+        engine.sends[1][DELAY_BUS].level = 0.4;
+        engine.inserts[1].add(EffectType::Chorus, 48000.0);
+        engine.inserts[1].set_effect_parameter(0, 1, 0.3);
+        engine.buses[REVERB_BUS].set_effect_parameter(0, 0, 0.9);
+        engine.buses[DELAY_BUS].set_effect_parameter(0, 1, 0.6);
+        engine.buses.push(SendBus::new("phaser"));
+        engine.buses[2].add(EffectType::Phaser, 48000.0);
+        engine.buses[2].return_level = 0.5;
+        engine.input.inserts.add(EffectType::Saturator, 48000.0);
+        engine.track_outputs[1] = 3;
+        let preset = engine.capture_preset();
+
+        let (_, rx) = channel::unbounded();
+        let mut other = Engine::new(rx, 48000.0);
+        // the preset's chains replace what's there
+        other.inserts[1].add(EffectType::Delay, 48000.0);
+        let preset = Preset::from_bytes(&preset.to_bytes().unwrap()).unwrap();
+        other.apply_preset(&preset);
+        assert_eq!(other.voices[1].get_parameter(4), 0.75);
+        assert_eq!(other.sequencer.events().len(), 1);
+        assert_eq!(other.mixer.strip(1).pan, 0.5);
+        assert_eq!(other.mixer.master_volume(), 0.8);
+        assert_eq!(other.sends[1][DELAY_BUS].level, 0.4);
+        assert_eq!(other.inserts[1].effect_count(), 1);
+        assert_eq!(other.inserts[1].effect_parameter(0, 1), 0.3);
+        assert_eq!(other.buses[REVERB_BUS].effect_parameter(0, 0), 0.9);
+        assert_eq!(other.buses[DELAY_BUS].effect_parameter(0, 1), 0.6);
+        assert_eq!(other.buses.len(), 3);
+        assert_eq!(other.buses[2].name, "phaser");
+        assert_eq!(other.buses[2].return_level, 0.5);
+        assert_eq!(other.input.inserts.effect_count(), 1);
+        assert_eq!(other.track_outputs[1], 3);
+        // buses created afterwards come after the preset's
+        assert_eq!(other.shared.next_bus(), Some(3));
+        assert_eq!(other.capture_preset(), preset);
+    }
+
+    #[test]
+    fn preset_capture_notes_missing_room() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.sequencer.add_event(Event::default());
+        engine.sequencer.add_event(Event::default());
+
+        let mut capture = PresetCapture::with_room(1, 0);
+        engine.capture_preset_into(&mut capture);
+        assert_eq!(capture.missing_room(), Some((2, "reverb".len())));
+        assert!(capture.events.is_empty());
+
+        let mut capture = PresetCapture::with_room(2, 6);
+        engine.capture_preset_into(&mut capture);
+        assert_eq!(capture.missing_room(), None);
+        assert_eq!(capture.into_preset(48000.0), engine.capture_preset());
+    }
+
+    #[test]
+    fn loaded_presets_take_back_what_they_replace() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.set_sound(0, VoiceType::Fm);
+        engine.sequencer.add_event(Event::default());
+        let mut preset = engine.capture_preset();
+        preset.tracks[0].voice_type = VoiceType::Organ;
+        preset.events.clear();
+
+        let mut loaded = LoadedPreset::new(preset, 48000.0, [false; TRACK_COUNT]);
+        engine.apply_loaded_preset(&mut loaded);
+        assert_eq!(engine.voice_types[0], VoiceType::Organ);
+        assert!(engine.sequencer.events().is_empty());
+        // the old voice and events are dropped along with the loaded preset
+        let organ = create_voice(VoiceType::Organ, 48000.0);
+        assert_ne!(loaded.voices[0].parameter_count(), organ.parameter_count());
+        assert_eq!(loaded.preset.events.len(), 1);
+        assert_eq!(loaded.buses.len(), 2);
+    }
+
+    #[test]
+    fn renders_bars_to_wav() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.metronome.enabled = true;
+        engine.sequencer.add_event(Event {
+            beat_time: 1.0,
+            duration: 0.5,
+            ..Default::default()
+        });
+
+        let path = std::env::temp_dir().join("cp3_dsp_render_test.wav");
+        engine
+            .render_offline(1, 120.0, &path, WavFormat::Float32)
+            .unwrap();
+        assert!(!engine.is_playing);
+        assert!(engine.metronome.enabled);
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        // one bar of 4/4 at 120 BPM
+        assert_eq!(reader.duration(), 96000);
+        let samples: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+        // silent until the note on the second beat
+        assert!(samples[..48000].iter().all(|&x| x == 0.0));
+        assert!(samples[48000..].iter().any(|&x| x != 0.0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn renders_a_stem_per_track_and_bus() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.mixer.set_pan(2, -1.0);
+        engine.sequencer.add_event(Event {
+            track: 2,
+            duration: 0.5,
+            ..Default::default()
+        });
+
+        let directory = std::env::temp_dir().join("cp3_dsp_stems_test");
+        std::fs::create_dir_all(&directory).unwrap();
+        engine
+            .render_stems(1, 120.0, &directory, WavFormat::Float32, true)
+            .unwrap();
+
+        let read = |name: &str| -> Vec<f32> {
+            let mut reader = hound::WavReader::open(directory.join(name)).unwrap();
+            reader.samples().map(Result::unwrap).collect()
+        };
+        let stem = read("track_03.wav");
+        assert_eq!(stem.len(), 2 * 96000);
+        // panned hard left
+        assert!(stem.iter().step_by(2).any(|&x| x != 0.0));
+        assert!(stem.iter().skip(1).step_by(2).all(|&x| x.abs() < 1e-6));
+        assert!(read("track_01.wav").iter().all(|&x| x == 0.0));
+        assert_eq!(read("bus_02.wav").len(), 2 * 96000);
+        assert!(engine.stems.is_none());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn eq_parameters_route_to_track_eq() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::ParameterChange(EQ_PARAMETER_OFFSET + 2, -6.0, 5))
+            .unwrap();
+        let (mut buf_l, mut buf_r) = (vec![0.0; 1024], vec![0.0; 1024]);
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 1024);
+        assert_eq!(engine.eqs[5].get_parameter(2), -6.0);
+        assert!(engine.eqs[0].is_flat());
+
+        // the EQ is part of a preset
+        let preset = engine.capture_preset();
+        let (_, rx) = channel::unbounded();
+        let mut other = Engine::new(rx, 48000.0);
+        other.apply_preset(&preset);
+        assert_eq!(other.eqs[5].get_parameter(2), -6.0);
+    }
+
+    #[test]
+    fn parameter_changes_are_smoothed() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let (mut buf_l, mut buf_r) = (vec![0.0; 240], vec![0.0; 240]);
+        engine.set_track_parameter(0, 4, 0.0);
+        tx.send(Message::ParameterChange(4, 1.0, 0)).unwrap();
+        tx.send(Message::ParameterChange(ALGORITHM_PARAMETER, 2.0, 0))
+            .unwrap();
+
+        // halfway through the default 10 ms ramp
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 240);
+        assert!((engine.voices[0].get_parameter(4) - 0.5).abs() < 1e-3);
+        // stepped parameters don't glide
+        assert_eq!(engine.voices[0].get_parameter(ALGORITHM_PARAMETER), 2.0);
+
+        engine.process(&mut buf_l, &mut buf_r, 240, 120.0, 240);
+        assert_eq!(engine.voices[0].get_parameter(4), 1.0);
+        assert!(engine.parameter_ramps[0].is_empty());
+
+        // without a smoothing time changes apply at once
+        tx.send(Message::ParameterSmoothing {
+            smoothing_type: SmoothingType::OnePole,
+            time_ms: 0.0,
+        })
+        .unwrap();
+        tx.send(Message::ParameterChange(4, 0.25, 0)).unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[0].get_parameter(4), 0.25);
+    }
+
+    #[test]
+    fn block_ramps_span_the_next_block() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let (mut buf_l, mut buf_r) = (vec![0.0; 240], vec![0.0; 240]);
+        engine.set_track_parameter(0, 4, 0.0);
+        tx.send(Message::BlockRamps(true)).unwrap();
+
+        // done by the end of a block shorter than the smoothing time
+        tx.send(Message::ParameterChange(4, 1.0, 0)).unwrap();
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 240);
+        assert_eq!(engine.voices[0].get_parameter(4), 1.0);
+        assert!(engine.parameter_ramps[0].is_empty());
+
+        // and halfway through a longer one
+        tx.send(Message::ParameterChange(4, 0.0, 0)).unwrap();
+        engine.block_frames = 2048;
+        engine.get_msgs();
+        for _ in 0..1024 {
+            engine.advance_parameter_ramps();
+        }
+        assert!((engine.voices[0].get_parameter(4) - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn morphs_track_parameters_between_snapshots() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::ParameterSmoothing {
+            smoothing_type: SmoothingType::Linear,
+            time_ms: 0.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        // nothing to morph between yet
+        tx.send(Message::Morph {
+            track: 2,
+            amount: 0.5,
+        })
+        .unwrap();
+        engine.get_msgs();
+
+        engine.set_track_parameter(2, 4, 0.0);
+        engine.set_track_parameter(2, ALGORITHM_PARAMETER, 0.0);
+        tx.send(Message::CaptureSnapshot { track: 2, slot: 0 })
+            .unwrap();
+        engine.get_msgs();
+        engine.set_track_parameter(2, 4, 1.0);
+        engine.set_track_parameter(2, ALGORITHM_PARAMETER, 2.0);
+        tx.send(Message::CaptureSnapshot { track: 2, slot: 1 })
+            .unwrap();
+        tx.send(Message::Morph {
+            track: 2,
+            amount: 0.25,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[2].get_parameter(4), 0.25);
+        assert_eq!(engine.voices[2].get_parameter(ALGORITHM_PARAMETER), 0.0);
+
+        tx.send(Message::Morph {
+            track: 2,
+            amount: 0.75,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[2].get_parameter(4), 0.75);
+        assert_eq!(engine.voices[2].get_parameter(ALGORITHM_PARAMETER), 2.0);
+
+        // the snapshots belong to the sound they were captured from
+        engine.set_sound(2, VoiceType::Subtractive);
+        let before = engine.voices[2].get_parameter(4);
+        tx.send(Message::Morph {
+            track: 2,
+            amount: 0.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[2].get_parameter(4), before);
+    }
+
+    #[test]
+    fn bus_messages_build_effect_chains() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        assert_eq!(engine.buses.len(), 2);
+
+        tx.send(Message::CreateBus(SendBus::new("echo"))).unwrap();
+        tx.send(Message::AddBusEffect {
+            bus: 2,
+            effect_type: EffectType::Delay,
+            effect: create_effect(EffectType::Delay, 48000.0),
+        })
+        .unwrap();
+        tx.send(Message::BusEffectParameter {
+            bus: 2,
+            effect: 0,
+            parameter: 1,
+            value: 0.25,
+        })
+        .unwrap();
+        tx.send(Message::TrackSend {
+            track: 3,
+            bus: 2,
+            send: TrackSend {
+                level: 0.5,
+                pre_fader: true,
+            },
+        })
+        .unwrap();
+        engine.get_msgs();
+
+        assert_eq!(engine.buses[2].name, "echo");
+        assert_eq!(engine.buses[2].effect_count(), 1);
+        assert_eq!(engine.buses[2].effect_parameter(0, 1), 0.25);
+        assert_eq!(engine.sends[3][2].level, 0.5);
+
+        // buses beyond the maximum are ignored
+        for _ in 0..MAX_BUSES {
+            tx.send(Message::CreateBus(SendBus::new(""))).unwrap();
+        }
+        engine.get_msgs();
+        assert_eq!(engine.buses.len(), MAX_BUSES);
+    }
+
+    #[test]
+    fn pre_fader_send_ignores_track_volume() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::TrackVolume {
+            track: 0,
+            volume: 0.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+
+        let render = |engine: &mut Engine| {
+            engine.voices[0].play(60, 100, 0.0, 0.0);
+            let mut buf_l = vec![0.0; 9600];
+            let mut buf_r = vec![0.0; 9600];
+            engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 9600);
+            buf_l.iter().map(|y| y.abs()).sum::<f32>()
+        };
+
+        // a muted track with only post-fader sends is silent
+        assert_eq!(render(&mut engine), 0.0);
+
+        tx.send(Message::TrackSend {
+            track: 0,
+            bus: REVERB_BUS as u8,
+            send: TrackSend {
+                level: 1.0,
+                pre_fader: true,
+            },
+        })
+        .unwrap();
+        assert!(render(&mut engine) > 0.0);
+    }
+
+    #[test]
+    fn mixer_pans_mutes_and_solos_tracks() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+
+        let render = |engine: &mut Engine, tracks: &[usize]| {
+            for &track in tracks {
+                engine.voices[track].play(60, 100, 0.0, 0.0);
+            }
+            let mut buf_l = vec![0.0; 4800];
+            let mut buf_r = vec![0.0; 4800];
+            engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 4800);
+            (
+                buf_l.iter().map(|y| y.abs()).sum::<f32>(),
+                buf_r.iter().map(|y| y.abs()).sum::<f32>(),
+            )
+        };
+
+        tx.send(Message::TrackPan { track: 0, pan: 1.0 }).unwrap();
+        // silence is checked exactly, without the DC blockers' tails
+        tx.send(Message::DcBlocking(false)).unwrap();
+        engine.get_msgs();
+        let (left, right) = render(&mut engine, &[0]);
+        assert!(left < 1e-3 && right > 0.0);
+
+        // soloing another track silences this one, sends included
+        tx.send(Message::TrackSend {
+            track: 0,
+            bus: REVERB_BUS as u8,
+            send: TrackSend {
+                level: 1.0,
+                pre_fader: true,
+            },
+        })
+        .unwrap();
+        tx.send(Message::TrackSolo {
+            track: 1,
+            solo: true,
+        })
+        .unwrap();
+        engine.get_msgs();
+        engine.buses[REVERB_BUS].set_freeze(false);
+        for voice in engine.voices.iter_mut() {
+            voice.stop();
+        }
+        let _ = render(&mut engine, &[]);
+        let _ = render(&mut engine, &[]);
+        assert_eq!(render(&mut engine, &[0]), (0.0, 0.0));
+
+        tx.send(Message::TrackMute {
+            track: 1,
+            mute: true,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(render(&mut engine, &[1]), (0.0, 0.0));
+
+        tx.send(Message::TrackSolo {
+            track: 1,
+            solo: false,
+        })
+        .unwrap();
+        tx.send(Message::MasterVolume(0.0)).unwrap();
+        engine.get_msgs();
+        assert_eq!(render(&mut engine, &[0]), (0.0, 0.0));
+    }
+
+    #[test]
+    fn dc_is_blocked_before_the_limiter() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let input = vec![0.25; 4800];
+        let mut buf_l = vec![0.0; 4800];
+        let mut buf_r = vec![0.0; 4800];
+        for start in (0..48000).step_by(4800) {
+            engine.process_with_input(
+                Some((&input, &input)),
+                &mut buf_l,
+                &mut buf_r,
+                start,
+                120.0,
+                4800,
+            );
+        }
+        assert!(buf_l[4799].abs() < 1e-3);
+
+        tx.send(Message::DcBlocking(false)).unwrap();
+        engine.get_msgs();
+        engine.process_with_input(
+            Some((&input, &input)),
+            &mut buf_l,
+            &mut buf_r,
+            48000,
+            120.0,
+            4800,
+        );
+        assert!((buf_l[4799] - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tracks_and_master_are_metered() {
+        let (_tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.voices[2].play(60, 100, 0.0, 0.0);
+        let mut buf_l = vec![0.0; 4800];
+        let mut buf_r = vec![0.0; 4800];
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 4800);
+
+        let peak = buf_l.iter().fold(0.0_f32, |a, y| a.max(y.abs()));
+        assert!((engine.master_meter.level().peak - peak).abs() < 0.1);
+        assert!(engine.track_meters[2].level().peak > 0.0);
+        assert_eq!(engine.track_meters[3].level(), Level::default());
+    }
+
+    #[cfg(feature = "analyzer")]
+    #[test]
+    fn analyzer_taps_the_master_output() {
+        use crate::analyzer::SPECTRUM_FLOOR_DB;
+
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::SpectrumAnalyzer(Some(Box::new(
+            SpectrumAnalyzer::new(256),
+        ))))
+        .unwrap();
+        engine.get_msgs();
+
+        engine.voices[0].play(69, 100, 0.0, 0.0);
+        let mut buf_l = vec![0.0; 2048];
+        let mut buf_r = vec![0.0; 2048];
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 2048);
+        let analyzer = engine.analyzer.as_ref().unwrap();
+        assert_eq!(analyzer.magnitudes().len(), 128);
+        assert!(analyzer.magnitudes().iter().any(|&m| m > SPECTRUM_FLOOR_DB));
+
+        tx.send(Message::SpectrumAnalyzer(None)).unwrap();
+        engine.get_msgs();
+        assert!(engine.analyzer.is_none());
+    }
+
+    #[test]
+    fn scope_captures_the_selected_track() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::ScopeSource(Some(1))).unwrap();
+        engine.get_msgs();
+
+        engine.voices[0].play(60, 100, 0.0, 0.0);
+        let mut buf_l = vec![0.0; 960];
+        let mut buf_r = vec![0.0; 960];
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 960);
+        let mut points = [1.0; 96];
+        assert_eq!(engine.shared().scope().snapshot(20.0, &mut points), 96);
+        assert!(points.iter().all(|&y| y == 0.0));
+
+        tx.send(Message::ScopeSource(None)).unwrap();
+        engine.get_msgs();
+        engine.process(&mut buf_l, &mut buf_r, 960, 120.0, 960);
+        assert_eq!(engine.shared().scope().snapshot(20.0, &mut points), 96);
+        assert!(points.iter().any(|&y| y != 0.0));
+    }
+
+    #[test]
+    fn audio_input_is_processed_like_a_track() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let input: Vec<f32> = (0..4800).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        let mut buf_l = vec![0.0; 4800];
+        let mut buf_r = vec![0.0; 4800];
+
+        // monitored dry by default, passing only the limiter's delay
+        engine.process_with_input(
+            Some((&input, &input)),
+            &mut buf_l,
+            &mut buf_r,
+            0,
+            120.0,
+            4800,
+        );
+        assert!(buf_r.iter().any(|&y| y != 0.0));
+        assert_eq!(buf_l, buf_r);
+
+        tx.send(Message::InputMonitor(0.0)).unwrap();
+        tx.send(Message::InputSend {
+            bus: DELAY_BUS as u8,
+            send: TrackSend {
+                level: 1.0,
+                pre_fader: true,
+            },
+        })
+        .unwrap();
+        tx.send(Message::AddInputInsert(
+            EffectType::Saturator,
+            create_effect(EffectType::Saturator, 48000.0),
+        ))
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.input.inserts.effect_count(), 1);
+        // silence, then the delayed input
+        let mut returns = 0.0;
+        for start in (0..48000).step_by(4800) {
+            engine.process_with_input(
+                Some((&input, &input)),
+                &mut buf_l,
+                &mut buf_r,
+                start,
+                120.0,
+                4800,
+            );
+            returns += buf_l.iter().map(|y| y.abs()).sum::<f32>();
+        }
+        assert!(returns > 0.0);
+
+        tx.send(Message::InputSend {
+            bus: DELAY_BUS as u8,
+            send: TrackSend::default(),
+        })
+        .unwrap();
+        engine.get_msgs();
+        engine.buses[DELAY_BUS] = SendBus::new("silent");
+        engine.process_with_input(
+            Some((&input, &input)),
+            &mut buf_l,
+            &mut buf_r,
+            0,
+            120.0,
+            4800,
+        );
+        engine.process_with_input(
+            Some((&input, &input)),
+            &mut buf_l,
+            &mut buf_r,
+            0,
+            120.0,
+            4800,
+        );
+        assert!(buf_l.iter().all(|&y| y.abs() < 1e-3));
+    }
+
+    #[test]
+    fn input_is_recorded_into_a_sample() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::Record {
+            settings: RecordSettings {
+                name: "vocal".to_string(),
+                source: RecordSource::Input,
+                threshold: Some(0.1),
+                synced: false,
+                track: Some(3),
+            },
+            buffer: Vec::with_capacity(48000),
+        })
+        .unwrap();
+
+        let input: Vec<f32> = (0..480).map(|i| i as f32 / 480.0).collect();
+        let mut buf_l = vec![0.0; 480];
+        let mut buf_r = vec![0.0; 480];
+        engine.process_with_input(
+            Some((&input, &input)),
+            &mut buf_l,
+            &mut buf_r,
+            0,
+            120.0,
+            480,
+        );
+        tx.send(Message::StopRecording).unwrap();
+        engine.get_msgs();
+
+        // recorded from the first sample over the threshold
+        let sample = engine.samples["vocal"].clone();
+        assert_eq!(sample.len(), 480 - 48);
+        assert_eq!(sample.data[0], input[48]);
+        assert_eq!(engine.voice_types[3], VoiceType::Sampler);
+        assert!(Arc::ptr_eq(&engine.voices[3].sample().unwrap(), &sample));
+
+        tx.send(Message::LoadRecordedSample {
+            track: 5,
+            name: "vocal".to_string(),
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voice_types[5], VoiceType::Sampler);
+    }
+
+    #[test]
+    fn track_inserts_process_the_voice() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::AddTrackInsert {
+            track: 2,
+            effect_type: EffectType::Saturator,
+            effect: create_effect(EffectType::Saturator, 48000.0),
+        })
+        .unwrap();
+        tx.send(Message::TrackInsertParameter {
+            track: 2,
+            effect: 0,
+            parameter: 0,
+            value: 10.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+
+        assert_eq!(engine.inserts[2].effect_count(), 1);
+        assert_eq!(engine.inserts[2].effect_parameter(0, 0), 10.0);
+        assert_eq!(engine.inserts[0].effect_count(), 0);
+    }
+
+    #[test]
+    fn track_inserts_ring_out_after_the_voice() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::AddTrackInsert {
+            track: 0,
+            effect_type: EffectType::Delay,
+            effect: create_effect(EffectType::Delay, 48000.0),
+        })
+        .unwrap();
+        engine.get_msgs();
+        // only the insert is heard
+        for bus in engine.buses.iter_mut() {
+            bus.return_level = 0.0;
+        }
+
+        let (mut left, mut right) = (vec![0.0; 512], vec![0.0; 512]);
+        engine.play_note(0, 60, 100, 0.0, 0.0);
+        engine.process(&mut left, &mut right, 0, 120.0, 512);
+        engine.release_note(0, 60);
+        while engine.voices[0].is_active() {
+            engine.process(&mut left, &mut right, 0, 120.0, 512);
+        }
+        // past what's left of the note in the master's look-ahead
+        for _ in 0..10 {
+            engine.process(&mut left, &mut right, 0, 120.0, 512);
+        }
+        let mut tail = 0.0_f32;
+        for _ in 0..100 {
+            engine.process(&mut left, &mut right, 0, 120.0, 512);
+            tail = left.iter().fold(tail, |a, y| a.max(y.abs()));
+        }
+        assert!(tail > 1e-3);
+    }
+
+    #[test]
+    fn sidechain_messages_set_duckers() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let sidechain = |target, source, amount| Message::Sidechain {
+            target,
+            source,
+            amount,
+            attack: 1.0,
+            release: 100.0,
+        };
+
+        tx.send(sidechain(SidechainTarget::Track(1), 0, 0.5))
+            .unwrap();
+        tx.send(sidechain(SidechainTarget::Bus(REVERB_BUS as u8), 0, 0.8))
+            .unwrap();
+        // out of range sources and targets are ignored
+        tx.send(sidechain(SidechainTarget::Track(2), TRACK_COUNT as u8, 0.5))
+            .unwrap();
+        tx.send(sidechain(SidechainTarget::Bus(MAX_BUSES as u8), 0, 0.5))
+            .unwrap();
+        engine.get_msgs();
+
+        let ducker = engine.track_duckers[1].as_ref().unwrap();
+        assert_eq!((ducker.source, ducker.amount), (0, 0.5));
+        assert_eq!(engine.bus_duckers[REVERB_BUS].as_ref().unwrap().amount, 0.8);
+        assert!(engine.track_duckers[2].is_none());
+
+        // a zero amount removes the ducker
+        tx.send(sidechain(SidechainTarget::Track(1), 0, 0.0))
+            .unwrap();
+        engine.get_msgs();
+        assert!(engine.track_duckers[1].is_none());
+    }
+
+    #[test]
+    fn pitches_past_the_midi_range_play_the_top_note() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::NoteOn {
+            id: 1,
+            track: 0,
+            pitch: 200,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: 0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.held_notes[0], 1 << 127);
+
+        tx.send(Message::NoteOff {
+            track: 0,
+            pitch: 200,
+            frame: 0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.held_notes[0], 0);
+    }
+
+    #[test]
+    fn messages_for_missing_tracks_are_dropped() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let (mut left, mut right) = (vec![0.0; 4800], vec![0.0; 4800]);
+        tx.send(Message::NoteOn {
+            id: 1,
+            track: 99,
+            pitch: 60,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: 0,
+        })
+        .unwrap();
+        tx.send(Message::NoteOff {
+            track: 99,
+            pitch: 60,
+            frame: 10,
+        })
+        .unwrap();
+        tx.send(Message::Schedule(Event {
+            track: 99,
+            ..Default::default()
+        }))
+        .unwrap();
+        tx.send(Message::ModSlot {
+            track: 99,
+            index: 0,
+            slot: ModSlot::default(),
+        })
+        .unwrap();
+        tx.send(Message::ModShaper {
+            track: 99,
+            index: 0,
+            shaper: ModShaper::default(),
+        })
+        .unwrap();
+        engine.set_playing(true);
+        engine.process(&mut left, &mut right, 0, 120.0, 4800);
+        assert!(engine.live_notes.is_empty());
+        assert!(engine.sequencer.events().is_empty());
+    }
+
+    #[test]
+    fn notes_are_quantized_to_track_scale() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::Scale {
+            track: 1,
+            root: 0,
+            scale: Scale::Major,
+        })
+        .unwrap();
+        tx.send(Message::NoteOn {
+            id: 1,
+            track: 1,
+            pitch: 61,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: 0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[1].get_pitch(), 60);
+        assert_eq!(engine.live_notes[0], (1, 1, 60));
+
+        // other tracks stay chromatic
+        engine.handle_midi(
+            MidiMessage::NoteOn {
+                channel: 2,
+                pitch: 61,
+                velocity: 100,
+            },
+            0,
+        );
+        assert_eq!(engine.voices[2].get_pitch(), 61);
+
+        // tracks follow changes to their user scale
+        tx.send(Message::Scale {
+            track: 2,
+            root: 0,
+            scale: Scale::User(0),
+        })
+        .unwrap();
+        tx.send(Message::UserScale {
+            index: 0,
+            mask: 1 | 1 << 7,
+        })
+        .unwrap();
+        engine.get_msgs();
+        engine.handle_midi(
+            MidiMessage::NoteOn {
+                channel: 2,
+                pitch: 66,
+                velocity: 100,
+            },
+            0,
+        );
+        assert_eq!(engine.voices[2].get_pitch(), 67);
+    }
+
+    #[test]
+    fn velocity_curves_shape_played_notes() {
+        let render = |velocity: u8, curve: VelocityCurve| {
+            let (tx, rx) = channel::unbounded();
+            let mut engine = Engine::new(rx, 48000.0);
+            tx.send(Message::VelocityCurve { track: 3, curve }).unwrap();
+            tx.send(Message::NoteOn {
+                id: 1,
+                track: 3,
+                pitch: 60,
+                velocity,
+                expression: NoteExpression::default(),
+                frame: 0,
+            })
+            .unwrap();
+            let mut buf_l = vec![0.0; 4800];
+            let mut buf_r = vec![0.0; 4800];
+            engine.process(&mut buf_l, &mut buf_r, 0, 120.0, 4800);
+            buf_l
+        };
+
+        // a fixed curve makes the track velocity-insensitive
+        let loud = render(100, VelocityCurve::Linear);
+        assert_eq!(render(20, VelocityCurve::Fixed(100)), loud);
+        assert_ne!(render(20, VelocityCurve::Linear), loud);
+    }
+
+    #[test]
+    fn global_lfos_follow_the_transport() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::LatencyCompensation(false)).unwrap();
+        engine.set_sound(1, VoiceType::Subtractive);
+        engine.sequencer.set_loop_markers(0.0, 2.0);
+        tx.send(Message::ParameterSmoothing {
+            smoothing_type: SmoothingType::Linear,
+            time_ms: 0.0,
+        })
+        .unwrap();
+        let target = LfoTarget::Track {
+            track: 1,
+            parameter: 0,
+        };
+        let lfo = GlobalLfo::new(LfoShape::RampUp, 4.0, 1000.0, 5000.0, target);
+        tx.send(Message::GlobalLfo {
+            index: 0,
+            lfo: Some(lfo),
+        })
+        .unwrap();
+
+        // a beat at 120 bpm is 24000 samples
+        let (mut buf_l, mut buf_r) = (vec![0.0; 24000], vec![0.0; 24000]);
+        engine.set_playing(true);
+        let mut cutoffs = Vec::new();
+        for block in 0..4 {
+            engine.process(&mut buf_l, &mut buf_r, block * 24000, 120.0, 24000);
+            cutoffs.push(engine.voices[1].get_parameter(0));
+        }
+        // a quarter of the cycle per beat, restarting with the two beat loop
+        assert_eq!(cutoffs, [1000.0, 2000.0, 1000.0, 2000.0]);
+    }
+
+    #[test]
+    fn callbacks_report_frames_and_beats() {
+        use std::ffi::c_void;
+        use std::sync::Mutex;
+
+        extern "C" fn record_note(
+            context: *mut c_void,
+            note_on: bool,
+            _: u8,
+            _: u8,
+            frame: u32,
+            beats: f32,
+        ) {
+            let notes = unsafe { &*(context as *const Mutex<Vec<(bool, u32, f32)>>) };
+            notes.lock().unwrap().push((note_on, frame, beats));
+        }
+        extern "C" fn record_transport(context: *mut c_void, is_playing: bool, _: f32) {
+            let changes = unsafe { &*(context as *const Mutex<Vec<bool>>) };
+            changes.lock().unwrap().push(is_playing);
+        }
+
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let notes: Mutex<Vec<(bool, u32, f32)>> = Mutex::new(Vec::new());
+        let changes: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+        let shared = engine.shared().clone();
+        shared.set_note_callback(record_note, &notes as *const _ as *mut c_void);
+        shared.set_transport_callback(record_transport, &changes as *const _ as *mut c_void);
+        tx.send(Message::LatencyCompensation(false)).unwrap();
+        engine.sequencer.add_event(Event {
+            beat_time: 1.25,
+            duration: 0.25,
+            ..Default::default()
+        });
+
+        // a beat at 120 bpm is 24000 samples
+        let (mut buf_l, mut buf_r) = (vec![0.0; 24000], vec![0.0; 24000]);
+        engine.set_playing(true);
+        engine.set_playing(true);
+        for block in 0..2 {
+            engine.process(&mut buf_l, &mut buf_r, block * 24000, 120.0, 24000);
+        }
+        engine.set_playing(false);
+        assert_eq!(*changes.lock().unwrap(), [true, false]);
+        assert_eq!(
+            *notes.lock().unwrap(),
+            [(true, 6000, 1.25), (false, 12000, 1.5)]
+        );
+    }
+
+    #[test]
+    fn macros_set_their_parameters() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.set_sound(2, VoiceType::Subtractive);
+        tx.send(Message::ParameterSmoothing {
+            smoothing_type: SmoothingType::Linear,
+            time_ms: 0.0,
+        })
+        .unwrap();
+        let destinations = [(0, 200.0, 2000.0), (1, 10.0, 1.0)];
+        for (slot, (parameter, min, max)) in destinations.into_iter().enumerate() {
+            tx.send(Message::MacroAssign {
+                track: 2,
+                index: 1,
+                slot: slot as u8,
+                destination: Some(MacroDestination {
+                    parameter,
+                    min,
+                    max,
+                    curve: MacroCurve::Linear,
+                }),
+            })
+            .unwrap();
+        }
+        tx.send(Message::Macro {
+            track: 2,
+            index: 1,
+            value: 0.5,
+        })
+        .unwrap();
+        // out of range macros are ignored
+        tx.send(Message::Macro {
+            track: 2,
+            index: MACRO_COUNT as u8,
+            value: 1.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[2].get_parameter(0), 1100.0);
+        assert_eq!(engine.voices[2].get_parameter(1), 5.5);
+        // other tracks' macros are untouched
+        assert_eq!(engine.macros[3][1].value(), 0.0);
+    }
+
+    #[test]
+    fn chord_mode_keeps_track_settings() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.set_sound(3, VoiceType::Subtractive);
+        engine.set_track_parameter(3, 4, 0.25);
+        tx.send(Message::Chord {
+            track: 3,
+            chord: Some(Chord::new(ChordType::Minor, 0, 0)),
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[3].get_parameter(4), 0.25);
+
+        // the played note sounds with the others of the chord
+        let mut mono = create_voice(VoiceType::Subtractive, 48000.0);
+        mono.set_parameter(4, 0.25);
+        mono.play(60, 100, 0.0, 0.0);
+        engine.handle_midi(
+            MidiMessage::NoteOn {
+                channel: 3,
+                pitch: 60,
+                velocity: 100,
+            },
+            0,
+        );
+        assert_eq!(engine.voices[3].get_pitch(), 60);
+        let chord_energy: f32 = (0..4800).map(|_| engine.voices[3].process().abs()).sum();
+        let note_energy: f32 = (0..4800).map(|_| mono.process().abs()).sum();
+        assert!(chord_energy > note_energy * 1.5);
+
+        // switching voices stays in chord mode, clearing it leaves the settings
+        engine.set_sound(3, VoiceType::Fm);
+        engine.set_track_parameter(3, 4, 0.75);
+        tx.send(Message::Chord {
+            track: 3,
+            chord: None,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voices[3].get_parameter(4), 0.75);
+        assert_eq!(
+            engine.voices[3].parameter_count(),
+            crate::plaits_voice::PARAMETER_COUNT
+        );
+    }
+
+    #[test]
+    fn midi_routes_to_channel_track() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+
+        engine.handle_midi(
+            MidiMessage::NoteOn {
+                channel: 2,
+                pitch: 60,
+                velocity: 100,
+            },
+            0,
+        );
+        assert!(engine.voices[2].is_active());
+        assert!(!engine.voices[0].is_active());
+
+        engine.handle_midi(
+            MidiMessage::ControlChange {
+                channel: 3,
+                controller: CC_PARAMETER_OFFSET + 4,
+                value: 127,
+            },
+            0,
+        );
+        assert_eq!(engine.voices[3].get_parameter(4), 1.0);
+
+        engine.handle_midi(
+            MidiMessage::PitchBend {
+                channel: 1,
+                bend: 1.0,
+            },
+            0,
+        );
+        assert!((engine.pitch_bend_ratio(1) - (2f32.powf(2.0 / 12.0) - 1.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn count_in_delays_sequence_start() {
+        let (_, rx) = channel::unbounded();
+        let sample_rate = 48000.0;
+        let tempo = 120.0;
+        let mut engine = Engine::new(rx, sample_rate);
+        engine.count_in_bars = 1;
+        engine.sequencer.add_event(Event {
+            beat_time: 0.0,
+            track: 0,
+            ..Default::default()
+        });
+        engine.set_playing(true);
+
+        // one bar of 4 beats at 120 bpm is 2 seconds
+        let count_in = 2 * sample_rate as usize;
+        let block = 500;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        let mut start = 0;
+        // the sequence is rendered ahead by the latency
+        while start + block + engine.latency() <= count_in {
+            engine.process(&mut buf_l, &mut buf_r, start as i64, tempo, block as i32);
+            assert!(!engine.voices[0].is_active());
+            start += block;
+        }
+        // the sequence starts with the buffer reaching the end of the count-in
+        engine.process(&mut buf_l, &mut buf_r, start as i64, tempo, block as i32);
+        assert!(engine.count_in_end.is_none());
+        assert!(engine.voices[0].is_active());
+    }
+
+    #[test]
+    fn sequence_is_rendered_ahead_by_the_latency() {
+        let onset = |compensation: bool| {
+            let (tx, rx) = channel::unbounded();
+            let mut engine = Engine::new(rx, 48000.0);
+            tx.send(Message::LatencyCompensation(compensation)).unwrap();
+            engine.sequencer.add_event(Event {
+                beat_time: 1.0,
+                track: 0,
+                ..Default::default()
+            });
+            engine.set_playing(true);
+            let mut output = vec![];
+            let mut buf_l = vec![0.0; 500];
+            let mut buf_r = vec![0.0; 500];
+            for start in (0..30000).step_by(500) {
+                engine.process(&mut buf_l, &mut buf_r, start, 120.0, 500);
+                output.extend_from_slice(&buf_l);
+            }
+            (
+                output.iter().position(|&y| y != 0.0).unwrap(),
+                engine.latency(),
+            )
+        };
+
+        // beat 1 at 120 bpm is half a second in
+        let (compensated, _) = onset(true);
+        assert!(compensated.abs_diff(24000) <= 1);
+        let (late, latency) = onset(false);
+        assert!(late.abs_diff(24000 + latency) <= 1);
+    }
+
+    #[test]
+    fn event_params_reach_the_voice() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.set_sound(2, VoiceType::Subtractive);
+        engine.sequencer.add_event(Event {
+            beat_time: 0.0,
+            track: 2,
+            param1: 0.5,
+            param2: 0.1,
+            ..Default::default()
+        });
+        engine.set_playing(true);
+
+        let block = 512;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, block as i32);
+        assert!(engine.voices[2].is_active());
+        // the subtractive voice maps them onto cutoff and resonance
+        assert!((engine.voices[2].get_parameter(0) - 5000.0).abs() < 1e-2);
+        assert!((engine.voices[2].get_parameter(1) - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn parameter_locks_hold_for_one_step() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        engine.set_track_parameter(0, 4, 0.5);
+        let mut locks = ParameterLocks::default();
+        locks.set(4, 1.0);
+        locks.set(EQ_PARAMETER_OFFSET + 2, -6.0);
+        engine.sequencer.add_event(Event {
+            beat_time: 0.0,
+            duration: 0.25,
+            locks,
+            ..Default::default()
+        });
+        engine.sequencer.add_event(Event {
+            beat_time: 1.0,
+            duration: 0.25,
+            ..Default::default()
+        });
+        engine.set_playing(true);
+
+        // one beat at 120 bpm is 24000 samples
+        let block = 500;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, block as i32);
+        assert_eq!(engine.voices[0].get_parameter(4), 1.0);
+        assert_eq!(engine.eqs[0].get_parameter(2), -6.0);
+        // presets keep the unlocked values
+        let preset = engine.capture_preset();
+        assert!(preset.tracks[0].parameters.contains(&(4, 0.5)));
+
+        // the next trig restores them
+        let mut start = block;
+        while start < 24000 + block {
+            engine.process(&mut buf_l, &mut buf_r, start as i64, 120.0, block as i32);
+            start += block;
+        }
+        assert_eq!(engine.voices[0].get_parameter(4), 0.5);
+        assert_eq!(engine.eqs[0].get_parameter(2), 0.0);
+        assert!(engine.locked_parameters[0].is_empty());
+    }
+
+    #[test]
+    fn patterns_mutate_every_few_bars() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        for i in 0..8 {
+            engine.sequencer.add_event(Event {
+                id: i,
+                beat_time: i as f32 * 0.5,
+                duration: 0.25,
+                ..Default::default()
+            });
+        }
+        let original = engine.sequencer.events().to_vec();
+        engine.sequencer.set_mutation(Mutation {
+            amount: 1.0,
+            seed: Some(3),
+            every_bars: 2,
+        });
+        engine.set_playing(true);
+
+        // one bar of 4/4 at 120 bpm is 96000 samples
+        let block = 1000;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        let mut start = 0;
+        while start <= 96000 {
+            engine.process(&mut buf_l, &mut buf_r, start as i64, 120.0, block as i32);
+            start += block;
+        }
+        assert_eq!(engine.sequencer.events(), &original[..]);
+        while start <= 2 * 96000 {
+            engine.process(&mut buf_l, &mut buf_r, start as i64, 120.0, block as i32);
+            start += block;
+        }
+        assert_ne!(engine.sequencer.events(), &original[..]);
+    }
+
+    #[test]
+    fn records_live_notes_while_playing() {
+        let (tx, rx) = channel::unbounded();
+        let sample_rate = 48000.0;
+        let tempo = 120.0;
+        let block = 512;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        let mut engine = Engine::new(rx, sample_rate);
+        engine.set_playing(true);
+        tx.send(Message::Recording(true)).unwrap();
+        tx.send(Message::RecordQuantize(0.25)).unwrap();
+        engine.process(&mut buf_l, &mut buf_r, 0, tempo, block as i32);
+
+        // a note played just after beat 1 is quantized onto it
+        let note_on_time = 24000 + 1000;
+        tx.send(Message::NoteOn {
+            id: 1,
+            track: 2,
+            pitch: 64,
+            velocity: 90,
+            expression: NoteExpression::default(),
+            frame: 0,
+        })
+        .unwrap();
+        engine.process(&mut buf_l, &mut buf_r, note_on_time, tempo, block as i32);
+        tx.send(Message::NoteOff {
+            track: 2,
+            pitch: 64,
+            frame: 0,
+        })
+        .unwrap();
+        engine.process(
+            &mut buf_l,
+            &mut buf_r,
+            note_on_time + 12000,
+            tempo,
+            block as i32,
+        );
+
+        let events = engine.sequencer.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].beat_time, 1.0);
+        assert_eq!(events[0].duration, 0.5);
+        assert_eq!(
+            (events[0].track, events[0].pitch, events[0].velocity),
+            (2, 64, 90)
+        );
+    }
+
+    #[test]
+    fn note_offs_release_held_notes() {
+        let (_, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        // the organ holds its notes until they're stopped
+        engine.set_sound(0, VoiceType::Organ);
+        let note_on = |id| Message::NoteOn {
+            id,
+            track: 0,
+            pitch: 60,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: 0,
+        };
+        let note_off = || Message::NoteOff {
+            track: 0,
+            pitch: 60,
+            frame: 0,
+        };
+        let (mut left, mut right) = (vec![0.0; 4800], vec![0.0; 4800]);
+        let mut sounds = |engine: &mut Engine| {
+            engine.process(&mut left, &mut right, 0, 120.0, 4800);
+            engine.voices[0].is_active()
+        };
+
+        // an overlapping note ends the one before, so one note off releases it
+        engine.handle_msg(note_on(1), 0);
+        engine.handle_msg(note_on(2), 0);
+        assert!(sounds(&mut engine));
+        engine.handle_msg(note_off(), 0);
+        assert_eq!(engine.held_notes[0], 0);
+        assert!(!sounds(&mut engine));
+
+        // a note whose note off never comes
+        engine.handle_msg(note_on(3), 0);
+        assert!(sounds(&mut engine));
+        engine.handle_msg(Message::AllNotesOff, 0);
+        assert!(engine.live_notes.is_empty());
+        assert!(!sounds(&mut engine));
+    }
+
+    #[test]
+    fn stopping_follows_the_stop_behavior() {
+        // peak level of a block rendered at `time`
+        let render = |engine: &mut Engine, time: i64, frames: usize| {
+            let (mut left, mut right) = (vec![0.0; frames], vec![0.0; frames]);
+            engine.process(&mut left, &mut right, time, 120.0, frames as i32);
+            left.iter().fold(0.0f32, |peak, y| peak.max(y.abs()))
+        };
+        // stopped two beats into a held three beat note; a beat is 24000
+        // samples
+        let stopped = |mode, rewind| {
+            let (tx, rx) = channel::unbounded();
+            let mut engine = Engine::new(rx, 48000.0);
+            engine.set_sound(0, VoiceType::Organ);
+            engine.sequencer.add_event(Event {
+                duration: 3.0,
+                ..Default::default()
+            });
+            tx.send(Message::LatencyCompensation(false)).unwrap();
+            tx.send(Message::StopBehavior { mode, rewind }).unwrap();
+            engine.set_playing(true);
+            render(&mut engine, 0, 24000);
+            render(&mut engine, 24000, 24000);
+            engine.set_playing(false);
+            engine
+        };
+
+        let mut engine = stopped(StopMode::Release, false);
+        assert_eq!(engine.held_notes[0], 0);
+        // resumes where it stopped
+        engine.set_playing(true);
+        render(&mut engine, 48000, 64);
+        assert_eq!(engine.sequencer.position_beats(), 1.0);
+
+        let mut engine = stopped(StopMode::Finish, true);
+        render(&mut engine, 48000, 12000);
+        assert_ne!(engine.held_notes[0], 0);
+        render(&mut engine, 60000, 24000);
+        assert_eq!(engine.held_notes[0], 0);
+        engine.set_playing(true);
+        render(&mut engine, 84000, 64);
+        assert_eq!(engine.sequencer.position_beats(), 0.0);
+
+        let mut engine = stopped(StopMode::Flush, false);
+        assert!(render(&mut engine, 48000, 480) > 0.0);
+        render(&mut engine, 48480, 480);
+        assert!(engine.silenced[0]);
+        assert!(render(&mut engine, 48960, 4800) < 1e-3);
+    }
+
+    #[test]
+    fn timestamped_notes_start_at_their_frame() {
+        let (tx, rx) = channel::unbounded();
+        let block = 512;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::NoteOn {
+            id: 1,
+            track: 0,
+            pitch: 60,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: 100,
+        })
+        .unwrap();
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, block as i32);
+        assert!(buf_l[..100].iter().all(|&y| y == 0.0));
+        assert!(buf_l[100..].iter().any(|&y| y != 0.0));
+
+        // an offset past the block carries over into the next one
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::NoteOn {
+            id: 2,
+            track: 0,
+            pitch: 60,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: block as u32 + 88,
+        })
+        .unwrap();
+        engine.process(&mut buf_l, &mut buf_r, 0, 120.0, block as i32);
+        assert!(buf_l.iter().all(|&y| y == 0.0));
+        engine.process(&mut buf_l, &mut buf_r, block as i64, 120.0, block as i32);
+        assert!(buf_l[..88].iter().all(|&y| y == 0.0));
+        assert!(buf_l[88..].iter().any(|&y| y != 0.0));
+    }
+
+    #[test]
+    fn queues_keep_their_capacity() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let pending = engine.pending.capacity();
+        let live_notes = engine.live_notes.capacity();
+        for id in 0..PENDING_CAPACITY as u32 * 2 {
+            for frame in [0, 100] {
+                tx.send(Message::NoteOn {
+                    id,
+                    track: 0,
+                    pitch: 60,
+                    velocity: 100,
+                    expression: NoteExpression::default(),
+                    frame,
+                })
+                .unwrap();
+            }
+        }
+        engine.get_msgs();
+        assert_eq!(engine.pending.len(), PENDING_CAPACITY);
+        assert_eq!(engine.pending.capacity(), pending);
+        assert_eq!(engine.live_notes.len(), LIVE_NOTE_CAPACITY);
+        assert_eq!(engine.live_notes.capacity(), live_notes);
+    }
+
+    #[test]
+    fn playing_pattern_is_published() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let (mut left, mut right) = (vec![0.0; 4800], vec![0.0; 4800]);
+        tx.send(Message::CreatePattern {
+            name: "B".to_string(),
+            length: 4.0,
+        })
+        .unwrap();
+        tx.send(Message::QueuePattern(1)).unwrap();
+        engine.set_playing(true);
+        // a 4 beat pattern at 120 BPM is 96000 samples
+        for block in 0..20 {
+            engine.process(&mut left, &mut right, block * 4800, 120.0, 4800);
+            assert_eq!(engine.shared.current_pattern(), 0);
+        }
+        engine.process(&mut left, &mut right, 96000, 120.0, 4800);
+        assert_eq!(engine.shared.current_pattern(), 1);
+    }
+
+    #[test]
+    fn one_shot_stops_at_loop_end() {
+        let (tx, rx) = channel::unbounded();
+        let sample_rate = 48000.0;
+        let tempo = 120.0;
+        let block = 1000;
+        let mut buf_l = vec![0.0; block];
+        let mut buf_r = vec![0.0; block];
+        let mut engine = Engine::new(rx, sample_rate);
+        tx.send(Message::OneShot(true)).unwrap();
+        tx.send(Message::SetPosition(2.5)).unwrap();
+        engine.set_playing(true);
+
+        engine.process(&mut buf_l, &mut buf_r, 0, tempo, block as i32);
+        assert_eq!((engine.current_bar(), engine.current_beat()), (0, 2));
+        assert_eq!(engine.shared.bar_and_beat(), (0, 2));
+
+        // 1.5 beats remain in the 4 beat pattern, which is 36000 samples
+        let mut start = block;
+        while start < 36000 + block {
+            engine.process(&mut buf_l, &mut buf_r, start as i64, tempo, block as i32);
+            start += block;
+        }
+        assert!(!engine.is_playing);
+    }
+
+    #[test]
+    fn note_expression_follows_note_id() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::NoteOn {
+            id: 7,
+            track: 3,
+            pitch: 60,
+            velocity: 100,
+            expression: NoteExpression {
+                pressure: 0.25,
+                ..Default::default()
+            },
+            frame: 0,
+        })
+        .unwrap();
+        tx.send(Message::NoteExpression {
+            id: 7,
+            dimension: ExpressionDimension::PitchBend,
+            value: 12.0,
+        })
+        .unwrap();
+        // unknown notes are ignored
+        tx.send(Message::NoteExpression {
+            id: 8,
+            dimension: ExpressionDimension::Timbre,
+            value: 1.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+
+        let expression = engine.voices[3].expression();
+        assert_eq!(expression.pitch_bend, 12.0);
+        assert_eq!(expression.pressure, 0.25);
+        assert_eq!(expression.timbre, 0.0);
+
+        tx.send(Message::NoteOff {
+            track: 3,
+            pitch: 60,
+            frame: 0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert!(engine.live_notes.is_empty());
+    }
+
+    #[test]
+    fn pitch_bend_range_is_per_track() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::PitchBendRange {
+            track: 1,
+            semitones: 12.0,
+        })
+        .unwrap();
+        tx.send(Message::PitchBend {
+            track: 1,
+            bend: 1.0,
+        })
+        .unwrap();
+        tx.send(Message::PitchBend {
+            track: 0,
+            bend: 1.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert!((engine.pitch_bend_ratio(1) - 1.0).abs() < 1e-6);
+        assert!((engine.pitch_bend_ratio(0) - (2f32.powf(2.0 / 12.0) - 1.0)).abs() < 1e-6);
+
+        // changing the range rescales the current bend
+        tx.send(Message::PitchBendRange {
+            track: 1,
+            semitones: 0.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.pitch_bend_ratio(1), 0.0);
+    }
+
+    #[test]
+    fn set_sound_switches_track_voice() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::SetSound {
+            track: 2,
+            voice_type: VoiceType::Subtractive,
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voice_types[2], VoiceType::Subtractive);
+        assert_eq!(engine.voices[2].parameter_count(), 14);
+        assert_eq!(
+            engine.voices[0].parameter_count(),
+            crate::plaits_voice::PARAMETER_COUNT
+        );
+
+        // the voice type is part of a preset
+        let preset = engine.capture_preset();
+        let (_, rx) = channel::unbounded();
+        let mut other = Engine::new(rx, 48000.0);
+        other.apply_preset(&preset);
+        assert_eq!(other.voice_types[2], VoiceType::Subtractive);
+    }
+
+    #[test]
+    fn set_sound_keeps_the_retrigger_mode() {
+        // a held note retriggered half way, with the mode set before or
+        // after switching the sound
+        let render = |mode, mode_first| {
+            let (tx, rx) = channel::unbounded();
+            let mut engine = Engine::new(rx, 48000.0);
+            let mode = Message::RetriggerMode { track: 0, mode };
+            let sound = Message::SetSound {
+                track: 0,
+                voice_type: VoiceType::Subtractive,
+            };
+            let (first, second) = if mode_first {
+                (mode, sound)
+            } else {
+                (sound, mode)
+            };
+            tx.send(first).unwrap();
+            tx.send(second).unwrap();
+            for (id, frame) in [(1, 0), (2, 500)] {
+                tx.send(Message::NoteOn {
+                    id,
+                    track: 0,
+                    pitch: 60,
+                    velocity: 127,
+                    expression: NoteExpression::default(),
+                    frame,
+                })
+                .unwrap();
+            }
+            let (mut left, mut right) = (vec![0.0; 1000], vec![0.0; 1000]);
+            engine.process(&mut left, &mut right, 0, 120.0, 1000);
+            left
+        };
+        let legato = render(RetriggerMode::Legato, false);
+        assert_eq!(render(RetriggerMode::Legato, true), legato);
+        assert_ne!(render(RetriggerMode::Reset, false), legato);
+    }
+
+    #[test]
+    fn load_sample_switches_track_to_sampler() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let sample = Sample::new(vec![0.5; 100], 48000.0);
+        tx.send(Message::LoadSample {
+            track: 4,
+            sample: Arc::new(sample),
+        })
+        .unwrap();
+        engine.get_msgs();
+        assert_eq!(engine.voice_types[4], VoiceType::Sampler);
+
+        engine.voices[4].play(60, 127, 0.0, 0.0);
+        assert_eq!(engine.voices[4].process(), 0.5);
+    }
+
+    #[test]
+    fn retriggers_are_declicked() {
+        // largest step between samples with a ramp retriggered half way
+        let largest_step = |declick_ms| {
+            let (tx, rx) = channel::unbounded();
+            let mut engine = Engine::new(rx, 48000.0);
+            let ramp = (0..1000).map(|i| i as f32 / 1000.0).collect();
+            let sample = Arc::new(Sample::new(ramp, 48000.0));
+            tx.send(Message::LoadSample { track: 0, sample }).unwrap();
+            tx.send(Message::DeclickTime(declick_ms)).unwrap();
+            for (id, frame) in [(1, 0), (2, 500)] {
+                tx.send(Message::NoteOn {
+                    id,
+                    track: 0,
+                    pitch: 60,
+                    velocity: 127,
+                    expression: NoteExpression::default(),
+                    frame,
+                })
+                .unwrap();
+            }
+            // stopping before the retriggered ramp ends and jumps to silence
+            let mut output = Vec::new();
+            for block in 0..2 {
+                let (mut left, mut right) = (vec![0.0; 600], vec![0.0; 600]);
+                engine.process(&mut left, &mut right, block * 600, 120.0, 600);
+                output.extend(left);
+            }
+            output
+                .windows(2)
+                .map(|w| (w[1] - w[0]).abs())
+                .fold(0.0, f32::max)
+        };
+        let clicking = largest_step(0.0);
+        let declicked = largest_step(2.0);
+        assert!(declicked * 10.0 < clicking, "{declicked} {clicking}");
+    }
+
+    #[test]
+    fn tracks_play_on_their_outputs() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        tx.send(Message::TrackOutput {
+            track: 1,
+            output: 2,
+        })
+        .unwrap();
+        tx.send(Message::NoteOn {
+            id: 1,
+            track: 1,
+            pitch: 60,
+            velocity: 100,
+            expression: NoteExpression::default(),
+            frame: 0,
+        })
+        .unwrap();
+        let is_silent = |buffer: &[f32]| buffer.iter().all(|&y| y == 0.0);
+
+        let mut channels = vec![vec![0.0; 512]; 6];
+        let [main_l, main_r, aux_l, aux_r, out_l, out_r] = &mut channels[..] else {
+            unreachable!()
+        };
+        let mut buffers = [
+            [&mut main_l[..], &mut main_r[..]],
+            [&mut aux_l[..], &mut aux_r[..]],
+            [&mut out_l[..], &mut out_r[..]],
+        ];
+        engine.process_outputs(None, &mut buffers, 0, 120.0, 512);
+        assert!(is_silent(&channels[0]) && is_silent(&channels[2]));
+        assert!(!is_silent(&channels[4]) && !is_silent(&channels[5]));
+
+        // without its output the track plays on the main one
+        let (mut left, mut right) = (vec![0.0; 512], vec![0.0; 512]);
+        engine.process(&mut left, &mut right, 512, 120.0, 512);
+        assert!(!is_silent(&left));
+    }
+
+    #[test]
+    fn hosts_at_other_rates_hear_the_same_pitch() {
+        // rising zero crossings in the first 100 ms of a note
+        let crossings = |host_rate: f32, block: usize| {
+            let (tx, rx) = channel::unbounded();
+            let mut engine = Engine::new(rx, 48000.0);
+            engine.set_host_rate(host_rate);
+            tx.send(Message::NoteOn {
+                id: 1,
+                track: 0,
+                pitch: 69,
+                velocity: 100,
+                expression: NoteExpression::default(),
+                frame: 0,
+            })
+            .unwrap();
+            let length = (host_rate * 0.1) as usize;
+            let (mut left, mut right) = (vec![0.0; length], vec![0.0; length]);
+            for start in (0..length).step_by(block) {
+                let frames = block.min(length - start);
+                engine.process(
+                    &mut left[start..start + frames],
+                    &mut right[start..start + frames],
+                    start as i64,
+                    120.0,
+                    frames as i32,
+                );
+            }
+            left.windows(2)
+                .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+                .count()
+        };
+        let native = crossings(48000.0, 512);
+        assert!(native > 20);
+        // a crossing either way at the ends of the window
+        assert!(crossings(44100.0, 441).abs_diff(native) <= 2);
+        assert!(crossings(96000.0, 333).abs_diff(native) <= 2);
+    }
+
+    #[test]
+    fn notes_are_transposed_and_tuned() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let sample = Sample::new((0..100).map(|i| i as f32 / 100.0).collect(), 48000.0);
+        tx.send(Message::LoadSample {
+            track: 4,
+            sample: Arc::new(sample),
+        })
+        .unwrap();
+        tx.send(Message::TrackTuning {
+            track: 4,
+            transpose: 12,
+            cents: 0.0,
+        })
+        .unwrap();
+        engine.get_msgs();
+        // linear reads, so the ramp shows the playback rate
+        engine.voices[4].set_parameter(6, 0.0);
+        let second_sample = |engine: &mut Engine| {
+            engine.play_note(4, 60, 127, 0.0, 0.0);
+            engine.voices[4].process();
+            engine.voices[4].process()
+        };
+        assert_eq!(second_sample(&mut engine), 0.02);
+
+        tx.send(Message::TrackTuning {
+            track: 4,
+            transpose: 0,
+            cents: 50.0,
+        })
+        .unwrap();
+        tx.send(Message::MasterTune(50.0)).unwrap();
+        engine.get_msgs();
+        let semitone = 2f32.powf(1.0 / 12.0);
+        assert!((second_sample(&mut engine) - 0.01 * semitone).abs() < 1e-6);
+    }
+
+    #[test]
+    fn slices_reach_every_voice_of_a_sampler() {
+        let (tx, rx) = channel::unbounded();
+        let mut engine = Engine::new(rx, 48000.0);
+        let sample = Sample::new((0..100).map(|i| i as f32 / 100.0).collect(), 48000.0);
+        tx.send(Message::LoadSample {
+            track: 2,
+            sample: Arc::new(sample),
+        })
+        .unwrap();
+        tx.send(Message::SampleSlices {
+            track: 2,
+            slices: Arc::from(equal_slices(4)),
+        })
+        .unwrap();
+        engine.get_msgs();
+        // published for the host
+        let mut points = [0.0; 8];
+        assert_eq!(engine.shared().sample_slices(2, &mut points), 4);
+        assert_eq!(points[..4], [0.0, 0.25, 0.5, 0.75]);
+        assert!(engine.shared().sample(2).is_some());
+        assert_eq!(engine.shared().sample_slices(3, &mut points), 0);
+        assert!(engine.shared().sample(3).is_none());
+
+        // two above the root plays the third slice
+        engine.voices[2].set_parameter(9, 1.0);
+        engine.voices[2].play(62, 127, 0.0, 0.0);
+        assert_eq!(engine.voices[2].process(), 0.5);
+    }
+}
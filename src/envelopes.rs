@@ -10,10 +10,66 @@ pub enum EnvelopeState {
 #[derive(Debug, Clone, Copy)]
 pub enum CurveType {
     Linear,
-    Exponential { pow: i8 },
+    Exponential {
+        pow: i8,
+    },
+    /// RC-style segments, charging towards a target past their end like an
+    /// analog envelope's capacitor. Each stage has its own shape, from 0.0,
+    /// nearly linear, to 1.0, sharply curved.
+    Analog {
+        attack: f32,
+        decay: f32,
+    },
     // Logarithmic,
 }
 
+/// What a trigger does to an envelope that's still sounding
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RetriggerMode {
+    /// restart the attack from silence
+    #[default]
+    Reset,
+    /// attack from the level reached, so the restart doesn't click
+    Continue,
+    /// keep going, only triggering once the envelope has finished
+    Legato,
+}
+
+impl RetriggerMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(RetriggerMode::Reset),
+            1 => Some(RetriggerMode::Continue),
+            2 => Some(RetriggerMode::Legato),
+            _ => None,
+        }
+    }
+}
+
+// the target an analog segment charges towards lies this far past its end,
+// as a fraction of the segment: a distant target makes for a nearly
+// straight segment, a close one for a sharp curve
+fn analog_overshoot(shape: f32) -> f32 {
+    (10f32).powf(2.0 - 6.0 * shape.clamp(0.0, 1.0))
+}
+
+/// Per-sample coefficient and offset of a one-pole segment covering
+/// `length` samples, rising from 0.0 to 1.0 or falling from 1.0 to 0.0
+fn analog_segment(length: f32, shape: f32, rising: bool) -> (f32, f32) {
+    let overshoot = analog_overshoot(shape);
+    let coef = if length > 0.0 {
+        (-((1.0 + overshoot) / overshoot).ln() / length).exp()
+    } else {
+        0.0
+    };
+    let offset = if rising {
+        (1.0 + overshoot) * (1.0 - coef)
+    } else {
+        -overshoot * (1.0 - coef)
+    };
+    (coef, offset)
+}
+
 /*
     Attack/Release envelope
 */
@@ -22,10 +78,15 @@ pub struct AR {
     pub attack_ms: f32,
     pub decay_ms: f32,
     pub state: EnvelopeState,
+    pub retrigger: RetriggerMode,
     value: f32,
     time: f32,
     velocity: f32,
     curve_type: CurveType,
+    // level before velocity, and the current segment's coefficient and
+    // offset, of analog curves
+    level: f32,
+    segment: (f32, f32),
     sample_rate: f32,
 }
 
@@ -38,26 +99,63 @@ impl AR {
             time: 0.0,
             velocity: 1.0,
             state: EnvelopeState::Off,
+            retrigger: RetriggerMode::Reset,
             curve_type,
+            level: 0.0,
+            segment: (0.0, 0.0),
             sample_rate,
         };
 
         ar
     }
 
+    pub fn set_curve_type(&mut self, curve_type: CurveType) {
+        self.curve_type = curve_type;
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
     pub fn trigger(&mut self, velocity: u8) {
-        self.reset();
-        self.velocity = velocity as f32 / 127.0;
+        let velocity = velocity as f32 / 127.0;
+        let length = self.attack_ms * (self.sample_rate / 1000.0);
+        match self.retrigger {
+            _ if !self.is_active() => self.reset(),
+            RetriggerMode::Reset => self.reset(),
+            RetriggerMode::Continue => {
+                // pick the attack up where its curve passes the level reached
+                self.level = (self.value / velocity.max(f32::EPSILON)).min(1.0);
+                self.time = match self.curve_type {
+                    CurveType::Linear => self.level * length,
+                    CurveType::Exponential { pow } => self.level.powf(1.0 / pow as f32) * length,
+                    CurveType::Analog { .. } => 0.0,
+                };
+            }
+            RetriggerMode::Legato => return,
+        }
+        self.velocity = velocity;
         self.state = EnvelopeState::Attack;
+        if let CurveType::Analog { attack, .. } = self.curve_type {
+            self.segment = analog_segment(length, attack, true);
+        }
     }
 
     pub fn decay(&mut self) {
         self.state = EnvelopeState::Decay;
+        if let CurveType::Analog { decay, .. } = self.curve_type {
+            // falls from wherever the attack got to
+            let length = self.decay_ms * (self.sample_rate / 1000.0);
+            self.segment = analog_segment(length, decay, false);
+        }
     }
 
     #[inline]
     pub fn process(&mut self) -> f32 {
         use EnvelopeState as E;
+        if let CurveType::Analog { .. } = self.curve_type {
+            return self.process_analog();
+        }
         match self.state {
             E::Attack => {
                 let length = self.attack_ms * (self.sample_rate / 1000.0);
@@ -91,10 +189,35 @@ impl AR {
         self.value
     }
 
+    #[inline]
+    fn process_analog(&mut self) -> f32 {
+        let (coef, offset) = self.segment;
+        match self.state {
+            EnvelopeState::Attack => {
+                self.level = offset + self.level * coef;
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.decay();
+                }
+            }
+            EnvelopeState::Decay => {
+                self.level = offset + self.level * coef;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.state = EnvelopeState::Off;
+                }
+            }
+            EnvelopeState::Off => {}
+        }
+        self.value = self.level * self.velocity;
+        self.value
+    }
+
     fn get_curve(&self, length: f32) -> f32 {
         match self.curve_type {
             CurveType::Linear => lerp(self.time, length),
             CurveType::Exponential { pow } => xerp(self.time, length, pow),
+            CurveType::Analog { .. } => unreachable!(),
         }
     }
 
@@ -102,6 +225,7 @@ impl AR {
         match self.curve_type {
             CurveType::Linear => 1.0 - lerp(self.time, length),
             CurveType::Exponential { pow } => xerp(length - self.time, length, pow),
+            CurveType::Analog { .. } => unreachable!(),
         }
     }
 
@@ -116,6 +240,252 @@ impl AR {
     fn reset(&mut self) {
         self.time = 0.0;
         self.value = 0.0;
+        self.level = 0.0;
+    }
+}
+
+/// most breakpoints a multi-stage envelope holds
+pub const MAX_STAGES: usize = 8;
+
+/// One segment of a multi-stage envelope: a move from the level the
+/// previous stage ended at to `level`, over `time_ms`
+#[derive(Debug, Clone, Copy)]
+pub struct Stage {
+    pub time_ms: f32,
+    pub level: f32,
+    pub curve: CurveType,
+}
+
+impl Stage {
+    pub fn new(time_ms: f32, level: f32, curve: CurveType) -> Self {
+        Self {
+            time_ms,
+            level,
+            curve,
+        }
+    }
+
+    /// Position between the segment's start and end levels, `x` 0.0..1.0
+    /// through it. Like AR's curves, exponential segments rise slowly and
+    /// fall quickly.
+    fn shape(&self, x: f32, rising: bool) -> f32 {
+        match self.curve {
+            CurveType::Linear => x,
+            CurveType::Exponential { pow } if rising => x.powf(pow as f32),
+            CurveType::Exponential { pow } => 1.0 - (1.0 - x).powf(pow as f32),
+            CurveType::Analog { attack, decay } => {
+                let overshoot = analog_overshoot(if rising { attack } else { decay });
+                (1.0 + overshoot) * (1.0 - (overshoot / (1.0 + overshoot)).powf(x))
+            }
+        }
+    }
+}
+
+/*
+    Multi-stage envelope: runs through a list of breakpoints, optionally
+    holding one until released, e.g. DAHDSR or DX-style rate/level envelopes
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct MultiStage {
+    stages: [Stage; MAX_STAGES],
+    stage_count: usize,
+    /// stage whose level is held until release, None runs straight through
+    pub sustain_stage: Option<usize>,
+    pub state: EnvelopeState,
+    // current stage, and the level it started from
+    stage: usize,
+    start: f32,
+    time: f32,
+    level: f32,
+    velocity: f32,
+    released: bool,
+    sample_rate: f32,
+}
+
+impl MultiStage {
+    /// Stages past MAX_STAGES are ignored
+    pub fn new(stages: &[Stage], sustain_stage: Option<usize>, sample_rate: f32) -> Self {
+        let mut envelope = Self {
+            stages: [Stage::new(0.0, 0.0, CurveType::Linear); MAX_STAGES],
+            stage_count: 0,
+            sustain_stage,
+            state: EnvelopeState::Off,
+            stage: 0,
+            start: 0.0,
+            time: 0.0,
+            level: 0.0,
+            velocity: 1.0,
+            released: false,
+            sample_rate,
+        };
+        envelope.set_stages(stages);
+        envelope
+    }
+
+    /// Delay, attack, hold, decay, sustain and release, with the times in
+    /// milliseconds and the sustain level 0.0..1.0
+    pub fn dahdsr(
+        delay_ms: f32,
+        attack_ms: f32,
+        hold_ms: f32,
+        decay_ms: f32,
+        sustain: f32,
+        release_ms: f32,
+        sample_rate: f32,
+    ) -> Self {
+        let curve = CurveType::Exponential { pow: 3 };
+        let stages = [
+            Stage::new(delay_ms, 0.0, CurveType::Linear),
+            Stage::new(attack_ms, 1.0, CurveType::Linear),
+            Stage::new(hold_ms, 1.0, CurveType::Linear),
+            Stage::new(decay_ms, sustain, curve),
+            Stage::new(release_ms, 0.0, curve),
+        ];
+        Self::new(&stages, Some(3), sample_rate)
+    }
+
+    pub fn set_stages(&mut self, stages: &[Stage]) {
+        self.stage_count = stages.len().min(MAX_STAGES);
+        self.stages[..self.stage_count].copy_from_slice(&stages[..self.stage_count]);
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages[..self.stage_count]
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Change one breakpoint, e.g. the level of an operator's stage
+    pub fn set_stage(&mut self, index: usize, stage: Stage) {
+        if index < self.stage_count {
+            self.stages[index] = stage;
+        }
+    }
+
+    /// Restart from the first stage, from silence
+    pub fn trigger(&mut self, velocity: u8) {
+        self.velocity = velocity as f32 / 127.0;
+        self.level = 0.0;
+        self.released = false;
+        self.enter_stage(0);
+    }
+
+    /// Leave the sustained stage for the ones after it, from the level
+    /// reached
+    pub fn release(&mut self) {
+        if self.released || !self.is_active() {
+            return;
+        }
+        self.released = true;
+        match self.sustain_stage {
+            Some(sustain) if self.stage <= sustain => self.enter_stage(sustain + 1),
+            _ => {}
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self) -> f32 {
+        // finished stages hand over right away, so zero length ones take
+        // no time
+        while self.is_active() {
+            let stage = self.stages[self.stage];
+            let length = stage.time_ms * (self.sample_rate / 1000.0);
+            if self.time < length {
+                let rising = stage.level > self.start;
+                let x = stage.shape(self.time / length, rising);
+                self.level = self.start + (stage.level - self.start) * x;
+                self.time += 1.0;
+                break;
+            }
+            self.level = stage.level;
+            if !self.released && self.sustain_stage == Some(self.stage) {
+                break;
+            }
+            self.enter_stage(self.stage + 1);
+        }
+        self.level * self.velocity
+    }
+
+    pub fn is_active(&self) -> bool {
+        !matches!(self.state, EnvelopeState::Off)
+    }
+
+    fn enter_stage(&mut self, stage: usize) {
+        self.stage = stage;
+        self.start = self.level;
+        self.time = 0.0;
+        self.state = if stage >= self.stage_count {
+            EnvelopeState::Off
+        } else if self.sustain_stage.is_some_and(|sustain| stage > sustain) {
+            EnvelopeState::Decay
+        } else {
+            EnvelopeState::Attack
+        };
+    }
+}
+
+/*
+    Portamento: glides a (fractional MIDI) pitch to its target in a fixed time
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct Portamento {
+    pub time_ms: f32,
+    value: f32,
+    target: f32,
+    increment: f32,
+    sample_rate: f32,
+}
+
+impl Portamento {
+    pub fn new(time_ms: f32, sample_rate: f32) -> Self {
+        Portamento {
+            time_ms,
+            value: 0.0,
+            target: 0.0,
+            increment: 0.0,
+            sample_rate,
+        }
+    }
+
+    /// Glide from the current pitch to `pitch`
+    pub fn set_target(&mut self, pitch: f32) {
+        self.target = pitch;
+        let samples = self.time_ms * 0.001 * self.sample_rate;
+        if samples < 1.0 {
+            self.value = pitch;
+        } else {
+            self.increment = (pitch - self.value) / samples;
+        }
+    }
+
+    /// Move to `pitch` without gliding
+    pub fn jump(&mut self, pitch: f32) {
+        self.target = pitch;
+        self.value = pitch;
+    }
+
+    #[inline]
+    pub fn process(&mut self) -> f32 {
+        if self.value != self.target {
+            self.value += self.increment;
+            let overshot = (self.increment > 0.0 && self.value > self.target)
+                || (self.increment < 0.0 && self.value < self.target)
+                || self.increment == 0.0;
+            if overshot {
+                self.value = self.target;
+            }
+        }
+        self.value
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.value != self.target
     }
 }
 
@@ -225,4 +595,147 @@ mod tests {
         }
         assert_eq!(ar.is_active(), false);
     }
+
+    fn analog(shape: f32) -> CurveType {
+        CurveType::Analog {
+            attack: shape,
+            decay: shape,
+        }
+    }
+
+    #[test]
+    fn analog_segments_take_their_length() {
+        let sample_rate = 1000.0;
+        for shape in [0.0, 0.5, 1.0] {
+            let mut ar = AR::new(10.0, 100.0, analog(shape), sample_rate);
+            ar.trigger(127);
+            let ys: Vec<f32> = (0..200).map(|_| ar.process()).collect();
+            // 10 samples of attack, then 100 of decay
+            let peak = ys.iter().position(|&y| y == 1.0).unwrap();
+            let end = ys.iter().position(|&y| y == 0.0).unwrap();
+            assert!((9..=10).contains(&peak));
+            assert!((99..=101).contains(&(end - peak)));
+            assert!(!ar.is_active());
+        }
+
+        // curved decays fall quickly at first, then slow down
+        let mut ar = AR::new(0.0, 100.0, analog(1.0), sample_rate);
+        ar.trigger(127);
+        let ys: Vec<f32> = (0..100).map(|_| ar.process()).collect();
+        assert!(ys[10] < 0.5);
+        assert!(ys[50] > 0.0);
+
+        // releasing early falls from the level reached
+        let mut ar = AR::new(100.0, 100.0, analog(0.5), sample_rate);
+        ar.trigger(127);
+        let attack: Vec<f32> = (0..20).map(|_| ar.process()).collect();
+        ar.decay();
+        let y = ar.process();
+        assert!(y > 0.0 && y < attack[19]);
+    }
+
+    #[test]
+    fn retriggers_follow_the_mode() {
+        let sample_rate = 1000.0;
+        let release = |mode: RetriggerMode, curve_type: CurveType| {
+            let mut ar = AR::new(10.0, 100.0, curve_type, sample_rate);
+            ar.retrigger = mode;
+            ar.trigger(127);
+            for _ in 0..30 {
+                ar.process();
+            }
+            let level = ar.process();
+            ar.trigger(127);
+            (level, ar.process(), ar.state)
+        };
+        for curve_type in [
+            CurveType::Linear,
+            CurveType::Exponential { pow: 3 },
+            analog(0.5),
+        ] {
+            // restarting from silence drops below the level reached
+            let (level, reset, _) = release(RetriggerMode::Reset, curve_type);
+            assert!(level > 0.1);
+            assert!(reset < level);
+
+            // rising on from it
+            let (_, y, state) = release(RetriggerMode::Continue, curve_type);
+            assert!(y >= level - 1e-6 && y < 1.0);
+            assert!(matches!(state, EnvelopeState::Attack));
+
+            let (level, y, state) = release(RetriggerMode::Legato, curve_type);
+            assert!(y < level);
+            assert!(matches!(state, EnvelopeState::Decay));
+        }
+    }
+
+    #[test]
+    fn multi_stage_holds_its_sustain() {
+        let sample_rate = 1000.0;
+        let mut env = MultiStage::dahdsr(5.0, 10.0, 5.0, 10.0, 0.5, 20.0, sample_rate);
+        assert_eq!(env.stages().len(), 5);
+        env.trigger(127);
+        let ys: Vec<f32> = (0..100).map(|_| env.process()).collect();
+        // silent through the delay, then up to full level and held
+        assert!(ys[..5].iter().all(|&y| y == 0.0));
+        assert!(ys[10] > 0.0 && ys[10] < 1.0);
+        assert_eq!(ys[15], 1.0);
+        assert_eq!(ys[20], 1.0);
+        assert!(ys[21] < 1.0);
+        assert_eq!(ys[99], 0.5);
+        assert!(env.is_active());
+
+        env.release();
+        assert!(matches!(env.state, EnvelopeState::Decay));
+        let ys: Vec<f32> = (0..30).map(|_| env.process()).collect();
+        assert_eq!(ys[0], 0.5);
+        assert!(ys[1] < 0.5);
+        assert_eq!(ys[29], 0.0);
+        assert!(!env.is_active());
+    }
+
+    #[test]
+    fn multi_stage_runs_through_without_sustain() {
+        // DX-style: up, partly down, then out to silence
+        let stages = [
+            Stage::new(2.0, 1.0, CurveType::Linear),
+            Stage::new(4.0, 0.25, CurveType::Linear),
+            Stage::new(4.0, 0.0, CurveType::Exponential { pow: 2 }),
+        ];
+        let mut env = MultiStage::new(&stages, None, 1000.0);
+        env.trigger(127);
+        let ys: Vec<f32> = (0..20).map(|_| env.process()).collect();
+        assert_eq!(ys[..5], [0.0, 0.5, 1.0, 0.8125, 0.625]);
+        assert_eq!(ys[6], 0.25);
+        assert!(!env.is_active());
+        assert_eq!(ys[19], 0.0);
+
+        // releasing early jumps to the stages after the sustain
+        env.sustain_stage = Some(0);
+        env.trigger(127);
+        env.process();
+        env.process();
+        env.release();
+        assert_eq!(env.process(), 0.5);
+        assert!(env.process() < 0.5);
+    }
+
+    #[test]
+    fn portamento_glides_to_target() {
+        let sample_rate = 1000.0;
+        let mut portamento = Portamento::new(10.0, sample_rate);
+        portamento.jump(60.0);
+        portamento.set_target(70.0);
+        assert_eq!(portamento.process(), 61.0);
+        for _ in 0..20 {
+            portamento.process();
+        }
+        assert_eq!(portamento.process(), 70.0);
+        assert!(!portamento.is_gliding());
+
+        // no glide time moves straight to the target
+        portamento.time_ms = 0.0;
+        portamento.set_target(48.0);
+        assert_eq!(portamento.process(), 48.0);
+    }
 }
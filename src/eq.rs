@@ -0,0 +1,133 @@
+//! Per-track 3-band equalizer
+//!
+//! A low shelf, a parametric mid band and a high shelf in series. Its
+//! parameters are addressed through the track's `set_parameter`, starting
+//! at `EQ_PARAMETER_OFFSET`.
+
+use crate::filters::{Biquad, BiquadType};
+use crate::parameters::ParameterInfo;
+
+/// parameters from this index onward go to the track's EQ instead of its voice
+pub const EQ_PARAMETER_OFFSET: i8 = 100;
+
+/// 0: low gain (dB), 1: low frequency, 2: mid gain (dB), 3: mid frequency,
+/// 4: mid Q, 5: high gain (dB), 6: high frequency
+pub const EQ_PARAMETER_COUNT: i8 = 7;
+
+pub struct Eq3 {
+    low: Biquad,
+    mid: Biquad,
+    high: Biquad,
+}
+
+impl Eq3 {
+    /// Identified by their track parameter index, from `EQ_PARAMETER_OFFSET`
+    pub fn parameters() -> Vec<ParameterInfo> {
+        let id = |parameter: i8| EQ_PARAMETER_OFFSET + parameter;
+        vec![
+            ParameterInfo::linear(id(0), "Low gain", -24.0, 24.0, 0.0, "dB"),
+            ParameterInfo::logarithmic(id(1), "Low frequency", 20.0, 1000.0, 200.0, "Hz"),
+            ParameterInfo::linear(id(2), "Mid gain", -24.0, 24.0, 0.0, "dB"),
+            ParameterInfo::logarithmic(id(3), "Mid frequency", 100.0, 10000.0, 1000.0, "Hz"),
+            ParameterInfo::logarithmic(id(4), "Mid Q", 0.1, 10.0, 0.707, ""),
+            ParameterInfo::linear(id(5), "High gain", -24.0, 24.0, 0.0, "dB"),
+            ParameterInfo::logarithmic(id(6), "High frequency", 1000.0, 20000.0, 5000.0, "Hz"),
+        ]
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            low: Biquad::new(BiquadType::LowShelf, 200.0, 0.707, 0.0, sample_rate),
+            mid: Biquad::new(BiquadType::Peak, 1000.0, 0.707, 0.0, sample_rate),
+            high: Biquad::new(BiquadType::HighShelf, 5000.0, 0.707, 0.0, sample_rate),
+        }
+    }
+
+    /// The EQ is bypassed while all bands are flat
+    pub fn is_flat(&self) -> bool {
+        self.low.get_gain() == 0.0 && self.mid.get_gain() == 0.0 && self.high.get_gain() == 0.0
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        if self.is_flat() {
+            return x;
+        }
+        self.high.process(self.mid.process(self.low.process(x)))
+    }
+
+    pub fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.low.update_gain(value),
+            1 => self.low.update_freq(value),
+            2 => self.mid.update_gain(value),
+            3 => self.mid.update_freq(value),
+            4 => self.mid.update_q(value),
+            5 => self.high.update_gain(value),
+            6 => self.high.update_freq(value),
+            _ => (),
+        }
+    }
+
+    pub fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.low.get_gain(),
+            1 => self.low.get_freq(),
+            2 => self.mid.get_gain(),
+            3 => self.mid.get_freq(),
+            4 => self.mid.get_q(),
+            5 => self.high.get_gain(),
+            6 => self.high.get_freq(),
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_eq_is_transparent() {
+        let mut eq = Eq3::new(48000.0);
+        assert!(eq.is_flat());
+        for x in [0.5, -0.25, 1.0] {
+            assert_eq!(eq.process(x), x);
+        }
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut eq = Eq3::new(48000.0);
+        let parameters = Eq3::parameters();
+        assert_eq!(parameters.len(), EQ_PARAMETER_COUNT as usize);
+        for info in parameters {
+            assert_eq!(
+                eq.get_parameter(info.id - EQ_PARAMETER_OFFSET),
+                info.default
+            );
+        }
+        for parameter in 0..EQ_PARAMETER_COUNT {
+            let value = parameter as f32 * 100.0 + 1.0;
+            eq.set_parameter(parameter, value);
+            assert_eq!(eq.get_parameter(parameter), value);
+        }
+        assert!(!eq.is_flat());
+    }
+
+    #[test]
+    fn low_cut_attenuates_bass() {
+        let sample_rate = 48000.0;
+        let mut eq = Eq3::new(sample_rate);
+        eq.set_parameter(0, -24.0);
+        let energy: f32 = (0..4800)
+            .map(|i| {
+                let x = (2.0 * std::f32::consts::PI * 50.0 * i as f32 / sample_rate).sin();
+                eq.process(x).powi(2)
+            })
+            .skip(2400)
+            .sum();
+        // a full scale sine has an energy of 0.5 per sample
+        assert!(energy < 2400.0 * 0.5 * 0.1);
+    }
+}
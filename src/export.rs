@@ -0,0 +1,76 @@
+//! WAV export
+//!
+//! Sample formats for bouncing the engine's output to a stereo WAV file.
+//! Integer formats are clipped to full scale.
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::io::{Seek, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WavFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavFormat {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(WavFormat::Int16),
+            1 => Some(WavFormat::Int24),
+            2 => Some(WavFormat::Float32),
+            _ => None,
+        }
+    }
+
+    pub fn spec(&self, sample_rate: f32) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            WavFormat::Int16 => (16, SampleFormat::Int),
+            WavFormat::Int24 => (24, SampleFormat::Int),
+            WavFormat::Float32 => (32, SampleFormat::Float),
+        };
+        WavSpec {
+            channels: 2,
+            sample_rate: sample_rate as u32,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+
+    pub fn write_sample<W: Write + Seek>(
+        &self,
+        writer: &mut WavWriter<W>,
+        x: f32,
+    ) -> Result<(), hound::Error> {
+        let full_scale = match self {
+            WavFormat::Int16 => i16::MAX as f32,
+            WavFormat::Int24 => 8_388_607.0,
+            WavFormat::Float32 => return writer.write_sample(x),
+        };
+        writer.write_sample((x.clamp(-1.0, 1.0) * full_scale).round() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn integer_samples_are_clipped() {
+        let format = WavFormat::Int16;
+        let mut buffer = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut buffer, format.spec(48000.0)).unwrap();
+        for x in [0.5, 2.0, -1.5] {
+            format.write_sample(&mut writer, x).unwrap();
+            format.write_sample(&mut writer, x).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        buffer.set_position(0);
+        let mut reader = hound::WavReader::new(buffer).unwrap();
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, [16384, 16384, 32767, 32767, -32767, -32767]);
+        assert_eq!(WavFormat::from_u8(3), None);
+    }
+}
@@ -1,6 +1,8 @@
 //! Various types of filters
 
 use crate::delay::{DelayLine, InterpolationType};
+use crate::simd::F32x8;
+use crate::utils::undenormalize;
 use std::f32::consts::PI;
 
 /// # 1st order FIR Filter
@@ -85,10 +87,40 @@ pub enum SVFMode {
     Bandpass,
 }
 
+/// Filter model used by a voice
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    Svf,
+    Ladder,
+}
+
+impl FilterType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FilterType::Svf),
+            1 => Some(FilterType::Ladder),
+            _ => None,
+        }
+    }
+}
+
+/// pitch a key-tracked cutoff is set for, middle C
+pub const KEY_TRACKING_CENTER: f32 = 60.0;
+
+/// Ratio a cutoff is scaled by to follow `pitch` on the keyboard. An
+/// `amount` of 1.0 moves it an octave per octave, 2.0 two octaves.
+#[inline]
+pub fn key_tracking(pitch: f32, amount: f32) -> f32 {
+    (2f32).powf((pitch - KEY_TRACKING_CENTER) / 12.0 * amount)
+}
+
 /// Cytomic (Andrew Simper) state-variable filter
 #[derive(Debug, Clone, Copy)]
 pub struct SVF {
     freq: f32,
+    freq_mod: f32,
+    q: f32,
+    q_mod: f32,
     g: f32,
     k: f32,
     a1: f32,
@@ -96,6 +128,8 @@ pub struct SVF {
     a3: f32,
     ic1eq: f32,
     ic2eq: f32,
+    // saturation of the integrators, linear at zero
+    drive: f32,
     sample_rate: f32,
     pub mode: SVFMode,
 }
@@ -104,6 +138,9 @@ impl SVF {
     pub fn new(freq: f32, q: f32, sample_rate: f32) -> SVF {
         let mut svf = SVF {
             freq,
+            freq_mod: 0.0,
+            q,
+            q_mod: 0.0,
             g: 0.0,
             k: 0.0,
             a1: 0.0,
@@ -111,6 +148,7 @@ impl SVF {
             a3: 0.0,
             ic1eq: 0.0,
             ic2eq: 0.0,
+            drive: 0.0,
             sample_rate,
             mode: SVFMode::Highpass,
         };
@@ -120,35 +158,76 @@ impl SVF {
     }
     #[inline]
     pub fn process(&mut self, x: f32, freq_mod: f32) -> f32 {
-        if freq_mod > 0.0 {
-            let freq = self.freq + (freq_mod * self.freq);
-            self.g = (std::f32::consts::PI * freq / self.sample_rate).tan();
-            self.update_coefficients();
+        let freq_mod = freq_mod.max(0.0);
+        if freq_mod != self.freq_mod {
+            // also restores the cutoff once the modulation returns to zero
+            self.freq_mod = freq_mod;
+            self.update_g();
         }
         let v3 = x - self.ic2eq;
         let v1 = self.a1 * self.ic1eq + self.a2 * v3;
         let v2 = self.ic2eq + self.a2 * self.ic1eq + self.a3 * v3;
-        self.ic1eq = 2.0 * v1 - self.ic1eq;
-        self.ic2eq = 2.0 * v2 - self.ic2eq;
+        self.ic1eq = undenormalize(2.0 * v1 - self.ic1eq);
+        self.ic2eq = undenormalize(2.0 * v2 - self.ic2eq);
+        if self.drive > 0.0 {
+            // Simper's nonlinear variant: saturating the integrators bounds
+            // the energy they hold, so high resonance compresses instead of
+            // ringing ever louder
+            self.ic1eq = (self.ic1eq * self.drive).tanh() / self.drive;
+            self.ic2eq = (self.ic2eq * self.drive).tanh() / self.drive;
+        }
 
         match self.mode {
-            SVFMode::Lowpass => v1,
+            SVFMode::Lowpass => v2,
             SVFMode::Highpass => x - self.ic2eq - self.a2 * self.ic1eq,
-            SVFMode::Bandpass => v2,
+            SVFMode::Bandpass => v1,
         }
     }
 
     pub fn update_freq(&mut self, freq: f32) {
         self.freq = freq;
+        self.update_g();
+    }
+
+    fn update_g(&mut self) {
+        let freq = (self.freq + self.freq_mod * self.freq).min(self.sample_rate * 0.49);
         self.g = (std::f32::consts::PI * freq / self.sample_rate).tan();
         self.update_coefficients();
     }
 
     pub fn update_q(&mut self, q: f32) {
-        self.k = 1.0 / q;
+        self.q = q;
+        self.k = 1.0 / (q * (1.0 + self.q_mod)).max(0.01);
         self.update_coefficients();
     }
 
+    /// Offset Q relative to its set value, e.g. from a modulation source
+    #[inline]
+    pub fn modulate_q(&mut self, q_mod: f32) {
+        if q_mod != self.q_mod {
+            self.q_mod = q_mod;
+            self.update_q(self.q);
+        }
+    }
+
+    /// Set how hard the integrators saturate, they clip at `1.0 / drive`;
+    /// zero keeps the filter linear
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(0.0);
+    }
+
+    pub fn get_freq(&self) -> f32 {
+        self.freq
+    }
+
+    pub fn get_q(&self) -> f32 {
+        self.q
+    }
+
+    pub fn get_drive(&self) -> f32 {
+        self.drive
+    }
+
     pub fn reset(&mut self) {
         self.g = 0.0;
         self.k = 0.0;
@@ -180,6 +259,452 @@ impl SVF {
     }
 }
 
+/// SVFs sharing one cutoff and Q, processed eight at a time, for banks of
+/// identical filters such as the damping in a reverb's delay paths
+pub struct SvfBank<const N: usize> {
+    // holds the shared coefficients
+    svf: SVF,
+    ic1eq: [F32x8; N],
+    ic2eq: [F32x8; N],
+}
+
+impl<const N: usize> SvfBank<N> {
+    pub fn new(mode: SVFMode, freq: f32, q: f32, sample_rate: f32) -> Self {
+        let mut svf = SVF::new(freq, q, sample_rate);
+        svf.mode = mode;
+        svf.update_freq(freq);
+        Self {
+            svf,
+            ic1eq: [F32x8::ZERO; N],
+            ic2eq: [F32x8::ZERO; N],
+        }
+    }
+
+    pub fn update_freq(&mut self, freq: f32) {
+        self.svf.update_freq(freq);
+    }
+
+    /// Filter `N * 8` signals in place, one per lane
+    #[inline]
+    pub fn process(&mut self, xs: &mut [F32x8; N]) {
+        let a1 = F32x8::splat(self.svf.a1);
+        let a2 = F32x8::splat(self.svf.a2);
+        let a3 = F32x8::splat(self.svf.a3);
+        let two = F32x8::splat(2.0);
+        for ((x, ic1eq), ic2eq) in xs
+            .iter_mut()
+            .zip(self.ic1eq.iter_mut())
+            .zip(self.ic2eq.iter_mut())
+        {
+            let v3 = *x - *ic2eq;
+            let v1 = a1 * *ic1eq + a2 * v3;
+            let v2 = *ic2eq + a2 * *ic1eq + a3 * v3;
+            *ic1eq = F32x8((two * v1 - *ic1eq).0.map(undenormalize));
+            *ic2eq = F32x8((two * v2 - *ic2eq).0.map(undenormalize));
+
+            *x = match self.svf.mode {
+                SVFMode::Lowpass => v2,
+                SVFMode::Highpass => *x - *ic2eq - a2 * *ic1eq,
+                SVFMode::Bandpass => v1,
+            };
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BiquadType {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+/// Second order IIR filter, with coefficients from Robert Bristow-Johnson's
+/// Audio EQ Cookbook, in transposed direct form II
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    filter_type: BiquadType,
+    freq: f32,
+    q: f32,
+    gain_db: f32,
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+    sample_rate: f32,
+}
+
+impl Biquad {
+    /// `gain_db` only applies to the peak and shelving types
+    pub fn new(filter_type: BiquadType, freq: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let mut biquad = Self {
+            filter_type,
+            freq,
+            q,
+            gain_db,
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            z1: 0.0,
+            z2: 0.0,
+            sample_rate,
+        };
+        biquad.update_coefficients();
+        biquad
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    pub fn update_freq(&mut self, freq: f32) {
+        self.freq = freq;
+        self.update_coefficients();
+    }
+
+    pub fn update_q(&mut self, q: f32) {
+        self.q = q;
+        self.update_coefficients();
+    }
+
+    pub fn update_gain(&mut self, gain_db: f32) {
+        self.gain_db = gain_db;
+        self.update_coefficients();
+    }
+
+    pub fn get_freq(&self) -> f32 {
+        self.freq
+    }
+
+    pub fn get_q(&self) -> f32 {
+        self.q
+    }
+
+    pub fn get_gain(&self) -> f32 {
+        self.gain_db
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    fn update_coefficients(&mut self) {
+        let freq = self.freq.clamp(1.0, self.sample_rate * 0.49);
+        let w0 = 2.0 * PI * freq / self.sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q.max(0.01));
+        let a = 10f32.powf(self.gain_db / 40.0);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match self.filter_type {
+            BiquadType::Lowpass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::Highpass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::Bandpass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            BiquadType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            BiquadType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BiquadType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            BiquadType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+        };
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+}
+
+/// 4-pole Moog-style ladder filter, using zero-delay feedback one-pole
+/// stages with a saturating feedback loop (after Huovilainen/Zavalishin).
+/// Self-oscillates as the resonance approaches 1.0
+#[derive(Debug, Clone, Copy)]
+pub struct LadderFilter {
+    freq: f32,
+    freq_mod: f32,
+    resonance: f32,
+    drive: f32,
+    g: f32,
+    stages: [f32; 4],
+    sample_rate: f32,
+}
+
+impl LadderFilter {
+    pub fn new(freq: f32, resonance: f32, sample_rate: f32) -> Self {
+        let mut filter = Self {
+            freq,
+            freq_mod: 0.0,
+            resonance,
+            drive: 1.0,
+            g: 0.0,
+            stages: [0.0; 4],
+            sample_rate,
+        };
+        filter.update_g();
+        filter
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32, freq_mod: f32) -> f32 {
+        let freq_mod = freq_mod.max(0.0);
+        if freq_mod != self.freq_mod {
+            self.freq_mod = freq_mod;
+            self.update_g();
+        }
+        let k = 4.0 * self.resonance;
+        let big_g = self.g / (1.0 + self.g);
+
+        // solve the feedback loop for the input of the first stage, from
+        // the stages' contributions to the output
+        let mut sum = 0.0;
+        for s in self.stages.iter() {
+            sum = sum * big_g + s / (1.0 + self.g);
+        }
+        let g4 = big_g * big_g * big_g * big_g;
+        let u = (x * self.drive - k * sum) / (1.0 + k * g4);
+        let mut y = u.tanh();
+
+        for s in self.stages.iter_mut() {
+            let v = (y - *s) * big_g;
+            y = v + *s;
+            *s = y + v;
+        }
+        // compensate for the passband loss at high resonance, and the drive
+        y * (1.0 + 0.5 * k) / self.drive.sqrt()
+    }
+
+    pub fn update_freq(&mut self, freq: f32) {
+        self.freq = freq;
+        self.update_g();
+    }
+
+    fn update_g(&mut self) {
+        let freq = (self.freq + self.freq_mod * self.freq).min(self.sample_rate * 0.49);
+        self.g = (PI * freq / self.sample_rate).tan();
+    }
+
+    /// Set the resonance, 0.0..1.0
+    pub fn update_resonance(&mut self, resonance: f32) {
+        self.resonance = resonance.clamp(0.0, 1.0);
+    }
+
+    /// Set the input gain driving the saturation, 1.0 is clean-ish
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(0.01);
+    }
+
+    pub fn get_freq(&self) -> f32 {
+        self.freq
+    }
+
+    pub fn get_resonance(&self) -> f32 {
+        self.resonance
+    }
+
+    pub fn get_drive(&self) -> f32 {
+        self.drive
+    }
+
+    pub fn reset(&mut self) {
+        self.stages = [0.0; 4];
+    }
+}
+
+/// Feedforward comb filter: `y[n] = x[n] + gain * x[n - delay]`.
+/// The delay is fractional and can be modulated per sample, e.g. for
+/// flanging
+pub struct FeedforwardComb {
+    delay_line: DelayLine,
+    pub delay: f32,
+    pub gain: f32,
+}
+
+impl FeedforwardComb {
+    /// `max_delay` is the longest delay in samples, including modulation
+    pub fn new(max_delay: usize, delay: f32, gain: f32) -> Self {
+        Self {
+            delay_line: DelayLine::new(InterpolationType::Linear, max_delay + 1),
+            delay,
+            gain,
+        }
+    }
+
+    /// Process one sample, with the delay offset by `delay_mod` samples
+    #[inline]
+    pub fn process(&mut self, x: f32, delay_mod: f32) -> f32 {
+        self.delay_line.write_and_increment(x);
+        // the sample just written is at a delay of 1
+        let delayed = self.delay_line.read_delayed(self.delay + delay_mod + 1.0);
+        x + self.gain * delayed
+    }
+}
+
+/// Feedback comb filter: `y[n] = x[n] + feedback * y[n - delay]`, with an
+/// optional one-pole lowpass damping the feedback path. Resonates at
+/// multiples of `sample_rate / delay`, which makes it useful for resonator
+/// banks and physical models
+pub struct FeedbackComb {
+    delay_line: DelayLine,
+    pub delay: f32,
+    pub feedback: f32,
+    /// 0.0 (no damping) .. 1.0
+    pub damping: f32,
+    filter_state: f32,
+}
+
+impl FeedbackComb {
+    /// `max_delay` is the longest delay in samples, including modulation
+    pub fn new(max_delay: usize, delay: f32, feedback: f32) -> Self {
+        Self {
+            delay_line: DelayLine::new(InterpolationType::Linear, max_delay + 1),
+            delay,
+            feedback,
+            damping: 0.0,
+            filter_state: 0.0,
+        }
+    }
+
+    /// Process one sample, with the delay offset by `delay_mod` samples
+    #[inline]
+    pub fn process(&mut self, x: f32, delay_mod: f32) -> f32 {
+        let delayed = self.delay_line.read_delayed(self.delay + delay_mod);
+        self.filter_state = delayed + (self.filter_state - delayed) * self.damping;
+        let y = x + self.feedback * self.filter_state;
+        self.delay_line.write_and_increment(y);
+        y
+    }
+}
+
+/// First order all-pass, shifting the phase by 90° at its corner frequency
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstOrderAllPass {
+    a: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl FirstOrderAllPass {
+    pub fn new(freq: f32, sample_rate: f32) -> Self {
+        let mut filter = Self::default();
+        filter.set_freq(freq, sample_rate);
+        filter
+    }
+
+    /// Coefficient for a corner at `freq`, to share between stages
+    #[inline]
+    pub fn coefficient(freq: f32, sample_rate: f32) -> f32 {
+        let t = (PI * freq.clamp(1.0, sample_rate * 0.49) / sample_rate).tan();
+        (t - 1.0) / (t + 1.0)
+    }
+
+    pub fn set_freq(&mut self, freq: f32, sample_rate: f32) {
+        self.a = Self::coefficient(freq, sample_rate);
+    }
+
+    #[inline]
+    pub fn set_coefficient(&mut self, a: f32) {
+        self.a = a;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.a * x + self.x1 - self.a * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.y1 = 0.0;
+    }
+}
+
+// corner of the DC blocker, low enough to leave the lowest notes alone
+const DC_BLOCKER_FREQ: f32 = 10.0;
+
+/// One-pole DC blocker, removing the offset some oscillators and feedback
+/// paths add, which would otherwise eat into the headroom
+///
+///  **Difference equation:**
+///  `y[n] = x[n] - x[n-1] + r * y[n-1]`
+#[derive(Debug, Clone, Copy)]
+pub struct DcBlocker {
+    r: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            r: (-2.0 * PI * DC_BLOCKER_FREQ / sample_rate).exp(),
+            x1: 0.0,
+            y1: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + self.r * self.y1;
+        self.x1 = x;
+        self.y1 = undenormalize(y);
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.y1 = 0.0;
+    }
+}
+
 /// Schroeder all-pass filter
 pub struct AllPass {
     delay_line: DelayLine,
@@ -221,6 +746,33 @@ mod tests {
     const A0: f32 = 0.5;
     const A1: f32 = 0.5;
 
+    #[test]
+    fn svf_bank_matches_single_filters() {
+        let sample_rate = 48000.0;
+        for mode in [SVFMode::Lowpass, SVFMode::Highpass, SVFMode::Bandpass] {
+            let mut bank = SvfBank::<2>::new(mode, 1200.0, 0.8, sample_rate);
+            let mut svfs: Vec<SVF> = (0..16)
+                .map(|_| {
+                    let mut svf = SVF::new(1200.0, 0.8, sample_rate);
+                    svf.mode = mode;
+                    svf
+                })
+                .collect();
+            for n in 0..1000 {
+                let input = |lane: usize| ((n * (lane + 1)) as f32 * 0.01).sin();
+                let mut xs = [F32x8::ZERO; 2];
+                for lane in 0..16 {
+                    xs[lane / 8].0[lane % 8] = input(lane);
+                }
+                bank.process(&mut xs);
+                for (lane, svf) in svfs.iter_mut().enumerate() {
+                    let y = svf.process(input(lane), 0.0);
+                    assert!((xs[lane / 8].0[lane % 8] - y).abs() < 1e-5);
+                }
+            }
+        }
+    }
+
     #[test]
     fn fir_filter_create() {
         let lpf = FIRFilter::new(A0, A1);
@@ -347,4 +899,214 @@ mod tests {
         plot_graph(&bins, &dbs, "fir_freq_response.png");
         plot_graph(&bins, &phases, "fir_phase_response.png");
     }
+
+    fn sine_rms(filter: &mut LadderFilter, freq: f32, sample_rate: f32) -> f32 {
+        let n = sample_rate as usize / 4;
+        let ys: Vec<f32> = (0..n)
+            .map(|i| filter.process((2.0 * PI * freq * i as f32 / sample_rate).sin() * 0.1, 0.0))
+            .collect();
+        let tail = &ys[n / 2..];
+        (tail.iter().map(|y| y * y).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset() {
+        let sample_rate = 48000.0;
+        let mut blocker = DcBlocker::new(sample_rate);
+        let mut y = 0.0;
+        for _ in 0..sample_rate as usize {
+            y = blocker.process(0.5);
+        }
+        assert!(y.abs() < 1e-4);
+
+        // audio passes through
+        let mut blocker = DcBlocker::new(sample_rate);
+        let mut peak: f32 = 0.0;
+        for n in 0..4800 {
+            let x = 0.5 + (2.0 * PI * 100.0 * n as f32 / sample_rate).sin();
+            let y = blocker.process(x);
+            if n > 2400 {
+                peak = peak.max(y.abs());
+            }
+        }
+        assert!((peak - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn key_tracking_follows_octaves() {
+        assert_eq!(key_tracking(72.0, 0.0), 1.0);
+        assert_eq!(key_tracking(72.0, 1.0), 2.0);
+        assert_eq!(key_tracking(48.0, 2.0), 0.25);
+        assert_eq!(key_tracking(KEY_TRACKING_CENTER, 2.0), 1.0);
+    }
+
+    #[test]
+    fn svf_drive_tames_resonance() {
+        let sample_rate = 48000.0;
+        let peak = |drive: f32| {
+            let mut svf = SVF::new(1000.0, 20.0, sample_rate);
+            svf.mode = SVFMode::Lowpass;
+            svf.update_q(20.0);
+            svf.set_drive(drive);
+            (0..sample_rate as usize)
+                .map(|n| {
+                    let x = (2.0 * PI * 1000.0 * n as f32 / sample_rate).sin();
+                    svf.process(x, 0.0).abs()
+                })
+                .fold(0.0, f32::max)
+        };
+        let linear = peak(0.0);
+        let driven = peak(1.0);
+        assert!(linear > 10.0);
+        assert!(driven < 2.0);
+        assert!(driven > 0.1);
+    }
+
+    #[test]
+    fn ladder_is_a_lowpass() {
+        let sample_rate = 48000.0;
+        let low = sine_rms(
+            &mut LadderFilter::new(1000.0, 0.0, sample_rate),
+            100.0,
+            sample_rate,
+        );
+        let high = sine_rms(
+            &mut LadderFilter::new(1000.0, 0.0, sample_rate),
+            8000.0,
+            sample_rate,
+        );
+        // 24 dB/octave: three octaves above the cutoff is down ~72 dB
+        assert!(low > 0.05);
+        assert!(high < low * 0.01);
+    }
+
+    #[test]
+    fn ladder_self_oscillates() {
+        let sample_rate = 48000.0;
+        let mut filter = LadderFilter::new(1000.0, 1.0, sample_rate);
+        // a single impulse keeps ringing at full resonance
+        filter.process(1.0, 0.0);
+        let ys: Vec<f32> = (0..sample_rate as usize)
+            .map(|_| filter.process(0.0, 0.0))
+            .collect();
+        assert!(ys[ys.len() - 1000..].iter().any(|y| y.abs() > 0.01));
+        assert!(ys.iter().all(|y| y.is_finite()));
+
+        // but decays below it
+        let mut filter = LadderFilter::new(1000.0, 0.5, sample_rate);
+        filter.process(1.0, 0.0);
+        let ys: Vec<f32> = (0..sample_rate as usize)
+            .map(|_| filter.process(0.0, 0.0))
+            .collect();
+        assert!(ys[ys.len() - 1000..].iter().all(|y| y.abs() < 1e-4));
+    }
+
+    #[test]
+    fn ladder_drive_saturates() {
+        let mut filter = LadderFilter::new(20000.0, 0.0, 48000.0);
+        filter.set_drive(20.0);
+        let ys: Vec<f32> = (0..1000).map(|_| filter.process(1.0, 0.0)).collect();
+        assert!(ys.iter().all(|y| y.abs() <= 1.0));
+    }
+
+    #[test]
+    fn feedforward_comb_impulse_response() {
+        let mut comb = FeedforwardComb::new(16, 3.0, 0.5);
+        let ir: Vec<f32> = IMPULSE_SIGNAL
+            .iter()
+            .map(|&x| comb.process(x, 0.0))
+            .collect();
+        assert_eq!(ir, vec![1.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn feedback_comb_impulse_response() {
+        let mut comb = FeedbackComb::new(16, 2.0, 0.5);
+        let ir: Vec<f32> = IMPULSE_SIGNAL
+            .iter()
+            .map(|&x| comb.process(x, 0.0))
+            .collect();
+        assert_eq!(ir, vec![1.0, 0.0, 0.5, 0.0, 0.25, 0.0, 0.125]);
+    }
+
+    #[test]
+    fn comb_delay_is_modulatable() {
+        // a half sample of modulation splits the echo over two samples
+        let mut comb = FeedforwardComb::new(16, 2.0, 1.0);
+        let ir: Vec<f32> = IMPULSE_SIGNAL
+            .iter()
+            .map(|&x| comb.process(x, 0.5))
+            .collect();
+        assert_eq!(ir, vec![1.0, 0.0, 0.5, 0.5, 0.0, 0.0, 0.0]);
+    }
+
+    /// steady-state gain (dB) of a biquad for a sine at `freq`
+    fn biquad_gain_db(biquad: &mut Biquad, freq: f32) -> f32 {
+        let sample_rate = 48000.0;
+        let n = 48000;
+        let mut peak: f32 = 0.0;
+        for i in 0..n {
+            let y = biquad.process((2.0 * PI * freq * i as f32 / sample_rate).sin());
+            if i > n / 2 {
+                peak = peak.max(y.abs());
+            }
+        }
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn biquad_peak_boosts_center() {
+        let mut peak = Biquad::new(BiquadType::Peak, 1000.0, 1.0, 6.0, 48000.0);
+        assert!((biquad_gain_db(&mut peak, 1000.0) - 6.0).abs() < 0.1);
+        peak.reset();
+        assert!(biquad_gain_db(&mut peak, 50.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn biquad_shelves() {
+        let mut low = Biquad::new(BiquadType::LowShelf, 200.0, 0.707, -12.0, 48000.0);
+        assert!((biquad_gain_db(&mut low, 20.0) + 12.0).abs() < 0.5);
+        low.reset();
+        assert!(biquad_gain_db(&mut low, 10000.0).abs() < 0.5);
+
+        let mut high = Biquad::new(BiquadType::HighShelf, 5000.0, 0.707, 6.0, 48000.0);
+        assert!((biquad_gain_db(&mut high, 18000.0) - 6.0).abs() < 0.5);
+        high.reset();
+        assert!(biquad_gain_db(&mut high, 100.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn biquad_lowpass_and_highpass() {
+        let mut lowpass = Biquad::new(BiquadType::Lowpass, 1000.0, 0.707, 0.0, 48000.0);
+        assert!(biquad_gain_db(&mut lowpass, 100.0).abs() < 0.1);
+        lowpass.reset();
+        assert!(biquad_gain_db(&mut lowpass, 10000.0) < -30.0);
+
+        let mut highpass = Biquad::new(BiquadType::Highpass, 1000.0, 0.707, 0.0, 48000.0);
+        assert!(biquad_gain_db(&mut highpass, 100.0) < -30.0);
+    }
+
+    #[test]
+    fn first_order_all_pass_shifts_phase_only() {
+        let sample_rate = 48000.0;
+        for freq in [100.0, 1000.0, 10000.0] {
+            let mut filter = FirstOrderAllPass::new(1000.0, sample_rate);
+            let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+            for i in 0..48000 {
+                let x = (2.0 * PI * freq * i as f32 / sample_rate).sin();
+                let y = filter.process(x);
+                if i >= 24000 {
+                    xx += x * x;
+                    yy += y * y;
+                    xy += x * y;
+                }
+            }
+            // unity gain everywhere
+            assert!((yy / xx - 1.0).abs() < 0.01);
+            // a quarter cycle behind at the corner frequency
+            if freq == 1000.0 {
+                assert!((xy / xx).abs() < 0.01);
+            }
+        }
+    }
 }
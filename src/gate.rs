@@ -0,0 +1,254 @@
+//! Trance gate
+//!
+//! Chops its input with a 16 step on/off pattern, one step per note
+//! division at the current tempo. While the transport plays the pattern
+//! follows the sequencer's position, so the chops land on the beat; when
+//! stopped it runs on from where it was. Short attack and release ramps
+//! keep the edges from clicking.
+
+use crate::bus::Effect;
+use crate::parameters::ParameterInfo;
+use crate::sequencer::NoteDivision;
+
+pub const GATE_STEPS: usize = 16;
+
+// parameter of the first step, the steps follow in order
+const FIRST_STEP_PARAMETER: i8 = 4;
+
+// sixteenth notes
+const DEFAULT_DIVISION: u8 = 12;
+
+pub struct TranceGate {
+    pattern: [bool; GATE_STEPS],
+    division: NoteDivision,
+    attack_ms: f32,
+    release_ms: f32,
+    attack: f32,
+    release: f32,
+    // how far closed steps cut the level
+    depth: f32,
+    // position in beats, and how far it moves per sample
+    beat: f32,
+    beats_per_sample: f32,
+    level: f32,
+    sample_rate: f32,
+}
+
+impl TranceGate {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        let mut parameters = vec![
+            ParameterInfo::stepped(
+                0,
+                "Rate",
+                0.0,
+                (NoteDivision::COUNT - 1) as f32,
+                DEFAULT_DIVISION as f32,
+            ),
+            ParameterInfo::logarithmic(1, "Attack", 0.1, 100.0, 2.0, "ms"),
+            ParameterInfo::logarithmic(2, "Release", 0.1, 100.0, 10.0, "ms"),
+            ParameterInfo::linear(3, "Depth", 0.0, 1.0, 1.0, ""),
+        ];
+        for step in 0..GATE_STEPS {
+            let default = if step % 2 == 0 { 1.0 } else { 0.0 };
+            parameters.push(ParameterInfo::stepped(
+                FIRST_STEP_PARAMETER + step as i8,
+                format!("Step {}", step + 1),
+                0.0,
+                1.0,
+                default,
+            ));
+        }
+        parameters
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        let mut gate = Self {
+            pattern: std::array::from_fn(|step| step % 2 == 0),
+            division: NoteDivision::from_index(DEFAULT_DIVISION).unwrap(),
+            attack_ms: 0.0,
+            release_ms: 0.0,
+            attack: 1.0,
+            release: 1.0,
+            depth: 1.0,
+            beat: 0.0,
+            // 120 bpm until told otherwise
+            beats_per_sample: 2.0 / sample_rate,
+            level: 1.0,
+            sample_rate,
+        };
+        gate.set_attack(2.0);
+        gate.set_release(10.0);
+        gate
+    }
+
+    /// Length of a step
+    pub fn set_division(&mut self, division: NoteDivision) {
+        self.division = division;
+    }
+
+    pub fn set_step(&mut self, step: usize, on: bool) {
+        if let Some(s) = self.pattern.get_mut(step) {
+            *s = on;
+        }
+    }
+
+    pub fn pattern(&self) -> &[bool; GATE_STEPS] {
+        &self.pattern
+    }
+
+    /// Time to open, in milliseconds
+    pub fn set_attack(&mut self, ms: f32) {
+        self.attack_ms = ms.max(0.0);
+        self.attack = self.coefficient(self.attack_ms);
+    }
+
+    /// Time to close, in milliseconds
+    pub fn set_release(&mut self, ms: f32) {
+        self.release_ms = ms.max(0.0);
+        self.release = self.coefficient(self.release_ms);
+    }
+
+    /// How far closed steps cut the level, 0.0..1.0
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    // share of the distance to the target covered per sample
+    fn coefficient(&self, ms: f32) -> f32 {
+        if ms > 0.0 {
+            1.0 - (-1.0 / (ms * 0.001 * self.sample_rate)).exp()
+        } else {
+            1.0
+        }
+    }
+
+    /// The gain of the gate for the next sample
+    #[inline]
+    fn next_gain(&mut self) -> f32 {
+        let step = (self.beat / self.division.beats()) as usize % GATE_STEPS;
+        let target = if self.pattern[step] {
+            1.0
+        } else {
+            1.0 - self.depth
+        };
+        let coefficient = if target > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.level += (target - self.level) * coefficient;
+        self.beat += self.beats_per_sample;
+        // wrap around whole patterns, keeping the beat small and precise
+        let length = self.division.beats() * GATE_STEPS as f32;
+        if self.beat >= length {
+            self.beat -= length;
+        }
+        self.level
+    }
+}
+
+/// 0: rate (note division, see `NoteDivision::from_index`), 1: attack
+/// (ms), 2: release (ms), 3: depth, 4..=19: steps 1 to 16, on above 0.5
+impl Effect for TranceGate {
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        x * self.next_gain()
+    }
+
+    #[inline]
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let gain = self.next_gain();
+        (left * gain, right * gain)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => {
+                if let Some(division) = NoteDivision::from_index(value as u8) {
+                    self.set_division(division);
+                }
+            }
+            1 => self.set_attack(value),
+            2 => self.set_release(value),
+            3 => self.set_depth(value),
+            p if p >= FIRST_STEP_PARAMETER => {
+                self.set_step((p - FIRST_STEP_PARAMETER) as usize, value > 0.5);
+            }
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.division.index() as f32,
+            1 => self.attack_ms,
+            2 => self.release_ms,
+            3 => self.depth,
+            p if p >= FIRST_STEP_PARAMETER => self
+                .pattern
+                .get((p - FIRST_STEP_PARAMETER) as usize)
+                .map_or(0.0, |&on| if on { 1.0 } else { 0.0 }),
+            _ => 0.0,
+        }
+    }
+
+    fn set_tempo(&mut self, samples_per_beat: f32) {
+        self.beats_per_sample = 1.0 / samples_per_beat.max(1.0);
+    }
+
+    fn set_position(&mut self, beats: f32) {
+        let length = self.division.beats() * GATE_STEPS as f32;
+        self.beat = beats.rem_euclid(length);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    #[test]
+    fn steps_follow_the_pattern() {
+        let mut gate = TranceGate::new(SAMPLE_RATE);
+        gate.set_attack(0.0);
+        gate.set_release(0.0);
+        // a beat per 4 samples, so a sixteenth step per sample
+        gate.set_tempo(4.0);
+        gate.set_parameter(5, 1.0);
+        gate.set_parameter(6, 1.0);
+        let ys: Vec<f32> = (0..6).map(|_| gate.process(1.0)).collect();
+        assert_eq!(ys, [1.0, 1.0, 1.0, 0.0, 1.0, 0.0]);
+
+        // half depth only dips closed steps
+        gate.set_depth(0.5);
+        gate.set_position(3.0);
+        let ys: Vec<f32> = (0..2).map(|_| gate.process(1.0)).collect();
+        assert_eq!(ys, [1.0, 0.5]);
+    }
+
+    #[test]
+    fn follows_the_transport() {
+        let mut gate = TranceGate::new(SAMPLE_RATE);
+        gate.set_attack(0.0);
+        gate.set_release(0.0);
+        gate.set_tempo(4.0);
+        // the fourth step of the second pattern
+        gate.set_position(4.75);
+        assert_eq!(gate.process(1.0), 0.0);
+        assert_eq!(gate.process(1.0), 1.0);
+        gate.set_parameter(FIRST_STEP_PARAMETER + 3, 1.0);
+        assert_eq!(gate.get_parameter(FIRST_STEP_PARAMETER + 3), 1.0);
+        assert!(gate.pattern()[3]);
+    }
+
+    #[test]
+    fn ramps_smooth_the_edges() {
+        let mut gate = TranceGate::new(SAMPLE_RATE);
+        gate.set_tempo(SAMPLE_RATE * 0.5);
+        let ys: Vec<f32> = (0..12000).map(|_| gate.process(1.0)).collect();
+        // no jumps between samples, but the gate does close
+        assert!(ys.windows(2).all(|w| (w[1] - w[0]).abs() < 0.05));
+        assert!(ys.iter().any(|&y| y < 0.01));
+    }
+}
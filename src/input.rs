@@ -0,0 +1,80 @@
+//! Audio input
+//!
+//! Host audio passed to `render` is treated like a stereo track: it runs
+//! through its own insert chain, feeds the send buses and is mixed in at
+//! the monitor level, so the engine can be used as a live effect unit.
+
+use crate::bus::{EffectChain, TrackSend, MAX_BUSES};
+
+pub struct AudioInput {
+    pub inserts: EffectChain,
+    pub sends: [TrackSend; MAX_BUSES],
+    /// level of the input in the mix, 0.0 to hear it only through the buses
+    pub monitor: f32,
+}
+
+impl Default for AudioInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioInput {
+    pub fn new() -> Self {
+        Self {
+            inserts: EffectChain::new(),
+            sends: [TrackSend::default(); MAX_BUSES],
+            monitor: 1.0,
+        }
+    }
+
+    /// Run a frame through the inserts, returning the monitored output and
+    /// adding the sends to `bus_inputs`
+    #[inline]
+    pub fn process(
+        &mut self,
+        left: f32,
+        right: f32,
+        bus_inputs: &mut [f32; MAX_BUSES],
+    ) -> (f32, f32) {
+        let (left, right) = self.inserts.process_stereo(left, right);
+        let pre_fader = (left + right) * 0.5;
+        let post_fader = pre_fader * self.monitor;
+        for (input, send) in bus_inputs.iter_mut().zip(self.sends.iter()) {
+            if send.level != 0.0 {
+                let tap = if send.pre_fader {
+                    pre_fader
+                } else {
+                    post_fader
+                };
+                *input += send.level * tap;
+            }
+        }
+        (left * self.monitor, right * self.monitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::REVERB_BUS;
+
+    #[test]
+    fn input_feeds_mix_and_sends() {
+        let mut input = AudioInput::new();
+        input.monitor = 0.0;
+        input.sends[REVERB_BUS] = TrackSend {
+            level: 0.5,
+            pre_fader: true,
+        };
+        let mut bus_inputs = [0.0; MAX_BUSES];
+        assert_eq!(input.process(1.0, 0.5, &mut bus_inputs), (0.0, 0.0));
+        assert_eq!(bus_inputs[REVERB_BUS], 0.375);
+
+        // post-fader sends follow the monitor level
+        input.sends[REVERB_BUS].pre_fader = false;
+        let mut bus_inputs = [0.0; MAX_BUSES];
+        input.process(1.0, 0.5, &mut bus_inputs);
+        assert_eq!(bus_inputs[REVERB_BUS], 0.0);
+    }
+}
@@ -1,29 +1,162 @@
+use crate::parameters::ParameterInfo;
 use crate::synth::SynthVoice;
-use crate::utils::{freq_to_period, pitch_to_freq};
+use crate::utils::{fractional_pitch_to_freq, freq_to_period, pitch_to_freq};
 use rand::Rng;
+use std::f32::consts::PI;
 
-// this is the number of samples we need to represent a full period
-// of the lowest possible MIDI pitch's frequency (A0 / 27.50 Hz)
-pub const MAX_BUFFER_SIZE: u16 = 8192;
+// the delay lines hold a full period of the lowest playable frequency
+// (A0 / 27.50 Hz) at the voice's sample rate
+const LOWEST_FREQ: f32 = 27.5;
 
-enum Mode {
+// number of delay lines resonating together in coupled mode
+const STRING_COUNT: usize = 3;
+
+// pitch ratios of the coupled strings: unison, slightly detuned unison
+// (3 cents) and octave, like a course of a 12-string guitar
+const COUPLED_RATIOS: [f32; STRING_COUNT] = [1.0, 1.001734, 2.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// a single plucked string
     String,
+    /// Karplus-Strong drum: the feedback sign is randomly flipped, turning
+    /// the pitched tone into a noisy, drum-like one
     Drum,
+    /// several strings excited by one pluck, feeding into each other
+    Coupled,
+}
+
+impl Mode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Mode::String),
+            1 => Some(Mode::Drum),
+            2 => Some(Mode::Coupled),
+            _ => None,
+        }
+    }
+
+    fn string_count(&self) -> usize {
+        match self {
+            Mode::Coupled => STRING_COUNT,
+            _ => 1,
+        }
+    }
+}
+
+/*
+    A delay line with a one-pole damping filter in its feedback path
+*/
+struct DelayString {
+    buffer: Vec<f32>,
+    period: f32,
+    delay: f32,
+    write_pos: usize,
+    filter_state: f32,
+}
+
+impl DelayString {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length],
+            period: 0.0,
+            delay: 0.0,
+            write_pos: 0,
+            filter_state: 0.0,
+        }
+    }
+
+    /// Set the period in samples, compensating for the phase delay of the
+    /// damping filter (coefficient `a`) at the fundamental so the string
+    /// stays in tune
+    fn set_period(&mut self, period: f32, a: f32) {
+        self.period = period.clamp(2.0, self.max_period());
+        let w = 2.0 * PI / self.period;
+        let filter_delay = (a * w.sin()).atan2(1.0 - a * w.cos()) / w;
+        self.delay = (self.period - filter_delay).clamp(1.0, self.max_period());
+    }
+
+    fn max_period(&self) -> f32 {
+        self.buffer.len() as f32 - 2.0
+    }
+
+    /// Read the delay line `delay` samples behind the write position,
+    /// linearly interpolating between samples
+    #[inline]
+    fn read(&self) -> f32 {
+        let len = self.buffer.len();
+        let pos = self.write_pos as f32 - self.delay + len as f32;
+        let i = pos as usize;
+        let frac = pos - i as f32;
+        let a = self.buffer[i % len];
+        let b = self.buffer[(i + 1) % len];
+        a + (b - a) * frac
+    }
+
+    /// Damp `x` and write it back into the line, scaled by `gain`
+    #[inline]
+    fn write(&mut self, x: f32, a: f32, gain: f32) {
+        self.filter_state = (1.0 - a) * x + a * self.filter_state;
+        self.buffer[self.write_pos] = self.filter_state * gain;
+        self.write_pos = (self.write_pos + 1) % self.buffer.len();
+    }
+
+    /// Fill one period with the excitation signal
+    fn excite(&mut self, excitation: impl Fn(usize, f32) -> f32) {
+        self.buffer.fill(0.0);
+        self.filter_state = 0.0;
+        let length = self.period.ceil() as usize;
+        for i in 0..length {
+            self.buffer[i] = excitation(i, self.period);
+        }
+        self.write_pos = length;
+    }
+
+    fn reset(&mut self) {
+        self.period = 0.0;
+        self.write_pos = 0;
+        self.filter_state = 0.0;
+    }
 }
 
 pub struct KarplusVoice {
-    // mode: Mode,
+    mode: Mode,
     tone: f32,
     damping: f32,
-    buffer: [f32; MAX_BUFFER_SIZE as usize],
+    decay: f32,
+    coupling: f32,
+    strings: [DelayString; STRING_COUNT],
     period: f32,
-    read_pos: usize,
-    pitch_track: f32,
+    feedback: f32,
+    release_feedback: f32,
+    silent_samples: usize,
+    pitch: u8,
     is_stopped: bool,
     sample_rate: f32,
 }
 
+// time (s) for a released note to fade out
+const RELEASE_TIME: f32 = 0.05;
+
+// output level under which the string is considered silent
+const SILENCE_THRESHOLD: f32 = 1e-5;
+
 impl KarplusVoice {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Tone", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::linear(1, "Damping", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::logarithmic(2, "Decay", 0.01, 20.0, 2.0, "s"),
+            ParameterInfo::stepped(3, "Mode", 0.0, 2.0, 0.0),
+            ParameterInfo::linear(4, "Coupling", 0.0, 1.0, 0.2, ""),
+        ]
+    }
+
+    /// Longest period the delay lines can hold, in samples
+    fn max_period(&self) -> f32 {
+        self.strings[0].max_period()
+    }
+
     fn generate_triangle_wave(sample: i32, period: f32) -> f32 {
         let phase = sample as f32 / period;
         if phase < 0.25 {
@@ -34,18 +167,55 @@ impl KarplusVoice {
             -4.0 + 4.0 * phase
         }
     }
+
+    /// Coefficient of the one-pole damping lowpass. Fully damped is
+    /// limited so the fundamental still rings for a while
+    fn damping_coeff(&self) -> f32 {
+        self.damping.clamp(0.0, 1.0) * 0.7
+    }
+
+    /// Loop gain per pass through the delay line, so the string decays by
+    /// 60 dB in `time` seconds
+    fn loop_gain(&self, time: f32) -> f32 {
+        10.0_f32.powf(-3.0 * self.period / (time.max(0.001) * self.sample_rate))
+    }
+
+    /// Derive the delay line lengths and loop gains from the period
+    fn update_delay(&mut self) {
+        let a = self.damping_coeff();
+        for (string, ratio) in self.strings.iter_mut().zip(COUPLED_RATIOS) {
+            string.set_period(self.period / ratio, a);
+        }
+        self.feedback = self.loop_gain(self.decay);
+        self.release_feedback = self.loop_gain(RELEASE_TIME);
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        // strings not used by the new mode must not ring on
+        for string in self.strings.iter_mut().skip(mode.string_count()) {
+            string.buffer.fill(0.0);
+            string.filter_state = 0.0;
+        }
+    }
 }
 
 impl SynthVoice for KarplusVoice {
     fn new(sample_rate: f32) -> Self {
         Self {
-            // mode: Mode::String,
+            mode: Mode::String,
             tone: 0.5,
             damping: 0.5,
-            buffer: [0.0; MAX_BUFFER_SIZE as usize],
+            decay: 2.0,
+            coupling: 0.2,
+            strings: std::array::from_fn(|_| {
+                DelayString::new((sample_rate / LOWEST_FREQ).ceil() as usize + 4)
+            }),
             period: 0.0,
-            read_pos: 0,
-            pitch_track: 0.0,
+            feedback: 0.0,
+            release_feedback: 0.0,
+            silent_samples: 0,
+            pitch: 0,
             is_stopped: true,
             sample_rate,
         }
@@ -57,8 +227,9 @@ impl SynthVoice for KarplusVoice {
 
     fn reset(&mut self) {
         self.period = 0.0;
-        self.read_pos = 0;
-        self.pitch_track = 0.0;
+        for string in self.strings.iter_mut() {
+            string.reset();
+        }
     }
 
     #[inline]
@@ -66,78 +237,259 @@ impl SynthVoice for KarplusVoice {
         if !self.is_active() {
             return 0.0;
         }
-        // increment read position
-        // TODO: is it a problem that we're rounding here?
-        // should we interpolate between buffer values?
-        self.read_pos = (self.read_pos + 1) % self.period as usize;
+        let a = self.damping_coeff();
+        let gain = if self.is_stopped {
+            self.release_feedback
+        } else {
+            self.feedback
+        };
 
-        // smooth signal using simple averaging
-        // try more advanced filter
-        let mut sum = 0.0;
-        // let window = 10.0;
-        let mut window = self.damping.powf(2.0);
-        window = (2.0 as f32).max(window * self.pitch_track);
-        for i in 0..window as usize {
-            let idx = (self.read_pos + i) % self.period as usize;
-            sum += self.buffer[idx];
-        }
-        self.buffer[self.read_pos] = sum * (1.0 / window);
+        let y = match self.mode {
+            Mode::String => {
+                let y = self.strings[0].read();
+                self.strings[0].write(y, a, gain);
+                y
+            }
+            Mode::Drum => {
+                let y = self.strings[0].read();
+                let sign = if rand::thread_rng().gen::<bool>() {
+                    1.0
+                } else {
+                    -1.0
+                };
+                self.strings[0].write(y * sign, a, gain);
+                y
+            }
+            Mode::Coupled => {
+                let ys: [f32; STRING_COUNT] = std::array::from_fn(|i| self.strings[i].read());
+                let sum: f32 = ys.iter().sum();
+                // each string is fed a mix of its own and the other strings'
+                // output, which keeps the total loop gain below one
+                for (string, y) in self.strings.iter_mut().zip(ys) {
+                    let others = (sum - y) / (STRING_COUNT - 1) as f32;
+                    string.write(y + (others - y) * self.coupling, a, gain);
+                }
+                sum / STRING_COUNT as f32
+            }
+        };
 
-        if self.is_stopped {
-            // fade out note
-            self.buffer[self.read_pos] *= 0.9;
+        // free the voice once the string has been silent for a full period
+        if y.abs() < SILENCE_THRESHOLD {
+            self.silent_samples += 1;
+            if self.silent_samples > self.period as usize {
+                self.reset();
+            }
+        } else {
+            self.silent_samples = 0;
         }
 
-        self.buffer[self.read_pos]
+        y
     }
 
     fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32) {
-        // self.tone = param1;
-        // self.damping = param2;
+        // per-note params override the tone and damping when set
+        if param1 > 0.0 {
+            self.tone = param1.clamp(0.0, 1.0);
+        }
+        if param2 > 0.0 {
+            self.damping = param2.clamp(0.0, 1.0);
+        }
 
         self.is_stopped = false;
+        self.pitch = pitch;
         let freq = pitch_to_freq(pitch);
-        self.period = freq_to_period(self.sample_rate, freq);
-        self.read_pos = 0;
+        self.period = freq_to_period(self.sample_rate, freq).clamp(2.0, self.max_period());
+        self.update_delay();
+        self.silent_samples = 0;
 
-        self.pitch_track = (5.0 as f32).max(self.period / 7.0);
-        assert!(self.period < MAX_BUFFER_SIZE as f32);
-
-        for i in 0..self.period as usize {
-            if i > self.period as usize {
-                self.buffer[i] = 0.0;
-            }
-            // generate one period of a sine wave
-            // let sine = ((i as f32 / self.period) * (PI * 2.0)).sin();
-            let tri = Self::generate_triangle_wave(i as i32, self.period);
+        // excite the strings with one period of a triangle / noise mix,
+        // drums are excited by noise only
+        let tone = if self.mode == Mode::Drum {
+            0.0
+        } else {
+            self.tone
+        };
+        let amplitude = velocity as f32 / 127.0;
+        let excitation = |i: usize, period: f32| {
+            let tri = Self::generate_triangle_wave(i as i32, period);
 
             let noise = if rand::thread_rng().gen::<bool>() {
                 1.0
             } else {
                 -1.0
             };
-            let y = (tri * self.tone) + (noise * (1.0 - self.tone));
-            self.buffer[i] = y;
+            ((tri * tone) + (noise * (1.0 - tone))) * amplitude
+        };
+        for string in self.strings.iter_mut().take(self.mode.string_count()) {
+            string.excite(excitation);
         }
     }
 
+    fn set_pitch(&mut self, pitch: f32) {
+        if !self.is_active() {
+            return;
+        }
+        let period = freq_to_period(self.sample_rate, fractional_pitch_to_freq(pitch));
+        self.period = period.clamp(2.0, self.max_period());
+        self.update_delay();
+    }
+
     fn stop(&mut self) {
         self.is_stopped = true;
     }
 
+    /// 0: tone (excitation brightness), 1: damping, 2: decay (s), 3: mode
+    /// (see `Mode`), 4: coupling between strings in coupled mode
     fn set_parameter(&mut self, parameter: i8, value: f32) {
         match parameter {
-            0 => self.tone = value,
-            1 => self.damping = value,
-            _ => (),
+            0 => self.tone = value.clamp(0.0, 1.0),
+            1 => self.damping = value.clamp(0.0, 1.0),
+            2 => self.decay = value.max(0.001),
+            3 => {
+                if let Some(mode) = Mode::from_u8(value as u8) {
+                    self.set_mode(mode);
+                }
+                return;
+            }
+            4 => {
+                self.coupling = value.clamp(0.0, 1.0);
+                return;
+            }
+            _ => return,
+        }
+        if self.is_active() {
+            self.update_delay();
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.tone,
+            1 => self.damping,
+            2 => self.decay,
+            3 => self.mode as u8 as f32,
+            4 => self.coupling,
+            _ => 0.0,
         }
     }
 
+    fn parameter_count(&self) -> i8 {
+        5
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        parameter == 3
+    }
+
     fn get_pitch(&self) -> u8 {
-        (self.period * 27.5) as u8
+        self.pitch
     }
 
     fn is_active(&self) -> bool {
         self.period > 0.0
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    #[test]
+    fn plucked_string_is_in_tune() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(0, 1.0);
+        voice.set_parameter(1, 0.5);
+        voice.play(69, 127, 0.0, 0.0);
+
+        // skip the attack, then count rising zero crossings over a second
+        for _ in 0..4800 {
+            voice.process();
+        }
+        let ys: Vec<f32> = (0..SAMPLE_RATE as usize).map(|_| voice.process()).collect();
+        let crossings = ys.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as f32 - 440.0).abs() <= 2.0, "{crossings}");
+    }
+
+    #[test]
+    fn string_decays_and_frees_voice() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(2, 0.2);
+        voice.play(60, 127, 0.0, 0.0);
+        assert!(voice.is_active());
+        for _ in 0..SAMPLE_RATE as usize {
+            voice.process();
+        }
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn stop_releases_string() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(2, 10.0);
+        voice.play(60, 127, 0.0, 0.0);
+        voice.stop();
+        for _ in 0..(SAMPLE_RATE * 0.5) as usize {
+            voice.process();
+        }
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn note_params_override_tone_and_damping() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.play(60, 127, 0.8, 0.2);
+        assert_eq!(voice.get_parameter(0), 0.8);
+        assert_eq!(voice.get_parameter(1), 0.2);
+        // zero leaves the track's settings alone
+        voice.play(60, 127, 0.0, 0.0);
+        assert_eq!(voice.get_parameter(0), 0.8);
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(0, 0.25);
+        voice.set_parameter(1, 0.75);
+        voice.set_parameter(2, 3.0);
+        assert_eq!(voice.get_parameter(0), 0.25);
+        assert_eq!(voice.get_parameter(1), 0.75);
+        assert_eq!(voice.get_parameter(2), 3.0);
+    }
+
+    #[test]
+    fn coupled_strings_ring_together() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(3, 2.0);
+        voice.set_parameter(4, 0.5);
+        assert_eq!(voice.get_parameter(3), 2.0);
+        voice.play(48, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..4800).map(|_| voice.process()).collect();
+        assert!(ys.iter().all(|y| y.is_finite() && y.abs() <= 1.0));
+        assert!(ys[4000..].iter().any(|y| y.abs() > 0.01));
+        assert!(voice
+            .strings
+            .iter()
+            .all(|s| s.buffer.iter().any(|y| *y != 0.0)));
+    }
+
+    #[test]
+    fn drum_mode_decays() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(3, 1.0);
+        voice.set_parameter(2, 0.5);
+        voice.play(48, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..SAMPLE_RATE as usize * 2)
+            .map(|_| voice.process())
+            .collect();
+        assert!(ys[..4800].iter().any(|y| y.abs() > 0.01));
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn invalid_mode_is_ignored() {
+        let mut voice = KarplusVoice::new(SAMPLE_RATE);
+        voice.set_parameter(3, 7.0);
+        assert_eq!(voice.mode, Mode::String);
+    }
+}
@@ -0,0 +1,150 @@
+//! Global LFOs
+//!
+//! Besides the LFO in each voice, the engine has LFOs that run in beats
+//! with the transport instead of in Hz. They restart whenever the pattern
+//! or loop wraps around, so a sweep always lines up with the music, and
+//! each moves one track or bus effect parameter between two values, e.g. a
+//! filter sweep over four bars on the delay return.
+
+use std::f32::consts::TAU;
+
+/// global LFOs in an engine
+pub const GLOBAL_LFO_COUNT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    RampUp,
+    RampDown,
+    Square,
+}
+
+impl LfoShape {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LfoShape::Sine),
+            1 => Some(LfoShape::Triangle),
+            2 => Some(LfoShape::RampUp),
+            3 => Some(LfoShape::RampDown),
+            4 => Some(LfoShape::Square),
+            _ => None,
+        }
+    }
+
+    /// 0.0..1.0 at `phase`, 0.0..1.0 through the cycle. All shapes but the
+    /// ramp down start at the bottom.
+    pub fn value(&self, phase: f32) -> f32 {
+        match self {
+            LfoShape::Sine => 0.5 - 0.5 * (TAU * phase).cos(),
+            LfoShape::Triangle => 1.0 - (2.0 * phase - 1.0).abs(),
+            LfoShape::RampUp => phase,
+            LfoShape::RampDown => 1.0 - phase,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoTarget {
+    /// track parameter, as indexed by `Message::ParameterChange`
+    Track { track: u8, parameter: i8 },
+    /// parameter of an effect on a send bus
+    Bus { bus: u8, effect: u8, parameter: i8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalLfo {
+    pub shape: LfoShape,
+    /// length of a cycle in beats, e.g. 16.0 for four bars of 4/4
+    pub beats: f32,
+    /// the target's value at the bottom of the cycle
+    pub min: f32,
+    /// and at the top
+    pub max: f32,
+    pub target: LfoTarget,
+    // beats since the last restart, and the pattern position they were
+    // counted up to
+    elapsed: f32,
+    last_position: Option<f32>,
+}
+
+impl GlobalLfo {
+    pub fn new(shape: LfoShape, beats: f32, min: f32, max: f32, target: LfoTarget) -> Self {
+        Self {
+            shape,
+            beats,
+            min,
+            max,
+            target,
+            elapsed: 0.0,
+            last_position: None,
+        }
+    }
+
+    /// Start the next cycle from the beginning
+    pub fn restart(&mut self) {
+        self.elapsed = 0.0;
+        self.last_position = None;
+    }
+
+    /// Follow the transport to `position`, in beats within the playing
+    /// pattern, returning the target's value there. A position before the
+    /// last one means the pattern or loop wrapped around, which restarts
+    /// the cycle.
+    pub fn advance(&mut self, position: f32) -> f32 {
+        match self.last_position {
+            Some(last) if position >= last => self.elapsed += position - last,
+            _ => self.elapsed = 0.0,
+        }
+        self.last_position = Some(position);
+        let phase = if self.beats > 0.0 {
+            (self.elapsed / self.beats).fract()
+        } else {
+            0.0
+        };
+        self.min + (self.max - self.min) * self.shape.value(phase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_in_beats_and_restarts_on_wrap() {
+        let target = LfoTarget::Track {
+            track: 0,
+            parameter: 2,
+        };
+        let mut lfo = GlobalLfo::new(LfoShape::RampUp, 4.0, 100.0, 500.0, target);
+        assert_eq!(lfo.advance(2.0), 100.0);
+        assert_eq!(lfo.advance(3.0), 200.0);
+        assert_eq!(lfo.advance(5.0), 400.0);
+        // a cycle later
+        assert_eq!(lfo.advance(7.0), 200.0);
+        // the pattern wrapped
+        assert_eq!(lfo.advance(0.0), 100.0);
+        assert_eq!(lfo.advance(2.0), 300.0);
+
+        lfo.restart();
+        assert_eq!(lfo.advance(2.0), 100.0);
+    }
+
+    #[test]
+    fn shapes_span_the_range() {
+        for shape in (0..5).filter_map(LfoShape::from_u8) {
+            let values: Vec<f32> = (0..64).map(|n| shape.value(n as f32 / 64.0)).collect();
+            assert!(values.iter().all(|y| (0.0..=1.0).contains(y)));
+            assert!(values.iter().any(|&y| y < 0.05));
+            assert!(values.iter().any(|&y| y > 0.95));
+        }
+        assert_eq!(LfoShape::from_u8(5), None);
+    }
+}
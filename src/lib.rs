@@ -1,152 +1,1903 @@
-use crossbeam::channel;
-use engine::Engine;
+#[cfg(feature = "analyzer")]
+use analyzer::SpectrumAnalyzer;
+use api::{EngineBuilder, EngineHandle};
+use bus::{create_effect, EffectType, SendBus, TrackSend};
+use chords::{Chord, ChordType};
+#[cfg(feature = "convolution")]
+use convolution::ImpulseResponse;
+use engine::{Engine, MAX_OUTPUTS};
+use envelopes::{RetriggerMode, AR};
+use eq::Eq3;
+use export::WavFormat;
 use lazy_static::lazy_static;
-use sequencer::{Event, Message};
-use std::os::raw::c_float;
-use std::sync::Mutex;
+use lfo::{GlobalLfo, LfoShape, LfoTarget};
+use macros::{MacroCurve, MacroDestination};
+use meter::Level;
+use modulation::{ModDestination, ModSlot, ModSource};
+use modulators::ModShaper;
+use mutation::Mutation;
+use parameters::{ParameterDescription, ParameterInfo};
+use plaits_voice::OPERATOR_CURVE;
+use presets::{Preset, PresetBank};
+use recorder::{RecordSettings, RecordSource};
+use resampler::ResamplerQuality;
+use sampler::{equal_slices, sorted_slices, Sample};
+use scales::Scale;
+use sequencer::{
+    ChainEntry, Event, ExpressionDimension, Humanize, Message, NoteExpression, ParameterLocks,
+    StopMode, TimeSignature, TrigCondition,
+};
+use shared::{MeteringCallback, NotePlayedCallback, PlaybackProgressCallback, TransportCallback};
+use sidechain::SidechainTarget;
+use smoothing::SmoothingType;
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_float};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use synth::VoiceType;
+use tuning::{reference_cents, ScalaScale};
+use velocity::{VelocityCurve, VELOCITY_TABLE_SIZE};
 
+#[cfg(feature = "analyzer")]
+pub mod analyzer;
+pub mod api;
+pub mod bus;
+pub mod chords;
+pub mod chorus;
 pub mod consts;
+#[cfg(feature = "convolution")]
+pub mod convolution;
+pub mod declick;
 pub mod delay;
 pub mod drums;
 pub mod engine;
 pub mod envelopes;
+pub mod eq;
+pub mod export;
 pub mod filters;
+pub mod gate;
+pub mod input;
 pub mod karplus;
+pub mod lfo;
 pub mod limiter;
+pub mod macros;
+pub mod meter;
+pub mod metronome;
+pub mod midi_parse;
+pub mod mixer;
+pub mod modal;
+pub mod modulation;
+pub mod modulators;
+pub mod mutation;
+pub mod organ;
 pub mod osc;
+pub mod parameters;
+pub mod phaser;
+pub mod pitch_shifter;
 pub mod plaits_voice;
 pub mod plot;
+pub mod presets;
+pub mod recorder;
+pub mod resampler;
 pub mod reverb;
+pub mod sampler;
+pub mod saturation;
+pub mod scales;
+pub mod scope;
 pub mod sequencer;
+pub mod shared;
+pub mod sidechain;
+pub mod simd;
+pub mod smoothing;
+pub mod snapshot;
+pub mod stretch;
 pub mod subtractive;
 pub mod synth;
+pub mod tuning;
 pub mod utils;
+pub mod velocity;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-// Callback type definition
-type PlaybackProgressCallback = extern "C" fn(f32);
+static NEXT_EVENT_ID: AtomicU32 = AtomicU32::new(1);
+pub(crate) static NEXT_NOTE_ID: AtomicU32 = AtomicU32::new(1);
 
-type NotePlayedCallback = extern "C" fn(bool, u8, u8);
+pub(crate) fn next_event_id() -> u32 {
+    NEXT_EVENT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// messages waiting for the audio thread; the channel is bounded so it is
+// backed by a preallocated lock-free ring buffer
+pub(crate) const MESSAGE_CAPACITY: usize = 4096;
+
+/// A sequencer event as passed to `add_event` and `update_event`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EventC {
+    pub beat_time: f32,
+    pub duration: f32,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub track: u8,
+    pub param1: f32,
+    pub param2: f32,
+    /// chance of the event playing, 0.0 to 1.0
+    pub probability: f32,
+    /// 0: always, 1: on loop `condition_a` of every `condition_b`, 2: first
+    /// loop only, 3: all but the first loop
+    pub condition: u8,
+    pub condition_a: u8,
+    pub condition_b: u8,
+    pub nudge_ms: f32,
+    /// number of hits (ratchets), 1 plays the event once
+    pub retrigger_count: u8,
+    /// beats between ratchets, 0.0 spreads them over the duration
+    pub retrigger_rate: f32,
+}
+
+impl EventC {
+    fn to_event(self, id: u32) -> Event {
+        Event {
+            id,
+            beat_time: self.beat_time,
+            pitch: self.pitch,
+            velocity: self.velocity,
+            duration: self.duration,
+            track: self.track,
+            param1: self.param1,
+            param2: self.param2,
+            probability: self.probability,
+            condition: TrigCondition::from_u8(self.condition, self.condition_a, self.condition_b),
+            nudge_ms: self.nudge_ms,
+            retrigger_count: self.retrigger_count,
+            retrigger_rate: self.retrigger_rate,
+            expression: NoteExpression::default(),
+            locks: ParameterLocks::default(),
+        }
+    }
+}
+
+/// A parameter change as passed to `set_parameter`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ParamChangeC {
+    pub track: u8,
+    /// voice parameter, drum parameter or from `EQ_PARAMETER_OFFSET` on, an
+    /// EQ parameter
+    pub parameter: i8,
+    pub value: f32,
+}
+
+// how long saving a preset waits for the audio thread
+const PRESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref PRESET_BANK: Mutex<PresetBank> = Mutex::new(PresetBank::new());
+}
+
+fn get_handle<'a>(handle: *const EngineHandle) -> &'a EngineHandle {
+    unsafe {
+        assert!(!handle.is_null());
+        &*handle
+    }
+}
+
+fn get_engine<'a>(engine: *mut Engine) -> &'a mut Engine {
+    unsafe {
+        assert!(!engine.is_null());
+        &mut *engine
+    }
+}
+
+fn get_c_str<'a>(string: *const c_char) -> &'a CStr {
+    unsafe {
+        assert!(!string.is_null());
+        CStr::from_ptr(string)
+    }
+}
+
+fn get_slice<'a, T>(values: *const T, len: usize) -> &'a [T] {
+    unsafe {
+        assert!(!values.is_null());
+        std::slice::from_raw_parts(values, len)
+    }
+}
+
+fn get_slice_mut<'a, T>(values: *mut T, len: usize) -> &'a mut [T] {
+    unsafe {
+        assert!(!values.is_null());
+        std::slice::from_raw_parts_mut(values, len)
+    }
+}
+
+/// Hand `value` to the host through an out parameter
+fn write_out<T>(out: *mut T, value: T) {
+    unsafe {
+        assert!(!out.is_null());
+        *out = value;
+    }
+}
+
+/// Drop a value boxed for the host, null is ignored
+fn free_boxed<T>(boxed: *mut T) {
+    if !boxed.is_null() {
+        unsafe {
+            drop(Box::from_raw(boxed));
+        }
+    }
+}
+
+/// Queue a message for the engine, false when the queue is full
+fn send(handle: *const EngineHandle, msg: Message) -> bool {
+    get_handle(handle).send(msg).is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn set_playback_progress_callback(
+    handle: *const EngineHandle,
+    callback: PlaybackProgressCallback,
+    context: *mut c_void,
+) {
+    let shared = get_handle(handle).shared();
+    shared.set_progress_callback(callback, context);
+}
+
+#[no_mangle]
+pub extern "C" fn set_note_played_callback(
+    handle: *const EngineHandle,
+    callback: NotePlayedCallback,
+    context: *mut c_void,
+) {
+    let shared = get_handle(handle).shared();
+    shared.set_note_callback(callback, context);
+}
+
+#[no_mangle]
+pub extern "C" fn set_transport_callback(
+    handle: *const EngineHandle,
+    callback: TransportCallback,
+    context: *mut c_void,
+) {
+    let shared = get_handle(handle).shared();
+    shared.set_transport_callback(callback, context);
+}
+
+#[no_mangle]
+pub extern "C" fn set_metering_callback(
+    handle: *const EngineHandle,
+    callback: MeteringCallback,
+    context: *mut c_void,
+) {
+    let shared = get_handle(handle).shared();
+    shared.set_metering_callback(callback, context);
+}
+
+#[no_mangle]
+pub extern "C" fn get_track_level(handle: *const EngineHandle, track: u8) -> Level {
+    get_handle(handle).shared().track_level(track as usize)
+}
+
+#[no_mangle]
+pub extern "C" fn get_master_level(handle: *const EngineHandle) -> Level {
+    get_handle(handle).shared().master_level()
+}
+
+#[cfg(feature = "analyzer")]
+#[no_mangle]
+pub extern "C" fn set_spectrum_analyzer(
+    handle: *const EngineHandle,
+    enabled: bool,
+    size: u32,
+) -> bool {
+    // planned here, off the audio thread
+    let analyzer = enabled.then(|| Box::new(SpectrumAnalyzer::new(size as usize)));
+    send(handle, Message::SpectrumAnalyzer(analyzer))
+}
+
+#[cfg(feature = "analyzer")]
+#[no_mangle]
+pub extern "C" fn get_spectrum(handle: *const EngineHandle, bins: *mut f32, len: usize) -> usize {
+    let bins = get_slice_mut(bins, len);
+    get_handle(handle).shared().spectrum(bins)
+}
+
+/// Create an engine, along with the handle that sends it messages and
+/// sets its callbacks; every engine has its own, so several can run at once
+#[no_mangle]
+pub extern "C" fn engine_init(sample_rate: f32, handle: *mut *mut EngineHandle) -> *mut Engine {
+    let (engine, engine_handle) = EngineBuilder::new(sample_rate).build();
+    write_out(handle, Box::into_raw(Box::new(engine_handle)));
+    Box::into_raw(Box::new(engine))
+}
+
+/// Like `engine_init`, processing at `processing_rate` whatever the host's
+/// `sample_rate`, converting between them at the boundary
+#[no_mangle]
+pub extern "C" fn engine_init_with_processing_rate(
+    sample_rate: f32,
+    processing_rate: f32,
+    handle: *mut *mut EngineHandle,
+) -> *mut Engine {
+    let (engine, engine_handle) = EngineBuilder::new(sample_rate)
+        .processing_rate(processing_rate)
+        .build();
+    write_out(handle, Box::into_raw(Box::new(engine_handle)));
+    Box::into_raw(Box::new(engine))
+}
+
+/// Follow a change of the host's sample rate, the engine keeps processing
+/// at the rate it was created with. Not while rendering.
+#[no_mangle]
+pub extern "C" fn set_host_sample_rate(engine: *mut Engine, sample_rate: f32) {
+    let engine = get_engine(engine);
+    engine.set_host_rate(sample_rate);
+}
+
+#[no_mangle]
+pub extern "C" fn set_play_pause(engine: *mut Engine, is_playing: bool) {
+    let engine = get_engine(engine);
+    engine.set_playing(is_playing);
+}
+
+/// Stop every sounding note
+#[no_mangle]
+pub extern "C" fn all_notes_off(handle: *const EngineHandle) -> bool {
+    send(handle, Message::AllNotesOff)
+}
+
+#[no_mangle]
+pub extern "C" fn render_offline(
+    engine: *mut Engine,
+    bars: u32,
+    tempo: f32,
+    path: *const c_char,
+    format: u8,
+) -> bool {
+    let engine = get_engine(engine);
+    let path = get_c_str(path);
+    let (Ok(path), Some(format)) = (path.to_str(), WavFormat::from_u8(format)) else {
+        return false;
+    };
+    engine.render_offline(bars, tempo, path, format).is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn render_stems(
+    engine: *mut Engine,
+    bars: u32,
+    tempo: f32,
+    directory: *const c_char,
+    format: u8,
+    include_buses: bool,
+) -> bool {
+    let engine = get_engine(engine);
+    let directory = get_c_str(directory);
+    let (Ok(directory), Some(format)) = (directory.to_str(), WavFormat::from_u8(format)) else {
+        return false;
+    };
+    engine
+        .render_stems(bars, tempo, directory, format, include_buses)
+        .is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn set_metronome(handle: *const EngineHandle, enabled: bool, volume: f32) -> bool {
+    send(handle, Message::Metronome { enabled, volume })
+}
+
+#[no_mangle]
+pub extern "C" fn set_count_in(handle: *const EngineHandle, bars: u32) -> bool {
+    send(handle, Message::CountIn(bars))
+}
+
+#[no_mangle]
+pub extern "C" fn set_position_beats(handle: *const EngineHandle, beats: f32) -> bool {
+    send(handle, Message::SetPosition(beats))
+}
+
+#[no_mangle]
+pub extern "C" fn set_loop_markers(handle: *const EngineHandle, start: f32, end: f32) -> bool {
+    send(handle, Message::LoopMarkers { start, end })
+}
+
+#[no_mangle]
+pub extern "C" fn set_one_shot(handle: *const EngineHandle, one_shot: bool) -> bool {
+    send(handle, Message::OneShot(one_shot))
+}
+
+/// Length in milliseconds, up to 5.0, of the crossfade hiding the click of a
+/// sounding voice being restarted or stolen; 0.0 turns it off
+#[no_mangle]
+pub extern "C" fn set_declick_time(handle: *const EngineHandle, ms: f32) -> bool {
+    send(handle, Message::DeclickTime(ms))
+}
+
+/// What stopping the transport does to the sounding notes, 0: flush them
+/// with a short fade, 1: release them, 2: let them play to their note offs;
+/// with `rewind` the transport starts from the beginning again, otherwise
+/// it resumes where it stopped
+#[no_mangle]
+pub extern "C" fn set_stop_behavior(handle: *const EngineHandle, mode: u8, rewind: bool) -> bool {
+    let Some(mode) = StopMode::from_u8(mode) else {
+        return false;
+    };
+    send(handle, Message::StopBehavior { mode, rewind })
+}
+
+/// Zero-based bar of the transport position at the end of the last
+/// rendered buffer
+#[no_mangle]
+pub extern "C" fn get_current_bar(handle: *const EngineHandle) -> u32 {
+    get_handle(handle).shared().bar_and_beat().0
+}
+
+/// Zero-based beat within the current bar, see `get_current_bar`
+#[no_mangle]
+pub extern "C" fn get_current_beat(handle: *const EngineHandle) -> u32 {
+    get_handle(handle).shared().bar_and_beat().1
+}
+
+/// Delay of the output in samples, to report to the host. The sequence is
+/// rendered ahead by this much unless latency compensation is turned off.
+#[no_mangle]
+pub extern "C" fn get_latency_samples(engine: *mut Engine) -> u32 {
+    let engine = get_engine(engine);
+    engine.latency() as u32
+}
+
+/// Turn off when the host delays everything else by the reported latency
+#[no_mangle]
+pub extern "C" fn set_latency_compensation(handle: *const EngineHandle, enabled: bool) -> bool {
+    send(handle, Message::LatencyCompensation(enabled))
+}
+
+#[no_mangle]
+pub extern "C" fn set_scope_source(handle: *const EngineHandle, track: i8) -> bool {
+    // negative for the master output
+    let track = u8::try_from(track).ok();
+    send(handle, Message::ScopeSource(track))
+}
+
+#[no_mangle]
+pub extern "C" fn get_scope_snapshot(
+    handle: *const EngineHandle,
+    ms: f32,
+    points: *mut f32,
+    len: usize,
+) -> usize {
+    let points = get_slice_mut(points, len);
+    get_handle(handle).shared().scope().snapshot(ms, points)
+}
+
+#[no_mangle]
+pub extern "C" fn set_recording(handle: *const EngineHandle, enabled: bool) -> bool {
+    send(handle, Message::Recording(enabled))
+}
+
+#[no_mangle]
+pub extern "C" fn set_record_quantize(handle: *const EngineHandle, grid: f32) -> bool {
+    send(handle, Message::RecordQuantize(grid))
+}
+
+/// The new event's id, 0 when the engine's queue is full
+#[no_mangle]
+pub extern "C" fn add_event(handle: *const EngineHandle, event: EventC) -> u32 {
+    let id = next_event_id();
+    if !send(handle, Message::Schedule(event.to_event(id))) {
+        return 0;
+    }
+    id
+}
+
+/// Convert `count` events under new ids, writing the ids to `ids` unless
+/// it's null
+fn events_with_ids(events: *const EventC, count: usize, ids: *mut u32) -> Vec<Event> {
+    let events = get_slice(events, count);
+    let events: Vec<Event> = events
+        .iter()
+        .map(|event| event.to_event(next_event_id()))
+        .collect();
+    if !ids.is_null() {
+        let ids = unsafe { std::slice::from_raw_parts_mut(ids, count) };
+        for (id, event) in ids.iter_mut().zip(&events) {
+            *id = event.id;
+        }
+    }
+    events
+}
+
+/// Add `count` events in one go, writing their ids to `ids` unless it's null
+#[no_mangle]
+pub extern "C" fn add_events(
+    handle: *const EngineHandle,
+    events: *const EventC,
+    count: usize,
+    ids: *mut u32,
+) -> bool {
+    let events = events_with_ids(events, count, ids);
+    send(handle, Message::ScheduleBatch(events))
+}
+
+/// Stage `count` events to replace the edited pattern's events once
+/// committed, writing their ids to `ids` unless it's null
+#[no_mangle]
+pub extern "C" fn stage_events(
+    handle: *const EngineHandle,
+    events: *const EventC,
+    count: usize,
+    ids: *mut u32,
+) -> bool {
+    let events = events_with_ids(events, count, ids);
+    send(handle, Message::StageEvents(events))
+}
+
+/// Swap the staged events into the edited pattern at the next loop
+/// boundary, or right away
+#[no_mangle]
+pub extern "C" fn commit_pattern(handle: *const EngineHandle, immediately: bool) -> bool {
+    send(handle, Message::CommitPattern { immediately })
+}
+
+#[no_mangle]
+pub extern "C" fn update_event(handle: *const EngineHandle, id: u32, event: EventC) -> bool {
+    send(handle, Message::UpdateEvent(event.to_event(id)))
+}
+
+#[no_mangle]
+pub extern "C" fn remove_event(handle: *const EngineHandle, id: u32) -> bool {
+    send(handle, Message::RemoveEvent(id))
+}
+
+#[no_mangle]
+pub extern "C" fn set_event_nudge(handle: *const EngineHandle, id: u32, nudge_ms: f32) -> bool {
+    send(handle, Message::Nudge { id, nudge_ms })
+}
+
+#[no_mangle]
+pub extern "C" fn add_event_lock(
+    handle: *const EngineHandle,
+    event_id: u32,
+    parameter: i8,
+    value: f32,
+) -> bool {
+    send(
+        handle,
+        Message::EventLock {
+            id: event_id,
+            parameter,
+            value: Some(value),
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn remove_event_lock(
+    handle: *const EngineHandle,
+    event_id: u32,
+    parameter: i8,
+) -> bool {
+    send(
+        handle,
+        Message::EventLock {
+            id: event_id,
+            parameter,
+            value: None,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn note_on(
+    handle: *const EngineHandle,
+    pitch: u8,
+    velocity: u8,
+    track: u8,
+    _: f32,
+    _: f32,
+    pitch_bend: f32,
+    pressure: f32,
+    timbre: f32,
+) -> u32 {
+    note_on_at(
+        handle, pitch, velocity, track, 0.0, 0.0, pitch_bend, pressure, timbre, 0,
+    )
+}
+
+/// The note's id, 0 when the engine's queue is full
+#[no_mangle]
+pub extern "C" fn note_on_at(
+    handle: *const EngineHandle,
+    pitch: u8,
+    velocity: u8,
+    track: u8,
+    _: f32,
+    _: f32,
+    pitch_bend: f32,
+    pressure: f32,
+    timbre: f32,
+    frame_offset: u32,
+) -> u32 {
+    let id = NEXT_NOTE_ID.fetch_add(1, Ordering::Relaxed);
+    let sent = send(
+        handle,
+        Message::NoteOn {
+            id,
+            track,
+            pitch,
+            velocity,
+            expression: NoteExpression {
+                pitch_bend,
+                pressure,
+                timbre,
+            },
+            frame: frame_offset,
+        },
+    );
+    if !sent {
+        return 0;
+    }
+    id
+}
+
+#[no_mangle]
+pub extern "C" fn update_note_expression(
+    handle: *const EngineHandle,
+    note_id: u32,
+    dimension: u8,
+    value: f32,
+) -> bool {
+    let Some(dimension) = ExpressionDimension::from_u8(dimension) else {
+        return false;
+    };
+    send(
+        handle,
+        Message::NoteExpression {
+            id: note_id,
+            dimension,
+            value,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn note_off(handle: *const EngineHandle, pitch: u8, track: u8) -> bool {
+    note_off_at(handle, pitch, track, 0)
+}
+
+#[no_mangle]
+pub extern "C" fn note_off_at(
+    handle: *const EngineHandle,
+    pitch: u8,
+    track: u8,
+    frame_offset: u32,
+) -> bool {
+    send(
+        handle,
+        Message::NoteOff {
+            track,
+            pitch,
+            frame: frame_offset,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_pitch_bend(handle: *const EngineHandle, track: u8, bend: f32) -> bool {
+    send(handle, Message::PitchBend { track, bend })
+}
+
+#[no_mangle]
+pub extern "C" fn set_pitch_bend_range(
+    handle: *const EngineHandle,
+    track: u8,
+    semitones: f32,
+) -> bool {
+    send(handle, Message::PitchBendRange { track, semitones })
+}
+
+#[no_mangle]
+pub extern "C" fn handle_midi_message(
+    handle: *const EngineHandle,
+    bytes: *const u8,
+    len: usize,
+) -> bool {
+    handle_midi_message_at(handle, bytes, len, 0)
+}
+
+#[no_mangle]
+pub extern "C" fn handle_midi_message_at(
+    handle: *const EngineHandle,
+    bytes: *const u8,
+    len: usize,
+    frame_offset: u32,
+) -> bool {
+    let bytes = get_slice(bytes, len);
+    midi_parse::parse(bytes).into_iter().all(|msg| {
+        send(
+            handle,
+            Message::Midi {
+                message: msg,
+                frame: frame_offset,
+            },
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn set_sound(handle: *const EngineHandle, sound: u8, track: u8) -> bool {
+    let Some(voice_type) = VoiceType::from_u8(sound) else {
+        return false;
+    };
+    send(handle, Message::SetSound { track, voice_type })
+}
+
+#[no_mangle]
+pub extern "C" fn load_sample(handle: *const EngineHandle, track: u8, path: *const c_char) -> bool {
+    let path = get_c_str(path);
+    let sample = match path.to_str().map(Sample::load) {
+        Ok(Ok(sample)) => sample,
+        _ => return false,
+    };
+    // converted here, off the audio thread, so the sampler plays it at its
+    // own rate
+    let sample = sample.resampled(get_handle(handle).sample_rate(), ResamplerQuality::High);
+    send(
+        handle,
+        Message::LoadSample {
+            track,
+            sample: Arc::new(sample),
+        },
+    )
+}
+
+/// Chop the track's sample into `count` equal slices, played from the root
+/// pitch up once the sampler's slice parameter is on
+#[no_mangle]
+pub extern "C" fn slice_sample_equally(handle: *const EngineHandle, track: u8, count: u8) -> bool {
+    let slices = Arc::from(equal_slices(count as usize));
+    send(handle, Message::SampleSlices { track, slices })
+}
+
+/// Chop the track's sample at `count` points, normalized to its length;
+/// they're sorted and clamped to the sample
+#[no_mangle]
+pub extern "C" fn set_sample_slices(
+    handle: *const EngineHandle,
+    track: u8,
+    points: *const f32,
+    count: usize,
+) -> bool {
+    let points = get_slice(points, count);
+    let slices = Arc::from(sorted_slices(points));
+    send(handle, Message::SampleSlices { track, slices })
+}
+
+/// Copy the track's slice points into `points`, returning how many there
+/// are
+#[no_mangle]
+pub extern "C" fn get_sample_slices(
+    handle: *const EngineHandle,
+    track: u8,
+    points: *mut f32,
+    len: usize,
+) -> usize {
+    let points = get_slice_mut(points, len);
+    get_handle(handle)
+        .shared()
+        .sample_slices(track as usize, points)
+}
+
+/// Find slice points at the transients of the track's sample (see
+/// `Sample::detect_transients`), copying them into `points` and returning
+/// how many were found. They aren't applied, pass them on, adjusted or not,
+/// to `set_sample_slices`.
+#[no_mangle]
+pub extern "C" fn detect_sample_transients(
+    handle: *const EngineHandle,
+    track: u8,
+    sensitivity: f32,
+    points: *mut f32,
+    len: usize,
+) -> usize {
+    let points = get_slice_mut(points, len);
+    let Some(sample) = get_handle(handle).shared().sample(track as usize) else {
+        return 0;
+    };
+    let slices = sample.detect_transients(sensitivity);
+    for (point, slice) in points.iter_mut().zip(&slices) {
+        *point = *slice;
+    }
+    slices.len()
+}
+
+#[no_mangle]
+pub extern "C" fn set_parameter(handle: *const EngineHandle, change: ParamChangeC) -> bool {
+    send(
+        handle,
+        Message::ParameterChange(change.parameter, change.value, change.track),
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_parameter_smoothing(
+    handle: *const EngineHandle,
+    smoothing_type: u8,
+    time_ms: f32,
+) -> bool {
+    let Some(smoothing_type) = SmoothingType::from_u8(smoothing_type) else {
+        return false;
+    };
+    send(
+        handle,
+        Message::ParameterSmoothing {
+            smoothing_type,
+            time_ms,
+        },
+    )
+}
+
+/// Glide parameter changes linearly across the next rendered block instead
+/// of over the smoothing time, for host automation at low block rates
+#[no_mangle]
+pub extern "C" fn set_block_ramps(handle: *const EngineHandle, enabled: bool) -> bool {
+    send(handle, Message::BlockRamps(enabled))
+}
+
+/// Capture the track's parameters into snapshot `slot`, 0 for A and 1 for B
+#[no_mangle]
+pub extern "C" fn capture_snapshot(handle: *const EngineHandle, track: u8, slot: u8) -> bool {
+    send(handle, Message::CaptureSnapshot { track, slot })
+}
+
+/// Morph the track's parameters between its snapshots, from A at 0.0 to B
+/// at 1.0
+#[no_mangle]
+pub extern "C" fn set_morph(handle: *const EngineHandle, track: u8, amount: f32) -> bool {
+    send(handle, Message::Morph { track, amount })
+}
+
+#[no_mangle]
+pub extern "C" fn get_voice_parameter_count(voice_type: u8) -> u8 {
+    VoiceType::from_u8(voice_type).map_or(0, |voice_type| voice_type.parameters().len() as u8)
+}
+
+#[no_mangle]
+pub extern "C" fn get_voice_parameter_info(
+    voice_type: u8,
+    index: u8,
+    info: *mut ParameterDescription,
+) -> bool {
+    let Some(voice_type) = VoiceType::from_u8(voice_type) else {
+        return false;
+    };
+    describe_parameter(&voice_type.parameters(), index, info)
+}
+
+#[no_mangle]
+pub extern "C" fn get_effect_parameter_count(effect_type: u8, sample_rate: f32) -> u8 {
+    EffectType::from_u8(effect_type).map_or(0, |effect_type| {
+        effect_type.parameters(sample_rate).len() as u8
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn get_effect_parameter_info(
+    effect_type: u8,
+    index: u8,
+    sample_rate: f32,
+    info: *mut ParameterDescription,
+) -> bool {
+    let Some(effect_type) = EffectType::from_u8(effect_type) else {
+        return false;
+    };
+    describe_parameter(&effect_type.parameters(sample_rate), index, info)
+}
 
-lazy_static! {
-    static ref CHANNEL: Mutex<(channel::Sender<Message>, channel::Receiver<Message>)> =
-        Mutex::new(channel::unbounded());
-    static ref PROGRESS_CALLBACK: Mutex<Option<PlaybackProgressCallback>> = Mutex::new(None);
-    static ref NOTE_CALLBACK: Mutex<Option<NotePlayedCallback>> = Mutex::new(None);
+/// Render a cycle of a global LFO's shape into the `len` points of
+/// `buffer`, 0.0..1.0, for drawing; false for an unknown shape
+#[no_mangle]
+pub extern "C" fn render_lfo_cycle(shape: u8, buffer: *mut f32, len: usize) -> bool {
+    let buffer = get_slice_mut(buffer, len);
+    let Some(shape) = LfoShape::from_u8(shape) else {
+        return false;
+    };
+    plot::render_lfo(shape, buffer);
+    true
 }
 
-fn get_sender() -> channel::Sender<Message> {
-    CHANNEL.lock().unwrap().0.clone()
+/// Render the envelope of an FM operator with these attack and decay times
+/// into the `len` points of `buffer`, for drawing. Returns the time the
+/// buffer spans in milliseconds.
+#[no_mangle]
+pub extern "C" fn render_operator_envelope(
+    attack_ms: f32,
+    decay_ms: f32,
+    sample_rate: f32,
+    buffer: *mut f32,
+    len: usize,
+) -> f32 {
+    let buffer = get_slice_mut(buffer, len);
+    let envelope = AR::new(attack_ms, decay_ms, OPERATOR_CURVE, sample_rate);
+    plot::render_ar(&envelope, buffer)
 }
 
-fn get_receiver() -> channel::Receiver<Message> {
-    CHANNEL.lock().unwrap().1.clone()
+#[no_mangle]
+pub extern "C" fn get_eq_parameter_count() -> u8 {
+    Eq3::parameters().len() as u8
 }
 
 #[no_mangle]
-pub extern "C" fn set_playback_progress_callback(callback: PlaybackProgressCallback) {
-    let mut cb = PROGRESS_CALLBACK.lock().unwrap();
-    *cb = Some(callback);
+pub extern "C" fn get_eq_parameter_info(index: u8, info: *mut ParameterDescription) -> bool {
+    describe_parameter(&Eq3::parameters(), index, info)
+}
+
+fn describe_parameter(
+    parameters: &[ParameterInfo],
+    index: u8,
+    info: *mut ParameterDescription,
+) -> bool {
+    let Some(parameter) = parameters.get(index as usize) else {
+        return false;
+    };
+    write_out(info, ParameterDescription::from(parameter));
+    true
 }
 
 #[no_mangle]
-pub extern "C" fn set_note_played_callback(callback: NotePlayedCallback) {
-    let mut cb = NOTE_CALLBACK.lock().unwrap();
-    *cb = Some(callback);
+pub extern "C" fn set_swing(handle: *const EngineHandle, track: u8, amount: f32) -> bool {
+    send(handle, Message::Swing { track, amount })
 }
 
 #[no_mangle]
-pub extern "C" fn engine_init(sample_rate: f32) -> *mut Engine {
-    let rx = get_receiver();
-    let engine = Engine::new(rx, sample_rate);
-    Box::into_raw(Box::new(engine))
+pub extern "C" fn set_humanize(
+    handle: *const EngineHandle,
+    track: u8,
+    timing_ms: f32,
+    velocity: f32,
+    repeat: bool,
+) -> bool {
+    let humanize = Humanize {
+        timing_ms,
+        velocity,
+        repeat,
+    };
+    send(handle, Message::Humanize { track, humanize })
 }
 
 #[no_mangle]
-pub extern "C" fn set_play_pause(engine: *mut Engine, is_playing: bool) {
-    let engine = unsafe {
-        assert!(!engine.is_null());
-        &mut *engine
+pub extern "C" fn set_mutation(
+    handle: *const EngineHandle,
+    amount: f32,
+    seed: u64,
+    lock_seed: bool,
+    every_bars: u32,
+) -> bool {
+    let mutation = Mutation {
+        amount,
+        seed: lock_seed.then_some(seed),
+        every_bars,
     };
-    engine.is_playing = is_playing;
+    send(handle, Message::Mutation(mutation))
 }
 
 #[no_mangle]
-pub extern "C" fn add_event(
-    beat_time: f32,
-    pitch: u8,
-    velocity: u8,
-    duration: f32,
+pub extern "C" fn mutate_pattern(handle: *const EngineHandle) -> bool {
+    send(handle, Message::Mutate)
+}
+
+#[no_mangle]
+pub extern "C" fn set_scale(
+    handle: *const EngineHandle,
     track: u8,
-    param1: f32,
-    param2: f32,
-) {
-    let sender = get_sender();
-    let event = Event {
-        beat_time,
-        pitch,
-        velocity,
-        duration,
-        track,
-        param1,
-        param2,
+    root: u8,
+    scale_id: u8,
+) -> bool {
+    let Some(scale) = Scale::from_u8(scale_id) else {
+        return false;
+    };
+    send(handle, Message::Scale { track, root, scale })
+}
+
+/// `curve` 0: linear, 1: exponential, 2: fixed at `value`
+#[no_mangle]
+pub extern "C" fn set_velocity_curve(
+    handle: *const EngineHandle,
+    track: u8,
+    curve: u8,
+    value: u8,
+) -> bool {
+    let Some(curve) = VelocityCurve::from_u8(curve, value) else {
+        return false;
+    };
+    send(handle, Message::VelocityCurve { track, curve })
+}
+
+/// `mode` 0: restart from silence, 1: continue from the level reached,
+/// 2: legato, only triggering finished envelopes
+#[no_mangle]
+pub extern "C" fn set_retrigger_mode(handle: *const EngineHandle, track: u8, mode: u8) -> bool {
+    let Some(mode) = RetriggerMode::from_u8(mode) else {
+        return false;
+    };
+    send(handle, Message::RetriggerMode { track, mode })
+}
+
+/// `table` holds VELOCITY_TABLE_SIZE velocities, the one played for each
+/// incoming velocity
+#[no_mangle]
+pub extern "C" fn set_velocity_table(
+    handle: *const EngineHandle,
+    track: u8,
+    table: *const u8,
+) -> bool {
+    let table = get_slice(table, VELOCITY_TABLE_SIZE);
+    let curve = VelocityCurve::Table(table.try_into().unwrap());
+    send(handle, Message::VelocityCurve { track, curve })
+}
+
+/// Sweep a track parameter between `min` and `max` with global LFO
+/// `index`, once every `beats`. `shape` 0: sine, 1: triangle, 2: ramp up,
+/// 3: ramp down, 4: square
+#[no_mangle]
+pub extern "C" fn set_track_lfo(
+    handle: *const EngineHandle,
+    index: u8,
+    track: u8,
+    parameter: i8,
+    shape: u8,
+    beats: f32,
+    min: f32,
+    max: f32,
+) -> bool {
+    let Some(shape) = LfoShape::from_u8(shape) else {
+        return false;
+    };
+    let target = LfoTarget::Track { track, parameter };
+    send(
+        handle,
+        Message::GlobalLfo {
+            index,
+            lfo: Some(GlobalLfo::new(shape, beats, min, max, target)),
+        },
+    )
+}
+
+/// Sweep a parameter of an effect on a send bus with global LFO `index`,
+/// see `set_track_lfo`
+#[no_mangle]
+pub extern "C" fn set_bus_lfo(
+    handle: *const EngineHandle,
+    index: u8,
+    bus: u8,
+    effect: u8,
+    parameter: i8,
+    shape: u8,
+    beats: f32,
+    min: f32,
+    max: f32,
+) -> bool {
+    let Some(shape) = LfoShape::from_u8(shape) else {
+        return false;
+    };
+    let target = LfoTarget::Bus {
+        bus,
+        effect,
+        parameter,
+    };
+    send(
+        handle,
+        Message::GlobalLfo {
+            index,
+            lfo: Some(GlobalLfo::new(shape, beats, min, max, target)),
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn clear_global_lfo(handle: *const EngineHandle, index: u8) -> bool {
+    send(handle, Message::GlobalLfo { index, lfo: None })
+}
+
+/// Assign a track parameter to `slot` of one of the track's macros, taking
+/// `min` to `max` as the macro turns. `curve` 0: linear, 1: exponential,
+/// 2: logarithmic
+#[no_mangle]
+pub extern "C" fn set_macro_destination(
+    handle: *const EngineHandle,
+    track: u8,
+    index: u8,
+    slot: u8,
+    parameter: i8,
+    min: f32,
+    max: f32,
+    curve: u8,
+) -> bool {
+    let Some(curve) = MacroCurve::from_u8(curve) else {
+        return false;
+    };
+    send(
+        handle,
+        Message::MacroAssign {
+            track,
+            index,
+            slot,
+            destination: Some(MacroDestination {
+                parameter,
+                min,
+                max,
+                curve,
+            }),
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn clear_macro_destination(
+    handle: *const EngineHandle,
+    track: u8,
+    index: u8,
+    slot: u8,
+) -> bool {
+    send(
+        handle,
+        Message::MacroAssign {
+            track,
+            index,
+            slot,
+            destination: None,
+        },
+    )
+}
+
+/// `value` 0.0..1.0
+#[no_mangle]
+pub extern "C" fn set_macro(handle: *const EngineHandle, track: u8, index: u8, value: f32) -> bool {
+    send(
+        handle,
+        Message::Macro {
+            track,
+            index,
+            value,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_chord(
+    handle: *const EngineHandle,
+    track: u8,
+    chord_type: u8,
+    inversion: u8,
+    spread: u8,
+) -> bool {
+    let Some(chord_type) = ChordType::from_u8(chord_type) else {
+        return false;
+    };
+    send(
+        handle,
+        Message::Chord {
+            track,
+            chord: Some(Chord::new(chord_type, inversion, spread)),
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_chord_intervals(
+    handle: *const EngineHandle,
+    track: u8,
+    intervals: *const u8,
+    len: usize,
+    inversion: u8,
+    spread: u8,
+) -> bool {
+    let intervals = get_slice(intervals, len);
+    send(
+        handle,
+        Message::Chord {
+            track,
+            chord: Chord::from_intervals(intervals, inversion, spread),
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn clear_chord(handle: *const EngineHandle, track: u8) -> bool {
+    send(handle, Message::Chord { track, chord: None })
+}
+
+#[no_mangle]
+pub extern "C" fn set_user_scale(handle: *const EngineHandle, index: u8, mask: u16) -> bool {
+    send(
+        handle,
+        Message::UserScale {
+            index: index as usize,
+            mask,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_mod_slot(
+    handle: *const EngineHandle,
+    track: u8,
+    slot: u8,
+    source: u8,
+    destination: u8,
+    depth: f32,
+) -> bool {
+    let destination = match ModDestination::from_u8(destination) {
+        Some(destination) => destination,
+        None => return false,
+    };
+    send(
+        handle,
+        Message::ModSlot {
+            track,
+            index: slot as usize,
+            slot: ModSlot::new(ModSource::from_u8(source), destination, depth),
+        },
+    )
+}
+
+/// Shape the source of a mod slot. `shaper` 0: none, 1: sample and hold at
+/// `a` Hz, or on every note at zero, 2: slew rising over `a` ms and falling
+/// over `b` ms, 3: quantize to steps of `a`
+#[no_mangle]
+pub extern "C" fn set_mod_shaper(
+    handle: *const EngineHandle,
+    track: u8,
+    slot: u8,
+    shaper: u8,
+    a: f32,
+    b: f32,
+) -> bool {
+    let Some(shaper) = ModShaper::from_u8(shaper, a, b) else {
+        return false;
+    };
+    send(
+        handle,
+        Message::ModShaper {
+            track,
+            index: slot as usize,
+            shaper,
+        },
+    )
+}
+
+/// The new bus's index, -1 once all buses are taken or when the engine's
+/// queue is full
+#[no_mangle]
+pub extern "C" fn create_send_bus(handle: *const EngineHandle, name: *const c_char) -> i8 {
+    let name = if name.is_null() {
+        String::new()
+    } else {
+        get_c_str(name).to_string_lossy().into_owned()
+    };
+    let shared = get_handle(handle).shared();
+    let Some(index) = shared.next_bus() else {
+        return -1;
+    };
+    if !send(handle, Message::CreateBus(SendBus::new(&name))) {
+        shared.cancel_bus();
+        return -1;
+    }
+    index as i8
+}
+
+#[no_mangle]
+pub extern "C" fn add_bus_effect(handle: *const EngineHandle, bus: u8, effect_type: u8) -> bool {
+    let Some(effect_type) = EffectType::from_u8(effect_type) else {
+        return false;
+    };
+    // created here, off the audio thread, which only adds it
+    let effect = create_effect(effect_type, get_handle(handle).sample_rate());
+    send(
+        handle,
+        Message::AddBusEffect {
+            bus,
+            effect_type,
+            effect,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_bus_effect_parameter(
+    handle: *const EngineHandle,
+    bus: u8,
+    effect: u8,
+    parameter: i8,
+    value: f32,
+) -> bool {
+    send(
+        handle,
+        Message::BusEffectParameter {
+            bus,
+            effect,
+            parameter,
+            value,
+        },
+    )
+}
+
+/// Load a mono or stereo WAV impulse response into the convolution
+/// effect at index `effect` on a bus, transformed for an engine running at
+/// `sample_rate`. Returns false if the file can't be read.
+#[cfg(feature = "convolution")]
+#[no_mangle]
+pub extern "C" fn load_bus_impulse_response(
+    handle: *const EngineHandle,
+    bus: u8,
+    effect: u8,
+    path: *const c_char,
+    sample_rate: f32,
+) -> bool {
+    let Some(ir) = load_impulse_response(path, sample_rate) else {
+        return false;
+    };
+    send(handle, Message::BusImpulseResponse { bus, effect, ir })
+}
+
+#[no_mangle]
+pub extern "C" fn add_track_insert(
+    handle: *const EngineHandle,
+    track: u8,
+    effect_type: u8,
+) -> bool {
+    let Some(effect_type) = EffectType::from_u8(effect_type) else {
+        return false;
+    };
+    let effect = create_effect(effect_type, get_handle(handle).sample_rate());
+    send(
+        handle,
+        Message::AddTrackInsert {
+            track,
+            effect_type,
+            effect,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_track_insert_parameter(
+    handle: *const EngineHandle,
+    track: u8,
+    effect: u8,
+    parameter: i8,
+    value: f32,
+) -> bool {
+    send(
+        handle,
+        Message::TrackInsertParameter {
+            track,
+            effect,
+            parameter,
+            value,
+        },
+    )
+}
+
+/// Load an impulse response into a convolution insert on a track, see
+/// `load_bus_impulse_response`
+#[cfg(feature = "convolution")]
+#[no_mangle]
+pub extern "C" fn load_track_insert_impulse_response(
+    handle: *const EngineHandle,
+    track: u8,
+    effect: u8,
+    path: *const c_char,
+    sample_rate: f32,
+) -> bool {
+    let Some(ir) = load_impulse_response(path, sample_rate) else {
+        return false;
+    };
+    send(
+        handle,
+        Message::TrackInsertImpulseResponse { track, effect, ir },
+    )
+}
+
+// the impulse response is transformed here, on the calling thread, so the
+// audio thread only swaps it in
+#[cfg(feature = "convolution")]
+fn load_impulse_response(path: *const c_char, sample_rate: f32) -> Option<Arc<ImpulseResponse>> {
+    let path = get_c_str(path);
+    let ir = path.to_str().ok()?;
+    ImpulseResponse::load(ir, sample_rate).ok().map(Arc::new)
+}
+
+#[no_mangle]
+pub extern "C" fn add_input_insert(handle: *const EngineHandle, effect_type: u8) -> bool {
+    let Some(effect_type) = EffectType::from_u8(effect_type) else {
+        return false;
+    };
+    let effect = create_effect(effect_type, get_handle(handle).sample_rate());
+    send(handle, Message::AddInputInsert(effect_type, effect))
+}
+
+#[no_mangle]
+pub extern "C" fn set_input_insert_parameter(
+    handle: *const EngineHandle,
+    effect: u8,
+    parameter: i8,
+    value: f32,
+) -> bool {
+    send(
+        handle,
+        Message::InputInsertParameter {
+            effect,
+            parameter,
+            value,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_input_send(
+    handle: *const EngineHandle,
+    bus: u8,
+    level: f32,
+    pre_fader: bool,
+) -> bool {
+    send(
+        handle,
+        Message::InputSend {
+            bus,
+            send: TrackSend { level, pre_fader },
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_input_monitor(handle: *const EngineHandle, level: f32) -> bool {
+    send(handle, Message::InputMonitor(level))
+}
+
+/// `threshold` 0 to start right away, `track` negative to only keep the
+/// recording under its name
+#[no_mangle]
+pub extern "C" fn record_sample(
+    handle: *const EngineHandle,
+    name: *const c_char,
+    source: u8,
+    threshold: f32,
+    synced: bool,
+    track: i8,
+    max_frames: u32,
+) -> bool {
+    let name = get_c_str(name);
+    let Some(source) = RecordSource::from_u8(source) else {
+        return false;
+    };
+    let settings = RecordSettings {
+        name: name.to_string_lossy().into_owned(),
+        source,
+        threshold: (threshold > 0.0).then_some(threshold),
+        synced,
+        track: u8::try_from(track).ok(),
+    };
+    let buffer = Vec::with_capacity(max_frames as usize);
+    send(handle, Message::Record { settings, buffer })
+}
+
+#[no_mangle]
+pub extern "C" fn stop_recording(handle: *const EngineHandle) -> bool {
+    send(handle, Message::StopRecording)
+}
+
+#[no_mangle]
+pub extern "C" fn load_recorded_sample(
+    handle: *const EngineHandle,
+    track: u8,
+    name: *const c_char,
+) -> bool {
+    let name = get_c_str(name);
+    send(
+        handle,
+        Message::LoadRecordedSample {
+            track,
+            name: name.to_string_lossy().into_owned(),
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_bus_return(handle: *const EngineHandle, bus: u8, level: f32) -> bool {
+    send(handle, Message::BusReturn { bus, level })
+}
+
+#[no_mangle]
+pub extern "C" fn set_bus_freeze(handle: *const EngineHandle, bus: u8, freeze: bool) -> bool {
+    send(handle, Message::BusFreeze { bus, freeze })
+}
+
+#[no_mangle]
+pub extern "C" fn set_send(
+    handle: *const EngineHandle,
+    track: u8,
+    bus: u8,
+    level: f32,
+    pre_fader: bool,
+) -> bool {
+    send(
+        handle,
+        Message::TrackSend {
+            track,
+            bus,
+            send: TrackSend { level, pre_fader },
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_track_volume(handle: *const EngineHandle, track: u8, volume: f32) -> bool {
+    send(handle, Message::TrackVolume { track, volume })
+}
+
+#[no_mangle]
+pub extern "C" fn set_track_pan(handle: *const EngineHandle, track: u8, pan: f32) -> bool {
+    send(handle, Message::TrackPan { track, pan })
+}
+
+#[no_mangle]
+pub extern "C" fn set_track_mute(handle: *const EngineHandle, track: u8, mute: bool) -> bool {
+    send(handle, Message::TrackMute { track, mute })
+}
+
+#[no_mangle]
+pub extern "C" fn set_track_solo(handle: *const EngineHandle, track: u8, solo: bool) -> bool {
+    send(handle, Message::TrackSolo { track, solo })
+}
+
+/// Play a track on one of the stereo outputs of `render_outputs`, 0 for the
+/// main one
+#[no_mangle]
+pub extern "C" fn set_track_output(handle: *const EngineHandle, track: u8, output: u8) -> bool {
+    send(handle, Message::TrackOutput { track, output })
+}
+
+/// Master tune in cents, -100.0..100.0
+#[no_mangle]
+pub extern "C" fn set_master_tune(handle: *const EngineHandle, cents: f32) -> bool {
+    send(handle, Message::MasterTune(cents))
+}
+
+/// Master tune putting A4 at `freq`, e.g. 432.0 Hz
+#[no_mangle]
+pub extern "C" fn set_reference_pitch(handle: *const EngineHandle, freq: f32) -> bool {
+    send(handle, Message::MasterTune(reference_cents(freq)))
+}
+
+/// Transpose a track's notes by `transpose` semitones and fine tune it by
+/// `cents`, -100.0..100.0. Drum tracks aren't transposed.
+#[no_mangle]
+pub extern "C" fn set_track_tuning(
+    handle: *const EngineHandle,
+    track: u8,
+    transpose: i8,
+    cents: f32,
+) -> bool {
+    send(
+        handle,
+        Message::TrackTuning {
+            track,
+            transpose,
+            cents,
+        },
+    )
+}
+
+/// Retune the notes to the Scala (.scl) scale at `path`, `root` keeping its
+/// equal-tempered pitch
+#[no_mangle]
+pub extern "C" fn load_scala_scale(
+    handle: *const EngineHandle,
+    path: *const c_char,
+    root: u8,
+) -> bool {
+    let path = get_c_str(path);
+    let scale = match path.to_str().map(ScalaScale::load) {
+        Ok(Ok(scale)) => scale,
+        _ => return false,
+    };
+    send(
+        handle,
+        Message::TuningScale {
+            scale: Some(Arc::new(scale)),
+            root,
+        },
+    )
+}
+
+/// Back to equal temperament
+#[no_mangle]
+pub extern "C" fn clear_scala_scale(handle: *const EngineHandle) -> bool {
+    send(
+        handle,
+        Message::TuningScale {
+            scale: None,
+            root: 0,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_master_volume(handle: *const EngineHandle, volume: f32) -> bool {
+    send(handle, Message::MasterVolume(volume))
+}
+
+#[no_mangle]
+pub extern "C" fn set_dc_blocking(handle: *const EngineHandle, enabled: bool) -> bool {
+    send(handle, Message::DcBlocking(enabled))
+}
+
+#[no_mangle]
+pub extern "C" fn set_track_sidechain(
+    handle: *const EngineHandle,
+    track: u8,
+    source: u8,
+    amount: f32,
+    attack: f32,
+    release: f32,
+) -> bool {
+    send(
+        handle,
+        Message::Sidechain {
+            target: SidechainTarget::Track(track),
+            source,
+            amount,
+            attack,
+            release,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_bus_sidechain(
+    handle: *const EngineHandle,
+    bus: u8,
+    source: u8,
+    amount: f32,
+    attack: f32,
+    release: f32,
+) -> bool {
+    send(
+        handle,
+        Message::Sidechain {
+            target: SidechainTarget::Bus(bus),
+            source,
+            amount,
+            attack,
+            release,
+        },
+    )
+}
+
+/// The new pattern's index, 255 when the engine's queue is full
+#[no_mangle]
+pub extern "C" fn create_pattern(
+    handle: *const EngineHandle,
+    name: *const c_char,
+    length: f32,
+) -> u8 {
+    let name = if name.is_null() {
+        String::new()
+    } else {
+        get_c_str(name).to_string_lossy().into_owned()
+    };
+    let shared = get_handle(handle).shared();
+    let index = shared.next_pattern();
+    if !send(handle, Message::CreatePattern { name, length }) {
+        shared.cancel_pattern();
+        return u8::MAX;
+    }
+    index as u8
+}
+
+#[no_mangle]
+pub extern "C" fn set_time_signature(
+    handle: *const EngineHandle,
+    pattern: u8,
+    numerator: u8,
+    denominator: u8,
+) -> bool {
+    let Some(time_signature) = TimeSignature::new(numerator, denominator) else {
+        return false;
     };
-    sender.send(Message::Schedule(event)).unwrap();
+    send(
+        handle,
+        Message::TimeSignature {
+            pattern: pattern as usize,
+            time_signature,
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn set_edit_pattern(handle: *const EngineHandle, pattern: u8) -> bool {
+    send(handle, Message::EditPattern(pattern as usize))
+}
+
+#[no_mangle]
+pub extern "C" fn queue_pattern(handle: *const EngineHandle, pattern: u8) -> bool {
+    send(handle, Message::QueuePattern(pattern as usize))
+}
+
+#[no_mangle]
+pub extern "C" fn append_chain(handle: *const EngineHandle, pattern: u8, repeats: u32) -> bool {
+    send(
+        handle,
+        Message::AppendChain(ChainEntry {
+            pattern: pattern as usize,
+            repeats,
+        }),
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn clear_chain(handle: *const EngineHandle) -> bool {
+    send(handle, Message::ClearChain)
+}
+
+/// Index of the pattern playing at the end of the last rendered buffer
+#[no_mangle]
+pub extern "C" fn get_current_pattern(handle: *const EngineHandle) -> u8 {
+    get_handle(handle).shared().current_pattern() as u8
+}
+
+#[no_mangle]
+pub extern "C" fn clear_events(handle: *const EngineHandle) -> bool {
+    send(handle, Message::Clear)
 }
 
+/// Save the engine's sound and sequence to `path` as JSON, waiting for the
+/// audio thread to capture it. False when the engine doesn't answer, e.g.
+/// because it isn't rendering, or the file can't be written.
 #[no_mangle]
-pub extern "C" fn note_on(_: *mut Engine, _: u8, velocity: u8, track: u8, _: f32, _: f32) {
-    let sender = get_sender();
-    sender.send(Message::NoteOn { track, velocity }).unwrap();
+pub extern "C" fn save_preset(handle: *const EngineHandle, path: *const c_char) -> bool {
+    save_preset_with(handle, path, |preset, path| preset.save(path))
 }
 
+/// Save the engine's sound and sequence to `path` in the binary format, see
+/// `save_preset`. `load_preset` reads either.
 #[no_mangle]
-pub extern "C" fn note_off(_: *mut Engine, _: u8, _: u8) {
-    // not implemented
-    todo!("not implemented")
+pub extern "C" fn save_preset_binary(handle: *const EngineHandle, path: *const c_char) -> bool {
+    save_preset_with(handle, path, |preset, path| preset.save_binary(path))
+}
+
+fn save_preset_with(
+    handle: *const EngineHandle,
+    path: *const c_char,
+    save: impl FnOnce(&Preset, &str) -> std::io::Result<()>,
+) -> bool {
+    let path = get_c_str(path);
+    let Ok(path) = path.to_str() else {
+        return false;
+    };
+    match get_handle(handle).capture_preset(PRESET_TIMEOUT) {
+        Ok(preset) => save(&preset, path).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Make sure newly added events don't reuse ids of a loaded preset's events
+fn reserve_event_ids(preset: &Preset) {
+    if let Some(max_id) = preset.events.iter().map(|ev| ev.id).max() {
+        NEXT_EVENT_ID.fetch_max(max_id + 1, Ordering::Relaxed);
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn set_sound(_: *mut Engine, _: u8, _: u8) {
-    todo!("not implemented")
+pub extern "C" fn load_preset(handle: *const EngineHandle, path: *const c_char) -> bool {
+    let path = get_c_str(path);
+    let preset = match path.to_str().map(Preset::load) {
+        Ok(Ok(preset)) => preset,
+        _ => return false,
+    };
+    reserve_event_ids(&preset);
+    get_handle(handle).load_preset(preset).is_ok()
 }
 
+/// Keep the engine's sound and sequence in the preset bank, see
+/// `save_preset`
 #[no_mangle]
-pub extern "C" fn set_parameter(parameter: i8, value: f32, track: u8) {
-    let sender = get_sender();
-    sender
-        .send(Message::ParameterChange(parameter, value, track))
-        .unwrap();
+pub extern "C" fn store_preset(handle: *const EngineHandle, slot: u8) -> bool {
+    match get_handle(handle).capture_preset(PRESET_TIMEOUT) {
+        Ok(preset) => {
+            PRESET_BANK.lock().unwrap().store(slot as usize, preset);
+            true
+        }
+        Err(_) => false,
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn clear_events() {
-    let sender = get_sender();
-    sender.send(Message::Clear).unwrap();
+pub extern "C" fn recall_preset(handle: *const EngineHandle, slot: u8) -> bool {
+    let preset = match PRESET_BANK.lock().unwrap().get(slot as usize) {
+        Some(preset) => preset.clone(),
+        None => return false,
+    };
+    get_handle(handle).load_preset(preset).is_ok()
 }
 
+/// `in_l` and `in_r` are the host's audio input, or null without one
 #[no_mangle]
 pub extern "C" fn render(
     engine: *mut Engine,
+    in_l: *const c_float,
+    in_r: *const c_float,
     buf_l: *mut c_float,
     buf_r: *mut c_float,
     sample_time: i64,
     tempo: f32,
     num_frames: i32,
 ) {
-    let engine = unsafe {
-        assert!(!engine.is_null());
-        &mut *engine
-    };
-    let buf_l = unsafe { std::slice::from_raw_parts_mut(buf_l, num_frames as usize) };
-    let buf_r = unsafe { std::slice::from_raw_parts_mut(buf_r, num_frames as usize) };
-    engine.process(buf_l, buf_r, sample_time, tempo, num_frames);
+    let engine = get_engine(engine);
+    let input = (!in_l.is_null() && !in_r.is_null()).then(|| {
+        (
+            get_slice(in_l, num_frames as usize),
+            get_slice(in_r, num_frames as usize),
+        )
+    });
+    let buf_l = get_slice_mut(buf_l, num_frames as usize);
+    let buf_r = get_slice_mut(buf_r, num_frames as usize);
+    engine.process_with_input(input, buf_l, buf_r, sample_time, tempo, num_frames);
 }
 
+/// Render into `output_count` stereo outputs, `outputs` holding the left and
+/// right channel of each in turn. The first is the main output; tracks
+/// routed to an output that isn't passed play on the main one.
 #[no_mangle]
-pub extern "C" fn engine_free(ptr: *mut Engine) {
-    if !ptr.is_null() {
-        unsafe {
-            drop(Box::from_raw(ptr));
-        }
+pub extern "C" fn render_outputs(
+    engine: *mut Engine,
+    in_l: *const c_float,
+    in_r: *const c_float,
+    outputs: *const *mut c_float,
+    output_count: usize,
+    sample_time: i64,
+    tempo: f32,
+    num_frames: i32,
+) {
+    // nothing to render into
+    if output_count == 0 {
+        return;
     }
+    let engine = get_engine(engine);
+    let input = (!in_l.is_null() && !in_r.is_null()).then(|| {
+        (
+            get_slice(in_l, num_frames as usize),
+            get_slice(in_r, num_frames as usize),
+        )
+    });
+    let channels = get_slice(outputs, output_count * 2);
+    let channel =
+        |index: usize| -> &mut [f32] { get_slice_mut(channels[index], num_frames as usize) };
+    let output_count = output_count.min(MAX_OUTPUTS);
+    // kept on the stack, this runs on the audio thread
+    let mut buffers: [[&mut [f32]; 2]; MAX_OUTPUTS] = std::array::from_fn(|output| {
+        if output < output_count {
+            [channel(output * 2), channel(output * 2 + 1)]
+        } else {
+            [&mut [][..], &mut [][..]]
+        }
+    });
+    engine.process_outputs(
+        input,
+        &mut buffers[..output_count],
+        sample_time,
+        tempo,
+        num_frames,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn engine_free(ptr: *mut Engine) {
+    free_boxed(ptr);
+}
+
+#[no_mangle]
+pub extern "C" fn engine_handle_free(handle: *mut EngineHandle) {
+    free_boxed(handle);
 }
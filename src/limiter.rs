@@ -1,38 +1,142 @@
-/*
-  adapted from https://www.musicdsp.org/en/latest/Filters/265-output-limiter-using-envelope-follower-in-c.html
-  not actually sure if it works as it should
-*/
+//! Look-ahead brickwall limiter
+//!
+//! The input is delayed by the look-ahead time, so the gain computer sees
+//! every peak before it reaches the output and can ramp the gain down in
+//! time. Peaks are estimated between samples as well (true peak), and the
+//! gain recovers with an exponential release. Stereo input is limited with
+//! one gain for both channels, so the image doesn't shift.
+
+use crate::utils::undenormalize;
+use std::collections::VecDeque;
 
 pub struct Limiter {
     threshold: f32,
-    env_follower: EnvelopeFollower,
-    sample_rate: f32,
+    lookahead: usize,
+    // delayed input, left and right
+    buffer: Vec<[f32; 2]>,
+    // required gains that can still be the lowest in the look-ahead window,
+    // with the frame they're from, rising from front to back
+    minima: VecDeque<(u64, f32)>,
+    frame: u64,
+    // held gain per sample, for the moving average
+    gains: Vec<f32>,
+    gain_sum: f64,
+    pos: usize,
+    history: [[f32; 4]; 2],
+    gain: f32,
+    release: f32,
 }
 
 impl Limiter {
-    pub fn new(attack: f32, release: f32, threshold: f32, sample_rate: f32) -> Self {
+    /// `lookahead` and `release` are in milliseconds, `threshold` is the
+    /// linear output ceiling
+    pub fn new(lookahead: f32, release: f32, threshold: f32, sample_rate: f32) -> Self {
+        let lookahead = ((lookahead * sample_rate * 0.001) as usize).max(1);
         Self {
             threshold,
-            env_follower: EnvelopeFollower::new(attack, release, sample_rate),
-            sample_rate,
+            lookahead,
+            buffer: vec![[0.0; 2]; lookahead],
+            // never holds more than the window, so it doesn't grow
+            minima: VecDeque::with_capacity(lookahead),
+            frame: 0,
+            gains: vec![1.0; lookahead],
+            gain_sum: lookahead as f64,
+            pos: 0,
+            history: [[0.0; 4]; 2],
+            gain: 1.0,
+            release: 0.01_f32.powf(1.0 / (release.max(0.01) * sample_rate * 0.001)),
         }
     }
 
+    /// Delay of the output relative to the input, in samples
+    pub fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill([0.0; 2]);
+        self.minima.clear();
+        self.gains.fill(1.0);
+        self.gain_sum = self.lookahead as f64;
+        self.history = [[0.0; 4]; 2];
+        self.gain = 1.0;
+    }
+
     #[inline]
     pub fn process(&mut self, input: f32) -> f32 {
-        self.env_follower.process(input);
-        if self.env_follower.env > self.threshold {
-            input / self.env_follower.env
+        self.process_stereo(input, input).0
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        for (history, input) in self.history.iter_mut().zip([left, right]) {
+            *history = [history[1], history[2], history[3], input];
+        }
+        let peak = Self::true_peak(&self.history[0]).max(Self::true_peak(&self.history[1]));
+        let target = if peak > self.threshold {
+            self.threshold / peak
+        } else {
+            1.0
+        };
+
+        // hold the lowest gain required anywhere in the look-ahead window
+        let window = self.lookahead as u64;
+        while let Some(&(frame, _)) = self.minima.front() {
+            if frame + window > self.frame {
+                break;
+            }
+            self.minima.pop_front();
+        }
+        while self.minima.back().is_some_and(|&(_, gain)| gain >= target) {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((self.frame, target));
+        self.frame += 1;
+        let held = self.minima.front().map_or(1.0, |&(_, gain)| gain);
+
+        // attack instantly, release smoothly
+        self.gain = if held < self.gain {
+            held
         } else {
-            input
+            held + self.release * (self.gain - held)
+        };
+
+        // average over the window so the gain ramps down ahead of each peak
+        self.gain_sum += (self.gain - self.gains[self.pos]) as f64;
+        self.gains[self.pos] = self.gain;
+        let gain = (self.gain_sum / self.lookahead as f64) as f32;
+
+        let [delayed_left, delayed_right] = self.buffer[self.pos];
+        self.buffer[self.pos] = [left, right];
+        self.pos = (self.pos + 1) % self.lookahead;
+
+        (delayed_left * gain, delayed_right * gain)
+    }
+
+    /// Peak of the segment between the middle two samples, with three
+    /// interpolated points in between
+    #[inline]
+    fn true_peak(x: &[f32; 4]) -> f32 {
+        let mut peak = x[1].abs().max(x[2].abs());
+        for t in [0.25, 0.5, 0.75] {
+            peak = peak.max(Self::catmull_rom(x, t).abs());
         }
+        peak
+    }
+
+    #[inline]
+    fn catmull_rom(x: &[f32; 4], t: f32) -> f32 {
+        let a = -0.5 * x[0] + 1.5 * x[1] - 1.5 * x[2] + 0.5 * x[3];
+        let b = x[0] - 2.5 * x[1] + 2.0 * x[2] - 0.5 * x[3];
+        let c = -0.5 * x[0] + 0.5 * x[2];
+        ((a * t + b) * t + c) * t + x[1]
     }
 }
 
-struct EnvelopeFollower {
+pub struct EnvelopeFollower {
     attack: f32,
     release: f32,
-    env: f32,
+    pub env: f32,
 }
 
 impl EnvelopeFollower {
@@ -51,7 +155,7 @@ impl EnvelopeFollower {
         if v > self.env {
             self.env = self.attack * (self.env - v) + v
         } else {
-            self.env = self.release * (self.env - v) + v
+            self.env = undenormalize(self.release * (self.env - v) + v)
         }
     }
 }
@@ -59,6 +163,7 @@ impl EnvelopeFollower {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::f32::consts::PI;
 
     #[test]
     fn creates_new_limiter() {
@@ -69,23 +174,71 @@ mod tests {
         let limiter = Limiter::new(attack, release, threshold, sample_rate);
 
         assert_eq!(limiter.threshold, 0.5);
+        assert_eq!(limiter.latency(), 24);
     }
 
     #[test]
     fn test_limiter() {
-        let attack = 0.0;
-        let release = 0.0;
+        let lookahead = 5.0;
+        let release = 50.0;
         let threshold = 0.1;
         let sample_rate = 48000.0;
-        let mut limiter = Limiter::new(attack, release, threshold, sample_rate);
-
-        // should limit value
-        assert_eq!(limiter.process(1.0), 1.0);
-        assert_eq!(limiter.process(1.0), 1.0);
-        assert_eq!(limiter.process(1.0), 1.0);
-        assert_eq!(limiter.process(1.0), 1.0);
-        assert_eq!(limiter.process(1.0), 1.0);
-        assert_eq!(limiter.process(1.0), 1.0);
+        let mut limiter = Limiter::new(lookahead, release, threshold, sample_rate);
+
+        // should limit value, including the very first peak
+        for i in 0..48000 {
+            let x = 4.0 * (2.0 * PI * 220.0 * i as f32 / sample_rate).sin();
+            assert!(limiter.process(x).abs() <= threshold + 1e-6);
+        }
+    }
+
+    #[test]
+    fn quiet_signal_is_only_delayed() {
+        let mut limiter = Limiter::new(1.0, 50.0, 0.9, 48000.0);
+        let latency = limiter.latency();
+        let xs: Vec<f32> = (0..1000).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let ys: Vec<f32> = xs.iter().map(|&x| limiter.process(x)).collect();
+        for (y, x) in ys[latency..].iter().zip(xs.iter()) {
+            assert!((y - x).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn catches_intersample_peaks() {
+        // a quarter sample rate sine sampled 45 degrees off its peaks:
+        // samples stay at 0.707 while the waveform reaches 1.0
+        let mut limiter = Limiter::new(1.0, 50.0, 0.8, 48000.0);
+        let ys: Vec<f32> = (0..2000)
+            .map(|i| limiter.process((PI * 0.5 * i as f32 + PI * 0.25).sin()))
+            .collect();
+        let max = ys[1000..].iter().fold(0.0_f32, |a, y| a.max(y.abs()));
+        assert!(max < 0.7);
+    }
+
+    #[test]
+    fn gain_recovers_after_release() {
+        let sample_rate = 48000.0;
+        let mut limiter = Limiter::new(1.0, 10.0, 0.5, sample_rate);
+        for _ in 0..100 {
+            limiter.process(1.0);
+        }
+        let mut y = 0.0;
+        for _ in 0..4800 {
+            y = limiter.process(0.25);
+        }
+        assert!((y - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn stereo_channels_share_gain() {
+        let mut limiter = Limiter::new(1.0, 100.0, 0.5, 48000.0);
+        let mut y = (0.0, 0.0);
+        for _ in 0..1000 {
+            y = limiter.process_stereo(1.0, 0.25);
+        }
+        // the loud channel pulls the quiet one down with it
+        assert!(y.0 <= 0.5 + 1e-3);
+        assert!((y.1 - y.0 * 0.25).abs() < 1e-6);
     }
 
     #[test]
@@ -0,0 +1,131 @@
+//! Macro controls
+//!
+//! Each track has two macros: single knobs a host can expose without
+//! knowing the track's parameter map. Turning a macro sets every parameter
+//! assigned to it, each scaled into its own range through its own curve.
+
+/// macros per track
+pub const MACRO_COUNT: usize = 2;
+
+/// parameters one macro can set
+pub const MAX_MACRO_DESTINATIONS: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MacroCurve {
+    #[default]
+    Linear,
+    /// slow at the start of the macro's range, fast at the end
+    Exponential,
+    /// fast at the start, slow at the end
+    Logarithmic,
+}
+
+impl MacroCurve {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(MacroCurve::Linear),
+            1 => Some(MacroCurve::Exponential),
+            2 => Some(MacroCurve::Logarithmic),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            MacroCurve::Linear => x,
+            MacroCurve::Exponential => x * x,
+            MacroCurve::Logarithmic => x * (2.0 - x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacroDestination {
+    /// track parameter, as indexed by `Message::ParameterChange`
+    pub parameter: i8,
+    /// value with the macro all the way down
+    pub min: f32,
+    /// value with the macro all the way up, below `min` to invert it
+    pub max: f32,
+    pub curve: MacroCurve,
+}
+
+impl MacroDestination {
+    /// The parameter's value for a macro at `x`, 0.0..1.0
+    pub fn value(&self, x: f32) -> f32 {
+        self.min + (self.max - self.min) * self.curve.apply(x.clamp(0.0, 1.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Macro {
+    destinations: [Option<MacroDestination>; MAX_MACRO_DESTINATIONS],
+    value: f32,
+}
+
+impl Macro {
+    /// Assign a parameter to one of the macro's slots, None clears the slot
+    pub fn assign(&mut self, slot: usize, destination: Option<MacroDestination>) {
+        if let Some(assigned) = self.destinations.get_mut(slot) {
+            *assigned = destination;
+        }
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        self.value = value.clamp(0.0, 1.0);
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// The value of every assigned parameter at the macro's position
+    pub fn parameters(&self) -> impl Iterator<Item = (i8, f32)> + '_ {
+        self.destinations
+            .iter()
+            .flatten()
+            .map(|destination| (destination.parameter, destination.value(self.value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destinations_follow_their_ranges() {
+        let mut m = Macro::default();
+        m.assign(
+            0,
+            Some(MacroDestination {
+                parameter: 2,
+                min: 100.0,
+                max: 1100.0,
+                curve: MacroCurve::Exponential,
+            }),
+        );
+        m.assign(
+            3,
+            Some(MacroDestination {
+                parameter: 4,
+                min: 1.0,
+                max: 0.0,
+                curve: MacroCurve::Linear,
+            }),
+        );
+        // out of range slots are ignored
+        m.assign(MAX_MACRO_DESTINATIONS, None);
+
+        m.set_value(0.5);
+        assert_eq!(m.parameters().collect::<Vec<_>>(), [(2, 350.0), (4, 0.5)]);
+        m.set_value(2.0);
+        assert_eq!(m.value(), 1.0);
+        assert_eq!(m.parameters().collect::<Vec<_>>(), [(2, 1100.0), (4, 0.0)]);
+
+        m.assign(0, None);
+        assert_eq!(m.parameters().count(), 1);
+        assert_eq!(MacroCurve::Logarithmic.apply(0.5), 0.75);
+        assert_eq!(MacroCurve::from_u8(3), None);
+    }
+}
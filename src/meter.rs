@@ -0,0 +1,117 @@
+//! Level metering
+//!
+//! A `LevelMeter` follows the peak of a signal, falling back exponentially
+//! after each peak, and its RMS over a fixed window. The engine meters every
+//! track after its fader and the master after the limiter.
+
+// time for the peak to fall back to 1/e of its value
+const PEAK_DECAY_MS: f32 = 300.0;
+const RMS_WINDOW_MS: f32 = 300.0;
+
+/// Peak and RMS level, linear
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Level {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl Level {
+    /// Both values packed into one word, so they can be published atomically
+    pub fn to_bits(self) -> u64 {
+        (self.peak.to_bits() as u64) << 32 | self.rms.to_bits() as u64
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            peak: f32::from_bits((bits >> 32) as u32),
+            rms: f32::from_bits(bits as u32),
+        }
+    }
+}
+
+pub struct LevelMeter {
+    peak: f32,
+    decay: f32,
+    square_sum: f32,
+    count: usize,
+    window: usize,
+    rms: f32,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            peak: 0.0,
+            decay: (-1.0 / (PEAK_DECAY_MS * 0.001 * sample_rate)).exp(),
+            square_sum: 0.0,
+            count: 0,
+            window: ((RMS_WINDOW_MS * 0.001 * sample_rate) as usize).max(1),
+            rms: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) {
+        self.measure(x.abs(), x * x);
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, left: f32, right: f32) {
+        self.measure(
+            left.abs().max(right.abs()),
+            (left * left + right * right) * 0.5,
+        );
+    }
+
+    #[inline]
+    fn measure(&mut self, magnitude: f32, square: f32) {
+        self.peak = magnitude.max(self.peak * self.decay);
+
+        // the RMS is updated once per window
+        self.square_sum += square;
+        self.count += 1;
+        if self.count == self.window {
+            self.rms = (self.square_sum / self.window as f32).sqrt();
+            self.square_sum = 0.0;
+            self.count = 0;
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        Level {
+            peak: self.peak,
+            rms: self.rms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{PI, SQRT_2};
+
+    #[test]
+    fn measures_peak_and_rms() {
+        let sample_rate = 48000.0;
+        let mut meter = LevelMeter::new(sample_rate);
+        for i in 0..48000 {
+            meter.process(0.5 * (2.0 * PI * 100.0 * i as f32 / sample_rate).sin());
+        }
+        let level = meter.level();
+        assert!((level.peak - 0.5).abs() < 1e-2);
+        assert!((level.rms - 0.5 / SQRT_2).abs() < 1e-2);
+
+        // the peak falls back after the signal stops
+        for _ in 0..4800 {
+            meter.process(0.0);
+        }
+        let peak = meter.level().peak;
+        assert!(peak < 0.5 && peak > 0.2);
+        for _ in 0..48000 {
+            meter.process(0.0);
+        }
+        assert_eq!(meter.level().rms, 0.0);
+        assert_eq!(Level::from_bits(level.to_bits()), level);
+    }
+}
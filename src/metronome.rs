@@ -0,0 +1,95 @@
+//! Metronome click generator
+
+use crate::envelopes::{CurveType, AR};
+use crate::osc::{Osc, Waveform};
+
+const ACCENT_FREQ: f32 = 1600.0;
+const CLICK_FREQ: f32 = 800.0;
+
+pub struct Metronome {
+    pub enabled: bool,
+    pub volume: f32,
+    pub beats_per_bar: u32,
+    osc: Osc,
+    env: AR,
+    last_beat: i64,
+}
+
+impl Metronome {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            enabled: false,
+            volume: 0.5,
+            beats_per_bar: 4,
+            osc: Osc::new(Waveform::Sine, sample_rate),
+            env: AR::new(0.0, 30.0, CurveType::Exponential { pow: 3 }, sample_rate),
+            last_beat: -1,
+        }
+    }
+
+    /// Restart the beat count, so the next beat is treated as a new one
+    pub fn reset(&mut self) {
+        self.last_beat = -1;
+    }
+
+    /// Render one sample at `beat_position` (in beats since the transport
+    /// started), clicking at each new beat and accenting bar starts. Clicks
+    /// are generated even when disabled if `force` is set (count-in).
+    #[inline]
+    pub fn process(&mut self, beat_position: f32, force: bool) -> f32 {
+        let beat = beat_position.floor() as i64;
+        if beat != self.last_beat && beat >= 0 {
+            self.last_beat = beat;
+            if self.enabled || force {
+                let is_accent = beat % self.beats_per_bar.max(1) as i64 == 0;
+                self.osc
+                    .set_freq(if is_accent { ACCENT_FREQ } else { CLICK_FREQ });
+                self.osc.reset();
+                self.env.trigger(if is_accent { 127 } else { 90 });
+            }
+        }
+
+        if !self.env.is_active() {
+            return 0.0;
+        }
+        self.osc.process() * self.env.process() * self.volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_when_disabled() {
+        let mut metronome = Metronome::new(48000.0);
+        for i in 0..1000 {
+            assert_eq!(metronome.process(i as f32 / 100.0, false), 0.0);
+        }
+    }
+
+    #[test]
+    fn clicks_on_each_beat() {
+        let sample_rate = 48000.0;
+        let mut metronome = Metronome::new(sample_rate);
+        metronome.enabled = true;
+
+        // 4 beats, 1000 samples per beat
+        let ys: Vec<f32> = (0..4000)
+            .map(|i| metronome.process(i as f32 / 1000.0, false))
+            .collect();
+        for beat in 0..4 {
+            let click = &ys[beat * 1000..beat * 1000 + 100];
+            assert!(click.iter().any(|y| y.abs() > 0.01));
+        }
+    }
+
+    #[test]
+    fn count_in_forces_clicks() {
+        let mut metronome = Metronome::new(48000.0);
+        let ys: Vec<f32> = (0..100)
+            .map(|i| metronome.process(i as f32 / 1000.0, true))
+            .collect();
+        assert!(ys.iter().any(|y| y.abs() > 0.01));
+    }
+}
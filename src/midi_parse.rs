@@ -0,0 +1,242 @@
+//! Raw MIDI byte stream decoding
+
+/// pitch bend range in semitones (up and down)
+pub const PITCH_BEND_RANGE: f32 = 2.0;
+
+/// controller numbers from this offset onward are mapped to voice parameters
+pub const CC_PARAMETER_OFFSET: u8 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        pitch: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        pitch: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// bend amount, normalized to -1.0..1.0
+    PitchBend {
+        channel: u8,
+        bend: f32,
+    },
+    /// pressure amount, normalized to 0.0..1.0
+    ChannelPressure {
+        channel: u8,
+        pressure: f32,
+    },
+}
+
+impl MidiMessage {
+    pub fn channel(&self) -> u8 {
+        match *self {
+            MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::PitchBend { channel, .. }
+            | MidiMessage::ChannelPressure { channel, .. } => channel,
+        }
+    }
+}
+
+/// Number of data bytes following a channel voice status byte
+fn data_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
+
+fn decode(status: u8, data: &[u8]) -> Option<MidiMessage> {
+    let channel = status & 0x0F;
+    match status & 0xF0 {
+        0x80 => Some(MidiMessage::NoteOff {
+            channel,
+            pitch: data[0],
+        }),
+        // a note on with zero velocity is a note off
+        0x90 if data[1] == 0 => Some(MidiMessage::NoteOff {
+            channel,
+            pitch: data[0],
+        }),
+        0x90 => Some(MidiMessage::NoteOn {
+            channel,
+            pitch: data[0],
+            velocity: data[1],
+        }),
+        0xB0 => Some(MidiMessage::ControlChange {
+            channel,
+            controller: data[0],
+            value: data[1],
+        }),
+        0xD0 => Some(MidiMessage::ChannelPressure {
+            channel,
+            pressure: data[0] as f32 / 127.0,
+        }),
+        0xE0 => {
+            let value = ((data[1] as i32) << 7) | data[0] as i32;
+            Some(MidiMessage::PitchBend {
+                channel,
+                bend: (value - 8192) as f32 / 8192.0,
+            })
+        }
+        // polyphonic aftertouch and program change are ignored
+        _ => None,
+    }
+}
+
+/// Parse a buffer of raw MIDI bytes, which may contain several messages
+/// and use running status. System messages are skipped.
+pub fn parse(bytes: &[u8]) -> Vec<MidiMessage> {
+    let mut messages = Vec::new();
+    let mut running_status: Option<u8> = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if byte >= 0xF0 {
+            // system messages cancel running status, real-time ones don't
+            if byte < 0xF8 {
+                running_status = None;
+            }
+            i += 1;
+            continue;
+        }
+
+        let status = if byte & 0x80 != 0 {
+            i += 1;
+            running_status = Some(byte);
+            byte
+        } else {
+            match running_status {
+                Some(status) => status,
+                None => {
+                    // stray data byte
+                    i += 1;
+                    continue;
+                }
+            }
+        };
+
+        let len = data_length(status);
+        if i + len > bytes.len() {
+            break;
+        }
+        if let Some(message) = decode(status, &bytes[i..i + len]) {
+            messages.push(message);
+        }
+        i += len;
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_note_on_and_off() {
+        assert_eq!(
+            parse(&[0x91, 60, 100]),
+            vec![MidiMessage::NoteOn {
+                channel: 1,
+                pitch: 60,
+                velocity: 100
+            }]
+        );
+        assert_eq!(
+            parse(&[0x80, 60, 64]),
+            vec![MidiMessage::NoteOff {
+                channel: 0,
+                pitch: 60
+            }]
+        );
+    }
+
+    #[test]
+    fn zero_velocity_note_on_is_note_off() {
+        assert_eq!(
+            parse(&[0x90, 60, 0]),
+            vec![MidiMessage::NoteOff {
+                channel: 0,
+                pitch: 60
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_control_change() {
+        assert_eq!(
+            parse(&[0xB2, 21, 127]),
+            vec![MidiMessage::ControlChange {
+                channel: 2,
+                controller: 21,
+                value: 127
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_pitch_bend() {
+        assert_eq!(
+            parse(&[0xE0, 0x00, 0x40]),
+            vec![MidiMessage::PitchBend {
+                channel: 0,
+                bend: 0.0
+            }]
+        );
+        assert_eq!(
+            parse(&[0xE0, 0x00, 0x00]),
+            vec![MidiMessage::PitchBend {
+                channel: 0,
+                bend: -1.0
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_channel_pressure() {
+        assert_eq!(
+            parse(&[0xD3, 127]),
+            vec![MidiMessage::ChannelPressure {
+                channel: 3,
+                pressure: 1.0
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_running_status() {
+        let messages = parse(&[0x90, 60, 100, 64, 100, 67, 0]);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[2],
+            MidiMessage::NoteOff {
+                channel: 0,
+                pitch: 67
+            }
+        );
+    }
+
+    #[test]
+    fn skips_system_and_truncated_messages() {
+        // timing clock in front, truncated note on at the end
+        let messages = parse(&[0xF8, 0x90, 60, 100, 0x90, 62]);
+        assert_eq!(
+            messages,
+            vec![MidiMessage::NoteOn {
+                channel: 0,
+                pitch: 60,
+                velocity: 100
+            }]
+        );
+    }
+}
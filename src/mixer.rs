@@ -0,0 +1,164 @@
+//! Track mixer
+//!
+//! Each track has a channel strip with a fader, pan, mute and solo. Solo is
+//! in place: while any track is soloed only soloed tracks are heard, sends
+//! included. The strip gains are worked out when a setting changes, so the
+//! render loop only multiplies.
+
+use crate::consts::TRACK_COUNT;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStrip {
+    pub volume: f32,
+    /// -1.0 hard left to 1.0 hard right
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Default for ChannelStrip {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+pub struct Mixer {
+    strips: [ChannelStrip; TRACK_COUNT],
+    master_volume: f32,
+    // 1.0 for tracks that are heard, 0.0 for muted or not soloed ones
+    audible: [f32; TRACK_COUNT],
+    volumes: [f32; TRACK_COUNT],
+    left_gains: [f32; TRACK_COUNT],
+    right_gains: [f32; TRACK_COUNT],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut mixer = Self {
+            strips: [ChannelStrip::default(); TRACK_COUNT],
+            master_volume: 1.0,
+            audible: [1.0; TRACK_COUNT],
+            volumes: [1.0; TRACK_COUNT],
+            left_gains: [1.0; TRACK_COUNT],
+            right_gains: [1.0; TRACK_COUNT],
+        };
+        mixer.update();
+        mixer
+    }
+
+    pub fn strip(&self, track: usize) -> &ChannelStrip {
+        &self.strips[track]
+    }
+
+    pub fn set_volume(&mut self, track: usize, volume: f32) {
+        self.strips[track].volume = volume.max(0.0);
+        self.update();
+    }
+
+    pub fn set_pan(&mut self, track: usize, pan: f32) {
+        self.strips[track].pan = pan.clamp(-1.0, 1.0);
+        self.update();
+    }
+
+    pub fn set_mute(&mut self, track: usize, mute: bool) {
+        self.strips[track].mute = mute;
+        self.update();
+    }
+
+    pub fn set_solo(&mut self, track: usize, solo: bool) {
+        self.strips[track].solo = solo;
+        self.update();
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume.max(0.0);
+    }
+
+    /// Fader levels, regardless of mute and solo
+    pub fn volumes(&self) -> &[f32; TRACK_COUNT] {
+        &self.volumes
+    }
+
+    pub fn audible(&self) -> &[f32; TRACK_COUNT] {
+        &self.audible
+    }
+
+    /// Pan gains of the left and right channel
+    pub fn pan_gains(&self) -> (&[f32; TRACK_COUNT], &[f32; TRACK_COUNT]) {
+        (&self.left_gains, &self.right_gains)
+    }
+
+    fn update(&mut self) {
+        let any_solo = self.strips.iter().any(|strip| strip.solo);
+        for (track, strip) in self.strips.iter().enumerate() {
+            let is_heard = !strip.mute && (!any_solo || strip.solo);
+            self.audible[track] = if is_heard { 1.0 } else { 0.0 };
+            self.volumes[track] = strip.volume;
+
+            // equal power, scaled so a centred track keeps its level
+            let angle = (strip.pan + 1.0) * FRAC_PI_4;
+            self.left_gains[track] = angle.cos() * SQRT_2;
+            self.right_gains[track] = angle.sin() * SQRT_2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solo_in_place_silences_other_tracks() {
+        let mut mixer = Mixer::new();
+        assert!(mixer.audible().iter().all(|&a| a == 1.0));
+
+        mixer.set_mute(0, true);
+        assert_eq!(mixer.audible()[..3], [0.0, 1.0, 1.0]);
+
+        mixer.set_solo(2, true);
+        mixer.set_solo(3, true);
+        assert_eq!(mixer.audible()[..4], [0.0, 0.0, 1.0, 1.0]);
+
+        // a muted track stays silent when soloed
+        mixer.set_solo(0, true);
+        assert_eq!(mixer.audible()[0], 0.0);
+
+        for track in [0, 2, 3] {
+            mixer.set_solo(track, false);
+        }
+        assert_eq!(mixer.audible()[..3], [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn pan_keeps_power_constant() {
+        let mut mixer = Mixer::new();
+        let (left, right) = mixer.pan_gains();
+        assert!((left[0] - 1.0).abs() < 1e-6 && (right[0] - 1.0).abs() < 1e-6);
+
+        for pan in [-1.0, -0.3, 0.5, 1.0] {
+            mixer.set_pan(1, pan);
+            let (left, right) = mixer.pan_gains();
+            assert!((left[1].powi(2) + right[1].powi(2) - 2.0).abs() < 1e-5);
+        }
+        mixer.set_pan(1, -2.0);
+        assert!(mixer.pan_gains().1[1].abs() < 1e-6);
+        assert_eq!(mixer.strip(1).pan, -1.0);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::drums::Burst;
+use crate::filters::{SVFMode, SVF};
+use crate::parameters::ParameterInfo;
+use crate::synth::SynthVoice;
+use crate::utils::{fractional_pitch_to_freq, pitch_to_freq};
+
+/*
+    Modal voice: a short click and noise burst strikes a bank of resonant
+    band-pass filters, one per mode of the struck body. Harmonic modes
+    sound like a string or tube, stretched ones like a bar (marimba,
+    vibraphone) and strongly stretched ones like a bell or membrane.
+*/
+
+// resonators in the bank
+const MODE_COUNT: usize = 8;
+
+// modes above this fraction of the sample rate are left silent
+const MAX_MODE_FREQ: f32 = 0.45;
+
+// time (s) for a released note to fade out
+const RELEASE_TIME: f32 = 0.08;
+
+// output level under which the body is considered silent
+const SILENCE_THRESHOLD: f32 = 1e-5;
+
+// samples of silence before the voice is freed
+const SILENT_SAMPLES: usize = 512;
+
+// ln(1000), the decay of a resonator's envelope over its T60
+const LN_1000: f32 = 6.907755;
+
+// level of the noise burst relative to the click, which excites the
+// resonators in a single sample
+const NOISE_LEVEL: f32 = 0.1;
+
+/// Frequency of mode `n` (1 is the fundamental) relative to the
+/// fundamental. At zero inharmonicity the modes are harmonic, at 1.0 they
+/// spread like those of a stiff bar, 1 : 4 : 9 : ...
+pub fn mode_ratio(n: usize, inharmonicity: f32) -> f32 {
+    let n = n as f32;
+    n * (1.0 + inharmonicity * (n * n - 1.0)).sqrt()
+}
+
+pub struct ModalVoice {
+    brightness: f32,
+    decay: f32,
+    inharmonicity: f32,
+    hardness: f32,
+    resonators: [SVF; MODE_COUNT],
+    gains: [f32; MODE_COUNT],
+    burst: Burst,
+    click: f32,
+    freq: f32,
+    level: f32,
+    release_gain: f32,
+    silent_samples: usize,
+    pitch: u8,
+    is_stopped: bool,
+    is_active: bool,
+    sample_rate: f32,
+}
+
+impl ModalVoice {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Brightness", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::logarithmic(1, "Decay", 0.05, 20.0, 1.5, "s"),
+            ParameterInfo::linear(2, "Inharmonicity", 0.0, 1.0, 0.3, ""),
+            ParameterInfo::linear(3, "Hardness", 0.0, 1.0, 0.7, ""),
+        ]
+    }
+
+    /// Tune the resonators to the modes of `freq`. Higher modes ring
+    /// shorter, and more so in a dark body, and their level follows the
+    /// brightness.
+    fn update_modes(&mut self) {
+        let mut total = 0.0;
+        for (n, (resonator, gain)) in self
+            .resonators
+            .iter_mut()
+            .zip(self.gains.iter_mut())
+            .enumerate()
+        {
+            let freq = self.freq * mode_ratio(n + 1, self.inharmonicity);
+            if freq >= self.sample_rate * MAX_MODE_FREQ {
+                *gain = 0.0;
+                continue;
+            }
+            let t60 = self.decay / (1.0 + n as f32 * (1.5 - self.brightness));
+            // a band-pass's envelope decays by exp(-pi * bandwidth * t)
+            resonator.update_freq(freq);
+            resonator.update_q((std::f32::consts::PI * freq * t60 / LN_1000).max(0.5));
+            let weight = (n as f32 + 1.0).powf(-2.0 * (1.0 - self.brightness));
+            total += weight;
+            // a click rings a band-pass at about 2 pi f / sample rate
+            *gain = weight * self.sample_rate / (2.0 * std::f32::consts::PI * freq);
+        }
+        // all modes together ring at about full scale
+        for gain in self.gains.iter_mut() {
+            *gain /= total.max(1.0);
+        }
+    }
+}
+
+impl SynthVoice for ModalVoice {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            brightness: 0.5,
+            decay: 1.5,
+            inharmonicity: 0.3,
+            hardness: 0.7,
+            resonators: std::array::from_fn(|_| {
+                let mut resonator = SVF::new(440.0, 1.0, sample_rate);
+                resonator.mode = SVFMode::Bandpass;
+                resonator
+            }),
+            gains: [0.0; MODE_COUNT],
+            burst: Burst::new(10.0, sample_rate),
+            click: 0.0,
+            freq: 440.0,
+            level: 1.0,
+            release_gain: 1.0,
+            silent_samples: 0,
+            pitch: 0,
+            is_stopped: true,
+            is_active: false,
+            sample_rate,
+        }
+    }
+
+    fn init(&mut self) {
+        self.release_gain = 10.0_f32.powf(-3.0 / (RELEASE_TIME * self.sample_rate));
+    }
+
+    fn reset(&mut self) {
+        self.is_active = false;
+        self.click = 0.0;
+        for resonator in self.resonators.iter_mut() {
+            resonator.reset();
+        }
+        self.update_modes();
+    }
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        if !self.is_active {
+            return 0.0;
+        }
+        // a hard mallet is mostly click, a soft one mostly noise
+        let x =
+            self.click * self.hardness + self.burst.process() * NOISE_LEVEL * (1.0 - self.hardness);
+        self.click = 0.0;
+
+        let y: f32 = self
+            .resonators
+            .iter_mut()
+            .zip(self.gains)
+            .filter(|(_, gain)| *gain > 0.0)
+            .map(|(resonator, gain)| resonator.process(x, 0.0) * gain)
+            .sum();
+        if self.is_stopped {
+            self.level *= self.release_gain;
+        }
+        let y = y * self.level;
+
+        if y.abs() < SILENCE_THRESHOLD && !self.burst.is_active() {
+            self.silent_samples += 1;
+            if self.silent_samples > SILENT_SAMPLES {
+                self.reset();
+            }
+        } else {
+            self.silent_samples = 0;
+        }
+
+        y
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32) {
+        // per-note params override the brightness and hardness when set
+        if param1 > 0.0 {
+            self.brightness = param1.clamp(0.0, 1.0);
+        }
+        if param2 > 0.0 {
+            self.hardness = param2.clamp(0.0, 1.0);
+        }
+
+        self.pitch = pitch;
+        self.freq = pitch_to_freq(pitch);
+        self.update_modes();
+        self.is_stopped = false;
+        self.is_active = true;
+        self.level = 1.0;
+        self.silent_samples = 0;
+
+        // the resonators keep ringing, so a repeated note strikes a
+        // sounding body
+        let amplitude = velocity as f32 / 127.0;
+        self.click = amplitude;
+        self.burst.trigger(velocity);
+    }
+
+    fn set_pitch(&mut self, pitch: f32) {
+        if !self.is_active {
+            return;
+        }
+        self.freq = fractional_pitch_to_freq(pitch);
+        self.update_modes();
+    }
+
+    fn stop(&mut self) {
+        self.is_stopped = true;
+    }
+
+    /// 0: brightness, 1: decay (s), 2: inharmonicity, 3: hardness of the
+    /// mallet, from a soft noise burst to a click
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.brightness = value.clamp(0.0, 1.0),
+            1 => self.decay = value.max(0.01),
+            2 => self.inharmonicity = value.clamp(0.0, 1.0),
+            3 => {
+                self.hardness = value.clamp(0.0, 1.0);
+                return;
+            }
+            _ => return,
+        }
+        self.update_modes();
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.brightness,
+            1 => self.decay,
+            2 => self.inharmonicity,
+            3 => self.hardness,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        4
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.is_active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn voice() -> ModalVoice {
+        let mut voice = ModalVoice::new(SAMPLE_RATE);
+        voice.init();
+        voice
+    }
+
+    #[test]
+    fn modes_stretch_with_inharmonicity() {
+        assert_eq!(mode_ratio(1, 0.7), 1.0);
+        assert_eq!(mode_ratio(3, 0.0), 3.0);
+        assert_eq!(mode_ratio(2, 1.0), 4.0);
+        assert_eq!(mode_ratio(3, 1.0), 9.0);
+    }
+
+    #[test]
+    fn harmonic_body_is_in_tune() {
+        let mut voice = voice();
+        voice.set_parameter(2, 0.0);
+        voice.set_parameter(0, 0.0);
+        voice.play(69, 127, 0.0, 0.0);
+
+        // skip the strike, then count rising zero crossings over a second
+        for _ in 0..4800 {
+            voice.process();
+        }
+        let ys: Vec<f32> = (0..SAMPLE_RATE as usize).map(|_| voice.process()).collect();
+        let crossings = ys.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as f32 - 440.0).abs() <= 2.0, "{crossings}");
+    }
+
+    #[test]
+    fn strike_rings_out_and_frees_voice() {
+        let mut voice = voice();
+        voice.set_parameter(1, 0.2);
+        voice.play(60, 127, 0.0, 0.0);
+        assert!(voice.is_active());
+        let ys: Vec<f32> = (0..SAMPLE_RATE as usize).map(|_| voice.process()).collect();
+        assert!(ys.iter().all(|y| y.is_finite() && y.abs() <= 1.0));
+        assert!(ys[..4800].iter().any(|y| y.abs() > 0.01));
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn stop_damps_the_body() {
+        let mut voice = voice();
+        voice.set_parameter(1, 10.0);
+        voice.play(60, 127, 0.0, 0.0);
+        voice.stop();
+        for _ in 0..(SAMPLE_RATE * 0.5) as usize {
+            voice.process();
+        }
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn modes_above_nyquist_are_silent() {
+        let mut voice = voice();
+        voice.set_parameter(2, 1.0);
+        voice.play(96, 127, 0.0, 0.0);
+        assert!(voice.gains[0] > 0.0);
+        assert_eq!(voice.gains[MODE_COUNT - 1], 0.0);
+    }
+}
@@ -0,0 +1,266 @@
+//! Modulation matrix
+//!
+//! Routes a fixed set of per-voice modulation sources to voice destinations,
+//! with a depth per slot. Contributions of slots targeting the same
+//! destination are summed. A slot's source can be shaped by a processor
+//! from `modulators` on its way.
+
+use crate::modulators::ModProcessor;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+pub const MOD_SLOT_COUNT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ModSource {
+    None,
+    CarrierEnv,
+    ModEnv,
+    Lfo,
+    Velocity,
+    Note,
+    Param1,
+    Param2,
+    /// per-note timbre expression
+    Timbre,
+    /// drawn on every note, evenly spread over -1.0..1.0
+    Random,
+    /// drawn on every note, mostly close to 0.0, see `gaussian_random`
+    RandomGaussian,
+}
+
+impl ModSource {
+    pub const COUNT: usize = 11;
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ModSource::CarrierEnv,
+            2 => ModSource::ModEnv,
+            3 => ModSource::Lfo,
+            4 => ModSource::Velocity,
+            5 => ModSource::Note,
+            6 => ModSource::Param1,
+            7 => ModSource::Param2,
+            8 => ModSource::Timbre,
+            9 => ModSource::Random,
+            10 => ModSource::RandomGaussian,
+            _ => ModSource::None,
+        }
+    }
+}
+
+/// A normally distributed value with a standard deviation of a third,
+/// clamped to -1.0..1.0, for small variations that are only rarely large
+pub fn gaussian_random(rng: &mut impl Rng) -> f32 {
+    // Box-Muller transform
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen();
+    let z = (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+    (z / 3.0).clamp(-1.0, 1.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ModDestination {
+    CarrierFreq,
+    ModFreq,
+    CarrierFb,
+    ModFb,
+    FilterCutoff,
+    FilterQ,
+    Amp,
+}
+
+impl ModDestination {
+    pub const COUNT: usize = 7;
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ModDestination::CarrierFreq),
+            1 => Some(ModDestination::ModFreq),
+            2 => Some(ModDestination::CarrierFb),
+            3 => Some(ModDestination::ModFb),
+            4 => Some(ModDestination::FilterCutoff),
+            5 => Some(ModDestination::FilterQ),
+            6 => Some(ModDestination::Amp),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModSlot {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    pub depth: f32,
+}
+
+impl ModSlot {
+    pub fn new(source: ModSource, destination: ModDestination, depth: f32) -> Self {
+        Self {
+            source,
+            destination,
+            depth,
+        }
+    }
+}
+
+impl Default for ModSlot {
+    fn default() -> Self {
+        Self::new(ModSource::None, ModDestination::Amp, 0.0)
+    }
+}
+
+/// Current value of each modulation source, indexed by `ModSource`
+pub type ModSources = [f32; ModSource::COUNT];
+
+/// Summed modulation per destination, indexed by `ModDestination`
+pub type ModValues = [f32; ModDestination::COUNT];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ModMatrix {
+    pub slots: [ModSlot; MOD_SLOT_COUNT],
+    pub processors: [ModProcessor; MOD_SLOT_COUNT],
+}
+
+impl ModMatrix {
+    pub fn new() -> Self {
+        Self {
+            slots: [ModSlot::default(); MOD_SLOT_COUNT],
+            processors: [ModProcessor::None; MOD_SLOT_COUNT],
+        }
+    }
+
+    pub fn set_slot(&mut self, index: usize, slot: ModSlot) {
+        if index < MOD_SLOT_COUNT {
+            self.slots[index] = slot;
+        }
+    }
+
+    pub fn set_depth(&mut self, index: usize, depth: f32) {
+        if index < MOD_SLOT_COUNT {
+            self.slots[index].depth = depth;
+        }
+    }
+
+    pub fn set_processor(&mut self, index: usize, processor: ModProcessor) {
+        if index < MOD_SLOT_COUNT {
+            self.processors[index] = processor;
+        }
+    }
+
+    /// Start of a note, for the processors that step on notes
+    pub fn trigger(&mut self) {
+        for processor in self.processors.iter_mut() {
+            processor.trigger();
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, sources: &ModSources) -> ModValues {
+        let mut values = [0.0; ModDestination::COUNT];
+        for (slot, processor) in self.slots.iter().zip(self.processors.iter_mut()) {
+            if matches!(slot.source, ModSource::None) || slot.depth == 0.0 {
+                continue;
+            }
+            let source = processor.process(sources[slot.source as usize]);
+            values[slot.destination as usize] += source * slot.depth;
+        }
+        values
+    }
+}
+
+impl Default for ModMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modulators::ModShaper;
+
+    #[test]
+    fn empty_matrix_is_silent() {
+        let mut matrix = ModMatrix::new();
+        let sources = [1.0; ModSource::COUNT];
+        assert_eq!(matrix.process(&sources), [0.0; ModDestination::COUNT]);
+    }
+
+    #[test]
+    fn slots_sum_per_destination() {
+        let mut matrix = ModMatrix::new();
+        matrix.set_slot(
+            0,
+            ModSlot::new(ModSource::Lfo, ModDestination::FilterCutoff, 0.5),
+        );
+        matrix.set_slot(
+            1,
+            ModSlot::new(ModSource::Velocity, ModDestination::FilterCutoff, 0.25),
+        );
+        matrix.set_slot(2, ModSlot::new(ModSource::ModEnv, ModDestination::Amp, 1.0));
+
+        let mut sources = [0.0; ModSource::COUNT];
+        sources[ModSource::Lfo as usize] = 1.0;
+        sources[ModSource::Velocity as usize] = 1.0;
+        sources[ModSource::ModEnv as usize] = 0.5;
+
+        let values = matrix.process(&sources);
+        assert_eq!(values[ModDestination::FilterCutoff as usize], 0.75);
+        assert_eq!(values[ModDestination::Amp as usize], 0.5);
+        assert_eq!(values[ModDestination::CarrierFreq as usize], 0.0);
+    }
+
+    #[test]
+    fn processors_shape_sources() {
+        let mut matrix = ModMatrix::new();
+        matrix.set_slot(0, ModSlot::new(ModSource::Lfo, ModDestination::Amp, 1.0));
+        matrix.set_processor(
+            0,
+            ModProcessor::new(ModShaper::SampleAndHold { rate_hz: 0.0 }, 48000.0),
+        );
+        let mut sources = [0.0; ModSource::COUNT];
+        sources[ModSource::Lfo as usize] = 0.5;
+        assert_eq!(matrix.process(&sources)[ModDestination::Amp as usize], 0.5);
+
+        // held until the next note
+        sources[ModSource::Lfo as usize] = 1.0;
+        assert_eq!(matrix.process(&sources)[ModDestination::Amp as usize], 0.5);
+        matrix.trigger();
+        assert_eq!(matrix.process(&sources)[ModDestination::Amp as usize], 1.0);
+    }
+
+    #[test]
+    fn gaussian_values_cluster_around_zero() {
+        let mut rng = rand::thread_rng();
+        let values: Vec<f32> = (0..10000).map(|_| gaussian_random(&mut rng)).collect();
+        assert!(values.iter().all(|x| (-1.0..=1.0).contains(x)));
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let near = values.iter().filter(|x| x.abs() < 1.0 / 3.0).count();
+        assert!(mean.abs() < 0.05);
+        // about 68% within a standard deviation
+        assert!((6000..7500).contains(&near));
+    }
+
+    #[test]
+    fn out_of_range_slot_is_ignored() {
+        let mut matrix = ModMatrix::new();
+        matrix.set_slot(
+            MOD_SLOT_COUNT,
+            ModSlot::new(ModSource::Lfo, ModDestination::Amp, 1.0),
+        );
+        assert!(matrix.slots.iter().all(|s| s.source == ModSource::None));
+    }
+
+    #[test]
+    fn from_u8_conversions() {
+        assert_eq!(ModSource::from_u8(3), ModSource::Lfo);
+        assert_eq!(ModSource::from_u8(10), ModSource::RandomGaussian);
+        assert_eq!(ModSource::from_u8(200), ModSource::None);
+        assert_eq!(
+            ModDestination::from_u8(4),
+            Some(ModDestination::FilterCutoff)
+        );
+        assert_eq!(ModDestination::from_u8(7), None);
+    }
+}
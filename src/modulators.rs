@@ -0,0 +1,265 @@
+//! Control signal processors
+//!
+//! Small processors for shaping modulation signals rather than audio:
+//! sample and hold, a slew limiter and a quantizer. A mod matrix slot can
+//! run its source through one of them before it reaches the destination,
+//! e.g. to step an LFO, or to smooth a jumpy velocity or note source.
+
+use crate::utils::beats_to_samples;
+
+/// Samples its input and holds it, either at a fixed rate, which can
+/// follow the tempo, or whenever it's triggered
+#[derive(Debug, Clone, Copy)]
+pub struct SampleAndHold {
+    // samples between steps, zero to only sample on triggers
+    period: f32,
+    elapsed: f32,
+    value: f32,
+    triggered: bool,
+    sample_rate: f32,
+}
+
+impl SampleAndHold {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            period: 0.0,
+            elapsed: 0.0,
+            value: 0.0,
+            // takes its first value right away
+            triggered: true,
+            sample_rate,
+        }
+    }
+
+    /// Steps per second, zero to only step when triggered
+    pub fn set_rate(&mut self, hz: f32) {
+        self.period = if hz > 0.0 { self.sample_rate / hz } else { 0.0 };
+    }
+
+    /// Step every `beats` at `tempo`
+    pub fn set_tempo_rate(&mut self, beats: f32, tempo: f32) {
+        self.period = if tempo > 0.0 {
+            beats_to_samples(beats, tempo, self.sample_rate).max(0.0)
+        } else {
+            0.0
+        };
+    }
+
+    /// Take a new value on the next sample
+    pub fn trigger(&mut self) {
+        self.triggered = true;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.elapsed += 1.0;
+        let clocked = self.period > 0.0 && self.elapsed >= self.period;
+        if self.triggered || clocked {
+            self.value = x;
+            self.triggered = false;
+            self.elapsed = if clocked {
+                self.elapsed - self.period
+            } else {
+                0.0
+            };
+        }
+        self.value
+    }
+}
+
+/// Limits how fast its output can move, separately for rising and falling
+/// signals, turning jumps into ramps
+#[derive(Debug, Clone, Copy)]
+pub struct SlewLimiter {
+    // largest change per sample
+    rise: f32,
+    fall: f32,
+    value: f32,
+    sample_rate: f32,
+}
+
+impl SlewLimiter {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            rise: f32::INFINITY,
+            fall: f32::INFINITY,
+            value: 0.0,
+            sample_rate,
+        }
+    }
+
+    /// Time to rise or fall by 1.0, in milliseconds; zero doesn't limit
+    pub fn set_times(&mut self, rise_ms: f32, fall_ms: f32) {
+        let step = |ms: f32| {
+            if ms > 0.0 {
+                1000.0 / (ms * self.sample_rate)
+            } else {
+                f32::INFINITY
+            }
+        };
+        self.rise = step(rise_ms);
+        self.fall = step(fall_ms);
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.value += (x - self.value).clamp(-self.fall, self.rise);
+        self.value
+    }
+
+    pub fn reset(&mut self, value: f32) {
+        self.value = value;
+    }
+}
+
+/// Rounds its input to multiples of a step, e.g. 1.0 / 12.0 to move pitch
+/// modulation in semitones
+#[derive(Debug, Clone, Copy)]
+pub struct Quantizer {
+    step: f32,
+}
+
+impl Quantizer {
+    /// A step of zero lets the input through
+    pub fn new(step: f32) -> Self {
+        Self {
+            step: step.max(0.0),
+        }
+    }
+
+    #[inline]
+    pub fn process(&self, x: f32) -> f32 {
+        if self.step > 0.0 {
+            (x / self.step).round() * self.step
+        } else {
+            x
+        }
+    }
+}
+
+/// Settings of a slot's processor, independent of the sample rate
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ModShaper {
+    #[default]
+    None,
+    /// steps per second, zero to step on every note
+    SampleAndHold {
+        rate_hz: f32,
+    },
+    Slew {
+        rise_ms: f32,
+        fall_ms: f32,
+    },
+    Quantize {
+        step: f32,
+    },
+}
+
+impl ModShaper {
+    /// 0: none, 1: sample and hold at `a` Hz, 2: slew rising over `a` ms
+    /// and falling over `b` ms, 3: quantize to steps of `a`
+    pub fn from_u8(shaper: u8, a: f32, b: f32) -> Option<Self> {
+        match shaper {
+            0 => Some(ModShaper::None),
+            1 => Some(ModShaper::SampleAndHold { rate_hz: a }),
+            2 => Some(ModShaper::Slew {
+                rise_ms: a,
+                fall_ms: b,
+            }),
+            3 => Some(ModShaper::Quantize { step: a }),
+            _ => None,
+        }
+    }
+}
+
+/// A processor shaping a modulation source
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ModProcessor {
+    #[default]
+    None,
+    SampleAndHold(SampleAndHold),
+    Slew(SlewLimiter),
+    Quantize(Quantizer),
+}
+
+impl ModProcessor {
+    pub fn new(shaper: ModShaper, sample_rate: f32) -> Self {
+        match shaper {
+            ModShaper::None => ModProcessor::None,
+            ModShaper::SampleAndHold { rate_hz } => {
+                let mut sample_and_hold = SampleAndHold::new(sample_rate);
+                sample_and_hold.set_rate(rate_hz);
+                ModProcessor::SampleAndHold(sample_and_hold)
+            }
+            ModShaper::Slew { rise_ms, fall_ms } => {
+                let mut slew = SlewLimiter::new(sample_rate);
+                slew.set_times(rise_ms, fall_ms);
+                ModProcessor::Slew(slew)
+            }
+            ModShaper::Quantize { step } => ModProcessor::Quantize(Quantizer::new(step)),
+        }
+    }
+
+    /// Called on every note, stepping sample and holds
+    pub fn trigger(&mut self) {
+        if let ModProcessor::SampleAndHold(sample_and_hold) = self {
+            sample_and_hold.trigger();
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        match self {
+            ModProcessor::None => x,
+            ModProcessor::SampleAndHold(sample_and_hold) => sample_and_hold.process(x),
+            ModProcessor::Slew(slew) => slew.process(x),
+            ModProcessor::Quantize(quantizer) => quantizer.process(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_and_hold_steps() {
+        let mut sample_and_hold = SampleAndHold::new(4.0);
+        sample_and_hold.set_rate(1.0);
+        let ys: Vec<f32> = (0..10).map(|n| sample_and_hold.process(n as f32)).collect();
+        assert_eq!(ys, [0.0, 0.0, 0.0, 0.0, 4.0, 4.0, 4.0, 4.0, 8.0, 8.0]);
+
+        // a beat at 120 bpm is half a second
+        sample_and_hold.set_tempo_rate(1.0, 120.0);
+        assert_eq!(sample_and_hold.period, 2.0);
+
+        // without a rate it only steps when triggered
+        sample_and_hold.set_rate(0.0);
+        assert_eq!(sample_and_hold.process(1.0), 8.0);
+        sample_and_hold.trigger();
+        assert_eq!(sample_and_hold.process(2.0), 2.0);
+        assert_eq!(sample_and_hold.process(3.0), 2.0);
+    }
+
+    #[test]
+    fn slew_limits_rise_and_fall() {
+        let mut slew = SlewLimiter::new(1000.0);
+        slew.set_times(4.0, 2.0);
+        let ys: Vec<f32> = (0..5).map(|_| slew.process(1.0)).collect();
+        assert_eq!(ys, [0.25, 0.5, 0.75, 1.0, 1.0]);
+        assert_eq!(slew.process(0.0), 0.5);
+        assert_eq!(slew.process(0.0), 0.0);
+
+        slew.set_times(0.0, 0.0);
+        assert_eq!(slew.process(-3.0), -3.0);
+    }
+
+    #[test]
+    fn quantizer_rounds_to_steps() {
+        let quantizer = Quantizer::new(0.25);
+        assert_eq!(quantizer.process(0.3), 0.25);
+        assert_eq!(quantizer.process(-0.9), -1.0);
+        assert_eq!(Quantizer::new(0.0).process(0.3), 0.3);
+        assert_eq!(ModShaper::from_u8(4, 0.0, 0.0), None);
+    }
+}
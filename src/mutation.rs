@@ -0,0 +1,218 @@
+//! Pattern mutation
+//!
+//! Mutating a pattern makes small random changes to its events: a note
+//! moves a step, moves up or down its track's scale, drops out, or is
+//! copied onto a free step. Applied every few bars, a pattern slowly
+//! evolves away from what was programmed.
+
+use crate::consts::TRACK_COUNT;
+use crate::next_event_id;
+use crate::scales::ScaleQuantizer;
+use crate::sequencer::Event;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+// largest pitch change, in scale steps
+const MAX_PITCH_STEPS: i32 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Mutation {
+    /// chance of each note changing, 0.0..1.0
+    pub amount: f32,
+    /// make the same random choices on each mutation, for repeatable results
+    pub seed: Option<u64>,
+    /// mutate the playing pattern every this many bars, 0 only on request
+    pub every_bars: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Change {
+    Shift,
+    Pitch,
+    Drop,
+    Copy,
+}
+
+/// Mutate the events of a pattern `length` beats long, on a grid of `step`
+/// beats. Pitches move along the scale of their track; tracks without a
+/// scale, e.g. drums where pitch picks the instrument, keep theirs.
+pub fn mutate_events(
+    events: &mut Vec<Event>,
+    amount: f32,
+    length: f32,
+    step: f32,
+    scales: &[Option<ScaleQuantizer>; TRACK_COUNT],
+    rng: &mut StdRng,
+) {
+    let amount = amount.clamp(0.0, 1.0);
+    if amount == 0.0 || step <= 0.0 {
+        return;
+    }
+    let steps = (length / step).round().max(1.0) as usize;
+
+    let mut index = 0;
+    // notes copied in this pass aren't mutated again
+    let mut remaining = events.len();
+    while index < events.len() && remaining > 0 {
+        remaining -= 1;
+        if rng.gen::<f32>() >= amount {
+            index += 1;
+            continue;
+        }
+
+        let track = events[index].track as usize % TRACK_COUNT;
+        let scale = scales[track];
+        let is_last_of_track = events
+            .iter()
+            .filter(|ev| ev.track as usize == track)
+            .count()
+            == 1;
+        let changes: &[Change] = match (scale.is_some(), is_last_of_track) {
+            (true, true) => &[Change::Shift, Change::Pitch, Change::Copy],
+            (true, false) => &[Change::Shift, Change::Pitch, Change::Drop, Change::Copy],
+            // a track's last note never drops out
+            (false, true) => &[Change::Shift, Change::Copy],
+            (false, false) => &[Change::Shift, Change::Drop, Change::Copy],
+        };
+
+        let ev = &mut events[index];
+        match changes[rng.gen_range(0..changes.len())] {
+            Change::Shift => {
+                let direction = if rng.gen::<bool>() { step } else { -step };
+                ev.beat_time = (ev.beat_time + direction).rem_euclid(length);
+            }
+            Change::Pitch => {
+                if let Some(scale) = scale {
+                    let mut steps = rng.gen_range(1..=MAX_PITCH_STEPS);
+                    if rng.gen::<bool>() {
+                        steps = -steps;
+                    }
+                    ev.pitch = move_in_scale(&scale, ev.pitch, steps);
+                }
+            }
+            Change::Drop => {
+                events.remove(index);
+                continue;
+            }
+            Change::Copy => {
+                let beat_time = rng.gen_range(0..steps) as f32 * step;
+                let is_free = !events.iter().any(|other| {
+                    other.track as usize == track && (other.beat_time - beat_time).abs() < 1e-3
+                });
+                if is_free {
+                    let copy = Event {
+                        id: next_event_id(),
+                        beat_time,
+                        ..events[index].clone()
+                    };
+                    events.push(copy);
+                }
+            }
+        }
+        index += 1;
+    }
+}
+
+/// Move `pitch` by `steps` notes of the scale, staying put at the ends of
+/// the MIDI range
+fn move_in_scale(scale: &ScaleQuantizer, pitch: u8, steps: i32) -> u8 {
+    let direction = steps.signum();
+    let mut moved = scale.quantize(pitch) as i32;
+    for _ in 0..steps.abs() {
+        let mut next = moved + direction;
+        while (0..=127).contains(&next) && scale.quantize(next as u8) as i32 != next {
+            next += direction;
+        }
+        if !(0..=127).contains(&next) {
+            break;
+        }
+        moved = next;
+    }
+    moved as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scales::{Scale, USER_SCALE_COUNT};
+    use rand::SeedableRng;
+
+    fn pattern() -> Vec<Event> {
+        (0..8)
+            .map(|i| Event {
+                id: i,
+                beat_time: i as f32 * 0.5,
+                track: (i % 2) as u8,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pitches_move_along_the_scale() {
+        let c_major = ScaleQuantizer::new(0, Scale::Major, &[0; USER_SCALE_COUNT]);
+        assert_eq!(move_in_scale(&c_major, 60, 1), 62);
+        assert_eq!(move_in_scale(&c_major, 60, -2), 57);
+        assert_eq!(move_in_scale(&c_major, 64, 1), 65);
+        assert_eq!(move_in_scale(&c_major, 127, 2), 127);
+    }
+
+    #[test]
+    fn mutation_keeps_events_on_grid_and_in_key() {
+        let c_major = ScaleQuantizer::new(0, Scale::Major, &[0; USER_SCALE_COUNT]);
+        let mut scales = [None; TRACK_COUNT];
+        scales[0] = Some(c_major);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut events = pattern();
+        for _ in 0..20 {
+            mutate_events(&mut events, 0.5, 4.0, 0.25, &scales, &mut rng);
+        }
+        assert_ne!(events, pattern());
+        for ev in events.iter() {
+            let step = ev.beat_time / 0.25;
+            assert!((step - step.round()).abs() < 1e-3);
+            assert!((0.0..4.0).contains(&ev.beat_time));
+            if ev.track == 0 {
+                assert_eq!(c_major.quantize(ev.pitch), ev.pitch);
+            } else {
+                // no scale, so the pitch stays
+                assert_eq!(ev.pitch, 60);
+            }
+        }
+        for track in 0..2 {
+            assert!(events.iter().any(|ev| ev.track == track));
+        }
+
+        // the same seed makes the same changes
+        let mut a = pattern();
+        let mut b = pattern();
+        mutate_events(
+            &mut a,
+            0.5,
+            4.0,
+            0.25,
+            &scales,
+            &mut StdRng::seed_from_u64(7),
+        );
+        mutate_events(
+            &mut b,
+            0.5,
+            4.0,
+            0.25,
+            &scales,
+            &mut StdRng::seed_from_u64(7),
+        );
+        let strip_ids = |events: &[Event]| {
+            events
+                .iter()
+                .map(|ev| (ev.beat_time, ev.pitch, ev.track))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(strip_ids(&a), strip_ids(&b));
+
+        let mut unchanged = pattern();
+        mutate_events(&mut unchanged, 0.0, 4.0, 0.25, &scales, &mut rng);
+        assert_eq!(unchanged, pattern());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::delay::{DelayLine, InterpolationType};
+use crate::drums::Burst;
+use crate::osc::{Osc, Waveform};
+use crate::parameters::ParameterInfo;
+use crate::synth::SynthVoice;
+use crate::utils::{fractional_pitch_to_freq, pitch_to_freq};
+use std::f32::consts::TAU;
+
+/*
+    Drawbar organ: nine sine partials at the footages of a tonewheel
+    organ's drawbars, a key click on each note and a rotary-style chorus,
+    a delay swept by a slow LFO together with a little tremolo.
+*/
+
+pub const DRAWBAR_COUNT: usize = 9;
+
+// partials relative to the played note: 16', 5 1/3', 8', 4', 2 2/3', 2',
+// 1 3/5', 1 1/3' and 1'
+const DRAWBAR_RATIOS: [f32; DRAWBAR_COUNT] = [0.5, 1.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0];
+
+// drawbars pull out from 0 to 8
+const MAX_DRAWBAR: f32 = 8.0;
+
+// partials above this fraction of the sample rate are left silent
+const MAX_PARTIAL_FREQ: f32 = 0.45;
+
+// time (ms) for the keys to open and close, short enough to sound
+// instant without clicking on their own
+const GATE_MS: f32 = 5.0;
+
+// the rotary chorus delay sweeps this far (ms) at full depth, around its
+// base delay
+const BASE_DELAY_MS: f32 = 1.0;
+const MAX_DEPTH_MS: f32 = 2.0;
+
+// level lost to the rotor's tremolo at full depth
+const TREMOLO_DEPTH: f32 = 0.2;
+
+pub struct OrganVoice {
+    drawbars: [f32; DRAWBAR_COUNT],
+    partials: [Osc; DRAWBAR_COUNT],
+    // level of each partial, zero when muted above the Nyquist limit
+    gains: [f32; DRAWBAR_COUNT],
+    click: Burst,
+    click_level: f32,
+    chorus: DelayLine,
+    chorus_depth: f32,
+    chorus_rate: f32,
+    chorus_phase: f32,
+    freq: f32,
+    velocity: f32,
+    gate: f32,
+    gate_step: f32,
+    pitch: u8,
+    is_stopped: bool,
+    sample_rate: f32,
+}
+
+impl OrganVoice {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        let names = [
+            "16'", "5 1/3'", "8'", "4'", "2 2/3'", "2'", "1 3/5'", "1 1/3'", "1'",
+        ];
+        let mut parameters: Vec<ParameterInfo> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let default = if i < 3 { MAX_DRAWBAR } else { 0.0 };
+                ParameterInfo::linear(i as i8, *name, 0.0, MAX_DRAWBAR, default, "")
+            })
+            .collect();
+        let first = DRAWBAR_COUNT as i8;
+        parameters.push(ParameterInfo::linear(first, "Key click", 0.0, 1.0, 0.3, ""));
+        parameters.push(ParameterInfo::linear(
+            first + 1,
+            "Chorus",
+            0.0,
+            1.0,
+            0.5,
+            "",
+        ));
+        parameters.push(ParameterInfo::logarithmic(
+            first + 2,
+            "Chorus rate",
+            0.1,
+            10.0,
+            0.8,
+            "Hz",
+        ));
+        parameters
+    }
+
+    /// Tune the partials to `freq` and set their levels from the drawbars,
+    /// scaled so the classic 888000000 registration peaks at full scale and
+    /// fuller ones don't go past it
+    fn update_partials(&mut self) {
+        let total: f32 = self.drawbars.iter().sum::<f32>() / MAX_DRAWBAR;
+        let scale = 1.0 / total.max(3.0);
+        for (i, (partial, gain)) in self
+            .partials
+            .iter_mut()
+            .zip(self.gains.iter_mut())
+            .enumerate()
+        {
+            let freq = self.freq * DRAWBAR_RATIOS[i];
+            partial.set_freq(freq);
+            *gain = if freq < self.sample_rate * MAX_PARTIAL_FREQ {
+                self.drawbars[i] / MAX_DRAWBAR * scale
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+impl SynthVoice for OrganVoice {
+    fn new(sample_rate: f32) -> Self {
+        let length = ((BASE_DELAY_MS + MAX_DEPTH_MS) * 0.001 * sample_rate) as usize + 4;
+        let mut drawbars = [0.0; DRAWBAR_COUNT];
+        drawbars[..3].fill(MAX_DRAWBAR);
+        Self {
+            drawbars,
+            partials: [Osc::new(Waveform::Sine, sample_rate); DRAWBAR_COUNT],
+            gains: [0.0; DRAWBAR_COUNT],
+            click: Burst::new(3.0, sample_rate),
+            click_level: 0.3,
+            chorus: DelayLine::new(InterpolationType::Linear, length),
+            chorus_depth: 0.5,
+            chorus_rate: 0.8,
+            chorus_phase: 0.0,
+            freq: 440.0,
+            velocity: 0.0,
+            gate: 0.0,
+            gate_step: 1.0 / (GATE_MS * 0.001 * sample_rate),
+            pitch: 0,
+            is_stopped: true,
+            sample_rate,
+        }
+    }
+
+    fn init(&mut self) {
+        self.update_partials();
+    }
+
+    fn reset(&mut self) {
+        self.gate = 0.0;
+        self.is_stopped = true;
+        for partial in self.partials.iter_mut() {
+            partial.reset();
+        }
+    }
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        if !self.is_active() {
+            return 0.0;
+        }
+        self.gate = if self.is_stopped {
+            (self.gate - self.gate_step).max(0.0)
+        } else {
+            (self.gate + self.gate_step).min(1.0)
+        };
+
+        let tone: f32 = self
+            .partials
+            .iter_mut()
+            .zip(self.gains)
+            .map(|(partial, gain)| partial.process() * gain)
+            .sum();
+        let x = (tone * self.gate + self.click.process() * self.click_level) * self.velocity;
+
+        // the rotor's doppler shift and tremolo, mixed with the dry signal
+        self.chorus.write_and_increment(x);
+        let lfo = 0.5 + 0.5 * (TAU * self.chorus_phase).sin();
+        self.chorus_phase = (self.chorus_phase + self.chorus_rate / self.sample_rate).fract();
+        let delay_ms = BASE_DELAY_MS + self.chorus_depth * MAX_DEPTH_MS * lfo;
+        let wet = self
+            .chorus
+            .read_delayed(delay_ms * 0.001 * self.sample_rate)
+            * (1.0 - self.chorus_depth * TREMOLO_DEPTH * lfo);
+        let mix = self.chorus_depth * 0.5;
+        x * (1.0 - mix) + wet * mix
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, _param1: f32, _param2: f32) {
+        self.pitch = pitch;
+        self.freq = pitch_to_freq(pitch);
+        self.update_partials();
+        self.velocity = velocity as f32 / 127.0;
+        if self.gate == 0.0 {
+            for partial in self.partials.iter_mut() {
+                partial.reset();
+            }
+        }
+        self.is_stopped = false;
+        if self.click_level > 0.0 {
+            self.click.trigger(velocity);
+        }
+    }
+
+    fn set_pitch(&mut self, pitch: f32) {
+        if !self.is_active() {
+            return;
+        }
+        self.freq = fractional_pitch_to_freq(pitch);
+        self.update_partials();
+    }
+
+    fn stop(&mut self) {
+        self.is_stopped = true;
+    }
+
+    /// 0..=8: drawbars, 16' to 1', 0.0..8.0, 9: key click, 10: chorus
+    /// depth, 11: chorus rate (Hz)
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0..=8 => {
+                self.drawbars[parameter as usize] = value.clamp(0.0, MAX_DRAWBAR);
+                self.update_partials();
+            }
+            9 => self.click_level = value.clamp(0.0, 1.0),
+            10 => self.chorus_depth = value.clamp(0.0, 1.0),
+            11 => self.chorus_rate = value.max(0.0),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0..=8 => self.drawbars[parameter as usize],
+            9 => self.click_level,
+            10 => self.chorus_depth,
+            11 => self.chorus_rate,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        DRAWBAR_COUNT as i8 + 3
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        !self.is_stopped || self.gate > 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn crossings(voice: &mut OrganVoice) -> usize {
+        for _ in 0..4800 {
+            voice.process();
+        }
+        let ys: Vec<f32> = (0..SAMPLE_RATE as usize).map(|_| voice.process()).collect();
+        ys.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count()
+    }
+
+    #[test]
+    fn drawbars_set_the_partials() {
+        let mut voice = OrganVoice::new(SAMPLE_RATE);
+        voice.init();
+        voice.set_parameter(10, 0.0);
+        for drawbar in 0..DRAWBAR_COUNT as i8 {
+            voice.set_parameter(drawbar, 0.0);
+        }
+        voice.set_parameter(2, 8.0);
+        voice.play(69, 127, 0.0, 0.0);
+        assert!(crossings(&mut voice).abs_diff(440) <= 1);
+
+        voice.set_parameter(2, 0.0);
+        voice.set_parameter(0, 8.0);
+        assert!(crossings(&mut voice).abs_diff(220) <= 1);
+    }
+
+    #[test]
+    fn full_registration_stays_in_range() {
+        let mut voice = OrganVoice::new(SAMPLE_RATE);
+        voice.init();
+        for drawbar in 0..DRAWBAR_COUNT as i8 {
+            voice.set_parameter(drawbar, 8.0);
+        }
+        voice.set_parameter(9, 1.0);
+        voice.play(60, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..4800).map(|_| voice.process()).collect();
+        assert!(ys.iter().all(|y| y.is_finite() && y.abs() <= 1.0));
+        assert!(ys.iter().any(|y| y.abs() > 0.2));
+    }
+
+    #[test]
+    fn keys_close_quickly() {
+        let mut voice = OrganVoice::new(SAMPLE_RATE);
+        voice.init();
+        voice.play(60, 127, 0.0, 0.0);
+        for _ in 0..4800 {
+            voice.process();
+        }
+        voice.stop();
+        assert!(voice.is_active());
+        for _ in 0..(GATE_MS * 0.001 * SAMPLE_RATE) as usize + 1 {
+            voice.process();
+        }
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn high_partials_are_muted() {
+        let mut voice = OrganVoice::new(SAMPLE_RATE);
+        voice.init();
+        voice.set_parameter(8, 8.0);
+        voice.play(120, 127, 0.0, 0.0);
+        assert_eq!(voice.gains[8], 0.0);
+        assert!(voice.gains[2] > 0.0);
+        assert_eq!(voice.get_parameter(8), 8.0);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::consts::A4_FREQ;
+use crate::filters::{SVFMode, SVF};
 use std::f32::consts::{FRAC_PI_4, PI, TAU};
 extern crate rand;
 
@@ -58,6 +59,16 @@ impl BlitSawOsc {
         self.period = self.sample_rate / freq;
     }
 
+    /// Restart at `phase` (0.0..1.0) of a period. The oscillator has no
+    /// closed-form phase, so this runs it forward up to one period.
+    pub fn reset_with_phase(&mut self, phase: f32) {
+        self.reset();
+        let samples = (phase.clamp(0.0, 1.0) * self.period) as usize;
+        for _ in 0..samples {
+            self.process();
+        }
+    }
+
     fn next_sample(&mut self) -> f32 {
         let y;
         self.phase += self.inc;
@@ -97,6 +108,7 @@ impl BlitSawOsc {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum Waveform {
     Sine,
     Saw,
@@ -107,8 +119,10 @@ pub enum Waveform {
 /*
     Naive, non-bandlimited oscillator with multiple waveforms
 */
+#[derive(Debug, Clone, Copy)]
 pub struct Osc {
     waveform: Waveform,
+    noise: Noise,
     phase: f32,
     frequency: f32,
     increment: f32,
@@ -119,6 +133,7 @@ impl Osc {
     pub fn new(waveform: Waveform, sample_rate: f32) -> Self {
         Self {
             waveform,
+            noise: Noise::new(NoiseColor::White, sample_rate),
             phase: 0.0,
             frequency: A4_FREQ,
             increment: 2.0 * PI * A4_FREQ / sample_rate, // default to 440 Hz
@@ -155,7 +170,15 @@ impl Osc {
         self.increment = 2.0 * PI * frequency / self.sample_rate;
     }
 
-    fn generate_waveform(&self) -> f32 {
+    pub fn get_freq(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    fn generate_waveform(&mut self) -> f32 {
         match self.waveform {
             Waveform::Sine => self.phase.sin(),
             Waveform::Saw => 2.0 * (self.phase / (2.0 * PI)) - 1.0,
@@ -166,7 +189,136 @@ impl Osc {
                     -1.0
                 }
             }
-            Waveform::Noise => rand::random::<f32>() * 2.0 - 1.0,
+            Waveform::Noise => self.noise.process(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseColor {
+    White,
+    /// -3 dB/octave, Voss-McCartney algorithm
+    Pink,
+    /// -6 dB/octave, leaky integrated white noise
+    Brown,
+    /// band-passed white noise, see `Noise::set_filter`
+    Filtered,
+}
+
+impl NoiseColor {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(NoiseColor::White),
+            1 => Some(NoiseColor::Pink),
+            2 => Some(NoiseColor::Brown),
+            3 => Some(NoiseColor::Filtered),
+            _ => None,
+        }
+    }
+}
+
+// number of random rows summed by the Voss-McCartney pink noise generator
+const PINK_ROWS: usize = 12;
+
+/*
+    Noise generator with a small xorshift PRNG, so generating a sample
+    doesn't touch the thread-local rand state
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct Noise {
+    pub color: NoiseColor,
+    state: u32,
+    pink_rows: [f32; PINK_ROWS],
+    pink_sum: f32,
+    pink_counter: u32,
+    brown: f32,
+    filter: SVF,
+}
+
+impl Noise {
+    pub fn new(color: NoiseColor, sample_rate: f32) -> Self {
+        let mut filter = SVF::new(1000.0, 1.0, sample_rate);
+        filter.mode = SVFMode::Bandpass;
+        Self {
+            color,
+            // xorshift must not be seeded with zero
+            state: rand::random::<u32>() | 1,
+            pink_rows: [0.0; PINK_ROWS],
+            pink_sum: 0.0,
+            pink_counter: 0,
+            brown: 0.0,
+            filter,
+        }
+    }
+
+    /// Set the center frequency and Q of the filtered noise color
+    pub fn set_filter(&mut self, freq: f32, q: f32) {
+        self.filter.update_freq(freq);
+        self.filter.update_q(q);
+    }
+
+    pub fn filter_freq(&self) -> f32 {
+        self.filter.get_freq()
+    }
+
+    #[inline]
+    pub fn process(&mut self) -> f32 {
+        match self.color {
+            NoiseColor::White => self.white(),
+            NoiseColor::Pink => self.pink(),
+            NoiseColor::Brown => self.brown(),
+            NoiseColor::Filtered => {
+                let x = self.white();
+                self.filter.process(x, 0.0)
+            }
+        }
+    }
+
+    /// Uniform white noise in -1.0..1.0
+    #[inline]
+    fn white(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    #[inline]
+    fn pink(&mut self) -> f32 {
+        // each sample updates one row, row n every 2^(n+1) samples
+        self.pink_counter = self.pink_counter.wrapping_add(1);
+        let row = self.pink_counter.trailing_zeros() as usize;
+        if row < PINK_ROWS {
+            let value = self.white();
+            self.pink_sum += value - self.pink_rows[row];
+            self.pink_rows[row] = value;
+        }
+        ((self.pink_sum + self.white()) / (PINK_ROWS + 1) as f32 * 3.0).clamp(-1.0, 1.0)
+    }
+
+    #[inline]
+    fn brown(&mut self) -> f32 {
+        let x = self.white();
+        self.brown = (self.brown + 0.02 * x) / 1.02;
+        (self.brown * 3.5).clamp(-1.0, 1.0)
+    }
+}
+
+/// How an FM operator derives its frequency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrequencyMode {
+    /// `freq_hz` is used as is, regardless of the played note
+    Fixed,
+    /// note frequency * `ratio` + `offset_hz`
+    Ratio,
+}
+
+impl FrequencyMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FrequencyMode::Fixed),
+            1 => Some(FrequencyMode::Ratio),
+            _ => None,
         }
     }
 }
@@ -175,7 +327,12 @@ impl Osc {
 pub struct FmOp {
     pub freq_hz: f32,
     pub fb_amt: f32,
+    pub fb_mod: f32,
     pub phase: f32,
+    pub mode: FrequencyMode,
+    pub ratio: f32,
+    pub offset_hz: f32,
+    note_freq: f32,
     z: f32, // 1 sample delay register: z^-1
     sample_rate: f32,
 }
@@ -185,16 +342,34 @@ impl FmOp {
         Self {
             freq_hz: 200.0,
             fb_amt: 0.9,
+            fb_mod: 0.0,
             phase: 0.0,
+            mode: FrequencyMode::Fixed,
+            ratio: 1.0,
+            offset_hz: 0.0,
+            note_freq: A4_FREQ,
             z: 0.0,
             sample_rate,
         }
     }
 
+    /// Set the frequency of the played note, which ratio mode tracks
+    pub fn set_note_freq(&mut self, note_freq: f32) {
+        self.note_freq = note_freq;
+        self.update_freq();
+    }
+
+    /// Recompute the frequency after changing the mode, ratio or offset
+    pub fn update_freq(&mut self) {
+        if self.mode == FrequencyMode::Ratio {
+            self.freq_hz = self.note_freq * self.ratio + self.offset_hz;
+        }
+    }
+
     #[inline]
     pub fn process(&mut self, phase_mod: f32, freq_mod: f32) -> f32 {
         let inc = (self.freq_hz + (freq_mod * self.freq_hz)) / self.sample_rate;
-        let y = (TAU * self.phase + (self.z * self.fb_amt) + phase_mod).sin();
+        let y = (TAU * self.phase + (self.z * (self.fb_amt + self.fb_mod)) + phase_mod).sin();
 
         self.phase += inc;
 
@@ -300,4 +475,36 @@ mod tests {
         // ys.iter().for_each(|y| println!("{}", y));
         plot_graph(&xs, &ys, "blit_saw.png");
     }
+
+    // correlation between successive samples, high for low-frequency noise
+    fn lag_one_correlation(ys: &[f32]) -> f32 {
+        let num: f32 = ys.windows(2).map(|w| w[0] * w[1]).sum();
+        let den: f32 = ys.iter().map(|y| y * y).sum();
+        num / den
+    }
+
+    #[test]
+    fn noise_colors() {
+        let sample_rate = 48000.0;
+        let mut correlations = vec![];
+        for color in [NoiseColor::White, NoiseColor::Pink, NoiseColor::Brown] {
+            let mut noise = Noise::new(color, sample_rate);
+            let ys: Vec<f32> = (0..48000).map(|_| noise.process()).collect();
+            assert!(ys.iter().all(|y| (-1.0..=1.0).contains(y)));
+            assert!(ys.iter().any(|y| y.abs() > 0.1));
+            correlations.push(lag_one_correlation(&ys));
+        }
+        // white noise is uncorrelated, pink and brown increasingly darker
+        assert!(correlations[0].abs() < 0.05);
+        assert!(correlations[1] > correlations[0]);
+        assert!(correlations[2] > correlations[1]);
+    }
+
+    #[test]
+    fn noise_generators_are_independent() {
+        let mut a = Noise::new(NoiseColor::White, 48000.0);
+        let mut b = Noise::new(NoiseColor::White, 48000.0);
+        let ys: Vec<(f32, f32)> = (0..100).map(|_| (a.process(), b.process())).collect();
+        assert!(ys.iter().any(|(a, b)| a != b));
+    }
 }
@@ -0,0 +1,175 @@
+//! Parameter metadata
+//!
+//! Voices and effects address their parameters by index. A `ParameterInfo`
+//! describes one of them: its name, range, default value, unit and the
+//! curve a control should follow across the range, so hosts can build a UI
+//! without knowing the meaning of each index.
+
+use std::os::raw::c_char;
+
+/// sizes of the name and unit buffers of a `ParameterDescription`,
+/// including the terminating NUL
+pub const PARAMETER_NAME_LENGTH: usize = 32;
+pub const PARAMETER_UNIT_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterCurve {
+    Linear,
+    /// equal ratios per step, for frequencies and times
+    Logarithmic,
+    /// whole values selecting a mode or type, or toggling something
+    Stepped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterInfo {
+    /// index passed to `set_parameter`
+    pub id: i8,
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    /// e.g. "Hz" or "ms", empty for plain amounts
+    pub unit: &'static str,
+    pub curve: ParameterCurve,
+}
+
+impl ParameterInfo {
+    pub fn linear(
+        id: i8,
+        name: impl Into<String>,
+        min: f32,
+        max: f32,
+        default: f32,
+        unit: &'static str,
+    ) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            min,
+            max,
+            default,
+            unit,
+            curve: ParameterCurve::Linear,
+        }
+    }
+
+    /// `min` has to be above zero
+    pub fn logarithmic(
+        id: i8,
+        name: impl Into<String>,
+        min: f32,
+        max: f32,
+        default: f32,
+        unit: &'static str,
+    ) -> Self {
+        Self {
+            curve: ParameterCurve::Logarithmic,
+            ..Self::linear(id, name, min, max, default, unit)
+        }
+    }
+
+    /// Whole values from `min` to `max`, e.g. the settings of a mode
+    pub fn stepped(id: i8, name: impl Into<String>, min: f32, max: f32, default: f32) -> Self {
+        Self {
+            curve: ParameterCurve::Stepped,
+            ..Self::linear(id, name, min, max, default, "")
+        }
+    }
+
+    /// Map a 0.0..1.0 control position onto the range, along the curve
+    pub fn from_normalized(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self.curve {
+            ParameterCurve::Linear => self.min + (self.max - self.min) * x,
+            ParameterCurve::Logarithmic => self.min * (self.max / self.min).powf(x),
+            ParameterCurve::Stepped => (self.min + (self.max - self.min) * x).round(),
+        }
+    }
+
+    /// Control position of a value, the inverse of `from_normalized`
+    pub fn to_normalized(&self, value: f32) -> f32 {
+        if self.max == self.min {
+            return 0.0;
+        }
+        let value = value.clamp(self.min.min(self.max), self.max.max(self.min));
+        match self.curve {
+            ParameterCurve::Logarithmic => (value / self.min).ln() / (self.max / self.min).ln(),
+            _ => (value - self.min) / (self.max - self.min),
+        }
+    }
+}
+
+/// `ParameterInfo` as passed to hosts over FFI. Names and units longer
+/// than their buffers are cut off.
+#[repr(C)]
+pub struct ParameterDescription {
+    pub id: i8,
+    pub name: [c_char; PARAMETER_NAME_LENGTH],
+    pub unit: [c_char; PARAMETER_UNIT_LENGTH],
+    pub min: f32,
+    pub max: f32,
+    pub default_value: f32,
+    /// see `ParameterCurve`
+    pub curve: u8,
+}
+
+impl ParameterDescription {
+    fn copy_str<const N: usize>(s: &str) -> [c_char; N] {
+        let mut buffer = [0; N];
+        for (c, &b) in buffer.iter_mut().zip(s.as_bytes().iter().take(N - 1)) {
+            *c = b as c_char;
+        }
+        buffer
+    }
+}
+
+impl From<&ParameterInfo> for ParameterDescription {
+    fn from(info: &ParameterInfo) -> Self {
+        Self {
+            id: info.id,
+            name: Self::copy_str(&info.name),
+            unit: Self::copy_str(info.unit),
+            min: info.min,
+            max: info.max,
+            default_value: info.default,
+            curve: info.curve as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_values_follow_the_curve() {
+        let cutoff = ParameterInfo::logarithmic(0, "cutoff", 20.0, 20000.0, 1000.0, "Hz");
+        assert!((cutoff.from_normalized(0.5) - 632.456).abs() < 0.01);
+        assert_eq!(cutoff.from_normalized(0.0), 20.0);
+
+        let mix = ParameterInfo::linear(1, "mix", 0.0, 1.0, 0.5, "");
+        assert_eq!(mix.from_normalized(0.25), 0.25);
+        assert_eq!(mix.from_normalized(2.0), 1.0);
+
+        let mode = ParameterInfo::stepped(2, "mode", 0.0, 3.0, 0.0);
+        assert_eq!(mode.from_normalized(0.4), 1.0);
+
+        for info in [cutoff, mix, mode] {
+            for x in [0.0, 0.25, 1.0] {
+                let value = info.from_normalized(x);
+                assert!((info.from_normalized(info.to_normalized(value)) - value).abs() < 1e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn descriptions_are_nul_terminated() {
+        let info = ParameterInfo::linear(3, "a".repeat(40), 0.0, 1.0, 0.5, "Hz");
+        let description = ParameterDescription::from(&info);
+        assert_eq!(description.name[PARAMETER_NAME_LENGTH - 2], b'a' as c_char);
+        assert_eq!(description.name[PARAMETER_NAME_LENGTH - 1], 0);
+        assert_eq!(&description.unit[..3], &[b'H' as c_char, b'z' as c_char, 0]);
+        assert_eq!((description.id, description.default_value), (3, 0.5));
+    }
+}
@@ -0,0 +1,236 @@
+//! Phaser
+//!
+//! A chain of first order all-pass stages whose corner frequency is swept
+//! by an LFO. Mixing the phase shifted signal with the dry one cuts notches
+//! that move with the sweep; feedback around the chain deepens them. The
+//! right channel runs the LFO at a phase offset for stereo movement.
+
+use crate::bus::Effect;
+use crate::filters::FirstOrderAllPass;
+use crate::parameters::ParameterInfo;
+use std::f32::consts::TAU;
+
+pub const MIN_PHASER_STAGES: usize = 4;
+pub const MAX_PHASER_STAGES: usize = 8;
+
+// octaves swept up and down from the center at full depth
+const SWEEP_OCTAVES: f32 = 2.0;
+
+pub struct Phaser {
+    stages: [[FirstOrderAllPass; MAX_PHASER_STAGES]; 2],
+    stage_count: usize,
+    rate: f32,
+    depth: f32,
+    center: f32,
+    feedback: f32,
+    stereo_offset: f32,
+    mix: f32,
+    phase: f32,
+    // last output of each channel's chain, fed back into its input
+    last: [f32; 2],
+    sample_rate: f32,
+}
+
+impl Phaser {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Rate", 0.01, 10.0, 0.3, "Hz"),
+            ParameterInfo::linear(1, "Depth", 0.0, 1.0, 0.7, ""),
+            ParameterInfo::logarithmic(2, "Center", 20.0, 20000.0, 800.0, "Hz"),
+            ParameterInfo::linear(3, "Feedback", -0.95, 0.95, 0.5, ""),
+            ParameterInfo::linear(4, "Stereo offset", 0.0, 1.0, 0.25, ""),
+            ParameterInfo::linear(5, "Mix", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::stepped(
+                6,
+                "Stages",
+                MIN_PHASER_STAGES as f32,
+                MAX_PHASER_STAGES as f32,
+                MIN_PHASER_STAGES as f32,
+            ),
+        ]
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            stages: [[FirstOrderAllPass::default(); MAX_PHASER_STAGES]; 2],
+            stage_count: MIN_PHASER_STAGES,
+            rate: 0.3,
+            depth: 0.7,
+            center: 800.0,
+            feedback: 0.5,
+            stereo_offset: 0.25,
+            mix: 0.5,
+            phase: 0.0,
+            last: [0.0; 2],
+            sample_rate,
+        }
+    }
+
+    /// Number of all-pass stages, MIN_PHASER_STAGES..=MAX_PHASER_STAGES
+    pub fn set_stage_count(&mut self, stages: usize) {
+        self.stage_count = stages.clamp(MIN_PHASER_STAGES, MAX_PHASER_STAGES);
+    }
+
+    /// LFO rate in Hz
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.max(0.0);
+    }
+
+    /// Sweep depth, 0.0..1.0
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    /// Center frequency of the sweep in Hz
+    pub fn set_center(&mut self, center: f32) {
+        self.center = center.clamp(20.0, 20000.0);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(-0.95, 0.95);
+    }
+
+    /// LFO phase offset of the right channel, 0.0..1.0 cycles
+    pub fn set_stereo_offset(&mut self, offset: f32) {
+        self.stereo_offset = offset.clamp(0.0, 1.0);
+    }
+
+    /// Dry/wet balance, 0.0..1.0
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    #[inline]
+    fn process_channel(&mut self, channel: usize, x: f32, lfo_phase: f32) -> f32 {
+        let sweep = self.depth * SWEEP_OCTAVES * (TAU * lfo_phase).sin();
+        let freq = self.center * sweep.exp2();
+        let a = FirstOrderAllPass::coefficient(freq, self.sample_rate);
+
+        let mut y = x + self.last[channel] * self.feedback;
+        for stage in self.stages[channel][..self.stage_count].iter_mut() {
+            stage.set_coefficient(a);
+            y = stage.process(y);
+        }
+        self.last[channel] = y;
+
+        x * (1.0 - self.mix) + y * self.mix
+    }
+
+    #[inline]
+    pub fn process_stereo(&mut self, x: f32) -> (f32, f32) {
+        let phase = self.phase;
+        let left = self.process_channel(0, x, phase);
+        let right = self.process_channel(1, x, phase + self.stereo_offset);
+
+        self.phase += self.rate / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        (left, right)
+    }
+}
+
+/// 0: rate (Hz), 1: depth, 2: center frequency, 3: feedback,
+/// 4: stereo offset, 5: mix, 6: stages
+impl Effect for Phaser {
+    fn process(&mut self, x: f32) -> f32 {
+        let (left, right) = Phaser::process_stereo(self, x);
+        (left + right) * 0.5
+    }
+
+    fn process_stereo(&mut self, left: f32, right: f32) -> (f32, f32) {
+        Phaser::process_stereo(self, (left + right) * 0.5)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_rate(value),
+            1 => self.set_depth(value),
+            2 => self.set_center(value),
+            3 => self.set_feedback(value),
+            4 => self.set_stereo_offset(value),
+            5 => self.set_mix(value),
+            6 => self.set_stage_count(value as usize),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.rate,
+            1 => self.depth,
+            2 => self.center,
+            3 => self.feedback,
+            4 => self.stereo_offset,
+            5 => self.mix,
+            6 => self.stage_count as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn gain_at(phaser: &mut Phaser, freq: f32) -> f32 {
+        let sample_rate = 48000.0;
+        let (mut xx, mut yy) = (0.0, 0.0);
+        for i in 0..9600 {
+            let x = (2.0 * PI * freq * i as f32 / sample_rate).sin();
+            let (y, _) = phaser.process_stereo(x);
+            if i >= 4800 {
+                xx += x * x;
+                yy += y * y;
+            }
+        }
+        (yy / xx).sqrt()
+    }
+
+    #[test]
+    fn notches_at_the_center() {
+        // without sweep or feedback, 4 stages shift the phase by 180° where
+        // each stage is at 45°, cancelling the dry signal there
+        let mut phaser = Phaser::new(48000.0);
+        phaser.set_depth(0.0);
+        phaser.set_feedback(0.0);
+        phaser.set_center(1000.0);
+        let notch = 1000.0 * (PI / 8.0).tan();
+        assert!(gain_at(&mut phaser, notch) < 0.05);
+        assert!(gain_at(&mut phaser, 20.0) > 0.9);
+    }
+
+    #[test]
+    fn stereo_offset_decorrelates_channels() {
+        let mut phaser = Phaser::new(48000.0);
+        phaser.set_rate(5.0);
+        phaser.set_stereo_offset(0.5);
+        let mut difference = 0.0;
+        for i in 0..9600 {
+            let (l, r) = phaser.process_stereo((i as f32 * 0.05).sin());
+            difference += (l - r).abs();
+        }
+        assert!(difference > 1.0);
+
+        let mut phaser = Phaser::new(48000.0);
+        phaser.set_stereo_offset(0.0);
+        for i in 0..1000 {
+            let (l, r) = phaser.process_stereo((i as f32 * 0.05).sin());
+            assert_eq!(l, r);
+        }
+    }
+
+    #[test]
+    fn feedback_stays_stable() {
+        let mut phaser = Phaser::new(48000.0);
+        phaser.set_parameter(3, 10.0);
+        phaser.set_parameter(6, 8.0);
+        assert_eq!(phaser.get_parameter(3), 0.95);
+        assert_eq!(phaser.get_parameter(6), 8.0);
+        for i in 0..48000_u32 {
+            let y = phaser.process(if i.is_multiple_of(100) { 1.0 } else { 0.0 });
+            assert!(y.is_finite() && y.abs() < 100.0);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Pitch shifter
+//!
+//! Two read heads sweep through a short window of a delay line at the
+//! pitch ratio, each jumping back when it reaches the end of the window.
+//! The heads are half a window apart and crossfaded, so each jump is hidden
+//! by the other head. Longer windows smear transients less audibly on low
+//! material, shorter ones keep the shifted voice tighter. Used on its own
+//! as a harmonizer and in the shimmer reverb's feedback path.
+
+use crate::bus::Effect;
+use crate::delay::{DelayLine, InterpolationType};
+use crate::parameters::ParameterInfo;
+use std::f32::consts::PI;
+
+/// furthest shift up or down, in semitones
+pub const MAX_SHIFT: f32 = 12.0;
+
+// range of the crossfaded window, in milliseconds
+const MIN_WINDOW_MS: f32 = 10.0;
+const MAX_WINDOW_MS: f32 = 100.0;
+
+pub struct PitchShifter {
+    delay_line: DelayLine,
+    semitones: f32,
+    ratio: f32,
+    window_ms: f32,
+    // window length in samples
+    window: f32,
+    mix: f32,
+    phase: f32,
+    sample_rate: f32,
+}
+
+impl PitchShifter {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Pitch", -MAX_SHIFT, MAX_SHIFT, 12.0, "st"),
+            ParameterInfo::logarithmic(1, "Window", MIN_WINDOW_MS, MAX_WINDOW_MS, 50.0, "ms"),
+            ParameterInfo::linear(2, "Mix", 0.0, 1.0, 0.5, ""),
+        ]
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        let length = (MAX_WINDOW_MS * 0.001 * sample_rate) as usize + 2;
+        let mut shifter = Self {
+            delay_line: DelayLine::new(InterpolationType::Linear, length),
+            semitones: 0.0,
+            ratio: 1.0,
+            window_ms: 0.0,
+            window: 0.0,
+            mix: 0.5,
+            phase: 0.0,
+            sample_rate,
+        };
+        shifter.set_semitones(12.0);
+        shifter.set_window(50.0);
+        shifter
+    }
+
+    /// Shift in semitones, -12.0..12.0
+    pub fn set_semitones(&mut self, semitones: f32) {
+        self.semitones = semitones.clamp(-MAX_SHIFT, MAX_SHIFT);
+        self.ratio = 2.0_f32.powf(self.semitones / 12.0);
+    }
+
+    /// Length of the crossfaded window in milliseconds, 10.0..100.0
+    pub fn set_window(&mut self, window_ms: f32) {
+        self.window_ms = window_ms.clamp(MIN_WINDOW_MS, MAX_WINDOW_MS);
+        self.window = self.window_ms * 0.001 * self.sample_rate;
+    }
+
+    /// Dry/wet balance, 0.0..1.0
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    /// The shifted signal alone
+    #[inline]
+    pub fn shift(&mut self, x: f32) -> f32 {
+        self.delay_line.write_and_increment(x);
+        let mut y = 0.0;
+        for offset in [0.0, 0.5] {
+            let phase = (self.phase + offset).fract();
+            let delay = 1.0 + phase * self.window;
+            // sin² windows of the two heads sum to one
+            let gain = (PI * phase).sin().powi(2);
+            y += self.delay_line.read_delayed(delay) * gain;
+        }
+        // the delay shrinks while the pitch is shifted up
+        self.phase = (self.phase + (1.0 - self.ratio) / self.window).rem_euclid(1.0);
+        y
+    }
+}
+
+/// 0: pitch (semitones), 1: window (ms), 2: mix
+impl Effect for PitchShifter {
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        x * (1.0 - self.mix) + self.shift(x) * self.mix
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_semitones(value),
+            1 => self.set_window(value),
+            2 => self.set_mix(value),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.semitones,
+            1 => self.window_ms,
+            2 => self.mix,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Level of `freq` in the shifted output of a 200 Hz sine
+    fn shifted_levels(shifter: &mut PitchShifter, freqs: &[f32]) -> Vec<f32> {
+        let ys: Vec<f32> = (0..48000)
+            .map(|i| shifter.shift((TAU * 200.0 * i as f32 / SAMPLE_RATE).sin()))
+            .collect();
+        // correlate the output with sines at each frequency
+        freqs
+            .iter()
+            .map(|freq| {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (i, y) in ys.iter().enumerate().skip(4800) {
+                    let w = TAU * freq * i as f32 / SAMPLE_RATE;
+                    re += y * w.cos();
+                    im += y * w.sin();
+                }
+                (re * re + im * im).sqrt()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pitch_shifter_doubles_frequency() {
+        let mut shifter = PitchShifter::new(SAMPLE_RATE);
+        let levels = shifted_levels(&mut shifter, &[400.0, 200.0]);
+        assert!(levels[0] > levels[1] * 4.0);
+    }
+
+    #[test]
+    fn shifts_down_by_semitones() {
+        let mut shifter = PitchShifter::new(SAMPLE_RATE);
+        shifter.set_semitones(-7.0);
+        let fifth_down = 200.0 * 2.0_f32.powf(-7.0 / 12.0);
+        let levels = shifted_levels(&mut shifter, &[fifth_down, 200.0]);
+        assert!(levels[0] > levels[1] * 4.0);
+    }
+
+    #[test]
+    fn settings_are_clamped() {
+        let mut shifter = PitchShifter::new(SAMPLE_RATE);
+        shifter.set_parameter(0, 24.0);
+        shifter.set_parameter(1, 500.0);
+        shifter.set_parameter(2, 0.0);
+        assert_eq!(shifter.get_parameter(0), MAX_SHIFT);
+        assert_eq!(shifter.get_parameter(1), MAX_WINDOW_MS);
+        // fully dry passes the input
+        assert_eq!(shifter.process(0.25), 0.25);
+    }
+}
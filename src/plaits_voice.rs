@@ -1,106 +1,554 @@
-use crate::envelopes::{CurveType, EnvelopeState, AR};
-use crate::filters::SVF;
-use crate::osc::{BlitSawOsc, FmOp};
+use crate::envelopes::{CurveType, EnvelopeState, Portamento, RetriggerMode, AR};
+use crate::filters::{key_tracking, SVF};
+use crate::modulation::{gaussian_random, ModDestination, ModMatrix, ModSlot, ModSource};
+use crate::osc::{BlitSawOsc, FmOp, FrequencyMode, Osc, Waveform};
+use crate::parameters::ParameterInfo;
+use crate::sequencer::NoteExpression;
 use crate::synth::SynthVoice;
-use crate::utils::pitch_to_freq;
+use crate::utils::{fractional_pitch_to_freq, pitch_to_freq, scale_log};
+use rand::Rng;
 use std::f32::consts::PI;
 
 const BLOCK_SIZE: usize = 1;
 
+/// number of parameters addressable through `set_parameter`
+pub const PARAMETER_COUNT: i8 = KEY_TRACKING_PARAMETER + 1;
+
+pub const OPERATOR_COUNT: usize = 4;
+
+/// shape of the operators' attack and decay
+pub const OPERATOR_CURVE: CurveType = CurveType::Exponential { pow: 3 };
+
+/// parameter selecting the operator routing, see `ALGORITHMS`
+pub const ALGORITHM_PARAMETER: i8 = 19;
+
+/// index of the first per-operator parameter. Each operator has a block of
+/// `OPERATOR_PARAMETERS`: frequency, level, attack, decay, feedback,
+/// frequency mode (see `FrequencyMode`), ratio and offset (Hz)
+pub const FIRST_OPERATOR_PARAMETER: i8 = 20;
+pub const OPERATOR_PARAMETERS: i8 = 8;
+
+/// parameter for how far the filter cutoff follows the note, after the
+/// operator blocks
+pub const KEY_TRACKING_PARAMETER: i8 =
+    FIRST_OPERATOR_PARAMETER + (OPERATOR_COUNT as i8) * OPERATOR_PARAMETERS;
+
+// mod matrix slots backing the envelope amount parameters
+const FILTER_MOD_ENV_SLOT: usize = 0;
+const PITCH_CARRIER_ENV_SLOT: usize = 1;
+const PITCH_MOD_ENV_SLOT: usize = 2;
+
+/// Operator routing. Modulation always flows from higher to lower operator
+/// indices, so the operators can be processed in descending order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Algorithm {
+    /// for each operator, a bit mask of the operators modulating it
+    pub modulators: [u8; OPERATOR_COUNT],
+    /// bit mask of the operators heard at the output
+    pub carriers: u8,
+}
+
+/// DX-style 4-operator algorithms
+pub const ALGORITHMS: [Algorithm; 8] = [
+    // 3 -> 2 -> 1 -> 0
+    Algorithm {
+        modulators: [0b0010, 0b0100, 0b1000, 0],
+        carriers: 0b0001,
+    },
+    // (3 + 2) -> 1 -> 0
+    Algorithm {
+        modulators: [0b0010, 0b1100, 0, 0],
+        carriers: 0b0001,
+    },
+    // (3 + (2 -> 1)) -> 0
+    Algorithm {
+        modulators: [0b1010, 0b0100, 0, 0],
+        carriers: 0b0001,
+    },
+    // ((3 -> 2) + 1) -> 0
+    Algorithm {
+        modulators: [0b0110, 0, 0b1000, 0],
+        carriers: 0b0001,
+    },
+    // (1 -> 0) + (3 -> 2)
+    Algorithm {
+        modulators: [0b0010, 0, 0b1000, 0],
+        carriers: 0b0101,
+    },
+    // 3 -> (0 + 1 + 2)
+    Algorithm {
+        modulators: [0b1000, 0b1000, 0b1000, 0],
+        carriers: 0b0111,
+    },
+    // (3 -> 2) + 1 + 0
+    Algorithm {
+        modulators: [0, 0, 0b1000, 0],
+        carriers: 0b0111,
+    },
+    // 0 + 1 + 2 + 3
+    Algorithm {
+        modulators: [0, 0, 0, 0],
+        carriers: 0b1111,
+    },
+];
+
 #[derive(Debug, Clone, Copy)]
 pub struct FmVoice {
-    pub carrier: FmOp,
-    pub carrier_env: AR,
-    pub modulator: FmOp,
-    pub mod_env: AR,
+    /// operator 0 is the carrier and operator 1 its modulator in the
+    /// default algorithm
+    pub ops: [FmOp; OPERATOR_COUNT],
+    pub envs: [AR; OPERATOR_COUNT],
+    pub levels: [f32; OPERATOR_COUNT],
+    pub algorithm: usize,
     pub fm_amt: f32,
     pub mod_index: f32,
-    pub filter_mod_env_amt: f32,
-    pub pitch_carrier_env_amt: f32,
-    pub pitch_mod_env_amt: f32,
+    pub mod_matrix: ModMatrix,
+    pub lfo: Osc,
     pub filter: SVF,
+    // cutoff at middle C, before key tracking
+    cutoff: f32,
+    // octaves the cutoff moves per octave played
+    key_tracking: f32,
     pub reverb_amt: f32,
     pub delay_amt: f32,
+    pub pitch_bend: f32,
+    pub pressure: f32,
+    expression: NoteExpression,
+    // frequency ratio offset of the expression pitch bend
+    expression_bend: f32,
+    portamento: Portamento,
+    lfo_rate: f32,
+    velocity: f32,
+    pitch: u8,
+    note: f32,
+    param1: f32,
+    param2: f32,
+    // per-note random values, uniform and gaussian
+    random: f32,
+    random_gaussian: f32,
 }
 
-impl FmVoice {
-    pub fn new(sample_rate: f32) -> Self {
+impl SynthVoice for FmVoice {
+    fn new(sample_rate: f32) -> Self {
+        let mut mod_matrix = ModMatrix::new();
+        mod_matrix.set_slot(
+            FILTER_MOD_ENV_SLOT,
+            ModSlot::new(ModSource::ModEnv, ModDestination::FilterCutoff, 0.0),
+        );
+        mod_matrix.set_slot(
+            PITCH_CARRIER_ENV_SLOT,
+            ModSlot::new(ModSource::CarrierEnv, ModDestination::CarrierFreq, 0.0),
+        );
+        mod_matrix.set_slot(
+            PITCH_MOD_ENV_SLOT,
+            ModSlot::new(ModSource::ModEnv, ModDestination::ModFreq, 0.0),
+        );
+
+        let lfo_rate = 1.0;
+        let mut lfo = Osc::new(Waveform::Sine, sample_rate);
+        lfo.set_freq(lfo_rate);
+
+        let mut envs = [AR::new(1.0, 100.0, OPERATOR_CURVE, sample_rate); OPERATOR_COUNT];
+        envs[0].decay_ms = 500.0;
+
         Self {
-            carrier: FmOp::new(sample_rate),
-            carrier_env: AR::new(1.0, 500.0, CurveType::Exponential { pow: 3 }, sample_rate),
+            ops: [FmOp::new(sample_rate); OPERATOR_COUNT],
+            envs,
+            // operators 2 and 3 are silent, leaving a 2-operator voice
+            levels: [1.0, 1.0, 0.0, 0.0],
+            algorithm: 0,
             fm_amt: 0.0,
-            modulator: FmOp::new(sample_rate),
-            mod_env: AR::new(1.0, 100.0, CurveType::Exponential { pow: 3 }, sample_rate),
             mod_index: 0.0,
-            filter_mod_env_amt: 0.0,
-            pitch_carrier_env_amt: 0.0,
-            pitch_mod_env_amt: 0.0,
+            mod_matrix,
+            lfo,
             filter: SVF::new(4000.0, 1.717, sample_rate),
+            cutoff: 4000.0,
+            key_tracking: 0.0,
             reverb_amt: 0.0,
             delay_amt: 0.0,
+            pitch_bend: 0.0,
+            pressure: 0.0,
+            expression: NoteExpression::default(),
+            expression_bend: 0.0,
+            portamento: Portamento::new(0.0, sample_rate),
+            lfo_rate,
+            velocity: 1.0,
+            pitch: 0,
+            note: 0.0,
+            param1: 0.0,
+            param2: 0.0,
+            random: 0.0,
+            random_gaussian: 0.0,
         }
     }
 
-    pub fn trigger(&mut self, velocity: u8) {
-        self.carrier_env.trigger(velocity);
-        self.mod_env.trigger(velocity);
+    fn init(&mut self) {}
+
+    /// Trigger the voice, updating the note-dependent modulation sources.
+    /// Notes played while the previous one sounds glide from its pitch.
+    fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32) {
+        if self.is_active() {
+            self.set_pitch(pitch as f32);
+        } else {
+            self.portamento.jump(pitch as f32);
+            self.set_note_freq(pitch_to_freq(pitch));
+        }
+        self.pitch = pitch;
+        self.note = pitch as f32 / 127.0;
+        self.param1 = param1;
+        self.param2 = param2;
+        self.update_cutoff();
+        self.trigger(velocity);
+    }
+
+    /// Glide to a new pitch without retriggering
+    fn set_pitch(&mut self, pitch: f32) {
+        self.portamento.set_target(pitch);
+        self.set_note_freq(fractional_pitch_to_freq(pitch));
+    }
+
+    fn stop(&mut self) {}
+
+    fn set_pitch_bend(&mut self, bend: f32) {
+        self.pitch_bend = bend;
+    }
+
+    fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        for env in self.envs.iter_mut() {
+            env.retrigger = mode;
+        }
+    }
+
+    fn set_pressure(&mut self, pressure: f32) {
+        self.pressure = pressure;
+    }
+
+    fn set_expression(&mut self, expression: NoteExpression) {
+        self.expression = expression;
+        self.expression_bend = (2f32).powf(expression.pitch_bend / 12.0) - 1.0;
+    }
+
+    fn expression(&self) -> NoteExpression {
+        self.expression
+    }
+
+    fn mod_matrix(&self) -> Option<&ModMatrix> {
+        Some(&self.mod_matrix)
+    }
+
+    fn mod_matrix_mut(&mut self) -> Option<&mut ModMatrix> {
+        Some(&mut self.mod_matrix)
+    }
+
+    fn sends(&self) -> (f32, f32) {
+        (self.reverb_amt, self.delay_amt)
     }
 
-    pub fn reset(&mut self) {
+    fn reset(&mut self) {
         // start carrier phase at 90 degrees to increase percussiveness/attack
-        self.carrier.phase = PI / 2.0;
-        self.modulator.phase = 0.0;
+        self.ops[0].phase = PI / 2.0;
+        for op in self.ops.iter_mut().skip(1) {
+            op.phase = 0.0;
+        }
     }
 
     #[inline]
-    pub fn process(&mut self) -> f32 {
-        let mod_env_signal = self.mod_env.process();
-
-        let mod_out = self
-            .modulator
-            .process(0.0, mod_env_signal * self.pitch_mod_env_amt);
-        let mod_signal = self.fm_amt * self.mod_index * mod_out;
-        let carrier_env_signal = self.carrier_env.process();
-
-        let carrier_out = self.carrier.process(
-            mod_signal * mod_env_signal,
-            carrier_env_signal * self.pitch_carrier_env_amt,
-        );
-        let mut y = carrier_out + (mod_out * (1.0 - self.fm_amt));
-        y = y * carrier_env_signal;
+    fn process(&mut self) -> f32 {
+        use ModDestination as D;
 
-        self.filter
-            .process(y, mod_env_signal * self.filter_mod_env_amt)
-            * 0.5
+        let env_signals: [f32; OPERATOR_COUNT] = std::array::from_fn(|i| self.envs[i].process());
+
+        let mut sources = [0.0; ModSource::COUNT];
+        sources[ModSource::CarrierEnv as usize] = env_signals[0];
+        sources[ModSource::ModEnv as usize] = env_signals[1];
+        sources[ModSource::Lfo as usize] = self.lfo.process();
+        sources[ModSource::Velocity as usize] = self.velocity;
+        sources[ModSource::Note as usize] = self.note;
+        sources[ModSource::Param1 as usize] = self.param1;
+        sources[ModSource::Param2 as usize] = self.param2;
+        sources[ModSource::Timbre as usize] = self.expression.timbre;
+        sources[ModSource::Random as usize] = self.random;
+        sources[ModSource::RandomGaussian as usize] = self.random_gaussian;
+        let mods = self.mod_matrix.process(&sources);
+        let mut pitch_bend = (1.0 + self.pitch_bend) * (1.0 + self.expression_bend) - 1.0;
+        if self.portamento.is_gliding() {
+            // the glide is applied relative to the pitch of the note
+            let offset = self.portamento.process() - self.portamento.target();
+            pitch_bend = (1.0 + pitch_bend) * (2f32).powf(offset / 12.0) - 1.0;
+        }
+
+        // modulators are split between modulating their targets and being
+        // heard directly, according to the FM amount
+        let algorithm = ALGORITHMS[self.algorithm];
+        let mod_depth = self.fm_amt * self.mod_index;
+        let mut outs = [0.0; OPERATOR_COUNT];
+        let mut y = 0.0;
+        for i in (0..OPERATOR_COUNT).rev() {
+            let is_carrier = algorithm.carriers & (1 << i) != 0;
+            let (freq_mod, fb_mod) = if is_carrier {
+                (mods[D::CarrierFreq as usize], mods[D::CarrierFb as usize])
+            } else {
+                (mods[D::ModFreq as usize], mods[D::ModFb as usize])
+            };
+            let phase_mod = (i + 1..OPERATOR_COUNT)
+                .filter(|j| algorithm.modulators[i] & (1 << j) != 0)
+                .map(|j| outs[j])
+                .sum::<f32>()
+                * mod_depth;
+
+            self.ops[i].fb_mod = fb_mod;
+            outs[i] = self.ops[i].process(phase_mod, freq_mod + pitch_bend)
+                * env_signals[i]
+                * self.levels[i];
+            y += if is_carrier {
+                outs[i]
+            } else {
+                outs[i] * (1.0 - self.fm_amt)
+            };
+        }
+        y *= (1.0 + mods[D::Amp as usize]).max(0.0);
+
+        self.filter.modulate_q(mods[D::FilterQ as usize]);
+        self.filter.process(
+            y,
+            mods[D::FilterCutoff as usize] + self.pressure + self.expression.pressure,
+        ) * 0.5
     }
 
-    pub fn set_parameter(&mut self, parameter: i8, value: f32) {
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
         match parameter {
-            0 => self.carrier.freq_hz = value,
-            1 => self.modulator.freq_hz = value,
-            2 => self.filter.update_freq(value),
+            0 => self.ops[0].freq_hz = value,
+            1 => self.ops[1].freq_hz = value,
+            2 => {
+                self.cutoff = value;
+                self.update_cutoff();
+            }
             3 => self.filter.update_q(value),
             4 => self.fm_amt = value,
             5 => self.mod_index = value,
-            6 => self.carrier.fb_amt = value,
-            7 => self.modulator.fb_amt = value,
-            8 => self.carrier_env.attack_ms = value,
-            9 => self.carrier_env.decay_ms = value,
-            10 => self.mod_env.attack_ms = value,
-            11 => self.mod_env.decay_ms = value,
-            12 => self.filter_mod_env_amt = value,
-            13 => self.pitch_carrier_env_amt = value,
-            14 => self.pitch_mod_env_amt = value,
+            6 => self.ops[0].fb_amt = value,
+            7 => self.ops[1].fb_amt = value,
+            8 => self.envs[0].attack_ms = value,
+            9 => self.envs[0].decay_ms = value,
+            10 => self.envs[1].attack_ms = value,
+            11 => self.envs[1].decay_ms = value,
+            12 => self.mod_matrix.set_depth(FILTER_MOD_ENV_SLOT, value),
+            13 => self.mod_matrix.set_depth(PITCH_CARRIER_ENV_SLOT, value),
+            14 => self.mod_matrix.set_depth(PITCH_MOD_ENV_SLOT, value),
             15 => self.reverb_amt = value,
             16 => self.delay_amt = value,
+            17 => {
+                self.lfo_rate = value;
+                self.lfo.set_freq(value);
+            }
+            18 => self.portamento.time_ms = value,
+            ALGORITHM_PARAMETER => {
+                self.algorithm = (value.max(0.0) as usize).min(ALGORITHMS.len() - 1)
+            }
+            KEY_TRACKING_PARAMETER => {
+                self.key_tracking = value.clamp(0.0, 2.0);
+                self.update_cutoff();
+            }
+            FIRST_OPERATOR_PARAMETER.. => {
+                let Some((op, parameter)) = Self::operator_parameter(parameter) else {
+                    return;
+                };
+                match parameter {
+                    0 => self.ops[op].freq_hz = value,
+                    1 => self.levels[op] = value,
+                    2 => self.envs[op].attack_ms = value,
+                    3 => self.envs[op].decay_ms = value,
+                    4 => self.ops[op].fb_amt = value,
+                    5 => {
+                        if let Some(mode) = FrequencyMode::from_u8(value as u8) {
+                            self.ops[op].mode = mode;
+                        }
+                    }
+                    6 => self.ops[op].ratio = value,
+                    _ => self.ops[op].offset_hz = value,
+                }
+                self.ops[op].update_freq();
+            }
             _ => (),
         }
     }
 
-    pub fn is_active(&self) -> bool {
-        !matches!(self.carrier_env.state, EnvelopeState::Off)
+    fn set_parameter_normalized(&mut self, parameter: i8, value: f32) {
+        let value = match parameter {
+            0 | 1 => scale_log(value, 20.0, 10000.0),
+            2 => scale_log(value, 20.0, 20000.0),
+            3 => 0.5 + value * 19.5,
+            5 => value * 10.0,
+            8..=11 => value * 5000.0,
+            17 => scale_log(value, 0.01, 50.0),
+            18 => value * 2000.0,
+            ALGORITHM_PARAMETER => (value * (ALGORITHMS.len() - 1) as f32).round(),
+            KEY_TRACKING_PARAMETER => value * 2.0,
+            FIRST_OPERATOR_PARAMETER.. => match Self::operator_parameter(parameter) {
+                Some((_, 0)) => scale_log(value, 20.0, 10000.0),
+                Some((_, 2 | 3)) => value * 5000.0,
+                Some((_, 5)) => value.round(),
+                Some((_, 6)) => value * 16.0,
+                Some((_, 7)) => value * 1000.0,
+                _ => value,
+            },
+            _ => value,
+        };
+        self.set_parameter(parameter, value);
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.ops[0].freq_hz,
+            1 => self.ops[1].freq_hz,
+            2 => self.cutoff,
+            3 => self.filter.get_q(),
+            4 => self.fm_amt,
+            5 => self.mod_index,
+            6 => self.ops[0].fb_amt,
+            7 => self.ops[1].fb_amt,
+            8 => self.envs[0].attack_ms,
+            9 => self.envs[0].decay_ms,
+            10 => self.envs[1].attack_ms,
+            11 => self.envs[1].decay_ms,
+            12 => self.mod_matrix.slots[FILTER_MOD_ENV_SLOT].depth,
+            13 => self.mod_matrix.slots[PITCH_CARRIER_ENV_SLOT].depth,
+            14 => self.mod_matrix.slots[PITCH_MOD_ENV_SLOT].depth,
+            15 => self.reverb_amt,
+            16 => self.delay_amt,
+            17 => self.lfo_rate,
+            18 => self.portamento.time_ms,
+            ALGORITHM_PARAMETER => self.algorithm as f32,
+            KEY_TRACKING_PARAMETER => self.key_tracking,
+            FIRST_OPERATOR_PARAMETER.. => match Self::operator_parameter(parameter) {
+                Some((op, 0)) => self.ops[op].freq_hz,
+                Some((op, 1)) => self.levels[op],
+                Some((op, 2)) => self.envs[op].attack_ms,
+                Some((op, 3)) => self.envs[op].decay_ms,
+                Some((op, 4)) => self.ops[op].fb_amt,
+                Some((op, 5)) => self.ops[op].mode as u8 as f32,
+                Some((op, 6)) => self.ops[op].ratio,
+                Some((op, _)) => self.ops[op].offset_hz,
+                None => 0.0,
+            },
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        PARAMETER_COUNT
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        parameter == ALGORITHM_PARAMETER
+            || matches!(Self::operator_parameter(parameter), Some((_, 5)))
+    }
+
+    /// Pitch of the last played note
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Active while any operator heard at the output is sounding
+    fn is_active(&self) -> bool {
+        let carriers = ALGORITHMS[self.algorithm].carriers;
+        self.envs
+            .iter()
+            .enumerate()
+            .any(|(i, env)| carriers & (1 << i) != 0 && !matches!(env.state, EnvelopeState::Off))
+    }
+}
+
+impl FmVoice {
+    pub fn trigger(&mut self, velocity: u8) {
+        self.velocity = velocity as f32 / 127.0;
+        let mut rng = rand::thread_rng();
+        self.random = rng.gen_range(-1.0..=1.0);
+        self.random_gaussian = gaussian_random(&mut rng);
+        self.mod_matrix.trigger();
+        for env in self.envs.iter_mut() {
+            env.trigger(velocity);
+        }
+    }
+
+    /// Apply the cutoff, following the pitch of the played note
+    fn update_cutoff(&mut self) {
+        self.filter
+            .update_freq(self.cutoff * key_tracking(self.pitch as f32, self.key_tracking));
+    }
+
+    /// Let operators in ratio mode follow the note frequency
+    fn set_note_freq(&mut self, note_freq: f32) {
+        for op in self.ops.iter_mut() {
+            op.set_note_freq(note_freq);
+        }
+    }
+
+    pub fn parameters() -> Vec<ParameterInfo> {
+        let mut parameters = vec![
+            ParameterInfo::logarithmic(0, "Carrier frequency", 20.0, 10000.0, 200.0, "Hz"),
+            ParameterInfo::logarithmic(1, "Modulator frequency", 20.0, 10000.0, 200.0, "Hz"),
+            ParameterInfo::logarithmic(2, "Filter cutoff", 20.0, 20000.0, 4000.0, "Hz"),
+            ParameterInfo::linear(3, "Filter resonance", 0.5, 20.0, 1.717, ""),
+            ParameterInfo::linear(4, "FM amount", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(5, "Modulation index", 0.0, 10.0, 0.0, ""),
+            ParameterInfo::linear(6, "Carrier feedback", 0.0, 1.0, 0.9, ""),
+            ParameterInfo::linear(7, "Modulator feedback", 0.0, 1.0, 0.9, ""),
+            ParameterInfo::linear(8, "Carrier attack", 0.0, 5000.0, 1.0, "ms"),
+            ParameterInfo::linear(9, "Carrier decay", 0.0, 5000.0, 500.0, "ms"),
+            ParameterInfo::linear(10, "Modulator attack", 0.0, 5000.0, 1.0, "ms"),
+            ParameterInfo::linear(11, "Modulator decay", 0.0, 5000.0, 100.0, "ms"),
+            ParameterInfo::linear(12, "Filter envelope", -1.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(13, "Carrier pitch envelope", -1.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(14, "Modulator pitch envelope", -1.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(15, "Reverb send", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(16, "Delay send", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::logarithmic(17, "LFO rate", 0.01, 50.0, 1.0, "Hz"),
+            ParameterInfo::linear(18, "Portamento", 0.0, 2000.0, 0.0, "ms"),
+            ParameterInfo::stepped(
+                ALGORITHM_PARAMETER,
+                "Algorithm",
+                0.0,
+                (ALGORITHMS.len() - 1) as f32,
+                0.0,
+            ),
+        ];
+        for op in 0..OPERATOR_COUNT {
+            let base = FIRST_OPERATOR_PARAMETER + op as i8 * OPERATOR_PARAMETERS;
+            let name = |parameter: &str| format!("Operator {} {}", op + 1, parameter);
+            let level = if op < 2 { 1.0 } else { 0.0 };
+            let decay = if op == 0 { 500.0 } else { 100.0 };
+            parameters.extend([
+                ParameterInfo::logarithmic(base, name("frequency"), 20.0, 10000.0, 200.0, "Hz"),
+                ParameterInfo::linear(base + 1, name("level"), 0.0, 1.0, level, ""),
+                ParameterInfo::linear(base + 2, name("attack"), 0.0, 5000.0, 1.0, "ms"),
+                ParameterInfo::linear(base + 3, name("decay"), 0.0, 5000.0, decay, "ms"),
+                ParameterInfo::linear(base + 4, name("feedback"), 0.0, 1.0, 0.9, ""),
+                ParameterInfo::stepped(base + 5, name("frequency mode"), 0.0, 1.0, 0.0),
+                ParameterInfo::linear(base + 6, name("ratio"), 0.0, 16.0, 1.0, ""),
+                ParameterInfo::linear(base + 7, name("offset"), 0.0, 1000.0, 0.0, "Hz"),
+            ]);
+        }
+        parameters.push(ParameterInfo::linear(
+            KEY_TRACKING_PARAMETER,
+            "Key tracking",
+            0.0,
+            2.0,
+            0.0,
+            "",
+        ));
+        parameters
+    }
+
+    /// Split a per-operator parameter into its operator and the parameter
+    /// within the operator's block
+    fn operator_parameter(parameter: i8) -> Option<(usize, i8)> {
+        let offset = parameter - FIRST_OPERATOR_PARAMETER;
+        let op = (offset / OPERATOR_PARAMETERS) as usize;
+        (offset >= 0 && op < OPERATOR_COUNT).then_some((op, offset % OPERATOR_PARAMETERS))
     }
 }
+
 pub struct BLITVoice {
     osc: BlitSawOsc,
     env: AR,
@@ -108,6 +556,18 @@ pub struct BLITVoice {
     sample_rate: f32,
 }
 
+impl BLITVoice {
+    /// Filter cutoff and resonance are normalized, to 10 kHz and a Q of 10
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Filter cutoff", 0.0, 1.0, 0.05, ""),
+            ParameterInfo::linear(1, "Filter resonance", 0.0, 1.0, 0.1717, ""),
+            ParameterInfo::linear(2, "Attack", 0.0, 5000.0, 10.0, "ms"),
+            ParameterInfo::linear(3, "Decay", 0.0, 5000.0, 500.0, "ms"),
+        ]
+    }
+}
+
 impl SynthVoice for BLITVoice {
     fn new(sample_rate: f32) -> Self {
         Self {
@@ -132,6 +592,14 @@ impl SynthVoice for BLITVoice {
         self.env.trigger(velocity);
     }
 
+    fn set_pitch(&mut self, pitch: f32) {
+        self.osc.set_freq(fractional_pitch_to_freq(pitch));
+    }
+
+    fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        self.env.retrigger = mode;
+    }
+
     fn reset(&mut self) {}
 
     fn stop(&mut self) {}
@@ -146,6 +614,20 @@ impl SynthVoice for BLITVoice {
         }
     }
 
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.filter.get_freq() / 10000.0,
+            1 => self.filter.get_q() / 10.0,
+            2 => self.env.attack_ms,
+            3 => self.env.decay_ms,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        4
+    }
+
     fn get_pitch(&self) -> u8 {
         0
     }
@@ -154,3 +636,135 @@ impl SynthVoice for BLITVoice {
         !matches!(self.env.state, EnvelopeState::Off)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn render(voice: &mut FmVoice, length: usize) -> Vec<f32> {
+        (0..length).map(|_| voice.process()).collect()
+    }
+
+    #[test]
+    fn operator_parameters_round_trip() {
+        let mut voice = FmVoice::new(SAMPLE_RATE);
+        for op in 0..OPERATOR_COUNT as i8 {
+            let base = FIRST_OPERATOR_PARAMETER + op * OPERATOR_PARAMETERS;
+            // frequency, level, envelope and feedback
+            for parameter in 0..5 {
+                let value = 10.0 * op as f32 + parameter as f32 + 1.0;
+                voice.set_parameter(base + parameter, value);
+                assert_eq!(voice.get_parameter(base + parameter), value);
+            }
+        }
+        // the original carrier / modulator parameters address ops 0 and 1
+        voice.set_parameter(1, 330.0);
+        assert_eq!(voice.ops[1].freq_hz, 330.0);
+        assert_eq!(
+            voice.get_parameter(FIRST_OPERATOR_PARAMETER + OPERATOR_PARAMETERS),
+            330.0
+        );
+        assert_eq!(voice.get_parameter(PARAMETER_COUNT), 0.0);
+    }
+
+    #[test]
+    fn random_sources_change_per_note() {
+        let mut voice = FmVoice::new(SAMPLE_RATE);
+        let mut draws = Vec::new();
+        for _ in 0..8 {
+            voice.play(60, 100, 0.0, 0.0);
+            assert!((-1.0..=1.0).contains(&voice.random));
+            assert!((-1.0..=1.0).contains(&voice.random_gaussian));
+            draws.push(voice.random);
+        }
+        assert!(draws.iter().any(|&x| x != draws[0]));
+    }
+
+    #[test]
+    fn ratio_mode_tracks_the_note() {
+        let mut voice = FmVoice::new(SAMPLE_RATE);
+        let base = FIRST_OPERATOR_PARAMETER + OPERATOR_PARAMETERS;
+        voice.set_parameter(base + 5, 1.0);
+        voice.set_parameter(base + 6, 2.0);
+        voice.set_parameter(base + 7, 5.0);
+        voice.play(69, 100, 0.0, 0.0);
+        assert_eq!(voice.ops[1].freq_hz, 885.0);
+        // fixed operators ignore the note
+        assert_eq!(voice.ops[0].freq_hz, 200.0);
+
+        // gliding to a new note retunes the operators right away
+        voice.set_pitch(57.0);
+        assert_eq!(voice.ops[1].freq_hz, 445.0);
+        assert_eq!(voice.get_parameter(base), 445.0);
+    }
+
+    #[test]
+    fn cutoff_tracks_the_key() {
+        let mut voice = FmVoice::new(SAMPLE_RATE);
+        voice.set_parameter(2, 1000.0);
+        voice.set_parameter(KEY_TRACKING_PARAMETER, 2.0);
+        voice.play(72, 100, 0.0, 0.0);
+        assert!((voice.filter.get_freq() - 4000.0).abs() < 0.1);
+        assert_eq!(voice.get_parameter(2), 1000.0);
+        assert_eq!(voice.get_parameter(KEY_TRACKING_PARAMETER), 2.0);
+
+        voice.set_parameter(KEY_TRACKING_PARAMETER, 0.0);
+        assert_eq!(voice.filter.get_freq(), 1000.0);
+    }
+
+    #[test]
+    fn algorithm_is_clamped() {
+        let mut voice = FmVoice::new(SAMPLE_RATE);
+        voice.set_parameter(ALGORITHM_PARAMETER, 100.0);
+        assert_eq!(voice.algorithm, ALGORITHMS.len() - 1);
+        voice.set_parameter_normalized(ALGORITHM_PARAMETER, 0.0);
+        assert_eq!(voice.algorithm, 0);
+    }
+
+    #[test]
+    fn modulation_flows_downward() {
+        for algorithm in ALGORITHMS.iter() {
+            for (i, modulators) in algorithm.modulators.iter().enumerate() {
+                assert_eq!(modulators & ((1 << (i + 1)) - 1), 0);
+            }
+            assert_ne!(algorithm.carriers, 0);
+        }
+    }
+
+    #[test]
+    fn algorithms_change_timbre() {
+        let mut stack = FmVoice::new(SAMPLE_RATE);
+        let mut additive = FmVoice::new(SAMPLE_RATE);
+        for voice in [&mut stack, &mut additive] {
+            voice.set_parameter(4, 1.0);
+            voice.set_parameter(5, 5.0);
+            for op in 0..OPERATOR_COUNT as i8 {
+                voice.set_parameter(FIRST_OPERATOR_PARAMETER + op * OPERATOR_PARAMETERS + 1, 1.0);
+            }
+        }
+        additive.set_parameter(ALGORITHM_PARAMETER, 7.0);
+        stack.play(60, 100, 0.0, 0.0);
+        additive.play(60, 100, 0.0, 0.0);
+        let a = render(&mut stack, 1000);
+        let b = render(&mut additive, 1000);
+        assert!(a.iter().zip(b.iter()).any(|(a, b)| (a - b).abs() > 1e-3));
+    }
+
+    #[test]
+    fn active_while_a_carrier_sounds() {
+        let mut voice = FmVoice::new(SAMPLE_RATE);
+        // operator 2 is a carrier with a long decay in algorithm 4
+        voice.set_parameter(ALGORITHM_PARAMETER, 4.0);
+        voice.set_parameter(9, 10.0);
+        voice.set_parameter(
+            FIRST_OPERATOR_PARAMETER + 2 * OPERATOR_PARAMETERS + 3,
+            1000.0,
+        );
+        voice.play(60, 100, 0.0, 0.0);
+        render(&mut voice, 4800);
+        assert!(voice.is_active());
+        assert!(matches!(voice.envs[0].state, EnvelopeState::Off));
+    }
+}
@@ -1,3 +1,14 @@
+//! Plots
+//!
+//! `plot_graph` draws a signal into a PNG in `plots/`, for looking at the
+//! DSP while developing. The rest render the shapes of modulators into a
+//! buffer the caller provides, one point per element, so an interface can
+//! draw an envelope or an LFO. They run the modulators themselves rather
+//! than a drawing of them, so the shapes are exactly what's heard.
+
+use crate::envelopes::{EnvelopeState, MultiStage, AR};
+use crate::lfo::LfoShape;
+use crate::osc::{Osc, Waveform};
 use plotters::prelude::*;
 
 pub fn plot_graph(xs: &[f32], ys: &[f32], filename: &str) {
@@ -26,3 +37,124 @@ pub fn plot_graph(xs: &[f32], ys: &[f32], filename: &str) {
         ))
         .unwrap();
 }
+
+/// Run `process` once per sample for `length` samples and the one after,
+/// keeping the points of `buffer` spread evenly from the first to the last
+fn sample_evenly(buffer: &mut [f32], length: usize, mut process: impl FnMut(usize) -> f32) {
+    let points = buffer.len();
+    let at = |point: usize| {
+        if points > 1 {
+            point * length / (points - 1)
+        } else {
+            0
+        }
+    };
+    let mut point = 0;
+    for n in 0..=length {
+        let y = process(n);
+        while point < points && at(point) == n {
+            buffer[point] = y;
+            point += 1;
+        }
+    }
+}
+
+/// Render an AR envelope, triggered at full velocity, into `buffer` until
+/// it has decayed. Returns the time the buffer spans in milliseconds.
+pub fn render_ar(envelope: &AR, buffer: &mut [f32]) -> f32 {
+    let mut envelope = *envelope;
+    // from silence, whatever the envelope was doing
+    envelope.state = EnvelopeState::Off;
+    let length_ms = envelope.attack_ms + envelope.decay_ms;
+    let length = (length_ms * envelope.sample_rate() / 1000.0).ceil() as usize;
+    envelope.trigger(127);
+    sample_evenly(buffer, length, |_| envelope.process());
+    length_ms
+}
+
+/// Render a multi-stage envelope, triggered at full velocity, into
+/// `buffer`, holding its sustain for `sustain_ms` before releasing it.
+/// Returns the time the buffer spans in milliseconds.
+pub fn render_envelope(envelope: &MultiStage, sustain_ms: f32, buffer: &mut [f32]) -> f32 {
+    let mut envelope = *envelope;
+    let stages_ms = |count: usize| -> f32 {
+        let stages = envelope.stages().iter().take(count);
+        stages.map(|stage| stage.time_ms).sum()
+    };
+    let (sustain_ms, release_ms) = match envelope.sustain_stage {
+        Some(sustain) => {
+            let sustain_ms = sustain_ms.max(0.0);
+            (sustain_ms, Some(stages_ms(sustain + 1) + sustain_ms))
+        }
+        None => (0.0, None),
+    };
+    let length_ms = stages_ms(usize::MAX) + sustain_ms;
+    let samples_per_ms = envelope.sample_rate() / 1000.0;
+    let length = (length_ms * samples_per_ms).ceil() as usize;
+    let release = release_ms.map(|ms| (ms * samples_per_ms).round() as usize);
+    envelope.trigger(127);
+    sample_evenly(buffer, length, |n| {
+        if Some(n) == release {
+            envelope.release();
+        }
+        envelope.process()
+    });
+    length_ms
+}
+
+/// Render a cycle of a global LFO's shape into `buffer`, from 0.0 at the
+/// bottom to 1.0 at the top, the first point at the start of the cycle
+pub fn render_lfo(shape: LfoShape, buffer: &mut [f32]) {
+    let points = buffer.len() as f32;
+    for (point, y) in buffer.iter_mut().enumerate() {
+        *y = shape.value(point as f32 / points);
+    }
+}
+
+/// Render a cycle of an oscillator running as a voice's LFO into
+/// `buffer`, -1.0..1.0
+pub fn render_waveform(waveform: Waveform, buffer: &mut [f32]) {
+    // one cycle per buffer
+    let mut osc = Osc::new(waveform, buffer.len() as f32);
+    osc.set_freq(1.0);
+    for y in buffer.iter_mut() {
+        *y = osc.process();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelopes::CurveType;
+
+    #[test]
+    fn envelopes_render_their_whole_shape() {
+        let ar = AR::new(10.0, 30.0, CurveType::Linear, 1000.0);
+        let mut buffer = [1.0; 5];
+        assert_eq!(render_ar(&ar, &mut buffer), 40.0);
+        assert_eq!(buffer[0], 0.0);
+        assert!((buffer[1] - 1.0).abs() < 0.05);
+        assert_eq!(buffer[4], 0.0);
+
+        let adsr = MultiStage::dahdsr(0.0, 10.0, 0.0, 10.0, 0.5, 20.0, 1000.0);
+        let mut buffer = [1.0; 61];
+        assert_eq!(render_envelope(&adsr, 20.0, &mut buffer), 60.0);
+        assert!((buffer[10] - 1.0).abs() < 1e-6);
+        assert!((buffer[30] - 0.5).abs() < 1e-6);
+        assert!(buffer[50] < 0.5);
+        assert_eq!(buffer[60], 0.0);
+    }
+
+    #[test]
+    fn lfos_render_a_cycle() {
+        let mut buffer = [0.0; 4];
+        render_lfo(LfoShape::RampUp, &mut buffer);
+        assert_eq!(buffer, [0.0, 0.25, 0.5, 0.75]);
+        render_lfo(LfoShape::Triangle, &mut buffer);
+        assert_eq!(buffer, [0.0, 0.5, 1.0, 0.5]);
+
+        let mut buffer = [0.0; 8];
+        render_waveform(Waveform::Square, &mut buffer);
+        assert_eq!(buffer, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+    }
+}
@@ -0,0 +1,441 @@
+//! Preset persistence
+//!
+//! A preset captures every voice parameter, the mixer, the effects on the
+//! buses and inserts, and the current sequence. It can be written to / read
+//! from JSON or compact binary files, or kept in an in-memory bank.
+//!
+//! The audio thread never allocates for a preset: it's captured into a
+//! `PresetCapture` with room for everything, and loaded from a
+//! `LoadedPreset` holding the voices and effects already built, which
+//! takes the replaced ones back.
+
+use crate::bus::{ChainCapture, EffectChain, EffectType, SendBus, TrackSend, MAX_BUSES};
+use crate::chords::MAX_CHORD_NOTES;
+use crate::consts::TRACK_COUNT;
+use crate::eq::EQ_PARAMETER_COUNT;
+use crate::mixer::ChannelStrip;
+use crate::modulation::{ModSlot, MOD_SLOT_COUNT};
+use crate::sequencer::Event;
+use crate::synth::{create_voice, PolyVoice, SynthVoice, VoiceType};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const PRESET_BANK_SIZE: usize = 128;
+
+// start of binary presets, telling them apart from JSON ones
+const BINARY_MAGIC: &[u8; 4] = b"CP3P";
+
+// room a capture starts out with, it's retried with more when short
+pub(crate) const CAPTURE_EVENTS: usize = 1024;
+pub(crate) const CAPTURE_NAME_LEN: usize = 64;
+
+// voice parameters, whose count is an i8, and the EQ's
+const MAX_TRACK_PARAMETERS: usize = i8::MAX as usize + EQ_PARAMETER_COUNT as usize;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackPreset {
+    #[serde(default)]
+    pub voice_type: VoiceType,
+    pub parameters: Vec<(i8, f32)>,
+    #[serde(default)]
+    pub mod_slots: Vec<ModSlot>,
+    #[serde(default)]
+    pub strip: ChannelStrip,
+    /// send into each bus
+    #[serde(default)]
+    pub sends: Vec<TrackSend>,
+    #[serde(default)]
+    pub inserts: Vec<EffectPreset>,
+    /// stereo output the track plays on, 0 for the main one
+    #[serde(default)]
+    pub output: u8,
+}
+
+/// An effect on a bus or insert, with its parameters
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EffectPreset {
+    pub effect_type: EffectType,
+    pub parameters: Vec<(i8, f32)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BusPreset {
+    pub name: String,
+    pub return_level: f32,
+    pub effects: Vec<EffectPreset>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub tracks: Vec<TrackPreset>,
+    /// every bus, the reverb and delay first
+    #[serde(default)]
+    pub buses: Vec<BusPreset>,
+    #[serde(default)]
+    pub input_inserts: Vec<EffectPreset>,
+    #[serde(default = "unity")]
+    pub master_volume: f32,
+    pub events: Vec<Event>,
+}
+
+fn unity() -> f32 {
+    1.0
+}
+
+impl Preset {
+    pub fn to_json(&self) -> io::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// The preset in the binary format, smaller and quicker to read than
+    /// JSON
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, self).map_err(invalid_data)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let body = bytes
+            .strip_prefix(BINARY_MAGIC)
+            .ok_or_else(|| invalid_data("not a binary preset"))?;
+        bincode::deserialize(body).map_err(invalid_data)
+    }
+
+    /// Write the preset to `path` as JSON
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json()?)
+    }
+
+    /// Write the preset to `path` in the binary format
+    pub fn save_binary<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes()?)
+    }
+
+    /// Read a preset written by `save` or `save_binary`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(BINARY_MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            Self::from_json(std::str::from_utf8(&bytes).map_err(invalid_data)?)
+        }
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// A track as captured on the audio thread
+pub(crate) struct TrackCapture {
+    pub(crate) voice_type: VoiceType,
+    pub(crate) parameters: Vec<(i8, f32)>,
+    pub(crate) mod_slots: Option<[ModSlot; MOD_SLOT_COUNT]>,
+    pub(crate) strip: ChannelStrip,
+    pub(crate) sends: [TrackSend; MAX_BUSES],
+    pub(crate) inserts: ChainCapture,
+    pub(crate) output: u8,
+}
+
+/// A bus as captured on the audio thread
+pub(crate) struct BusCapture {
+    pub(crate) name: String,
+    pub(crate) return_level: f32,
+    pub(crate) effects: ChainCapture,
+}
+
+/// Room for the engine to copy its sound and sequence into on the audio
+/// thread, see `Engine::capture_preset_into`. Made into a `Preset` on the
+/// host.
+pub struct PresetCapture {
+    pub(crate) tracks: [TrackCapture; TRACK_COUNT],
+    pub(crate) buses: [BusCapture; MAX_BUSES],
+    pub(crate) bus_count: usize,
+    pub(crate) input_inserts: ChainCapture,
+    pub(crate) master_volume: f32,
+    pub(crate) events: Vec<Event>,
+    // events and bus name length needed, when there wasn't room for them
+    pub(crate) missing: Option<(usize, usize)>,
+}
+
+impl PresetCapture {
+    /// Room for `events` events and bus names of `name_len` bytes
+    pub fn with_room(events: usize, name_len: usize) -> Self {
+        Self {
+            tracks: std::array::from_fn(|_| TrackCapture {
+                voice_type: VoiceType::default(),
+                parameters: Vec::with_capacity(MAX_TRACK_PARAMETERS),
+                mod_slots: None,
+                strip: ChannelStrip::default(),
+                sends: [TrackSend::default(); MAX_BUSES],
+                inserts: ChainCapture::default(),
+                output: 0,
+            }),
+            buses: std::array::from_fn(|_| BusCapture {
+                name: String::with_capacity(name_len),
+                return_level: 1.0,
+                effects: ChainCapture::default(),
+            }),
+            bus_count: 0,
+            input_inserts: ChainCapture::default(),
+            master_volume: 1.0,
+            events: Vec::with_capacity(events),
+            missing: None,
+        }
+    }
+
+    /// The events and bus name length to make room for when the capture
+    /// didn't fit, None when it's complete
+    pub fn missing_room(&self) -> Option<(usize, usize)> {
+        self.missing
+    }
+
+    pub fn into_preset(self, sample_rate: f32) -> Preset {
+        let bus_count = self.bus_count;
+        let tracks = self
+            .tracks
+            .into_iter()
+            .map(|track| TrackPreset {
+                voice_type: track.voice_type,
+                parameters: track.parameters,
+                mod_slots: track
+                    .mod_slots
+                    .map(|slots| slots.to_vec())
+                    .unwrap_or_default(),
+                strip: track.strip,
+                sends: track.sends[..bus_count].to_vec(),
+                inserts: track.inserts.presets(sample_rate),
+                output: track.output,
+            })
+            .collect();
+        let buses = self
+            .buses
+            .into_iter()
+            .take(bus_count)
+            .map(|bus| BusPreset {
+                name: bus.name,
+                return_level: bus.return_level,
+                effects: bus.effects.presets(sample_rate),
+            })
+            .collect();
+
+        Preset {
+            tracks,
+            buses,
+            input_inserts: self.input_inserts.presets(sample_rate),
+            master_volume: self.master_volume,
+            events: self.events,
+        }
+    }
+}
+
+/// A preset with its voices, buses and effects built off the audio thread,
+/// see `Engine::apply_loaded_preset`. What the engine replaces is swapped
+/// in, to be dropped back on the host.
+pub struct LoadedPreset {
+    pub(crate) preset: Preset,
+    // one per track of the preset
+    pub(crate) voices: Vec<Box<dyn SynthVoice>>,
+    // tracks whose voices were built for chords
+    pub(crate) chord_tracks: [bool; TRACK_COUNT],
+    pub(crate) inserts: Vec<EffectChain>,
+    pub(crate) buses: Vec<SendBus>,
+    pub(crate) input_inserts: EffectChain,
+}
+
+impl LoadedPreset {
+    /// Build what `preset` needs, a voice per chord note on `chord_tracks`
+    pub fn new(preset: Preset, sample_rate: f32, chord_tracks: [bool; TRACK_COUNT]) -> Self {
+        let tracks = preset.tracks.iter().take(TRACK_COUNT);
+        let voices = tracks
+            .clone()
+            .zip(chord_tracks)
+            .map(|(track, is_chord)| -> Box<dyn SynthVoice> {
+                let voice = || create_voice(track.voice_type, sample_rate);
+                if is_chord {
+                    Box::new(PolyVoice::with_voices(
+                        (0..MAX_CHORD_NOTES).map(|_| voice()).collect(),
+                    ))
+                } else {
+                    voice()
+                }
+            })
+            .collect();
+        let inserts = tracks
+            .map(|track| EffectChain::from_presets(&track.inserts, sample_rate))
+            .collect();
+        let buses = preset
+            .buses
+            .iter()
+            .take(MAX_BUSES)
+            .map(|saved| {
+                let mut bus = SendBus::new(&saved.name);
+                bus.return_level = saved.return_level;
+                bus.chain = EffectChain::from_presets(&saved.effects, sample_rate);
+                bus
+            })
+            .collect();
+        let input_inserts = EffectChain::from_presets(&preset.input_inserts, sample_rate);
+
+        Self {
+            preset,
+            voices,
+            chord_tracks,
+            inserts,
+            buses,
+            input_inserts,
+        }
+    }
+}
+
+/// In-memory collection of presets, addressed by slot
+pub struct PresetBank {
+    presets: Vec<Option<Preset>>,
+}
+
+impl PresetBank {
+    pub fn new() -> Self {
+        Self {
+            presets: vec![None; PRESET_BANK_SIZE],
+        }
+    }
+
+    pub fn store(&mut self, slot: usize, preset: Preset) {
+        if let Some(p) = self.presets.get_mut(slot) {
+            *p = Some(preset);
+        }
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Preset> {
+        self.presets.get(slot).and_then(|p| p.as_ref())
+    }
+
+    pub fn clear(&mut self, slot: usize) {
+        if let Some(p) = self.presets.get_mut(slot) {
+            *p = None;
+        }
+    }
+}
+
+impl Default for PresetBank {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_preset() -> Preset {
+        Preset {
+            tracks: vec![TrackPreset {
+                voice_type: VoiceType::Fm,
+                parameters: vec![(0, 220.0), (4, 0.5)],
+                mod_slots: vec![ModSlot::default()],
+                strip: ChannelStrip {
+                    volume: 0.5,
+                    pan: -0.25,
+                    mute: false,
+                    solo: true,
+                },
+                sends: vec![TrackSend {
+                    level: 0.3,
+                    pre_fader: true,
+                }],
+                inserts: vec![EffectPreset {
+                    effect_type: EffectType::Saturator,
+                    parameters: vec![(0, 2.0)],
+                }],
+                output: 1,
+            }],
+            buses: vec![BusPreset {
+                name: "delay".to_string(),
+                return_level: 0.8,
+                effects: vec![EffectPreset {
+                    effect_type: EffectType::Delay,
+                    parameters: vec![(0, 24000.0), (1, 0.5)],
+                }],
+            }],
+            input_inserts: Vec::new(),
+            master_volume: 0.9,
+            events: vec![Event {
+                beat_time: 1.0,
+                pitch: 60,
+                velocity: 100,
+                param1: 0.0,
+                param2: 0.0,
+                track: 0,
+                duration: 1.0,
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let preset = test_preset();
+        let json = preset.to_json().unwrap();
+        assert_eq!(Preset::from_json(&json).unwrap(), preset);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let preset = test_preset();
+        let bytes = preset.to_bytes().unwrap();
+        assert!(bytes.len() < preset.to_json().unwrap().len());
+        assert_eq!(Preset::from_bytes(&bytes).unwrap(), preset);
+        assert!(Preset::from_bytes(b"CP3P").is_err());
+        assert!(Preset::from_bytes(preset.to_json().unwrap().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn save_and_load_file() {
+        let preset = test_preset();
+        let path = std::env::temp_dir().join("cp3_dsp_preset_test.json");
+        preset.save(&path).unwrap();
+        assert_eq!(Preset::load(&path).unwrap(), preset);
+        // either format loads
+        preset.save_binary(&path).unwrap();
+        assert_eq!(Preset::load(&path).unwrap(), preset);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn older_presets_load_with_defaults() {
+        let json = r#"{"tracks": [{"parameters": []}], "events": []}"#;
+        let preset = Preset::from_json(json).unwrap();
+        assert_eq!(preset.master_volume, 1.0);
+        assert_eq!(preset.tracks[0].strip, ChannelStrip::default());
+        assert!(preset.buses.is_empty());
+    }
+
+    #[test]
+    fn invalid_json_is_an_error() {
+        assert!(Preset::from_json("not a preset").is_err());
+    }
+
+    #[test]
+    fn bank_store_and_get() {
+        let mut bank = PresetBank::new();
+        assert!(bank.get(0).is_none());
+        bank.store(0, test_preset());
+        assert_eq!(bank.get(0), Some(&test_preset()));
+        bank.clear(0);
+        assert!(bank.get(0).is_none());
+
+        // out of range slots are ignored
+        bank.store(PRESET_BANK_SIZE, test_preset());
+        assert!(bank.get(PRESET_BANK_SIZE).is_none());
+    }
+}
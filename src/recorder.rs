@@ -0,0 +1,223 @@
+//! Sample recorder
+//!
+//! Records the audio input or the master output into a sample buffer, for
+//! the sampler to play back. Recording can wait for the signal to cross a
+//! threshold, and synced recordings start and stop on bar lines of the
+//! playing pattern, so loops come out a whole number of bars long.
+
+use crate::sampler::Sample;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordSource {
+    Input,
+    Master,
+}
+
+impl RecordSource {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(RecordSource::Input),
+            1 => Some(RecordSource::Master),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordSettings {
+    /// name the finished sample is kept under
+    pub name: String,
+    pub source: RecordSource,
+    /// level the signal has to reach before recording starts
+    pub threshold: Option<f32>,
+    /// start and stop on bar lines while the transport runs
+    pub synced: bool,
+    /// track the finished sample is loaded onto
+    pub track: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    // waiting for the threshold, or the threshold crossed and waiting for a
+    // bar line
+    Armed { triggered: bool },
+    Recording,
+    // recording on until the next bar line
+    Stopping,
+}
+
+pub struct Recorder {
+    settings: Option<RecordSettings>,
+    buffer: Vec<f32>,
+    state: State,
+    last_bar_position: Option<f32>,
+    sample_rate: f32,
+}
+
+impl Recorder {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            settings: None,
+            buffer: Vec::new(),
+            state: State::Idle,
+            last_bar_position: None,
+            sample_rate,
+        }
+    }
+
+    /// Arm a recording into `buffer`, which is allocated by the caller so
+    /// the audio thread doesn't have to; its capacity is the longest the
+    /// recording can get
+    pub fn arm(&mut self, settings: RecordSettings, mut buffer: Vec<f32>) {
+        buffer.clear();
+        self.state = State::Armed {
+            triggered: settings.threshold.is_none(),
+        };
+        self.settings = Some(settings);
+        self.buffer = buffer;
+    }
+
+    /// Stop recording, at the next bar line for synced recordings. An armed
+    /// recording that hasn't started is cancelled.
+    pub fn stop(&mut self) -> Option<(RecordSettings, Sample)> {
+        match self.state {
+            State::Recording if self.is_synced() => {
+                self.state = State::Stopping;
+                None
+            }
+            State::Recording | State::Stopping => self.finish(),
+            State::Armed { .. } => {
+                self.state = State::Idle;
+                self.settings = None;
+                None
+            }
+            State::Idle => None,
+        }
+    }
+
+    pub fn source(&self) -> Option<RecordSource> {
+        self.settings.as_ref().map(|settings| settings.source)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.state, State::Recording | State::Stopping)
+    }
+
+    /// Feed one sample. `bar_position` is the playing position in bars,
+    /// None while the transport is stopped. Returns the finished recording
+    /// and its settings.
+    #[inline]
+    pub fn process(
+        &mut self,
+        x: f32,
+        bar_position: Option<f32>,
+    ) -> Option<(RecordSettings, Sample)> {
+        let is_bar_line = match (self.last_bar_position, bar_position) {
+            (Some(last), Some(position)) => position.floor() != last.floor() || position < last,
+            (None, Some(position)) => position.fract() < 1e-4,
+            _ => false,
+        };
+        self.last_bar_position = bar_position;
+
+        match self.state {
+            State::Idle => return None,
+            State::Armed { triggered } => {
+                let threshold = self.settings.as_ref().and_then(|s| s.threshold);
+                let triggered = triggered || threshold.is_some_and(|t| x.abs() >= t);
+                if !triggered || (self.is_synced() && !is_bar_line) {
+                    self.state = State::Armed { triggered };
+                    return None;
+                }
+                self.state = State::Recording;
+            }
+            State::Stopping if is_bar_line => return self.finish(),
+            _ => {}
+        }
+
+        if self.buffer.len() == self.buffer.capacity() {
+            return self.finish();
+        }
+        self.buffer.push(x);
+        None
+    }
+
+    fn is_synced(&self) -> bool {
+        self.settings.as_ref().is_some_and(|s| s.synced)
+    }
+
+    fn finish(&mut self) -> Option<(RecordSettings, Sample)> {
+        self.state = State::Idle;
+        let settings = self.settings.take()?;
+        let data = std::mem::take(&mut self.buffer);
+        Some((settings, Sample::new(data, self.sample_rate)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(threshold: Option<f32>, synced: bool) -> RecordSettings {
+        RecordSettings {
+            name: "loop".to_string(),
+            source: RecordSource::Input,
+            threshold,
+            synced,
+            track: None,
+        }
+    }
+
+    #[test]
+    fn threshold_arms_the_recording() {
+        let mut recorder = Recorder::new(48000.0);
+        recorder.arm(settings(Some(0.5), false), Vec::with_capacity(4));
+        for x in [0.1, -0.2, 0.6, 0.3, 0.0, 0.1] {
+            assert!(recorder.process(x, None).is_none());
+        }
+        assert!(recorder.is_recording());
+        let (settings, sample) = recorder.process(0.2, None).unwrap();
+        // full after four samples
+        assert_eq!(sample.data, [0.6, 0.3, 0.0, 0.1]);
+        assert_eq!(sample.sample_rate, 48000.0);
+        assert_eq!(settings.name, "loop");
+        assert!(!recorder.is_recording());
+    }
+
+    #[test]
+    fn synced_recordings_span_whole_bars() {
+        let mut recorder = Recorder::new(1.0);
+        recorder.arm(settings(None, true), Vec::with_capacity(100));
+        // a quarter bar per sample, starting mid bar
+        let mut position = 0.5;
+        let mut step = |recorder: &mut Recorder| {
+            let result = recorder.process(position, Some(position % 2.0));
+            position += 0.25;
+            result
+        };
+        for _ in 0..2 {
+            step(&mut recorder);
+        }
+        assert!(!recorder.is_recording());
+        step(&mut recorder);
+        assert!(recorder.is_recording());
+
+        for _ in 0..2 {
+            step(&mut recorder);
+        }
+        assert!(recorder.stop().is_none());
+        let mut finished = None;
+        while finished.is_none() {
+            finished = step(&mut recorder);
+        }
+        // from the bar line at 1.0 to the one at 2.0, wrapping around
+        let (_, sample) = finished.unwrap();
+        assert_eq!(sample.data, [1.0, 1.25, 1.5, 1.75]);
+
+        // stopping an armed recording cancels it
+        recorder.arm(settings(Some(1.0), false), Vec::with_capacity(10));
+        assert!(recorder.stop().is_none());
+        assert!(recorder.process(2.0, None).is_none());
+        assert!(!recorder.is_recording());
+    }
+}
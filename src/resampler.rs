@@ -0,0 +1,350 @@
+//! Resampling
+//!
+//! Reads sample data at fractional positions through a windowed sinc
+//! kernel, used by the sampler to pitch its samples and by the WAV loaders
+//! to convert files recorded at other sample rates to the engine's. When
+//! the data is read faster than its own rate the kernel is widened to cut
+//! off below the new Nyquist frequency, so pitching up doesn't alias.
+//! Higher qualities use more of the kernel's zero crossings, trading CPU
+//! for a steeper cutoff. A `StreamResampler` does the same for audio
+//! arriving block by block, converting the engine's output to the host's
+//! sample rate when they differ.
+
+use lazy_static::lazy_static;
+use std::f32::consts::PI;
+
+// table entries per zero crossing of the kernel
+const PHASES: usize = 512;
+
+// the kernel is widened at most this much, the cost grows with it
+const MAX_STRETCH: f32 = 4.0;
+
+// samples a stream buffers per channel without allocating
+const STREAM_CAPACITY: usize = 8192;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ResamplerQuality {
+    /// linear interpolation, cheapest, dull and aliasing
+    Linear,
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ResamplerQuality {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ResamplerQuality::Linear),
+            1 => Some(ResamplerQuality::Low),
+            2 => Some(ResamplerQuality::Medium),
+            3 => Some(ResamplerQuality::High),
+            _ => None,
+        }
+    }
+
+    /// zero crossings of the kernel on either side of its center
+    fn zero_crossings(&self) -> usize {
+        match self {
+            ResamplerQuality::Linear => 0,
+            ResamplerQuality::Low => 4,
+            ResamplerQuality::Medium => 8,
+            ResamplerQuality::High => 16,
+        }
+    }
+
+    fn table(&self) -> &'static [f32] {
+        match self {
+            ResamplerQuality::Linear => &[],
+            ResamplerQuality::Low => &LOW_TABLE,
+            ResamplerQuality::Medium => &MEDIUM_TABLE,
+            ResamplerQuality::High => &HIGH_TABLE,
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOW_TABLE: Vec<f32> = sinc_table(ResamplerQuality::Low.zero_crossings());
+    static ref MEDIUM_TABLE: Vec<f32> = sinc_table(ResamplerQuality::Medium.zero_crossings());
+    static ref HIGH_TABLE: Vec<f32> = sinc_table(ResamplerQuality::High.zero_crossings());
+}
+
+/// The right half of a Blackman windowed sinc spanning `zero_crossings`
+fn sinc_table(zero_crossings: usize) -> Vec<f32> {
+    let length = zero_crossings * PHASES;
+    (0..=length + 1)
+        .map(|i| {
+            let x = i as f32 / PHASES as f32;
+            if i == 0 {
+                return 1.0;
+            }
+            if i >= length {
+                return 0.0;
+            }
+            let sinc = (PI * x).sin() / (PI * x);
+            let w = PI * x / zero_crossings as f32;
+            let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+            sinc * window
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Resampler {
+    quality: ResamplerQuality,
+}
+
+impl Resampler {
+    pub fn new(quality: ResamplerQuality) -> Self {
+        Self { quality }
+    }
+
+    pub fn quality(&self) -> ResamplerQuality {
+        self.quality
+    }
+
+    pub fn set_quality(&mut self, quality: ResamplerQuality) {
+        self.quality = quality;
+    }
+
+    /// The value of `data` at fractional `position`, read at `ratio` input
+    /// samples per output sample; silence outside the data
+    #[inline]
+    pub fn read(&self, data: &[f32], position: f64, ratio: f32) -> f32 {
+        let index = position.floor() as isize;
+        let frac = (position - index as f64) as f32;
+        let at = |i: isize| {
+            if i < 0 {
+                0.0
+            } else {
+                data.get(i as usize).copied().unwrap_or(0.0)
+            }
+        };
+
+        let zero_crossings = self.quality.zero_crossings();
+        if zero_crossings == 0 {
+            let (a, b) = (at(index), at(index + 1));
+            return a + (b - a) * frac;
+        }
+        // a cutoff below 1.0 widens the kernel by its inverse
+        let cutoff = 1.0 / ratio.abs().clamp(1.0, MAX_STRETCH);
+        if frac == 0.0 && cutoff == 1.0 {
+            return at(index);
+        }
+        let table = self.quality.table();
+        let span = (zero_crossings as f32 / cutoff).ceil() as isize;
+        let mut y = 0.0;
+        for offset in (1 - span)..=span {
+            let distance = (offset as f32 - frac).abs() * cutoff;
+            let t = distance * PHASES as f32;
+            let i = t as usize;
+            if i + 1 >= table.len() {
+                continue;
+            }
+            let weight = table[i] + (table[i + 1] - table[i]) * (t - i as f32);
+            y += at(index + offset) * weight;
+        }
+        y * cutoff
+    }
+}
+
+/// Convert `data` recorded at `from_rate` to `to_rate`
+pub fn resample(data: &[f32], from_rate: f32, to_rate: f32, quality: ResamplerQuality) -> Vec<f32> {
+    if from_rate == to_rate || data.is_empty() || to_rate <= 0.0 {
+        return data.to_vec();
+    }
+    let resampler = Resampler::new(quality);
+    let ratio = from_rate / to_rate;
+    let length = (data.len() as f64 / ratio as f64).round() as usize;
+    (0..length)
+        .map(|i| resampler.read(data, i as f64 * ratio as f64, ratio))
+        .collect()
+}
+
+/// Converts audio between two sample rates a block at a time. Each
+/// channel's input is buffered, keeping as much of the past as the kernel
+/// reaches, and read at positions stepping by the ratio of the rates.
+pub struct StreamResampler {
+    resampler: Resampler,
+    // input samples per output sample
+    ratio: f64,
+    channels: Vec<Vec<f32>>,
+    // position of the next output sample in the buffered input
+    position: f64,
+    // input samples the kernel reaches on either side of a position
+    span: usize,
+}
+
+impl StreamResampler {
+    pub fn new(
+        channel_count: usize,
+        from_rate: f32,
+        to_rate: f32,
+        quality: ResamplerQuality,
+    ) -> Self {
+        let ratio = from_rate as f64 / to_rate as f64;
+        let cutoff = 1.0 / (ratio as f32).clamp(1.0, MAX_STRETCH);
+        let span = (quality.zero_crossings().max(1) as f32 / cutoff).ceil() as usize;
+        // starts after a stretch of silence for the kernel to reach back into
+        let channels = (0..channel_count)
+            .map(|_| {
+                let mut channel = Vec::with_capacity(span + STREAM_CAPACITY);
+                channel.resize(span, 0.0);
+                channel
+            })
+            .collect();
+        Self {
+            resampler: Resampler::new(quality),
+            ratio,
+            channels,
+            position: span as f64,
+            span,
+        }
+    }
+
+    /// Input samples the kernel reads past a position, the delay before
+    /// pushed input can be read
+    pub fn lookahead(&self) -> usize {
+        self.span
+    }
+
+    /// Input samples still to be pushed before `frames` can be read
+    pub fn needed(&self, frames: usize) -> usize {
+        if frames == 0 {
+            return 0;
+        }
+        let last = self.position + (frames - 1) as f64 * self.ratio;
+        (last.floor() as usize + self.span + 1).saturating_sub(self.buffered())
+    }
+
+    /// Output samples that can be read from the input pushed so far
+    pub fn available(&self) -> usize {
+        let ahead = (self.buffered() - self.span) as f64 - self.position;
+        (ahead / self.ratio).ceil().max(0.0) as usize
+    }
+
+    /// Append `frames` samples to each channel, silence to the channels
+    /// `input` leaves out
+    pub fn push(&mut self, input: &[&[f32]], frames: usize) {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            match input.get(index) {
+                Some(samples) => channel.extend_from_slice(&samples[..frames]),
+                None => channel.resize(channel.len() + frames, 0.0),
+            }
+        }
+    }
+
+    /// Read `frames` samples into each channel of `output`, silence where
+    /// the input runs out. The channels `output` leaves out are skipped.
+    pub fn read(&mut self, output: &mut [&mut [f32]], frames: usize) {
+        let ratio = self.ratio;
+        for (channel, output) in self.channels.iter().zip(output.iter_mut()) {
+            for (i, y) in output[..frames].iter_mut().enumerate() {
+                let position = self.position + i as f64 * ratio;
+                *y = self.resampler.read(channel, position, ratio as f32);
+            }
+        }
+        self.position += frames as f64 * ratio;
+
+        // drop what the kernel won't reach back to anymore
+        let consumed = (self.position.floor() as usize).saturating_sub(self.span);
+        for channel in self.channels.iter_mut() {
+            channel.drain(..consumed);
+        }
+        self.position -= consumed as f64;
+    }
+
+    fn buffered(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    fn sine(freq: f32, sample_rate: f32, length: usize) -> Vec<f32> {
+        (0..length)
+            .map(|i| (TAU * freq * i as f32 / sample_rate).sin())
+            .collect()
+    }
+
+    #[test]
+    fn whole_positions_read_the_data() {
+        let data = sine(1000.0, 48000.0, 64);
+        for quality in (0..4).filter_map(ResamplerQuality::from_u8) {
+            let resampler = Resampler::new(quality);
+            for i in [0, 10, 63] {
+                assert!((resampler.read(&data, i as f64, 1.0) - data[i]).abs() < 1e-4);
+            }
+        }
+        assert_eq!(ResamplerQuality::from_u8(4), None);
+    }
+
+    #[test]
+    fn sinc_interpolates_closer_than_linear() {
+        let sample_rate = 48000.0;
+        let data = sine(9000.0, sample_rate, 256);
+        let error = |quality| {
+            let resampler = Resampler::new(quality);
+            (64..192)
+                .map(|i| {
+                    let position = i as f32 + 0.5;
+                    let expected = (TAU * 9000.0 * position / sample_rate).sin();
+                    (resampler.read(&data, position as f64, 1.0) - expected).abs()
+                })
+                .fold(0.0, f32::max)
+        };
+        let linear = error(ResamplerQuality::Linear);
+        let high = error(ResamplerQuality::High);
+        assert!(linear > 0.1);
+        assert!(high < 0.01, "{high}");
+    }
+
+    #[test]
+    fn reading_fast_filters_what_would_alias() {
+        // 20 kHz read an octave up would fold back to 8 kHz
+        let sample_rate = 48000.0;
+        let data = sine(20000.0, sample_rate, 1024);
+        let resampler = Resampler::new(ResamplerQuality::High);
+        let peak = (100..400)
+            .map(|i| resampler.read(&data, i as f64 * 2.0, 2.0).abs())
+            .fold(0.0, f32::max);
+        assert!(peak < 0.05, "{peak}");
+    }
+
+    #[test]
+    fn streams_convert_block_by_block() {
+        let data = sine(1000.0, 48000.0, 9600);
+        let mut stream = StreamResampler::new(1, 48000.0, 44100.0, ResamplerQuality::Medium);
+        let mut pushed = 0;
+        let mut converted = Vec::new();
+        // odd block sizes, pushing only what each one needs
+        for frames in [1, 37, 512, 300, 1000].into_iter().cycle().take(15) {
+            let needed = stream.needed(frames);
+            stream.push(&[&data[pushed..pushed + needed]], needed);
+            pushed += needed;
+            assert_eq!(stream.needed(frames), 0);
+            assert!(stream.available() >= frames);
+            let mut block = vec![0.0; frames];
+            stream.read(&mut [&mut block[..]], frames);
+            converted.extend(block);
+        }
+        let expected = sine(1000.0, 44100.0, converted.len());
+        for i in 100..converted.len() {
+            assert!((converted[i] - expected[i]).abs() < 0.01, "{i}");
+        }
+    }
+
+    #[test]
+    fn converts_sample_rates() {
+        let data = sine(1000.0, 44100.0, 4410);
+        let converted = resample(&data, 44100.0, 48000.0, ResamplerQuality::Medium);
+        assert_eq!(converted.len(), 4800);
+        let expected = sine(1000.0, 48000.0, 4800);
+        for i in 100..4700 {
+            assert!((converted[i] - expected[i]).abs() < 0.01);
+        }
+    }
+}
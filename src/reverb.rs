@@ -1,108 +1,958 @@
 use std::vec;
 
+use crate::bus::Effect;
 use crate::delay::{DelayLine, InterpolationType};
-use crate::filters::{AllPass, SVF};
-use rand::{thread_rng, Rng};
+use crate::filters::{AllPass, FeedbackComb, SVFMode, SvfBank};
+use crate::limiter::EnvelopeFollower;
+use crate::parameters::ParameterInfo;
+use crate::pitch_shifter::PitchShifter;
+use crate::sequencer::NoteDivision;
+use crate::simd::{F32x8, LANES};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+// the delay and all-pass lengths are tuned at this sample rate and scaled
+// to the actual one
+const REFERENCE_SAMPLE_RATE: f32 = 48000.0;
+
+// range of the room size, scaling every path's delay time
+const MIN_SIZE: f32 = 0.25;
+const MAX_SIZE: f32 = 2.0;
+
+// the hall's path sizes are drawn from this seed, so it sounds the same
+// every time
+const PATH_SEED: u64 = 1;
+
+const MAX_PREDELAY_MS: f32 = 500.0;
+
+/// A reverb algorithm, run by `Reverb` with the shared controls applied
+pub trait ReverbAlgorithm: Send {
+    fn process(&mut self, x: f32) -> f32;
+
+    /// Scale of the delay times, 0.25..2.0
+    fn set_size(&mut self, size: f32);
+
+    /// Feedback gain, 0.0..0.99
+    fn set_decay(&mut self, decay: f32);
+
+    /// Cutoff of the lowpass in the feedback paths, in Hz
+    fn set_damping(&mut self, freq: f32);
+
+    /// Hold the tail at unity feedback, with no damping
+    fn set_freeze(&mut self, freeze: bool);
+}
+
+/// One delay of the network; the feedback, inversion and damping of all
+/// paths are processed together in `FdnReverb`
 struct ReverbPath {
     delay_line: DelayLine,
-    svf: SVF,
-    delay_time: i32,
+    // delay time at size 1.0
+    base_time: f32,
+    delay_time: f32,
     is_inverted: bool,
-    feedback: f32,
 }
 
 impl ReverbPath {
-    fn new(sample_rate: f32) -> Self {
-        let mut rng = thread_rng();
-        let delay_time = rng.gen_range(10..10000);
+    fn new(sample_rate: f32, rng: &mut StdRng) -> Self {
+        let scale = sample_rate / REFERENCE_SAMPLE_RATE;
+        let delay_time = (rng.gen_range(10..10000) as f32 * scale).max(1.0);
         let is_inverted = rng.gen_bool(1.0 / 3.0);
 
         Self {
-            delay_line: DelayLine::new(InterpolationType::None, delay_time),
-            svf: SVF::new(5000.0, 0.707, sample_rate),
-            delay_time: delay_time as i32,
+            // room for the largest size
+            delay_line: DelayLine::new(InterpolationType::None, (delay_time * MAX_SIZE) as usize),
+            base_time: delay_time,
+            delay_time,
             is_inverted,
-            feedback: 0.9,
         }
     }
 
-    #[inline]
-    fn process(&mut self, x: f32) -> f32 {
-        let mut read_pos = self.delay_line.index as f32 - self.delay_time as f32;
-        while read_pos < 0.0 {
-            read_pos += self.delay_line.buffer.len() as f32
-        }
-
-        let mut y = self.delay_line.read(Some(read_pos as usize));
-
-        y = x + (y * self.feedback);
-
-        // randomly invert the signal
-        if self.is_inverted {
-            y = -y
-        };
+    fn set_size(&mut self, size: f32) {
+        self.delay_time = (self.base_time * size).max(1.0);
+    }
 
-        // low pass filter
-        y = self.svf.process(y, 0.0);
+    #[inline]
+    fn read(&self) -> f32 {
+        self.delay_line.read_delayed(self.delay_time)
+    }
 
-        // write the signal back to the delay line
+    #[inline]
+    fn write(&mut self, y: f32) {
         self.delay_line.write_and_increment(y);
-
-        y
     }
 }
 
 const ALLPASS_COUNT: usize = 8;
 const DELAY_COUNT: usize = 32;
 const ALLPASS_LENGTHS: [usize; ALLPASS_COUNT] = [861, 732, 642, 562, 410, 352, 285, 199];
+// the paths are processed in groups of SIMD lanes
+const PATH_GROUPS: usize = DELAY_COUNT / LANES;
 
-pub struct Reverb {
+/// Feedback delay network of randomly sized paths, mixed through a
+/// Householder matrix. Long and dense, suited to halls.
+pub struct FdnReverb {
     allpasses: vec::Vec<AllPass>,
     paths: vec::Vec<ReverbPath>,
+    // -1.0 for inverted paths
+    signs: [F32x8; PATH_GROUPS],
+    filters: SvfBank<PATH_GROUPS>,
+    feedback: f32,
+    frozen: bool,
 }
 
-impl Reverb {
+impl FdnReverb {
     pub fn new(sample_rate: f32) -> Self {
+        let scale = sample_rate / REFERENCE_SAMPLE_RATE;
         let allpasses = (0..ALLPASS_COUNT)
-            .map(|i| AllPass::new(ALLPASS_LENGTHS[i]))
+            .map(|i| AllPass::new((ALLPASS_LENGTHS[i] as f32 * scale) as usize))
             .collect();
-        let paths = (0..DELAY_COUNT)
-            .map(|_| ReverbPath::new(sample_rate))
+        let mut rng = StdRng::seed_from_u64(PATH_SEED);
+        let paths: vec::Vec<ReverbPath> = (0..DELAY_COUNT)
+            .map(|_| ReverbPath::new(sample_rate, &mut rng))
             .collect();
-        Self { allpasses, paths }
+        let mut signs = [F32x8::splat(1.0); PATH_GROUPS];
+        for (i, path) in paths.iter().enumerate() {
+            if path.is_inverted {
+                signs[i / LANES].0[i % LANES] = -1.0;
+            }
+        }
+        Self {
+            allpasses,
+            paths,
+            signs,
+            filters: SvfBank::new(SVFMode::Lowpass, 5000.0, 0.707, sample_rate),
+            feedback: 0.9,
+            frozen: false,
+        }
+    }
+
+    // Householder mixing matrix
+    #[inline]
+    fn mix(arr: &mut [F32x8; PATH_GROUPS]) {
+        let sum = arr.iter().fold(F32x8::ZERO, |acc, &x| acc + x).sum();
+        let sum = F32x8::splat(sum * -2.0 / DELAY_COUNT as f32);
+
+        for x in arr.iter_mut() {
+            *x += sum;
+        }
     }
+}
+
+impl ReverbAlgorithm for FdnReverb {
     #[inline]
-    pub fn process(&mut self, x: f32) -> f32 {
+    fn process(&mut self, x: f32) -> f32 {
         let x = self
             .allpasses
             .iter_mut()
             .fold(x, |acc, allpass| allpass.process(acc))
             / 8.0;
 
-        let mut xs = [0.0; DELAY_COUNT];
+        let mut xs = [F32x8::ZERO; PATH_GROUPS];
+        for (i, path) in self.paths.iter().enumerate() {
+            xs[i / LANES].0[i % LANES] = path.read();
+        }
+
+        let feedback = F32x8::splat(if self.frozen { 1.0 } else { self.feedback });
+        let input = F32x8::splat(x);
+        for (x, sign) in xs.iter_mut().zip(self.signs) {
+            // randomly inverted paths
+            *x = (input + *x * feedback) * sign;
+        }
+
+        // low pass filter
+        if !self.frozen {
+            self.filters.process(&mut xs);
+        }
+
+        // write the signal back to the delay lines
         for (i, path) in self.paths.iter_mut().enumerate() {
-            xs[i] += path.process(x);
+            path.write(xs[i / LANES].0[i % LANES]);
         }
 
         Self::mix(&mut xs);
 
-        xs.iter().fold(0.0, |acc, &x| acc + x) / DELAY_COUNT as f32
+        xs.iter().fold(F32x8::ZERO, |acc, &x| acc + x).sum() / DELAY_COUNT as f32
+    }
+
+    fn set_size(&mut self, size: f32) {
+        for path in self.paths.iter_mut() {
+            path.set_size(size);
+        }
+    }
+
+    fn set_decay(&mut self, decay: f32) {
+        self.feedback = decay;
+    }
+
+    fn set_damping(&mut self, freq: f32) {
+        self.filters.update_freq(freq);
+    }
+
+    fn set_freeze(&mut self, freeze: bool) {
+        self.frozen = freeze;
+    }
+}
+
+/// Schroeder all-pass with a variable, optionally modulated length
+struct Diffuser {
+    delay_line: DelayLine,
+    base_length: f32,
+    length: f32,
+    gain: f32,
+}
+
+impl Diffuser {
+    /// `length` in samples at size 1.0, room is made for the largest size
+    /// plus `excursion` samples of modulation
+    fn new(length: f32, gain: f32, excursion: f32) -> Self {
+        Self {
+            delay_line: DelayLine::new(
+                InterpolationType::Linear,
+                (length * MAX_SIZE + excursion) as usize + 2,
+            ),
+            base_length: length,
+            length: length.round(),
+            gain,
+        }
+    }
+
+    fn set_size(&mut self, size: f32) {
+        // whole samples, so frozen loops don't lose level to interpolation
+        self.length = (self.base_length * size).round().max(1.0);
+    }
+
+    /// Read the internal delay line, for output taps
+    #[inline]
+    fn tap(&self, delay: f32) -> f32 {
+        self.delay_line.read_delayed(delay)
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32, modulation: f32) -> f32 {
+        let delayed = self.delay_line.read_delayed(self.length + modulation);
+        let v = x - self.gain * delayed;
+        self.delay_line.write_and_increment(v);
+        delayed + self.gain * v
+    }
+}
+
+/// Plain delay scaled by the reverb size
+struct TankDelay {
+    delay_line: DelayLine,
+    base_length: f32,
+    length: f32,
+}
+
+impl TankDelay {
+    fn new(length: f32) -> Self {
+        Self {
+            delay_line: DelayLine::new(InterpolationType::Linear, (length * MAX_SIZE) as usize + 2),
+            base_length: length,
+            length: length.round(),
+        }
+    }
+
+    fn set_size(&mut self, size: f32) {
+        // whole samples, so frozen loops don't lose level to interpolation
+        self.length = (self.base_length * size).round().max(1.0);
     }
 
-    // Householder mixing matrix
     #[inline]
-    fn mix(arr: &mut [f32; 32]) {
-        let mut sum = 0.0;
-        for i in 0..32 {
-            sum += arr[i];
+    fn tap(&self, delay: f32) -> f32 {
+        self.delay_line.read_delayed(delay)
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.delay_line.read_delayed(self.length);
+        self.delay_line.write_and_increment(x);
+        y
+    }
+}
+
+// Dattorro's plate, with lengths given at its 29761 Hz sample rate
+const PLATE_SAMPLE_RATE: f32 = 29761.0;
+const PLATE_INPUT_DIFFUSERS: [(f32, f32); 4] =
+    [(142.0, 0.75), (107.0, 0.75), (379.0, 0.625), (277.0, 0.625)];
+// per tank half: modulated all-pass, delay, decay all-pass, delay
+const PLATE_TANK: [[f32; 4]; 2] = [
+    [672.0, 4453.0, 1800.0, 3720.0],
+    [908.0, 4217.0, 2656.0, 3163.0],
+];
+// modulation of the tank's first all-pass, in samples at the plate's rate
+const PLATE_EXCURSION: f32 = 16.0;
+const PLATE_MOD_RATE: f32 = 1.0;
+
+/// Dattorro's plate reverb: input diffusion into a figure-eight tank of
+/// two cross-coupled all-pass and delay halves
+pub struct PlateReverb {
+    bandwidth: f32,
+    bandwidth_state: f32,
+    input_diffusers: [Diffuser; 4],
+    tank_diffusers: [[Diffuser; 2]; 2],
+    tank_delays: [[TankDelay; 2]; 2],
+    damping_state: [f32; 2],
+    damping: f32,
+    decay: f32,
+    frozen: bool,
+    // output of each tank half, fed into the other one
+    feedback: [f32; 2],
+    scale: f32,
+    size: f32,
+    phase: f32,
+    sample_rate: f32,
+}
+
+impl PlateReverb {
+    pub fn new(sample_rate: f32) -> Self {
+        let scale = sample_rate / PLATE_SAMPLE_RATE;
+        let excursion = PLATE_EXCURSION * scale;
+        Self {
+            bandwidth: 0.9995,
+            bandwidth_state: 0.0,
+            input_diffusers: PLATE_INPUT_DIFFUSERS
+                .map(|(length, gain)| Diffuser::new(length * scale, gain, 0.0)),
+            tank_diffusers: PLATE_TANK.map(|half| {
+                [
+                    Diffuser::new(half[0] * scale, -0.7, excursion),
+                    Diffuser::new(half[2] * scale, 0.5, 0.0),
+                ]
+            }),
+            tank_delays: PLATE_TANK.map(|half| {
+                [
+                    TankDelay::new(half[1] * scale),
+                    TankDelay::new(half[3] * scale),
+                ]
+            }),
+            damping_state: [0.0; 2],
+            damping: 0.0,
+            decay: 0.5,
+            frozen: false,
+            feedback: [0.0; 2],
+            scale,
+            size: 1.0,
+            phase: 0.0,
+            sample_rate,
         }
+    }
 
-        sum *= -2.0 / 32.0;
+    /// Output tap `delay` (at the plate's rate) into a tank delay
+    #[inline]
+    fn delay_tap(&self, half: usize, index: usize, delay: f32) -> f32 {
+        self.tank_delays[half][index].tap(delay * self.scale * self.size)
+    }
 
-        for i in 0..32 {
-            arr[i] += sum;
+    #[inline]
+    fn diffuser_tap(&self, half: usize, delay: f32) -> f32 {
+        self.tank_diffusers[half][1].tap(delay * self.scale * self.size)
+    }
+}
+
+impl ReverbAlgorithm for PlateReverb {
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        self.bandwidth_state += self.bandwidth * (x - self.bandwidth_state);
+        let diffused = self
+            .input_diffusers
+            .iter_mut()
+            .fold(self.bandwidth_state, |acc, d| d.process(acc, 0.0));
+
+        // no modulation while frozen, for the same reason
+        let excursion = if self.frozen {
+            0.0
+        } else {
+            PLATE_EXCURSION * self.scale
+        };
+        let lfo = (std::f32::consts::TAU * self.phase).sin() * excursion;
+        self.phase += PLATE_MOD_RATE / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+
+        let (decay, damping) = if self.frozen {
+            (1.0, 0.0)
+        } else {
+            (self.decay, self.damping)
+        };
+        let inputs = [diffused + self.feedback[1], diffused + self.feedback[0]];
+        for (half, input) in inputs.into_iter().enumerate() {
+            let modulation = if half == 0 { lfo } else { -lfo };
+            let y = self.tank_diffusers[half][0].process(input, modulation);
+            let y = self.tank_delays[half][0].process(y);
+            self.damping_state[half] = y + (self.damping_state[half] - y) * damping;
+            let y = self.damping_state[half] * decay;
+            let y = self.tank_diffusers[half][1].process(y, 0.0);
+            self.feedback[half] = self.tank_delays[half][1].process(y) * decay;
+        }
+
+        // left output taps of the original design
+        let y = self.delay_tap(1, 0, 266.0) + self.delay_tap(1, 0, 2974.0)
+            - self.diffuser_tap(1, 1913.0)
+            + self.delay_tap(1, 1, 1996.0)
+            - self.delay_tap(0, 0, 1990.0)
+            - self.diffuser_tap(0, 187.0)
+            - self.delay_tap(0, 1, 1066.0);
+        y * 0.3
+    }
+
+    fn set_size(&mut self, size: f32) {
+        self.size = size;
+        for diffuser in self.tank_diffusers.iter_mut().flatten() {
+            diffuser.set_size(size);
         }
+        for delay in self.tank_delays.iter_mut().flatten() {
+            delay.set_size(size);
+        }
+    }
+
+    fn set_decay(&mut self, decay: f32) {
+        self.decay = decay;
+    }
+
+    fn set_damping(&mut self, freq: f32) {
+        self.damping = damping_coefficient(freq, self.sample_rate);
+    }
+
+    fn set_freeze(&mut self, freeze: bool) {
+        self.frozen = freeze;
+    }
+}
+
+// early reflection times (ms) and gains at size 1.0
+const ROOM_REFLECTIONS: [(f32, f32); 8] = [
+    (4.3, 0.84),
+    (7.1, 0.71),
+    (11.7, 0.62),
+    (15.9, 0.55),
+    (21.3, 0.46),
+    (26.1, 0.38),
+    (33.7, 0.31),
+    (41.9, 0.24),
+];
+// lengths (ms) of the combs building the short diffuse tail
+const ROOM_COMBS: [f32; 4] = [29.7, 37.1, 41.1, 43.7];
+
+/// Small room: a pattern of early reflections followed by a short tail of
+/// damped combs
+pub struct RoomReverb {
+    reflections: DelayLine,
+    combs: [FeedbackComb; 4],
+    diffuser: Diffuser,
+    size: f32,
+    decay: f32,
+    damping: f32,
+    frozen: bool,
+    sample_rate: f32,
+}
+
+impl RoomReverb {
+    /// Set the combs' feedback and damping, held lossless while frozen
+    fn update_combs(&mut self) {
+        // rooms die away faster than the other algorithms
+        let (feedback, damping) = if self.frozen {
+            (1.0, 0.0)
+        } else {
+            (self.decay * 0.8, self.damping)
+        };
+        for comb in self.combs.iter_mut() {
+            comb.feedback = feedback;
+            comb.damping = damping;
+        }
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        let ms = sample_rate * 0.001;
+        let longest = ROOM_REFLECTIONS[ROOM_REFLECTIONS.len() - 1].0 * ms * MAX_SIZE;
+        Self {
+            reflections: DelayLine::new(InterpolationType::Linear, longest as usize + 2),
+            combs: ROOM_COMBS.map(|length| {
+                FeedbackComb::new(
+                    (length * ms * MAX_SIZE) as usize + 1,
+                    (length * ms).round(),
+                    0.0,
+                )
+            }),
+            diffuser: Diffuser::new(5.0 * ms, 0.6, 0.0),
+            size: 1.0,
+            decay: 0.0,
+            damping: 0.0,
+            frozen: false,
+            sample_rate,
+        }
+    }
+}
+
+impl ReverbAlgorithm for RoomReverb {
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        self.reflections.write_and_increment(x);
+        let ms = self.sample_rate * 0.001 * self.size;
+        let early = ROOM_REFLECTIONS.iter().fold(0.0, |acc, &(time, gain)| {
+            acc + self.reflections.read_delayed(time * ms) * gain
+        });
+
+        let tail = self
+            .combs
+            .iter_mut()
+            .fold(0.0, |acc, comb| acc + comb.process(early, 0.0));
+        let tail = self.diffuser.process(tail * 0.25, 0.0);
+
+        (early + tail) * 0.25
+    }
+
+    fn set_size(&mut self, size: f32) {
+        self.size = size;
+        let ms = self.sample_rate * 0.001;
+        for (comb, length) in self.combs.iter_mut().zip(ROOM_COMBS) {
+            comb.delay = (length * ms * size).round();
+        }
+        self.diffuser.set_size(size);
+    }
+
+    fn set_decay(&mut self, decay: f32) {
+        self.decay = decay;
+        self.update_combs();
+    }
+
+    fn set_damping(&mut self, freq: f32) {
+        self.damping = damping_coefficient(freq, self.sample_rate);
+        self.update_combs();
+    }
+
+    fn set_freeze(&mut self, freeze: bool) {
+        self.frozen = freeze;
+        self.update_combs();
+    }
+}
+
+// share of the tail fed back an octave up
+const SHIMMER_AMOUNT: f32 = 0.5;
+
+/// Plate whose tail is fed back through an octave-up pitch shifter, so
+/// the reverb rises as it decays
+pub struct ShimmerReverb {
+    plate: PlateReverb,
+    shifter: PitchShifter,
+    last: f32,
+}
+
+impl ShimmerReverb {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            plate: PlateReverb::new(sample_rate),
+            shifter: PitchShifter::new(sample_rate),
+            last: 0.0,
+        }
+    }
+}
+
+impl ReverbAlgorithm for ShimmerReverb {
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        // frozen tails are held as they are, without rising further
+        let shimmer = if self.plate.frozen {
+            0.0
+        } else {
+            self.shifter.shift(self.last) * SHIMMER_AMOUNT
+        };
+        self.last = self.plate.process(x + shimmer);
+        self.last
+    }
+
+    fn set_size(&mut self, size: f32) {
+        self.plate.set_size(size);
+    }
+
+    fn set_decay(&mut self, decay: f32) {
+        self.plate.set_decay(decay);
+    }
+
+    fn set_damping(&mut self, freq: f32) {
+        self.plate.set_damping(freq);
+    }
+
+    fn set_freeze(&mut self, freeze: bool) {
+        self.plate.set_freeze(freeze);
+    }
+}
+
+/// Coefficient of a one-pole lowpass with its corner at `freq`
+fn damping_coefficient(freq: f32, sample_rate: f32) -> f32 {
+    (-std::f32::consts::TAU * freq.min(sample_rate * 0.49) / sample_rate).exp()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReverbType {
+    Hall,
+    Plate,
+    Room,
+    Shimmer,
+}
+
+impl ReverbType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ReverbType::Hall),
+            1 => Some(ReverbType::Plate),
+            2 => Some(ReverbType::Room),
+            3 => Some(ReverbType::Shimmer),
+            _ => None,
+        }
+    }
+}
+
+pub fn create_algorithm(reverb_type: ReverbType, sample_rate: f32) -> Box<dyn ReverbAlgorithm> {
+    match reverb_type {
+        ReverbType::Hall => Box::new(FdnReverb::new(sample_rate)),
+        ReverbType::Plate => Box::new(PlateReverb::new(sample_rate)),
+        ReverbType::Room => Box::new(RoomReverb::new(sample_rate)),
+        ReverbType::Shimmer => Box::new(ShimmerReverb::new(sample_rate)),
+    }
+}
+
+// longest gate or reverse block
+const MAX_GATE_TIME_MS: f32 = 2000.0;
+// input level that opens the gate
+const GATE_THRESHOLD: f32 = 0.05;
+// fade of the gate and of the reversed block edges, avoiding clicks
+const GATE_FADE_MS: f32 = 5.0;
+
+/// How the reverb tail is shaped after the algorithm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReverbMode {
+    Normal,
+    /// the tail is cut off a fixed time after each hit
+    Gated,
+    /// the tail is played back in reversed blocks, swelling into each hit
+    Reverse,
+}
+
+impl ReverbMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ReverbMode::Normal),
+            1 => Some(ReverbMode::Gated),
+            2 => Some(ReverbMode::Reverse),
+            _ => None,
+        }
+    }
+}
+
+/// Records blocks of the signal and plays each one back reversed while
+/// the next is recorded, one block late
+struct BlockReverser {
+    blocks: [Vec<f32>; 2],
+    length: usize,
+    pos: usize,
+    fade: usize,
+}
+
+impl BlockReverser {
+    fn new(max_length: usize, fade: usize) -> Self {
+        Self {
+            blocks: [vec![0.0; max_length], vec![0.0; max_length]],
+            length: max_length,
+            pos: 0,
+            fade: fade.max(1),
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32, length: usize) -> f32 {
+        self.blocks[0][self.pos] = x;
+        let y = self.blocks[1][self.length - 1 - self.pos];
+        let edge = self.pos.min(self.length - 1 - self.pos);
+        let gain = (edge as f32 / self.fade as f32).min(1.0);
+
+        self.pos += 1;
+        if self.pos >= self.length {
+            // new lengths take effect at block boundaries
+            self.blocks.swap(0, 1);
+            self.length = length.clamp(1, self.blocks[0].len());
+            self.pos = 0;
+        }
+        y * gain
+    }
+}
+
+/// Reverb effect: pre-delay and dry/wet mix around one of the algorithms,
+/// which can be switched while running
+pub struct Reverb {
+    algorithm: Box<dyn ReverbAlgorithm>,
+    reverb_type: ReverbType,
+    predelay_line: DelayLine,
+    size: f32,
+    decay: f32,
+    damping: f32,
+    predelay: f32,
+    mix: f32,
+    frozen: bool,
+    mode: ReverbMode,
+    // gate hold or reverse block length in ms, unless synced
+    gate_time: f32,
+    sync: Option<NoteDivision>,
+    samples_per_beat: f32,
+    gate_follower: EnvelopeFollower,
+    gate_hold: usize,
+    gate_gain: f32,
+    gate_slew: f32,
+    reverser: BlockReverser,
+    sample_rate: f32,
+}
+
+impl Reverb {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Size", MIN_SIZE, MAX_SIZE, 1.0, ""),
+            ParameterInfo::linear(1, "Decay", 0.0, 0.99, 0.9, ""),
+            ParameterInfo::logarithmic(2, "Damping", 200.0, 20000.0, 5000.0, "Hz"),
+            ParameterInfo::linear(3, "Pre-delay", 0.0, MAX_PREDELAY_MS, 0.0, "ms"),
+            ParameterInfo::linear(4, "Mix", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::stepped(5, "Algorithm", 0.0, 3.0, 0.0),
+            ParameterInfo::stepped(6, "Mode", 0.0, 2.0, 0.0),
+            ParameterInfo::logarithmic(
+                7,
+                "Gate time",
+                GATE_FADE_MS * 2.0,
+                MAX_GATE_TIME_MS,
+                250.0,
+                "ms",
+            ),
+            ParameterInfo::stepped(8, "Gate sync", 0.0, NoteDivision::COUNT as f32, 0.0),
+            ParameterInfo::stepped(9, "Freeze", 0.0, 1.0, 0.0),
+        ]
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        let predelay_length = (MAX_PREDELAY_MS * 0.001 * sample_rate) as usize + 1;
+        let mut reverb = Self {
+            algorithm: create_algorithm(ReverbType::Hall, sample_rate),
+            reverb_type: ReverbType::Hall,
+            predelay_line: DelayLine::new(InterpolationType::Linear, predelay_length),
+            size: 1.0,
+            decay: 0.9,
+            damping: 5000.0,
+            predelay: 0.0,
+            mix: 1.0,
+            frozen: false,
+            mode: ReverbMode::Normal,
+            gate_time: 250.0,
+            sync: None,
+            samples_per_beat: 0.0,
+            gate_follower: EnvelopeFollower::new(1.0, 50.0, sample_rate),
+            gate_hold: 0,
+            gate_gain: 0.0,
+            gate_slew: 1.0 / (GATE_FADE_MS * 0.001 * sample_rate),
+            reverser: BlockReverser::new(
+                (MAX_GATE_TIME_MS * 0.001 * sample_rate) as usize,
+                (GATE_FADE_MS * 0.001 * sample_rate) as usize,
+            ),
+            sample_rate,
+        };
+        reverb.apply_parameters();
+        reverb
+    }
+
+    /// Replace the algorithm, keeping the size, decay and damping
+    pub fn set_type(&mut self, reverb_type: ReverbType) {
+        if reverb_type == self.reverb_type {
+            return;
+        }
+        self.reverb_type = reverb_type;
+        self.algorithm = create_algorithm(reverb_type, self.sample_rate);
+        self.apply_parameters();
+    }
+
+    pub fn reverb_type(&self) -> ReverbType {
+        self.reverb_type
+    }
+
+    fn apply_parameters(&mut self) {
+        self.algorithm.set_size(self.size);
+        self.algorithm.set_decay(self.decay);
+        self.algorithm.set_damping(self.damping);
+        self.algorithm.set_freeze(self.frozen);
+    }
+
+    /// Room size, scaling the delay times, 0.25..2.0
+    pub fn set_size(&mut self, size: f32) {
+        self.size = size.clamp(MIN_SIZE, MAX_SIZE);
+        self.algorithm.set_size(self.size);
+    }
+
+    /// Feedback of the algorithm, 0.0..0.99
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.clamp(0.0, 0.99);
+        self.algorithm.set_decay(self.decay);
+    }
+
+    /// Cutoff of the lowpass filters in the feedback paths, in Hz
+    pub fn set_damping(&mut self, freq: f32) {
+        self.damping = freq.clamp(200.0, 20000.0);
+        self.algorithm.set_damping(self.damping);
+    }
+
+    /// Delay before the reverb starts, in milliseconds
+    pub fn set_predelay(&mut self, predelay: f32) {
+        self.predelay = predelay.clamp(0.0, MAX_PREDELAY_MS);
+    }
+
+    /// Dry/wet balance, 0.0..1.0
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    /// Hold the current tail indefinitely and stop taking in new input
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.frozen = freeze;
+        self.algorithm.set_freeze(freeze);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn set_mode(&mut self, mode: ReverbMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> ReverbMode {
+        self.mode
+    }
+
+    /// Gate hold or reverse block length in ms; ignored while synced
+    pub fn set_gate_time(&mut self, time: f32) {
+        if self.sync.is_none() {
+            self.gate_time = time.clamp(GATE_FADE_MS * 2.0, MAX_GATE_TIME_MS);
+        }
+    }
+
+    /// Sync the gate time to a note value, or run free with `None`
+    pub fn set_sync(&mut self, sync: Option<NoteDivision>) {
+        self.sync = sync;
+        self.update_synced_time();
+    }
+
+    pub fn set_tempo(&mut self, samples_per_beat: f32) {
+        self.samples_per_beat = samples_per_beat;
+        self.update_synced_time();
+    }
+
+    fn update_synced_time(&mut self) {
+        if let Some(division) = self.sync {
+            if self.samples_per_beat > 0.0 {
+                let ms = division.beats() * self.samples_per_beat / self.sample_rate * 1000.0;
+                self.gate_time = ms.clamp(GATE_FADE_MS * 2.0, MAX_GATE_TIME_MS);
+            }
+        }
+    }
+
+    #[inline]
+    fn gate_samples(&self) -> usize {
+        (self.gate_time * 0.001 * self.sample_rate) as usize
+    }
+
+    /// Open the gate on each hit and close it once the hold time has passed
+    #[inline]
+    fn gate(&mut self, input: f32) -> f32 {
+        let was_open = self.gate_follower.env > GATE_THRESHOLD;
+        self.gate_follower.process(input);
+        if !was_open && self.gate_follower.env > GATE_THRESHOLD {
+            self.gate_hold = self.gate_samples();
+        }
+        let target = if self.gate_hold > 0 {
+            self.gate_hold -= 1;
+            1.0
+        } else {
+            0.0
+        };
+        self.gate_gain += (target - self.gate_gain).clamp(-self.gate_slew, self.gate_slew);
+        self.gate_gain
+    }
+
+    #[inline]
+    pub fn process(&mut self, input: f32) -> f32 {
+        let x = if self.predelay > 0.0 {
+            self.predelay_line
+                .read_delayed(self.predelay * 0.001 * self.sample_rate)
+        } else {
+            input
+        };
+        self.predelay_line.write_and_increment(input);
+        let x = if self.frozen { 0.0 } else { x };
+
+        let mut wet = self.algorithm.process(x);
+        match self.mode {
+            ReverbMode::Normal => (),
+            ReverbMode::Gated => wet *= self.gate(input),
+            ReverbMode::Reverse => wet = self.reverser.process(wet, self.gate_samples()),
+        }
+        input * (1.0 - self.mix) + wet * self.mix
+    }
+}
+
+/// 0: size, 1: decay, 2: damping (Hz), 3: pre-delay (ms), 4: mix,
+/// 5: algorithm (0: hall, 1: plate, 2: room, 3: shimmer),
+/// 6: mode (0: normal, 1: gated, 2: reverse), 7: gate time (ms),
+/// 8: gate sync (0: off, otherwise note division index + 1), 9: freeze
+impl Effect for Reverb {
+    fn process(&mut self, x: f32) -> f32 {
+        Reverb::process(self, x)
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_size(value),
+            1 => self.set_decay(value),
+            2 => self.set_damping(value),
+            3 => self.set_predelay(value),
+            4 => self.set_mix(value),
+            5 => {
+                if let Some(reverb_type) = ReverbType::from_u8(value as u8) {
+                    self.set_type(reverb_type);
+                }
+            }
+            6 => {
+                if let Some(mode) = ReverbMode::from_u8(value as u8) {
+                    self.set_mode(mode);
+                }
+            }
+            7 => self.set_gate_time(value),
+            8 => {
+                let sync = (value as u8)
+                    .checked_sub(1)
+                    .and_then(NoteDivision::from_index);
+                self.set_sync(sync);
+            }
+            9 => self.set_freeze(value > 0.5),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.size,
+            1 => self.decay,
+            2 => self.damping,
+            3 => self.predelay,
+            4 => self.mix,
+            5 => self.reverb_type as u8 as f32,
+            6 => self.mode as u8 as f32,
+            7 => self.gate_time,
+            8 => self
+                .sync
+                .map_or(0.0, |division| division.index() as f32 + 1.0),
+            9 => self.frozen as u8 as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn set_tempo(&mut self, samples_per_beat: f32) {
+        Reverb::set_tempo(self, samples_per_beat);
+    }
+
+    fn set_freeze(&mut self, freeze: bool) {
+        Reverb::set_freeze(self, freeze);
     }
 }
 
@@ -121,4 +971,148 @@ mod tests {
             assert!(y >= -1.0 && y <= 1.0);
         }
     }
+
+    fn tail_energy(reverb: &mut Reverb) -> f32 {
+        let ys: Vec<f32> = (0..48000)
+            .map(|i| reverb.process(if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        ys[24000..].iter().map(|y| y * y).sum()
+    }
+
+    #[test]
+    fn decay_lengthens_tail() {
+        let mut short = Reverb::new(48000.0);
+        short.set_decay(0.5);
+        let mut long = Reverb::new(48000.0);
+        long.set_decay(0.98);
+        assert!(tail_energy(&mut long) > tail_energy(&mut short) * 10.0);
+    }
+
+    #[test]
+    fn predelay_holds_back_the_reverb() {
+        let mut reverb = Reverb::new(48000.0);
+        reverb.set_predelay(100.0);
+        for i in 0..4800 {
+            assert_eq!(reverb.process(if i == 0 { 1.0 } else { 0.0 }), 0.0);
+        }
+    }
+
+    #[test]
+    fn dry_mix_passes_input() {
+        let mut reverb = Reverb::new(48000.0);
+        reverb.set_mix(0.0);
+        for i in 0..1000 {
+            let x = (i as f32 * 0.01).sin();
+            assert_eq!(reverb.process(x), x);
+        }
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut reverb = Reverb::new(48000.0);
+        for (parameter, value) in [(0, 1.5), (1, 0.7), (2, 3000.0), (3, 40.0), (4, 0.3)] {
+            reverb.set_parameter(parameter, value);
+            assert_eq!(reverb.get_parameter(parameter), value);
+        }
+        reverb.set_parameter(0, 10.0);
+        assert_eq!(reverb.get_parameter(0), MAX_SIZE);
+    }
+
+    const TYPES: [ReverbType; 4] = [
+        ReverbType::Hall,
+        ReverbType::Plate,
+        ReverbType::Room,
+        ReverbType::Shimmer,
+    ];
+
+    #[test]
+    fn algorithms_ring_and_stay_bounded() {
+        for reverb_type in TYPES {
+            let mut reverb = Reverb::new(48000.0);
+            reverb.set_type(reverb_type);
+            let ys: Vec<f32> = (0..96000)
+                .map(|i| reverb.process(if i == 0 { 1.0 } else { 0.0 }))
+                .collect();
+            assert!(ys.iter().all(|y| y.is_finite() && y.abs() <= 1.0));
+            let energy: f32 = ys[..4800].iter().map(|y| y * y).sum();
+            assert!(energy > 1e-6, "{:?} is silent", reverb_type);
+        }
+    }
+
+    #[test]
+    fn switching_keeps_parameters() {
+        let mut reverb = Reverb::new(48000.0);
+        reverb.set_parameter(0, 0.5);
+        reverb.set_parameter(5, 2.0);
+        assert_eq!(reverb.reverb_type(), ReverbType::Room);
+        assert_eq!(reverb.get_parameter(0), 0.5);
+        assert_eq!(reverb.get_parameter(5), 2.0);
+        // unknown algorithms are ignored
+        reverb.set_parameter(5, 9.0);
+        assert_eq!(reverb.reverb_type(), ReverbType::Room);
+    }
+
+    #[test]
+    fn freeze_holds_the_tail() {
+        for reverb_type in TYPES {
+            let mut reverb = Reverb::new(48000.0);
+            reverb.set_type(reverb_type);
+            reverb.set_decay(0.5);
+            let noise = |i: u32| ((i.wrapping_mul(2654435761) >> 16) as f32 / 65536.0) - 0.5;
+            for i in 0..4800 {
+                reverb.process(noise(i));
+            }
+            reverb.set_parameter(9, 1.0);
+            let energy = |reverb: &mut Reverb| {
+                (0..48000)
+                    .map(|i| reverb.process(noise(i)).powi(2))
+                    .sum::<f32>()
+            };
+            let first = energy(&mut reverb);
+            let second = energy(&mut reverb);
+            assert!(first > 0.0, "{:?} is silent", reverb_type);
+            assert!(
+                second > first * 0.5 && second < first * 2.0,
+                "{:?} does not hold",
+                reverb_type
+            );
+        }
+    }
+
+    #[test]
+    fn gate_cuts_the_tail() {
+        let sample_rate = 48000.0;
+        let mut reverb = Reverb::new(sample_rate);
+        reverb.set_mode(ReverbMode::Gated);
+        reverb.set_gate_time(100.0);
+        let ys: Vec<f32> = (0..24000)
+            .map(|i| reverb.process(if i < 48 { 1.0 } else { 0.0 }))
+            .collect();
+        let energy: f32 = ys[..4800].iter().map(|y| y * y).sum();
+        assert!(energy > 1e-6);
+        // closed after the hold time and the fade
+        assert!(ys[6000..].iter().all(|&y| y == 0.0));
+    }
+
+    #[test]
+    fn gate_time_follows_tempo() {
+        let mut reverb = Reverb::new(48000.0);
+        // an eighth note at 120 bpm
+        reverb.set_parameter(8, NoteDivision::from_index(9).unwrap().index() as f32 + 1.0);
+        reverb.set_tempo(24000.0);
+        assert_eq!(reverb.get_parameter(7), 250.0);
+        // the free running time is ignored while synced
+        reverb.set_parameter(7, 100.0);
+        assert_eq!(reverb.get_parameter(7), 250.0);
+    }
+
+    #[test]
+    fn reverse_plays_blocks_backwards() {
+        let mut reverser = BlockReverser::new(100, 1);
+        let ys: Vec<f32> = (0..200).map(|i| reverser.process(i as f32, 100)).collect();
+        assert!(ys[..100].iter().all(|&y| y == 0.0));
+        assert_eq!(ys[101], 98.0);
+        assert_eq!(ys[150], 49.0);
+        assert_eq!(ys[198], 1.0);
+    }
 }
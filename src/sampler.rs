@@ -0,0 +1,660 @@
+//! Sample playback voice
+//!
+//! Plays PCM samples loaded from WAV files, pitched by resampling relative to
+//! a root pitch, either once or looping between loop points while the note
+//! is held. Loops can be crossfaded and samples played in reverse. In slice
+//! mode the sample is chopped at slice points, spaced equally or at
+//! detected transients, and each pitch from the root up plays one slice at
+//! its recorded pitch. Stretched, the sample follows the tempo without
+//! changing pitch, lasting the set number of beats (see `stretch`). Files
+//! recorded at other rates can be converted to the engine rate on loading
+//! with `Sample::resampled`.
+
+use crate::parameters::ParameterInfo;
+use crate::resampler::{resample, Resampler, ResamplerQuality};
+use crate::stretch::{StretchQuality, Stretcher};
+use crate::synth::SynthVoice;
+use std::f32::consts::FRAC_PI_2;
+use std::path::Path;
+use std::sync::Arc;
+
+/// number of parameters addressable through `set_parameter`
+pub const PARAMETER_COUNT: i8 = 14;
+
+/// most slices a sample is chopped into, one per pitch
+pub const MAX_SLICES: usize = 128;
+
+// transients are found in the level of windows this long
+const TRANSIENT_WINDOW: usize = 256;
+
+// slices are at least this long, in seconds
+const MIN_SLICE_SECONDS: f32 = 0.05;
+
+/// Mono PCM sample data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub data: Vec<f32>,
+    pub sample_rate: f32,
+}
+
+impl Sample {
+    pub fn new(data: Vec<f32>, sample_rate: f32) -> Self {
+        Self { data, sample_rate }
+    }
+
+    /// Load a WAV file, mixing multichannel files down to mono
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, hound::Error> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        let channels = spec.channels.max(1) as usize;
+        let data = samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        Ok(Self::new(data, spec.sample_rate as f32))
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The sample converted to `sample_rate`
+    pub fn resampled(&self, sample_rate: f32, quality: ResamplerQuality) -> Self {
+        let data = resample(&self.data, self.sample_rate, sample_rate, quality);
+        Self::new(data, sample_rate)
+    }
+
+    /// Slice points at the onsets of the sample's hits, normalized to its
+    /// length. A window whose level jumps past the one before it starts a
+    /// slice; higher `sensitivity`, 0.0..1.0, takes smaller jumps. The first
+    /// slice always starts at the beginning.
+    pub fn detect_transients(&self, sensitivity: f32) -> Vec<f32> {
+        let levels: Vec<f32> = self
+            .data
+            .chunks(TRANSIENT_WINDOW)
+            .map(|window| (window.iter().map(|x| x * x).sum::<f32>() / window.len() as f32).sqrt())
+            .collect();
+        let peak = levels.iter().copied().fold(0.0, f32::max);
+        // a jump of 1.5 times the level at full sensitivity, 8 times at none
+        let jump = 1.5 + 6.5 * (1.0 - sensitivity.clamp(0.0, 1.0));
+        let floor = peak * 0.05;
+        let min_gap = (MIN_SLICE_SECONDS * self.sample_rate) as usize / TRANSIENT_WINDOW;
+
+        let mut slices = vec![0.0];
+        let mut last = 0;
+        for (i, pair) in levels.windows(2).enumerate() {
+            let i = i + 1;
+            if pair[1] > floor && pair[1] > pair[0] * jump && i - last > min_gap {
+                slices.push((i * TRANSIENT_WINDOW) as f32 / self.len() as f32);
+                last = i;
+                if slices.len() == MAX_SLICES {
+                    break;
+                }
+            }
+        }
+        slices
+    }
+}
+
+/// Slice points chopping a sample into `count` equal parts
+pub fn equal_slices(count: usize) -> Vec<f32> {
+    let count = count.clamp(1, MAX_SLICES);
+    (0..count).map(|i| i as f32 / count as f32).collect()
+}
+
+/// Slice points clamped to the sample, in order and without duplicates
+pub fn sorted_slices(points: &[f32]) -> Vec<f32> {
+    let mut slices: Vec<f32> = points
+        .iter()
+        .filter(|p| p.is_finite())
+        .map(|p| p.clamp(0.0, 1.0))
+        .collect();
+    slices.sort_by(f32::total_cmp);
+    slices.dedup();
+    slices.truncate(MAX_SLICES);
+    slices
+}
+
+pub struct SamplerVoice {
+    sample: Option<Arc<Sample>>,
+    resampler: Resampler,
+    // playback region and loop points, normalized to the sample length
+    start: f32,
+    end: f32,
+    loop_start: f32,
+    loop_end: f32,
+    is_looping: bool,
+    // share of the loop faded into the material before it
+    crossfade: f32,
+    is_reversed: bool,
+    is_sliced: bool,
+    slices: Arc<[f32]>,
+    // the slice playing in slice mode
+    slice: Option<usize>,
+    stretcher: Stretcher,
+    is_stretching: bool,
+    // length of the whole sample in beats when stretched
+    beats: f32,
+    samples_per_beat: f32,
+    root_pitch: f32,
+    position: f64,
+    increment: f64,
+    velocity: f32,
+    pitch: u8,
+    is_playing: bool,
+    is_released: bool,
+    sample_rate: f32,
+}
+
+impl SamplerVoice {
+    /// Start, end and loop points are positions in the sample, 0.0..1.0
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Start", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(1, "End", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::linear(2, "Loop start", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::linear(3, "Loop end", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::stepped(4, "Loop", 0.0, 1.0, 0.0),
+            ParameterInfo::linear(5, "Root pitch", 0.0, 127.0, 60.0, ""),
+            ParameterInfo::stepped(6, "Quality", 0.0, 3.0, 2.0),
+            ParameterInfo::linear(7, "Crossfade", 0.0, 0.5, 0.0, ""),
+            ParameterInfo::stepped(8, "Reverse", 0.0, 1.0, 0.0),
+            ParameterInfo::stepped(9, "Slice", 0.0, 1.0, 0.0),
+            ParameterInfo::stepped(10, "Stretch", 0.0, 1.0, 0.0),
+            ParameterInfo::linear(11, "Beats", 0.25, 64.0, 4.0, "beats"),
+            ParameterInfo::stepped(12, "Stretch quality", 0.0, 2.0, 1.0),
+            ParameterInfo::stepped(13, "Transients", 0.0, 1.0, 1.0),
+        ]
+    }
+
+    fn frame(&self, value: f32) -> f64 {
+        let len = self.sample.as_ref().map_or(0, |s| s.len());
+        value.clamp(0.0, 1.0) as f64 * len as f64
+    }
+
+    /// Frames playback moves through per sample when stretched, so the
+    /// whole sample lasts `beats`
+    fn stretch_speed(&self, len: usize) -> f64 {
+        len as f64 / (self.beats * self.samples_per_beat) as f64
+    }
+
+    /// First and last frames played, the slice's in slice mode
+    fn region(&self) -> (f64, f64) {
+        let Some(slice) = self.slice.filter(|&slice| slice < self.slices.len()) else {
+            return (self.frame(self.start), self.frame(self.end));
+        };
+        let next = self.slices.get(slice + 1).copied().unwrap_or(self.end);
+        (self.frame(self.slices[slice]), self.frame(next))
+    }
+
+    fn loop_points(&self) -> Option<(f64, f64)> {
+        let loop_start = self.frame(self.loop_start);
+        let loop_end = self.frame(self.loop_end);
+        let is_looping = self.is_looping && !self.is_released && self.slice.is_none();
+        (is_looping && loop_end > loop_start).then_some((loop_start, loop_end))
+    }
+
+    /// Nearing the point the loop jumps from, the offset to the material as
+    /// far past the point it jumps to and how far the fade into it has come,
+    /// so the jump lands on what has already faded in
+    fn loop_fade(&self, loop_start: f64, loop_end: f64, len: f64) -> Option<(f64, f32)> {
+        let length = loop_end - loop_start;
+        let fade = self.crossfade as f64 * length;
+        let (offset, fade, into) = if self.is_reversed {
+            let fade = fade.min(len - loop_end);
+            (length, fade, loop_start + fade - self.position)
+        } else {
+            let fade = fade.min(loop_start);
+            (-length, fade, self.position - (loop_end - fade))
+        };
+        (fade > 0.0 && into > 0.0).then(|| (offset, (into / fade).min(1.0) as f32))
+    }
+}
+
+impl SynthVoice for SamplerVoice {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            sample: None,
+            resampler: Resampler::new(ResamplerQuality::default()),
+            start: 0.0,
+            end: 1.0,
+            loop_start: 0.0,
+            loop_end: 1.0,
+            is_looping: false,
+            crossfade: 0.0,
+            is_reversed: false,
+            is_sliced: false,
+            slices: Arc::new([]),
+            slice: None,
+            stretcher: Stretcher::new(sample_rate),
+            is_stretching: false,
+            beats: 4.0,
+            // 120 bpm until told otherwise
+            samples_per_beat: sample_rate * 0.5,
+            root_pitch: 60.0,
+            position: 0.0,
+            increment: 1.0,
+            velocity: 1.0,
+            pitch: 0,
+            is_playing: false,
+            is_released: false,
+            sample_rate,
+        }
+    }
+
+    fn init(&mut self) {}
+
+    #[inline]
+    fn process(&mut self) -> f32 {
+        if !self.is_playing {
+            return 0.0;
+        }
+        let Some(sample) = self.sample.as_ref() else {
+            return 0.0;
+        };
+        let loop_points = self.loop_points();
+        let (y, step) = if self.is_stretching {
+            // stretched loops are joined by the overlapping grains
+            let speed = self.stretch_speed(sample.len());
+            let ratio = if self.is_reversed {
+                -self.increment
+            } else {
+                self.increment
+            };
+            let y =
+                self.stretcher
+                    .process(&sample.data, &self.resampler, self.position, speed, ratio);
+            (y, speed)
+        } else {
+            let ratio = self.increment as f32;
+            let mut y = self.resampler.read(&sample.data, self.position, ratio);
+            if let Some((loop_start, loop_end)) = loop_points {
+                if let Some((offset, t)) = self.loop_fade(loop_start, loop_end, sample.len() as f64)
+                {
+                    // equal power, for material that doesn't line up
+                    let z = self
+                        .resampler
+                        .read(&sample.data, self.position + offset, ratio);
+                    y = y * (t * FRAC_PI_2).cos() + z * (t * FRAC_PI_2).sin();
+                }
+            }
+            (y, self.increment)
+        };
+        let y = y * self.velocity;
+
+        let (from, to) = self.region();
+        if self.is_reversed {
+            self.position -= step;
+            if let Some((loop_start, loop_end)) = loop_points {
+                if self.position < loop_start {
+                    self.position =
+                        loop_end - (loop_start - self.position) % (loop_end - loop_start);
+                }
+            } else if self.position < from {
+                self.is_playing = false;
+            }
+        } else {
+            self.position += step;
+            if let Some((loop_start, loop_end)) = loop_points {
+                if self.position >= loop_end {
+                    self.position =
+                        loop_start + (self.position - loop_end) % (loop_end - loop_start);
+                }
+            } else if self.position >= to {
+                self.is_playing = false;
+            }
+        }
+        y
+    }
+
+    /// In slice mode the root pitch plays the first slice, each pitch above
+    /// it the next
+    fn play(&mut self, pitch: u8, velocity: u8, _: f32, _: f32) {
+        self.pitch = pitch;
+        self.velocity = velocity as f32 / 127.0;
+        self.slice = None;
+        if self.is_sliced {
+            let slice = pitch as f32 - self.root_pitch.round();
+            if slice < 0.0 || slice as usize >= self.slices.len() {
+                self.is_playing = false;
+                return;
+            }
+            self.slice = Some(slice as usize);
+        }
+        self.set_pitch(pitch as f32);
+        let (from, to) = self.region();
+        self.position = if self.is_reversed { to - 1.0 } else { from };
+        self.stretcher.reset();
+        self.is_playing = self.sample.is_some() && to > from;
+        self.is_released = false;
+    }
+
+    /// Slices play at their recorded pitch
+    fn set_pitch(&mut self, pitch: f32) {
+        let pitch = if self.is_sliced {
+            self.root_pitch
+        } else {
+            pitch
+        };
+        let rate = self
+            .sample
+            .as_ref()
+            .map_or(self.sample_rate, |s| s.sample_rate);
+        let ratio = (2f32).powf((pitch - self.root_pitch) / 12.0);
+        self.increment = (ratio * rate / self.sample_rate) as f64;
+    }
+
+    fn reset(&mut self) {
+        let (from, to) = self.region();
+        self.position = if self.is_reversed { to - 1.0 } else { from };
+        self.stretcher.reset();
+    }
+
+    /// Leave the loop, playing on to the end of the sample
+    fn stop(&mut self) {
+        self.is_released = true;
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.start = value.clamp(0.0, 1.0),
+            1 => self.end = value.clamp(0.0, 1.0),
+            2 => self.loop_start = value.clamp(0.0, 1.0),
+            3 => self.loop_end = value.clamp(0.0, 1.0),
+            4 => self.is_looping = value >= 0.5,
+            5 => self.root_pitch = value,
+            6 => {
+                if let Some(quality) = ResamplerQuality::from_u8(value as u8) {
+                    self.resampler.set_quality(quality);
+                }
+            }
+            7 => self.crossfade = value.clamp(0.0, 0.5),
+            8 => self.is_reversed = value >= 0.5,
+            9 => self.is_sliced = value >= 0.5,
+            10 => self.is_stretching = value >= 0.5,
+            11 => self.beats = value.max(0.25),
+            12 => {
+                if let Some(quality) = StretchQuality::from_u8(value as u8) {
+                    self.stretcher.set_quality(quality);
+                }
+            }
+            13 => self.stretcher.set_preserve_transients(value >= 0.5),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.start,
+            1 => self.end,
+            2 => self.loop_start,
+            3 => self.loop_end,
+            4 => self.is_looping as u8 as f32,
+            5 => self.root_pitch,
+            6 => self.resampler.quality() as u8 as f32,
+            7 => self.crossfade,
+            8 => self.is_reversed as u8 as f32,
+            9 => self.is_sliced as u8 as f32,
+            10 => self.is_stretching as u8 as f32,
+            11 => self.beats,
+            12 => self.stretcher.quality() as u8 as f32,
+            13 => self.stretcher.preserves_transients() as u8 as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        PARAMETER_COUNT
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        matches!(parameter, 4 | 6 | 8 | 9 | 10 | 12 | 13)
+    }
+
+    /// Slice points of an earlier sample are dropped
+    fn set_sample(&mut self, sample: Arc<Sample>) {
+        self.sample = Some(sample);
+        self.slices = Arc::new([]);
+        self.is_playing = false;
+    }
+
+    fn sample(&self) -> Option<Arc<Sample>> {
+        self.sample.clone()
+    }
+
+    fn set_slices(&mut self, slices: Arc<[f32]>) {
+        self.slices = slices;
+    }
+
+    fn set_tempo(&mut self, samples_per_beat: f32) {
+        self.samples_per_beat = samples_per_beat.max(1.0);
+    }
+
+    fn slices(&self) -> &[f32] {
+        &self.slices
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    fn is_active(&self) -> bool {
+        self.is_playing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp_voice(len: usize) -> SamplerVoice {
+        let mut voice = SamplerVoice::new(48000.0);
+        let data = (0..len).map(|i| i as f32 / len as f32).collect();
+        voice.set_sample(Arc::new(Sample::new(data, 48000.0)));
+        voice
+    }
+
+    #[test]
+    fn one_shot_plays_to_end() {
+        let mut voice = ramp_voice(100);
+        voice.play(60, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..200).map(|_| voice.process()).collect();
+        assert_eq!(ys[10], 0.1);
+        assert!(!voice.is_active());
+        assert_eq!(ys[150], 0.0);
+    }
+
+    #[test]
+    fn octave_up_plays_twice_as_fast() {
+        let mut voice = ramp_voice(100);
+        // linear reads land exactly on the ramp
+        voice.set_parameter(6, 0.0);
+        voice.play(72, 127, 0.0, 0.0);
+        voice.process();
+        assert_eq!(voice.process(), 0.02);
+        for _ in 0..50 {
+            voice.process();
+        }
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn loops_until_released() {
+        let mut voice = ramp_voice(100);
+        voice.set_parameter(2, 0.5);
+        voice.set_parameter(4, 1.0);
+        voice.play(60, 127, 0.0, 0.0);
+        for _ in 0..1000 {
+            voice.process();
+        }
+        assert!(voice.is_active());
+        assert!(voice.position >= 50.0 && voice.position < 100.0);
+
+        voice.stop();
+        for _ in 0..100 {
+            voice.process();
+        }
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn reverse_plays_from_the_end() {
+        let mut voice = ramp_voice(100);
+        voice.set_parameter(8, 1.0);
+        voice.play(60, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..100).map(|_| voice.process()).collect();
+        assert_eq!(ys[0], 0.99);
+        assert_eq!(ys[99], 0.0);
+        voice.process();
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn crossfaded_loop_joins_smoothly() {
+        // a ramp jumps back at the loop's end unless it's faded
+        let mut voice = ramp_voice(100);
+        voice.set_parameter(2, 0.5);
+        voice.set_parameter(4, 1.0);
+        voice.set_parameter(7, 0.5);
+        voice.play(60, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..300).map(|_| voice.process()).collect();
+        let largest_step = ys
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(largest_step < 0.1, "{largest_step}");
+
+        voice.set_parameter(7, 0.0);
+        let ys: Vec<f32> = (0..300).map(|_| voice.process()).collect();
+        let largest_step = ys
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(largest_step > 0.4);
+    }
+
+    #[test]
+    fn slices_map_to_pitches() {
+        let mut voice = ramp_voice(100);
+        voice.set_slices(Arc::from(equal_slices(4)));
+        voice.set_parameter(9, 1.0);
+        // the second slice plays its 25 frames at the recorded pitch
+        voice.play(61, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..25).map(|_| voice.process()).collect();
+        assert_eq!(ys[0], 0.25);
+        assert!(!voice.is_active());
+
+        // reversed, the last slice runs from the end of the sample
+        voice.set_parameter(8, 1.0);
+        voice.play(63, 127, 0.0, 0.0);
+        assert_eq!(voice.process(), 0.99);
+
+        // below the root or past the last slice is silent
+        voice.play(59, 127, 0.0, 0.0);
+        assert!(!voice.is_active());
+        voice.play(64, 127, 0.0, 0.0);
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn stretched_sample_follows_the_tempo() {
+        // half a second of sine, stretched over two beats
+        let data = (0..24000).map(|i| (i as f32 * 0.05).sin()).collect();
+        let mut voice = SamplerVoice::new(48000.0);
+        voice.set_sample(Arc::new(Sample::new(data, 48000.0)));
+        voice.set_parameter(10, 1.0);
+        voice.set_parameter(11, 2.0);
+        let length = |voice: &mut SamplerVoice| {
+            voice.play(60, 127, 0.0, 0.0);
+            (0..100000)
+                .take_while(|_| {
+                    voice.process();
+                    voice.is_active()
+                })
+                .count()
+        };
+        assert!(length(&mut voice).abs_diff(48000) <= 1);
+        voice.set_tempo(12000.0);
+        assert!(length(&mut voice).abs_diff(24000) <= 1);
+        assert_eq!(voice.get_parameter(12), 1.0);
+    }
+
+    #[test]
+    fn transients_start_slices() {
+        let sample_rate = 48000.0;
+        let mut data = vec![0.0; 48000];
+        for hit in [0, 12000, 30000] {
+            for (i, x) in data[hit..hit + 4000].iter_mut().enumerate() {
+                *x = (i as f32 * 0.3).sin() * (-(i as f32) / 800.0).exp();
+            }
+        }
+        let sample = Sample::new(data, sample_rate);
+        let slices = sample.detect_transients(0.5);
+        assert_eq!(slices.len(), 3);
+        assert!((slices[1] - 0.25).abs() < 0.01);
+        assert!((slices[2] - 0.625).abs() < 0.01);
+    }
+
+    #[test]
+    fn slice_points_are_sorted_and_clamped() {
+        assert_eq!(equal_slices(0), [0.0]);
+        assert_eq!(
+            sorted_slices(&[0.5, -1.0, f32::NAN, 0.5, 2.0]),
+            [0.0, 0.5, 1.0]
+        );
+    }
+
+    #[test]
+    fn load_wav_file() {
+        let path = std::env::temp_dir().join("cp3_dsp_sampler_test.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..10 {
+            writer.write_sample(i16::MAX / 2).unwrap();
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let sample = Sample::load(&path).unwrap();
+        assert_eq!(sample.len(), 10);
+        assert_eq!(sample.sample_rate, 44100.0);
+        assert!((sample.data[0] - 0.25).abs() < 1e-3);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(Sample::load("does-not-exist.wav").is_err());
+    }
+
+    #[test]
+    fn resampled_sample_plays_at_root_pitch() {
+        let sample =
+            Sample::new(vec![0.5; 441], 44100.0).resampled(48000.0, ResamplerQuality::High);
+        assert_eq!(sample.len(), 480);
+        assert_eq!(sample.sample_rate, 48000.0);
+
+        let mut voice = SamplerVoice::new(48000.0);
+        voice.set_sample(Arc::new(sample));
+        voice.play(60, 127, 0.0, 0.0);
+        assert_eq!(voice.increment, 1.0);
+        assert_eq!(voice.get_parameter(6), 2.0);
+    }
+}
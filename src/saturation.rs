@@ -0,0 +1,306 @@
+//! Waveshaping saturation
+//!
+//! The input is driven into one of several transfer curves. Shaping runs
+//! at a multiple of the sample rate to keep the added harmonics from
+//! aliasing, and a lowpass tone control tames the top end afterwards.
+
+use crate::bus::Effect;
+use crate::filters::{Biquad, BiquadType};
+use crate::parameters::ParameterInfo;
+
+/// maximum oversampling factor
+pub const MAX_OVERSAMPLING: usize = 8;
+
+// Q of the four sections of an 8th order Butterworth lowpass
+const BUTTERWORTH_Q: [f32; 4] = [0.509_795_6, 0.601_344_9, 0.899_976_2, 2.562_915_5];
+
+// the tone control sweeps the lowpass from 200 Hz to 20 kHz
+const TONE_MIN_FREQ: f32 = 200.0;
+const TONE_MAX_FREQ: f32 = 20000.0;
+
+// bias of the asymmetric curve, adds even harmonics
+const ASYMMETRIC_BIAS: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShaperType {
+    SoftClip,
+    Tanh,
+    HardClip,
+    Foldback,
+    Asymmetric,
+}
+
+impl ShaperType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ShaperType::SoftClip),
+            1 => Some(ShaperType::Tanh),
+            2 => Some(ShaperType::HardClip),
+            3 => Some(ShaperType::Foldback),
+            4 => Some(ShaperType::Asymmetric),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn shape(&self, x: f32) -> f32 {
+        match self {
+            // cubic soft clipper, reaching full scale at |x| = 1
+            ShaperType::SoftClip => {
+                if x.abs() >= 1.0 {
+                    x.signum()
+                } else {
+                    1.5 * (x - x * x * x / 3.0)
+                }
+            }
+            ShaperType::Tanh => x.tanh(),
+            ShaperType::HardClip => x.clamp(-1.0, 1.0),
+            // reflect the signal back off the ±1 boundaries
+            ShaperType::Foldback => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+            // biased tanh, scaled so the negative side still reaches -1
+            ShaperType::Asymmetric => {
+                let offset = ASYMMETRIC_BIAS.tanh();
+                ((x + ASYMMETRIC_BIAS).tanh() - offset) / (1.0 + offset)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Saturator {
+    shape: ShaperType,
+    drive: f32,
+    tone: f32,
+    oversampling: usize,
+    upsampler: [Biquad; 4],
+    downsampler: [Biquad; 4],
+    tone_filter: Biquad,
+    // DC blocker state, for the asymmetric curve
+    dc_x: f32,
+    dc_y: f32,
+    sample_rate: f32,
+}
+
+impl Saturator {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::linear(0, "Drive", 0.0, 20.0, 1.0, ""),
+            ParameterInfo::stepped(1, "Shape", 0.0, 4.0, 1.0),
+            ParameterInfo::linear(2, "Tone", 0.0, 1.0, 1.0, ""),
+            ParameterInfo::stepped(3, "Oversampling", 1.0, MAX_OVERSAMPLING as f32, 2.0),
+        ]
+    }
+
+    pub fn new(sample_rate: f32) -> Self {
+        let mut saturator = Self {
+            shape: ShaperType::Tanh,
+            drive: 1.0,
+            tone: 1.0,
+            oversampling: 1,
+            upsampler: Self::anti_aliasing_filter(sample_rate, 1),
+            downsampler: Self::anti_aliasing_filter(sample_rate, 1),
+            tone_filter: Biquad::new(BiquadType::Lowpass, TONE_MAX_FREQ, 0.707, 0.0, sample_rate),
+            dc_x: 0.0,
+            dc_y: 0.0,
+            sample_rate,
+        };
+        saturator.set_oversampling(2);
+        saturator.set_tone(1.0);
+        saturator
+    }
+
+    fn anti_aliasing_filter(sample_rate: f32, factor: usize) -> [Biquad; 4] {
+        let rate = sample_rate * factor as f32;
+        BUTTERWORTH_Q.map(|q| Biquad::new(BiquadType::Lowpass, sample_rate * 0.45, q, 0.0, rate))
+    }
+
+    pub fn set_shape(&mut self, shape: ShaperType) {
+        self.shape = shape;
+    }
+
+    /// Linear gain into the shaper
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(0.0);
+    }
+
+    /// 0.0 is dark, 1.0 fully open
+    pub fn set_tone(&mut self, tone: f32) {
+        self.tone = tone.clamp(0.0, 1.0);
+        let freq = TONE_MIN_FREQ * (TONE_MAX_FREQ / TONE_MIN_FREQ).powf(self.tone);
+        self.tone_filter
+            .update_freq(freq.min(self.sample_rate * 0.45));
+    }
+
+    /// Rounded down to a power of two, from 1 (off) to `MAX_OVERSAMPLING`
+    pub fn set_oversampling(&mut self, factor: usize) {
+        let factor = factor.clamp(1, MAX_OVERSAMPLING);
+        self.oversampling = 1 << factor.ilog2();
+        self.upsampler = Self::anti_aliasing_filter(self.sample_rate, self.oversampling);
+        self.downsampler = self.upsampler;
+    }
+
+    pub fn shape(&self) -> ShaperType {
+        self.shape
+    }
+
+    pub fn drive(&self) -> f32 {
+        self.drive
+    }
+
+    pub fn tone(&self) -> f32 {
+        self.tone
+    }
+
+    pub fn oversampling(&self) -> usize {
+        self.oversampling
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let x = x * self.drive;
+        let mut y = if self.oversampling == 1 {
+            self.shape.shape(x)
+        } else {
+            // zero stuffing, filtered on the way up and down
+            let mut y = 0.0;
+            for i in 0..self.oversampling {
+                let u = if i == 0 {
+                    x * self.oversampling as f32
+                } else {
+                    0.0
+                };
+                let u = self.upsampler.iter_mut().fold(u, |u, f| f.process(u));
+                let s = self.shape.shape(u);
+                y = self.downsampler.iter_mut().fold(s, |s, f| f.process(s));
+            }
+            y
+        };
+
+        if self.shape == ShaperType::Asymmetric {
+            let dc_y = y - self.dc_x + 0.995 * self.dc_y;
+            self.dc_x = y;
+            self.dc_y = dc_y;
+            y = dc_y;
+        }
+
+        self.tone_filter.process(y)
+    }
+}
+
+impl Effect for Saturator {
+    fn process(&mut self, x: f32) -> f32 {
+        Saturator::process(self, x)
+    }
+
+    /// 0: drive, 1: shape, 2: tone, 3: oversampling factor
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        match parameter {
+            0 => self.set_drive(value),
+            1 => {
+                if let Some(shape) = ShaperType::from_u8(value as u8) {
+                    self.set_shape(shape);
+                }
+            }
+            2 => self.set_tone(value),
+            3 => self.set_oversampling(value as usize),
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.drive,
+            1 => self.shape as u8 as f32,
+            2 => self.tone,
+            3 => self.oversampling as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SHAPES: [ShaperType; 5] = [
+        ShaperType::SoftClip,
+        ShaperType::Tanh,
+        ShaperType::HardClip,
+        ShaperType::Foldback,
+        ShaperType::Asymmetric,
+    ];
+
+    #[test]
+    fn curves_are_bounded() {
+        for shape in SHAPES {
+            assert!(shape.shape(0.0).abs() < 1e-6);
+            for i in -100..100 {
+                assert!(shape.shape(i as f32 * 0.1).abs() <= 1.0 + 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn foldback_reflects() {
+        let shape = ShaperType::Foldback;
+        assert!((shape.shape(0.5) - 0.5).abs() < 1e-6);
+        assert!((shape.shape(1.5) - 0.5).abs() < 1e-6);
+        assert!((shape.shape(-1.5) + 0.5).abs() < 1e-6);
+        assert!((shape.shape(3.0) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn oversampling_reduces_aliasing() {
+        // a hard clipped 7 kHz sine folds harmonics back below it at 48 kHz;
+        // measure energy below 2 kHz, where only aliases can land
+        let sample_rate = 48000.0;
+        let aliasing = |factor: usize| {
+            let mut saturator = Saturator::new(sample_rate);
+            saturator.set_shape(ShaperType::HardClip);
+            saturator.set_drive(8.0);
+            saturator.set_oversampling(factor);
+            let mut lowpass = [0.541_196_1, 1.306_563]
+                .map(|q| Biquad::new(BiquadType::Lowpass, 2000.0, q, 0.0, sample_rate));
+            (0..9600)
+                .map(|i| {
+                    let x = (2.0 * PI * 7000.0 * i as f32 / sample_rate).sin();
+                    let y = saturator.process(x);
+                    lowpass.iter_mut().fold(y, |y, f| f.process(y))
+                })
+                .skip(4800)
+                .map(|y| y * y)
+                .sum::<f32>()
+        };
+        assert!(aliasing(2) < aliasing(1) * 0.01);
+        assert!(aliasing(8) < aliasing(1) * 0.01);
+    }
+
+    #[test]
+    fn oversampling_is_a_power_of_two() {
+        let mut saturator = Saturator::new(48000.0);
+        saturator.set_oversampling(3);
+        assert_eq!(saturator.oversampling(), 2);
+        saturator.set_oversampling(100);
+        assert_eq!(saturator.oversampling(), MAX_OVERSAMPLING);
+        saturator.set_oversampling(0);
+        assert_eq!(saturator.oversampling(), 1);
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut saturator = Saturator::new(48000.0);
+        Effect::set_parameter(&mut saturator, 0, 4.0);
+        Effect::set_parameter(&mut saturator, 1, 3.0);
+        Effect::set_parameter(&mut saturator, 2, 0.5);
+        Effect::set_parameter(&mut saturator, 3, 4.0);
+        assert_eq!(saturator.get_parameter(0), 4.0);
+        assert_eq!(saturator.shape(), ShaperType::Foldback);
+        assert_eq!(saturator.get_parameter(2), 0.5);
+        assert_eq!(saturator.get_parameter(3), 4.0);
+
+        // invalid shapes are ignored
+        Effect::set_parameter(&mut saturator, 1, 9.0);
+        assert_eq!(saturator.shape(), ShaperType::Foldback);
+    }
+}
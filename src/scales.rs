@@ -0,0 +1,188 @@
+//! Scales and pitch quantization
+//!
+//! A scale is a set of pitch classes relative to its root, kept as a 12 bit
+//! mask with bit 0 for the root. A track's `ScaleQuantizer` snaps notes onto
+//! its scale and root, so live input and sequences stay in key.
+
+use crate::utils::{pitch_class, transpose};
+
+/// user defined scales, selected after the built-in ones
+pub const USER_SCALE_COUNT: usize = 4;
+/// scale id of the first user scale
+pub const FIRST_USER_SCALE: u8 = 14;
+
+/// every pitch class, quantizing to it leaves notes alone
+pub const CHROMATIC_MASK: u16 = 0xfff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    Minor,
+    HarmonicMinor,
+    MelodicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    WholeTone,
+    User(usize),
+}
+
+impl Scale {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Scale::Chromatic),
+            1 => Some(Scale::Major),
+            2 => Some(Scale::Minor),
+            3 => Some(Scale::HarmonicMinor),
+            4 => Some(Scale::MelodicMinor),
+            5 => Some(Scale::Dorian),
+            6 => Some(Scale::Phrygian),
+            7 => Some(Scale::Lydian),
+            8 => Some(Scale::Mixolydian),
+            9 => Some(Scale::Locrian),
+            10 => Some(Scale::MajorPentatonic),
+            11 => Some(Scale::MinorPentatonic),
+            12 => Some(Scale::Blues),
+            13 => Some(Scale::WholeTone),
+            _ => {
+                let index = value.checked_sub(FIRST_USER_SCALE)? as usize;
+                (index < USER_SCALE_COUNT).then_some(Scale::User(index))
+            }
+        }
+    }
+
+    /// Semitones above the root, for the built-in scales
+    pub fn intervals(&self) -> Option<&'static [u8]> {
+        let intervals: &[u8] = match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::WholeTone => &[0, 2, 4, 6, 8, 10],
+            Scale::User(_) => return None,
+        };
+        Some(intervals)
+    }
+
+    /// Pitch class mask of the scale, looking user scales up in `user_scales`
+    pub fn mask(&self, user_scales: &[u16; USER_SCALE_COUNT]) -> u16 {
+        match (self, self.intervals()) {
+            (Scale::User(index), _) => user_scales[*index],
+            (_, Some(intervals)) => intervals.iter().fold(0, |mask, &i| mask | 1 << i),
+            _ => CHROMATIC_MASK,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleQuantizer {
+    pub root: u8,
+    pub scale: Scale,
+    mask: u16,
+}
+
+impl Default for ScaleQuantizer {
+    fn default() -> Self {
+        Self {
+            root: 0,
+            scale: Scale::Chromatic,
+            mask: CHROMATIC_MASK,
+        }
+    }
+}
+
+impl ScaleQuantizer {
+    /// `root` is a pitch class, 0 for C
+    pub fn new(root: u8, scale: Scale, user_scales: &[u16; USER_SCALE_COUNT]) -> Self {
+        Self {
+            root: pitch_class(root),
+            scale,
+            mask: scale.mask(user_scales),
+        }
+    }
+
+    /// Pick up a changed user scale
+    pub fn update_mask(&mut self, user_scales: &[u16; USER_SCALE_COUNT]) {
+        self.mask = self.scale.mask(user_scales);
+    }
+
+    /// Snap `pitch` to the nearest note of the scale, the lower one when two
+    /// are equally near. An empty scale leaves notes alone.
+    pub fn quantize(&self, pitch: u8) -> u8 {
+        let mask = self.mask & CHROMATIC_MASK;
+        if mask == CHROMATIC_MASK || mask == 0 {
+            return pitch;
+        }
+        let pitch_class = (pitch as i32 - self.root as i32).rem_euclid(12);
+        for distance in 0..=6 {
+            for offset in [-distance, distance] {
+                if mask & 1 << (pitch_class + offset).rem_euclid(12) == 0 {
+                    continue;
+                }
+                if let Some(candidate) = transpose(pitch, offset) {
+                    return candidate;
+                }
+            }
+        }
+        pitch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_USER_SCALES: [u16; USER_SCALE_COUNT] = [0; USER_SCALE_COUNT];
+
+    #[test]
+    fn snaps_to_nearest_scale_note() {
+        let c_major = ScaleQuantizer::new(0, Scale::Major, &NO_USER_SCALES);
+        // C# is as near to C as to D and goes down, F# to F
+        let snapped: Vec<u8> = (60..72).map(|p| c_major.quantize(p)).collect();
+        assert_eq!(snapped, [60, 60, 62, 62, 64, 65, 65, 67, 67, 69, 69, 71]);
+
+        // A minor pentatonic: A C D E G
+        let a_pentatonic = ScaleQuantizer::new(9, Scale::MinorPentatonic, &NO_USER_SCALES);
+        assert_eq!(a_pentatonic.quantize(70), 69);
+        assert_eq!(a_pentatonic.quantize(71), 72);
+        assert_eq!(a_pentatonic.quantize(66), 67);
+
+        assert_eq!(ScaleQuantizer::default().quantize(61), 61);
+        assert_eq!(c_major.quantize(127), 127);
+    }
+
+    #[test]
+    fn user_scales_by_id() {
+        assert_eq!(Scale::from_u8(1), Some(Scale::Major));
+        assert_eq!(Scale::from_u8(FIRST_USER_SCALE + 1), Some(Scale::User(1)));
+        assert_eq!(
+            Scale::from_u8(FIRST_USER_SCALE + USER_SCALE_COUNT as u8),
+            None
+        );
+        assert_eq!(Scale::from_u8(FIRST_USER_SCALE - 1), Some(Scale::WholeTone));
+
+        // root and fifth only
+        let mut user_scales = NO_USER_SCALES;
+        let mut quantizer = ScaleQuantizer::new(2, Scale::User(0), &user_scales);
+        assert_eq!(quantizer.quantize(63), 63);
+        user_scales[0] = 1 | 1 << 7;
+        quantizer.update_mask(&user_scales);
+        assert_eq!(quantizer.quantize(63), 62);
+        assert_eq!(quantizer.quantize(67), 69);
+    }
+}
@@ -0,0 +1,96 @@
+//! Oscilloscope capture
+//!
+//! The audio thread writes every output sample into a ring buffer of atomic
+//! samples, so a UI thread can copy out the latest stretch of audio at any
+//! time without locking. Snapshots are decimated to the number of points
+//! the UI draws, keeping the largest sample of each stretch so peaks stay
+//! visible.
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// longest stretch of audio a snapshot can show
+pub const MAX_SCOPE_MS: f32 = 1000.0;
+
+pub struct Scope {
+    // twice the longest snapshot, so the writer stays clear of a reader
+    samples: Box<[AtomicU32]>,
+    // samples written so far, the newest is at `(written - 1) % len`
+    written: AtomicUsize,
+    sample_rate: f32,
+}
+
+impl Scope {
+    pub fn new(sample_rate: f32) -> Self {
+        let len = (2.0 * MAX_SCOPE_MS * 0.001 * sample_rate) as usize;
+        Self {
+            samples: (0..len.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            sample_rate,
+        }
+    }
+
+    #[inline]
+    pub fn write(&self, x: f32) {
+        let written = self.written.load(Ordering::Relaxed);
+        self.samples[written % self.samples.len()].store(x.to_bits(), Ordering::Relaxed);
+        self.written
+            .store(written.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Copy the last `ms` milliseconds into `points`, oldest first, and
+    /// return the number of points filled. Fewer are filled when less audio
+    /// was captured, or `points` has room for more than one per sample.
+    pub fn snapshot(&self, ms: f32, points: &mut [f32]) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        let length = ((ms.clamp(0.0, MAX_SCOPE_MS) * 0.001 * self.sample_rate) as usize)
+            .min(written)
+            .min(self.samples.len() / 2);
+        let count = points.len().min(length);
+        if count == 0 {
+            return 0;
+        }
+
+        let start = written - length;
+        for (i, point) in points[..count].iter_mut().enumerate() {
+            let from = start + i * length / count;
+            let to = start + (i + 1) * length / count;
+            *point = (from..to)
+                .map(|n| {
+                    let bits = self.samples[n % self.samples.len()].load(Ordering::Relaxed);
+                    f32::from_bits(bits)
+                })
+                .fold(0.0, |a: f32, x| if x.abs() > a.abs() { x } else { a });
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_show_the_latest_audio() {
+        let scope = Scope::new(1000.0);
+        let mut points = [0.0; 10];
+        assert_eq!(scope.snapshot(100.0, &mut points), 0);
+
+        for i in 0..5000 {
+            scope.write(i as f32);
+        }
+        // 10 ms at 1 kHz is one sample per point
+        assert_eq!(scope.snapshot(10.0, &mut points), 10);
+        assert_eq!(
+            points,
+            [4990.0, 4991.0, 4992.0, 4993.0, 4994.0, 4995.0, 4996.0, 4997.0, 4998.0, 4999.0]
+        );
+
+        // decimated, each point is the largest of 10 samples
+        assert_eq!(scope.snapshot(100.0, &mut points), 10);
+        assert_eq!(points[0], 4909.0);
+        assert_eq!(points[9], 4999.0);
+
+        let mut many = [0.0; 100];
+        assert_eq!(scope.snapshot(20.0, &mut many), 20);
+    }
+}
@@ -1,13 +1,441 @@
-use crate::PROGRESS_CALLBACK;
-use std::{collections::HashMap, usize};
+#[cfg(feature = "analyzer")]
+use crate::analyzer::SpectrumAnalyzer;
+use crate::bus::{Effect, EffectType, SendBus, TrackSend};
+use crate::chords::Chord;
+use crate::consts::TRACK_COUNT;
+#[cfg(feature = "convolution")]
+use crate::convolution::ImpulseResponse;
+use crate::envelopes::RetriggerMode;
+use crate::lfo::GlobalLfo;
+use crate::macros::MacroDestination;
+use crate::midi_parse::MidiMessage;
+use crate::modulation::ModSlot;
+use crate::modulators::ModShaper;
+use crate::mutation::{mutate_events, Mutation};
+use crate::presets::{LoadedPreset, PresetCapture};
+use crate::recorder::RecordSettings;
+use crate::sampler::Sample;
+use crate::scales::{Scale, ScaleQuantizer};
+use crate::sidechain::SidechainTarget;
+use crate::smoothing::SmoothingType;
+use crate::synth::VoiceType;
+use crate::tuning::ScalaScale;
+use crate::utils::{beats_to_samples, samples_to_beats};
+use crate::velocity::VelocityCurve;
+use crossbeam::channel::Sender;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// shortest duration in beats given to a recorded note
+const MIN_RECORD_DURATION: f32 = 1.0 / 64.0;
+
+/// A recorded note waiting for its note off to set the duration
+struct RecordingNote {
+    id: u32,
+    track: u8,
+    pitch: u8,
+    beat_time: f32,
+}
+
+/// resolution of the tick part of a bar.beat.tick position
+pub const TICKS_PER_BEAT: u32 = 960;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+impl TimeSignature {
+    /// Returns `None` unless the numerator is positive and the denominator
+    /// is a power of two up to 32
+    pub fn new(numerator: u8, denominator: u8) -> Option<Self> {
+        if numerator == 0 || !denominator.is_power_of_two() || denominator > 32 {
+            return None;
+        }
+        Some(TimeSignature {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Length of one beat of the signature in quarter note beats
+    pub fn beat_length(&self) -> f32 {
+        4.0 / self.denominator as f32
+    }
+
+    /// Length of a bar in quarter note beats
+    pub fn bar_length(&self) -> f32 {
+        self.numerator as f32 * self.beat_length()
+    }
+
+    /// Zero-based bar, beat and tick of a position in quarter note beats
+    pub fn bar_beat_tick(&self, position: f32) -> BarBeatTick {
+        let beats = position.max(0.0) / self.beat_length();
+        let whole_beats = beats.floor();
+        BarBeatTick {
+            bar: whole_beats as u32 / self.numerator as u32,
+            beat: whole_beats as u32 % self.numerator as u32,
+            tick: ((beats - whole_beats) * TICKS_PER_BEAT as f32) as u32,
+        }
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        TimeSignature {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteModifier {
+    Straight,
+    Dotted,
+    Triplet,
+}
+
+/// A note length for tempo-synced effects, e.g. a dotted eighth
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteDivision {
+    pub denominator: u8,
+    pub modifier: NoteModifier,
+}
+
+impl NoteDivision {
+    /// whole to 32nd notes, each straight, dotted and triplet
+    pub const COUNT: u8 = 18;
+
+    /// Divisions are indexed from whole notes down, with the straight,
+    /// dotted and triplet variant of each length in turn
+    pub fn from_index(index: u8) -> Option<Self> {
+        if index >= Self::COUNT {
+            return None;
+        }
+        let modifier = match index % 3 {
+            0 => NoteModifier::Straight,
+            1 => NoteModifier::Dotted,
+            _ => NoteModifier::Triplet,
+        };
+        Some(NoteDivision {
+            denominator: 1 << (index / 3),
+            modifier,
+        })
+    }
+
+    pub fn index(&self) -> u8 {
+        self.denominator.trailing_zeros() as u8 * 3 + self.modifier as u8
+    }
+
+    /// Length in quarter note beats
+    pub fn beats(&self) -> f32 {
+        let beats = 4.0 / self.denominator as f32;
+        match self.modifier {
+            NoteModifier::Straight => beats,
+            NoteModifier::Dotted => beats * 1.5,
+            NoteModifier::Triplet => beats * 2.0 / 3.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BarBeatTick {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
 
 struct Sequence {
+    name: String,
     events: Vec<Event>,
     length: f32,
+    time_signature: TimeSignature,
+}
+
+impl Sequence {
+    fn new(name: &str, length: f32) -> Self {
+        Sequence {
+            name: name.to_string(),
+            events: Vec::new(),
+            length,
+            time_signature: TimeSignature::default(),
+        }
+    }
+}
+
+/// Entry of a song arrangement: which pattern to play and how many times
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChainEntry {
+    pub pattern: usize,
+    pub repeats: u32,
+}
+
+/// Patterns plus the chain that arranges them, switched at loop boundaries
+struct Song {
+    patterns: Vec<Sequence>,
+    chain: Vec<ChainEntry>,
+    current: usize,
+    queued: Option<usize>,
+    chain_index: usize,
+    repeat_count: u32,
+    // the chain was (re)built and starts at its first entry on the next boundary
+    chain_pending: bool,
+}
+
+impl Song {
+    fn new(length: f32) -> Self {
+        Song {
+            patterns: vec![Sequence::new("Pattern 1", length)],
+            chain: Vec::new(),
+            current: 0,
+            queued: None,
+            chain_index: 0,
+            repeat_count: 0,
+            chain_pending: false,
+        }
+    }
+
+    /// Pattern, chain index and repeat count for the next loop
+    fn next_state(&self) -> (usize, usize, u32) {
+        if let Some(pattern) = self.queued {
+            return (pattern, self.chain_index, self.repeat_count);
+        }
+        if self.chain.is_empty() {
+            return (self.current, 0, 0);
+        }
+        if self.chain_pending {
+            return (self.chain[0].pattern, 0, 0);
+        }
+
+        let entry = self.chain[self.chain_index];
+        if self.repeat_count + 1 < entry.repeats {
+            (entry.pattern, self.chain_index, self.repeat_count + 1)
+        } else {
+            let index = (self.chain_index + 1) % self.chain.len();
+            (self.chain[index].pattern, index, 0)
+        }
+    }
+
+    fn peek_next(&self) -> usize {
+        self.next_state().0
+    }
+
+    /// Back to the first entry of the chain, if there is one
+    fn rewind(&mut self) {
+        if let Some(entry) = self.chain.first() {
+            self.current = entry.pattern;
+            self.chain_index = 0;
+            self.repeat_count = 0;
+            self.queued = None;
+            self.chain_pending = false;
+        }
+    }
+
+    fn advance(&mut self) {
+        let (pattern, chain_index, repeat_count) = self.next_state();
+        self.current = pattern;
+        self.chain_index = chain_index;
+        self.repeat_count = repeat_count;
+        self.queued = None;
+        self.chain_pending = false;
+    }
+}
+
+/// Condition deciding on which loop iterations an event plays
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TrigCondition {
+    #[default]
+    Always,
+    /// play on the `a`th of every `b` loops (1-based, Elektron-style A:B)
+    Ratio {
+        a: u8,
+        b: u8,
+    },
+    FirstLoop,
+    NotFirstLoop,
+}
+
+impl TrigCondition {
+    pub fn from_u8(kind: u8, a: u8, b: u8) -> Self {
+        match kind {
+            1 if b > 0 => TrigCondition::Ratio {
+                a: a.clamp(1, b),
+                b,
+            },
+            2 => TrigCondition::FirstLoop,
+            3 => TrigCondition::NotFirstLoop,
+            _ => TrigCondition::Always,
+        }
+    }
+
+    pub fn is_met(&self, loop_index: i64) -> bool {
+        match *self {
+            TrigCondition::Always => true,
+            TrigCondition::Ratio { a, b } => loop_index % b as i64 == (a as i64 - 1),
+            TrigCondition::FirstLoop => loop_index == 0,
+            TrigCondition::NotFirstLoop => loop_index > 0,
+        }
+    }
+}
+
+/// Per-note expression, as sent by MPE controllers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoteExpression {
+    /// pitch offset in semitones
+    pub pitch_bend: f32,
+    /// 0.0..1.0
+    pub pressure: f32,
+    /// 0.0..1.0, the vertical "slide" axis
+    pub timbre: f32,
+}
+
+/// What happens to the sounding notes when the transport stops
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StopMode {
+    /// fade the voices out quickly and silence them
+    Flush,
+    /// release the sounding notes
+    #[default]
+    Release,
+    /// let the sounding notes play on to their note offs
+    Finish,
+}
+
+impl StopMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(StopMode::Flush),
+            1 => Some(StopMode::Release),
+            2 => Some(StopMode::Finish),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpressionDimension {
+    PitchBend,
+    Pressure,
+    Timbre,
+}
+
+impl ExpressionDimension {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ExpressionDimension::PitchBend),
+            1 => Some(ExpressionDimension::Pressure),
+            2 => Some(ExpressionDimension::Timbre),
+            _ => None,
+        }
+    }
+}
+
+impl NoteExpression {
+    pub fn set(&mut self, dimension: ExpressionDimension, value: f32) {
+        match dimension {
+            ExpressionDimension::PitchBend => self.pitch_bend = value,
+            ExpressionDimension::Pressure => self.pressure = value,
+            ExpressionDimension::Timbre => self.timbre = value,
+        }
+    }
+}
+
+/// Random variation of a track's sequenced notes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Humanize {
+    /// largest timing offset either way, in milliseconds
+    pub timing_ms: f32,
+    /// largest velocity change either way, as a fraction of the velocity
+    pub velocity: f32,
+    /// vary notes the same way on every loop, rather than anew each time
+    pub repeat: bool,
+}
+
+/// parameter locks an event holds
+pub const MAX_PARAMETER_LOCKS: usize = 8;
+
+/// Parameter values locked for a single step, Elektron style. Fixed
+/// capacity, so scheduling events that carry them doesn't allocate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<(i8, f32)>", into = "Vec<(i8, f32)>")]
+pub struct ParameterLocks {
+    locks: [(i8, f32); MAX_PARAMETER_LOCKS],
+    len: usize,
+}
+
+impl ParameterLocks {
+    /// Lock `parameter` to `value`, replacing an earlier lock of it.
+    /// Returns false when all MAX_PARAMETER_LOCKS are taken.
+    pub fn set(&mut self, parameter: i8, value: f32) -> bool {
+        if let Some(lock) = self.locks[..self.len]
+            .iter_mut()
+            .find(|(p, _)| *p == parameter)
+        {
+            lock.1 = value;
+            return true;
+        }
+        if self.len == MAX_PARAMETER_LOCKS {
+            return false;
+        }
+        self.locks[self.len] = (parameter, value);
+        self.len += 1;
+        true
+    }
+
+    pub fn remove(&mut self, parameter: i8) {
+        if let Some(index) = self.locks[..self.len]
+            .iter()
+            .position(|(p, _)| *p == parameter)
+        {
+            self.locks.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+            self.locks[self.len] = (0, 0.0);
+        }
+    }
+
+    pub fn get(&self, parameter: i8) -> Option<f32> {
+        self.iter().find(|(p, _)| *p == parameter).map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i8, f32)> + '_ {
+        self.locks[..self.len].iter().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl From<Vec<(i8, f32)>> for ParameterLocks {
+    fn from(locks: Vec<(i8, f32)>) -> Self {
+        let mut parameter_locks = Self::default();
+        for (parameter, value) in locks {
+            parameter_locks.set(parameter, value);
+        }
+        parameter_locks
+    }
 }
 
-#[derive(Clone)]
+impl From<ParameterLocks> for Vec<(i8, f32)> {
+    fn from(locks: ParameterLocks) -> Self {
+        locks.iter().collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Event {
+    /// identifier used to edit or remove the event after scheduling
+    pub id: u32,
     pub beat_time: f32,
     pub pitch: u8,
     pub velocity: u8,
@@ -15,13 +443,435 @@ pub struct Event {
     pub param2: f32,
     pub track: u8,
     pub duration: f32,
+    /// chance of the event playing, 0.0..1.0
+    pub probability: f32,
+    pub condition: TrigCondition,
+    /// timing offset in milliseconds, negative values play early
+    pub nudge_ms: f32,
+    /// number of hits fired by the event (ratchets), 1 plays it once
+    pub retrigger_count: u8,
+    /// interval between ratchets in beats, 0.0 spreads them over the duration
+    pub retrigger_rate: f32,
+    pub expression: NoteExpression,
+    /// parameter values applied to the track for this step only
+    pub locks: ParameterLocks,
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Event {
+            id: 0,
+            beat_time: 0.0,
+            pitch: 60,
+            velocity: 100,
+            param1: 0.0,
+            param2: 0.0,
+            track: 0,
+            duration: 1.0,
+            probability: 1.0,
+            condition: TrigCondition::Always,
+            nudge_ms: 0.0,
+            retrigger_count: 1,
+            retrigger_rate: 0.0,
+            expression: NoteExpression::default(),
+            locks: ParameterLocks::default(),
+        }
+    }
 }
 
 pub enum Message {
     Schedule(Event),
+    /// Many events at once, e.g. a whole pattern
+    ScheduleBatch(Vec<Event>),
+    UpdateEvent(Event),
+    RemoveEvent(u32),
     ParameterChange(i8, f32, u8),
-    NoteOn { track: u8, velocity: u8 },
+    ParameterSmoothing {
+        smoothing_type: SmoothingType,
+        time_ms: f32,
+    },
+    /// Glide parameter changes linearly across the block they arrive
+    /// before, however long it is, instead of over the smoothing time
+    BlockRamps(bool),
+    /// Capture a track's parameters into snapshot A (0) or B (1)
+    CaptureSnapshot {
+        track: u8,
+        slot: u8,
+    },
+    /// Set a track's parameters between its snapshots, from A at 0.0 to B
+    /// at 1.0
+    Morph {
+        track: u8,
+        amount: f32,
+    },
+    NoteOn {
+        id: u32,
+        track: u8,
+        pitch: u8,
+        velocity: u8,
+        expression: NoteExpression,
+        /// offset into the next rendered block, in frames
+        frame: u32,
+    },
+    NoteExpression {
+        id: u32,
+        dimension: ExpressionDimension,
+        value: f32,
+    },
+    NoteOff {
+        track: u8,
+        pitch: u8,
+        frame: u32,
+    },
+    PitchBend {
+        track: u8,
+        bend: f32,
+    },
+    PitchBendRange {
+        track: u8,
+        semitones: f32,
+    },
+    /// master tune in cents
+    MasterTune(f32),
+    /// transposes a track's notes by semitones and fine tunes it in cents
+    TrackTuning {
+        track: u8,
+        transpose: i8,
+        cents: f32,
+    },
+    /// retunes the notes to a Scala scale around `root`, None for equal
+    /// temperament
+    TuningScale {
+        scale: Option<Arc<ScalaScale>>,
+        root: u8,
+    },
+    Midi {
+        message: MidiMessage,
+        frame: u32,
+    },
+    Swing {
+        track: u8,
+        amount: f32,
+    },
+    Humanize {
+        track: u8,
+        humanize: Humanize,
+    },
+    Mutation(Mutation),
+    /// mutates the edited pattern once
+    Mutate,
+    Scale {
+        track: u8,
+        root: u8,
+        scale: Scale,
+    },
+    UserScale {
+        index: usize,
+        mask: u16,
+    },
+    VelocityCurve {
+        track: u8,
+        curve: VelocityCurve,
+    },
+    RetriggerMode {
+        track: u8,
+        mode: RetriggerMode,
+    },
+    /// assign a parameter to a slot of one of a track's macros, None
+    /// clears the slot
+    MacroAssign {
+        track: u8,
+        index: u8,
+        slot: u8,
+        destination: Option<MacroDestination>,
+    },
+    /// set or clear one of the engine's global LFOs
+    GlobalLfo {
+        index: u8,
+        lfo: Option<GlobalLfo>,
+    },
+    /// turn one of a track's macros, 0.0..1.0
+    Macro {
+        track: u8,
+        index: u8,
+        value: f32,
+    },
+    /// chord mode of a track, None plays single notes
+    Chord {
+        track: u8,
+        chord: Option<Chord>,
+    },
+    Nudge {
+        id: u32,
+        nudge_ms: f32,
+    },
+    /// locks a parameter of an event's step, or removes the lock when
+    /// `value` is None
+    EventLock {
+        id: u32,
+        parameter: i8,
+        value: Option<f32>,
+    },
+    SetSound {
+        track: u8,
+        voice_type: VoiceType,
+    },
+    /// switches the track to a sampler playing `sample`
+    LoadSample {
+        track: u8,
+        sample: Arc<Sample>,
+    },
+    /// chops the track's sample at `slices`, normalized to its length
+    SampleSlices {
+        track: u8,
+        slices: Arc<[f32]>,
+    },
+    ModSlot {
+        track: u8,
+        index: usize,
+        slot: ModSlot,
+    },
+    /// shape the source of a track's mod slot
+    ModShaper {
+        track: u8,
+        index: usize,
+        shaper: ModShaper,
+    },
+    CreatePattern {
+        name: String,
+        length: f32,
+    },
+    EditPattern(usize),
+    QueuePattern(usize),
+    AppendChain(ChainEntry),
+    ClearChain,
+    Metronome {
+        enabled: bool,
+        volume: f32,
+    },
+    CountIn(u32),
+    /// render the sequence ahead by the engine's latency, false when the
+    /// host compensates for it
+    LatencyCompensation(bool),
+    Recording(bool),
+    RecordQuantize(f32),
+    SetPosition(f32),
+    LoopMarkers {
+        start: f32,
+        end: f32,
+    },
+    OneShot(bool),
+    /// Crossfade in milliseconds hiding the jump when a sounding voice is
+    /// restarted or stolen, 0.0 to turn it off
+    DeclickTime(f32),
+    /// What stopping the transport does to the sounding notes, and whether
+    /// it goes back to the start or resumes where it stopped
+    StopBehavior {
+        mode: StopMode,
+        rewind: bool,
+    },
+    TimeSignature {
+        pattern: usize,
+        time_signature: TimeSignature,
+    },
     Clear,
+    /// Stop every sounding note, whatever note offs are still to come
+    AllNotesOff,
+    /// Add events to the staged pattern, which isn't played until committed
+    StageEvents(Vec<Event>),
+    /// Replace the events of the edited pattern with the staged ones, at the
+    /// next loop boundary or right away
+    CommitPattern {
+        immediately: bool,
+    },
+    /// arm the recorder, `buffer` has room for the longest recording
+    Record {
+        settings: RecordSettings,
+        buffer: Vec<f32>,
+    },
+    StopRecording,
+    LoadRecordedSample {
+        track: u8,
+        name: String,
+    },
+    /// the loaded preset comes back to be dropped on the host, holding what
+    /// it replaced
+    LoadPreset(Box<LoadedPreset>),
+    // captured on the audio thread and sent back
+    CapturePreset(Box<PresetCapture>, Sender<Box<PresetCapture>>),
+    /// buses and effects are built off the audio thread, which only adds
+    /// them
+    CreateBus(SendBus),
+    AddBusEffect {
+        bus: u8,
+        effect_type: EffectType,
+        effect: Box<dyn Effect>,
+    },
+    BusEffectParameter {
+        bus: u8,
+        effect: u8,
+        parameter: i8,
+        value: f32,
+    },
+    /// load an impulse response into a convolution effect on a bus
+    #[cfg(feature = "convolution")]
+    BusImpulseResponse {
+        bus: u8,
+        effect: u8,
+        ir: Arc<ImpulseResponse>,
+    },
+    BusReturn {
+        bus: u8,
+        level: f32,
+    },
+    BusFreeze {
+        bus: u8,
+        freeze: bool,
+    },
+    AddTrackInsert {
+        track: u8,
+        effect_type: EffectType,
+        effect: Box<dyn Effect>,
+    },
+    TrackInsertParameter {
+        track: u8,
+        effect: u8,
+        parameter: i8,
+        value: f32,
+    },
+    /// load an impulse response into a convolution insert on a track
+    #[cfg(feature = "convolution")]
+    TrackInsertImpulseResponse {
+        track: u8,
+        effect: u8,
+        ir: Arc<ImpulseResponse>,
+    },
+    TrackSend {
+        track: u8,
+        bus: u8,
+        send: TrackSend,
+    },
+    AddInputInsert(EffectType, Box<dyn Effect>),
+    InputInsertParameter {
+        effect: u8,
+        parameter: i8,
+        value: f32,
+    },
+    InputSend {
+        bus: u8,
+        send: TrackSend,
+    },
+    /// level of the audio input in the mix
+    InputMonitor(f32),
+    TrackVolume {
+        track: u8,
+        volume: f32,
+    },
+    /// -1.0 hard left to 1.0 hard right
+    TrackPan {
+        track: u8,
+        pan: f32,
+    },
+    TrackMute {
+        track: u8,
+        mute: bool,
+    },
+    TrackSolo {
+        track: u8,
+        solo: bool,
+    },
+    /// Play a track on one of the stereo outputs, 0 for the main one
+    TrackOutput {
+        track: u8,
+        output: u8,
+    },
+    MasterVolume(f32),
+    /// remove DC from the voices and the master, false to bypass
+    DcBlocking(bool),
+    /// track the oscilloscope captures, None for the master output
+    ScopeSource(Option<u8>),
+    /// analyze the master output, None to stop; the analyzer is set up off
+    /// the audio thread
+    #[cfg(feature = "analyzer")]
+    SpectrumAnalyzer(Option<Box<SpectrumAnalyzer>>),
+    /// `attack` and `release` in milliseconds
+    Sidechain {
+        target: SidechainTarget,
+        source: u8,
+        amount: f32,
+        attack: f32,
+        release: f32,
+    },
+}
+
+impl Message {
+    /// Frame offset of a timestamped message into the next rendered block;
+    /// messages without one take effect at the start of the block
+    pub fn frame(&self) -> u32 {
+        match self {
+            Message::NoteOn { frame, .. }
+            | Message::NoteOff { frame, .. }
+            | Message::Midi { frame, .. } => *frame,
+            _ => 0,
+        }
+    }
+
+    /// Track the message is for, if it's for one
+    pub fn track(&self) -> Option<u8> {
+        match self {
+            Message::ParameterChange(_, _, track)
+            | Message::CaptureSnapshot { track, .. }
+            | Message::Morph { track, .. }
+            | Message::NoteOn { track, .. }
+            | Message::NoteOff { track, .. }
+            | Message::PitchBend { track, .. }
+            | Message::PitchBendRange { track, .. }
+            | Message::TrackTuning { track, .. }
+            | Message::Swing { track, .. }
+            | Message::Humanize { track, .. }
+            | Message::Scale { track, .. }
+            | Message::VelocityCurve { track, .. }
+            | Message::RetriggerMode { track, .. }
+            | Message::MacroAssign { track, .. }
+            | Message::Macro { track, .. }
+            | Message::Chord { track, .. }
+            | Message::SetSound { track, .. }
+            | Message::LoadSample { track, .. }
+            | Message::SampleSlices { track, .. }
+            | Message::ModSlot { track, .. }
+            | Message::ModShaper { track, .. }
+            | Message::LoadRecordedSample { track, .. }
+            | Message::AddTrackInsert { track, .. }
+            | Message::TrackInsertParameter { track, .. }
+            | Message::TrackSend { track, .. }
+            | Message::TrackVolume { track, .. }
+            | Message::TrackPan { track, .. }
+            | Message::TrackMute { track, .. }
+            | Message::TrackSolo { track, .. }
+            | Message::TrackOutput { track, .. } => Some(*track),
+            #[cfg(feature = "convolution")]
+            Message::TrackInsertImpulseResponse { track, .. } => Some(*track),
+            _ => None,
+        }
+    }
+}
+
+// events for tracks that don't exist are dropped
+fn is_valid_track(event: &Event) -> bool {
+    (event.track as usize) < TRACK_COUNT
+}
+
+// SplitMix64 finalizer, spreads similar inputs over all bits
+fn split_mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// -1.0..1.0 from the top bits of a hash
+fn hash_to_bipolar(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
 }
 
 #[derive(Clone, Debug)]
@@ -30,7 +880,12 @@ pub enum ScheduledEvent {
         time: i32,
         pitch: u8,
         velocity: u8,
+        /// per-step parameter locks, passed on to the voice
+        param1: f32,
+        param2: f32,
         track: u8,
+        expression: NoteExpression,
+        locks: ParameterLocks,
     },
     NoteOff {
         time: i32,
@@ -39,126 +894,826 @@ pub enum ScheduledEvent {
     },
 }
 
-pub struct Sequencer {
-    sequence: Sequence,
-    scheduled_events: Vec<ScheduledEvent>,
-    sample_rate: f32,
-}
+// events preallocated per block, so scheduling doesn't allocate on the
+// audio thread in normal use
+const EVENT_CAPACITY: usize = 1024;
+
+/// Events of one block, in frame order. Preallocated and reused from
+/// block to block.
+#[derive(Default)]
+pub struct EventBuffer {
+    frames: Vec<usize>,
+    events: Vec<ScheduledEvent>,
+}
+
+impl EventBuffer {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::with_capacity(EVENT_CAPACITY),
+            events: Vec::with_capacity(EVENT_CAPACITY),
+        }
+    }
+
+    /// Add an event at `frame`, which must not be earlier than the last one
+    pub fn push(&mut self, frame: usize, event: ScheduledEvent) {
+        debug_assert!(self.frames.last().is_none_or(|&last| last <= frame));
+        self.frames.push(frame);
+        self.events.push(event);
+    }
+
+    /// The events at `frame`, if any
+    pub fn get(&self, frame: &usize) -> Option<&[ScheduledEvent]> {
+        let start = self.frames.partition_point(|f| f < frame);
+        let end = self.frames.partition_point(|f| f <= frame);
+        (start < end).then(|| &self.events[start..end])
+    }
+
+    /// Move all events `offset` frames later
+    pub fn delay(&mut self, offset: usize) {
+        for frame in self.frames.iter_mut() {
+            *frame += offset;
+        }
+    }
+
+    /// Move all events `offset` frames earlier, those that would end up
+    /// before the start of the block to its first frame
+    pub fn advance(&mut self, offset: usize) {
+        for frame in self.frames.iter_mut() {
+            *frame = frame.saturating_sub(offset);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.events.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+pub struct Sequencer {
+    song: Song,
+    edit_pattern: usize,
+    // sample time at which the current loop started
+    loop_start: i64,
+    // number of loops the current pattern has played
+    pattern_loop: i64,
+    scheduled_events: Vec<ScheduledEvent>,
+    swing: [f32; TRACK_COUNT],
+    swing_step: f32,
+    humanize: [Humanize; TRACK_COUNT],
+    // variations are hashed from this, the event and the loop, so they
+    // stay put while an event is looked at in consecutive buffers
+    humanize_seed: u64,
+    mutation: Mutation,
+    // bars played since the last automatic mutation
+    mutation_bars: f32,
+    mutation_due: bool,
+    rng: StdRng,
+    recording: bool,
+    record_quantize: f32,
+    recording_notes: Vec<RecordingNote>,
+    loop_markers: Option<(f32, f32)>,
+    one_shot: bool,
+    finished: bool,
+    pending_position: Option<f32>,
+    // events collected for a pattern replacement, and a committed
+    // replacement with the pattern it's for, waiting for the loop boundary
+    staged: Vec<Event>,
+    pending_swap: Option<(usize, Vec<Event>)>,
+    position: f32,
+    sample_rate: f32,
+}
+
+impl Sequencer {
+    pub fn new(length: f32, sample_rate: f32) -> Self {
+        Sequencer {
+            song: Song::new(length),
+            edit_pattern: 0,
+            loop_start: 0,
+            pattern_loop: 0,
+            scheduled_events: Vec::with_capacity(EVENT_CAPACITY),
+            swing: [0.0; TRACK_COUNT],
+            swing_step: 0.25,
+            humanize: [Humanize::default(); TRACK_COUNT],
+            humanize_seed: rand::random(),
+            mutation: Mutation::default(),
+            mutation_bars: 0.0,
+            mutation_due: false,
+            rng: StdRng::from_entropy(),
+            recording: false,
+            record_quantize: 0.0,
+            recording_notes: Vec::new(),
+            loop_markers: None,
+            one_shot: false,
+            finished: false,
+            pending_position: None,
+            staged: Vec::new(),
+            pending_swap: None,
+            position: 0.0,
+            sample_rate,
+        }
+    }
+
+    /// Schedule the events due in this buffer, returning the playback
+    /// position, or None once one-shot playback has finished
+    pub fn process(
+        &mut self,
+        events: &mut EventBuffer,
+        sample_time: i64,
+        tempo: f32,
+        num_frames: i32,
+    ) -> Option<BarBeatTick> {
+        if let Some(beats) = self.pending_position.take() {
+            self.locate(sample_time, beats, tempo);
+        }
+
+        if sample_time < self.loop_start {
+            // the host transport jumped back
+            self.loop_start = 0;
+            self.pattern_loop = 0;
+        }
+
+        let (mut region_start, region_end) = self.loop_region(self.song.current, tempo);
+        let mut cycle = region_end - region_start;
+        let loops_passed = (sample_time - self.loop_start) / cycle as i64;
+        if loops_passed > 0 {
+            if self.one_shot {
+                self.finish(events);
+                return None;
+            }
+            self.swap_staged();
+            let previous = self.song.current;
+            self.count_mutation_bars(loops_passed * cycle as i64, tempo);
+            self.loop_start += loops_passed * cycle as i64;
+            self.song.advance();
+            if self.song.current == previous {
+                self.pattern_loop += loops_passed;
+            } else {
+                self.pattern_loop = 0;
+            }
+            (region_start, cycle) = self.loop_cycle(self.song.current, tempo);
+            // note offs the playhead won't pass anymore, e.g. after the loop
+            // markers moved, are sent right away
+            self.release_outside(events, region_start, region_start + cycle);
+        }
+
+        let buffer_start = (sample_time - self.loop_start) as i32;
+        let buffer_end = buffer_start + num_frames;
+
+        self.position = self.sample_to_beat((region_start + buffer_start) as i64, tempo);
+
+        let current = self.song.current;
+        let loop_index = self.pattern_loop;
+        let length = self.pattern_length(current, tempo);
+        self.schedule_pattern(
+            current,
+            region_start + buffer_start,
+            region_start + buffer_end.min(cycle),
+            length,
+            (region_start, cycle),
+            loop_index,
+            tempo,
+        );
+
+        // the buffer crosses the loop end, schedule the start of the next loop
+        let (mut next_start, mut next_cycle) = (region_start, cycle);
+        if buffer_end > cycle && !self.one_shot {
+            self.swap_staged();
+            let next = self.song.peek_next();
+            let next_loop = if next == current { loop_index + 1 } else { 0 };
+            (next_start, next_cycle) = self.loop_cycle(next, tempo);
+            let next_length = self.pattern_length(next, tempo);
+            self.schedule_pattern(
+                next,
+                next_start,
+                next_start + (buffer_end - cycle).min(next_cycle),
+                next_length,
+                (next_start, next_cycle),
+                next_loop,
+                tempo,
+            );
+        }
+
+        for frame_offset in 0..num_frames {
+            let time = buffer_start + frame_offset;
+            let position = if time < cycle {
+                region_start + time
+            } else {
+                next_start + (time - cycle) % next_cycle
+            };
+            let is_due = |ev: &ScheduledEvent| match *ev {
+                ScheduledEvent::NoteOn { time, .. } | ScheduledEvent::NoteOff { time, .. } => {
+                    time == position
+                }
+            };
+
+            let mut any_due = false;
+            for ev in self.scheduled_events.iter().filter(|ev| is_due(ev)) {
+                events.push(frame_offset as usize, ev.clone());
+                any_due = true;
+            }
+            if any_due {
+                self.scheduled_events.retain(|ev| !is_due(ev));
+            }
+        }
+        Some(self.bar_beat_tick())
+    }
+
+    /// Send the pending note offs outside `start..end` at the first frame
+    fn release_outside(&mut self, events: &mut EventBuffer, start: i32, end: i32) {
+        let is_outside = |ev: &ScheduledEvent| match *ev {
+            ScheduledEvent::NoteOff { time, .. } => time < start || time >= end,
+            ScheduledEvent::NoteOn { .. } => false,
+        };
+        for note_off in self.scheduled_events.iter().filter(|ev| is_outside(ev)) {
+            events.push(0, note_off.clone());
+        }
+        self.scheduled_events.retain(|ev| !is_outside(ev));
+    }
+
+    /// Stop one-shot playback, releasing all pending notes at the first frame
+    fn finish(&mut self, events: &mut EventBuffer) {
+        let note_offs = self
+            .scheduled_events
+            .drain(..)
+            .filter(|ev| matches!(ev, ScheduledEvent::NoteOff { .. }));
+        for note_off in note_offs {
+            events.push(0, note_off);
+        }
+        self.finished = true;
+        self.position = self.loop_markers.map_or(0.0, |(start, _)| start);
+    }
+
+    /// Move the playhead to `beats` within the loop region of the playing
+    /// pattern, as of `sample_time`
+    fn locate(&mut self, sample_time: i64, beats: f32, tempo: f32) {
+        let (region_start, cycle) = self.loop_cycle(self.song.current, tempo);
+        let offset = (self.beat_to_sample(beats, tempo) - region_start).clamp(0, cycle - 1);
+        self.loop_start = sample_time - offset as i64;
+        self.position = self.sample_to_beat((region_start + offset) as i64, tempo);
+        // pending note offs are kept so no notes hang
+        self.scheduled_events
+            .retain(|ev| matches!(ev, ScheduledEvent::NoteOff { .. }));
+    }
+
+    /// Start and end in samples of the part of a pattern that is played,
+    /// limited by the loop markers
+    fn loop_region(&self, pattern: usize, tempo: f32) -> (i32, i32) {
+        let length = self.pattern_length(pattern, tempo);
+        let Some((start, end)) = self.loop_markers else {
+            return (0, length);
+        };
+        let end = self.beat_to_sample(end, tempo).min(length);
+        let start = self.beat_to_sample(start, tempo);
+        if end - start < 1 {
+            return (0, length);
+        }
+        (start, end)
+    }
+
+    /// Start in samples and length in samples of a pattern's loop region
+    fn loop_cycle(&self, pattern: usize, tempo: f32) -> (i32, i32) {
+        let (start, end) = self.loop_region(pattern, tempo);
+        (start, end - start)
+    }
+
+    fn pattern_length(&self, pattern: usize, tempo: f32) -> i32 {
+        self.beat_to_sample(self.song.patterns[pattern].length, tempo)
+            .max(1)
+    }
+
+    /// Move the events of a pattern that fall within `start..end` (in samples
+    /// from the loop start) to the scheduled events. Ratchets and note offs
+    /// running past the end of the loop region, the start and length of
+    /// `region`, wrap around to its start, where the playhead comes next.
+    #[allow(clippy::too_many_arguments)]
+    fn schedule_pattern(
+        &mut self,
+        pattern: usize,
+        start: i32,
+        end: i32,
+        length: i32,
+        region: (i32, i32),
+        loop_index: i64,
+        tempo: f32,
+    ) {
+        let (region_start, cycle) = region;
+        let wrap = |time: i32| region_start + (time - region_start).rem_euclid(cycle.max(1));
+        let sequence_events = std::mem::take(&mut self.song.patterns[pattern].events);
+
+        for ev in &sequence_events {
+            let (jitter_ms, velocity) = self.humanize_event(ev, loop_index);
+            let event_time = (self.beat_to_sample(self.swing_beat_time(ev), tempo)
+                + self.ms_to_sample(ev.nudge_ms + jitter_ms))
+            .rem_euclid(length);
+
+            if Self::is_in_buffer(event_time, start, end) && self.should_play(ev, loop_index) {
+                let count = ev.retrigger_count.max(1) as i32;
+                let mut duration = self.beat_to_sample(ev.duration, tempo);
+                let mut interval = 0;
+                if count > 1 {
+                    interval = if ev.retrigger_rate > 0.0 {
+                        self.beat_to_sample(ev.retrigger_rate, tempo)
+                    } else {
+                        duration / count
+                    };
+                    duration = duration.min(interval);
+                }
+
+                for hit in 0..count {
+                    let time = wrap(event_time + hit * interval);
+
+                    let note_on = ScheduledEvent::NoteOn {
+                        time,
+                        pitch: ev.pitch,
+                        velocity,
+                        param1: ev.param1,
+                        param2: ev.param2,
+                        track: ev.track,
+                        expression: ev.expression,
+                        locks: ev.locks,
+                    };
+                    self.scheduled_events.push(note_on);
+
+                    let note_off = ScheduledEvent::NoteOff {
+                        time: wrap(time + duration),
+                        pitch: ev.pitch,
+                        track: ev.track,
+                    };
+
+                    self.scheduled_events.push(note_off);
+                }
+            }
+        }
+
+        self.song.patterns[pattern].events = sequence_events;
+    }
+
+    /// Evaluate the event's trig condition and probability for a loop iteration
+    fn should_play(&mut self, ev: &Event, loop_index: i64) -> bool {
+        if !ev.condition.is_met(loop_index) {
+            return false;
+        }
+        ev.probability >= 1.0 || self.rng.gen::<f32>() < ev.probability
+    }
+
+    /// Timing offset in milliseconds and velocity of an event in a loop,
+    /// after its track's humanization
+    fn humanize_event(&self, ev: &Event, loop_index: i64) -> (f32, u8) {
+        let humanize = self.humanize[ev.track as usize % TRACK_COUNT];
+        if humanize.timing_ms == 0.0 && humanize.velocity == 0.0 {
+            return (0.0, ev.velocity);
+        }
+        let loop_index = if humanize.repeat {
+            0
+        } else {
+            loop_index as u64
+        };
+        let hash = split_mix(self.humanize_seed ^ ((ev.id as u64) << 32) ^ loop_index);
+        let timing = hash_to_bipolar(hash);
+        let velocity = hash_to_bipolar(split_mix(hash));
+
+        let velocity = ev.velocity as f32 * (1.0 + velocity * humanize.velocity);
+        (
+            timing * humanize.timing_ms,
+            velocity.round().clamp(1.0, 127.0) as u8,
+        )
+    }
+
+    /// Delay events on off-beat steps by a fraction of half a step
+    fn swing_beat_time(&self, ev: &Event) -> f32 {
+        let amount = self.swing[ev.track as usize % TRACK_COUNT];
+        if amount == 0.0 {
+            return ev.beat_time;
+        }
+
+        let step = ev.beat_time / self.swing_step;
+        let index = step.round();
+        let is_on_grid = (step - index).abs() < 1e-3;
+        if is_on_grid && index as i64 % 2 == 1 {
+            ev.beat_time + amount * self.swing_step * 0.5
+        } else {
+            ev.beat_time
+        }
+    }
+
+    pub fn beat_to_sample(&self, beat_time: f32, tempo: f32) -> i32 {
+        beats_to_samples(beat_time, tempo, self.sample_rate as f32) as i32
+    }
+
+    pub fn ms_to_sample(&self, ms: f32) -> i32 {
+        (ms * 0.001 * self.sample_rate) as i32
+    }
+
+    pub fn sample_to_beat(&self, sample_time: i64, tempo: f32) -> f32 {
+        samples_to_beats(sample_time as f32, tempo, self.sample_rate as f32)
+    }
+
+    fn is_in_buffer(time: i32, buffer_start: i32, buffer_end: i32) -> bool {
+        time >= buffer_start && time < buffer_end
+    }
+
+    fn sequence(&self) -> &Sequence {
+        &self.song.patterns[self.edit_pattern]
+    }
+
+    fn sequence_mut(&mut self) -> &mut Sequence {
+        &mut self.song.patterns[self.edit_pattern]
+    }
+
+    pub(crate) fn add_event(&mut self, event: Event) {
+        if is_valid_track(&event) {
+            self.sequence_mut().events.push(event);
+        }
+    }
+
+    pub(crate) fn add_events(&mut self, events: Vec<Event>) {
+        self.sequence_mut()
+            .events
+            .extend(events.into_iter().filter(is_valid_track));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.sequence_mut().events.clear();
+    }
+
+    pub(crate) fn stage_events(&mut self, events: Vec<Event>) {
+        self.staged
+            .extend(events.into_iter().filter(is_valid_track));
+    }
+
+    /// Replace the events of the edited pattern with the staged ones, at the
+    /// next loop boundary so the pattern is never heard half replaced, or
+    /// right away
+    pub(crate) fn commit_staged(&mut self, immediately: bool) {
+        let events = std::mem::take(&mut self.staged);
+        self.pending_swap = Some((self.edit_pattern, events));
+        if immediately {
+            self.swap_staged();
+        }
+    }
+
+    /// Swap the events of the edited pattern with `events`, dropping those
+    /// for tracks that don't exist
+    pub(crate) fn swap_events(&mut self, events: &mut Vec<Event>) {
+        events.retain(is_valid_track);
+        std::mem::swap(&mut self.sequence_mut().events, events);
+    }
+
+    fn swap_staged(&mut self) {
+        if let Some((pattern, events)) = self.pending_swap.take() {
+            self.song.patterns[pattern].events = events;
+        }
+    }
+
+    /// Add an empty pattern and return its index
+    pub fn create_pattern(&mut self, name: &str, length: f32) -> usize {
+        self.song.patterns.push(Sequence::new(name, length));
+        self.song.patterns.len() - 1
+    }
+
+    /// Select the pattern that event edits are applied to
+    pub fn set_edit_pattern(&mut self, pattern: usize) {
+        if pattern < self.song.patterns.len() {
+            self.edit_pattern = pattern;
+        }
+    }
+
+    /// Switch to a pattern at the next loop boundary
+    pub fn queue_pattern(&mut self, pattern: usize) {
+        if pattern < self.song.patterns.len() {
+            self.song.queued = Some(pattern);
+        }
+    }
+
+    /// Append an entry to the song chain, the chain starts from its first
+    /// entry at the next loop boundary after it was (re)built
+    pub fn append_chain(&mut self, entry: ChainEntry) {
+        if entry.pattern >= self.song.patterns.len() {
+            return;
+        }
+        if self.song.chain.is_empty() {
+            self.song.chain_pending = true;
+        }
+        self.song.chain.push(ChainEntry {
+            repeats: entry.repeats.max(1),
+            ..entry
+        });
+    }
+
+    pub fn clear_chain(&mut self) {
+        self.song.chain.clear();
+        self.song.chain_index = 0;
+        self.song.repeat_count = 0;
+        self.song.chain_pending = false;
+    }
+
+    /// Start playback at `sample_time` from the transport position
+    pub fn start(&mut self, sample_time: i64, tempo: f32) {
+        if let Some(beats) = self.pending_position.take() {
+            self.position = beats;
+        }
+        self.finished = false;
+        self.locate(sample_time, self.position, tempo);
+    }
+
+    /// Move the transport to a position in beats within the playing pattern,
+    /// applied from the next processed buffer
+    pub fn set_position_beats(&mut self, beats: f32) {
+        self.pending_position = Some(beats.max(0.0));
+        self.finished = false;
+    }
+
+    /// Go back to the start of the loop markers, or of the pattern, and to
+    /// the first entry of the chain
+    pub(crate) fn rewind(&mut self) {
+        self.song.rewind();
+        self.pattern_loop = 0;
+        self.set_position_beats(self.loop_markers.map_or(0.0, |(start, _)| start));
+    }
+
+    /// Drop the scheduled events, returning the note offs that were still to
+    /// come as samples after `sample_time`, with their track and pitch
+    pub(crate) fn flush(
+        &mut self,
+        sample_time: i64,
+        tempo: f32,
+    ) -> impl Iterator<Item = (i64, u8, u8)> + '_ {
+        let length = self.pattern_length(self.song.current, tempo) as i64;
+        let (region_start, cycle) = self.loop_cycle(self.song.current, tempo);
+        let position =
+            region_start as i64 + (sample_time - self.loop_start).rem_euclid(cycle.max(1) as i64);
+        self.scheduled_events
+            .drain(..)
+            .filter_map(move |ev| match ev {
+                ScheduledEvent::NoteOff { time, pitch, track } => {
+                    Some(((time as i64 - position).rem_euclid(length), track, pitch))
+                }
+                ScheduledEvent::NoteOn { .. } => None,
+            })
+    }
+
+    /// Position in beats within the playing pattern at the last processed buffer
+    pub fn position_beats(&self) -> f32 {
+        self.position
+    }
+
+    /// Restrict playback to the `start..end` range in beats, an empty range
+    /// plays the whole pattern
+    pub fn set_loop_markers(&mut self, start: f32, end: f32) {
+        self.loop_markers = if end > start {
+            Some((start.max(0.0), end))
+        } else {
+            None
+        };
+    }
+
+    /// Play the loop region once instead of repeating it
+    pub fn set_one_shot(&mut self, one_shot: bool) {
+        self.one_shot = one_shot;
+    }
 
-impl Sequencer {
-    pub fn new(length: f32, sample_rate: f32) -> Self {
-        Sequencer {
-            sequence: Sequence {
-                events: Vec::new(),
-                length,
-            },
-            scheduled_events: Vec::new(),
-            sample_rate,
+    /// Whether one-shot playback reached the end of the loop region
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn set_recording(&mut self, enabled: bool) {
+        self.recording = enabled;
+        if !enabled {
+            self.recording_notes.clear();
         }
     }
 
-    pub fn process(
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Set the record quantize grid in beats, 0 disables quantization
+    pub fn set_record_quantize(&mut self, grid: f32) {
+        self.record_quantize = grid.max(0.0);
+    }
+
+    /// Position in beats within the playing pattern at `sample_time`
+    pub fn pattern_position(&self, sample_time: i64, tempo: f32) -> f32 {
+        let (region_start, cycle) = self.loop_cycle(self.song.current, tempo);
+        let offset = (sample_time - self.loop_start).rem_euclid(cycle as i64);
+        self.sample_to_beat(region_start as i64 + offset, tempo)
+    }
+
+    /// Capture a live note into the playing pattern, its duration is set
+    /// when the matching note off is recorded
+    pub fn record_note_on(
         &mut self,
-        events: &mut HashMap<usize, Vec<ScheduledEvent>>,
+        id: u32,
+        track: u8,
+        pitch: u8,
+        velocity: u8,
         sample_time: i64,
         tempo: f32,
-        num_frames: i32,
     ) {
-        let length = self.beat_to_sample(self.sequence.length, tempo);
-        let buffer_start = (sample_time % length as i64) as i32;
-        let buffer_end = buffer_start as i32 + num_frames;
+        if !self.recording {
+            return;
+        }
+        let length = self.song.patterns[self.song.current].length;
+        let mut beat_time = self.pattern_position(sample_time, tempo);
+        if self.record_quantize > 0.0 {
+            beat_time = ((beat_time / self.record_quantize).round() * self.record_quantize)
+                .rem_euclid(length);
+        }
+        self.song.patterns[self.song.current].events.push(Event {
+            id,
+            beat_time,
+            pitch,
+            velocity,
+            track,
+            duration: self.record_quantize.max(MIN_RECORD_DURATION),
+            ..Default::default()
+        });
+        self.recording_notes.push(RecordingNote {
+            id,
+            track,
+            pitch,
+            beat_time,
+        });
+    }
 
-        let beat_time = self.sample_to_beat(sample_time % length as i64, tempo);
-        Self::update_playback_progress(beat_time);
+    pub fn record_note_off(&mut self, track: u8, pitch: u8, sample_time: i64, tempo: f32) {
+        let Some(index) = self
+            .recording_notes
+            .iter()
+            .position(|n| n.track == track && n.pitch == pitch)
+        else {
+            return;
+        };
+        let note = self.recording_notes.remove(index);
+        let length = self.song.patterns[self.song.current].length;
+        let mut duration =
+            (self.pattern_position(sample_time, tempo) - note.beat_time).rem_euclid(length);
+        if self.record_quantize > 0.0 {
+            duration = (duration / self.record_quantize).round() * self.record_quantize;
+        }
+        let duration = duration.max(self.record_quantize).max(MIN_RECORD_DURATION);
+        for sequence in self.song.patterns.iter_mut() {
+            if let Some(event) = sequence.events.iter_mut().find(|ev| ev.id == note.id) {
+                event.duration = duration;
+            }
+        }
+    }
 
-        for ev in &self.sequence.events {
-            let mut event_time = self.beat_to_sample(ev.beat_time, tempo);
-            let mut is_in_buffer = Self::is_in_buffer(event_time, buffer_start, buffer_end);
+    pub fn current_pattern(&self) -> usize {
+        self.song.current
+    }
 
-            // check if event loops around (ie, is in beginning of next buffer)
-            if Self::loops_around(event_time, buffer_end, length) {
-                is_in_buffer = true;
-                event_time += length - buffer_start;
-            }
+    pub fn pattern_name(&self, pattern: usize) -> Option<&str> {
+        self.song.patterns.get(pattern).map(|p| p.name.as_str())
+    }
 
-            if is_in_buffer {
-                let note_on = ScheduledEvent::NoteOn {
-                    time: event_time,
-                    pitch: ev.pitch,
-                    velocity: ev.velocity,
-                    track: ev.track,
-                };
-                // TODO: stop already playing notes at same pitch
-                self.scheduled_events.push(note_on);
-
-                let duration = self.beat_to_sample(ev.duration, tempo);
-                let note_off = ScheduledEvent::NoteOff {
-                    time: (event_time + duration) % length,
-                    pitch: ev.pitch,
-                    track: ev.track,
-                };
-
-                self.scheduled_events.push(note_off);
-            }
-        }
+    /// Set a pattern's time signature, keeping its number of bars
+    pub fn set_time_signature(&mut self, pattern: usize, time_signature: TimeSignature) {
+        let Some(sequence) = self.song.patterns.get_mut(pattern) else {
+            return;
+        };
+        let bars = (sequence.length / sequence.time_signature.bar_length())
+            .ceil()
+            .max(1.0);
+        sequence.length = bars * time_signature.bar_length();
+        sequence.time_signature = time_signature;
+    }
 
-        for frame_offset in 0..num_frames {
-            let mut to_remove = Vec::new();
+    /// Time signature of the playing pattern
+    pub fn time_signature(&self) -> TimeSignature {
+        self.song.patterns[self.song.current].time_signature
+    }
 
-            for (index, ev) in self.scheduled_events.iter().enumerate() {
-                let event_time = match *ev {
-                    ScheduledEvent::NoteOn { time, .. } | ScheduledEvent::NoteOff { time, .. } => {
-                        time
-                    }
-                };
+    /// Transport position in bars, beats and ticks of the playing pattern's
+    /// time signature
+    pub fn bar_beat_tick(&self) -> BarBeatTick {
+        self.time_signature().bar_beat_tick(self.position)
+    }
 
-                if event_time == (buffer_start + frame_offset) % length {
-                    if !events.contains_key(&(frame_offset as usize)) {
-                        events.insert(frame_offset as usize, vec![(*ev).clone()]);
-                    } else {
-                        events
-                            .get_mut(&(frame_offset as usize))
-                            .unwrap()
-                            .push((*ev).clone());
-                    }
-                    to_remove.push(index);
-                }
-            }
+    /// Set swing for a track, 0.0 is straight, 1.0 delays off-beats by half a step
+    pub fn set_swing(&mut self, track: u8, amount: f32) {
+        if let Some(swing) = self.swing.get_mut(track as usize) {
+            *swing = amount.clamp(0.0, 1.0);
+        }
+    }
 
-            for index in to_remove.iter().rev() {
-                self.scheduled_events.swap_remove(*index);
-            }
+    /// Set the timing and velocity variation of a track's notes
+    pub fn set_humanize(&mut self, track: u8, humanize: Humanize) {
+        if let Some(h) = self.humanize.get_mut(track as usize) {
+            *h = Humanize {
+                timing_ms: humanize.timing_ms.max(0.0),
+                velocity: humanize.velocity.clamp(0.0, 1.0),
+                ..humanize
+            };
         }
     }
 
-    fn update_playback_progress(progress: f32) {
-        if let Some(callback) = *PROGRESS_CALLBACK.lock().unwrap() {
-            callback(progress);
+    pub fn set_mutation(&mut self, mutation: Mutation) {
+        self.mutation = mutation;
+        self.mutation_bars = 0.0;
+    }
+
+    /// Whether the playing pattern is due for its automatic mutation,
+    /// clearing the flag
+    pub(crate) fn take_mutation_due(&mut self) -> bool {
+        std::mem::take(&mut self.mutation_due)
+    }
+
+    fn count_mutation_bars(&mut self, samples: i64, tempo: f32) {
+        if self.mutation.every_bars == 0 {
+            return;
+        }
+        let bar_length = self.time_signature().bar_length();
+        self.mutation_bars += self.sample_to_beat(samples, tempo) / bar_length;
+        if self.mutation_bars >= self.mutation.every_bars as f32 - 1e-3 {
+            self.mutation_bars = 0.0;
+            self.mutation_due = true;
         }
     }
 
-    pub fn beat_to_sample(&self, beat_time: f32, tempo: f32) -> i32 {
-        (beat_time / tempo * 60.0 * self.sample_rate as f32) as i32
+    /// Mutate a pattern, moving pitches along the tracks' scales
+    pub(crate) fn mutate(
+        &mut self,
+        pattern: usize,
+        scales: &[Option<ScaleQuantizer>; TRACK_COUNT],
+    ) {
+        let Some(sequence) = self.song.patterns.get_mut(pattern) else {
+            return;
+        };
+        let mut rng = match self.mutation.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::seed_from_u64(self.rng.gen()),
+        };
+        mutate_events(
+            &mut sequence.events,
+            self.mutation.amount,
+            sequence.length,
+            self.swing_step,
+            scales,
+            &mut rng,
+        );
     }
 
-    pub fn sample_to_beat(&self, sample_time: i64, tempo: f32) -> f32 {
-        sample_time as f32 / self.sample_rate as f32 * tempo / 60.0
+    pub fn edit_pattern(&self) -> usize {
+        self.edit_pattern
     }
 
-    fn is_in_buffer(time: i32, buffer_start: i32, buffer_end: i32) -> bool {
-        time >= buffer_start && time < buffer_end
+    /// Set the swing grid in beats, e.g. 0.5 for 8th notes or 0.25 for 16ths
+    pub fn set_swing_step(&mut self, step: f32) {
+        if step > 0.0 {
+            self.swing_step = step;
+        }
     }
 
-    fn loops_around(time: i32, buffer_end: i32, length: i32) -> bool {
-        buffer_end > length && time <= (buffer_end % length)
+    /// Replace the event with the same id, keeping its parameter locks,
+    /// which are edited with `set_event_lock`. Notes already triggered by
+    /// the old version still receive their note off
+    pub(crate) fn update_event(&mut self, event: Event) {
+        if !is_valid_track(&event) {
+            return;
+        }
+        if let Some(ev) = self
+            .sequence_mut()
+            .events
+            .iter_mut()
+            .find(|ev| ev.id == event.id)
+        {
+            *ev = Event {
+                locks: ev.locks,
+                ..event
+            };
+        }
     }
 
-    pub(crate) fn add_event(&mut self, event: Event) {
-        self.sequence.events.push(event);
+    pub(crate) fn remove_event(&mut self, id: u32) {
+        self.sequence_mut().events.retain(|ev| ev.id != id);
     }
 
-    pub(crate) fn clear(&mut self) {
-        self.sequence.events.clear();
+    pub(crate) fn set_event_nudge(&mut self, id: u32, nudge_ms: f32) {
+        if let Some(ev) = self.sequence_mut().events.iter_mut().find(|ev| ev.id == id) {
+            ev.nudge_ms = nudge_ms;
+        }
+    }
+
+    /// Lock a parameter for the event's step, or unlock it with None
+    pub(crate) fn set_event_lock(&mut self, id: u32, parameter: i8, value: Option<f32>) {
+        if let Some(ev) = self.sequence_mut().events.iter_mut().find(|ev| ev.id == id) {
+            match value {
+                Some(value) => {
+                    ev.locks.set(parameter, value);
+                }
+                None => ev.locks.remove(parameter),
+            }
+        }
+    }
+
+    pub(crate) fn events(&self) -> &[Event] {
+        &self.sequence().events
     }
 }
 
@@ -166,13 +1721,46 @@ impl Sequencer {
 mod tests {
     use super::*;
 
+    #[test]
+    fn event_buffer_groups_events_by_frame() {
+        let note_off = |pitch| ScheduledEvent::NoteOff {
+            time: 0,
+            pitch,
+            track: 0,
+        };
+        let mut events = EventBuffer::new();
+        events.push(0, note_off(60));
+        events.push(3, note_off(61));
+        events.push(3, note_off(62));
+        events.delay(2);
+
+        assert!(events.get(&0).is_none());
+        assert_eq!(events.get(&2).unwrap().len(), 1);
+        let pitches: Vec<u8> = events
+            .get(&5)
+            .unwrap()
+            .iter()
+            .map(|ev| match ev {
+                ScheduledEvent::NoteOff { pitch, .. } => *pitch,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(pitches, [61, 62]);
+
+        // clearing keeps the preallocated storage
+        let capacity = events.events.capacity();
+        events.clear();
+        assert!(events.is_empty());
+        assert_eq!(events.events.capacity(), capacity);
+    }
+
     #[test]
     fn new_creates_sequencer() {
         // let (_, rx) = channel::unbounded();
         let sample_rate = 48000.0;
         let sequencer = Sequencer::new(4., sample_rate);
-        assert_eq!(sequencer.sequence.events.len(), 0);
-        assert_eq!(sequencer.sequence.length, 4.);
+        assert_eq!(sequencer.sequence().events.len(), 0);
+        assert_eq!(sequencer.sequence().length, 4.);
     }
 
     #[test]
@@ -191,18 +1779,19 @@ mod tests {
             param1: 0.0,
             param2: 0.0,
             duration,
+            ..Default::default()
         };
         sequencer.add_event(event);
 
         // process one block to move event to scheduled events
-        sequencer.process(&mut HashMap::new(), 0, tempo, 1);
-        assert_eq!(sequencer.sequence.events.len(), 1);
-        assert_eq!(sequencer.sequence.events[0].beat_time, beat_time);
-        assert_eq!(sequencer.sequence.events[0].pitch, 60);
-        assert_eq!(sequencer.sequence.events[0].velocity, 100);
-        assert_eq!(sequencer.sequence.events[0].param1, 0.0);
-        assert_eq!(sequencer.sequence.events[0].param2, 0.0);
-        assert_eq!(sequencer.sequence.events[0].duration, duration);
+        sequencer.process(&mut EventBuffer::new(), 0, tempo, 1);
+        assert_eq!(sequencer.sequence().events.len(), 1);
+        assert_eq!(sequencer.sequence().events[0].beat_time, beat_time);
+        assert_eq!(sequencer.sequence().events[0].pitch, 60);
+        assert_eq!(sequencer.sequence().events[0].velocity, 100);
+        assert_eq!(sequencer.sequence().events[0].param1, 0.0);
+        assert_eq!(sequencer.sequence().events[0].param2, 0.0);
+        assert_eq!(sequencer.sequence().events[0].duration, duration);
     }
 
     #[test]
@@ -222,6 +1811,7 @@ mod tests {
             param1: 0.0,
             param2: 0.0,
             duration,
+            ..Default::default()
         };
         sequencer.add_event(ev1);
 
@@ -233,25 +1823,26 @@ mod tests {
             param1: 0.0,
             param2: 0.0,
             duration,
+            ..Default::default()
         };
         sequencer.add_event(ev2);
 
         // process one block to move event to scheduled events
-        sequencer.process(&mut HashMap::new(), 0, tempo, 1);
-        assert_eq!(sequencer.sequence.events.len(), 2);
-        assert_eq!(sequencer.sequence.events[0].beat_time, beat_time);
-        assert_eq!(sequencer.sequence.events[0].pitch, 60);
-        assert_eq!(sequencer.sequence.events[0].velocity, 100);
-        assert_eq!(sequencer.sequence.events[0].param1, 0.0);
-        assert_eq!(sequencer.sequence.events[0].param2, 0.0);
-        assert_eq!(sequencer.sequence.events[0].duration, duration);
-
-        assert_eq!(sequencer.sequence.events[1].beat_time, beat_time);
-        assert_eq!(sequencer.sequence.events[1].pitch, 67);
-        assert_eq!(sequencer.sequence.events[1].velocity, 100);
-        assert_eq!(sequencer.sequence.events[1].param1, 0.0);
-        assert_eq!(sequencer.sequence.events[1].param2, 0.0);
-        assert_eq!(sequencer.sequence.events[1].duration, duration);
+        sequencer.process(&mut EventBuffer::new(), 0, tempo, 1);
+        assert_eq!(sequencer.sequence().events.len(), 2);
+        assert_eq!(sequencer.sequence().events[0].beat_time, beat_time);
+        assert_eq!(sequencer.sequence().events[0].pitch, 60);
+        assert_eq!(sequencer.sequence().events[0].velocity, 100);
+        assert_eq!(sequencer.sequence().events[0].param1, 0.0);
+        assert_eq!(sequencer.sequence().events[0].param2, 0.0);
+        assert_eq!(sequencer.sequence().events[0].duration, duration);
+
+        assert_eq!(sequencer.sequence().events[1].beat_time, beat_time);
+        assert_eq!(sequencer.sequence().events[1].pitch, 67);
+        assert_eq!(sequencer.sequence().events[1].velocity, 100);
+        assert_eq!(sequencer.sequence().events[1].param1, 0.0);
+        assert_eq!(sequencer.sequence().events[1].param2, 0.0);
+        assert_eq!(sequencer.sequence().events[1].duration, duration);
     }
 
     #[test]
@@ -270,18 +1861,19 @@ mod tests {
             param1: 0.0,
             param2: 0.0,
             duration,
+            ..Default::default()
         };
         sequencer.add_event(event);
 
         // process one block to move event to scheduled events
-        sequencer.process(&mut HashMap::new(), 0, tempo, 1);
+        sequencer.process(&mut EventBuffer::new(), 0, tempo, 1);
 
-        assert_eq!(sequencer.sequence.events.len(), 1);
+        assert_eq!(sequencer.sequence().events.len(), 1);
 
         // clear events
         sequencer.clear();
-        sequencer.process(&mut HashMap::new(), 0, tempo, 1);
-        assert_eq!(sequencer.sequence.events.len(), 0);
+        sequencer.process(&mut EventBuffer::new(), 0, tempo, 1);
+        assert_eq!(sequencer.sequence().events.len(), 0);
     }
 
     #[test]
@@ -302,11 +1894,12 @@ mod tests {
             param1: 0.0,
             param2: 0.0,
             duration,
+            ..Default::default()
         };
         sequencer.add_event(event);
 
         for i in 0..frame_count as usize {
-            let mut events = HashMap::new();
+            let mut events = EventBuffer::new();
             sequencer.process(&mut events, i as i64, tempo, 1);
             let sample_time = sequencer.beat_to_sample(beat_time, tempo);
             let duration_in_samples = sequencer.beat_to_sample(duration, tempo);
@@ -317,6 +1910,7 @@ mod tests {
                         pitch,
                         velocity,
                         track,
+                        ..
                     } => {
                         assert_eq!(pitch, 60);
                         assert_eq!(velocity, 100);
@@ -359,12 +1953,13 @@ mod tests {
                 param1: 0.0,
                 param2: 0.0,
                 duration: 1.0,
+                ..Default::default()
             };
             sequencer.add_event(event);
         }
 
         for i in 0..frame_count as usize {
-            let mut events = HashMap::new();
+            let mut events = EventBuffer::new();
             sequencer.process(&mut events, i as i64, tempo, 1);
             // check if we have a note on
             if let Some(ev) = events.get(&0) {
@@ -375,6 +1970,7 @@ mod tests {
                             pitch: _,
                             velocity: _,
                             track: _,
+                            ..
                         } => {
                             println!("time: {}", time);
                             assert_eq!(*time, i as i32);
@@ -385,4 +1981,586 @@ mod tests {
             }
         }
     }
+
+    fn note_on_frames(sequencer: &mut Sequencer, tempo: f32, frame_count: usize) -> Vec<usize> {
+        let mut frames = Vec::new();
+        for i in 0..frame_count {
+            let mut events = EventBuffer::new();
+            sequencer.process(&mut events, i as i64, tempo, 1);
+            if let Some(ev) = events.get(&0) {
+                for ev in ev.iter() {
+                    if let ScheduledEvent::NoteOn { .. } = ev {
+                        frames.push(i);
+                    }
+                }
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn swing_delays_off_beats() {
+        let length = 1.;
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(length, sample_rate);
+        sequencer.set_swing_step(0.5);
+        sequencer.set_swing(0, 0.5);
+
+        for i in 0..2 {
+            sequencer.add_event(Event {
+                beat_time: i as f32 * 0.5,
+                pitch: 60,
+                velocity: 100,
+                track: 0,
+                param1: 0.0,
+                param2: 0.0,
+                duration: 0.1,
+                ..Default::default()
+            });
+        }
+
+        let frame_count = 60.0 / tempo * length * sample_rate;
+        let frames = note_on_frames(&mut sequencer, tempo, frame_count as usize);
+        // the on-beat stays put, the off-beat moves by a quarter of a beat
+        assert_eq!(
+            frames,
+            vec![0, sequencer.beat_to_sample(0.625, tempo) as usize]
+        );
+    }
+
+    #[test]
+    fn swing_is_per_track() {
+        let sample_rate = 48000.0;
+        let mut sequencer = Sequencer::new(4., sample_rate);
+        sequencer.set_swing(1, 2.0);
+        let event = Event {
+            beat_time: 0.25,
+            pitch: 60,
+            velocity: 100,
+            track: 0,
+            param1: 0.0,
+            param2: 0.0,
+            duration: 0.1,
+            ..Default::default()
+        };
+        assert_eq!(sequencer.swing_beat_time(&event), 0.25);
+        let event = Event { track: 1, ..event };
+        // amount is clamped to 1.0
+        assert_eq!(sequencer.swing_beat_time(&event), 0.375);
+    }
+
+    #[test]
+    fn humanize_varies_timing_and_velocity() {
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        // note on frames and velocities over `loops` one beat loops
+        let play = |humanize: Humanize, loops: usize| {
+            let mut sequencer = Sequencer::new(1., sample_rate);
+            sequencer.set_humanize(0, humanize);
+            for id in 0..4 {
+                sequencer.add_event(Event {
+                    id,
+                    beat_time: id as f32 * 0.25,
+                    duration: 0.1,
+                    ..Default::default()
+                });
+            }
+            let block = 500;
+            let mut notes = Vec::new();
+            for start in (0..24000 * loops).step_by(block) {
+                let mut events = EventBuffer::new();
+                sequencer.process(&mut events, start as i64, tempo, block as i32);
+                for frame in 0..block {
+                    for ev in events.get(&frame).unwrap_or_default() {
+                        if let ScheduledEvent::NoteOn { velocity, .. } = ev {
+                            notes.push(((start + frame) % 24000, *velocity));
+                        }
+                    }
+                }
+            }
+            notes
+        };
+
+        let humanize = Humanize {
+            timing_ms: 10.0,
+            velocity: 0.2,
+            repeat: false,
+        };
+        let notes = play(humanize, 2);
+        assert_eq!(notes.len(), 8);
+        for &(frame, velocity) in notes.iter() {
+            // a downbeat pushed early in the first loop comes before the
+            // start and is dropped, so compare with the nearest step
+            let offset = (frame as i64 + 3000) % 6000 - 3000;
+            assert!(offset.abs() <= 480);
+            assert!((80..=120).contains(&velocity));
+        }
+        assert!(notes.iter().any(|&(_, velocity)| velocity != 100));
+        // every loop varies anew
+        assert_ne!(notes[..4], notes[4..]);
+
+        let notes = play(
+            Humanize {
+                repeat: true,
+                ..humanize
+            },
+            2,
+        );
+        assert_eq!(notes[..4], notes[4..]);
+
+        let notes = play(Humanize::default(), 1);
+        assert_eq!(notes, [(0, 100), (6000, 100), (12000, 100), (18000, 100)]);
+    }
+
+    #[test]
+    fn trig_conditions() {
+        assert!(TrigCondition::Always.is_met(3));
+        assert!(TrigCondition::FirstLoop.is_met(0));
+        assert!(!TrigCondition::FirstLoop.is_met(1));
+        assert!(!TrigCondition::NotFirstLoop.is_met(0));
+        assert!(TrigCondition::NotFirstLoop.is_met(1));
+
+        // 2:4 plays on the second of every four loops
+        let ratio = TrigCondition::from_u8(1, 2, 4);
+        let played: Vec<bool> = (0..8).map(|i| ratio.is_met(i)).collect();
+        assert_eq!(
+            played,
+            vec![false, true, false, false, false, true, false, false]
+        );
+
+        // a ratio without a loop count falls back to always
+        assert_eq!(TrigCondition::from_u8(1, 1, 0), TrigCondition::Always);
+    }
+
+    #[test]
+    fn conditions_and_probability_filter_events() {
+        let length = 1.;
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(length, sample_rate);
+        sequencer.add_event(Event {
+            beat_time: 0.0,
+            condition: TrigCondition::Ratio { a: 2, b: 2 },
+            ..Default::default()
+        });
+        sequencer.add_event(Event {
+            beat_time: 0.5,
+            probability: 0.0,
+            ..Default::default()
+        });
+
+        // three loops, only the second one plays the first event
+        let frame_count = 60.0 / tempo * length * sample_rate;
+        let frames = note_on_frames(&mut sequencer, tempo, 3 * frame_count as usize);
+        assert_eq!(frames, vec![frame_count as usize]);
+    }
+
+    #[test]
+    fn nudge_shifts_events() {
+        let length = 1.;
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(length, sample_rate);
+        sequencer.add_event(Event {
+            beat_time: 0.5,
+            nudge_ms: 10.0,
+            ..Default::default()
+        });
+        // nudging the first beat early wraps around to the end of the loop
+        sequencer.add_event(Event {
+            beat_time: 0.0,
+            nudge_ms: -10.0,
+            ..Default::default()
+        });
+
+        let frame_count = (60.0 / tempo * length * sample_rate) as usize;
+        let frames = note_on_frames(&mut sequencer, tempo, frame_count);
+        let half = sequencer.beat_to_sample(0.5, tempo) as usize;
+        assert_eq!(frames, vec![half + 480, frame_count - 480]);
+    }
+
+    #[test]
+    fn set_event_nudge_by_id() {
+        let mut sequencer = Sequencer::new(4., 48000.0);
+        sequencer.add_event(Event {
+            id: 1,
+            ..Default::default()
+        });
+        sequencer.add_event(Event {
+            id: 2,
+            ..Default::default()
+        });
+        sequencer.set_event_nudge(2, 5.0);
+        assert_eq!(sequencer.events()[0].nudge_ms, 0.0);
+        assert_eq!(sequencer.events()[1].nudge_ms, 5.0);
+    }
+
+    #[test]
+    fn event_locks_are_edited_by_id() {
+        let mut sequencer = Sequencer::new(4., 48000.0);
+        sequencer.add_event(Event {
+            id: 1,
+            ..Default::default()
+        });
+        sequencer.set_event_lock(1, 4, Some(0.25));
+        sequencer.set_event_lock(1, 0, Some(800.0));
+        sequencer.set_event_lock(1, 4, Some(0.75));
+        let locks = sequencer.events()[0].locks;
+        assert_eq!(
+            locks.iter().collect::<Vec<_>>(),
+            vec![(4, 0.75), (0, 800.0)]
+        );
+
+        // updating the event keeps its locks
+        sequencer.update_event(Event {
+            id: 1,
+            pitch: 72,
+            ..Default::default()
+        });
+        assert_eq!(sequencer.events()[0].locks.get(0), Some(800.0));
+
+        sequencer.set_event_lock(1, 4, None);
+        assert_eq!(sequencer.events()[0].locks.get(4), None);
+        assert_eq!(sequencer.events()[0].locks.get(0), Some(800.0));
+
+        let mut full = ParameterLocks::default();
+        for parameter in 0..MAX_PARAMETER_LOCKS as i8 {
+            assert!(full.set(parameter, 1.0));
+        }
+        assert!(!full.set(MAX_PARAMETER_LOCKS as i8, 1.0));
+        assert!(full.set(0, 2.0));
+
+        // saved as a list of parameter, value pairs
+        let json = serde_json::to_string(&locks).unwrap();
+        assert_eq!(json, "[[4,0.75],[0,800.0]]");
+        assert_eq!(
+            serde_json::from_str::<ParameterLocks>(&json).unwrap(),
+            locks
+        );
+    }
+
+    #[test]
+    fn update_and_remove_events() {
+        let mut sequencer = Sequencer::new(4., 48000.0);
+        for id in 1..=3 {
+            sequencer.add_event(Event {
+                id,
+                beat_time: id as f32,
+                ..Default::default()
+            });
+        }
+
+        sequencer.update_event(Event {
+            id: 2,
+            beat_time: 2.5,
+            pitch: 72,
+            ..Default::default()
+        });
+        assert_eq!(sequencer.events()[1].beat_time, 2.5);
+        assert_eq!(sequencer.events()[1].pitch, 72);
+
+        sequencer.remove_event(1);
+        let ids: Vec<u32> = sequencer.events().iter().map(|ev| ev.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+
+        // unknown ids are ignored
+        sequencer.remove_event(42);
+        sequencer.update_event(Event {
+            id: 42,
+            ..Default::default()
+        });
+        assert_eq!(sequencer.events().len(), 2);
+    }
+
+    #[test]
+    fn retrigger_fires_ratchets() {
+        let length = 1.;
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(length, sample_rate);
+        sequencer.add_event(Event {
+            beat_time: 0.0,
+            duration: 0.5,
+            retrigger_count: 4,
+            ..Default::default()
+        });
+        sequencer.add_event(Event {
+            beat_time: 0.5,
+            retrigger_count: 2,
+            retrigger_rate: 0.25,
+            ..Default::default()
+        });
+
+        let frame_count = (60.0 / tempo * length * sample_rate) as usize;
+        let frames = note_on_frames(&mut sequencer, tempo, frame_count);
+        let step = sequencer.beat_to_sample(0.125, tempo) as usize;
+        // four hits spread over the first half beat, two hits a 16th apart
+        assert_eq!(
+            frames,
+            vec![0, step, 2 * step, 3 * step, 4 * step, 6 * step]
+        );
+    }
+
+    fn current_patterns(
+        sequencer: &mut Sequencer,
+        tempo: f32,
+        block_size: usize,
+        frame_count: usize,
+    ) -> Vec<usize> {
+        (0..frame_count / block_size)
+            .map(|i| {
+                let mut events = EventBuffer::new();
+                sequencer.process(
+                    &mut events,
+                    (i * block_size) as i64,
+                    tempo,
+                    block_size as i32,
+                );
+                sequencer.current_pattern()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queued_pattern_switches_at_loop_boundary() {
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(1., sample_rate);
+        let b = sequencer.create_pattern("B", 2.);
+        assert_eq!(sequencer.pattern_name(b), Some("B"));
+
+        let loop_length = sequencer.beat_to_sample(1., tempo) as usize;
+        current_patterns(&mut sequencer, tempo, 1, loop_length / 2);
+        sequencer.queue_pattern(b);
+        let mut events = EventBuffer::new();
+        sequencer.process(&mut events, (loop_length - 1) as i64, tempo, 1);
+        assert_eq!(sequencer.current_pattern(), 0);
+        sequencer.process(&mut events, loop_length as i64, tempo, 1);
+        assert_eq!(sequencer.current_pattern(), b);
+    }
+
+    #[test]
+    fn chain_plays_patterns_with_repeats() {
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(1., sample_rate);
+        let b = sequencer.create_pattern("B", 1.);
+        sequencer.append_chain(ChainEntry {
+            pattern: 0,
+            repeats: 2,
+        });
+        sequencer.append_chain(ChainEntry {
+            pattern: b,
+            repeats: 1,
+        });
+
+        let block_size = 1000;
+        let loop_length = sequencer.beat_to_sample(1., tempo) as usize;
+        let patterns = current_patterns(&mut sequencer, tempo, block_size, 6 * loop_length);
+        let per_loop: Vec<usize> = patterns
+            .chunks(loop_length / block_size)
+            .map(|loop_patterns| loop_patterns[0])
+            .collect();
+        // the chain starts after the first loop, then 0, 0, B repeated
+        assert_eq!(per_loop, vec![0, 0, 0, b, 0, 0]);
+    }
+
+    #[test]
+    fn events_of_next_pattern_play_when_buffer_crosses_boundary() {
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(1., sample_rate);
+        let b = sequencer.create_pattern("B", 1.);
+        sequencer.set_edit_pattern(b);
+        sequencer.add_event(Event {
+            beat_time: 0.0,
+            pitch: 72,
+            ..Default::default()
+        });
+        sequencer.queue_pattern(b);
+
+        let loop_length = sequencer.beat_to_sample(1., tempo) as i64;
+        let mut events = EventBuffer::new();
+        sequencer.process(&mut events, loop_length - 10, tempo, 20);
+        match events.get(&10).map(|ev| &ev[0]) {
+            Some(ScheduledEvent::NoteOn { pitch, .. }) => assert_eq!(*pitch, 72),
+            _ => panic!("expected note on at the start of the next pattern"),
+        }
+    }
+
+    #[test]
+    fn committed_patterns_swap_at_the_loop_boundary() {
+        let sample_rate = 48000.0;
+        let tempo: f32 = 120.0;
+        let mut sequencer = Sequencer::new(1., sample_rate);
+        let note = |pitch| Event {
+            beat_time: 0.0,
+            pitch,
+            duration: 0.5,
+            ..Default::default()
+        };
+        sequencer.add_event(note(60));
+        sequencer.stage_events(vec![note(72)]);
+        assert_eq!(sequencer.events().len(), 1);
+
+        let loop_length = sequencer.beat_to_sample(1., tempo) as i64;
+        let pitch_at = |events: &EventBuffer, frame| match events.get(&frame).map(|ev| &ev[0]) {
+            Some(ScheduledEvent::NoteOn { pitch, .. }) => *pitch,
+            _ => panic!("expected a note on"),
+        };
+        let mut events = EventBuffer::new();
+        sequencer.process(&mut events, 0, tempo, 10);
+        sequencer.commit_staged(false);
+        assert_eq!(pitch_at(&events, 0), 60);
+        assert_eq!(sequencer.events()[0].pitch, 60);
+
+        let mut events = EventBuffer::new();
+        sequencer.process(&mut events, loop_length - 10, tempo, 20);
+        assert_eq!(pitch_at(&events, 10), 72);
+        assert_eq!(sequencer.events().len(), 1);
+
+        sequencer.stage_events(vec![note(48)]);
+        sequencer.commit_staged(true);
+        assert_eq!(sequencer.events()[0].pitch, 48);
+    }
+
+    #[test]
+    fn record_captures_unquantized_notes_only_when_enabled() {
+        let sample_rate = 48000.0;
+        let tempo = 120.0;
+        let mut sequencer = Sequencer::new(4., sample_rate);
+        sequencer.record_note_on(1, 0, 60, 100, 12000, tempo);
+        assert!(sequencer.events().is_empty());
+
+        sequencer.set_recording(true);
+        // one loop later, the position wraps around into the pattern
+        sequencer.record_note_on(2, 0, 60, 100, 96000 + 12000, tempo);
+        sequencer.record_note_off(0, 60, 96000 + 36000, tempo);
+        let events = sequencer.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, 2);
+        assert_eq!(events[0].beat_time, 0.5);
+        assert_eq!(events[0].duration, 1.0);
+    }
+
+    fn add_quarter_notes(sequencer: &mut Sequencer) {
+        for beat in 0..4 {
+            sequencer.add_event(Event {
+                id: beat + 1,
+                beat_time: beat as f32,
+                duration: 0.5,
+                ..Default::default()
+            });
+        }
+    }
+
+    #[test]
+    fn loop_markers_restrict_playback() {
+        // one beat is 1000 samples
+        let sample_rate = 1000.0;
+        let tempo: f32 = 60.0;
+        let mut sequencer = Sequencer::new(4., sample_rate);
+        add_quarter_notes(&mut sequencer);
+        sequencer.set_loop_markers(1.0, 3.0);
+
+        assert_eq!(
+            note_on_frames(&mut sequencer, tempo, 4000),
+            vec![0, 1000, 2000, 3000]
+        );
+        assert_eq!(sequencer.position_beats(), 2.999);
+    }
+
+    #[test]
+    fn note_offs_past_the_loop_end_wrap_around() {
+        let sample_rate = 1000.0;
+        let tempo: f32 = 60.0;
+        let mut sequencer = Sequencer::new(4., sample_rate);
+        sequencer.add_event(Event {
+            id: 1,
+            beat_time: 1.5,
+            duration: 1.0,
+            ..Default::default()
+        });
+        sequencer.set_loop_markers(0.0, 2.0);
+
+        let (mut note_ons, mut note_offs) = (0, 0);
+        for i in 0..16000 {
+            let mut events = EventBuffer::new();
+            sequencer.process(&mut events, i, tempo, 1);
+            for ev in events.get(&0).into_iter().flatten() {
+                match ev {
+                    ScheduledEvent::NoteOn { .. } => note_ons += 1,
+                    ScheduledEvent::NoteOff { .. } => note_offs += 1,
+                }
+            }
+        }
+        // the last note off is due in the next loop
+        assert_eq!((note_ons, note_offs), (8, 7));
+        assert_eq!(sequencer.scheduled_events.len(), 1);
+
+        // moving the loop away from a pending note off releases it
+        sequencer.set_loop_markers(2.0, 4.0);
+        let mut events = EventBuffer::new();
+        sequencer.process(&mut events, 16000, tempo, 1);
+        sequencer.process(&mut events, 16001, tempo, 1);
+        assert!(sequencer.scheduled_events.is_empty());
+    }
+
+    #[test]
+    fn set_position_moves_playhead() {
+        let sample_rate = 1000.0;
+        let tempo: f32 = 60.0;
+        let mut sequencer = Sequencer::new(4., sample_rate);
+        add_quarter_notes(&mut sequencer);
+        sequencer.set_position_beats(2.5);
+
+        // beats 3 and 0 follow
+        assert_eq!(note_on_frames(&mut sequencer, tempo, 2000), vec![500, 1500]);
+    }
+
+    #[test]
+    fn time_signature_bar_beat_tick() {
+        assert!(TimeSignature::new(0, 4).is_none());
+        assert!(TimeSignature::new(4, 3).is_none());
+
+        let six_eight = TimeSignature::new(6, 8).unwrap();
+        assert_eq!(six_eight.bar_length(), 3.0);
+        // 3.75 quarter notes is the 8th eighth note (index 7), half way through
+        assert_eq!(
+            six_eight.bar_beat_tick(3.75),
+            BarBeatTick {
+                bar: 1,
+                beat: 1,
+                tick: TICKS_PER_BEAT / 2,
+            }
+        );
+    }
+
+    #[test]
+    fn note_division_lengths() {
+        let quarter = NoteDivision::from_index(6).unwrap();
+        assert_eq!(quarter.denominator, 4);
+        assert_eq!(quarter.beats(), 1.0);
+
+        let dotted_eighth = NoteDivision::from_index(10).unwrap();
+        assert_eq!(dotted_eighth.modifier, NoteModifier::Dotted);
+        assert_eq!(dotted_eighth.beats(), 0.75);
+
+        let eighth_triplet = NoteDivision::from_index(11).unwrap();
+        assert!((eighth_triplet.beats() - 1.0 / 3.0).abs() < 1e-6);
+
+        for index in 0..NoteDivision::COUNT {
+            assert_eq!(NoteDivision::from_index(index).unwrap().index(), index);
+        }
+        assert!(NoteDivision::from_index(NoteDivision::COUNT).is_none());
+    }
+
+    #[test]
+    fn time_signature_keeps_bar_count() {
+        let mut sequencer = Sequencer::new(8., 48000.0);
+        sequencer.set_time_signature(0, TimeSignature::new(7, 8).unwrap());
+        assert_eq!(sequencer.sequence().length, 7.0);
+        assert_eq!(sequencer.time_signature().numerator, 7);
+    }
 }
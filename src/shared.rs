@@ -0,0 +1,458 @@
+//! Engine state shared with the host
+//!
+//! Everything one engine publishes to, or takes from, the threads around
+//! it: the host's callbacks, the latest levels, spectrum, scope, transport
+//! position and playing pattern, the tracks' samples, the number of
+//! patterns and buses handed out so far, and what the audio thread is done
+//! with, to be dropped elsewhere. Each engine has its own, so
+//! several engines can run side by side, e.g. one per plugin instance.
+
+#[cfg(feature = "analyzer")]
+use crate::analyzer::MAX_SPECTRUM_SIZE;
+use crate::bus::MAX_BUSES;
+use crate::consts::TRACK_COUNT;
+use crate::meter::Level;
+use crate::sampler::{Sample, MAX_SLICES};
+use crate::scope::Scope;
+use crate::sequencer::BarBeatTick;
+use crossbeam::channel::{self, Receiver, Sender};
+use std::ffi::c_void;
+use std::sync::atomic::{fence, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Something the audio thread replaced, dropped off it
+pub(crate) type Retired = Box<dyn Send>;
+
+// retired values waiting for the host; beyond this the audio thread drops
+// them itself
+const RETIRED_CAPACITY: usize = 256;
+
+/// Called once per buffer with the host's context, the zero-based bar, beat
+/// and tick of the playback position, and the position in beats, at the
+/// start of the buffer
+pub type PlaybackProgressCallback = extern "C" fn(*mut c_void, u32, u32, u32, f32);
+
+/// Called with the host's context, whether a note starts or stops, its pitch,
+/// its track, the frame within the current buffer it plays at, and its
+/// position in beats
+pub type NotePlayedCallback = extern "C" fn(*mut c_void, bool, u8, u8, u32, f32);
+
+/// Called with the host's context when the transport starts or stops,
+/// whether it's playing, and the position in beats
+pub type TransportCallback = extern "C" fn(*mut c_void, bool, f32);
+
+/// Called after each rendered buffer with the host's context and the levels
+/// of every track, followed by the master level
+pub type MeteringCallback = extern "C" fn(*mut c_void, *const Level, usize);
+
+// a callback is stored as an atomic function pointer next to the context
+// it's called with, so the audio thread can call it without taking a lock;
+// null when unset. The pair sits behind a sequence number that's odd while
+// it's being changed, so a function is never read with another's context;
+// the audio thread never waits for it, a call is skipped while it changes.
+struct Callback {
+    sequence: AtomicU32,
+    function: AtomicPtr<()>,
+    context: AtomicPtr<c_void>,
+}
+
+impl Callback {
+    const fn new() -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+            function: AtomicPtr::new(std::ptr::null_mut()),
+            context: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    fn set(&self, function: *mut (), context: *mut c_void) {
+        // writers take turns making the sequence odd
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence & 1 == 1 {
+                std::hint::spin_loop();
+                sequence = self.sequence.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.sequence.compare_exchange_weak(
+                sequence,
+                sequence + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => sequence = current,
+            }
+        }
+        fence(Ordering::Release);
+        self.function.store(function, Ordering::Relaxed);
+        self.context.store(context, Ordering::Relaxed);
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    /// The function and its context, `None` when unset or while a writer
+    /// is busy
+    fn get(&self) -> Option<(*mut (), *mut c_void)> {
+        let sequence = self.sequence.load(Ordering::Acquire);
+        if sequence & 1 == 1 {
+            return None;
+        }
+        let function = self.function.load(Ordering::Relaxed);
+        let context = self.context.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        (self.sequence.load(Ordering::Relaxed) == sequence && !function.is_null())
+            .then_some((function, context))
+    }
+}
+
+// the sample a track plays and its slice points, kept in place so
+// publishing them never allocates
+struct TrackSample {
+    sample: Option<Arc<Sample>>,
+    slices: [f32; MAX_SLICES],
+    slice_count: usize,
+}
+
+impl TrackSample {
+    const fn new() -> Self {
+        Self {
+            sample: None,
+            slices: [0.0; MAX_SLICES],
+            slice_count: 0,
+        }
+    }
+}
+
+pub struct Shared {
+    progress_callback: Callback,
+    note_callback: Callback,
+    transport_callback: Callback,
+    metering_callback: Callback,
+    // levels of the tracks and the master, last, as packed `Level`s
+    levels: [AtomicU64; TRACK_COUNT + 1],
+    scope: Scope,
+    // bar and beat of the transport position, packed so they're read
+    // together
+    position: AtomicU64,
+    current_pattern: AtomicU32,
+    // bit per track in chord mode, for building its voices for presets
+    chord_tracks: AtomicU32,
+    samples: [Mutex<TrackSample>; TRACK_COUNT],
+    // latest spectrum frame, empty while the analyzer is off; room for the
+    // largest is kept, so publishing one never allocates
+    #[cfg(feature = "analyzer")]
+    spectrum: Mutex<Vec<f32>>,
+    // pattern 0 is created by the sequencer
+    pattern_count: AtomicU32,
+    // the reverb and delay buses are created by the engine
+    bus_count: AtomicU32,
+    retired_sender: Sender<Retired>,
+    retired: Receiver<Retired>,
+}
+
+impl Shared {
+    pub fn new(sample_rate: f32) -> Self {
+        let (retired_sender, retired) = channel::bounded(RETIRED_CAPACITY);
+        Self {
+            progress_callback: Callback::new(),
+            note_callback: Callback::new(),
+            transport_callback: Callback::new(),
+            metering_callback: Callback::new(),
+            levels: [const { AtomicU64::new(0) }; TRACK_COUNT + 1],
+            scope: Scope::new(sample_rate),
+            position: AtomicU64::new(0),
+            current_pattern: AtomicU32::new(0),
+            chord_tracks: AtomicU32::new(0),
+            samples: [const { Mutex::new(TrackSample::new()) }; TRACK_COUNT],
+            #[cfg(feature = "analyzer")]
+            spectrum: Mutex::new(Vec::with_capacity(MAX_SPECTRUM_SIZE / 2)),
+            pattern_count: AtomicU32::new(1),
+            bus_count: AtomicU32::new(2),
+            retired_sender,
+            retired,
+        }
+    }
+
+    pub fn set_progress_callback(&self, callback: PlaybackProgressCallback, context: *mut c_void) {
+        self.progress_callback.set(callback as *mut (), context);
+    }
+
+    pub fn set_note_callback(&self, callback: NotePlayedCallback, context: *mut c_void) {
+        self.note_callback.set(callback as *mut (), context);
+    }
+
+    pub fn set_transport_callback(&self, callback: TransportCallback, context: *mut c_void) {
+        self.transport_callback.set(callback as *mut (), context);
+    }
+
+    pub fn set_metering_callback(&self, callback: MeteringCallback, context: *mut c_void) {
+        self.metering_callback.set(callback as *mut (), context);
+    }
+
+    pub(crate) fn playback_progress(&self, position: BarBeatTick, beats: f32) {
+        if let Some((callback, context)) = self.progress_callback.get() {
+            // only ever set from a PlaybackProgressCallback
+            let callback =
+                unsafe { std::mem::transmute::<*mut (), PlaybackProgressCallback>(callback) };
+            callback(context, position.bar, position.beat, position.tick, beats);
+        }
+    }
+
+    pub(crate) fn note_played(&self, note_on: bool, pitch: u8, track: u8, frame: u32, beats: f32) {
+        if let Some((callback, context)) = self.note_callback.get() {
+            // only ever set from a NotePlayedCallback
+            let callback = unsafe { std::mem::transmute::<*mut (), NotePlayedCallback>(callback) };
+            callback(context, note_on, pitch, track, frame, beats);
+        }
+    }
+
+    pub(crate) fn transport_changed(&self, is_playing: bool, beats: f32) {
+        if let Some((callback, context)) = self.transport_callback.get() {
+            // only ever set from a TransportCallback
+            let callback = unsafe { std::mem::transmute::<*mut (), TransportCallback>(callback) };
+            callback(context, is_playing, beats);
+        }
+    }
+
+    /// Make the latest levels available to the host
+    pub(crate) fn publish_levels(&self, levels: &[Level; TRACK_COUNT + 1]) {
+        for (level, published) in levels.iter().zip(self.levels.iter()) {
+            published.store(level.to_bits(), Ordering::Relaxed);
+        }
+        if let Some((callback, context)) = self.metering_callback.get() {
+            // only ever set from a MeteringCallback
+            let callback = unsafe { std::mem::transmute::<*mut (), MeteringCallback>(callback) };
+            callback(context, levels.as_ptr(), levels.len());
+        }
+    }
+
+    pub fn track_level(&self, track: usize) -> Level {
+        if track >= TRACK_COUNT {
+            return Level::default();
+        }
+        Level::from_bits(self.levels[track].load(Ordering::Relaxed))
+    }
+
+    pub fn master_level(&self) -> Level {
+        Level::from_bits(self.levels[TRACK_COUNT].load(Ordering::Relaxed))
+    }
+
+    /// Oscilloscope capture of the output, written by the audio thread
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// Make the transport position at the end of the latest buffer
+    /// available to the host
+    pub(crate) fn publish_position(&self, position: BarBeatTick) {
+        let packed = (position.bar as u64) << 32 | position.beat as u64;
+        self.position.store(packed, Ordering::Relaxed);
+    }
+
+    /// Zero-based bar and beat of the transport position within the
+    /// playing pattern
+    pub fn bar_and_beat(&self) -> (u32, u32) {
+        let packed = self.position.load(Ordering::Relaxed);
+        ((packed >> 32) as u32, packed as u32)
+    }
+
+    pub(crate) fn publish_pattern(&self, pattern: usize) {
+        self.current_pattern
+            .store(pattern as u32, Ordering::Relaxed);
+    }
+
+    /// Index of the pattern playing at the end of the latest buffer
+    pub fn current_pattern(&self) -> usize {
+        self.current_pattern.load(Ordering::Relaxed) as usize
+    }
+
+    pub(crate) fn publish_chord_track(&self, track: usize, is_chord: bool) {
+        if is_chord {
+            self.chord_tracks.fetch_or(1 << track, Ordering::Relaxed);
+        } else {
+            self.chord_tracks
+                .fetch_and(!(1 << track), Ordering::Relaxed);
+        }
+    }
+
+    /// Which tracks are in chord mode
+    pub fn chord_tracks(&self) -> [bool; TRACK_COUNT] {
+        let bits = self.chord_tracks.load(Ordering::Relaxed);
+        std::array::from_fn(|track| bits & 1 << track != 0)
+    }
+
+    /// Make a track's sample and slice points available to the host.
+    /// Skipped while the host is reading them, returning false so they're
+    /// published again later.
+    pub(crate) fn publish_sample(
+        &self,
+        track: usize,
+        sample: Option<Arc<Sample>>,
+        slices: &[f32],
+    ) -> bool {
+        let Ok(mut published) = self.samples[track].try_lock() else {
+            return false;
+        };
+        let count = slices.len().min(MAX_SLICES);
+        published.sample = sample;
+        published.slices[..count].copy_from_slice(&slices[..count]);
+        published.slice_count = count;
+        true
+    }
+
+    /// The sample a track plays, if it's a sampler
+    pub fn sample(&self, track: usize) -> Option<Arc<Sample>> {
+        self.samples.get(track)?.lock().unwrap().sample.clone()
+    }
+
+    /// Copy a track's slice points into `points`, returning how many there
+    /// are
+    pub fn sample_slices(&self, track: usize, points: &mut [f32]) -> usize {
+        let Some(published) = self.samples.get(track) else {
+            return 0;
+        };
+        let published = published.lock().unwrap();
+        let slices = &published.slices[..published.slice_count];
+        for (point, slice) in points.iter_mut().zip(slices) {
+            *point = *slice;
+        }
+        slices.len()
+    }
+
+    /// Make the latest spectrum frame available to the host. Skipped while
+    /// the host is reading the previous one, so the audio thread never waits.
+    #[cfg(feature = "analyzer")]
+    pub(crate) fn publish_spectrum(&self, magnitudes: &[f32]) {
+        if let Ok(mut spectrum) = self.spectrum.try_lock() {
+            spectrum.clear();
+            let count = magnitudes.len().min(spectrum.capacity());
+            spectrum.extend_from_slice(&magnitudes[..count]);
+        }
+    }
+
+    /// Copy the latest spectrum frame into `bins`, returning the number of
+    /// bins copied
+    #[cfg(feature = "analyzer")]
+    pub fn spectrum(&self, bins: &mut [f32]) -> usize {
+        let spectrum = self.spectrum.lock().unwrap();
+        let count = spectrum.len().min(bins.len());
+        bins[..count].copy_from_slice(&spectrum[..count]);
+        count
+    }
+
+    /// Index of the next pattern created
+    pub(crate) fn next_pattern(&self) -> u32 {
+        self.pattern_count.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Index of the next bus created, None once all buses are taken
+    pub(crate) fn next_bus(&self) -> Option<u32> {
+        self.bus_count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                ((count as usize) < MAX_BUSES).then_some(count + 1)
+            })
+            .ok()
+    }
+
+    /// Give back the index taken by `next_pattern` or `next_bus` when the
+    /// message creating it couldn't be sent
+    pub(crate) fn cancel_pattern(&self) {
+        self.pattern_count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn cancel_bus(&self) {
+        self.bus_count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Hand something the audio thread replaced over to be dropped by the
+    /// host's next call, so freeing it doesn't hold up rendering
+    pub(crate) fn retire(&self, retired: Retired) {
+        // dropped here only when the host is far behind
+        let _ = self.retired_sender.try_send(retired);
+    }
+
+    /// Drop what the audio thread retired, on the calling thread
+    pub fn drop_retired(&self) {
+        while self.retired.try_recv().is_ok() {}
+    }
+
+    /// Make sure the next bus created comes after the first `count`, once a
+    /// preset has created them
+    pub(crate) fn reserve_buses(&self, count: u32) {
+        self.bus_count.fetch_max(count, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn count_notes(context: *mut c_void, _: bool, _: u8, _: u8, _: u32, _: f32) {
+        let count = unsafe { &*(context as *const AtomicU32) };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn callbacks_get_their_context() {
+        let shared = Shared::new(48000.0);
+        // without a callback nothing is called
+        shared.note_played(true, 60, 0, 0, 0.0);
+
+        let count = AtomicU32::new(0);
+        shared.set_note_callback(count_notes, &count as *const _ as *mut c_void);
+        shared.note_played(true, 60, 0, 0, 0.0);
+        shared.note_played(false, 60, 0, 16, 0.5);
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+
+        for _ in 2..MAX_BUSES {
+            assert!(shared.next_bus().is_some());
+        }
+        assert_eq!(shared.next_bus(), None);
+    }
+
+    #[cfg(feature = "analyzer")]
+    #[test]
+    fn spectrum_fits_without_allocating() {
+        let shared = Shared::new(48000.0);
+        let capacity = shared.spectrum.lock().unwrap().capacity();
+        shared.publish_spectrum(&[-6.0; MAX_SPECTRUM_SIZE / 2]);
+        assert_eq!(shared.spectrum.lock().unwrap().capacity(), capacity);
+
+        let mut bins = [0.0; 4];
+        assert_eq!(shared.spectrum(&mut bins), 4);
+        assert_eq!(bins, [-6.0; 4]);
+    }
+
+    #[test]
+    fn retired_values_are_dropped_by_the_host() {
+        let shared = Shared::new(48000.0);
+        let value = std::sync::Arc::new(0);
+        shared.retire(Box::new(value.clone()));
+        assert_eq!(std::sync::Arc::strong_count(&value), 2);
+        shared.drop_retired();
+        assert_eq!(std::sync::Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn callbacks_change_with_their_context() {
+        let callback = std::sync::Arc::new(Callback::new());
+        // each function goes with a context of the same address
+        let pair = |n: usize| (n as *mut (), n as *mut c_void);
+        let writer = {
+            let callback = callback.clone();
+            std::thread::spawn(move || {
+                for n in 1..100_000 {
+                    let (function, context) = pair(n);
+                    callback.set(function, context);
+                }
+            })
+        };
+        while !writer.is_finished() {
+            if let Some((function, context)) = callback.get() {
+                assert_eq!(function as usize, context as usize);
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(callback.get(), Some(pair(99_999)));
+    }
+}
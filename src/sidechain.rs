@@ -0,0 +1,70 @@
+//! Sidechain ducking
+//!
+//! A ducker follows the envelope of a source track and turns the gain of
+//! its target down while the source is loud, e.g. to make room for a kick.
+
+use crate::limiter::EnvelopeFollower;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SidechainTarget {
+    Track(u8),
+    Bus(u8),
+}
+
+pub struct Ducker {
+    /// track whose signal drives the ducking
+    pub source: usize,
+    /// gain reduction at full source level, 0..1
+    pub amount: f32,
+    follower: EnvelopeFollower,
+}
+
+impl Ducker {
+    /// `attack` and `release` are in milliseconds
+    pub fn new(source: usize, amount: f32, attack: f32, release: f32, sample_rate: f32) -> Self {
+        Self {
+            source,
+            amount: amount.clamp(0.0, 1.0),
+            follower: EnvelopeFollower::new(attack.max(0.01), release.max(0.01), sample_rate),
+        }
+    }
+
+    /// Feed one sample of the source signal and return the gain for the
+    /// target
+    #[inline]
+    pub fn process(&mut self, key: f32) -> f32 {
+        self.follower.process(key);
+        1.0 - self.amount * self.follower.env.min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ducks_while_source_is_loud() {
+        let mut ducker = Ducker::new(0, 0.8, 1.0, 50.0, 48000.0);
+        assert_eq!(ducker.process(0.0), 1.0);
+
+        let mut gain = 1.0;
+        for _ in 0..480 {
+            gain = ducker.process(1.0);
+        }
+        assert!((gain - 0.2).abs() < 1e-3);
+
+        // recovers once the source is silent
+        for _ in 0..48000 {
+            gain = ducker.process(0.0);
+        }
+        assert!(gain > 0.999);
+    }
+
+    #[test]
+    fn amount_is_clamped() {
+        let mut ducker = Ducker::new(0, 2.0, 1.0, 50.0, 48000.0);
+        for _ in 0..4800 {
+            assert!(ducker.process(4.0) >= 0.0);
+        }
+    }
+}
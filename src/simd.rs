@@ -0,0 +1,176 @@
+//! Portable SIMD helpers
+//!
+//! `F32x8` keeps eight lanes in an aligned array. Its element-wise
+//! operations are simple enough for the compiler to map onto SSE/AVX or
+//! NEON registers on stable Rust, without target specific code. The slice
+//! helpers work through eight samples at a time and finish any remainder
+//! with a scalar loop.
+
+use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+
+pub const LANES: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[repr(C, align(32))]
+pub struct F32x8(pub [f32; LANES]);
+
+impl F32x8 {
+    pub const ZERO: Self = Self([0.0; LANES]);
+
+    #[inline]
+    pub fn splat(x: f32) -> Self {
+        Self([x; LANES])
+    }
+
+    /// Load the first eight values of `xs`
+    #[inline]
+    pub fn from_slice(xs: &[f32]) -> Self {
+        let mut lanes = [0.0; LANES];
+        lanes.copy_from_slice(&xs[..LANES]);
+        Self(lanes)
+    }
+
+    /// Store the lanes in the first eight values of `xs`
+    #[inline]
+    pub fn write_to_slice(self, xs: &mut [f32]) {
+        xs[..LANES].copy_from_slice(&self.0);
+    }
+
+    /// Horizontal sum, added pairwise
+    #[inline]
+    pub fn sum(self) -> f32 {
+        let [a, b, c, d, e, f, g, h] = self.0;
+        ((a + e) + (c + g)) + ((b + f) + (d + h))
+    }
+}
+
+impl Add for F32x8 {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl AddAssign for F32x8 {
+    #[inline]
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for F32x8 {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] - rhs.0[i]))
+    }
+}
+
+impl Mul for F32x8 {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl Neg for F32x8 {
+    type Output = Self;
+
+    #[inline]
+    fn neg(self) -> Self {
+        Self(self.0.map(|x| -x))
+    }
+}
+
+/// Sum of all values
+#[inline]
+pub fn sum(xs: &[f32]) -> f32 {
+    let chunks = xs.chunks_exact(LANES);
+    let rest = chunks.remainder().iter().sum::<f32>();
+    chunks
+        .fold(F32x8::ZERO, |acc, chunk| acc + F32x8::from_slice(chunk))
+        .sum()
+        + rest
+}
+
+/// `out[i] = a[i] * b[i]`, over the shortest of the three
+#[inline]
+pub fn multiply(out: &mut [f32], a: &[f32], b: &[f32]) {
+    let len = out.len().min(a.len()).min(b.len());
+    let split = len - len % LANES;
+    for ((out, a), b) in out[..split]
+        .chunks_exact_mut(LANES)
+        .zip(a.chunks_exact(LANES))
+        .zip(b.chunks_exact(LANES))
+    {
+        (F32x8::from_slice(a) * F32x8::from_slice(b)).write_to_slice(out);
+    }
+    for i in split..len {
+        out[i] = a[i] * b[i];
+    }
+}
+
+/// `out[i] += xs[i] * gain`, over the shorter of the two
+#[inline]
+pub fn add_scaled(out: &mut [f32], xs: &[f32], gain: f32) {
+    let len = out.len().min(xs.len());
+    let split = len - len % LANES;
+    let gains = F32x8::splat(gain);
+    for (out, xs) in out[..split]
+        .chunks_exact_mut(LANES)
+        .zip(xs.chunks_exact(LANES))
+    {
+        (F32x8::from_slice(out) + F32x8::from_slice(xs) * gains).write_to_slice(out);
+    }
+    for i in split..len {
+        out[i] += xs[i] * gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(len: usize, offset: f32) -> Vec<f32> {
+        (0..len).map(|i| i as f32 * 0.25 + offset).collect()
+    }
+
+    #[test]
+    fn lanes_match_scalar() {
+        let a = F32x8::from_slice(&ramp(8, 1.0));
+        let b = F32x8::from_slice(&ramp(8, -3.0));
+        for i in 0..LANES {
+            assert_eq!((a + b).0[i], a.0[i] + b.0[i]);
+            assert_eq!((a - b).0[i], a.0[i] - b.0[i]);
+            assert_eq!((a * b).0[i], a.0[i] * b.0[i]);
+            assert_eq!((-a).0[i], -a.0[i]);
+        }
+        assert_eq!(a.sum(), a.0.iter().sum::<f32>());
+    }
+
+    #[test]
+    fn slice_helpers_cover_the_remainder() {
+        // lengths around the lane count, including the scalar tail
+        for len in [0, 3, 8, 13, 16, 21] {
+            let a = ramp(len, 1.0);
+            let b = ramp(len, 2.0);
+            assert_eq!(sum(&a), a.iter().sum::<f32>());
+
+            let mut out = vec![0.0; len];
+            multiply(&mut out, &a, &b);
+            for i in 0..len {
+                assert_eq!(out[i], a[i] * b[i]);
+            }
+
+            add_scaled(&mut out, &a, 0.5);
+            for i in 0..len {
+                assert_eq!(out[i], a[i] * b[i] + a[i] * 0.5);
+            }
+        }
+    }
+}
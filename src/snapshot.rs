@@ -0,0 +1,69 @@
+//! Snapshots
+//!
+//! A snapshot holds the values of a track's parameters, its voice's and
+//! its EQ's, as they were when it was captured. Each track keeps two, A and
+//! B, and morphing between them moves every continuous parameter along the
+//! line from its value in A to its value in B; stepped parameters, like an
+//! algorithm, switch over halfway.
+
+use crate::synth::VoiceType;
+
+/// Snapshots kept per track, A and B
+pub const SNAPSHOT_SLOTS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// the sound the parameters belong to
+    pub voice_type: VoiceType,
+    pub parameters: Vec<(i8, f32)>,
+}
+
+impl Snapshot {
+    /// Each parameter in both snapshots, with its value in `self` and in
+    /// `other`
+    pub fn pairs<'a>(&'a self, other: &'a Snapshot) -> impl Iterator<Item = (i8, f32, f32)> + 'a {
+        self.parameters.iter().filter_map(|&(parameter, from)| {
+            let &(_, to) = other.parameters.iter().find(|(p, _)| *p == parameter)?;
+            Some((parameter, from, to))
+        })
+    }
+}
+
+/// The value `amount`, 0.0..1.0, of the way from `from` to `to`; stepped
+/// parameters take the nearer of the two
+pub fn morph(from: f32, to: f32, amount: f32, is_stepped: bool) -> f32 {
+    let amount = amount.clamp(0.0, 1.0);
+    if is_stepped {
+        if amount < 0.5 {
+            from
+        } else {
+            to
+        }
+    } else {
+        from + (to - from) * amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn morphs_between_snapshots() {
+        let a = Snapshot {
+            voice_type: VoiceType::Fm,
+            parameters: vec![(0, 0.0), (1, 2.0), (2, 1.0)],
+        };
+        let b = Snapshot {
+            voice_type: VoiceType::Fm,
+            parameters: vec![(0, 1.0), (2, 5.0)],
+        };
+        let pairs: Vec<_> = a.pairs(&b).collect();
+        assert_eq!(pairs, [(0, 0.0, 1.0), (2, 1.0, 5.0)]);
+
+        assert_eq!(morph(0.0, 1.0, 0.25, false), 0.25);
+        assert_eq!(morph(0.0, 1.0, 2.0, false), 1.0);
+        assert_eq!(morph(1.0, 5.0, 0.4, true), 1.0);
+        assert_eq!(morph(1.0, 5.0, 0.5, true), 5.0);
+    }
+}
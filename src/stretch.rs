@@ -0,0 +1,414 @@
+//! Time stretching
+//!
+//! Plays sample data at one speed and pitch independently of each other
+//! by overlapping windowed grains (WSOLA). A new grain starts every hop at
+//! the playback position, each reading the data at the pitch ratio; grains
+//! overlap by half, so their windows sum to one. Before a grain starts,
+//! the position is nudged within a search range to where the data best
+//! lines up with what the previous grain would play next, so the overlap
+//! doesn't beat or comb. Preserving transients, a grain starts right on
+//! each onset the position passes, fading out the grains before it, so
+//! drum hits aren't smeared or doubled.
+
+use crate::resampler::Resampler;
+use std::f32::consts::FRAC_PI_2;
+
+// at most two grains overlap, plus the ones fading out at an onset
+const MAX_GRAINS: usize = 4;
+
+// time (ms) grains fade in at an onset, and older ones fade out
+const ONSET_FADE_MS: f32 = 2.0;
+
+// onsets are at least this far apart (ms)
+const MIN_ONSET_GAP_MS: f32 = 50.0;
+
+// onsets are found in the level of windows this long (frames), and have to
+// jump by this much over the window before
+const ONSET_WINDOW: usize = 64;
+const ONSET_JUMP: f32 = 4.0;
+const ONSET_FLOOR: f32 = 0.01;
+
+// frames skipped between the values correlated in the search
+const CORRELATION_STEP: usize = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StretchQuality {
+    /// short grains without a search, cheapest and grainiest
+    Low,
+    #[default]
+    Medium,
+    /// long grains and a wider, finer search
+    High,
+}
+
+impl StretchQuality {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(StretchQuality::Low),
+            1 => Some(StretchQuality::Medium),
+            2 => Some(StretchQuality::High),
+            _ => None,
+        }
+    }
+
+    /// Hop between grains, search range either way and length of the
+    /// correlated data, all in milliseconds, and the step of the search
+    fn settings(&self) -> (f32, f32, f32, usize) {
+        match self {
+            StretchQuality::Low => (15.0, 0.0, 0.0, 1),
+            StretchQuality::Medium => (30.0, 5.0, 10.0, 2),
+            StretchQuality::High => (40.0, 8.0, 10.0, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum GrainStart {
+    // the first grain of a note
+    First,
+    // every hop, overlapping the grain before
+    Regular,
+    // on an onset, fading out the grains before
+    Onset,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Grain {
+    // read position in the data
+    position: f64,
+    age: usize,
+    // samples the window takes to open, it stays open until the hop is over
+    rise: usize,
+    // how far the grain has faded out, once it's released at an onset
+    release: Option<usize>,
+    is_active: bool,
+}
+
+impl Grain {
+    fn window(&self, hop: usize, fade: usize) -> f32 {
+        let w = if self.age < self.rise {
+            (FRAC_PI_2 * self.age as f32 / self.rise as f32)
+                .sin()
+                .powi(2)
+        } else if self.age < hop {
+            1.0
+        } else {
+            (FRAC_PI_2 * (self.age - hop) as f32 / hop as f32)
+                .cos()
+                .powi(2)
+        };
+        match self.release {
+            Some(release) => w * (1.0 - release as f32 / fade as f32),
+            None => w,
+        }
+    }
+}
+
+pub struct Stretcher {
+    grains: [Grain; MAX_GRAINS],
+    quality: StretchQuality,
+    // in samples, or frames of the data
+    hop: usize,
+    search: usize,
+    search_step: usize,
+    fade: usize,
+    min_onset_gap: f64,
+    // what the youngest grain would play next, correlated in the search
+    continuation: Vec<f32>,
+    until_grain: usize,
+    preserve_transients: bool,
+    next_onset: Option<f64>,
+    last_onset: f64,
+    sample_rate: f32,
+}
+
+impl Stretcher {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut stretcher = Self {
+            grains: [Grain::default(); MAX_GRAINS],
+            quality: StretchQuality::default(),
+            hop: 1,
+            search: 0,
+            search_step: 1,
+            fade: ((ONSET_FADE_MS * 0.001 * sample_rate) as usize).max(1),
+            min_onset_gap: (MIN_ONSET_GAP_MS * 0.001 * sample_rate) as f64,
+            continuation: Vec::new(),
+            until_grain: 0,
+            preserve_transients: true,
+            next_onset: None,
+            last_onset: f64::NEG_INFINITY,
+            sample_rate,
+        };
+        stretcher.set_quality(StretchQuality::default());
+        stretcher
+    }
+
+    pub fn quality(&self) -> StretchQuality {
+        self.quality
+    }
+
+    pub fn set_quality(&mut self, quality: StretchQuality) {
+        let (hop_ms, search_ms, correlation_ms, search_step) = quality.settings();
+        let samples = |ms: f32| (ms * 0.001 * self.sample_rate) as usize;
+        self.quality = quality;
+        self.hop = samples(hop_ms).max(1);
+        self.search = samples(search_ms);
+        self.search_step = search_step;
+        let length = samples(correlation_ms) / CORRELATION_STEP;
+        // allocates only when the quality changes
+        self.continuation.resize(length, 0.0);
+    }
+
+    pub fn preserves_transients(&self) -> bool {
+        self.preserve_transients
+    }
+
+    pub fn set_preserve_transients(&mut self, preserve: bool) {
+        self.preserve_transients = preserve;
+        self.next_onset = None;
+    }
+
+    /// Stop the grains, so the next sample starts afresh
+    pub fn reset(&mut self) {
+        for grain in self.grains.iter_mut() {
+            grain.is_active = false;
+        }
+        self.until_grain = 0;
+        self.next_onset = None;
+        self.last_onset = f64::NEG_INFINITY;
+    }
+
+    /// The next sample of `data` stretched, with playback at `position`
+    /// moving `speed` frames per sample and grains reading `ratio` frames
+    /// per sample, negative in reverse
+    #[inline]
+    pub fn process(
+        &mut self,
+        data: &[f32],
+        resampler: &Resampler,
+        position: f64,
+        speed: f64,
+        ratio: f64,
+    ) -> f32 {
+        let is_forward = ratio > 0.0 && speed > 0.0;
+        // onset grains open just before the onset, so it plays in time
+        let lead = self.fade as f64;
+        if let Some(onset) = self
+            .next_onset
+            .filter(|&onset| position >= onset - lead * speed)
+        {
+            self.start_grain(data, onset - lead * ratio, GrainStart::Onset, ratio);
+            self.last_onset = onset;
+            self.find_onset(data, position, speed, ratio, is_forward);
+        } else if self.until_grain == 0 {
+            let start = if self.grains.iter().any(|g| g.is_active) {
+                GrainStart::Regular
+            } else {
+                GrainStart::First
+            };
+            self.start_grain(data, position, start, ratio);
+            self.find_onset(data, position, speed, ratio, is_forward);
+        }
+        self.until_grain = self.until_grain.saturating_sub(1);
+
+        let mut y = 0.0;
+        for grain in self.grains.iter_mut().filter(|g| g.is_active) {
+            // grains fade out before they reach the next onset, it's left
+            // to the grain starting on it
+            if let Some(onset) = self.next_onset {
+                if grain.release.is_none() && grain.position + lead * ratio >= onset {
+                    grain.release = Some(0);
+                }
+            }
+            let w = grain.window(self.hop, self.fade);
+            y += resampler.read(data, grain.position, ratio.abs() as f32) * w;
+            grain.position += ratio;
+            grain.age += 1;
+            if let Some(release) = grain.release.as_mut() {
+                *release += 1;
+            }
+            grain.is_active =
+                grain.age < self.hop * 2 && grain.release.is_none_or(|r| r < self.fade);
+        }
+        y
+    }
+
+    fn start_grain(&mut self, data: &[f32], position: f64, start: GrainStart, ratio: f64) {
+        let (position, rise) = match start {
+            // nothing to fade from, the note starts at once
+            GrainStart::First => (position, 1),
+            GrainStart::Regular => (position + self.best_offset(data, position, ratio), self.hop),
+            GrainStart::Onset => {
+                for grain in self.grains.iter_mut().filter(|g| g.is_active) {
+                    grain.release.get_or_insert(0);
+                }
+                (position, self.fade)
+            }
+        };
+        let slot = self
+            .grains
+            .iter()
+            .position(|g| !g.is_active)
+            .unwrap_or_else(|| {
+                (0..MAX_GRAINS)
+                    .max_by_key(|&i| self.grains[i].age)
+                    .unwrap_or(0)
+            });
+        self.grains[slot] = Grain {
+            position,
+            age: 0,
+            rise,
+            release: None,
+            is_active: true,
+        };
+        self.until_grain = self.hop;
+    }
+
+    /// Offset from `position`, within the search range, where the data
+    /// correlates best with what the youngest grain plays next
+    fn best_offset(&mut self, data: &[f32], position: f64, ratio: f64) -> f64 {
+        let Some(previous) = self
+            .grains
+            .iter()
+            .filter(|g| g.is_active && g.release.is_none())
+            .min_by_key(|g| g.age)
+        else {
+            return 0.0;
+        };
+        if self.search == 0 || self.continuation.is_empty() {
+            return 0.0;
+        }
+        let at = |x: f64| {
+            if x < 0.0 {
+                0.0
+            } else {
+                data.get(x as usize).copied().unwrap_or(0.0)
+            }
+        };
+        let step = CORRELATION_STEP as f64 * ratio;
+        for (k, value) in self.continuation.iter_mut().enumerate() {
+            *value = at(previous.position + k as f64 * step);
+        }
+
+        let search = self.search as isize;
+        let mut best = (0, f32::MIN);
+        for offset in (-search..=search).step_by(self.search_step) {
+            let start = position + offset as f64;
+            let correlation: f32 = self
+                .continuation
+                .iter()
+                .enumerate()
+                .map(|(k, value)| value * at(start + k as f64 * step))
+                .sum();
+            if correlation > best.1 {
+                best = (offset, correlation);
+            }
+        }
+        best.0 as f64
+    }
+
+    /// Look for an onset as far ahead as the grains started before the
+    /// next one will read, remembering it to start a grain on
+    fn find_onset(
+        &mut self,
+        data: &[f32],
+        position: f64,
+        speed: f64,
+        ratio: f64,
+        is_forward: bool,
+    ) {
+        self.next_onset = None;
+        if !self.preserve_transients || !is_forward {
+            return;
+        }
+        let level = |start: usize| {
+            let window = data.get(start..(start + ONSET_WINDOW).min(data.len()));
+            window.map_or(0.0, |w| {
+                w.iter().map(|x| x * x).sum::<f32>() / ONSET_WINDOW as f32
+            })
+        };
+        let from = position.max(self.last_onset + self.min_onset_gap) as usize;
+        let to = (position + self.hop as f64 * (speed + 2.0 * ratio)) as usize;
+        let mut start = from.max(ONSET_WINDOW);
+        while start < to.min(data.len()) {
+            let current = level(start);
+            if current > ONSET_FLOOR * ONSET_FLOOR
+                && current > level(start - ONSET_WINDOW) * ONSET_JUMP * ONSET_JUMP
+            {
+                self.next_onset = Some(start as f64);
+                return;
+            }
+            start += ONSET_WINDOW;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resampler::ResamplerQuality;
+    use std::f32::consts::TAU;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn stretch(stretcher: &mut Stretcher, data: &[f32], speed: f64, length: usize) -> Vec<f32> {
+        let resampler = Resampler::new(ResamplerQuality::Linear);
+        let mut position = 0.0;
+        (0..length)
+            .map(|_| {
+                let y = stretcher.process(data, &resampler, position, speed, 1.0);
+                position += speed;
+                y
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stretching_keeps_the_pitch() {
+        let data: Vec<f32> = (0..48000)
+            .map(|i| (TAU * 440.0 * i as f32 / SAMPLE_RATE).sin())
+            .collect();
+        // low quality grains don't line up, so the count wanders
+        for quality in [StretchQuality::Medium, StretchQuality::High] {
+            let mut stretcher = Stretcher::new(SAMPLE_RATE);
+            stretcher.set_quality(quality);
+            let ys = stretch(&mut stretcher, &data, 0.5, 72000);
+            let crossings = ys[24000..72000]
+                .windows(2)
+                .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+                .count();
+            assert!(crossings.abs_diff(440) <= 8, "{quality:?} {crossings}");
+            // the grains' windows sum to a steady level
+            let peak = ys[24000..].iter().fold(0.0f32, |a, y| a.max(y.abs()));
+            assert!(peak > 0.7 && peak < 1.3, "{quality:?} {peak}");
+        }
+        assert_eq!(StretchQuality::from_u8(3), None);
+    }
+
+    #[test]
+    fn onsets_start_grains() {
+        // a hit every 100 ms, stretched to every 200 ms
+        let mut data = vec![0.0; 48000];
+        for hit in (0..10).map(|i| i * 4800) {
+            for (i, x) in data[hit..hit + 2400].iter_mut().enumerate() {
+                *x = (i as f32 * 0.2).sin() * (-(i as f32) / 200.0).exp();
+            }
+        }
+        let mut stretcher = Stretcher::new(SAMPLE_RATE);
+        let ys = stretch(&mut stretcher, &data, 0.5, 96000);
+        // each hit starts once, about where the stretched hit falls
+        let attacks: Vec<usize> = (500..ys.len())
+            .filter(|&i| {
+                let before = ys[i - 500..i - 20]
+                    .iter()
+                    .fold(0.0f32, |a, y| a.max(y.abs()));
+                ys[i].abs() > 0.5 && ys[i - 20..i].iter().all(|y| y.abs() <= 0.5) && before < 0.05
+            })
+            .collect();
+        assert_eq!(attacks.len(), 9);
+        for (n, attack) in attacks.iter().enumerate() {
+            let expected = (n + 1) * 9600;
+            assert!(attack.abs_diff(expected) < 200, "{n} {attack}");
+        }
+    }
+}
@@ -1,28 +1,191 @@
-use crate::envelopes::{CurveType, AR};
-use crate::filters::SVF;
+use crate::envelopes::{CurveType, RetriggerMode, AR};
+use crate::filters::{key_tracking, FilterType, LadderFilter, SVFMode, SVF};
 use crate::osc::BlitSawOsc;
+use crate::parameters::ParameterInfo;
+use crate::saturation::{Saturator, ShaperType};
 use crate::synth::SynthVoice;
-use crate::utils::pitch_to_freq;
+use crate::utils::fractional_pitch_to_freq;
+use rand::Rng;
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+/// maximum number of oscillators stacked in unison mode
+pub const MAX_UNISON: usize = 8;
 
 pub struct SubtractiveVoice {
-    osc: BlitSawOsc,
+    oscs: [BlitSawOsc; MAX_UNISON],
+    // (left, right) gain of each oscillator
+    pans: [(f32, f32); MAX_UNISON],
+    unison: usize,
+    // detune of the outer oscillators in semitones
+    detune: f32,
+    // stereo width of the oscillators, 0.0..1.0
+    spread: f32,
+    pitch_value: f32,
     env: AR,
+    // cutoff modulation by the envelope, as a multiple of the cutoff
+    env_amount: f32,
     velocity: f32,
-    filter: SVF,
+    // cutoff at middle C, before key tracking
+    cutoff: f32,
+    // octaves the cutoff moves per octave played
+    key_tracking: f32,
+    filter_type: FilterType,
+    filters: [SVF; 2],
+    ladders: [LadderFilter; 2],
+    // post-filter drive, bypassed at zero drive
+    saturators: [Saturator; 2],
     pitch: Option<u8>,
     sample_rate: f32,
 }
 
+impl SubtractiveVoice {
+    pub fn parameters() -> Vec<ParameterInfo> {
+        vec![
+            ParameterInfo::logarithmic(0, "Cutoff", 20.0, 20000.0, 5000.0, "Hz"),
+            ParameterInfo::linear(1, "Resonance", 0.5, 20.0, 0.707, ""),
+            ParameterInfo::linear(2, "Attack", 0.0, 5000.0, 0.0, "ms"),
+            ParameterInfo::linear(3, "Decay", 0.0, 30000.0, 30000.0, "ms"),
+            ParameterInfo::linear(4, "Filter envelope", 0.0, 1.0, 0.0, ""),
+            ParameterInfo::stepped(5, "Unison", 1.0, MAX_UNISON as f32, 1.0),
+            ParameterInfo::linear(6, "Detune", 0.0, 1.0, 0.1, "st"),
+            ParameterInfo::linear(7, "Spread", 0.0, 1.0, 0.5, ""),
+            ParameterInfo::stepped(8, "Filter type", 0.0, 1.0, 0.0),
+            ParameterInfo::linear(9, "Filter drive", 0.01, 10.0, 1.0, ""),
+            ParameterInfo::linear(10, "Saturation", 0.0, 10.0, 0.0, ""),
+            ParameterInfo::stepped(11, "Saturation shape", 0.0, 4.0, 1.0),
+            ParameterInfo::linear(12, "SVF drive", 0.0, 4.0, 0.0, ""),
+            ParameterInfo::linear(13, "Key tracking", 0.0, 2.0, 0.0, ""),
+        ]
+    }
+
+    /// Position of unison oscillator `index` in -1.0..1.0
+    fn unison_position(index: usize, count: usize) -> f32 {
+        if count < 2 {
+            return 0.0;
+        }
+        index as f32 / (count - 1) as f32 * 2.0 - 1.0
+    }
+
+    fn update_pans(&mut self) {
+        // equal power panning, normalized for the number of oscillators
+        let gain = 1.0 / (self.unison as f32).sqrt();
+        for i in 0..self.unison {
+            let pan = self.spread * Self::unison_position(i, self.unison);
+            let angle = (pan + 1.0) * FRAC_PI_4;
+            self.pans[i] = (angle.cos() * SQRT_2 * gain, angle.sin() * SQRT_2 * gain);
+        }
+    }
+
+    /// Render one stereo frame: oscillators → filter → saturation → envelope
+    #[inline]
+    pub fn process_stereo(&mut self) -> (f32, f32) {
+        if !self.env.is_active() {
+            return (0.0, 0.0);
+        }
+        let env = self.env.process();
+        let (left, right) = self.process_oscillators();
+        let cutoff_mod = self.env_amount * env;
+        let gain = env * self.velocity * 0.5;
+        let (left, right) = match self.filter_type {
+            FilterType::Svf => (
+                self.filters[0].process(left, cutoff_mod),
+                self.filters[1].process(right, cutoff_mod),
+            ),
+            FilterType::Ladder => (
+                self.ladders[0].process(left, cutoff_mod),
+                self.ladders[1].process(right, cutoff_mod),
+            ),
+        };
+        if self.saturators[0].drive() > 0.0 {
+            (
+                self.saturators[0].process(left) * gain,
+                self.saturators[1].process(right) * gain,
+            )
+        } else {
+            (left * gain, right * gain)
+        }
+    }
+
+    fn set_cutoff(&mut self, freq: f32) {
+        self.cutoff = freq;
+        self.update_cutoff();
+    }
+
+    /// Apply the cutoff, following the pitch of the played note
+    fn update_cutoff(&mut self) {
+        let freq = self.cutoff * key_tracking(self.pitch_value, self.key_tracking);
+        for filter in self.filters.iter_mut() {
+            filter.update_freq(freq);
+        }
+        for ladder in self.ladders.iter_mut() {
+            ladder.update_freq(freq);
+        }
+    }
+
+    /// The resonance is set as a Q; the ladder maps it to its 0.0..1.0
+    /// range, reaching self-oscillation as Q grows large
+    fn set_resonance(&mut self, q: f32) {
+        for filter in self.filters.iter_mut() {
+            filter.update_q(q);
+        }
+        let resonance = (1.0 - 0.5 / q.max(0.5)).clamp(0.0, 1.0);
+        for ladder in self.ladders.iter_mut() {
+            ladder.update_resonance(resonance);
+        }
+    }
+
+    fn set_filter_type(&mut self, filter_type: FilterType) {
+        if filter_type != self.filter_type {
+            self.filter_type = filter_type;
+            for ladder in self.ladders.iter_mut() {
+                ladder.reset();
+            }
+        }
+    }
+
+    /// Stereo mix of the unison oscillators
+    #[inline]
+    fn process_oscillators(&mut self) -> (f32, f32) {
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for i in 0..self.unison {
+            let y = self.oscs[i].process();
+            left += y * self.pans[i].0;
+            right += y * self.pans[i].1;
+        }
+        (left, right)
+    }
+}
+
 impl SynthVoice for SubtractiveVoice {
     fn new(sample_rate: f32) -> Self {
-        Self {
-            osc: BlitSawOsc::new(sample_rate),
+        let mut filter = SVF::new(5000.0, 0.707, sample_rate);
+        filter.mode = SVFMode::Lowpass;
+        let ladder = LadderFilter::new(5000.0, 0.0, sample_rate);
+        let mut saturator = Saturator::new(sample_rate);
+        saturator.set_drive(0.0);
+        let mut voice = Self {
+            oscs: std::array::from_fn(|_| BlitSawOsc::new(sample_rate)),
+            pans: [(1.0, 1.0); MAX_UNISON],
+            unison: 1,
+            detune: 0.1,
+            spread: 0.5,
+            pitch_value: 0.0,
             env: AR::new(0.0, 30000.0, CurveType::Exponential { pow: 8 }, sample_rate),
+            env_amount: 0.0,
             velocity: 1.0,
-            filter: SVF::new(5000.0, 0.707, sample_rate),
+            cutoff: 5000.0,
+            key_tracking: 0.0,
+            filter_type: FilterType::Svf,
+            filters: [filter; 2],
+            ladders: [ladder; 2],
+            saturators: [saturator; 2],
             pitch: None,
             sample_rate,
-        }
+        };
+        voice.update_pans();
+        voice.set_resonance(0.707);
+        voice
     }
 
     fn init(&mut self) {
@@ -31,28 +194,51 @@ impl SynthVoice for SubtractiveVoice {
 
     #[inline]
     fn process(&mut self) -> f32 {
-        todo!()
-        // if !self.env.is_active() {
-        //     return 0.0;
-        // }
-        // let y = self.osc.process();
-        // self.filter.process(y)
+        let (left, right) = self.process_stereo();
+        (left + right) * 0.5
     }
 
     fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32) {
         self.velocity = velocity as f32 / 128.0;
         self.pitch = Some(pitch);
-        self.filter.update_freq(param1 * 10000.0);
-        self.filter.update_q(param2 * 20.0);
-        let freq = pitch_to_freq(pitch);
-        self.osc.reset(); // resetting the phase is optional!
-        self.osc.set_freq(freq);
+        self.set_pitch(pitch as f32);
+        // per-note params override the cutoff and resonance when set
+        if param1 > 0.0 {
+            self.cutoff = param1 * 10000.0;
+        }
+        if param2 > 0.0 {
+            self.set_resonance(param2 * 20.0);
+        }
+        self.update_cutoff();
+        if self.unison > 1 {
+            // random phases keep the stacked oscillators from phasing in unison
+            let mut rng = rand::thread_rng();
+            for osc in self.oscs[..self.unison].iter_mut() {
+                osc.reset_with_phase(rng.gen());
+            }
+        } else {
+            self.oscs[0].reset(); // resetting the phase is optional!
+        }
         self.env.trigger(velocity);
     }
 
+    fn set_pitch(&mut self, pitch: f32) {
+        self.pitch_value = pitch;
+        for i in 0..self.unison {
+            let detune = self.detune * Self::unison_position(i, self.unison);
+            self.oscs[i].set_freq(fractional_pitch_to_freq(pitch + detune));
+        }
+    }
+
+    fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        self.env.retrigger = mode;
+    }
+
     fn reset(&mut self) {
         self.env.decay();
-        self.osc.reset();
+        for osc in self.oscs.iter_mut() {
+            osc.reset();
+        }
     }
 
     fn stop(&mut self) {
@@ -61,7 +247,86 @@ impl SynthVoice for SubtractiveVoice {
     }
 
     fn set_parameter(&mut self, parameter: i8, value: f32) {
-        todo!()
+        match parameter {
+            0 => self.set_cutoff(value),
+            1 => self.set_resonance(value),
+            2 => self.env.attack_ms = value,
+            3 => self.env.decay_ms = value,
+            4 => self.env_amount = value,
+            5 => {
+                self.unison = (value as usize).clamp(1, MAX_UNISON);
+                self.update_pans();
+                self.set_pitch(self.pitch_value);
+            }
+            6 => {
+                self.detune = value;
+                self.set_pitch(self.pitch_value);
+            }
+            7 => {
+                self.spread = value.clamp(0.0, 1.0);
+                self.update_pans();
+            }
+            8 => {
+                if let Some(filter_type) = FilterType::from_u8(value as u8) {
+                    self.set_filter_type(filter_type);
+                }
+            }
+            9 => {
+                for ladder in self.ladders.iter_mut() {
+                    ladder.set_drive(value);
+                }
+            }
+            10 => {
+                for saturator in self.saturators.iter_mut() {
+                    saturator.set_drive(value);
+                }
+            }
+            11 => {
+                if let Some(shape) = ShaperType::from_u8(value as u8) {
+                    for saturator in self.saturators.iter_mut() {
+                        saturator.set_shape(shape);
+                    }
+                }
+            }
+            12 => {
+                for filter in self.filters.iter_mut() {
+                    filter.set_drive(value);
+                }
+            }
+            13 => {
+                self.key_tracking = value.clamp(0.0, 2.0);
+                self.update_cutoff();
+            }
+            _ => (),
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        match parameter {
+            0 => self.cutoff,
+            1 => self.filters[0].get_q(),
+            2 => self.env.attack_ms,
+            3 => self.env.decay_ms,
+            4 => self.env_amount,
+            5 => self.unison as f32,
+            6 => self.detune,
+            7 => self.spread,
+            8 => self.filter_type as u8 as f32,
+            9 => self.ladders[0].get_drive(),
+            10 => self.saturators[0].drive(),
+            11 => self.saturators[0].shape() as u8 as f32,
+            12 => self.filters[0].get_drive(),
+            13 => self.key_tracking,
+            _ => 0.0,
+        }
+    }
+
+    fn parameter_count(&self) -> i8 {
+        14
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        matches!(parameter, 5 | 8 | 11)
     }
 
     fn get_pitch(&self) -> u8 {
@@ -72,3 +337,113 @@ impl SynthVoice for SubtractiveVoice {
         self.env.is_active()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unison_positions_span_stereo_field() {
+        assert_eq!(SubtractiveVoice::unison_position(0, 1), 0.0);
+        assert_eq!(SubtractiveVoice::unison_position(0, 3), -1.0);
+        assert_eq!(SubtractiveVoice::unison_position(1, 3), 0.0);
+        assert_eq!(SubtractiveVoice::unison_position(2, 3), 1.0);
+    }
+
+    #[test]
+    fn unison_spreads_oscillators() {
+        let mut voice = SubtractiveVoice::new(48000.0);
+        voice.set_parameter(5, 4.0);
+        voice.set_parameter(7, 1.0);
+        voice.play(48, 100, 0.5, 0.1);
+
+        // the outer oscillators are hard panned
+        assert!(voice.pans[0].1.abs() < 1e-6);
+        assert!(voice.pans[3].0.abs() < 1e-6);
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for _ in 0..4800 {
+            let (l, r) = voice.process_oscillators();
+            left += l * l;
+            right += r * r;
+        }
+        assert!(left > 0.0 && right > 0.0);
+        // detuned oscillators at different positions give different channels
+        assert!((left - right).abs() > 1e-3);
+    }
+
+    #[test]
+    fn plays_and_decays() {
+        let mut voice = SubtractiveVoice::new(48000.0);
+        assert_eq!(voice.process(), 0.0);
+        voice.set_parameter(0, 2000.0);
+        voice.set_parameter(3, 50.0);
+        voice.play(60, 100, 0.0, 0.0);
+        assert_eq!(voice.filters[0].get_freq(), 2000.0);
+
+        let ys: Vec<f32> = (0..4800).map(|_| voice.process()).collect();
+        assert!(ys.iter().any(|y| y.abs() > 0.01));
+        assert!(!voice.is_active());
+        assert_eq!(voice.process(), 0.0);
+    }
+
+    #[test]
+    fn key_tracking_follows_the_note() {
+        let mut voice = SubtractiveVoice::new(48000.0);
+        voice.set_parameter(0, 1000.0);
+        voice.set_parameter(13, 1.0);
+        voice.play(72, 100, 0.0, 0.0);
+        assert!((voice.filters[0].get_freq() - 2000.0).abs() < 0.01);
+        assert!((voice.ladders[0].get_freq() - 2000.0).abs() < 0.01);
+        voice.play(48, 100, 0.0, 0.0);
+        assert!((voice.filters[0].get_freq() - 500.0).abs() < 0.01);
+        // the set cutoff is kept
+        assert_eq!(voice.get_parameter(0), 1000.0);
+    }
+
+    #[test]
+    fn unison_count_is_clamped() {
+        let mut voice = SubtractiveVoice::new(48000.0);
+        voice.set_parameter(5, 100.0);
+        assert_eq!(voice.unison, MAX_UNISON);
+        voice.set_parameter(5, 0.0);
+        assert_eq!(voice.unison, 1);
+    }
+
+    #[test]
+    fn ladder_filter_is_selectable() {
+        let mut voice = SubtractiveVoice::new(48000.0);
+        voice.set_parameter(8, 1.0);
+        voice.set_parameter(9, 4.0);
+        voice.set_parameter(1, 20.0);
+        assert_eq!(voice.get_parameter(8), 1.0);
+        assert_eq!(voice.get_parameter(9), 4.0);
+        assert!(voice.ladders[0].get_resonance() > 0.9);
+
+        voice.play(36, 127, 0.0, 0.0);
+        let ys: Vec<f32> = (0..4800).map(|_| voice.process()).collect();
+        assert!(ys.iter().any(|y| y.abs() > 0.01));
+        assert!(ys.iter().all(|y| y.is_finite()));
+
+        // unknown filter types are ignored
+        voice.set_parameter(8, 5.0);
+        assert_eq!(voice.filter_type, FilterType::Ladder);
+    }
+
+    #[test]
+    fn post_filter_saturation() {
+        let render = |drive: f32| {
+            let mut voice = SubtractiveVoice::new(48000.0);
+            voice.set_parameter(10, drive);
+            voice.set_parameter(11, ShaperType::HardClip as u8 as f32);
+            voice.play(48, 127, 0.0, 0.0);
+            (0..4800).map(|_| voice.process()).collect::<Vec<f32>>()
+        };
+        let clean = render(0.0);
+        let driven = render(20.0);
+        let peak = |ys: &[f32]| ys.iter().fold(0.0_f32, |a, y| a.max(y.abs()));
+        assert!(peak(&driven) > peak(&clean));
+        // the envelope gain follows the shaper, so it stays bounded
+        assert!(peak(&driven) <= 1.0);
+    }
+}
@@ -1,37 +1,379 @@
+use crate::chords::MAX_CHORD_NOTES;
+use crate::drums::DrumKit;
+use crate::envelopes::RetriggerMode;
+use crate::karplus::KarplusVoice;
+use crate::modal::ModalVoice;
+use crate::modulation::{ModMatrix, ModSlot};
+use crate::modulators::ModProcessor;
+use crate::organ::OrganVoice;
+use crate::parameters::ParameterInfo;
+use crate::plaits_voice::{BLITVoice, FmVoice};
 use crate::reverb::Reverb;
+use crate::sampler::{Sample, SamplerVoice};
+use crate::sequencer::NoteExpression;
+use crate::subtractive::SubtractiveVoice;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 pub const VOICE_COUNT: usize = 1;
 
-pub trait SynthVoice {
-    fn new(sample_rate: f32) -> Self;
+/// Synthesis engines a track can be switched to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum VoiceType {
+    #[default]
+    Fm,
+    Subtractive,
+    Karplus,
+    DrumKit,
+    /// Plaits-style BLIT sawtooth voice
+    Plaits,
+    Sampler,
+    /// struck resonators: bells, bars and membranes
+    Modal,
+    /// drawbar organ
+    Organ,
+}
+
+impl VoiceType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(VoiceType::Fm),
+            1 => Some(VoiceType::Subtractive),
+            2 => Some(VoiceType::Karplus),
+            3 => Some(VoiceType::DrumKit),
+            4 => Some(VoiceType::Plaits),
+            5 => Some(VoiceType::Sampler),
+            6 => Some(VoiceType::Modal),
+            7 => Some(VoiceType::Organ),
+            _ => None,
+        }
+    }
+
+    /// Metadata of the voice's parameters
+    pub fn parameters(&self) -> Vec<ParameterInfo> {
+        match self {
+            VoiceType::Fm => FmVoice::parameters(),
+            VoiceType::Subtractive => SubtractiveVoice::parameters(),
+            VoiceType::Karplus => KarplusVoice::parameters(),
+            VoiceType::DrumKit => DrumKit::parameters(),
+            VoiceType::Plaits => BLITVoice::parameters(),
+            VoiceType::Sampler => SamplerVoice::parameters(),
+            VoiceType::Modal => ModalVoice::parameters(),
+            VoiceType::Organ => OrganVoice::parameters(),
+        }
+    }
+}
+
+pub fn create_voice(voice_type: VoiceType, sample_rate: f32) -> Box<dyn SynthVoice> {
+    let mut voice: Box<dyn SynthVoice> = match voice_type {
+        VoiceType::Fm => Box::new(FmVoice::new(sample_rate)),
+        VoiceType::Subtractive => Box::new(SubtractiveVoice::new(sample_rate)),
+        VoiceType::Karplus => Box::new(KarplusVoice::new(sample_rate)),
+        VoiceType::DrumKit => Box::new(DrumKit::new(sample_rate)),
+        VoiceType::Plaits => Box::new(BLITVoice::new(sample_rate)),
+        VoiceType::Sampler => Box::new(SamplerVoice::new(sample_rate)),
+        VoiceType::Modal => Box::new(ModalVoice::new(sample_rate)),
+        VoiceType::Organ => Box::new(OrganVoice::new(sample_rate)),
+    };
+    voice.init();
+    voice
+}
+
+pub trait SynthVoice: Send {
+    fn new(sample_rate: f32) -> Self
+    where
+        Self: Sized;
     fn init(&mut self);
     fn get_pitch(&self) -> u8;
     fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32);
+    /// Change the pitch of the sounding note without retriggering it
+    fn set_pitch(&mut self, pitch: f32);
     fn stop(&mut self);
     fn set_parameter(&mut self, parameter: i8, value: f32);
+
+    /// Stop the note at `pitch`, if it's the one sounding
+    fn stop_note(&mut self, pitch: u8) {
+        if self.get_pitch() == pitch {
+            self.stop();
+        }
+    }
+
     fn reset(&mut self);
     fn is_active(&self) -> bool;
     fn process(&mut self) -> f32;
+
+    /// Set a parameter from a normalized 0.0..1.0 value (e.g. a MIDI CC)
+    fn set_parameter_normalized(&mut self, parameter: i8, value: f32) {
+        self.set_parameter(parameter, value);
+    }
+
+    fn get_parameter(&self, _parameter: i8) -> f32 {
+        0.0
+    }
+
+    /// number of parameters addressable through `set_parameter`
+    fn parameter_count(&self) -> i8 {
+        0
+    }
+
+    /// Whether a parameter selects between discrete settings (a mode, a
+    /// type) and has to change at once rather than be smoothed
+    fn is_stepped(&self, _parameter: i8) -> bool {
+        false
+    }
+
+    /// Pitch bend as a frequency ratio offset
+    fn set_pitch_bend(&mut self, _bend: f32) {}
+
+    /// What notes played over a sounding note do to its envelopes
+    fn set_retrigger_mode(&mut self, _mode: RetriggerMode) {}
+
+    fn set_pressure(&mut self, _pressure: f32) {}
+
+    fn set_expression(&mut self, _expression: NoteExpression) {}
+
+    fn expression(&self) -> NoteExpression {
+        NoteExpression::default()
+    }
+
+    fn mod_matrix(&self) -> Option<&ModMatrix> {
+        None
+    }
+
+    fn mod_matrix_mut(&mut self) -> Option<&mut ModMatrix> {
+        None
+    }
+
+    fn set_mod_slot(&mut self, index: usize, slot: ModSlot) {
+        if let Some(matrix) = self.mod_matrix_mut() {
+            matrix.set_slot(index, slot);
+        }
+    }
+
+    fn set_mod_processor(&mut self, index: usize, processor: ModProcessor) {
+        if let Some(matrix) = self.mod_matrix_mut() {
+            matrix.set_processor(index, processor);
+        }
+    }
+
+    /// Reverb and delay send levels
+    fn sends(&self) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+
+    /// Give sample based voices their sample data
+    fn set_sample(&mut self, _sample: Arc<Sample>) {}
+
+    fn sample(&self) -> Option<Arc<Sample>> {
+        None
+    }
+
+    /// Tempo for tempo-synced playback, as samples per beat
+    fn set_tempo(&mut self, _samples_per_beat: f32) {}
+
+    /// Points the sample is chopped at, normalized to its length
+    fn set_slices(&mut self, _slices: Arc<[f32]>) {}
+
+    fn slices(&self) -> &[f32] {
+        &[]
+    }
 }
 
-pub struct Synth<V: SynthVoice> {
-    // voices: Vec<SynthVoice>,
-    voices: Vec<V>,
+/// Several voices of one type behind a single `SynthVoice`, for tracks
+/// that play chords. Notes go to the voices in turn, settings go to all of
+/// them.
+pub struct PolyVoice {
+    voices: Vec<Box<dyn SynthVoice>>,
+    // voice the next note goes to, and the one that played the last
+    next: usize,
+    last: usize,
+}
+
+impl PolyVoice {
+    /// `voices` has to hold at least one voice, set up alike
+    pub fn with_voices(voices: Vec<Box<dyn SynthVoice>>) -> Self {
+        assert!(!voices.is_empty());
+        Self {
+            voices,
+            next: 0,
+            last: 0,
+        }
+    }
+}
+
+impl SynthVoice for PolyVoice {
+    fn new(sample_rate: f32) -> Self {
+        Self::with_voices(
+            (0..MAX_CHORD_NOTES)
+                .map(|_| create_voice(VoiceType::default(), sample_rate))
+                .collect(),
+        )
+    }
+
+    fn init(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.init();
+        }
+    }
+
+    fn get_pitch(&self) -> u8 {
+        self.voices[self.last].get_pitch()
+    }
+
+    fn play(&mut self, pitch: u8, velocity: u8, param1: f32, param2: f32) {
+        self.voices[self.next].play(pitch, velocity, param1, param2);
+        self.last = self.next;
+        self.next = (self.next + 1) % self.voices.len();
+    }
+
+    fn set_pitch(&mut self, pitch: f32) {
+        self.voices[self.last].set_pitch(pitch);
+    }
+
+    fn stop(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.stop();
+        }
+    }
+
+    fn stop_note(&mut self, pitch: u8) {
+        for voice in self.voices.iter_mut() {
+            voice.stop_note(pitch);
+        }
+    }
+
+    fn set_parameter(&mut self, parameter: i8, value: f32) {
+        for voice in self.voices.iter_mut() {
+            voice.set_parameter(parameter, value);
+        }
+    }
+
+    fn reset(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.reset();
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.voices.iter().any(|voice| voice.is_active())
+    }
+
+    fn process(&mut self) -> f32 {
+        self.voices
+            .iter_mut()
+            .filter(|voice| voice.is_active())
+            .map(|voice| voice.process())
+            .sum()
+    }
+
+    fn set_parameter_normalized(&mut self, parameter: i8, value: f32) {
+        for voice in self.voices.iter_mut() {
+            voice.set_parameter_normalized(parameter, value);
+        }
+    }
+
+    fn get_parameter(&self, parameter: i8) -> f32 {
+        self.voices[0].get_parameter(parameter)
+    }
+
+    fn parameter_count(&self) -> i8 {
+        self.voices[0].parameter_count()
+    }
+
+    fn is_stepped(&self, parameter: i8) -> bool {
+        self.voices[0].is_stepped(parameter)
+    }
+
+    fn set_pitch_bend(&mut self, bend: f32) {
+        for voice in self.voices.iter_mut() {
+            voice.set_pitch_bend(bend);
+        }
+    }
+
+    fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        for voice in self.voices.iter_mut() {
+            voice.set_retrigger_mode(mode);
+        }
+    }
+
+    fn set_pressure(&mut self, pressure: f32) {
+        for voice in self.voices.iter_mut() {
+            voice.set_pressure(pressure);
+        }
+    }
+
+    fn set_expression(&mut self, expression: NoteExpression) {
+        for voice in self.voices.iter_mut() {
+            voice.set_expression(expression);
+        }
+    }
+
+    fn expression(&self) -> NoteExpression {
+        self.voices[self.last].expression()
+    }
+
+    fn mod_matrix(&self) -> Option<&ModMatrix> {
+        self.voices[0].mod_matrix()
+    }
+
+    fn set_mod_slot(&mut self, index: usize, slot: ModSlot) {
+        for voice in self.voices.iter_mut() {
+            voice.set_mod_slot(index, slot);
+        }
+    }
+
+    fn set_mod_processor(&mut self, index: usize, processor: ModProcessor) {
+        for voice in self.voices.iter_mut() {
+            voice.set_mod_processor(index, processor);
+        }
+    }
+
+    fn sends(&self) -> (f32, f32) {
+        self.voices[0].sends()
+    }
+
+    fn set_sample(&mut self, sample: Arc<Sample>) {
+        for voice in self.voices.iter_mut() {
+            voice.set_sample(sample.clone());
+        }
+    }
+
+    fn sample(&self) -> Option<Arc<Sample>> {
+        self.voices[0].sample()
+    }
+
+    fn set_tempo(&mut self, samples_per_beat: f32) {
+        for voice in self.voices.iter_mut() {
+            voice.set_tempo(samples_per_beat);
+        }
+    }
+
+    fn set_slices(&mut self, slices: Arc<[f32]>) {
+        for voice in self.voices.iter_mut() {
+            voice.set_slices(slices.clone());
+        }
+    }
+
+    fn slices(&self) -> &[f32] {
+        self.voices[0].slices()
+    }
+}
+
+pub struct Synth {
+    voices: Vec<Box<dyn SynthVoice>>,
     current_voice_index: usize,
     rev_l: Reverb,
     rev_r: Reverb,
     rev_level: f32,
+    sample_rate: f32,
 }
 
-impl<V: SynthVoice> Synth<V> {
-    pub fn new() -> Self {
-        let mut voices = Vec::new();
+impl Synth {
+    pub fn new(voice_type: VoiceType) -> Self {
         let sample_rate = 48000.0;
-        for _ in 0..VOICE_COUNT {
-            let mut voice = V::new(sample_rate);
-            voice.init();
-            voices.push(voice);
-        }
+        let voices = (0..VOICE_COUNT)
+            .map(|_| create_voice(voice_type, sample_rate))
+            .collect();
 
         Self {
             voices,
@@ -39,6 +381,7 @@ impl<V: SynthVoice> Synth<V> {
             rev_l: Reverb::new(sample_rate),
             rev_r: Reverb::new(sample_rate),
             rev_level: 1.0,
+            sample_rate,
         }
     }
 
@@ -69,9 +412,15 @@ impl<V: SynthVoice> Synth<V> {
         mix
     }
 
+    /// Replace all voices with voices of another type, unknown types are ignored
     pub fn set_sound(&mut self, sound: i8) {
-        // TODO: change voice type
-        todo!()
+        let Some(voice_type) = VoiceType::from_u8(sound as u8) else {
+            return;
+        };
+        for voice in self.voices.iter_mut() {
+            *voice = create_voice(voice_type, self.sample_rate);
+        }
+        self.current_voice_index = 0;
     }
 
     pub(crate) fn set_parameter(&mut self, parameter: i8, value: f32) {
@@ -84,12 +433,74 @@ impl<V: SynthVoice> Synth<V> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::subtractive::SubtractiveVoice;
+    use crate::parameters::ParameterCurve;
 
     #[test]
     fn new_creates_synth() {
-        let synth = Synth::<SubtractiveVoice>::new();
+        let synth = Synth::new(VoiceType::Subtractive);
         assert_eq!(synth.voices.len(), VOICE_COUNT);
         assert_eq!(synth.current_voice_index, 0);
     }
+
+    #[test]
+    fn set_sound_switches_voices() {
+        let mut synth = Synth::new(VoiceType::Fm);
+        assert_eq!(
+            synth.voices[0].parameter_count(),
+            crate::plaits_voice::PARAMETER_COUNT
+        );
+        synth.set_sound(VoiceType::Subtractive as i8);
+        assert_eq!(synth.voices[0].parameter_count(), 14);
+
+        synth.set_sound(100);
+        synth.play(60, 100, 0.0, 0.0);
+        assert!(synth.voices[0].is_active());
+    }
+
+    #[test]
+    fn poly_voice_spreads_notes() {
+        let mut poly = PolyVoice::with_voices(
+            (0..3)
+                .map(|_| create_voice(VoiceType::Subtractive, 48000.0))
+                .collect(),
+        );
+        poly.set_parameter(4, 0.25);
+        for pitch in [60, 64, 67] {
+            poly.play(pitch, 100, 0.0, 0.0);
+        }
+        let pitches: Vec<u8> = poly.voices.iter().map(|v| v.get_pitch()).collect();
+        assert_eq!(pitches, [60, 64, 67]);
+        assert_eq!(poly.get_pitch(), 67);
+        assert!(poly.voices.iter().all(|v| v.get_parameter(4) == 0.25));
+
+        // the oldest voice takes the next note
+        poly.play(72, 100, 0.0, 0.0);
+        assert_eq!(poly.voices[0].get_pitch(), 72);
+    }
+
+    #[test]
+    fn parameter_info_matches_voices() {
+        for voice_type in (0..).map_while(VoiceType::from_u8) {
+            let voice = create_voice(voice_type, 48000.0);
+            let parameters = voice_type.parameters();
+            assert!(!parameters.is_empty());
+            for info in parameters {
+                assert!(info.id < voice.parameter_count());
+                let value = voice.get_parameter(info.id);
+                assert!(
+                    (value - info.default).abs() <= 1e-4 * info.default.abs().max(1.0),
+                    "{:?} {}: {} != {}",
+                    voice_type,
+                    info.name,
+                    value,
+                    info.default
+                );
+                assert!(info.min <= info.default && info.default <= info.max);
+                assert_eq!(
+                    voice.is_stepped(info.id),
+                    info.curve == ParameterCurve::Stepped
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,203 @@
+//! Tuning
+//!
+//! Maps MIDI notes to the fractional pitches voices play, in the
+//! equal-tempered semitones `fractional_pitch_to_freq` expects. A master
+//! tune in cents moves everything, like changing the A4 reference, and a
+//! Scala (.scl) scale retunes the notes around a root note, which keeps its
+//! equal-tempered pitch.
+
+use crate::consts::A4_FREQ;
+use crate::utils::ratio_to_cents;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A Scala scale: the steps of one period, in cents above the root
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalaScale {
+    pub description: String,
+    // every degree but the root, the last is the period
+    cents: Vec<f64>,
+}
+
+impl ScalaScale {
+    /// Parse the text of a .scl file. Pitches with a period are in cents,
+    /// the rest are ratios like `3/2` or whole numbers like `2`.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        let description = lines
+            .next()
+            .ok_or_else(|| invalid("missing description"))?
+            .trim()
+            .to_string();
+        let count: usize = lines
+            .next()
+            .and_then(|line| line.split_whitespace().next())
+            .and_then(|count| count.parse().ok())
+            .ok_or_else(|| invalid("missing note count"))?;
+
+        let cents = lines
+            .filter_map(|line| line.split_whitespace().next())
+            .take(count)
+            .map(|pitch| parse_pitch(pitch).ok_or_else(|| invalid("invalid pitch")))
+            .collect::<io::Result<Vec<f64>>>()?;
+        if count == 0 || cents.len() != count {
+            return Err(invalid("wrong number of pitches"));
+        }
+        if cents[count - 1] <= 0.0 {
+            return Err(invalid("period has to rise"));
+        }
+        Ok(Self { description, cents })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Notes per period
+    pub fn len(&self) -> usize {
+        self.cents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cents.is_empty()
+    }
+
+    /// Cents above the root of a number of scale steps, negative below it
+    pub fn cents(&self, steps: i32) -> f64 {
+        let len = self.cents.len() as i32;
+        let period = steps.div_euclid(len);
+        let degree = steps.rem_euclid(len) as usize;
+        let degree_cents = if degree == 0 {
+            0.0
+        } else {
+            self.cents[degree - 1]
+        };
+        period as f64 * self.cents[self.cents.len() - 1] + degree_cents
+    }
+}
+
+/// A pitch in cents, or as a ratio
+fn parse_pitch(pitch: &str) -> Option<f64> {
+    if pitch.contains('.') {
+        return pitch.parse().ok();
+    }
+    let (numerator, denominator) = pitch.split_once('/').unwrap_or((pitch, "1"));
+    let ratio = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (ratio > 0.0 && ratio.is_finite()).then(|| 1200.0 * ratio.log2())
+}
+
+/// Master tune in cents putting A4 at `freq`
+pub fn reference_cents(freq: f32) -> f32 {
+    ratio_to_cents(freq / A4_FREQ)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tuning {
+    master_cents: f32,
+    scale: Option<Arc<ScalaScale>>,
+    root: u8,
+}
+
+impl Tuning {
+    /// Master tune in cents, -100.0..100.0
+    pub fn set_master_cents(&mut self, cents: f32) {
+        self.master_cents = cents.clamp(-100.0, 100.0);
+    }
+
+    pub fn master_cents(&self) -> f32 {
+        self.master_cents
+    }
+
+    /// Master tune putting A4 at `freq`
+    pub fn set_reference(&mut self, freq: f32) {
+        self.set_master_cents(reference_cents(freq));
+    }
+
+    /// Retune the notes to `scale`, `root` keeping its pitch; none for
+    /// equal temperament
+    pub fn set_scale(&mut self, scale: Option<Arc<ScalaScale>>, root: u8) {
+        self.scale = scale;
+        self.root = root.min(127);
+    }
+
+    pub fn scale(&self) -> Option<&ScalaScale> {
+        self.scale.as_deref()
+    }
+
+    /// The fractional pitch `note` plays at
+    pub fn pitch(&self, note: u8) -> f32 {
+        let pitch = match &self.scale {
+            Some(scale) => {
+                let steps = note as i32 - self.root as i32;
+                self.root as f32 + (scale.cents(steps) / 100.0) as f32
+            }
+            None => note as f32,
+        };
+        pitch + self.master_cents / 100.0
+    }
+
+    /// Whether notes play at their equal-tempered pitches
+    pub fn is_equal_tempered(&self) -> bool {
+        self.scale.is_none() && self.master_cents == 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fractional_pitch_to_freq;
+
+    const PENTATONIC: &str = "! slendro.scl
+!
+Just pentatonic
+ 5
+!
+ 9/8
+ 5/4
+ 3/2
+ 5/3
+ 2/1
+";
+
+    #[test]
+    fn parses_scala_files() {
+        let scale = ScalaScale::parse(PENTATONIC).unwrap();
+        assert_eq!(scale.description, "Just pentatonic");
+        assert_eq!(scale.len(), 5);
+        assert!((scale.cents(3) - 701.955).abs() < 1e-3);
+        assert!((scale.cents(5) - 1200.0).abs() < 1e-9);
+        assert!((scale.cents(-1) - (1200.0 * (5.0f64 / 3.0).log2() - 1200.0)).abs() < 1e-9);
+
+        let cents = ScalaScale::parse("cents\n2\n150.0 three quarter tone\n1200.\n").unwrap();
+        assert_eq!(cents.cents(1), 150.0);
+
+        assert!(ScalaScale::parse("").is_err());
+        assert!(ScalaScale::parse("short\n3\n100.0\n").is_err());
+        assert!(ScalaScale::parse("bad\n1\nabc\n").is_err());
+    }
+
+    #[test]
+    fn scales_map_around_the_root() {
+        let mut tuning = Tuning::default();
+        assert!(tuning.is_equal_tempered());
+        assert_eq!(tuning.pitch(64), 64.0);
+
+        let scale = Arc::new(ScalaScale::parse(PENTATONIC).unwrap());
+        tuning.set_scale(Some(scale), 60);
+        assert_eq!(tuning.pitch(60), 60.0);
+        assert!((tuning.pitch(63) - 67.01955).abs() < 1e-4);
+        assert!((tuning.pitch(55) - 48.0).abs() < 1e-4);
+        assert!(!tuning.is_equal_tempered());
+    }
+
+    #[test]
+    fn reference_sets_the_master_tune() {
+        let mut tuning = Tuning::default();
+        tuning.set_reference(432.0);
+        assert!((fractional_pitch_to_freq(tuning.pitch(69)) - 432.0).abs() < 1e-2);
+        tuning.set_master_cents(500.0);
+        assert_eq!(tuning.master_cents(), 100.0);
+    }
+}
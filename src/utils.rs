@@ -5,10 +5,128 @@ pub fn pitch_to_freq(pitch: u8) -> f32 {
     A4_FREQ * (2f32).powf((pitch as f32 - A4_MIDI as f32) as f32 / 12.0)
 }
 
+/// Frequency of a fractional MIDI pitch
+pub fn fractional_pitch_to_freq(pitch: f32) -> f32 {
+    A4_FREQ * (2f32).powf((pitch - A4_MIDI as f32) / 12.0)
+}
+
 pub fn freq_to_pitch(freq: f32) -> u8 {
     ((freq / A4_FREQ).log2() * 12.0 + A4_MIDI as f32).round() as u8
 }
 
+/// Fractional MIDI pitch of a frequency
+pub fn freq_to_fractional_pitch(freq: f32) -> f32 {
+    (freq / A4_FREQ).log2() * 12.0 + A4_MIDI as f32
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// semitones of the natural notes above C
+const NATURALS: [(char, u8); 7] = [
+    ('C', 0),
+    ('D', 2),
+    ('E', 4),
+    ('F', 5),
+    ('G', 7),
+    ('A', 9),
+    ('B', 11),
+];
+
+/// Pitch class of a MIDI pitch, 0 for C to 11 for B
+pub fn pitch_class(pitch: u8) -> u8 {
+    pitch % 12
+}
+
+/// Octave of a MIDI pitch, with middle C (60) in octave 4
+pub fn octave(pitch: u8) -> i8 {
+    (pitch / 12) as i8 - 1
+}
+
+/// Name of a MIDI pitch, with sharps, e.g. "C4" for 60 and "F#-1" for 6
+pub fn note_name(pitch: u8) -> String {
+    format!(
+        "{}{}",
+        NOTE_NAMES[pitch_class(pitch) as usize],
+        octave(pitch)
+    )
+}
+
+/// MIDI pitch of a note name like "C4", "f#3" or "Bb-1"; `#` and `b` can
+/// be repeated. None if it's malformed or out of range.
+pub fn parse_note_name(name: &str) -> Option<u8> {
+    let mut chars = name.trim().chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let &(_, natural) = NATURALS.iter().find(|(n, _)| *n == letter)?;
+    let rest = chars.as_str();
+    let accidentals = rest.len() - rest.trim_start_matches(['#', 'b']).len();
+    let alteration: i32 = rest[..accidentals]
+        .chars()
+        .map(|c| if c == '#' { 1 } else { -1 })
+        .sum();
+    let octave: i32 = rest[accidentals..].parse().ok()?;
+    let pitch = (octave + 1) * 12 + natural as i32 + alteration;
+    u8::try_from(pitch).ok().filter(|&p| p <= 127)
+}
+
+/// A MIDI pitch moved by `semitones`, None when it leaves the MIDI range
+pub fn transpose(pitch: u8, semitones: i32) -> Option<u8> {
+    u8::try_from(pitch as i32 + semitones)
+        .ok()
+        .filter(|&p| p <= 127)
+}
+
+/// Short name of an interval, e.g. "m3" or "P5", reduced to an octave;
+/// descending intervals are named as ascending ones
+pub fn interval_name(semitones: i32) -> &'static str {
+    const NAMES: [&str; 12] = [
+        "P1", "m2", "M2", "m3", "M3", "P4", "TT", "P5", "m6", "M6", "m7", "M7",
+    ];
+    let reduced = semitones.unsigned_abs() % 12;
+    if reduced == 0 && semitones != 0 {
+        "P8"
+    } else {
+        NAMES[reduced as usize]
+    }
+}
+
+/// Frequency ratio of an interval in cents
+pub fn cents_to_ratio(cents: f32) -> f32 {
+    (2f32).powf(cents / 1200.0)
+}
+
+/// Interval in cents of a frequency ratio
+pub fn ratio_to_cents(ratio: f32) -> f32 {
+    1200.0 * ratio.log2()
+}
+
+/// Cents from one frequency up to another, negative when it's lower
+pub fn cents_between(from: f32, to: f32) -> f32 {
+    ratio_to_cents(to / from)
+}
+
+/// Length of a beat in samples at `tempo` (bpm)
+pub fn samples_per_beat(tempo: f32, sample_rate: f32) -> f32 {
+    60.0 / tempo * sample_rate
+}
+
+pub fn beats_to_samples(beats: f32, tempo: f32, sample_rate: f32) -> f32 {
+    beats / tempo * 60.0 * sample_rate
+}
+
+pub fn samples_to_beats(samples: f32, tempo: f32, sample_rate: f32) -> f32 {
+    samples / sample_rate * tempo / 60.0
+}
+
+pub fn beats_to_seconds(beats: f32, tempo: f32) -> f32 {
+    beats * 60.0 / tempo
+}
+
+pub fn seconds_to_beats(seconds: f32, tempo: f32) -> f32 {
+    seconds * tempo / 60.0
+}
+
 pub fn freq_to_period(sample_rate: f32, freq: f32) -> f32 {
     sample_rate / freq
 }
@@ -30,6 +148,83 @@ pub fn lin_to_log(lin: f32, lin_min: f32, lin_max: f32, min_log: f32, max_log: f
     return min_log * (max_log / min_log).powf(lin_norm);
 }
 
+// values smaller than this are inaudible and flushed to zero, well before
+// they reach the denormal range
+const DENORMAL_THRESHOLD: f32 = 1e-15;
+
+/// Flush tiny values to zero, so decaying feedback paths don't end up in
+/// denormals, which are very slow to compute on x86
+#[inline]
+pub fn undenormalize(x: f32) -> f32 {
+    if x.abs() < DENORMAL_THRESHOLD {
+        0.0
+    } else {
+        x
+    }
+}
+
+/// Makes the CPU flush denormals to zero for as long as it lives, and
+/// restores the previous floating point mode when dropped. Hold one for
+/// the duration of each render call.
+pub struct DenormalGuard {
+    #[allow(dead_code)]
+    previous: u64,
+}
+
+impl DenormalGuard {
+    #[cfg(target_arch = "x86_64")]
+    pub fn new() -> Self {
+        // flush-to-zero (bit 15) and denormals-are-zero (bit 6) in MXCSR
+        const FTZ_DAZ: u32 = 0x8040;
+        let mut csr: u32 = 0;
+        unsafe {
+            std::arch::asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack));
+            let flushed = csr | FTZ_DAZ;
+            std::arch::asm!("ldmxcsr [{}]", in(reg) &flushed, options(nostack));
+        }
+        Self {
+            previous: csr as u64,
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn new() -> Self {
+        // flush-to-zero (bit 24) in FPCR
+        const FZ: u64 = 1 << 24;
+        let fpcr: u64;
+        unsafe {
+            std::arch::asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack));
+            std::arch::asm!("msr fpcr, {}", in(reg) fpcr | FZ, options(nomem, nostack));
+        }
+        Self { previous: fpcr }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn new() -> Self {
+        Self { previous: 0 }
+    }
+}
+
+impl Default for DenormalGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DenormalGuard {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            let csr = self.previous as u32;
+            std::arch::asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack));
+        }
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            std::arch::asm!("msr fpcr, {}", in(reg) self.previous, options(nomem, nostack));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,10 +236,90 @@ mod tests {
         assert_eq!(pitch_to_freq(127), 12543.855);
     }
 
+    #[test]
+    fn flushes_tiny_values() {
+        assert_eq!(undenormalize(1e-20), 0.0);
+        assert_eq!(undenormalize(-1e-20), 0.0);
+        assert_eq!(undenormalize(1e-6), 1e-6);
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn guard_flushes_denormals() {
+        let tiny = std::hint::black_box(f32::MIN_POSITIVE);
+        let half = std::hint::black_box(0.5);
+        assert!(tiny * half > 0.0);
+        {
+            let _guard = DenormalGuard::new();
+            assert_eq!(std::hint::black_box(tiny) * half, 0.0);
+        }
+        // the previous mode is restored
+        assert!(tiny * half > 0.0);
+    }
+
     #[test]
     fn test_freq_to_midi() {
         assert_eq!(freq_to_pitch(8.17), 0);
         assert_eq!(freq_to_pitch(440.0), 69);
         assert_eq!(freq_to_pitch(12543.855), 127);
+        assert!((freq_to_fractional_pitch(452.893) - 69.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn names_notes() {
+        assert_eq!(note_name(60), "C4");
+        assert_eq!(note_name(69), "A4");
+        assert_eq!(note_name(6), "F#-1");
+        assert_eq!(note_name(127), "G9");
+        assert_eq!((pitch_class(61), octave(61)), (1, 4));
+    }
+
+    #[test]
+    fn parses_note_names() {
+        assert_eq!(parse_note_name("C4"), Some(60));
+        assert_eq!(parse_note_name("a4"), Some(69));
+        assert_eq!(parse_note_name("Bb3"), Some(58));
+        assert_eq!(parse_note_name("F##2"), Some(43));
+        assert_eq!(parse_note_name("C-1"), Some(0));
+        assert_eq!(parse_note_name(" G9 "), Some(127));
+        assert_eq!(parse_note_name("Cb-1"), None);
+        assert_eq!(parse_note_name("G#9"), None);
+        assert_eq!(parse_note_name("H2"), None);
+        assert_eq!(parse_note_name("C"), None);
+        assert_eq!(parse_note_name(""), None);
+        // names round trip
+        for pitch in 0..=127 {
+            assert_eq!(parse_note_name(&note_name(pitch)), Some(pitch));
+        }
+    }
+
+    #[test]
+    fn interval_math() {
+        assert_eq!(transpose(60, 7), Some(67));
+        assert_eq!(transpose(60, -61), None);
+        assert_eq!(transpose(120, 8), None);
+        assert_eq!(interval_name(7), "P5");
+        assert_eq!(interval_name(-3), "m3");
+        assert_eq!(interval_name(15), "m3");
+        assert_eq!(interval_name(12), "P8");
+        assert_eq!(interval_name(0), "P1");
+    }
+
+    #[test]
+    fn cents_and_ratios() {
+        assert!((cents_to_ratio(1200.0) - 2.0).abs() < 1e-6);
+        assert!((cents_to_ratio(-1200.0) - 0.5).abs() < 1e-6);
+        assert!((ratio_to_cents(1.5) - 701.955).abs() < 1e-3);
+        assert!((cents_between(440.0, 880.0) - 1200.0).abs() < 1e-3);
+        assert!((cents_between(440.0, 415.305) + 100.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn beats_and_time() {
+        assert_eq!(samples_per_beat(120.0, 48000.0), 24000.0);
+        assert_eq!(beats_to_samples(4.0, 120.0, 48000.0), 96000.0);
+        assert_eq!(samples_to_beats(96000.0, 120.0, 48000.0), 4.0);
+        assert_eq!(beats_to_seconds(3.0, 90.0), 2.0);
+        assert_eq!(seconds_to_beats(2.0, 90.0), 3.0);
     }
 }
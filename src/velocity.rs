@@ -0,0 +1,69 @@
+//! Velocity curves
+//!
+//! Each track maps the velocity of the notes it plays through a curve
+//! before they reach its voice, so a drum track can ignore velocity while a
+//! melodic track stays dynamic, or a soft keyboard can be made to reach
+//! full level.
+
+/// entries in a custom velocity table, one per MIDI velocity
+pub const VELOCITY_TABLE_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    /// squared, for more control over quiet notes
+    Exponential,
+    /// every note plays at this velocity
+    Fixed(u8),
+    /// the velocity played for each incoming velocity
+    Table([u8; VELOCITY_TABLE_SIZE]),
+}
+
+impl VelocityCurve {
+    /// 0: linear, 1: exponential, 2: fixed at `value`
+    pub fn from_u8(curve: u8, value: u8) -> Option<Self> {
+        match curve {
+            0 => Some(VelocityCurve::Linear),
+            1 => Some(VelocityCurve::Exponential),
+            2 => Some(VelocityCurve::Fixed(value.min(127))),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let velocity = velocity.min(127);
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Exponential => {
+                let x = velocity as f32 / 127.0;
+                // loud enough to be heard at the lowest velocities
+                ((x * x * 127.0).round() as u8).max(velocity.min(1))
+            }
+            VelocityCurve::Fixed(value) => *value,
+            VelocityCurve::Table(table) => table[velocity as usize].min(127),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_map_velocities() {
+        assert_eq!(VelocityCurve::Linear.apply(64), 64);
+        assert_eq!(VelocityCurve::Exponential.apply(127), 127);
+        assert_eq!(VelocityCurve::Exponential.apply(64), 32);
+        assert_eq!(VelocityCurve::Exponential.apply(1), 1);
+        assert_eq!(VelocityCurve::from_u8(2, 100).unwrap().apply(10), 100);
+
+        let mut table = [0; VELOCITY_TABLE_SIZE];
+        for (velocity, y) in table.iter_mut().enumerate() {
+            *y = 127 - velocity as u8;
+        }
+        assert_eq!(VelocityCurve::Table(table).apply(27), 100);
+        assert_eq!(VelocityCurve::from_u8(3, 0), None);
+    }
+}
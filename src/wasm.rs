@@ -0,0 +1,213 @@
+//! WebAssembly bindings
+//!
+//! In the browser each `WasmEngine` owns its engine and the sending end of
+//! its channel, and is driven from an AudioWorklet's `process`. Callbacks
+//! are JavaScript functions; an AudioWorklet runs on a single thread, so
+//! they are kept in thread locals and reached through the same callback
+//! hooks the C API uses.
+
+use crate::engine::Engine;
+use crate::sequencer::{Event, Message, NoteExpression};
+use crate::synth::VoiceType;
+use crate::{next_event_id, MESSAGE_CAPACITY, NEXT_NOTE_ID};
+use crossbeam::channel;
+use js_sys::{Array, Function};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::sync::atomic::Ordering;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static PROGRESS_FUNCTION: RefCell<Option<Function>> = const { RefCell::new(None) };
+    static NOTE_FUNCTION: RefCell<Option<Function>> = const { RefCell::new(None) };
+    static TRANSPORT_FUNCTION: RefCell<Option<Function>> = const { RefCell::new(None) };
+}
+
+extern "C" fn call_progress_function(_: *mut c_void, bar: u32, beat: u32, tick: u32, beats: f32) {
+    PROGRESS_FUNCTION.with(|function| {
+        if let Some(function) = function.borrow().as_ref() {
+            let args = Array::of4(&bar.into(), &beat.into(), &tick.into(), &beats.into());
+            let _ = function.apply(&JsValue::NULL, &args);
+        }
+    });
+}
+
+extern "C" fn call_note_function(
+    _: *mut c_void,
+    note_on: bool,
+    pitch: u8,
+    track: u8,
+    frame: u32,
+    beats: f32,
+) {
+    NOTE_FUNCTION.with(|function| {
+        if let Some(function) = function.borrow().as_ref() {
+            let args = Array::of5(
+                &note_on.into(),
+                &pitch.into(),
+                &track.into(),
+                &frame.into(),
+                &beats.into(),
+            );
+            let _ = function.apply(&JsValue::NULL, &args);
+        }
+    });
+}
+
+extern "C" fn call_transport_function(_: *mut c_void, is_playing: bool, beats: f32) {
+    TRANSPORT_FUNCTION.with(|function| {
+        if let Some(function) = function.borrow().as_ref() {
+            let _ = function.call2(&JsValue::NULL, &is_playing.into(), &beats.into());
+        }
+    });
+}
+
+#[wasm_bindgen]
+pub struct WasmEngine {
+    engine: Engine,
+    sender: channel::Sender<Message>,
+}
+
+#[wasm_bindgen]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: f32) -> WasmEngine {
+        let (sender, receiver) = channel::bounded(MESSAGE_CAPACITY);
+        WasmEngine {
+            engine: Engine::new(receiver, sample_rate),
+            sender,
+        }
+    }
+
+    fn send(&self, msg: Message) {
+        // a full queue drops the message rather than block the worklet
+        let _ = self.sender.try_send(msg);
+    }
+
+    pub fn set_playing(&mut self, is_playing: bool) {
+        self.engine.set_playing(is_playing);
+    }
+
+    /// Returns the event's id
+    pub fn add_event(
+        &self,
+        beat_time: f32,
+        pitch: u8,
+        velocity: u8,
+        duration: f32,
+        track: u8,
+    ) -> u32 {
+        let id = next_event_id();
+        self.send(Message::Schedule(Event {
+            id,
+            beat_time,
+            pitch,
+            velocity,
+            duration,
+            track,
+            ..Default::default()
+        }));
+        id
+    }
+
+    pub fn remove_event(&self, id: u32) {
+        self.send(Message::RemoveEvent(id));
+    }
+
+    /// Returns the note's id
+    pub fn note_on(&self, track: u8, pitch: u8, velocity: u8) -> u32 {
+        let id = NEXT_NOTE_ID.fetch_add(1, Ordering::Relaxed);
+        self.send(Message::NoteOn {
+            id,
+            track,
+            pitch,
+            velocity,
+            expression: NoteExpression::default(),
+            frame: 0,
+        });
+        id
+    }
+
+    pub fn note_off(&self, track: u8, pitch: u8) {
+        self.send(Message::NoteOff {
+            track,
+            pitch,
+            frame: 0,
+        });
+    }
+
+    pub fn set_sound(&self, track: u8, voice_type: u8) {
+        if let Some(voice_type) = VoiceType::from_u8(voice_type) {
+            self.send(Message::SetSound { track, voice_type });
+        }
+    }
+
+    pub fn set_parameter(&self, track: u8, parameter: i8, value: f32) {
+        self.send(Message::ParameterChange(parameter, value, track));
+    }
+
+    /// Render a block into the two channels, which have to be the same length
+    pub fn render(&mut self, left: &mut [f32], right: &mut [f32], sample_time: f64, tempo: f32) {
+        let frames = left.len().min(right.len());
+        self.engine.process(
+            &mut left[..frames],
+            &mut right[..frames],
+            sample_time as i64,
+            tempo,
+            frames as i32,
+        );
+    }
+
+    /// `function(bar, beat, tick, beats)`, null to remove
+    pub fn set_playback_progress_callback(&self, function: Option<Function>) {
+        let is_set = function.is_some();
+        PROGRESS_FUNCTION.with(|f| *f.borrow_mut() = function);
+        if is_set {
+            self.engine
+                .shared()
+                .set_progress_callback(call_progress_function, std::ptr::null_mut());
+        }
+    }
+
+    /// `function(note_on, pitch, track, frame, beats)`, null to remove
+    pub fn set_note_played_callback(&self, function: Option<Function>) {
+        let is_set = function.is_some();
+        NOTE_FUNCTION.with(|f| *f.borrow_mut() = function);
+        if is_set {
+            self.engine
+                .shared()
+                .set_note_callback(call_note_function, std::ptr::null_mut());
+        }
+    }
+
+    /// `function(is_playing, beats)`, null to remove
+    pub fn set_transport_callback(&self, function: Option<Function>) {
+        let is_set = function.is_some();
+        TRANSPORT_FUNCTION.with(|f| *f.borrow_mut() = function);
+        if is_set {
+            self.engine
+                .shared()
+                .set_transport_callback(call_transport_function, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engines_have_their_own_messages() {
+        let mut engine = WasmEngine::new(48000.0);
+        let other = WasmEngine::new(48000.0);
+        engine.add_event(0.0, 60, 100, 0.5, 0);
+        other.add_event(1.0, 60, 100, 0.5, 0);
+        engine.set_playing(true);
+
+        let mut left = vec![0.0; 4800];
+        let mut right = vec![0.0; 4800];
+        engine.render(&mut left, &mut right, 0.0, 120.0);
+        assert!(left.iter().any(|&y| y != 0.0));
+        assert_eq!(other.sender.len(), 1);
+    }
+}